@@ -0,0 +1,166 @@
+// Counts of documents grouped by a property, over the whole corpus or the
+// matches of a query: the index as a small source of statistics about the
+// documents in it, e.g. how many of each file type, or per month.
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use crate::collector::Collector;
+use crate::{document_date, locale, Model};
+
+#[derive(Clone, Copy)]
+pub enum Interval {
+    Year,
+    Month,
+    Day,
+}
+
+impl Interval {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "year" => Some(Self::Year),
+            "month" => Some(Self::Month),
+            "day" => Some(Self::Day),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Year => "year",
+            Self::Month => "month",
+            Self::Day => "day",
+        }
+    }
+
+    // Length of the RFC 3339 prefix naming the bucket, "2024", "2024-05" or
+    // "2024-05-17".
+    fn prefix_len(self) -> usize {
+        match self {
+            Self::Year => 4,
+            Self::Month => 7,
+            Self::Day => 10,
+        }
+    }
+}
+
+pub enum GroupBy {
+    // The file extension, lowercased, of the document or archive member.
+    Ext,
+    // The date from the metadata, or else the modification time of the file.
+    Date(Interval),
+    // A metadata field.
+    Field(String),
+}
+
+impl GroupBy {
+    pub fn parse(field: &str, interval: Interval) -> Self {
+        match field {
+            "ext" => Self::Ext,
+            "date" | "mtime" => Self::Date(interval),
+            _ => Self::Field(field.to_string()),
+        }
+    }
+
+    // A document falls into one group, or into one per value of a
+    // multi-valued field.
+    fn keys(&self, model: &Model, path: &Path) -> Option<Vec<String>> {
+        match self {
+            Self::Ext => {
+                let file = file_part(path);
+                let ext = Path::new(file).extension()?.to_str()?;
+                Some(vec![ext.to_lowercase()])
+            }
+            Self::Date(interval) => {
+                let date = match document_date(model, path) {
+                    Some(date) => date.to_string(),
+                    None => modified(path)?,
+                };
+                Some(vec![date.get(..interval.prefix_len())?.to_string()])
+            }
+            Self::Field(field) => {
+                let values = model
+                    .docs
+                    .get(&*path.to_string_lossy())?
+                    .meta
+                    .get(field)?
+                    .values();
+                (!values.is_empty()).then(|| values.to_vec())
+            }
+        }
+    }
+}
+
+// The file a document comes from, without the anchor of a section.
+fn file_part(path: &Path) -> &str {
+    let path = path.to_str().unwrap_or_default();
+    path.split_once('#').map_or(path, |(file, _)| file)
+}
+
+// Members of archives have no modification time of their own.
+fn modified(path: &Path) -> Option<String> {
+    let metadata = fs::metadata(file_part(path)).ok()?;
+    Some(locale::rfc3339_from_system_time(metadata.modified().ok()?))
+}
+
+// The number of documents per group. Documents the grouping has no key
+// for, e.g. without the field, are counted as missing. A document with
+// several values of a field counts in the group of each, so the groups can
+// add up to more than the total.
+pub struct Aggregate<'a> {
+    model: &'a Model,
+    group_by: GroupBy,
+    pub buckets: BTreeMap<String, usize>,
+    pub missing: usize,
+    pub total: usize,
+}
+
+impl<'a> Aggregate<'a> {
+    // `model` has to be the index searched, e.g. `SearchHandle::snapshot`.
+    pub fn new(model: &'a Model, group_by: GroupBy) -> Self {
+        Self {
+            model,
+            group_by,
+            buckets: BTreeMap::new(),
+            missing: 0,
+            total: 0,
+        }
+    }
+
+    // Dates in order, other groups largest first.
+    pub fn to_json(&self) -> Value {
+        let mut buckets = self.buckets.iter().collect::<Vec<_>>();
+        if !matches!(self.group_by, GroupBy::Date(_)) {
+            buckets.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        }
+        let buckets = buckets
+            .into_iter()
+            .map(|(key, count)| json!({"key": key, "count": count}))
+            .collect::<Vec<_>>();
+        json!({
+            "total": self.total,
+            "missing": self.missing,
+            "buckets": buckets,
+        })
+    }
+}
+
+impl Collector for Aggregate<'_> {
+    fn collect(&mut self, path: &Path, _score: f32) {
+        self.total += 1;
+        match self.group_by.keys(self.model, path) {
+            Some(keys) => {
+                for key in keys {
+                    *self.buckets.entry(key).or_insert(0) += 1;
+                }
+            }
+            None => self.missing += 1,
+        }
+    }
+
+    fn needs_scores(&self) -> bool {
+        false
+    }
+}
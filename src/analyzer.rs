@@ -0,0 +1,368 @@
+// Turns text into index terms. The pipeline is kept as a list of named stages
+// so the `analyze` subcommand can show what every one of them does to a text.
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::config::{IndexConfig, Stemmer, Tokenizer};
+use crate::plugins::{self, TokenFilter};
+use crate::{ascii_lexer, Error, Lexer};
+
+// A term with its position in the original token stream. Stages that drop
+// tokens keep the positions of the rest, so phrase gaps stay visible.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub text: String,
+    pub position: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Stage {
+    // Letters are uppercased, the way ASCII terms were always stored, and
+    // other terms composed (NFC) so "naïve" is one term however it is typed.
+    CaseFold,
+    // Only composes terms, for searches that tell case apart.
+    Compose,
+    // Drops the (case folded) stopwords.
+    Stop(HashSet<String>),
+    // Drops the stopwords however they are written.
+    StopAnyCase(HashSet<String>),
+    Stem(Stemmer),
+    // Stems words without folding their case.
+    StemKeepingCase(Stemmer),
+    // A token filter plugin.
+    Filter(Arc<TokenFilter>),
+}
+
+// Harman's S stemmer, in place on case folded words. Short words are left
+// alone so "is" and "as" do not lose their `s`.
+fn stem_plural(word: &mut String) {
+    if word.len() <= 3 {
+        return;
+    }
+    if let Some(stem) = word.strip_suffix("IES") {
+        if !stem.ends_with(['E', 'A']) {
+            word.truncate(word.len() - 3);
+            word.push('Y');
+            return;
+        }
+    }
+    if let Some(stem) = word.strip_suffix("ES") {
+        if !stem.ends_with(['A', 'E', 'O']) {
+            word.pop();
+            return;
+        }
+    }
+    if let Some(stem) = word.strip_suffix('S') {
+        if !stem.ends_with(['U', 'S']) {
+            word.pop();
+        }
+    }
+}
+
+// `stem_plural` for words in the case they are written in: only the ending
+// changes, in the case of the last letter.
+fn stem_plural_keeping_case(word: &mut String) {
+    if !word.is_ascii() {
+        return;
+    }
+    let mut folded = word.to_ascii_uppercase();
+    stem_plural(&mut folded);
+    match word.len() - folded.len() {
+        0 => {}
+        // IES became Y.
+        2 => {
+            let lower = word.ends_with(|c: char| c.is_ascii_lowercase());
+            word.truncate(folded.len() - 1);
+            word.push(if lower { 'y' } else { 'Y' });
+        }
+        _ => word.truncate(folded.len()),
+    }
+}
+
+pub(crate) fn fold_case(term: &mut String) {
+    if term.is_ascii() {
+        term.make_ascii_uppercase();
+    } else {
+        *term = term.to_uppercase().nfc().collect();
+    }
+}
+
+impl Stage {
+    fn name(&self) -> &'static str {
+        match self {
+            Stage::CaseFold => "folded",
+            Stage::Compose => "composed",
+            Stage::Stop(_) | Stage::StopAnyCase(_) => "stopped",
+            Stage::Stem(_) | Stage::StemKeepingCase(_) => "stemmed",
+            Stage::Filter(_) => "filtered",
+        }
+    }
+
+    // Rewrites the term in place, false when the term is dropped.
+    fn apply_to_term(&self, term: &mut String) -> bool {
+        match self {
+            Stage::CaseFold => fold_case(term),
+            Stage::Compose => {
+                if !term.is_ascii() {
+                    *term = term.nfc().collect();
+                }
+            }
+            Stage::Stop(stopwords) => return !stopwords.contains(term.as_str()),
+            Stage::StopAnyCase(stopwords) => {
+                let mut folded = term.clone();
+                fold_case(&mut folded);
+                return !stopwords.contains(&folded);
+            }
+            Stage::Stem(Stemmer::None) | Stage::StemKeepingCase(Stemmer::None) => {}
+            Stage::Stem(Stemmer::Plural) => stem_plural(term),
+            Stage::StemKeepingCase(Stemmer::Plural) => stem_plural_keeping_case(term),
+            Stage::Filter(filter) => return filter.apply(term),
+        }
+        true
+    }
+
+    fn apply(&self, tokens: Vec<Token>) -> Vec<Token> {
+        tokens
+            .into_iter()
+            .filter_map(|mut token| self.apply_to_term(&mut token.text).then_some(token))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Analyzer {
+    tokenizer: Tokenizer,
+    joiners: String,
+    positions: bool,
+    stages: Vec<Stage>,
+}
+
+impl Default for Analyzer {
+    fn default() -> Self {
+        Self::new(&IndexConfig::default())
+    }
+}
+
+impl Analyzer {
+    pub fn new(config: &IndexConfig) -> Self {
+        Self::build(config, true, false)
+    }
+
+    // The analyzer of the configuration without its stemmer unless `stem`,
+    // and with `keep_case` keeping words in the case they are written in,
+    // for searches that override the analysis of an index. Its terms are
+    // not those of the index, it analyzes the text of documents again, and
+    // it records positions for phrases whether the index does or not.
+    pub fn overriding(config: &IndexConfig, stem: bool, keep_case: bool) -> Self {
+        Self {
+            positions: true,
+            ..Self::build(config, stem, keep_case)
+        }
+    }
+
+    fn build(config: &IndexConfig, stem: bool, keep_case: bool) -> Self {
+        let mut stages = vec![if keep_case {
+            Stage::Compose
+        } else {
+            Stage::CaseFold
+        }];
+        if !config.stopwords.is_empty() {
+            let stopwords = config
+                .stopwords
+                .iter()
+                .map(|word| {
+                    let mut word = word.clone();
+                    fold_case(&mut word);
+                    word
+                })
+                .collect();
+            stages.push(if keep_case {
+                Stage::StopAnyCase(stopwords)
+            } else {
+                Stage::Stop(stopwords)
+            });
+        }
+        if stem && config.stemmer != Stemmer::None {
+            stages.push(if keep_case {
+                Stage::StemKeepingCase(config.stemmer)
+            } else {
+                Stage::Stem(config.stemmer)
+            });
+        }
+        for module in &config.token_filters {
+            stages.push(Stage::Filter(plugins::token_filter(module)));
+        }
+        Self {
+            tokenizer: config.tokenizer,
+            joiners: config.joiners.clone(),
+            positions: config.positions,
+            stages,
+        }
+    }
+
+    // The analyzer of documents, which keeps the stopwords of indexes that
+    // only leave them out of queries.
+    pub fn for_documents(config: &IndexConfig) -> Self {
+        let analyzer = Self::new(config);
+        if config.index_stopwords {
+            analyzer.keeping_stopwords()
+        } else {
+            analyzer
+        }
+    }
+
+    pub fn keeping_stopwords(mut self) -> Self {
+        self.stages
+            .retain(|stage| !matches!(stage, Stage::Stop(_) | Stage::StopAnyCase(_)));
+        self
+    }
+
+    fn keeps_token(&self, token: &[char]) -> bool {
+        match self.tokenizer {
+            Tokenizer::Default => true,
+            Tokenizer::Words => token
+                .iter()
+                .all(|&c| c.is_alphanumeric() || is_combining_mark(c)),
+            Tokenizer::Code => token.iter().any(|c| c.is_alphanumeric()),
+        }
+    }
+
+    // Whether documents analyzed with this record where their terms occur.
+    pub fn records_positions(&self) -> bool {
+        self.positions
+    }
+
+    // The tokens of the text as the tokenizer splits it, before any stage.
+    pub fn lexer<'a>(&'a self, content: &'a [char]) -> Lexer<'a> {
+        match self.tokenizer {
+            Tokenizer::Code => Lexer::code(content, &self.joiners),
+            _ => Lexer::new(content),
+        }
+    }
+
+    fn raw_tokens(&self, text: &str) -> Vec<Token> {
+        let content = text.chars().collect::<Vec<_>>();
+        self.lexer(&content)
+            .filter(|token| self.keeps_token(token))
+            .enumerate()
+            .map(|(position, token)| Token {
+                text: token.iter().collect(),
+                position,
+            })
+            .collect()
+    }
+
+    // The token stream after every stage, starting with the raw lexer output.
+    pub fn stages(&self, text: &str) -> Vec<(&'static str, Vec<Token>)> {
+        let mut tokens = self.raw_tokens(text);
+        let mut stages = vec![("tokens", tokens.clone())];
+        for stage in &self.stages {
+            tokens = stage.apply(tokens);
+            stages.push((stage.name(), tokens.clone()));
+        }
+        stages
+    }
+
+    pub fn analyze(&self, text: &str) -> Vec<Token> {
+        self.stages
+            .iter()
+            .fold(self.raw_tokens(text), |tokens, stage| stage.apply(tokens))
+    }
+
+    pub fn terms(&self, text: &str) -> Vec<String> {
+        self.analyze(text)
+            .into_iter()
+            .map(|token| token.text)
+            .collect()
+    }
+
+    // Wildcard patterns are only case folded: stemming a word or dropping it
+    // makes no sense when part of it is left open.
+    pub fn fold_pattern(&self, pattern: &str) -> String {
+        let mut pattern = pattern.to_string();
+        if self.stages.contains(&Stage::CaseFold) {
+            fold_case(&mut pattern);
+        }
+        pattern
+    }
+
+    // A term of the index as people write it: terms folded to upper case
+    // are shown in lower case, which folds back to the same term.
+    pub fn display_term(&self, term: &str) -> String {
+        if self.stages.contains(&Stage::CaseFold) {
+            term.to_lowercase()
+        } else {
+            term.to_string()
+        }
+    }
+
+    // Fails when a token filter of the analyzer stopped working, so its
+    // terms since are not what the index expects. Callers ask once they
+    // analyzed what they needed to.
+    pub fn check_filters(&self) -> Result<(), Error> {
+        for stage in &self.stages {
+            if let Stage::Filter(filter) = stage {
+                if let Some(err) = filter.failure() {
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // The term a single token of the text becomes, if any.
+    pub fn normalize(&self, token: &str) -> Option<String> {
+        let mut term = token.to_string();
+        self.stages
+            .iter()
+            .all(|stage| stage.apply_to_term(&mut term))
+            .then_some(term)
+    }
+
+    // Calls `f` with every term of the text without allocating per token:
+    // each token is built in the same scratch buffer.
+    pub fn for_each_term(&self, text: &str, mut f: impl FnMut(&str)) {
+        let mut term = String::new();
+        let mut emit = |token: &str| {
+            term.clear();
+            term.push_str(token);
+            if self
+                .stages
+                .iter()
+                .all(|stage| stage.apply_to_term(&mut term))
+            {
+                f(&term);
+            }
+        };
+        if text.is_ascii() && self.tokenizer == Tokenizer::Code {
+            ascii_lexer::for_each_code_token(text, &self.joiners, |token| {
+                if token.bytes().any(|b| b.is_ascii_alphanumeric()) {
+                    emit(token);
+                }
+            });
+            return;
+        }
+        if text.is_ascii() {
+            ascii_lexer::for_each_token(text, |token| {
+                if self.tokenizer == Tokenizer::Default
+                    || token.bytes().all(|b| b.is_ascii_alphanumeric())
+                {
+                    emit(token);
+                }
+            });
+            return;
+        }
+        let content = text.chars().collect::<Vec<_>>();
+        let mut token = String::new();
+        for chars in self.lexer(&content) {
+            if self.keeps_token(chars) {
+                token.clear();
+                token.extend(chars);
+                emit(&token);
+            }
+        }
+    }
+}
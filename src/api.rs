@@ -0,0 +1,1188 @@
+// Payloads of the server's search routes. They are served as JSON or
+// rendered into the HTML results page, and batch searches print the same
+// shape.
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use tracing::debug_span;
+
+use crate::export::ExportFormat;
+use crate::http::percent_encode;
+use crate::popularity::{Popularity, Sort};
+use crate::resultsets::{self, ResultSet, ResultSets};
+use tinysearch::aggregate::{Aggregate, GroupBy, Interval};
+use tinysearch::analyzer::Analyzer;
+use tinysearch::bundle::{SourceBundle, Sources};
+use tinysearch::collector::{Collector, Count, FacetCounts};
+use tinysearch::exclude;
+use tinysearch::extract::{thumbnail, ExtractOptions};
+use tinysearch::feedback::Expansion;
+use tinysearch::filter::{self, Filter};
+use tinysearch::handle::{SearchHandle, SearchResults};
+use tinysearch::language;
+use tinysearch::overrides::{self, Narrowing, OVERRIDE_NAMES};
+use tinysearch::percolate::Percolator;
+use tinysearch::query::{self, ParseError, Query, QueryLimits, Typos};
+use tinysearch::scoring::{MinScore, Normalization, Ranking};
+use tinysearch::{
+    document_date, document_url, is_truncated, snippet, source, DocId, ErrorCode, Metadata, Model,
+};
+
+pub const DEFAULT_PAGE_SIZE: usize = 20;
+pub const MAX_PAGE_SIZE: usize = 100;
+pub const DEFAULT_COMPLETIONS: usize = 10;
+pub const MAX_COMPLETIONS: usize = 50;
+
+pub struct SearchRequest {
+    pub query: String,
+    // In the `key=value` syntax of `--filter`.
+    pub filters: Vec<String>,
+    pub offset: usize,
+    pub limit: usize,
+    // False for `hits=0`: only the number of matches and the facets are
+    // wanted, so the matches are counted without scoring them.
+    pub hits: bool,
+    // Metadata fields whose values are counted over all matches.
+    pub facets: Vec<String>,
+    // Overrides the typo tolerance of the server for this search.
+    pub typos: Option<Typos>,
+    // Overrides whether the server takes every word as `word~`.
+    pub fuzzy: Option<bool>,
+    // Answer with the page of results as CSV or Markdown instead.
+    pub export: Option<ExportFormat>,
+    // Overrides the ranking function of the server for this search.
+    pub ranking: Option<Ranking>,
+    // Adds the score normalized to 0 to 1 to every result as `relevance`.
+    pub normalize: Option<Normalization>,
+    // Overrides the cutoff of the server for this search. Only matches that
+    // are scored can fall below it, so `hits=0` counts them all.
+    pub min_score: Option<MinScore>,
+    // The token of an earlier search whose matches this one is narrowed to.
+    pub within: Option<String>,
+    // The token of an earlier search whose kept matches are paged through
+    // instead of searching again; its query only picks the snippets.
+    pub result_set: Option<String>,
+    // POST /api/search answers with result objects, snippets included,
+    // rather than `[path, score]` pairs.
+    pub snippets: bool,
+    // Expands the query before searching, e.g. with the terms of its best
+    // results.
+    pub expand: Option<Expansion>,
+    // Searches for the stopwords of the query too, in indexes that keep them.
+    pub keep_stopwords: bool,
+    pub sort: Sort,
+    // The fields of the results to answer with, all of them when empty.
+    pub fields: Vec<String>,
+    // Languages whose documents rank higher, in the syntax of Accept-Language;
+    // the server takes `auto` for the Accept-Language of the request.
+    pub prefer_lang: Option<String>,
+    // Analysis options overridden for this search, by name, see `overrides`.
+    pub analysis: Vec<(String, String)>,
+}
+
+impl SearchRequest {
+    // Reads `q`, `filter` (repeatable), `offset`, `limit`, `page`, `hits`,
+    // `facet` (repeatable), `typos`, `fuzzy`, `ranking`, `normalize` (max or
+    // logistic), `min_score`, `within`, `result_set`, `snippets`, `expand`
+    // (prf), `stopwords` (keep), `sort` (relevance or popular), `fields`
+    // (comma-separated), `prefer_lang`, the analysis overrides `stemming`,
+    // `case` and `fold_accents`, and `format` (csv or md). Values that do not
+    // parse fall back to the defaults, but for the overrides.
+    pub fn from_params(params: &[(String, String)]) -> Self {
+        let mut request = Self {
+            query: String::new(),
+            filters: Vec::new(),
+            offset: 0,
+            limit: DEFAULT_PAGE_SIZE,
+            hits: true,
+            facets: Vec::new(),
+            typos: None,
+            fuzzy: None,
+            export: None,
+            ranking: None,
+            normalize: None,
+            min_score: None,
+            within: None,
+            result_set: None,
+            snippets: false,
+            expand: None,
+            keep_stopwords: false,
+            sort: Sort::Relevance,
+            fields: Vec::new(),
+            prefer_lang: None,
+            analysis: Vec::new(),
+        };
+        let mut page = None;
+        for (name, value) in params {
+            match name.as_str() {
+                "q" => request.query = value.clone(),
+                "filter" => request.filters.push(value.clone()),
+                "offset" => request.offset = value.parse().unwrap_or(0),
+                "limit" => request.limit = value.parse().unwrap_or(DEFAULT_PAGE_SIZE),
+                "page" => page = value.parse().ok(),
+                "hits" => request.hits = !matches!(value.as_str(), "0" | "false"),
+                "facet" => request.facets.push(value.clone()),
+                "typos" => request.typos = Typos::parse(value),
+                "fuzzy" => request.fuzzy = parse_switch(value),
+                "format" => request.export = ExportFormat::parse(value),
+                "ranking" => request.ranking = Ranking::parse(value),
+                "normalize" => request.normalize = Normalization::parse(value),
+                "min_score" => request.min_score = MinScore::parse(value),
+                "within" => request.within = Some(value.clone()),
+                "result_set" => request.result_set = Some(value.clone()),
+                "snippets" => request.snippets = parse_switch(value).unwrap_or(false),
+                "expand" => request.expand = Expansion::parse(value),
+                "stopwords" => request.keep_stopwords = value == "keep",
+                "sort" => request.sort = Sort::parse(value).unwrap_or_default(),
+                "fields" => request.fields = split_fields(value),
+                "prefer_lang" => request.prefer_lang = Some(value.clone()),
+                name if OVERRIDE_NAMES.contains(&name) => {
+                    request.analysis.push((name.to_string(), value.clone()))
+                }
+                _ => {}
+            }
+        }
+        request.limit = request.limit.min(MAX_PAGE_SIZE);
+        request.turn_to(page);
+        request
+    }
+
+    // Reads the body of POST /api/search: a JSON object with `query`,
+    // `filters`, `offset`, `limit`, `page`, `hits`, `facets`, `typos`,
+    // `fuzzy`, `ranking`, `normalize`, `min_score`, `within`, `result_set`,
+    // `snippets`, `expand`, `stopwords`, `sort`, `fields` (a list or a
+    // comma-separated string), `prefer_lang`, `stemming`, `case`,
+    // `fold_accents` and `format`, or the query as plain text.
+    // Missing or mistyped fields fall back to the defaults.
+    pub fn from_body(body: &str) -> Self {
+        let mut request = Self {
+            query: body.trim().to_string(),
+            filters: Vec::new(),
+            offset: 0,
+            limit: DEFAULT_PAGE_SIZE,
+            hits: true,
+            facets: Vec::new(),
+            typos: None,
+            fuzzy: None,
+            export: None,
+            ranking: None,
+            normalize: None,
+            min_score: None,
+            within: None,
+            result_set: None,
+            snippets: false,
+            expand: None,
+            keep_stopwords: false,
+            sort: Sort::Relevance,
+            fields: Vec::new(),
+            prefer_lang: None,
+            analysis: Vec::new(),
+        };
+        let Ok(Value::Object(fields)) = serde_json::from_str::<Value>(body) else {
+            return request;
+        };
+        let number = |name: &str| {
+            fields
+                .get(name)
+                .and_then(Value::as_u64)
+                .map(|value| value as usize)
+        };
+        request.query = fields
+            .get("query")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+        let strings = |name: &str| -> Vec<String> {
+            fields
+                .get(name)
+                .and_then(Value::as_array)
+                .map(|values| {
+                    values
+                        .iter()
+                        .filter_map(|value| value.as_str().map(str::to_string))
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        request.filters = strings("filters");
+        request.facets = strings("facets");
+        request.fields = match fields.get("fields") {
+            Some(Value::String(names)) => split_fields(names),
+            _ => strings("fields"),
+        };
+        request.hits = !matches!(
+            fields.get("hits"),
+            Some(hits) if hits.as_u64() == Some(0) || hits.as_bool() == Some(false)
+        );
+        // A number or a name like the parameter.
+        request.typos = match fields.get("typos") {
+            Some(Value::String(typos)) => Typos::parse(typos),
+            Some(typos) => typos
+                .as_u64()
+                .and_then(|typos| Typos::parse(&typos.to_string())),
+            None => None,
+        };
+        request.fuzzy = fields.get("fuzzy").and_then(Value::as_bool);
+        let token = |name: &str| fields.get(name).and_then(Value::as_str).map(str::to_string);
+        request.within = token("within");
+        request.result_set = token("result_set");
+        request.prefer_lang = token("prefer_lang");
+        // Switches may be booleans too.
+        request.analysis = OVERRIDE_NAMES
+            .iter()
+            .filter_map(|name| {
+                let value = match fields.get(*name)? {
+                    Value::String(value) => value.clone(),
+                    Value::Bool(true) => "on".to_string(),
+                    Value::Bool(false) => "off".to_string(),
+                    value => value.to_string(),
+                };
+                Some((name.to_string(), value))
+            })
+            .collect();
+        request.snippets = fields.get("snippets").and_then(Value::as_bool) == Some(true);
+        request.export = fields
+            .get("format")
+            .and_then(Value::as_str)
+            .and_then(ExportFormat::parse);
+        request.ranking = fields
+            .get("ranking")
+            .and_then(Value::as_str)
+            .and_then(Ranking::parse);
+        request.normalize = fields
+            .get("normalize")
+            .and_then(Value::as_str)
+            .and_then(Normalization::parse);
+        request.expand = fields
+            .get("expand")
+            .and_then(Value::as_str)
+            .and_then(Expansion::parse);
+        request.keep_stopwords = fields.get("stopwords").and_then(Value::as_str) == Some("keep");
+        request.sort = fields
+            .get("sort")
+            .and_then(Value::as_str)
+            .and_then(Sort::parse)
+            .unwrap_or_default();
+        // A number or a string like the parameter.
+        request.min_score = match fields.get("min_score") {
+            Some(Value::String(min_score)) => MinScore::parse(min_score),
+            Some(min_score) => min_score.as_f64().map(|min| MinScore::Absolute(min as f32)),
+            None => None,
+        };
+        request.offset = number("offset").unwrap_or(0);
+        request.limit = number("limit")
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .min(MAX_PAGE_SIZE);
+        request.turn_to(number("page"));
+        request
+    }
+
+    // A 1-based page of `limit` results replaces the offset; page 0 is taken
+    // as the first.
+    fn turn_to(&mut self, page: Option<usize>) {
+        if let Some(page) = page {
+            self.offset = page.saturating_sub(1).saturating_mul(self.limit);
+        }
+    }
+}
+
+fn split_fields(names: &str) -> Vec<String> {
+    names
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// Trims the results of a search payload, or the documents of /api/docs, to
+// the fields the request asks for with `fields`, for clients that need only
+// some of them. Asking for fields
+// turns `[path, score]` pairs into objects with the asked for ones of path,
+// score and relevance. The rest of the payload stays as it is.
+pub fn select_fields(payload: &mut Value, fields: &[String]) {
+    if fields.is_empty() {
+        return;
+    }
+    let Some(payload) = payload.as_object_mut() else {
+        return;
+    };
+    let results = payload
+        .iter_mut()
+        .filter(|(name, _)| matches!(name.as_str(), "results" | "docs"))
+        .filter_map(|(_, list)| list.as_array_mut())
+        .flatten();
+    for result in results {
+        if let Value::Array(pair) = result {
+            let named = ["path", "score", "relevance"]
+                .into_iter()
+                .zip(pair.drain(..))
+                .map(|(name, value)| (name.to_string(), value))
+                .collect::<Map<_, _>>();
+            *result = Value::Object(named);
+        }
+        if let Value::Object(result) = result {
+            result.retain(|name, _| fields.iter().any(|field| field == name));
+        }
+    }
+}
+
+// `true` or `1` and `false` or `0`, as in `hits`.
+fn parse_switch(value: &str) -> Option<bool> {
+    match value {
+        "1" | "true" => Some(true),
+        "0" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+// The payload of a failed request, which a status of 400 or above goes
+// with: `code` tells clients what kind of failure it is, `message` tells
+// people what went wrong. Routes add the ID of the request.
+pub fn error(code: ErrorCode, message: impl Into<String>) -> Value {
+    json!({"error": {"code": code.as_str(), "message": message.into()}})
+}
+
+pub fn query_error(query: &str, err: &ParseError) -> Value {
+    json!({
+        "query": query,
+        "error": {
+            "code": ErrorCode::QueryParse.as_str(),
+            "message": err.message,
+            "start": err.start,
+            "end": err.end,
+            "suggestion": err.suggestion,
+        }
+    })
+}
+
+// The query expands to more terms than the limits allow.
+pub fn limit_error(query: &str, message: &str) -> Value {
+    json!({"query": query, "error": {"code": ErrorCode::QueryTooComplex.as_str(), "message": message}})
+}
+
+// The request's query, expanded against the index, and its filters.
+fn prepare(
+    handle: &SearchHandle,
+    request: &SearchRequest,
+    limits: &QueryLimits,
+) -> Result<(Query, Vec<Filter>), Value> {
+    let analyzer = handle
+        .query_analyzer(request.keep_stopwords)
+        .map_err(|err| error(err.code(), err.to_string()))?;
+    let query = handle.rewrite(&request.query);
+    let parsed = debug_span!("parse")
+        .in_scope(|| query::parse(&query, &analyzer))
+        .map_err(|err| query_error(&query, &err))?;
+    analyzer
+        .check_filters()
+        .map_err(|err| error(err.code(), err.to_string()))?;
+    let limits = QueryLimits {
+        typos: request.typos.unwrap_or(limits.typos),
+        fuzzy: request.fuzzy.unwrap_or(limits.fuzzy),
+        ..*limits
+    };
+    let parsed = debug_span!("expand")
+        .in_scope(|| handle.expand(parsed, &limits))
+        .map_err(|err| limit_error(&request.query, &err))?;
+    let filters = parse_filters(request)?;
+    let parsed = match request.expand {
+        Some(Expansion::Prf) => {
+            debug_span!("feedback").in_scope(|| handle.expand_feedback(parsed, &filters))
+        }
+        None => parsed,
+    };
+    Ok((parsed, filters))
+}
+
+// Narrows the matches of a search overriding the analysis of the index to
+// the documents whose kept copies match the overridden way.
+struct Overridden {
+    narrowing: Narrowing,
+    bundle: Arc<SourceBundle>,
+    model: Arc<Model>,
+}
+
+impl Overridden {
+    fn keeps(&self, path: &Path) -> bool {
+        self.bundle
+            .sources(&self.model)
+            .document_text(path)
+            .is_some_and(|text| self.narrowing.matches(&text))
+    }
+}
+
+// What the analysis overrides of the request take, none when the index
+// already analyzes that way, or the error payload listing the overrides it
+// cannot honor.
+fn overridden(
+    handle: &SearchHandle,
+    request: &SearchRequest,
+    limits: &QueryLimits,
+) -> Result<Option<Overridden>, Value> {
+    if request.analysis.is_empty() {
+        return Ok(None);
+    }
+    let model = handle.snapshot();
+    let bundle = handle.sources();
+    let analyzer = overrides::resolve(&model.manifest.config, &request.analysis, bundle.is_some())
+        .map_err(|unsupported| {
+            let names = unsupported
+                .iter()
+                .map(|unsupported| format!("{}={}", unsupported.name, unsupported.value))
+                .collect::<Vec<_>>();
+            json!({
+                "query": request.query,
+                "error": {
+                    "code": ErrorCode::UnsupportedOverride.as_str(),
+                    "message": format!("the index cannot honor {}", names.join(", ")),
+                    "unsupported": unsupported
+                        .iter()
+                        .zip(&names)
+                        .map(|(unsupported, name)| json!({"override": name, "reason": unsupported.reason}))
+                        .collect::<Vec<_>>(),
+                }
+            })
+        })?;
+    let (Some(analyzer), Some(bundle)) = (analyzer, bundle) else {
+        return Ok(None);
+    };
+    let analyzer = if request.keep_stopwords {
+        analyzer.keeping_stopwords()
+    } else {
+        analyzer
+    };
+    let limits = QueryLimits {
+        typos: request.typos.unwrap_or(limits.typos),
+        fuzzy: request.fuzzy.unwrap_or(limits.fuzzy),
+        ..*limits
+    };
+    let query = handle.rewrite(&request.query);
+    let narrowing = Narrowing::new(&model, analyzer, &query, limits)
+        .map_err(|err| query_error(&query, &err))?;
+    Ok(Some(Overridden {
+        narrowing,
+        bundle,
+        model,
+    }))
+}
+
+// The kept matches of an earlier search the request refers to with its
+// `within` or `result_set` token, or the error payload when the server no
+// longer keeps them.
+fn kept(
+    request: &SearchRequest,
+    token: Option<&String>,
+    sets: &ResultSets,
+) -> Result<Option<ResultSet>, Value> {
+    let Some(token) = token else {
+        return Ok(None);
+    };
+    match sets.get(token) {
+        Some(set) => Ok(Some(set)),
+        None => Err(json!({
+            "query": request.query,
+            "error": {
+                "code": ErrorCode::ResultSetExpired.as_str(),
+                "message": format!("result set {token} has expired, search again"),
+            }
+        })),
+    }
+}
+
+fn parse_filters(request: &SearchRequest) -> Result<Vec<Filter>, Value> {
+    request
+        .filters
+        .iter()
+        .map(|source| {
+            Filter::parse(source).map_err(|err| {
+                json!({
+                    "query": request.query,
+                    "error": {
+                        "code": ErrorCode::InvalidFilter.as_str(),
+                        "message": err.to_string(),
+                    }
+                })
+            })
+        })
+        .collect()
+}
+
+// Counts the matches and the values of the requested facets in one pass,
+// without scoring.
+struct Summary<'a> {
+    count: Count,
+    facets: Vec<FacetCounts<'a>>,
+    within: Option<HashSet<&'a str>>,
+    overridden: Option<&'a Overridden>,
+}
+
+impl Collector for Summary<'_> {
+    fn collect(&mut self, path: &Path, score: f32) {
+        if self
+            .within
+            .as_ref()
+            .is_some_and(|within| !within.contains(&*path.to_string_lossy()))
+            || self
+                .overridden
+                .is_some_and(|overridden| !overridden.keeps(path))
+        {
+            return;
+        }
+        self.count.collect(path, score);
+        for facet in &mut self.facets {
+            facet.collect(path, score);
+        }
+    }
+
+    fn needs_scores(&self) -> bool {
+        false
+    }
+}
+
+fn facet_counts(facets: Vec<FacetCounts>) -> Value {
+    facets
+        .into_iter()
+        .map(|facet| (facet.field().to_string(), json!(facet.counts)))
+        .collect::<Map<_, _>>()
+        .into()
+}
+
+// The number of matches and the facet counts of a `hits=0` request, or of
+// the kept matches it pages through.
+fn summary(
+    handle: &SearchHandle,
+    request: &SearchRequest,
+    query: &Query,
+    filters: &[Filter],
+    within: Option<&ResultSet>,
+    kept: Option<&ResultSet>,
+    overridden: Option<&Overridden>,
+) -> Value {
+    let model = handle.snapshot();
+    let mut summary = Summary {
+        count: Count::default(),
+        facets: request
+            .facets
+            .iter()
+            .map(|field| FacetCounts::new(&model, field.as_str()))
+            .collect(),
+        within: within.map(resultsets::paths),
+        overridden,
+    };
+    debug_span!("count").in_scope(|| match kept {
+        Some(kept) => {
+            for (path, score) in kept.iter() {
+                summary.collect(path, *score);
+            }
+        }
+        None => handle.collect(query, filters, &mut summary),
+    });
+    json!({
+        "query": request.query,
+        "total": summary.count.0,
+        "offset": request.offset,
+        "results": [],
+        "facets": facet_counts(summary.facets),
+    })
+}
+
+// Every match of the request, best first.
+fn matches(
+    handle: &SearchHandle,
+    request: &SearchRequest,
+    query: &Query,
+    filters: &[Filter],
+    within: Option<&ResultSet>,
+    overridden: Option<&Overridden>,
+    popularity: Option<&Popularity>,
+) -> SearchResults {
+    let ranking = request.ranking.unwrap_or(handle.ranking());
+    // Every match is needed for the total; the result cache keeps paging
+    // through them cheap.
+    let mut matches = handle.search_ranked(query, filters, ranking, usize::MAX);
+    if let Some(within) = within.map(resultsets::paths) {
+        matches.retain(|(path, _)| within.contains(&*path.to_string_lossy()));
+    }
+    if let Some(overridden) = overridden {
+        let _narrow = debug_span!("narrow").entered();
+        matches.retain(|(path, _)| overridden.keeps(path));
+    }
+    if let Some(preferences) = &request.prefer_lang {
+        prefer_languages(handle, &mut matches, preferences);
+    }
+    if let Some(popularity) = popularity {
+        popularity.rank(&mut matches, request.sort);
+    }
+    if let Some(min_score) = request.min_score.or(handle.min_score()) {
+        min_score.apply(&mut matches);
+    }
+    matches
+}
+
+// Boosts the matches in the preferred languages by their `lang`, so that in
+// a multilingual corpus those of the searcher are not buried under others.
+fn prefer_languages(handle: &SearchHandle, matches: &mut SearchResults, preferences: &str) {
+    let preferences = language::parse_preferences(preferences);
+    if preferences.is_empty() {
+        return;
+    }
+    let model = handle.snapshot();
+    for (path, score) in matches.iter_mut() {
+        let lang = model
+            .doc(path)
+            .and_then(|doc| doc.meta.get(language::LANG_KEY)?.first());
+        if let Some(lang) = lang {
+            *score *= language::preference_boost(&preferences, lang);
+        }
+    }
+    matches.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+}
+
+// The normalized score of every raw score of the matches, if the request
+// asks for them.
+fn relevance(request: &SearchRequest, matches: &SearchResults) -> Option<impl Fn(f32) -> f32> {
+    let scores = matches.iter().map(|(_, score)| *score).collect::<Vec<_>>();
+    Some(request.normalize?.normalizer(&scores))
+}
+
+// One page of the ranked list as `[path, score]` pairs, best first, or
+// `[path, score, relevance]` when scores are normalized: the results of
+// POST /api/search, with the total number of matches and the token of their
+// result set. A `hits=0` request gets the summary instead.
+pub fn ranked(
+    handle: &SearchHandle,
+    request: &SearchRequest,
+    limits: &QueryLimits,
+    sets: &ResultSets,
+    popularity: Option<&Popularity>,
+) -> Result<Value, Value> {
+    let (query, filters) = prepare(handle, request, limits)?;
+    let overridden = overridden(handle, request, limits)?;
+    let within = kept(request, request.within.as_ref(), sets)?;
+    let kept = kept(request, request.result_set.as_ref(), sets)?;
+    if !request.hits {
+        return Ok(summary(
+            handle,
+            request,
+            &query,
+            &filters,
+            within.as_ref(),
+            kept.as_ref(),
+            overridden.as_ref(),
+        ));
+    }
+    let matches = kept.unwrap_or_else(|| {
+        Arc::new(matches(
+            handle,
+            request,
+            &query,
+            &filters,
+            within.as_ref(),
+            overridden.as_ref(),
+            popularity,
+        ))
+    });
+    let relevance = relevance(request, &matches);
+    let pairs = matches
+        .iter()
+        .skip(request.offset)
+        .take(request.limit)
+        .map(|(path, score)| match &relevance {
+            Some(relevance) => json!([path, score, relevance(*score)]),
+            None => json!([path, score]),
+        })
+        .collect::<Vec<_>>();
+    Ok(json!({
+        "query": request.query,
+        "total": matches.len(),
+        "offset": request.offset,
+        "results": pairs,
+        "result_set": sets.keep(matches.clone()),
+    }))
+}
+
+// One page of results with the total number of matches, the token of their
+// result set and a corrected query when a word of it is not in the index's
+// spelling dictionary, or the error payload when the query or a filter is
+// malformed or the result set to search within has expired.
+pub fn search(
+    handle: &SearchHandle,
+    request: &SearchRequest,
+    limits: &QueryLimits,
+    sets: &ResultSets,
+    popularity: Option<&Popularity>,
+) -> Result<Value, Value> {
+    let analyzer = handle.analyzer();
+    let (parsed, filters) = prepare(handle, request, limits)?;
+    let overridden = overridden(handle, request, limits)?;
+    let within = kept(request, request.within.as_ref(), sets)?;
+    let kept = kept(request, request.result_set.as_ref(), sets)?;
+    if !request.hits {
+        let summary = summary(
+            handle,
+            request,
+            &parsed,
+            &filters,
+            within.as_ref(),
+            kept.as_ref(),
+            overridden.as_ref(),
+        );
+        return Ok(summary);
+    }
+    let matches = kept.unwrap_or_else(|| {
+        Arc::new(matches(
+            handle,
+            request,
+            &parsed,
+            &filters,
+            within.as_ref(),
+            overridden.as_ref(),
+            popularity,
+        ))
+    });
+    let relevance = relevance(request, &matches);
+    let terms = parsed.positive_terms();
+    let model = handle.snapshot();
+    let bundle = handle.sources();
+    let sources = bundle.as_deref().map(|bundle| bundle.sources(&model));
+    let _snippet = debug_span!("snippet").entered();
+    let results = matches
+        .iter()
+        .skip(request.offset)
+        .take(request.limit)
+        .map(|(path, score)| {
+            let mut result = result(
+                &model,
+                path,
+                *score,
+                &terms,
+                &analyzer,
+                sources,
+                handle.extract_options(),
+            );
+            if let Some(relevance) = &relevance {
+                result["relevance"] = json!(relevance(*score));
+            }
+            result
+        })
+        .collect::<Vec<_>>();
+    let mut payload = json!({
+        "query": request.query,
+        "total": matches.len(),
+        "offset": request.offset,
+        "results": results,
+        "result_set": sets.keep(matches.clone()),
+    });
+    if let Some(corrected) = handle.did_you_mean(&request.query) {
+        payload["did_you_mean"] = json!(corrected);
+    }
+    if !request.facets.is_empty() {
+        let mut facets = request
+            .facets
+            .iter()
+            .map(|field| FacetCounts::new(&model, field.as_str()))
+            .collect::<Vec<_>>();
+        for (path, score) in matches.iter() {
+            for facet in &mut facets {
+                facet.collect(path, *score);
+            }
+        }
+        payload["facets"] = facet_counts(facets);
+    }
+    Ok(payload)
+}
+
+// One result of a search: its path and score, and its title, URL, date and a
+// snippet as far as they are known, with the field the snippet is from, and
+// the pages with matches of PDFs.
+pub fn result(
+    model: &Model,
+    path: &Path,
+    score: f32,
+    terms: &[&str],
+    analyzer: &Analyzer,
+    sources: Option<Sources>,
+    extract: &ExtractOptions,
+) -> Value {
+    let mut result = json!({"path": path, "score": score});
+    let title = model
+        .doc(path)
+        .and_then(|doc| doc.meta.get("title")?.first());
+    if let Some(title) = title {
+        result["title"] = json!(title);
+    }
+    if let Some(url) = document_url(model, path) {
+        result["url"] = json!(url);
+    }
+    if let Some(date) = document_date(model, path) {
+        result["date"] = json!(date);
+    }
+    if is_truncated(model, path) {
+        result["truncated"] = json!(true);
+    }
+    if let Some((field, snippet)) =
+        snippet::field_snippet(path, title, terms, analyzer, sources, extract)
+    {
+        result["snippet"] = json!(snippet);
+        result["snippet_field"] = json!(field.name());
+    }
+    let pages = snippet::matching_pages(path, terms, analyzer, sources, extract);
+    if !pages.is_empty() {
+        result["pages"] = json!(pages);
+    }
+    let has_preview = model
+        .doc(path)
+        .is_some_and(|doc| doc.meta.contains_key("thumbnail") || doc.meta.contains_key("preview"));
+    if has_preview {
+        result["thumbnail"] = json!(format!(
+            "/api/v1/thumb?path={}",
+            percent_encode(&path.to_string_lossy())
+        ));
+    }
+    result
+}
+
+// GET /api/thumb: the preview of a document indexed with `--thumbnails` and
+// its content type, the PNG of the first page of a PDF or else its first
+// heading drawn as SVG.
+pub fn thumbnail(model: &Model, path: &Path) -> Option<(Vec<u8>, &'static str)> {
+    let meta = &model.doc(path)?.meta;
+    let png = meta
+        .get("thumbnail")
+        .and_then(|thumbnail| thumbnail::thumbnail_png(thumbnail.first()?));
+    if let Some(png) = png {
+        return Some((png, "image/png"));
+    }
+    let preview = meta.get("preview")?.first()?;
+    Some((preview_svg(preview).into_bytes(), "image/svg+xml"))
+}
+
+// Characters of a line of the SVG preview and the lines it has room for.
+const PREVIEW_LINE_CHARS: usize = 18;
+const PREVIEW_LINES: usize = 5;
+
+// A page-shaped card with the heading broken into lines at words, as SVG
+// has no wrapping of its own.
+fn preview_svg(preview: &str) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    for word in preview.split_whitespace() {
+        match lines.last_mut() {
+            Some(line) if line.chars().count() + 1 + word.chars().count() <= PREVIEW_LINE_CHARS => {
+                line.push(' ');
+                line.push_str(word);
+            }
+            _ => lines.push(word.to_string()),
+        }
+    }
+    if lines.len() > PREVIEW_LINES {
+        lines.truncate(PREVIEW_LINES);
+        lines[PREVIEW_LINES - 1].push('…');
+    }
+    let text = lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| {
+            let escaped = line
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;");
+            format!(r#"<text x="12" y="{}">{escaped}</text>"#, 28 + 20 * i)
+        })
+        .collect::<String>();
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="120" height="160" viewBox="0 0 120 160"><rect width="120" height="160" fill="#fff" stroke="#ccc"/><g font-family="sans-serif" font-size="12" font-weight="bold" fill="#333">{text}</g></svg>"##
+    )
+}
+
+// The body of POST /api/documents: the `path` of a document, or its URL, its
+// `text` and optionally its `meta`, an object of strings and arrays of
+// strings like the metadata of indexed documents.
+#[derive(Deserialize)]
+pub struct DocumentRequest {
+    pub path: String,
+    pub text: String,
+    #[serde(default)]
+    pub meta: Metadata,
+}
+
+impl DocumentRequest {
+    pub fn from_body(body: &str) -> Result<Self, String> {
+        let mut request: Self = serde_json::from_str(body).map_err(|err| {
+            format!("the document must be a JSON object with a path and a text: {err}")
+        })?;
+        if request.path.is_empty() {
+            return Err("the path of the document is empty".to_string());
+        }
+        // Like the pages the indexer fetches.
+        if source::is_url(&request.path) && !request.meta.contains_key("url") {
+            request
+                .meta
+                .insert("url".to_string(), request.path.clone().into());
+        }
+        Ok(request)
+    }
+}
+
+// GET /api/doc: the path, metadata and text of a document, if the index has
+// it. The text is read from `sources` when the file is not there.
+pub fn document(
+    model: &Model,
+    path: &Path,
+    sources: Option<Sources>,
+    extract: &ExtractOptions,
+) -> Option<Value> {
+    let doc = model.doc(path)?;
+    let mut document = json!({"path": path, "meta": doc.meta});
+    if let Some(text) = snippet::bundled_document_text(path, sources, extract) {
+        document["text"] = json!(text);
+    }
+    Some(document)
+}
+
+// The body of POST /api/docs: the documents to look up, by path or by the
+// ID the index gave them, and whether to add their text, which takes
+// reading every one of them again.
+#[derive(Deserialize)]
+pub struct DocumentsRequest {
+    pub docs: Vec<DocRef>,
+    #[serde(default)]
+    pub text: bool,
+    #[serde(default)]
+    pub fields: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum DocRef {
+    Id(DocId),
+    Path(PathBuf),
+}
+
+// POST /api/docs: the path, ID and metadata of several documents at once,
+// with their text if asked for, in the order they were asked for. A document
+// the index does not have gets an error in its place, a moved one is looked
+// up at its new path and tells where it was asked for with `moved_from`.
+pub fn documents(handle: &SearchHandle, body: &str) -> Result<Value, Value> {
+    let request: DocumentsRequest = serde_json::from_str(body).map_err(|err| {
+        error(
+            ErrorCode::InvalidBody,
+            format!("the body must be a JSON object with a list of docs, paths or IDs: {err}"),
+        )
+    })?;
+    if request.docs.len() > MAX_PAGE_SIZE {
+        return Err(error(
+            ErrorCode::InvalidBody,
+            format!("at most {MAX_PAGE_SIZE} documents can be asked for at once"),
+        ));
+    }
+    let model = handle.snapshot();
+    // IDs are only mapped to paths when some are asked for.
+    let by_id = if request.docs.iter().any(|doc| matches!(doc, DocRef::Id(_))) {
+        model
+            .docs
+            .iter()
+            .map(|(path, doc)| (doc.id, path))
+            .collect::<HashMap<_, _>>()
+    } else {
+        HashMap::new()
+    };
+    let bundle = handle.sources();
+    let sources = bundle.as_deref().map(|bundle| bundle.sources(&model));
+    let not_found = |asked: Value| {
+        let mut missing = error(ErrorCode::DocNotFound, "no such document in the index");
+        missing["asked"] = asked;
+        missing
+    };
+    let docs = request
+        .docs
+        .iter()
+        .map(|asked| {
+            let path = match asked {
+                DocRef::Id(id) => match by_id.get(id).filter(|_| *id != 0) {
+                    Some(path) => path.to_path_buf(),
+                    None => return not_found(json!(id)),
+                },
+                DocRef::Path(path) => path.clone(),
+            };
+            let target = model.resolve_alias(&path);
+            let moved = target.is_some();
+            let resolved = target.unwrap_or_else(|| path.clone());
+            let Some(doc) = model.doc(&resolved) else {
+                return not_found(json!(path));
+            };
+            let mut document = if request.text {
+                self::document(&model, &resolved, sources, handle.extract_options())
+                    .expect("the document is in the index")
+            } else {
+                json!({"path": resolved, "meta": doc.meta})
+            };
+            if doc.id != 0 {
+                document["id"] = json!(doc.id);
+            }
+            if moved {
+                document["moved_from"] = json!(path);
+            }
+            document
+        })
+        .collect::<Vec<_>>();
+    let mut payload = json!({"docs": docs});
+    select_fields(&mut payload, &request.fields);
+    Ok(payload)
+}
+
+// The body of POST /api/percolate: a document that is not in the index.
+#[derive(Deserialize)]
+pub struct PercolateRequest {
+    pub text: String,
+    #[serde(default)]
+    pub meta: Metadata,
+}
+
+// POST /api/percolate: the names of the queries stored in the index that
+// the posted document matches.
+pub fn percolate(handle: &SearchHandle, body: &str) -> Result<Value, Value> {
+    let request: PercolateRequest = serde_json::from_str(body).map_err(|err| {
+        error(
+            ErrorCode::InvalidBody,
+            format!(
+                "the body must be a JSON object with the text of a document and its meta: {err}"
+            ),
+        )
+    })?;
+    let percolator =
+        Percolator::new(&handle.snapshot()).map_err(|err| error(err.code(), err.to_string()))?;
+    let matches = percolator
+        .matches(&request.text, request.meta)
+        .map_err(|err| error(err.code(), err.to_string()))?;
+    Ok(json!({"matches": matches}))
+}
+
+// GET /api/complete: index terms starting with the last word of `q`, those
+// in the most documents first, at most `limit` of them. A query ending in a
+// space has no word to complete.
+pub fn complete(handle: &SearchHandle, params: &[(String, String)]) -> Value {
+    let param = |name: &str| {
+        params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+    let query = param("q").unwrap_or_default();
+    let limit = param("limit")
+        .and_then(|limit| limit.parse().ok())
+        .unwrap_or(DEFAULT_COMPLETIONS)
+        .min(MAX_COMPLETIONS);
+    let completions = if query.ends_with(char::is_whitespace) {
+        Vec::new()
+    } else {
+        handle.suggest(query, limit)
+    };
+    json!({
+        "query": query,
+        "completions": completions
+            .into_iter()
+            .map(|(term, docs)| json!({"term": term, "docs": docs}))
+            .collect::<Vec<_>>(),
+    })
+}
+
+// GET /api/facets: the counts of the values of every `facet` over the
+// matches of `q` and `filter`, `{field: {value: count}}`, of the language
+// of the documents when no facet is asked for.
+pub fn facets(
+    handle: &SearchHandle,
+    params: &[(String, String)],
+    limits: &QueryLimits,
+    sets: &ResultSets,
+) -> Result<Value, Value> {
+    let mut request = SearchRequest::from_params(params);
+    request.hits = false;
+    if request.facets.is_empty() {
+        request.facets.push(language::LANG_KEY.to_string());
+    }
+    let mut payload = search(handle, &request, limits, sets, None)?;
+    Ok(payload["facets"].take())
+}
+
+// GET /api/aggregate: documents counted per `field` (`ext`, `date` with an
+// `interval` of year, month or day, or a metadata field), over the matches
+// of `q` and `filter` or, without a query, over the whole corpus.
+pub fn aggregate(
+    handle: &SearchHandle,
+    params: &[(String, String)],
+    limits: &QueryLimits,
+) -> Result<Value, Value> {
+    let request = SearchRequest::from_params(params);
+    let param = |name: &str| {
+        params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+    let error = |message: String| json!({"query": request.query, "error": {"code": ErrorCode::InvalidAggregate.as_str(), "message": message}});
+    let field = param("field")
+        .filter(|field| !field.is_empty())
+        .ok_or_else(|| error("aggregate needs a field, e.g. field=ext".to_string()))?;
+    let metric = param("metric").unwrap_or("count");
+    if metric != "count" {
+        return Err(error(format!(
+            "unknown metric {metric}, only count is supported"
+        )));
+    }
+    let interval_name = param("interval").unwrap_or("month");
+    let interval = Interval::parse(interval_name).ok_or_else(|| {
+        error(format!(
+            "unknown interval {interval_name}, expected year, month or day"
+        ))
+    })?;
+
+    let model = handle.snapshot();
+    let mut aggregate = Aggregate::new(&model, GroupBy::parse(field, interval));
+    if request.query.trim().is_empty() {
+        let filters = parse_filters(&request)?;
+        let _count = debug_span!("count").entered();
+        for (path, doc) in &model.docs {
+            if !exclude::is_excluded(doc)
+                && !model.is_moved(path)
+                && filter::matches_all(&filters, &model.manifest.config.fields, doc)
+            {
+                aggregate.collect(path, 0.0);
+            }
+        }
+    } else {
+        let (query, filters) = prepare(handle, &request, limits)?;
+        debug_span!("count").in_scope(|| handle.collect(&query, &filters, &mut aggregate));
+    }
+    let mut payload = aggregate.to_json();
+    payload["field"] = json!(field);
+    payload["metric"] = json!(metric);
+    if matches!(GroupBy::parse(field, interval), GroupBy::Date(_)) {
+        payload["interval"] = json!(interval.name());
+    }
+    if !request.query.trim().is_empty() {
+        payload["query"] = json!(request.query);
+    }
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tinysearch::config::IndexConfig;
+
+    #[test]
+    fn completions_are_not_case_folded() {
+        let mut model = Model::new(IndexConfig::default());
+        model.add_document("a.txt", "Search the SEARCHES", Metadata::new());
+        let handle = SearchHandle::new(model);
+        let completions = complete(&handle, &[("q".to_string(), "sea".to_string())]);
+        assert_eq!(
+            completions["completions"],
+            json!([{"term": "search", "docs": 1}, {"term": "searches", "docs": 1}])
+        );
+    }
+}
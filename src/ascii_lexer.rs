@@ -0,0 +1,85 @@
+// Fast path of the Lexer for pure ASCII text, which is most of what gets
+// indexed. It works on bytes instead of a Vec<char>, and finds the end of
+// letter and digit runs 8 bytes at a time, classifying every byte of a u64 at
+// once (SIMD within a register). Tokens are the same the Lexer produces.
+use crate::code_token_len;
+
+const LOW_BITS: u64 = 0x0101_0101_0101_0101;
+const HIGH_BITS: u64 = 0x8080_8080_8080_8080;
+
+// High bit set in every byte of `word` that lies in `lo..=hi`. Only valid for
+// ASCII bytes, so that adding to a byte never carries into the next one.
+fn bytes_in_range(word: u64, lo: u8, hi: u8) -> u64 {
+    let above_lo = word + LOW_BITS * (0x80 - lo as u64);
+    let above_hi = word + LOW_BITS * (0x80 - (hi as u64 + 1));
+    above_lo & !above_hi & HIGH_BITS
+}
+
+fn letters(word: u64) -> u64 {
+    // Setting 0x20 folds uppercase letters onto lowercase ones.
+    bytes_in_range(word | (LOW_BITS * 0x20), b'a', b'z')
+}
+
+fn digits(word: u64) -> u64 {
+    bytes_in_range(word, b'0', b'9')
+}
+
+// Index of the first byte from `start` on that is not in the class.
+fn run_end(bytes: &[u8], start: usize, class: fn(u64) -> u64, is: fn(&u8) -> bool) -> usize {
+    let mut at = start;
+    while at + 8 <= bytes.len() {
+        let word = u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+        let outside = !class(word) & HIGH_BITS;
+        if outside != 0 {
+            return at + outside.trailing_zeros() as usize / 8;
+        }
+        at += 8;
+    }
+    while at < bytes.len() && is(&bytes[at]) {
+        at += 1;
+    }
+    at
+}
+
+// `char::is_whitespace` restricted to ASCII, which unlike
+// `u8::is_ascii_whitespace` includes the vertical tab.
+fn is_whitespace(byte: u8) -> bool {
+    matches!(byte, b' ' | b'\t'..=b'\r')
+}
+
+pub fn for_each_token<'a>(text: &'a str, mut f: impl FnMut(&'a str)) {
+    debug_assert!(text.is_ascii());
+    let bytes = text.as_bytes();
+    let mut at = 0;
+    while at < bytes.len() {
+        let byte = bytes[at];
+        let end = if is_whitespace(byte) {
+            at += 1;
+            continue;
+        } else if byte.is_ascii_digit() {
+            run_end(bytes, at, digits, u8::is_ascii_digit)
+        } else if byte.is_ascii_alphabetic() {
+            run_end(bytes, at, letters, u8::is_ascii_alphabetic)
+        } else {
+            at + 1
+        };
+        f(&text[at..end]);
+        at = end;
+    }
+}
+
+// The tokens of the code tokenizer, as `Lexer::code` produces them.
+pub fn for_each_code_token<'a>(text: &'a str, joiners: &str, mut f: impl FnMut(&'a str)) {
+    debug_assert!(text.is_ascii());
+    let bytes = text.as_bytes();
+    let mut at = 0;
+    while at < bytes.len() {
+        if is_whitespace(bytes[at]) {
+            at += 1;
+            continue;
+        }
+        let end = at + code_token_len(&bytes[at..], joiners).max(1);
+        f(&text[at..end]);
+        at = end;
+    }
+}
@@ -0,0 +1,39 @@
+// A set of document ordinals as one bit per document of an index, for
+// remembering which documents a filter lets through: a lookup is a shift and
+// a mask, and combining filters is an AND of words.
+use std::mem::size_of;
+
+#[derive(Clone)]
+pub struct Bitset {
+    words: Vec<u64>,
+}
+
+impl Bitset {
+    // An empty set of the ordinals below `len`.
+    pub fn new(len: usize) -> Self {
+        Self {
+            words: vec![0; len.div_ceil(64)],
+        }
+    }
+
+    pub fn insert(&mut self, ordinal: usize) {
+        self.words[ordinal / 64] |= 1 << (ordinal % 64);
+    }
+
+    pub fn contains(&self, ordinal: usize) -> bool {
+        self.words
+            .get(ordinal / 64)
+            .is_some_and(|word| word & (1 << (ordinal % 64)) != 0)
+    }
+
+    // Keeps the ordinals that are in `other` too.
+    pub fn intersect_with(&mut self, other: &Bitset) {
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word &= other;
+        }
+    }
+
+    pub fn bytes(&self) -> usize {
+        size_of::<Self>() + self.words.len() * size_of::<u64>()
+    }
+}
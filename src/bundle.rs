@@ -0,0 +1,128 @@
+// Copies of the source files of an index, kept by `index --bundle-sources` in
+// `<index>.sources/` next to it, so a server without the corpus can still
+// show the text of documents. Copies are named by the content hash the
+// manifest records for every source file, so unchanged files are not copied
+// again and identical files are kept once. Builds with the `compression`
+// feature compress them with zstd.
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::extract::{self, ExtractOptions};
+use crate::snippet::split_doc_path;
+use crate::{FileStamps, Model};
+
+#[cfg(feature = "compression")]
+const ZSTD_LEVEL: i32 = 3;
+
+pub struct SourceBundle {
+    dir: PathBuf,
+}
+
+impl SourceBundle {
+    // The bundle of the index at `index_path`.
+    pub fn beside(index_path: &Path) -> Self {
+        let mut dir = index_path.as_os_str().to_owned();
+        dir.push(".sources");
+        Self {
+            dir: PathBuf::from(dir),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn exists(&self) -> bool {
+        self.dir.is_dir()
+    }
+
+    fn copy_path(&self, hash: &str) -> PathBuf {
+        if cfg!(feature = "compression") {
+            self.dir.join(format!("{hash}.zst"))
+        } else {
+            self.dir.join(hash)
+        }
+    }
+
+    // Keeps a copy of a source file with content hash `hash`, unless the
+    // bundle already has one.
+    pub fn add(&self, hash: &str, bytes: &[u8]) -> io::Result<()> {
+        let path = self.copy_path(hash);
+        if path.exists() {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.dir)?;
+        #[cfg(feature = "compression")]
+        let bytes = &zstd::encode_all(bytes, ZSTD_LEVEL)?[..];
+        // Written under another name first, so a copy is never half there.
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, bytes)?;
+        fs::rename(&tmp_path, &path)
+    }
+
+    // The source file with content hash `hash`, if the bundle has it.
+    pub fn read(&self, hash: &str) -> Option<Vec<u8>> {
+        let bytes = fs::read(self.copy_path(hash)).ok()?;
+        #[cfg(feature = "compression")]
+        let bytes = zstd::decode_all(&bytes[..]).ok()?;
+        Some(bytes)
+    }
+
+    // Removes the copies of files no longer in the index, returning how
+    // many there were.
+    pub fn retain(&self, files: &FileStamps) -> io::Result<usize> {
+        let kept = files
+            .values()
+            .map(|stamp| self.copy_path(&stamp.hash))
+            .collect::<HashSet<_>>();
+        let mut removed = 0;
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if !kept.contains(&path) {
+                fs::remove_file(&path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    // Where the documents of `model`, whose bundle this is, are read from.
+    pub fn sources<'a>(&'a self, model: &'a Model) -> Sources<'a> {
+        Sources {
+            bundle: self,
+            files: &model.manifest.files,
+        }
+    }
+
+    // The text of a document of an index whose source files are `files`,
+    // extracted from the copy of its file.
+    pub fn document_text(&self, files: &FileStamps, doc_path: &Path) -> Option<String> {
+        let (file_path, anchor) = match files.get(&*doc_path.to_string_lossy()) {
+            Some(_) => (doc_path, None),
+            None => split_doc_path(doc_path),
+        };
+        let bytes = self.read(&files.get(&*file_path.to_string_lossy())?.hash)?;
+        let chunks =
+            extract::extract_document(file_path, &bytes, &ExtractOptions::default()).ok()?;
+        chunks
+            .into_iter()
+            .find(|chunk| chunk.anchor.as_deref() == anchor)
+            .map(|chunk| chunk.text)
+    }
+}
+
+// Where the text of documents comes from when their files are not there:
+// the bundle of an index and the source files of its manifest.
+#[derive(Clone, Copy)]
+pub struct Sources<'a> {
+    pub bundle: &'a SourceBundle,
+    pub files: &'a FileStamps,
+}
+
+impl Sources<'_> {
+    pub fn document_text(&self, doc_path: &Path) -> Option<String> {
+        self.bundle.document_text(self.files, doc_path)
+    }
+}
@@ -0,0 +1,97 @@
+// In-memory caches of a loaded index. Each is a fixed number of entries
+// evicting the least recently used one, and counts its hits and misses so
+// its hit rate can be reported.
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::mem::size_of;
+
+#[derive(Clone, Copy, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl CacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+pub struct Lru<K, V> {
+    capacity: usize,
+    // Every entry with the tick of its last use; `by_use` orders the ticks.
+    entries: HashMap<K, (V, u64)>,
+    by_use: BTreeMap<u64, K>,
+    tick: u64,
+    stats: CacheStats,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Lru<K, V> {
+    // A cache of `capacity` entries. With no room at all it never hits.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            by_use: BTreeMap::new(),
+            tick: 0,
+            stats: CacheStats::default(),
+        }
+    }
+
+    pub fn get<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.tick += 1;
+        let Some((value, used)) = self.entries.get_mut(key) else {
+            self.stats.misses += 1;
+            return None;
+        };
+        self.stats.hits += 1;
+        let key = self.by_use.remove(used)?;
+        *used = self.tick;
+        self.by_use.insert(self.tick, key);
+        Some(value.clone())
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.tick += 1;
+        if let Some((_, used)) = self.entries.get(&key) {
+            self.by_use.remove(used);
+        } else if self.entries.len() == self.capacity {
+            if let Some((_, oldest)) = self.by_use.pop_first() {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.by_use.insert(self.tick, key.clone());
+        self.entries.insert(key, (value, self.tick));
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    // Drops every entry, keeping the counts of hits and misses.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.by_use.clear();
+    }
+
+    // Estimated bytes of the entries, with `size` the bytes of one.
+    pub fn bytes(&self, size: impl Fn(&K, &V) -> usize) -> usize {
+        self.entries
+            .iter()
+            .map(|(key, (value, _))| size(key, value) + 2 * size_of::<u64>())
+            .sum()
+    }
+}
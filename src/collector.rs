@@ -0,0 +1,200 @@
+// What a search does with the documents that match. The ranked list of hits
+// is one choice; counting the matches, tallying a metadata field or keeping
+// the best documents by some other key need neither the full list nor, for
+// the counting ones, the scores.
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BinaryHeap};
+use std::path::{Path, PathBuf};
+
+use crate::{MetaValue, Model};
+
+pub trait Collector {
+    // Called once for every matching document, in no particular order.
+    fn collect(&mut self, path: &Path, score: f32);
+
+    // Whether `collect` looks at the score. Searches skip scoring for
+    // collectors that do not, and pass 0.0 instead.
+    fn needs_scores(&self) -> bool {
+        true
+    }
+}
+
+// Number of matching documents.
+#[derive(Default)]
+pub struct Count(pub usize);
+
+impl Collector for Count {
+    fn collect(&mut self, _path: &Path, _score: f32) {
+        self.0 += 1;
+    }
+
+    fn needs_scores(&self) -> bool {
+        false
+    }
+}
+
+// Matching documents per value of a metadata field, e.g. per author.
+// Documents without the field are not counted, those with several values
+// count once for each.
+pub struct FacetCounts<'a> {
+    model: &'a Model,
+    field: String,
+    pub counts: BTreeMap<String, usize>,
+}
+
+impl<'a> FacetCounts<'a> {
+    // `model` has to be the index searched, e.g. `SearchHandle::snapshot`.
+    pub fn new(model: &'a Model, field: impl Into<String>) -> Self {
+        Self {
+            model,
+            field: field.into(),
+            counts: BTreeMap::new(),
+        }
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+}
+
+impl Collector for FacetCounts<'_> {
+    fn collect(&mut self, path: &Path, _score: f32) {
+        let value = self
+            .model
+            .docs
+            .get(&*path.to_string_lossy())
+            .and_then(|doc| doc.meta.get(&self.field));
+        for value in value.map(MetaValue::values).unwrap_or_default() {
+            *self.counts.entry(value.clone()).or_insert(0) += 1;
+        }
+    }
+
+    fn needs_scores(&self) -> bool {
+        false
+    }
+}
+
+// A kept document, ordered so that better ones compare greater: higher
+// score first, then the smaller path, as in the ranked list.
+struct Hit {
+    score: f32,
+    path: PathBuf,
+}
+
+impl Ord for Hit {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score
+            .total_cmp(&other.score)
+            .then_with(|| other.path.cmp(&self.path))
+    }
+}
+
+impl PartialOrd for Hit {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Hit {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Hit {}
+
+// The best `limit` documents by score. Only they are kept, in a heap whose
+// top is the worst of them, so a broad query does not sort every match.
+pub struct TopDocs {
+    limit: usize,
+    hits: BinaryHeap<Reverse<Hit>>,
+}
+
+impl TopDocs {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            hits: BinaryHeap::new(),
+        }
+    }
+
+    // Best first.
+    pub fn into_sorted(self) -> Vec<(PathBuf, f32)> {
+        self.hits
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(hit)| (hit.path, hit.score))
+            .collect()
+    }
+}
+
+impl Collector for TopDocs {
+    fn collect(&mut self, path: &Path, score: f32) {
+        if self.limit == 0 {
+            return;
+        }
+        if self.hits.len() == self.limit {
+            let Some(Reverse(worst)) = self.hits.peek() else {
+                return;
+            };
+            let better = score
+                .total_cmp(&worst.score)
+                .then_with(|| worst.path.as_path().cmp(path))
+                .is_gt();
+            if !better {
+                return;
+            }
+            self.hits.pop();
+        }
+        self.hits.push(Reverse(Hit {
+            score,
+            path: path.to_path_buf(),
+        }));
+    }
+}
+
+// The best `limit` documents by a key computed from their path and score,
+// e.g. the newest by a date from the metadata. Ties go to the smaller path.
+pub struct TopBy<K, F> {
+    limit: usize,
+    key: F,
+    hits: BinaryHeap<Reverse<(K, Reverse<PathBuf>)>>,
+}
+
+impl<K: Ord, F: FnMut(&Path, f32) -> K> TopBy<K, F> {
+    pub fn new(limit: usize, key: F) -> Self {
+        Self {
+            limit,
+            key,
+            hits: BinaryHeap::new(),
+        }
+    }
+
+    // Best first, with their keys.
+    pub fn into_sorted(self) -> Vec<(PathBuf, K)> {
+        self.hits
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((key, Reverse(path)))| (path, key))
+            .collect()
+    }
+}
+
+impl<K: Ord, F: FnMut(&Path, f32) -> K> Collector for TopBy<K, F> {
+    fn collect(&mut self, path: &Path, score: f32) {
+        if self.limit == 0 {
+            return;
+        }
+        let hit = Reverse(((self.key)(path, score), Reverse(path.to_path_buf())));
+        if self.hits.len() == self.limit {
+            match self.hits.peek() {
+                // The heap holds `Reverse`d hits, so a better hit is smaller.
+                Some(worst) if hit < *worst => {
+                    self.hits.pop();
+                }
+                _ => return,
+            }
+        }
+        self.hits.push(hit);
+    }
+}
@@ -0,0 +1,386 @@
+// How text is turned into terms, configured the same way by the CLI and by
+// programs embedding the engine. The configuration an index was built with
+// is stored in its manifest, and queries against the index are analyzed with
+// it too, so both sides always agree on what a term is.
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::schema::{FieldType, Schema};
+use crate::Error;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Tokenizer {
+    // Runs of letters, runs of digits, and every other character on its own.
+    #[default]
+    Default,
+    // Only the runs of letters and digits, punctuation is dropped.
+    Words,
+    // Identifiers as they are written in code: runs of letters, digits and
+    // `_` such as utf8, sha256 or tf_index, and names ending in `+` or `#`
+    // like C++ and C#. Punctuation is dropped.
+    Code,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stemmer {
+    #[default]
+    None,
+    // Strips English plural endings (Harman's S stemmer).
+    Plural,
+}
+
+impl Tokenizer {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(Self::Default),
+            "words" => Some(Self::Words),
+            "code" => Some(Self::Code),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Default => "default",
+            Self::Words => "words",
+            Self::Code => "code",
+        }
+    }
+}
+
+impl Stemmer {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(Self::None),
+            "plural" => Some(Self::Plural),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Plural => "plural",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IndexConfig {
+    pub tokenizer: Tokenizer,
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub stopwords: BTreeSet<String>,
+    // Index the stopwords too and only leave them out of queries, so a query
+    // can still ask for them with `stopwords=keep`.
+    #[serde(skip_serializing_if = "is_false")]
+    pub index_stopwords: bool,
+    pub stemmer: Stemmer,
+    // Characters besides `_` the code tokenizer keeps inside a token when a
+    // letter or digit follows, such as `-` and `.` for utf-8 and v1.2.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub joiners: String,
+    // Modules of the token filter plugins the terms went through, see
+    // `plugins`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub token_filters: Vec<PathBuf>,
+    // Record where terms occur, so phrases only match their words in order.
+    // It does not change the terms, so searches do not check it.
+    #[serde(skip_serializing_if = "is_false")]
+    pub positions: bool,
+    // Types of metadata fields, see `schema`. They shape what is stored
+    // rather than the terms of queries, so searches do not check them either.
+    #[serde(skip_serializing_if = "Schema::is_empty")]
+    pub fields: Schema,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+// Names of the configurations `preset` knows: the corpus profiles.
+pub const PRESET_NAMES: &[&str] = &["default", "code", "docs", "notes", "web"];
+
+// Words too common in English prose to tell documents apart.
+const ENGLISH_STOPWORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "from", "has", "have", "he",
+    "her", "his", "i", "if", "in", "into", "is", "it", "its", "of", "on", "or", "our", "she", "so",
+    "that", "the", "their", "them", "then", "there", "these", "they", "this", "to", "was", "we",
+    "were", "what", "when", "which", "who", "will", "with", "you", "your",
+];
+
+// Stopword lists `--stopwords` knows by name.
+pub const STOPWORD_LISTS: &[(&str, &[&str])] =
+    &[("english", ENGLISH_STOPWORDS), ("web", WEB_STOPWORDS)];
+
+// What the navigation, banners and footers of web pages repeat on every page.
+const WEB_STOPWORDS: &[&str] = &[
+    "accept",
+    "cookie",
+    "cookies",
+    "copyright",
+    "home",
+    "login",
+    "menu",
+    "navigation",
+    "policy",
+    "privacy",
+    "rights",
+    "reserved",
+    "share",
+    "skip",
+    "subscribe",
+];
+
+impl IndexConfig {
+    pub fn builder() -> IndexConfigBuilder {
+        IndexConfigBuilder::default()
+    }
+
+    // A named starting point that can still be adjusted with the builder:
+    // `default`, or the profile of a kind of corpus.
+    //
+    // code: identifiers without the punctuation around them, every word kept
+    //   as written, since `is` or `as` may be what is searched for.
+    // docs: prose, without common English words and with plurals folded.
+    // notes: short texts, where every word may be the only one that matches,
+    //   with plurals folded.
+    // web: like docs, also dropping the words of cookie banners and menus.
+    pub fn preset(name: &str) -> Option<IndexConfigBuilder> {
+        let builder = Self::builder();
+        match name {
+            "default" => Some(builder),
+            "code" => Some(builder.tokenizer(Tokenizer::Code)),
+            "docs" => Some(
+                builder
+                    .tokenizer(Tokenizer::Words)
+                    .stopwords(ENGLISH_STOPWORDS.iter().copied())
+                    .stemmer(Stemmer::Plural),
+            ),
+            "notes" => Some(builder.tokenizer(Tokenizer::Words).stemmer(Stemmer::Plural)),
+            "web" => Some(
+                builder
+                    .tokenizer(Tokenizer::Words)
+                    .stopwords(ENGLISH_STOPWORDS.iter().chain(WEB_STOPWORDS).copied())
+                    .stemmer(Stemmer::Plural),
+            ),
+            _ => None,
+        }
+    }
+
+    // The profile named `name`: one of `profiles`, which may also replace a
+    // bundled one, or a bundled one.
+    pub fn profile(name: &str, profiles: &Profiles) -> Option<IndexConfig> {
+        match profiles.get(name) {
+            Some(profile) => Some(profile.clone()),
+            None => Some(Self::preset(name)?.build()),
+        }
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    // The settings that differ from `requested`, one line each, named after
+    // the flags that set them.
+    pub fn differences(&self, requested: &IndexConfig) -> Vec<String> {
+        let stopwords = |config: &IndexConfig| {
+            if config.stopwords.is_empty() {
+                "none".to_string()
+            } else {
+                config
+                    .stopwords
+                    .iter()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(",")
+            }
+        };
+        let mut differences = Vec::new();
+        if self.tokenizer != requested.tokenizer {
+            differences.push(format!(
+                "--tokenizer {requested} was requested, the index uses {stored}",
+                requested = requested.tokenizer.name(),
+                stored = self.tokenizer.name()
+            ));
+        }
+        if self.stopwords != requested.stopwords {
+            differences.push(format!(
+                "--stopwords {requested} was requested, the index uses {stored}",
+                requested = stopwords(requested),
+                stored = stopwords(self)
+            ));
+        }
+        if self.index_stopwords != requested.index_stopwords {
+            differences.push(format!(
+                "{requested} was requested, the index {stored}",
+                requested = if requested.index_stopwords {
+                    "--index-stopwords"
+                } else {
+                    "leaving out the stopwords"
+                },
+                stored = if self.index_stopwords {
+                    "indexes them"
+                } else {
+                    "leaves them out"
+                }
+            ));
+        }
+        if self.joiners != requested.joiners {
+            differences.push(format!(
+                "--joiners \"{requested}\" was requested, the index uses \"{stored}\"",
+                requested = requested.joiners,
+                stored = self.joiners
+            ));
+        }
+        if self.stemmer != requested.stemmer {
+            differences.push(format!(
+                "--stemmer {requested} was requested, the index uses {stored}",
+                requested = requested.stemmer.name(),
+                stored = self.stemmer.name()
+            ));
+        }
+        if self.token_filters != requested.token_filters {
+            let filters = |config: &IndexConfig| {
+                if config.token_filters.is_empty() {
+                    "none".to_string()
+                } else {
+                    config
+                        .token_filters
+                        .iter()
+                        .map(|module| module.display().to_string())
+                        .collect::<Vec<_>>()
+                        .join(",")
+                }
+            };
+            differences.push(format!(
+                "token filter plugins {requested} were requested, the index used {stored}",
+                requested = filters(requested),
+                stored = filters(self)
+            ));
+        }
+        differences
+    }
+}
+
+// The stopwords of a `--stopwords` value: comma separated words, names of
+// the bundled lists and files with one word per line, e.g.
+// `english,via,stop.txt`. Lines of a file starting with `#` are comments.
+pub fn parse_stopwords(value: &str) -> Result<Vec<String>, Error> {
+    let mut stopwords = Vec::new();
+    for item in value.split(',').filter(|item| !item.is_empty()) {
+        if let Some((_, list)) = STOPWORD_LISTS.iter().find(|(name, _)| *name == item) {
+            stopwords.extend(list.iter().map(|word| word.to_string()));
+        } else if Path::new(item).is_file() {
+            let text = fs::read_to_string(item)
+                .map_err(|err| Error::io(format!("could not read stopwords {item}"), err))?;
+            stopwords.extend(
+                text.lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(str::to_string),
+            );
+        } else {
+            stopwords.push(item.to_string());
+        }
+    }
+    Ok(stopwords)
+}
+
+// Profiles users define in a JSON file, mapping their names to
+// configurations like those of the manifest:
+//
+//   {"recipes": {"tokenizer": "words", "stopwords": ["cup", "tbsp"], "stemmer": "plural",
+//                "fields": {"cuisine": "keyword", "minutes": "numeric"}}}
+pub type Profiles = BTreeMap<String, IndexConfig>;
+
+pub fn load_profiles(path: &str) -> Result<Profiles, Error> {
+    let json = fs::read_to_string(path)
+        .map_err(|err| Error::io(format!("could not read profiles {path}"), err))?;
+    serde_json::from_str(&json)
+        .map_err(|err| Error::json(format!("could not parse profiles {path}"), err))
+}
+
+// Settings given one by one win over those of the profile, whatever the
+// order they are given in, and stopwords and fields add to those of the
+// profile.
+#[derive(Default, Clone)]
+pub struct IndexConfigBuilder {
+    profile: IndexConfig,
+    tokenizer: Option<Tokenizer>,
+    stopwords: BTreeSet<String>,
+    index_stopwords: Option<bool>,
+    stemmer: Option<Stemmer>,
+    joiners: Option<String>,
+    token_filters: Option<Vec<PathBuf>>,
+    positions: Option<bool>,
+    fields: Schema,
+}
+
+impl IndexConfigBuilder {
+    pub fn profile(mut self, profile: IndexConfig) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    pub fn tokenizer(mut self, tokenizer: Tokenizer) -> Self {
+        self.tokenizer = Some(tokenizer);
+        self
+    }
+
+    pub fn stopwords<I, S>(mut self, stopwords: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.stopwords.extend(stopwords.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn index_stopwords(mut self, index_stopwords: bool) -> Self {
+        self.index_stopwords = Some(index_stopwords);
+        self
+    }
+
+    pub fn stemmer(mut self, stemmer: Stemmer) -> Self {
+        self.stemmer = Some(stemmer);
+        self
+    }
+
+    pub fn joiners(mut self, joiners: impl Into<String>) -> Self {
+        self.joiners = Some(joiners.into());
+        self
+    }
+
+    pub fn token_filters(mut self, modules: Vec<PathBuf>) -> Self {
+        self.token_filters = Some(modules);
+        self
+    }
+
+    pub fn positions(mut self, positions: bool) -> Self {
+        self.positions = Some(positions);
+        self
+    }
+
+    pub fn field(mut self, name: impl Into<String>, field: FieldType) -> Self {
+        self.fields.insert(name.into(), field);
+        self
+    }
+
+    pub fn build(self) -> IndexConfig {
+        let mut config = self.profile;
+        config.tokenizer = self.tokenizer.unwrap_or(config.tokenizer);
+        config.stopwords.extend(self.stopwords);
+        config.index_stopwords = self.index_stopwords.unwrap_or(config.index_stopwords);
+        config.stemmer = self.stemmer.unwrap_or(config.stemmer);
+        config.joiners = self.joiners.unwrap_or(config.joiners);
+        config.token_filters = self.token_filters.unwrap_or(config.token_filters);
+        config.positions = self.positions.unwrap_or(config.positions);
+        config.fields.extend(self.fields);
+        config
+    }
+}
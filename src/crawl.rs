@@ -0,0 +1,197 @@
+// Web sites crawled into an index: a start page, the pages of the same origin
+// it links to, and theirs, breadth first up to a depth and a number of pages.
+// Pages are stored under their URL like those of `HttpSource`. Unlike other
+// sources, listing the documents fetches them, as the pages to visit next are
+// only known from the links of those before; they are downloaded with curl.
+use std::collections::{HashSet, VecDeque};
+use std::io::Cursor;
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+use crate::extract;
+use crate::source::{self, DocumentSource, Documents, SourceDocument};
+use crate::{Error, Metadata};
+
+#[derive(Clone)]
+pub struct CrawlOptions {
+    // Links followed from the start page to the farthest page.
+    pub max_depth: usize,
+    pub max_pages: usize,
+    // Pause between two requests, to spare the server.
+    pub delay: Duration,
+    // Leave out the paths robots.txt disallows.
+    pub robots: bool,
+}
+
+impl Default for CrawlOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: 2,
+            max_pages: 100,
+            delay: Duration::ZERO,
+            robots: true,
+        }
+    }
+}
+
+pub struct CrawlSource {
+    pub start: String,
+    pub options: CrawlOptions,
+}
+
+// `scheme://host[:port]` of a URL.
+fn origin(url: &str) -> Option<&str> {
+    let (scheme, rest) = url.split_once("://")?;
+    let host_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    Some(&url[..scheme.len() + 3 + host_end])
+}
+
+fn without_fragment(url: &str) -> &str {
+    url.split_once('#').map_or(url, |(url, _)| url)
+}
+
+struct Page {
+    // The URL it ended up at, after redirects.
+    url: String,
+    content_type: String,
+    body: Vec<u8>,
+}
+
+fn fetch(url: &str) -> Result<Page, Error> {
+    let mut command = Command::new("curl");
+    command.args(["--silent", "--show-error", "--fail", "--location", url]);
+    // Where the page ended up and its type go to stderr, apart from the page.
+    command.args(["--write-out", "%{stderr}%{content_type}\n%{url_effective}"]);
+    let output = command
+        .output()
+        .map_err(|err| Error::io(format!("could not run curl to fetch {url}"), err))?;
+    if !output.status.success() {
+        return Err(Error::Http(format!(
+            "could not fetch {url}: {stderr}",
+            stderr = String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let written = String::from_utf8_lossy(&output.stderr).into_owned();
+    let (content_type, effective) = written.rsplit_once('\n').unwrap_or(("", url));
+    Ok(Page {
+        url: effective.trim().to_string(),
+        content_type: content_type.trim().to_ascii_lowercase(),
+        body: output.stdout,
+    })
+}
+
+// The path prefixes robots.txt disallows for every crawler or for this one.
+fn disallowed_paths(origin: &str) -> Vec<String> {
+    let Ok(robots) = fetch(&format!("{origin}/robots.txt")) else {
+        return Vec::new();
+    };
+    let mut paths = Vec::new();
+    // Whether the lines read apply to us, and whether the user-agent lines
+    // of the group are over.
+    let mut applies = false;
+    let mut in_agents = false;
+    for line in String::from_utf8_lossy(&robots.body).lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match key.trim().to_ascii_lowercase().as_str() {
+            "user-agent" => {
+                let agent = value.to_ascii_lowercase();
+                let ours = agent == "*" || agent.contains("tinysearch");
+                applies = if in_agents { applies || ours } else { ours };
+                in_agents = true;
+            }
+            "disallow" => {
+                in_agents = false;
+                if applies && !value.is_empty() {
+                    paths.push(value.to_string());
+                }
+            }
+            _ => in_agents = false,
+        }
+    }
+    paths
+}
+
+fn path_of<'a>(url: &'a str, origin: &str) -> &'a str {
+    let path = &url[origin.len()..];
+    if path.is_empty() {
+        "/"
+    } else {
+        path
+    }
+}
+
+impl DocumentSource for CrawlSource {
+    fn documents(&self) -> Result<Documents, Error> {
+        let start = without_fragment(&self.start).to_string();
+        let site = origin(&start)
+            .ok_or_else(|| Error::invalid(format!("{start} is not a URL to crawl from")))?
+            .to_string();
+        let disallowed = if self.options.robots {
+            disallowed_paths(&site)
+        } else {
+            Vec::new()
+        };
+        let allowed = |url: &str| {
+            origin(url) == Some(site.as_str()) && {
+                let path = path_of(url, &site);
+                !disallowed
+                    .iter()
+                    .any(|prefix| path.starts_with(prefix.as_str()))
+            }
+        };
+
+        let mut seen = HashSet::from([start.clone()]);
+        let mut queue = VecDeque::from([(start, 0)]);
+        let mut documents = Vec::new();
+        let mut fetched = 0;
+        while let Some((url, depth)) = queue.pop_front() {
+            if documents.len() >= self.options.max_pages {
+                break;
+            }
+            if !allowed(&url) {
+                continue;
+            }
+            if fetched > 0 {
+                thread::sleep(self.options.delay);
+            }
+            fetched += 1;
+            let page = match fetch(&url) {
+                Ok(page) => page,
+                Err(err) => {
+                    eprintln!("WARNING: {err}");
+                    continue;
+                }
+            };
+            // A redirect may lead to a page already crawled or off the site.
+            if page.url != url && (!seen.insert(page.url.clone()) || !allowed(&page.url)) {
+                continue;
+            }
+            if depth < self.options.max_depth && page.content_type.starts_with("text/html") {
+                let html = String::from_utf8_lossy(&page.body);
+                for href in extract::html_links(&html) {
+                    let Some(link) = source::resolve_url(Some(&page.url), &href) else {
+                        continue;
+                    };
+                    let link = without_fragment(&link).to_string();
+                    if allowed(&link) && seen.insert(link.clone()) {
+                        queue.push_back((link, depth + 1));
+                    }
+                }
+            }
+            let mut meta = Metadata::new();
+            meta.insert("url".to_string(), page.url.clone().into());
+            documents.push(SourceDocument {
+                id: page.url.as_str().into(),
+                reader: Box::new(Cursor::new(page.body)),
+                meta,
+                stat: None,
+            });
+        }
+        Ok(Box::new(documents.into_iter()))
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::{DocKey, TermFreqIndex};
+
+// How many terms with the largest document frequency shifts are listed.
+const TOP_TERM_SHIFTS: usize = 20;
+
+fn doc_freqs(tf_index: &TermFreqIndex) -> HashMap<&str, usize> {
+    let mut df = HashMap::new();
+    for doc in tf_index.values() {
+        for term in doc.tf.keys() {
+            *df.entry(term.as_str()).or_insert(0) += 1;
+        }
+    }
+    df
+}
+
+// Documents of a new index that an old one lacks, that it had and that differ
+// between the two, each sorted by path.
+pub struct DocChanges<'a> {
+    pub added: Vec<&'a DocKey>,
+    pub removed: Vec<&'a DocKey>,
+    pub changed: Vec<&'a DocKey>,
+}
+
+pub fn doc_changes<'a>(old: &'a TermFreqIndex, new: &'a TermFreqIndex) -> DocChanges<'a> {
+    let mut added = new
+        .keys()
+        .filter(|path| !old.contains_key(*path))
+        .collect::<Vec<_>>();
+    let mut removed = old
+        .keys()
+        .filter(|path| !new.contains_key(*path))
+        .collect::<Vec<_>>();
+    let mut changed = new
+        .iter()
+        .filter(|(path, doc)| {
+            old.get(*path)
+                .is_some_and(|old_doc| !old_doc.same_content(doc))
+        })
+        .map(|(path, _)| path)
+        .collect::<Vec<_>>();
+    added.sort();
+    removed.sort();
+    changed.sort();
+    DocChanges {
+        added,
+        removed,
+        changed,
+    }
+}
+
+pub fn print_index_diff(old: &TermFreqIndex, new: &TermFreqIndex) {
+    let DocChanges {
+        added,
+        removed,
+        changed,
+    } = doc_changes(old, new);
+
+    println!(
+        "Documents: {old_count} -> {new_count} ({added} added, {removed} removed, {changed} changed)",
+        old_count = old.len(),
+        new_count = new.len(),
+        added = added.len(),
+        removed = removed.len(),
+        changed = changed.len(),
+    );
+    for path in added {
+        println!("  + {path}", path = path.display());
+    }
+    for path in removed {
+        println!("  - {path}", path = path.display());
+    }
+    for path in changed {
+        println!("  ~ {path}", path = path.display());
+    }
+
+    let old_df = doc_freqs(old);
+    let new_df = doc_freqs(new);
+    let terms = old_df.keys().chain(new_df.keys()).collect::<BTreeSet<_>>();
+    let new_terms = terms.iter().filter(|t| !old_df.contains_key(**t)).count();
+    let gone_terms = terms.iter().filter(|t| !new_df.contains_key(**t)).count();
+    println!(
+        "Terms: {old_count} -> {new_count} ({new_terms} new, {gone_terms} gone)",
+        old_count = old_df.len(),
+        new_count = new_df.len(),
+    );
+
+    let mut shifts = terms
+        .into_iter()
+        .map(|term| {
+            let before = old_df.get(term).copied().unwrap_or(0);
+            let after = new_df.get(term).copied().unwrap_or(0);
+            (term, before, after)
+        })
+        .filter(|(_, before, after)| before != after)
+        .collect::<Vec<_>>();
+    shifts.sort_by_key(|(_, before, after)| std::cmp::Reverse(before.abs_diff(*after)));
+    if !shifts.is_empty() {
+        println!("Largest document frequency shifts:");
+    }
+    for (term, before, after) in shifts.into_iter().take(TOP_TERM_SHIFTS) {
+        println!("  {term:>20}: {before} -> {after}");
+    }
+}
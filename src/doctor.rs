@@ -0,0 +1,381 @@
+// Checks of the environment tinySearch runs in, for `tinySearch doctor`: the
+// index, the web UI, the server address, the config files and the tools some
+// formats need. Every check reports what it found and, when something is
+// off, what to do about it. A new check implements `Check` and goes into
+// `checks`.
+use std::env;
+use std::net::TcpListener;
+use std::path::Path;
+
+use tinysearch::store::StoreFormat;
+use tinysearch::{config, fsck, load_model};
+
+use crate::frontend::FrontendConfig;
+use crate::output::Style;
+
+pub struct DoctorOptions {
+    pub index: Option<String>,
+    // The address `serve` would listen at.
+    pub address: String,
+    pub frontend: Option<String>,
+    pub profiles: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Status {
+    Ok,
+    // Works, but something is missing or will not work as expected.
+    Warning,
+    // Something tinySearch needs is broken.
+    Error,
+}
+
+pub struct Finding {
+    pub status: Status,
+    pub message: String,
+    // What to do about a warning or an error.
+    pub hint: Option<String>,
+}
+
+impl Finding {
+    pub fn ok(message: impl Into<String>) -> Self {
+        Self {
+            status: Status::Ok,
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    pub fn warning(message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            status: Status::Warning,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    pub fn error(message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            status: Status::Error,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+pub trait Check {
+    // Short name shown in front of the findings.
+    fn name(&self) -> &'static str;
+
+    fn run(&self, options: &DoctorOptions) -> Vec<Finding>;
+}
+
+// Every check, in the order they run.
+pub fn checks() -> Vec<Box<dyn Check>> {
+    vec![
+        Box::new(IndexCheck),
+        Box::new(ConfigCheck),
+        Box::new(AssetsCheck),
+        Box::new(AddressCheck),
+        Box::new(ToolsCheck),
+    ]
+}
+
+// Runs the checks and prints their findings, failing if any found an error.
+pub fn run(checks: &[Box<dyn Check>], options: &DoctorOptions) -> Result<(), ()> {
+    let style = Style::detect();
+    let mut errors = 0;
+    let mut warnings = 0;
+    for check in checks {
+        for finding in check.run(options) {
+            let status = match finding.status {
+                Status::Ok => style.bold("ok     "),
+                Status::Warning => {
+                    warnings += 1;
+                    style.score("warning", 0.5)
+                }
+                Status::Error => {
+                    errors += 1;
+                    style.error("error  ")
+                }
+            };
+            println!("{status} {:<8} {}", check.name(), finding.message);
+            if let Some(hint) = finding.hint {
+                println!("{}", style.dim(&format!("{:17}{hint}", "")));
+            }
+        }
+    }
+    println!("{errors} errors, {warnings} warnings");
+    if errors > 0 {
+        return Err(());
+    }
+    Ok(())
+}
+
+// The index can be read by this version and is consistent as far as the
+// quick checks of fsck tell.
+struct IndexCheck;
+
+impl Check for IndexCheck {
+    fn name(&self) -> &'static str {
+        "index"
+    }
+
+    fn run(&self, options: &DoctorOptions) -> Vec<Finding> {
+        let index_path = match &options.index {
+            Some(path) => path.as_str(),
+            None if Path::new("index.json").exists() => "index.json",
+            None => {
+                return vec![Finding::warning(
+                    "no index given and no index.json here",
+                    "pass --index <file> to check the index you search",
+                )]
+            }
+        };
+        if !Path::new(index_path).exists() {
+            return vec![Finding::error(
+                format!("{index_path} does not exist"),
+                format!("build it with `tinySearch index <folder> --output {index_path}`"),
+            )];
+        }
+        let rebuild = format!("rebuild it with `tinySearch index <folder> --output {index_path}`");
+        let problems = match fsck::check_index(index_path, false) {
+            Ok(problems) => problems,
+            Err(err) => {
+                return vec![Finding::error(
+                    format!("{index_path} cannot be read by this version of tinySearch: {err}"),
+                    rebuild,
+                )]
+            }
+        };
+        if let Some(first) = problems.first() {
+            return vec![Finding::error(
+                format!(
+                    "{index_path} has {} problems, the first: {first}",
+                    problems.len()
+                ),
+                format!("run `tinySearch fsck {index_path}` for all of them, then {rebuild}"),
+            )];
+        }
+        let Ok(model) = load_model(index_path) else {
+            return vec![Finding::error(
+                format!("{index_path} cannot be loaded"),
+                rebuild,
+            )];
+        };
+        let format = match StoreFormat::detect(index_path) {
+            StoreFormat::Json => "JSON",
+            StoreFormat::Binary { compressed: false } => "binary",
+            StoreFormat::Binary { compressed: true } => "compressed binary",
+            StoreFormat::Sqlite => "SQLite",
+            StoreFormat::Sharded => "sharded",
+        };
+        let mut findings = vec![Finding::ok(format!(
+            "{index_path}: {} documents, {format} format",
+            model.docs.len()
+        ))];
+        if model.docs.is_empty() {
+            findings.push(Finding::warning(
+                format!("{index_path} has no documents, every search comes back empty"),
+                "index a folder with documents into it",
+            ));
+        }
+        findings
+    }
+}
+
+// The config files parse: the profiles and the frontend config.
+struct ConfigCheck;
+
+impl Check for ConfigCheck {
+    fn name(&self) -> &'static str {
+        "config"
+    }
+
+    fn run(&self, options: &DoctorOptions) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        match &options.profiles {
+            Some(path) => findings.push(match config::load_profiles(path) {
+                Ok(profiles) => Finding::ok(format!("{path}: {} profiles", profiles.len())),
+                Err(err) => Finding::error(
+                    format!("{path} is not a valid profiles file: {err}"),
+                    "fix it so it maps profile names to index settings, or remove it",
+                ),
+            }),
+            None => findings.push(Finding::ok(
+                "no profiles file, the bundled profiles are used",
+            )),
+        }
+        if let Some(path) = &options.frontend {
+            findings.push(match FrontendConfig::load(path) {
+                Ok(_) => Finding::ok(format!("{path}: valid frontend config")),
+                Err(err) => Finding::error(
+                    format!("{path} is not a valid frontend config: {err}"),
+                    "fix the JSON or pass no --frontend to use the defaults",
+                ),
+            });
+        }
+        findings
+    }
+}
+
+// The web UI renders, from the bundled files or the custom templates.
+struct AssetsCheck;
+
+impl Check for AssetsCheck {
+    fn name(&self) -> &'static str {
+        "assets"
+    }
+
+    fn run(&self, options: &DoctorOptions) -> Vec<Finding> {
+        let config = match &options.frontend {
+            Some(path) => match FrontendConfig::load(path) {
+                Ok(config) => config,
+                // The config check reports it.
+                Err(_) => return Vec::new(),
+            },
+            None => FrontendConfig::default(),
+        };
+        let source = match &config.templates {
+            Some(dir) if !dir.is_dir() => {
+                return vec![Finding::error(
+                    format!("template folder {} does not exist", dir.display()),
+                    "create it or remove templates from the frontend config",
+                )]
+            }
+            Some(dir) => format!("templates from {}", dir.display()),
+            None => "bundled templates".to_string(),
+        };
+        let mut findings = match config.render() {
+            Ok(_) => vec![Finding::ok(format!("web UI renders with the {source}"))],
+            Err(err) => vec![Finding::error(
+                format!("web UI does not render with the {source}: {err}"),
+                "fix the template it names",
+            )],
+        };
+        if let Some(dir) = config.static_dir.as_ref().filter(|dir| !dir.is_dir()) {
+            findings.push(Finding::warning(
+                format!("static folder {} does not exist", dir.display()),
+                "the bundled index.js and style.css are served until it does",
+            ));
+        }
+        findings
+    }
+}
+
+// `serve` can listen at its address.
+struct AddressCheck;
+
+impl Check for AddressCheck {
+    fn name(&self) -> &'static str {
+        "address"
+    }
+
+    fn run(&self, options: &DoctorOptions) -> Vec<Finding> {
+        let address = &options.address;
+        match TcpListener::bind(address) {
+            Ok(_) => vec![Finding::ok(format!("{address} is free for serve"))],
+            Err(err) => vec![Finding::error(
+                format!("cannot listen at {address}: {err}"),
+                "stop what is using it, or pass serve another address",
+            )],
+        }
+    }
+}
+
+// The programs some formats and features shell out to. Without them
+// tinySearch still runs, with those features off.
+struct ToolsCheck;
+
+// Each program with the package that has it and what does not work
+// without it.
+const TOOLS: &[(&str, &str, &str)] = &[
+    (
+        "tesseract",
+        "tesseract",
+        "scanned PDFs and images are indexed without their text (OCR)",
+    ),
+    (
+        "pdftoppm",
+        "scanned PDFs are indexed without their text (OCR) and PDFs without thumbnails",
+        "scanned PDFs are indexed without their text (OCR)",
+    ),
+    ("unzip", "unzip", ".zip archives cannot be indexed"),
+    ("gzip", "gzip", ".tar.gz archives cannot be indexed"),
+];
+
+// Each cargo feature, whether this build has it and what it brings.
+const FEATURES: &[(&str, bool, &str)] = &[
+    (
+        "extractor-xml",
+        cfg!(feature = "extractor-xml"),
+        "XML extraction",
+    ),
+    (
+        "extractor-pdf",
+        cfg!(feature = "extractor-pdf"),
+        "PDF text extraction",
+    ),
+    (
+        "extractor-html",
+        cfg!(feature = "extractor-html"),
+        "HTML extraction",
+    ),
+    (
+        "store-sqlite",
+        cfg!(feature = "store-sqlite"),
+        "SQLite indexes",
+    ),
+    (
+        "compression",
+        cfg!(feature = "compression"),
+        "compressed indexes",
+    ),
+    ("watch", cfg!(feature = "watch"), "folder watching"),
+    (
+        "metrics",
+        cfg!(feature = "metrics"),
+        "cache metrics and /metrics",
+    ),
+];
+
+fn on_path(program: &str) -> bool {
+    let Some(paths) = env::var_os("PATH") else {
+        return false;
+    };
+    env::split_paths(&paths).any(|dir| {
+        let path = dir.join(program);
+        path.is_file() || path.with_extension("exe").is_file()
+    })
+}
+
+impl Check for ToolsCheck {
+    fn name(&self) -> &'static str {
+        "tools"
+    }
+
+    fn run(&self, _options: &DoctorOptions) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        for (feature, built, what) in FEATURES {
+            findings.push(if *built {
+                Finding::ok(format!("built with {what}"))
+            } else {
+                Finding::warning(
+                    format!("built without {what}"),
+                    format!("rebuild tinySearch with the {feature} feature if you need it"),
+                )
+            });
+        }
+        for (program, package, missing) in TOOLS {
+            findings.push(if on_path(program) {
+                Finding::ok(format!("{program} found"))
+            } else {
+                Finding::warning(
+                    format!("{program} not found: {missing}"),
+                    format!("install {package} so that {program} is on the PATH"),
+                )
+            });
+        }
+        findings
+    }
+}
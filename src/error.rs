@@ -0,0 +1,204 @@
+// What can go wrong in tinySearch. Functions return an Error instead of
+// printing it, with a message saying what they were doing, and only the
+// command line formats it for people, as `ERROR[<code>]: <error>`.
+use std::fmt;
+use std::io;
+
+use crate::query::ParseError;
+use crate::INDEX_VERSION;
+
+#[derive(Debug)]
+pub enum Error {
+    // Reading or writing a file, or running another program, failed.
+    Io(String, io::Error),
+    Json(String, serde_json::Error),
+    #[cfg(feature = "extractor-xml")]
+    Xml(String, xml::reader::Error),
+    #[cfg(feature = "store-sqlite")]
+    Sqlite(String, rusqlite::Error),
+    // Serving a request, or starting the server, failed.
+    Http(String),
+    Query(ParseError),
+    // A document, an index or a value that is not what it should be; the
+    // message says what is wrong.
+    Invalid(String),
+    // The index at the path was written in a newer version of the index
+    // format than this build reads.
+    IndexVersion(String, u32),
+    // Another process, with the PID if it is known, holds the lock of the
+    // index at the path.
+    IndexLocked(String, Option<u32>),
+    // The caller stopped the work, e.g. an indexing run, before it was done.
+    Cancelled,
+    // A plugin stopped working, e.g. a token filter that exited, so what
+    // was analyzed with it since is not what it should be.
+    Plugin(String),
+}
+
+// Stable names of the kinds of failure, the same in the errors of the
+// command line and in the `code` of a failed API request, for scripts and
+// clients to tell failures apart without reading messages. A name, once
+// given, is never changed or reused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    Io,
+    Json,
+    Xml,
+    Sqlite,
+    Http,
+    QueryParse,
+    QueryTooComplex,
+    Invalid,
+    Cancelled,
+    NoIndex,
+    IndexUnreadable,
+    IndexInconsistent,
+    DocNotFound,
+    NotFound,
+    InvalidFilter,
+    InvalidAggregate,
+    InvalidBody,
+    InvalidArguments,
+    ResultSetExpired,
+    Forbidden,
+    MemoryPressure,
+    JobFinished,
+    IndexVersion,
+    IndexLocked,
+    ReadOnly,
+    UnsupportedOverride,
+    UnsupportedApiVersion,
+    // A bug: the work panicked.
+    Internal,
+    Plugin,
+}
+
+impl ErrorCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Io => "E_IO",
+            Self::Json => "E_JSON",
+            Self::Xml => "E_XML",
+            Self::Sqlite => "E_SQLITE",
+            Self::Http => "E_HTTP",
+            Self::QueryParse => "E_QUERY_PARSE",
+            Self::QueryTooComplex => "E_QUERY_TOO_COMPLEX",
+            Self::Invalid => "E_INVALID",
+            Self::Cancelled => "E_CANCELLED",
+            Self::NoIndex => "E_NO_INDEX",
+            Self::IndexUnreadable => "E_INDEX_UNREADABLE",
+            Self::IndexInconsistent => "E_INDEX_INCONSISTENT",
+            Self::DocNotFound => "E_DOC_NOT_FOUND",
+            Self::NotFound => "E_NOT_FOUND",
+            Self::InvalidFilter => "E_INVALID_FILTER",
+            Self::InvalidAggregate => "E_INVALID_AGGREGATE",
+            Self::InvalidBody => "E_INVALID_BODY",
+            Self::InvalidArguments => "E_INVALID_ARGUMENTS",
+            Self::ResultSetExpired => "E_RESULT_SET_EXPIRED",
+            Self::Forbidden => "E_FORBIDDEN",
+            Self::MemoryPressure => "E_MEMORY_PRESSURE",
+            Self::JobFinished => "E_JOB_FINISHED",
+            Self::IndexVersion => "E_INDEX_VERSION",
+            Self::IndexLocked => "E_INDEX_LOCKED",
+            Self::ReadOnly => "E_READ_ONLY",
+            Self::UnsupportedOverride => "E_UNSUPPORTED_OVERRIDE",
+            Self::UnsupportedApiVersion => "E_UNSUPPORTED_API_VERSION",
+            Self::Internal => "E_INTERNAL",
+            Self::Plugin => "E_PLUGIN",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Error {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::Io(..) => ErrorCode::Io,
+            Self::Json(..) => ErrorCode::Json,
+            #[cfg(feature = "extractor-xml")]
+            Self::Xml(..) => ErrorCode::Xml,
+            #[cfg(feature = "store-sqlite")]
+            Self::Sqlite(..) => ErrorCode::Sqlite,
+            Self::Http(_) => ErrorCode::Http,
+            Self::Query(_) => ErrorCode::QueryParse,
+            Self::Invalid(_) => ErrorCode::Invalid,
+            Self::IndexVersion(..) => ErrorCode::IndexVersion,
+            Self::IndexLocked(..) => ErrorCode::IndexLocked,
+            Self::Cancelled => ErrorCode::Cancelled,
+            Self::Plugin(_) => ErrorCode::Plugin,
+        }
+    }
+
+    pub fn io(context: impl Into<String>, err: io::Error) -> Self {
+        Self::Io(context.into(), err)
+    }
+
+    pub fn json(context: impl Into<String>, err: serde_json::Error) -> Self {
+        Self::Json(context.into(), err)
+    }
+
+    pub fn invalid(message: impl Into<String>) -> Self {
+        Self::Invalid(message.into())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(context, err) => write!(f, "{context}: {err}"),
+            Self::Json(context, err) => write!(f, "{context}: {err}"),
+            #[cfg(feature = "extractor-xml")]
+            Self::Xml(context, err) => write!(f, "{context}: {err}"),
+            #[cfg(feature = "store-sqlite")]
+            Self::Sqlite(context, err) => write!(f, "{context}: {err}"),
+            Self::Http(message) | Self::Invalid(message) | Self::Plugin(message) => {
+                f.write_str(message)
+            }
+            Self::Query(err) => write!(f, "{err}"),
+            Self::IndexVersion(path, version) => write!(
+                f,
+                "{path} is an index of format version {version}, and this tinySearch only reads versions up to {INDEX_VERSION}; search it with a newer tinySearch or build it again"
+            ),
+            Self::IndexLocked(path, Some(pid)) => write!(
+                f,
+                "{path} is locked by PID {pid}, which is writing it; try again once it is done"
+            ),
+            Self::IndexLocked(path, None) => write!(
+                f,
+                "{path} is locked by another process writing it; try again once it is done"
+            ),
+            Self::Cancelled => f.write_str("cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(_, err) => Some(err),
+            Self::Json(_, err) => Some(err),
+            #[cfg(feature = "extractor-xml")]
+            Self::Xml(_, err) => Some(err),
+            #[cfg(feature = "store-sqlite")]
+            Self::Sqlite(_, err) => Some(err),
+            Self::Query(err) => Some(err),
+            Self::Http(_)
+            | Self::Invalid(_)
+            | Self::IndexVersion(..)
+            | Self::IndexLocked(..)
+            | Self::Cancelled
+            | Self::Plugin(_) => None,
+        }
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Self {
+        Self::Query(err)
+    }
+}
@@ -0,0 +1,183 @@
+// Relevance evaluation of the ranking against human judgments (qrels), so
+// changes to the analyzer or scoring can be compared with numbers.
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+
+use crate::query::QueryLimits;
+use crate::scoring::{CorpusStats, Ranking};
+use crate::Model;
+use crate::{query, search, Error};
+
+// Only this many results per query are considered, like trec_eval's default.
+const MAX_RANK: usize = 1000;
+const NDCG_DEPTH: usize = 10;
+
+type Qrels = HashMap<String, HashMap<String, u32>>;
+
+fn read_tsv(path: &str) -> Result<String, Error> {
+    fs::read_to_string(path).map_err(|err| Error::io(format!("could not read {path}"), err))
+}
+
+// `qid<TAB>query text` per line.
+fn parse_queries(path: &str) -> Result<BTreeMap<String, String>, Error> {
+    let mut queries = BTreeMap::new();
+    for (row, line) in read_tsv(path)?.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (qid, query) = line.split_once('\t').ok_or_else(|| {
+            Error::invalid(format!(
+                "{path}: {row}: expected `qid<TAB>query`",
+                row = row + 1
+            ))
+        })?;
+        queries.insert(qid.trim().to_string(), query.trim().to_string());
+    }
+    Ok(queries)
+}
+
+// Either `qid<TAB>doc<TAB>relevance` or the 4 column TREC format
+// `qid 0 doc relevance`.
+fn parse_qrels(path: &str) -> Result<Qrels, Error> {
+    let mut qrels = Qrels::new();
+    for (row, line) in read_tsv(path)?.lines().enumerate() {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        let (qid, doc, relevance) = match fields.as_slice() {
+            [] => continue,
+            [qid, doc, relevance] | [qid, _, doc, relevance] => (qid, doc, relevance),
+            _ => {
+                return Err(Error::invalid(format!(
+                    "{path}: {row}: expected `qid<TAB>doc<TAB>relevance`",
+                    row = row + 1
+                )));
+            }
+        };
+        let relevance = relevance.parse::<u32>().map_err(|err| {
+            Error::invalid(format!(
+                "{path}: {row}: invalid relevance {relevance}: {err}",
+                row = row + 1
+            ))
+        })?;
+        qrels
+            .entry(qid.to_string())
+            .or_default()
+            .insert(doc.to_string(), relevance);
+    }
+    Ok(qrels)
+}
+
+struct Metrics {
+    ap: f64,
+    ndcg: f64,
+    rr: f64,
+}
+
+fn evaluate(ranked: &[String], judgments: &HashMap<String, u32>) -> Metrics {
+    let relevant = judgments.values().filter(|&&rel| rel > 0).count();
+    let mut hits = 0;
+    let mut precision_sum = 0.0;
+    let mut rr = 0.0;
+    let mut dcg = 0.0;
+    for (i, doc) in ranked.iter().enumerate() {
+        let rel = judgments.get(doc).copied().unwrap_or(0);
+        if rel == 0 {
+            continue;
+        }
+        hits += 1;
+        precision_sum += hits as f64 / (i + 1) as f64;
+        if rr == 0.0 {
+            rr = 1.0 / (i + 1) as f64;
+        }
+        if i < NDCG_DEPTH {
+            dcg += (2f64.powi(rel as i32) - 1.0) / (i as f64 + 2.0).log2();
+        }
+    }
+
+    let mut ideal = judgments
+        .values()
+        .copied()
+        .filter(|&rel| rel > 0)
+        .collect::<Vec<_>>();
+    ideal.sort_unstable_by(|a, b| b.cmp(a));
+    let idcg = ideal
+        .iter()
+        .take(NDCG_DEPTH)
+        .enumerate()
+        .map(|(i, &rel)| (2f64.powi(rel as i32) - 1.0) / (i as f64 + 2.0).log2())
+        .sum::<f64>();
+
+    Metrics {
+        ap: if relevant == 0 {
+            0.0
+        } else {
+            precision_sum / relevant as f64
+        },
+        ndcg: if idcg == 0.0 { 0.0 } else { dcg / idcg },
+        rr,
+    }
+}
+
+pub fn run_eval(
+    model: &Model,
+    queries_path: &str,
+    qrels_path: &str,
+    ranking: Ranking,
+) -> Result<(), Error> {
+    let queries = parse_queries(queries_path)?;
+    let qrels = parse_qrels(qrels_path)?;
+
+    let analyzer = model.analyzer();
+    let stats = CorpusStats::of(model);
+    let scorer = ranking.scorer(stats.avg_doc_len());
+    let (mut map, mut ndcg, mut mrr, mut evaluated) = (0.0, 0.0, 0.0, 0);
+    println!("{:<12} {:>8} {:>8} {:>8}", "query", "AP", "nDCG@10", "RR");
+    for (qid, query) in &queries {
+        let Some(judgments) = qrels.get(qid) else {
+            eprintln!("WARNING: query {qid} has no judgments, skipping it");
+            continue;
+        };
+        let parsed = match query::parse(query, &analyzer) {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                eprintln!("ERROR: query {qid} is malformed\n{}", err.render(query));
+                continue;
+            }
+        };
+        let parsed = match parsed.expand(&stats, &QueryLimits::default()) {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                eprintln!("ERROR: query {qid} is too broad: {err}");
+                continue;
+            }
+        };
+        let ranked = search::search_query(model, &stats, scorer.as_ref(), &parsed, &[])
+            .into_iter()
+            .take(MAX_RANK)
+            .map(|(path, _)| path.display().to_string())
+            .collect::<Vec<_>>();
+        let metrics = evaluate(&ranked, judgments);
+        println!(
+            "{qid:<12} {:>8.4} {:>8.4} {:>8.4}",
+            metrics.ap, metrics.ndcg, metrics.rr
+        );
+        map += metrics.ap;
+        ndcg += metrics.ndcg;
+        mrr += metrics.rr;
+        evaluated += 1;
+    }
+
+    if evaluated == 0 {
+        return Err(Error::invalid(format!(
+            "none of the queries in {queries_path} have judgments in {qrels_path}"
+        )));
+    }
+    let n = evaluated as f64;
+    println!(
+        "{:<12} {:>8.4} {:>8.4} {:>8.4}",
+        "all",
+        map / n,
+        ndcg / n,
+        mrr / n
+    );
+    Ok(())
+}
@@ -0,0 +1,83 @@
+// Documents hidden from results without re-indexing. `exclude` marks the
+// documents whose path matches a pattern in their metadata, searches skip
+// them, and they come back when they are included again or the index is
+// rebuilt.
+use std::path::Path;
+
+use crate::writer::IndexWriter;
+use crate::Doc;
+
+// Metadata key of a hidden document, holding the pattern that hid it.
+pub const EXCLUDED_KEY: &str = "excluded";
+
+pub fn is_excluded(doc: &Doc) -> bool {
+    doc.meta.contains_key(EXCLUDED_KEY)
+}
+
+// A pattern matched against whole document paths: `**` stands for any run of
+// characters, `*` for any run within one path component and `?` for a single
+// character other than `/`. `notes/**` hides everything under notes/, and
+// `**/*.log` every log file.
+#[derive(Clone)]
+pub struct PathPattern {
+    pattern: Vec<char>,
+}
+
+impl PathPattern {
+    pub fn new(pattern: &str) -> Self {
+        Self {
+            pattern: pattern.chars().collect(),
+        }
+    }
+
+    pub fn has_slash(&self) -> bool {
+        self.pattern.contains(&'/')
+    }
+
+    pub fn matches(&self, path: &Path) -> bool {
+        let path = path.to_string_lossy().chars().collect::<Vec<_>>();
+        glob_matches(&self.pattern, &path)
+    }
+}
+
+fn glob_matches(pattern: &[char], text: &[char]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        ['*', '*', rest @ ..] => {
+            // `a/**/b` also matches `a/b`.
+            let rest_after_slash = match rest {
+                ['/', after @ ..] => Some(after),
+                _ => None,
+            };
+            (0..=text.len()).any(|at| {
+                glob_matches(rest, &text[at..])
+                    || rest_after_slash.is_some_and(|after| glob_matches(after, &text[at..]))
+            })
+        }
+        ['*', rest @ ..] => {
+            let component = text.iter().position(|&c| c == '/').unwrap_or(text.len());
+            (0..=component).any(|at| glob_matches(rest, &text[at..]))
+        }
+        ['?', rest @ ..] => matches!(text, [c, ..] if *c != '/') && glob_matches(rest, &text[1..]),
+        [c, rest @ ..] => text.first() == Some(c) && glob_matches(rest, &text[1..]),
+    }
+}
+
+// Hides the documents matching the pattern. Returns how many were not
+// hidden before.
+pub fn exclude(writer: &mut IndexWriter, pattern: &str) -> usize {
+    let matcher = PathPattern::new(pattern);
+    writer.update_meta(|path, meta| {
+        if !matcher.matches(path) || meta.contains_key(EXCLUDED_KEY) {
+            return false;
+        }
+        meta.insert(EXCLUDED_KEY.to_string(), pattern.into());
+        true
+    })
+}
+
+// Shows the hidden documents matching the pattern again. Returns how many.
+pub fn include(writer: &mut IndexWriter, pattern: &str) -> usize {
+    let matcher = PathPattern::new(pattern);
+    writer.update_meta(|path, meta| matcher.matches(path) && meta.remove(EXCLUDED_KEY).is_some())
+}
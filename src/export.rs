@@ -0,0 +1,159 @@
+// Search results as CSV or as a Markdown table, for pasting into issues and
+// reports. Both are rendered from the result objects of `api::search`.
+use std::path::Path;
+
+use serde_json::Value;
+
+#[derive(Clone, Copy)]
+pub enum ExportFormat {
+    Csv,
+    Markdown,
+}
+
+impl ExportFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "csv" => Some(Self::Csv),
+            "md" | "markdown" => Some(Self::Markdown),
+            _ => None,
+        }
+    }
+
+    // The format an export file is named for, by its extension.
+    pub fn from_path(path: &str) -> Option<Self> {
+        Self::parse(Path::new(path).extension()?.to_str()?)
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Markdown => "text/markdown; charset=utf-8",
+        }
+    }
+
+    // One row per result with its rank, counted from `first_rank`, its
+    // title, path, score and snippet.
+    pub fn render(self, results: &[Value], first_rank: usize) -> String {
+        let rows = results
+            .iter()
+            .enumerate()
+            .map(|(i, result)| Row::of(result, first_rank + i));
+        match self {
+            Self::Csv => {
+                let mut csv = String::from("rank,title,path,score,snippet\r\n");
+                for row in rows {
+                    let snippet = row.snippet(|text, _| text.to_string());
+                    let fields = [
+                        row.rank.to_string(),
+                        row.title,
+                        row.path,
+                        row.score,
+                        snippet,
+                    ];
+                    let fields = fields.iter().map(|field| csv_field(field));
+                    csv.push_str(&fields.collect::<Vec<_>>().join(","));
+                    csv.push_str("\r\n");
+                }
+                csv
+            }
+            Self::Markdown => {
+                let mut markdown =
+                    String::from("| # | Title | Path | Score | Snippet |\n|---|---|---|---|---|\n");
+                for row in rows {
+                    let snippet = row.snippet(|text, hit| {
+                        let text = markdown_cell(text);
+                        // Emphasis markers must hug the text to count.
+                        let start = text.len() - text.trim_start().len();
+                        let end = text.trim_end().len();
+                        if hit && start < end {
+                            format!(
+                                "{}**{}**{}",
+                                &text[..start],
+                                &text[start..end],
+                                &text[end..]
+                            )
+                        } else {
+                            text
+                        }
+                    });
+                    markdown.push_str(&format!(
+                        "| {rank} | {title} | `{path}` | {score} | {snippet} |\n",
+                        rank = row.rank,
+                        title = markdown_cell(&row.title),
+                        path = row.path.replace('`', "'").replace('|', "\\|"),
+                        score = row.score,
+                    ));
+                }
+                markdown
+            }
+        }
+    }
+}
+
+struct Row<'a> {
+    rank: usize,
+    title: String,
+    path: String,
+    score: String,
+    // `[text, is query term]` pieces.
+    pieces: Vec<(&'a str, bool)>,
+}
+
+impl<'a> Row<'a> {
+    fn of(result: &'a Value, rank: usize) -> Self {
+        // Web documents go by their URL.
+        let path = result["url"]
+            .as_str()
+            .or(result["path"].as_str())
+            .unwrap_or_default()
+            .to_string();
+        // Documents without a title of their own go by their file name.
+        let title = result["title"].as_str().map_or_else(
+            || {
+                Path::new(&path)
+                    .file_name()
+                    .map_or_else(|| path.clone(), |name| name.to_string_lossy().into_owned())
+            },
+            str::to_string,
+        );
+        let pieces = result["snippet"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|piece| Some((piece[0].as_str()?, piece[1].as_bool()?)))
+            .collect();
+        Self {
+            rank,
+            title,
+            path,
+            score: result["score"]
+                .as_f64()
+                .map_or_else(String::new, |score| format!("{score:.4}")),
+            pieces,
+        }
+    }
+
+    fn snippet(&self, piece: impl Fn(&str, bool) -> String) -> String {
+        self.pieces
+            .iter()
+            .map(|(text, hit)| piece(text, *hit))
+            .collect::<String>()
+            .trim()
+            .to_string()
+    }
+}
+
+// Fields holding a separator, a quote or a line break are quoted, with their
+// quotes doubled (RFC 4180).
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// Table cells end at a line break or an unescaped `|`.
+fn markdown_cell(text: &str) -> String {
+    text.replace(['\r', '\n'], " ").replace('|', "\\|")
+}
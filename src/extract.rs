@@ -0,0 +1,717 @@
+use crate::plugins::{self, Plugins};
+use crate::{locale, Error, MetaValue, Metadata};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+#[cfg(feature = "extractor-xml")]
+use xml::reader::{ParserConfig2, XmlEvent};
+
+mod markup;
+mod media;
+mod ocr;
+pub mod sandbox;
+pub mod thumbnail;
+
+use sandbox::SandboxLimits;
+
+#[derive(Clone)]
+pub struct ExtractOptions {
+    // Index the outputs of notebook code cells (stream text, text/plain results).
+    pub notebook_outputs: bool,
+    // Fall back to OCR for PDFs and images without a usable text layer.
+    pub ocr: bool,
+    // Keep a thumbnail of the first page of PDFs and the first heading of
+    // HTML pages for the results of the web UI.
+    pub thumbnails: bool,
+    // Reuse extraction results of files whose content was already extracted.
+    pub cache: bool,
+    // Where extraction results are cached between runs.
+    pub cache_dir: PathBuf,
+    // Run the extractors in a child process under these limits, for
+    // documents that may be hostile.
+    pub sandbox: Option<SandboxLimits>,
+    // Extractor plugins, which go before the built-in extractors for the
+    // extensions they take.
+    pub plugins: Plugins,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        Self {
+            notebook_outputs: false,
+            ocr: false,
+            thumbnails: false,
+            cache: true,
+            cache_dir: PathBuf::from(".tinysearch-cache"),
+            sandbox: None,
+            plugins: Plugins::default(),
+        }
+    }
+}
+
+// A piece of a document that is indexed on its own. Chunks with an anchor are
+// stored as `<file>#<anchor>` so results can deep link into the document.
+#[derive(Serialize, Deserialize)]
+pub struct Chunk {
+    pub anchor: Option<String>,
+    pub text: String,
+    pub meta: Metadata,
+}
+
+impl Chunk {
+    fn whole(text: String) -> Self {
+        Self {
+            anchor: None,
+            text,
+            meta: Metadata::new(),
+        }
+    }
+}
+
+// 64-bit FNV-1a. Stable across Rust releases unlike `DefaultHasher`, so cache
+// entries stay valid between builds.
+pub fn content_hash(bytes: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &b in bytes {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{hash:016x}")
+}
+
+// Extraction results are cached by file content rather than path, so an
+// unchanged file, or a copy of it elsewhere in the corpus, is never parsed
+// twice. The extension and the options take part in the key because they
+// change what gets extracted from the same bytes, and so do what some
+// extractors take from the path and the version of the extractors, raised
+// whenever they extract something else.
+const EXTRACTOR_VERSION: u32 = 3;
+
+// Pages of PDFs are separated by a form feed, as pdftotext separates them,
+// so the page of a match can be told from the text.
+pub const PAGE_BREAK: &str = "\u{c}";
+
+fn extraction_cache_path(file_path: &Path, bytes: &[u8], options: &ExtractOptions) -> PathBuf {
+    let ext = file_path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let variant = u8::from(options.notebook_outputs)
+        | u8::from(options.ocr) << 1
+        | u8::from(options.thumbnails) << 2;
+    // What a plugin extracts changes with its module, and only with it.
+    let by_path = match options.plugins.extractor(file_path) {
+        Some((_, hash)) => format!("-{hash}"),
+        None => path_inputs(file_path, &ext)
+            .map(|inputs| format!("-p{}", content_hash(inputs.as_bytes())))
+            .unwrap_or_default(),
+    };
+    let name = format!(
+        "{hash}-{ext}-{variant}{by_path}-v{EXTRACTOR_VERSION}.json",
+        hash = content_hash(bytes)
+    );
+    options.cache_dir.join("extract").join(name)
+}
+
+// What the built-in extractors take from the path of a document besides its
+// bytes: the channel of a Slack export is the name of its folder, and the
+// canonical URL of a page is resolved against the page's own path or URL.
+// Pages fetched from URLs often have no extension, so a document of any
+// extension not known to be something else may be a page.
+fn path_inputs(file_path: &Path, ext: &str) -> Option<String> {
+    match ext {
+        "json" => file_path
+            .parent()
+            .and_then(|dir| dir.file_name())
+            .map(|channel| channel.to_string_lossy().into_owned()),
+        "ipynb" | "pdf" | "md" | "markdown" | "txt" | "text" | "xml" | "xhtml" => None,
+        _ if media::is_media_extension(ext) || ocr::is_ocr_extension(ext) => None,
+        _ => Some(file_path.to_string_lossy().into_owned()),
+    }
+}
+
+pub fn extract_chunks(file_path: &Path, options: &ExtractOptions) -> Result<Vec<Chunk>, Error> {
+    let bytes = fs::read(file_path).map_err(|err| {
+        Error::io(
+            format!(
+                "could not read file {file_path}",
+                file_path = file_path.display()
+            ),
+            err,
+        )
+    })?;
+    extract_document(file_path, &bytes, options)
+}
+
+// Extracts a document that does not have to be a file on disk. `name` picks
+// the extractor by its extension and shows up in error messages.
+pub fn extract_document(
+    name: &Path,
+    bytes: &[u8],
+    options: &ExtractOptions,
+) -> Result<Vec<Chunk>, Error> {
+    if !options.cache {
+        return extract_isolated(name, bytes, options);
+    }
+
+    let cache_path = extraction_cache_path(name, bytes, options);
+    if let Ok(cached) = fs::read(&cache_path) {
+        if let Ok(chunks) = serde_json::from_slice(&cached) {
+            return Ok(chunks);
+        }
+    }
+
+    let chunks = extract_isolated(name, bytes, options)?;
+    // A failing cache write only costs another extraction next time.
+    let written = fs::create_dir_all(options.cache_dir.join("extract")).and_then(|()| {
+        let file = File::create(&cache_path)?;
+        serde_json::to_writer(file, &chunks).map_err(std::io::Error::from)
+    });
+    if let Err(err) = written {
+        eprintln!(
+            "WARNING: could not cache extracted text in {cache_path}: {err}",
+            cache_path = cache_path.display()
+        );
+    }
+    Ok(chunks)
+}
+
+// Extracts in the sandbox when asked to. Only the parsing moves into the
+// child, reading and writing the cache stays here. A plugin runs in its
+// runtime inside the sandbox too, under the same limits.
+fn extract_isolated(
+    name: &Path,
+    bytes: &[u8],
+    options: &ExtractOptions,
+) -> Result<Vec<Chunk>, Error> {
+    match options.sandbox {
+        Some(limits) => sandbox::extract(name, bytes, options, limits),
+        None => extract_chunks_uncached(name, bytes, options),
+    }
+}
+
+// The targets of the links of an HTML page, as written, for a crawler to
+// follow.
+pub fn html_links(html: &str) -> Vec<String> {
+    markup::html_links(html)
+}
+
+// The headings of an HTML or Markdown file, with the title of a page first
+// if it has one. Other formats have none.
+pub fn extract_headings(file_path: &Path) -> Vec<String> {
+    let ext = file_path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+    let read = || fs::read(file_path).map(|bytes| String::from_utf8_lossy(&bytes).into_owned());
+    match ext.as_deref() {
+        #[cfg(feature = "extractor-html")]
+        Some("html" | "htm") => {
+            read().map_or_else(|_| Vec::new(), |html| markup::html_headings(&html))
+        }
+        Some("md" | "markdown") => read().map_or_else(
+            |_| Vec::new(),
+            |markdown| markup::markdown_headings(&markdown),
+        ),
+        _ => Vec::new(),
+    }
+}
+
+fn extract_chunks_uncached(
+    file_path: &Path,
+    bytes: &[u8],
+    options: &ExtractOptions,
+) -> Result<Vec<Chunk>, Error> {
+    if let Some((module, _)) = options.plugins.extractor(file_path) {
+        return Ok(vec![Chunk::whole(plugins::extract(
+            module, file_path, bytes,
+        )?)]);
+    }
+    let ext = file_path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+    let mut chunks = match ext.as_deref() {
+        Some("ipynb") => parse_notebook_file(file_path, bytes, options)?,
+        // Other JSON is taken like a document of an unknown extension.
+        Some("json") => match parse_chat_export_file(file_path, bytes) {
+            Some(chunks) => chunks,
+            None => parse_other_file(file_path, bytes)?,
+        },
+        Some(ext) if media::is_media_extension(ext) => parse_media_file(bytes, ext),
+        // A scanned PDF may have no text layer or a broken one, OCR below
+        // reads it instead.
+        Some("pdf") => match parse_pdf_file(file_path, bytes) {
+            Ok(text) => vec![Chunk::whole(text)],
+            Err(_) if options.ocr => Vec::new(),
+            Err(err) => return Err(err),
+        },
+        // Without the HTML extractor pages fall through to plain text.
+        #[cfg(feature = "extractor-html")]
+        Some("html" | "htm") => vec![html_chunk(file_path, &String::from_utf8_lossy(bytes))],
+        Some("md" | "markdown") => vec![markdown_chunk(&String::from_utf8_lossy(bytes))],
+        Some("txt" | "text") => vec![Chunk::whole(String::from_utf8_lossy(bytes).into_owned())],
+        Some("xml" | "xhtml") => vec![Chunk::whole(parse_entire_xml_file(file_path, bytes)?)],
+        // Pages fetched from URLs often have no extension.
+        #[cfg(feature = "extractor-html")]
+        _ if markup::looks_like_html(&String::from_utf8_lossy(&bytes[..bytes.len().min(512)])) => {
+            vec![html_chunk(file_path, &String::from_utf8_lossy(bytes))]
+        }
+        _ => parse_other_file(file_path, bytes)?,
+    };
+
+    if let Some(ext) = ext.as_deref() {
+        let text_chars = chunks
+            .iter()
+            .flat_map(|chunk| chunk.text.chars())
+            .filter(|c| c.is_alphanumeric())
+            .count();
+        if options.ocr && ocr::is_ocr_extension(ext) && text_chars < ocr::MIN_TEXT_CHARS {
+            let text = ocr::ocr_document(file_path, bytes, ext, &options.cache_dir)?;
+            match chunks.first_mut() {
+                Some(chunk) => {
+                    chunk.text.push(' ');
+                    chunk.text.push_str(&text);
+                    chunk.meta.insert("ocr".to_string(), "true".into());
+                }
+                None => {
+                    let mut chunk = Chunk::whole(text);
+                    chunk.meta.insert("ocr".to_string(), "true".into());
+                    chunks.push(chunk);
+                }
+            }
+        }
+        if options.thumbnails {
+            if let Some(chunk) = chunks.first_mut() {
+                thumbnail::add_preview(file_path, bytes, ext, &mut chunk.meta);
+            }
+        }
+    }
+    Ok(chunks)
+}
+
+// The text of a page, with its title, else its first heading, as `title`
+// and its canonical URL as `url`. A relative one is resolved against the URL
+// the page was fetched from, and left out for pages on disk.
+#[cfg(feature = "extractor-html")]
+fn html_chunk(file_path: &Path, html: &str) -> Chunk {
+    let mut chunk = Chunk::whole(markup::html_text(html));
+    if let Some(title) = markup::html_headings(html).into_iter().next() {
+        chunk.meta.insert("title".to_string(), title.into());
+    }
+    let canonical = markup::html_canonical(html)
+        .and_then(|href| crate::source::resolve_url(file_path.to_str(), &href));
+    if let Some(url) = canonical {
+        chunk.meta.insert("url".to_string(), url.into());
+    }
+    chunk
+}
+
+// The text of a Markdown file, with its first heading as `title`.
+fn markdown_chunk(markdown: &str) -> Chunk {
+    let mut chunk = Chunk::whole(markup::markdown_text(markdown));
+    if let Some(title) = markup::markdown_headings(markdown).into_iter().next() {
+        chunk.meta.insert("title".to_string(), title.into());
+    }
+    chunk
+}
+
+// Anything else is tried as XML, then taken as plain text if it looks like
+// text at all.
+fn parse_other_file(file_path: &Path, bytes: &[u8]) -> Result<Vec<Chunk>, Error> {
+    match parse_entire_xml_file(file_path, bytes) {
+        Ok(text) => Ok(vec![Chunk::whole(text)]),
+        Err(err) => Ok(vec![Chunk::whole(plain_text(bytes).ok_or(err)?)]),
+    }
+}
+
+// The bytes as text, unless they are binary: not UTF-8, or with NUL bytes,
+// which text files do not have.
+fn plain_text(bytes: &[u8]) -> Option<String> {
+    let text = std::str::from_utf8(bytes).ok()?;
+    (!text.contains('\0')).then(|| text.to_string())
+}
+
+// The text layer of a PDF, its pages separated by `PAGE_BREAK`. The parser
+// panics on some malformed files, which then fail like any other unreadable
+// document.
+#[cfg(feature = "extractor-pdf")]
+fn parse_pdf_file(file_path: &Path, bytes: &[u8]) -> Result<String, Error> {
+    let extracted = std::panic::catch_unwind(|| pdf_extract::extract_text_from_mem_by_pages(bytes));
+    match extracted {
+        Ok(Ok(pages)) => Ok(pages.join(PAGE_BREAK)),
+        Ok(Err(err)) => Err(Error::invalid(format!(
+            "{file_path}: could not read PDF: {err}",
+            file_path = file_path.display()
+        ))),
+        Err(_) => Err(Error::invalid(format!(
+            "{file_path}: could not read PDF, it is malformed",
+            file_path = file_path.display()
+        ))),
+    }
+}
+
+#[cfg(not(feature = "extractor-pdf"))]
+fn parse_pdf_file(file_path: &Path, _bytes: &[u8]) -> Result<String, Error> {
+    Err(Error::invalid(format!(
+        "{file_path}: PDF text extraction is not built in, rebuild with the extractor-pdf feature",
+        file_path = file_path.display()
+    )))
+}
+
+// Entities may grow the text of a document to this many times its size, plus
+// the slack below for small documents. Entities that name products or
+// symbols stay far under it; billion laughs style documents, which expand a
+// few kilobytes into gigabytes, are rejected as soon as they pass it.
+#[cfg(feature = "extractor-xml")]
+const MAX_ENTITY_EXPANSION_RATIO: usize = 8;
+#[cfg(feature = "extractor-xml")]
+const ENTITY_EXPANSION_SLACK: usize = 64 * 1024;
+// How deep entities may refer to other entities.
+#[cfg(feature = "extractor-xml")]
+const MAX_ENTITY_DEPTH: u8 = 4;
+
+// Documents can come from anywhere, so entities are treated as hostile.
+// External (SYSTEM and PUBLIC) entities are never resolved: xml-rs neither
+// reads files nor fetches URLs for them and expands them to nothing.
+#[cfg(feature = "extractor-xml")]
+pub fn parse_entire_xml_file(file_path: &Path, bytes: &[u8]) -> Result<String, Error> {
+    let budget = bytes
+        .len()
+        .saturating_mul(MAX_ENTITY_EXPANSION_RATIO)
+        .saturating_add(ENTITY_EXPANSION_SLACK);
+    // The parser checks single expansions and text runs, the loop below the
+    // text of the whole document.
+    let er = ParserConfig2::new()
+        .max_entity_expansion_depth(MAX_ENTITY_DEPTH)
+        .max_entity_expansion_length(budget)
+        .max_data_length(budget)
+        .max_attribute_length(budget)
+        .create_reader(bytes);
+    let mut content = String::new();
+    for event in er.into_iter() {
+        let event = event.map_err(|err| Error::Xml(file_path.display().to_string(), err))?;
+        if let XmlEvent::Characters(text) = event {
+            content.push_str(&text);
+            content.push(' ');
+            if content.len() > budget {
+                return Err(Error::invalid(format!(
+                    "{file_path}: entities expand the text past {MAX_ENTITY_EXPANSION_RATIO} times the size of the document",
+                    file_path = file_path.display()
+                )));
+            }
+        }
+    }
+    Ok(content)
+}
+
+// Without the XML extractor, XML documents fail and unknown extensions are
+// only taken as plain text.
+#[cfg(not(feature = "extractor-xml"))]
+pub fn parse_entire_xml_file(file_path: &Path, _bytes: &[u8]) -> Result<String, Error> {
+    Err(Error::invalid(format!(
+        "{file_path}: XML is not supported, rebuild with the extractor-xml feature",
+        file_path = file_path.display()
+    )))
+}
+
+// Media files have no text of their own, so the embedded metadata values
+// (title, artist, caption, camera, ...) become the searchable text.
+fn parse_media_file(bytes: &[u8], ext: &str) -> Vec<Chunk> {
+    let meta = media::read_media_metadata(ext, bytes);
+    if meta.is_empty() {
+        return Vec::new();
+    }
+    let text = meta
+        .values()
+        .flat_map(MetaValue::values)
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(" ");
+    vec![Chunk {
+        anchor: None,
+        text,
+        meta,
+    }]
+}
+
+// Cell sources and outputs are stored either as a single string or as a list
+// of lines, depending on the tool that wrote the notebook.
+fn notebook_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Array(lines) => lines.iter().filter_map(|line| line.as_str()).collect(),
+        _ => String::new(),
+    }
+}
+
+// Splits `snake_case`, `camelCase` and `HTTPServer` style identifiers into
+// their words so that searching for `index` finds `tf_index_of_folder`.
+pub fn split_identifier(ident: &str) -> Vec<String> {
+    let chars = ident.chars().collect::<Vec<_>>();
+    let mut words = Vec::new();
+    let mut word = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if !c.is_alphanumeric() {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            continue;
+        }
+        let boundary = match word.chars().last() {
+            Some(prev) if c.is_uppercase() => {
+                prev.is_lowercase()
+                    || prev.is_numeric()
+                    || (prev.is_uppercase() && chars.get(i + 1).is_some_and(|n| n.is_lowercase()))
+            }
+            _ => false,
+        };
+        if boundary {
+            words.push(std::mem::take(&mut word));
+        }
+        word.push(c);
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+// Appends the split words of every compound identifier in the code, so both
+// the identifier itself and its parts end up in the index.
+fn with_identifier_words(code: &str) -> String {
+    let mut text = code.to_string();
+    let idents = code.split(|c: char| !(c.is_alphanumeric() || c == '_'));
+    for ident in idents {
+        let words = split_identifier(ident);
+        if words.len() > 1 {
+            text.push(' ');
+            text.push_str(&words.join(" "));
+        }
+    }
+    text
+}
+
+fn notebook_outputs_text(outputs: &Value) -> String {
+    let mut text = String::new();
+    for output in outputs.as_array().into_iter().flatten() {
+        if let Some(stream) = output.get("text") {
+            text.push_str(&notebook_text(stream));
+        }
+        if let Some(plain) = output.get("data").and_then(|data| data.get("text/plain")) {
+            text.push_str(&notebook_text(plain));
+        }
+        text.push(' ');
+    }
+    text
+}
+
+fn parse_notebook_file(
+    file_path: &Path,
+    bytes: &[u8],
+    options: &ExtractOptions,
+) -> Result<Vec<Chunk>, Error> {
+    let notebook: Value = serde_json::from_slice(bytes).map_err(|err| {
+        Error::json(
+            format!(
+                "could not parse notebook {file_path}",
+                file_path = file_path.display()
+            ),
+            err,
+        )
+    })?;
+
+    // nbformat 4 keeps the cells at the top level, nbformat 3 inside the first worksheet.
+    let cells = notebook
+        .get("cells")
+        .or_else(|| notebook.pointer("/worksheets/0/cells"))
+        .and_then(|cells| cells.as_array())
+        .ok_or_else(|| {
+            Error::invalid(format!(
+                "{file_path} does not look like a Jupyter notebook: no cells found",
+                file_path = file_path.display()
+            ))
+        })?;
+
+    let mut chunks = Vec::new();
+    for (index, cell) in cells.iter().enumerate() {
+        let source = cell
+            .get("source")
+            .or_else(|| cell.get("input"))
+            .map(notebook_text)
+            .unwrap_or_default();
+        let mut text = match cell.get("cell_type").and_then(|t| t.as_str()) {
+            Some("code") => with_identifier_words(&source),
+            _ => source,
+        };
+        if options.notebook_outputs {
+            if let Some(outputs) = cell.get("outputs") {
+                text.push(' ');
+                text.push_str(&notebook_outputs_text(outputs));
+            }
+        }
+        if text.trim().is_empty() {
+            continue;
+        }
+        let mut meta = Metadata::new();
+        if let Some(cell_type) = cell.get("cell_type").and_then(|t| t.as_str()) {
+            meta.insert("cell_type".to_string(), cell_type.into());
+        }
+        chunks.push(Chunk {
+            anchor: Some(format!("cell-{index}")),
+            text,
+            meta,
+        });
+    }
+    Ok(chunks)
+}
+
+fn chat_chunk(
+    anchor: String,
+    text: &str,
+    author: Option<&str>,
+    channel: Option<&str>,
+    timestamp: Option<String>,
+) -> Chunk {
+    let mut meta = Metadata::new();
+    if let Some(author) = author {
+        meta.insert("author".to_string(), author.into());
+    }
+    if let Some(channel) = channel {
+        meta.insert("channel".to_string(), channel.into());
+    }
+    if let Some(timestamp) = timestamp {
+        meta.insert("timestamp".to_string(), timestamp.into());
+    }
+    Chunk {
+        anchor: Some(anchor),
+        text: text.to_string(),
+        meta,
+    }
+}
+
+// Slack exports are laid out as `<channel>/<day>.json`, each file holding an
+// array of messages keyed by their `ts`.
+fn slack_chunks(file_path: &Path, messages: &[Value]) -> Vec<Chunk> {
+    let channel = file_path
+        .parent()
+        .and_then(|dir| dir.file_name())
+        .and_then(|name| name.to_str());
+    let mut chunks = Vec::new();
+    for message in messages {
+        let (Some(ts), Some(text)) = (
+            message.get("ts").and_then(|ts| ts.as_str()),
+            message.get("text").and_then(|text| text.as_str()),
+        ) else {
+            continue;
+        };
+        let author = message
+            .pointer("/user_profile/real_name")
+            .or_else(|| message.get("user_name"))
+            .or_else(|| message.get("user"))
+            .and_then(|author| author.as_str());
+        let timestamp = ts
+            .split('.')
+            .next()
+            .and_then(|secs| secs.parse::<i64>().ok())
+            .map(locale::rfc3339_from_unix);
+        chunks.push(chat_chunk(
+            format!("msg-{ts}"),
+            text,
+            author,
+            channel,
+            timestamp,
+        ));
+    }
+    chunks
+}
+
+// DiscordChatExporter writes one object per channel with the messages inline.
+fn discord_chunks(export: &Value, messages: &[Value]) -> Vec<Chunk> {
+    let channel = export
+        .pointer("/channel/name")
+        .and_then(|name| name.as_str());
+    let mut chunks = Vec::new();
+    for message in messages {
+        let (Some(id), Some(text)) = (
+            message.get("id").and_then(|id| id.as_str()),
+            message.get("content").and_then(|text| text.as_str()),
+        ) else {
+            continue;
+        };
+        let author = message
+            .pointer("/author/nickname")
+            .or_else(|| message.pointer("/author/name"))
+            .and_then(|author| author.as_str());
+        let timestamp = message
+            .get("timestamp")
+            .and_then(|ts| ts.as_str())
+            .map(|ts| ts.to_string());
+        chunks.push(chat_chunk(
+            format!("msg-{id}"),
+            text,
+            author,
+            channel,
+            timestamp,
+        ));
+    }
+    chunks
+}
+
+// The messages of a Slack or a Discord export, told apart from other JSON by
+// their shape: Slack messages are objects with a `ts` and a `text`, in an
+// array, Discord ones objects with an `id` and a `content`, under
+// `messages`. None for anything else.
+fn parse_chat_export_file(file_path: &Path, bytes: &[u8]) -> Option<Vec<Chunk>> {
+    let export: Value = serde_json::from_slice(bytes).ok()?;
+    let has_fields = |message: &Value, fields: [&str; 2]| {
+        fields.iter().all(|field| message.get(field).is_some())
+    };
+
+    if let Some(messages) = export.as_array() {
+        let is_slack = messages
+            .iter()
+            .any(|message| has_fields(message, ["ts", "text"]));
+        return is_slack.then(|| slack_chunks(file_path, messages));
+    }
+    let messages = export.get("messages")?.as_array()?;
+    let is_discord = messages
+        .iter()
+        .any(|message| has_fields(message, ["id", "content"]));
+    is_discord.then(|| discord_chunks(&export, messages))
+}
+
+#[cfg(all(test, feature = "extractor-xml"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xml_entity_expansion_is_bounded() {
+        let mut doctype = String::from("<!ENTITY lol0 \"lol\">");
+        for level in 1..10 {
+            let previous = format!("&lol{};", level - 1);
+            doctype.push_str(&format!("<!ENTITY lol{level} \"{}\">", previous.repeat(10)));
+        }
+        let document =
+            format!("<?xml version=\"1.0\"?><!DOCTYPE lolz [{doctype}]><lolz>&lol9;</lolz>");
+        let parsed = parse_entire_xml_file(Path::new("lolz.xml"), document.as_bytes());
+        assert!(
+            parsed.is_err(),
+            "a billion laughs expanded to {} bytes",
+            parsed.unwrap().len()
+        );
+    }
+
+    #[test]
+    fn xml_external_entities_are_not_read() {
+        let document = "<?xml version=\"1.0\"?><!DOCTYPE foo [<!ENTITY xxe SYSTEM \"file:///etc/passwd\">]><foo>before &xxe; after</foo>";
+        // Failing on the entity is as good as expanding it to nothing.
+        if let Ok(text) = parse_entire_xml_file(Path::new("xxe.xml"), document.as_bytes()) {
+            assert!(!text.contains("root:"), "the entity was read: {text}");
+            assert!(!text.contains("/etc/passwd"), "the entity was kept: {text}");
+            assert!(text.contains("before") && text.contains("after"));
+        }
+    }
+}
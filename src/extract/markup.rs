@@ -0,0 +1,441 @@
+// Text of HTML and Markdown documents. Real-world HTML is rarely well-formed
+// XML, so it is not parsed but stripped: tags, comments and the contents of
+// scripts and styles go, entities are decoded. Markdown loses its markup
+// characters and keeps what a reader sees, link texts and image captions
+// included.
+
+// Elements whose contents are not text of the page.
+const SKIPPED_ELEMENTS: &[&str] = &["script", "style", "template", "noscript"];
+
+pub fn html_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len() / 2);
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        push_decoded(&mut text, &rest[..start]);
+        rest = &rest[start..];
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+            let end = cdata.find("]]>").unwrap_or(cdata.len());
+            text.push_str(&cdata[..end]);
+            rest = cdata.get(end + 3..).unwrap_or("");
+            continue;
+        }
+        let Some(end) = tag_end(rest) else {
+            // A lone `<`, as in `a < b`, is text.
+            text.push('<');
+            rest = &rest[1..];
+            continue;
+        };
+        let name = tag_name(&rest[1..end]);
+        rest = &rest[end + 1..];
+        // Tags separate words, `<p>one</p><p>two</p>` is not "onetwo".
+        text.push(' ');
+        if SKIPPED_ELEMENTS.contains(&name.as_str()) {
+            rest = skip_element(rest, &name);
+        }
+    }
+    push_decoded(&mut text, rest);
+    text
+}
+
+// Elements whose text names what follows, in the order of their rank.
+#[cfg(feature = "extractor-html")]
+const HEADING_ELEMENTS: &[&str] = &["title", "h1", "h2", "h3", "h4", "h5", "h6"];
+
+// The texts of the title and the h1 to h6 headings of a page, in the order
+// they appear.
+#[cfg(feature = "extractor-html")]
+pub fn html_headings(html: &str) -> Vec<String> {
+    let mut headings = Vec::new();
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start..];
+        let Some(end) = tag_end(rest) else {
+            rest = &rest[1..];
+            continue;
+        };
+        let name = tag_name(&rest[1..end]);
+        rest = &rest[end + 1..];
+        if HEADING_ELEMENTS.contains(&name.as_str()) {
+            let after = skip_element(rest, &name);
+            let inner = &rest[..rest.len() - after.len()];
+            // Up to the `</` of the end tag.
+            let inner = inner.rfind("</").map_or(inner, |close| &inner[..close]);
+            let text = html_text(inner)
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ");
+            if !text.is_empty() {
+                headings.push(text);
+            }
+            rest = after;
+        }
+    }
+    headings
+}
+
+// The `href` of the `<link rel="canonical">` of a page: the URL the page is
+// known under, whatever URL it was fetched from or file it was saved to.
+#[cfg(feature = "extractor-html")]
+pub fn html_canonical(html: &str) -> Option<String> {
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start..];
+        let Some(end) = tag_end(rest) else {
+            rest = &rest[1..];
+            continue;
+        };
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+        match tag_name(tag).as_str() {
+            "link" => {
+                let canonical = attribute(tag, "rel").is_some_and(|rel| {
+                    rel.split_ascii_whitespace()
+                        .any(|rel| rel.eq_ignore_ascii_case("canonical"))
+                });
+                if let Some(href) = attribute(tag, "href").filter(|_| canonical) {
+                    let href = href.trim();
+                    return (!href.is_empty()).then(|| href.to_string());
+                }
+            }
+            // Links in the body are not the page's.
+            "body" => return None,
+            name if SKIPPED_ELEMENTS.contains(&name) => rest = skip_element(rest, name),
+            _ => {}
+        }
+    }
+    None
+}
+
+// The targets of the links of a page, as written, in the order they appear.
+// Links marked `rel="nofollow"` are left out.
+pub fn html_links(html: &str) -> Vec<String> {
+    let mut links = Vec::new();
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start..];
+        let Some(end) = tag_end(rest) else {
+            rest = &rest[1..];
+            continue;
+        };
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+        match tag_name(tag).as_str() {
+            "a" | "area" => {
+                let nofollow = attribute(tag, "rel").is_some_and(|rel| {
+                    rel.split_ascii_whitespace()
+                        .any(|rel| rel.eq_ignore_ascii_case("nofollow"))
+                });
+                if let Some(href) = attribute(tag, "href").filter(|_| !nofollow) {
+                    links.push(href.trim().to_string());
+                }
+            }
+            name if SKIPPED_ELEMENTS.contains(&name) => rest = skip_element(rest, name),
+            _ => {}
+        }
+    }
+    links
+}
+
+// Whether text of a document without a telling extension, like a page
+// fetched from a URL ending in `/`, is HTML.
+#[cfg(feature = "extractor-html")]
+pub fn looks_like_html(text: &str) -> bool {
+    let start = text.trim_start_matches('\u{feff}').trim_start();
+    let head = start.get(..14).unwrap_or(start).to_ascii_lowercase();
+    head.starts_with("<!doctype html") || head.starts_with("<html")
+}
+
+// The decoded value of the attribute `name` of a tag, without its `<` and
+// `>`. Unquoted values run to the next whitespace.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let mut rest = tag.trim_start_matches(|c: char| !c.is_whitespace());
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+        if rest.is_empty() {
+            return None;
+        }
+        let name_end = rest
+            .find(|c: char| c.is_whitespace() || c == '=' || c == '/')
+            .unwrap_or(rest.len());
+        let found = rest[..name_end].eq_ignore_ascii_case(name);
+        rest = rest[name_end..].trim_start();
+        let mut raw = "";
+        if let Some(value) = rest.strip_prefix('=') {
+            let value = value.trim_start();
+            let (inner, after) = match value.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let value = &value[1..];
+                    let end = value.find(quote).unwrap_or(value.len());
+                    (&value[..end], value.get(end + 1..).unwrap_or(""))
+                }
+                _ => {
+                    let end = value.find(char::is_whitespace).unwrap_or(value.len());
+                    (&value[..end], &value[end..])
+                }
+            };
+            raw = inner;
+            rest = after;
+        }
+        if found {
+            let mut value = String::new();
+            push_decoded(&mut value, raw);
+            return Some(value);
+        }
+    }
+}
+
+// Index of the `>` closing the tag at the start of `html`, ignoring any in
+// quoted attribute values. None if `<` does not start a tag.
+fn tag_end(html: &str) -> Option<usize> {
+    let first = html[1..].chars().next()?;
+    if !(first.is_ascii_alphabetic() || first == '/' || first == '!' || first == '?') {
+        return None;
+    }
+    let mut quote = None;
+    for (i, c) in html.char_indices().skip(1) {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), _) if c == open => quote = None,
+            (None, '>') => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+// Lowercased name of an opening tag, empty for closing tags, doctypes and
+// processing instructions.
+fn tag_name(tag: &str) -> String {
+    tag.chars()
+        .take_while(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_ascii_lowercase()
+}
+
+// What follows the end tag of the element `name`, or nothing if it is never
+// closed.
+fn skip_element<'a>(html: &'a str, name: &str) -> &'a str {
+    let mut rest = html;
+    while let Some(start) = rest.find("</") {
+        let after = &rest[start + 2..];
+        let closes = after
+            .get(..name.len())
+            .is_some_and(|tag| tag.eq_ignore_ascii_case(name));
+        if closes {
+            return after.find('>').map_or("", |end| &after[end + 1..]);
+        }
+        rest = after;
+    }
+    ""
+}
+
+fn push_decoded(text: &mut String, raw: &str) {
+    let mut rest = raw;
+    while let Some(start) = rest.find('&') {
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest
+            .find(';')
+            .filter(|&end| end <= 10)
+            .and_then(|end| Some((decode_entity(&rest[1..end])?, end)));
+        match decoded {
+            Some((c, end)) => {
+                text.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                text.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    text.push_str(rest);
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    if let Some(number) = entity.strip_prefix('#') {
+        let code = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => number.parse().ok()?,
+        };
+        return char::from_u32(code);
+    }
+    Some(match entity {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => ' ',
+        "ndash" => '–',
+        "mdash" => '—',
+        "hellip" => '…',
+        "copy" => '©',
+        "reg" => '®',
+        "laquo" => '«',
+        "raquo" => '»',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "euro" => '€',
+        "szlig" => 'ß',
+        _ => return accented_letter(entity),
+    })
+}
+
+// `&eacute;`, `&Uuml;` and the other letters of Western European languages.
+fn accented_letter(entity: &str) -> Option<char> {
+    let mut chars = entity.chars();
+    let letter = chars.next()?;
+    let lower = match (letter.to_ascii_lowercase(), chars.as_str()) {
+        ('a', "acute") => 'á',
+        ('a', "grave") => 'à',
+        ('a', "circ") => 'â',
+        ('a', "uml") => 'ä',
+        ('a', "tilde") => 'ã',
+        ('a', "ring") => 'å',
+        ('e', "acute") => 'é',
+        ('e', "grave") => 'è',
+        ('e', "circ") => 'ê',
+        ('e', "uml") => 'ë',
+        ('i', "acute") => 'í',
+        ('i', "grave") => 'ì',
+        ('i', "circ") => 'î',
+        ('i', "uml") => 'ï',
+        ('o', "acute") => 'ó',
+        ('o', "grave") => 'ò',
+        ('o', "circ") => 'ô',
+        ('o', "uml") => 'ö',
+        ('o', "tilde") => 'õ',
+        ('o', "slash") => 'ø',
+        ('u', "acute") => 'ú',
+        ('u', "grave") => 'ù',
+        ('u', "circ") => 'û',
+        ('u', "uml") => 'ü',
+        ('n', "tilde") => 'ñ',
+        ('c', "cedil") => 'ç',
+        ('y', "acute") => 'ý',
+        _ => return None,
+    };
+    if letter.is_ascii_uppercase() {
+        lower.to_uppercase().next()
+    } else {
+        Some(lower)
+    }
+}
+
+pub fn markdown_text(markdown: &str) -> String {
+    let mut text = String::with_capacity(markdown.len());
+    let mut in_code_block = false;
+    for line in markdown.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            // The info string, e.g. `rust`, is not text of the document.
+            in_code_block = !in_code_block;
+            text.push('\n');
+            continue;
+        }
+        if in_code_block {
+            text.push_str(line);
+            text.push('\n');
+            continue;
+        }
+        let line = trimmed
+            .trim_start_matches('>')
+            .trim_start()
+            .trim_start_matches('#')
+            .trim_start();
+        let line = strip_list_marker(line);
+        // Setext underlines and rules.
+        if !line.is_empty()
+            && line
+                .chars()
+                .all(|c| matches!(c, '=' | '-' | '*' | '_' | ' '))
+        {
+            text.push('\n');
+            continue;
+        }
+        push_inline(&mut text, line);
+        text.push('\n');
+    }
+    text
+}
+
+// The texts of the `#` headings of a Markdown document, in order.
+pub fn markdown_headings(markdown: &str) -> Vec<String> {
+    let mut headings = Vec::new();
+    let mut in_code_block = false;
+    for line in markdown.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_code_block = !in_code_block;
+            continue;
+        }
+        let Some(heading) = trimmed.strip_prefix('#').filter(|_| !in_code_block) else {
+            continue;
+        };
+        let heading = heading.trim_start_matches('#');
+        // `#tag` is not a heading.
+        if !heading.starts_with([' ', '\t']) {
+            continue;
+        }
+        let mut text = String::new();
+        push_inline(&mut text, heading.trim().trim_end_matches('#').trim_end());
+        let text = text.trim();
+        if !text.is_empty() {
+            headings.push(text.to_string());
+        }
+    }
+    headings
+}
+
+fn strip_list_marker(line: &str) -> &str {
+    for marker in ["- ", "* ", "+ "] {
+        if let Some(rest) = line.strip_prefix(marker) {
+            return rest;
+        }
+    }
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    if digits > 0 {
+        if let Some(rest) = line[digits..]
+            .strip_prefix(". ")
+            .or_else(|| line[digits..].strip_prefix(") "))
+        {
+            return rest;
+        }
+    }
+    line
+}
+
+// Inline markup: `[text](url)` and `![alt](url)` keep their text, emphasis
+// and code markers go, inline HTML is stripped like a page.
+fn push_inline(text: &mut String, line: &str) {
+    let mut plain = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(start) = rest.find('[') {
+        let link = &rest[start + 1..];
+        let target = link
+            .find("](")
+            .and_then(|close| Some((close, link[close + 2..].find(')')? + close + 2)));
+        match target {
+            Some((close, end)) => {
+                let before = &rest[..start];
+                plain.push_str(before.strip_suffix('!').unwrap_or(before));
+                plain.push_str(&link[..close]);
+                rest = &link[end + 1..];
+            }
+            None => {
+                plain.push_str(&rest[..=start]);
+                rest = link;
+            }
+        }
+    }
+    plain.push_str(rest);
+    let plain = plain.replace(['*', '`'], "").replace("__", "");
+    text.push_str(&html_text(&plain));
+}
@@ -0,0 +1,312 @@
+// Embedded metadata of media files: EXIF for JPEG/TIFF images, text chunks
+// for PNG and ID3 tags for MP3. Only the fields people search by are kept.
+use crate::Metadata;
+
+fn read_u16(bytes: &[u8], at: usize, le: bool) -> Option<u16> {
+    let b = bytes.get(at..at + 2)?;
+    Some(if le {
+        u16::from_le_bytes([b[0], b[1]])
+    } else {
+        u16::from_be_bytes([b[0], b[1]])
+    })
+}
+
+fn read_u32(bytes: &[u8], at: usize, le: bool) -> Option<u32> {
+    let b = bytes.get(at..at + 4)?;
+    Some(if le {
+        u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+    } else {
+        u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+    })
+}
+
+fn clean_text(bytes: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(bytes);
+    let text = text.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+    if text.is_empty() {
+        None
+    } else {
+        Some(text.to_string())
+    }
+}
+
+fn utf16_text(bytes: &[u8], le: bool) -> Option<String> {
+    let units = bytes
+        .chunks_exact(2)
+        .map(|b| {
+            if le {
+                u16::from_le_bytes([b[0], b[1]])
+            } else {
+                u16::from_be_bytes([b[0], b[1]])
+            }
+        })
+        .collect::<Vec<_>>();
+    clean_text(String::from_utf16_lossy(&units).as_bytes())
+}
+
+fn exif_key(tag: u16) -> Option<&'static str> {
+    match tag {
+        0x010E => Some("caption"),
+        0x010F => Some("camera_make"),
+        0x0110 => Some("camera_model"),
+        0x0132 => Some("modified_at"),
+        0x013B => Some("artist"),
+        0x8298 => Some("copyright"),
+        0x9003 => Some("taken_at"),
+        0x9286 => Some("comment"),
+        0x9C9B => Some("title"),
+        0x9C9C => Some("comment"),
+        0x9C9D => Some("artist"),
+        0x9C9E => Some("keywords"),
+        0x9C9F => Some("subject"),
+        _ => None,
+    }
+}
+
+// EXIF stores "2023:01:02 03:04:05"; turn it into RFC 3339 so it compares
+// with the other timestamps in the index.
+fn exif_datetime(value: &str) -> String {
+    match value.split_once(' ') {
+        Some((date, time)) if date.len() == 10 => format!("{}T{time}", date.replace(':', "-")),
+        _ => value.to_string(),
+    }
+}
+
+fn read_ifd(tiff: &[u8], offset: usize, le: bool, meta: &mut Metadata, depth: usize) {
+    let Some(count) = read_u16(tiff, offset, le) else {
+        return;
+    };
+    for i in 0..count as usize {
+        let entry = offset + 2 + i * 12;
+        let (Some(tag), Some(kind), Some(len)) = (
+            read_u16(tiff, entry, le),
+            read_u16(tiff, entry + 2, le),
+            read_u32(tiff, entry + 4, le),
+        ) else {
+            return;
+        };
+        // The Exif sub-IFD holds the capture date and the user comment.
+        if tag == 0x8769 && depth == 0 {
+            if let Some(sub) = read_u32(tiff, entry + 8, le) {
+                read_ifd(tiff, sub as usize, le, meta, depth + 1);
+            }
+            continue;
+        }
+        let Some(key) = exif_key(tag) else {
+            continue;
+        };
+        let len = len as usize;
+        let data = if len <= 4 {
+            tiff.get(entry + 8..entry + 8 + len)
+        } else {
+            read_u32(tiff, entry + 8, le)
+                .and_then(|at| tiff.get(at as usize..(at as usize).checked_add(len)?))
+        };
+        let Some(data) = data else {
+            continue;
+        };
+        let value = match (tag, kind) {
+            // Windows XP tags are UTF-16LE regardless of the TIFF byte order.
+            (0x9C9B..=0x9C9F, _) => utf16_text(data, true),
+            // UserComment starts with an 8 byte character code.
+            (0x9286, _) if data.len() > 8 => match &data[..8] {
+                b"UNICODE\0" => utf16_text(&data[8..], le),
+                _ => clean_text(&data[8..]),
+            },
+            (_, 2) => clean_text(data),
+            _ => None,
+        };
+        if let Some(value) = value {
+            let value = match key {
+                "taken_at" | "modified_at" => exif_datetime(&value),
+                _ => value,
+            };
+            meta.entry(key.to_string()).or_insert(value.into());
+        }
+    }
+}
+
+fn read_tiff(tiff: &[u8], meta: &mut Metadata) {
+    let le = match tiff.get(0..2) {
+        Some(b"II") => true,
+        Some(b"MM") => false,
+        _ => return,
+    };
+    if let Some(offset) = read_u32(tiff, 4, le) {
+        read_ifd(tiff, offset as usize, le, meta, 0);
+    }
+}
+
+fn read_jpeg(bytes: &[u8], meta: &mut Metadata) {
+    let mut at = 2;
+    while let (Some(&0xFF), Some(&marker)) = (bytes.get(at), bytes.get(at + 1)) {
+        // Start of scan: the image data follows, no more metadata segments.
+        if marker == 0xDA {
+            break;
+        }
+        let Some(len) = read_u16(bytes, at + 2, false) else {
+            break;
+        };
+        let segment = bytes.get(at + 4..at + 2 + len as usize).unwrap_or_default();
+        if marker == 0xE1 && segment.starts_with(b"Exif\0\0") {
+            read_tiff(&segment[6..], meta);
+        }
+        at += 2 + len as usize;
+    }
+}
+
+fn read_png(bytes: &[u8], meta: &mut Metadata) {
+    let mut at = 8;
+    while let (Some(len), Some(kind)) = (read_u32(bytes, at, false), bytes.get(at + 4..at + 8)) {
+        let data = bytes.get(at + 8..at + 8 + len as usize).unwrap_or_default();
+        match kind {
+            b"tEXt" | b"iTXt" => {
+                if let Some(nul) = data.iter().position(|&b| b == 0) {
+                    let keyword = String::from_utf8_lossy(&data[..nul]).to_lowercase();
+                    let mut text = &data[nul + 1..];
+                    // iTXt: compression flag, method, language tag and translated keyword.
+                    if kind == b"iTXt" {
+                        if text.first() != Some(&0) {
+                            at += 12 + len as usize;
+                            continue;
+                        }
+                        text = text.get(2..).unwrap_or_default();
+                        for _ in 0..2 {
+                            let nul = text.iter().position(|&b| b == 0).unwrap_or(text.len());
+                            text = text.get(nul + 1..).unwrap_or_default();
+                        }
+                    }
+                    let key = match keyword.as_str() {
+                        "title" => "title",
+                        "author" => "artist",
+                        "description" => "caption",
+                        "comment" => "comment",
+                        "copyright" => "copyright",
+                        _ => "",
+                    };
+                    if let (false, Some(value)) = (key.is_empty(), clean_text(text)) {
+                        meta.entry(key.to_string()).or_insert(value.into());
+                    }
+                }
+            }
+            b"eXIf" => read_tiff(data, meta),
+            b"IDAT" | b"IEND" => break,
+            _ => {}
+        }
+        at += 12 + len as usize;
+    }
+}
+
+fn id3_key(frame: &[u8]) -> Option<&'static str> {
+    match frame {
+        b"TIT2" | b"TT2" => Some("title"),
+        b"TPE1" | b"TP1" => Some("artist"),
+        b"TALB" | b"TAL" => Some("album"),
+        b"TYER" | b"TDRC" | b"TYE" => Some("year"),
+        b"TCON" | b"TCO" => Some("genre"),
+        b"COMM" | b"COM" => Some("comment"),
+        _ => None,
+    }
+}
+
+fn id3_text(data: &[u8], is_comment: bool) -> Option<String> {
+    let (&encoding, mut text) = data.split_first()?;
+    // Comments carry a language code and a short description before the text.
+    if is_comment {
+        text = text.get(3..)?;
+        let terminator = if encoding == 1 || encoding == 2 { 2 } else { 1 };
+        let end = text
+            .chunks(terminator)
+            .position(|c| c.iter().all(|&b| b == 0))
+            .map(|i| (i + 1) * terminator)?;
+        text = text.get(end..)?;
+    }
+    match encoding {
+        1 if text.starts_with(&[0xFF, 0xFE]) => utf16_text(&text[2..], true),
+        1 if text.starts_with(&[0xFE, 0xFF]) => utf16_text(&text[2..], false),
+        1 => utf16_text(text, true),
+        2 => utf16_text(text, false),
+        _ => clean_text(text),
+    }
+}
+
+fn synchsafe(b: &[u8]) -> usize {
+    b.iter().fold(0, |acc, &b| (acc << 7) | (b & 0x7F) as usize)
+}
+
+fn read_id3v2(bytes: &[u8], meta: &mut Metadata) {
+    let Some(header) = bytes.get(0..10) else {
+        return;
+    };
+    let version = header[3];
+    let size = synchsafe(&header[6..10]);
+    let tag = bytes.get(10..10 + size).unwrap_or(&bytes[10..]);
+    let (id_len, header_len) = if version == 2 { (3, 6) } else { (4, 10) };
+    let mut at = 0;
+    while let Some(frame) = tag.get(at..at + header_len) {
+        let id = &frame[..id_len];
+        if id.iter().all(|&b| b == 0) {
+            break;
+        }
+        let len = match version {
+            2 => frame[3..6]
+                .iter()
+                .fold(0, |acc, &b| (acc << 8) | b as usize),
+            3 => frame[4..8]
+                .iter()
+                .fold(0, |acc, &b| (acc << 8) | b as usize),
+            _ => synchsafe(&frame[4..8]),
+        };
+        let data = tag
+            .get(at + header_len..at + header_len + len)
+            .unwrap_or_default();
+        if let Some(key) = id3_key(id) {
+            if let Some(value) = id3_text(data, key == "comment") {
+                meta.entry(key.to_string()).or_insert(value.into());
+            }
+        }
+        at += header_len + len;
+    }
+}
+
+fn read_id3v1(bytes: &[u8], meta: &mut Metadata) {
+    let Some(tag) = bytes.len().checked_sub(128).and_then(|at| bytes.get(at..)) else {
+        return;
+    };
+    if !tag.starts_with(b"TAG") {
+        return;
+    }
+    let fields = [
+        ("title", &tag[3..33]),
+        ("artist", &tag[33..63]),
+        ("album", &tag[63..93]),
+        ("year", &tag[93..97]),
+        ("comment", &tag[97..127]),
+    ];
+    for (key, field) in fields {
+        if let Some(value) = clean_text(field) {
+            meta.entry(key.to_string()).or_insert(value.into());
+        }
+    }
+}
+
+pub fn is_media_extension(ext: &str) -> bool {
+    matches!(ext, "jpg" | "jpeg" | "tif" | "tiff" | "png" | "mp3")
+}
+
+pub fn read_media_metadata(ext: &str, bytes: &[u8]) -> Metadata {
+    let mut meta = Metadata::new();
+    match ext {
+        "jpg" | "jpeg" => read_jpeg(bytes, &mut meta),
+        "tif" | "tiff" => read_tiff(bytes, &mut meta),
+        "png" => read_png(bytes, &mut meta),
+        "mp3" => {
+            if bytes.starts_with(b"ID3") {
+                read_id3v2(bytes, &mut meta);
+            }
+            read_id3v1(bytes, &mut meta);
+        }
+        _ => {}
+    }
+    meta
+}
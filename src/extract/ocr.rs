@@ -0,0 +1,121 @@
+// OCR for scanned documents by shelling out to `tesseract` (and `pdftoppm`
+// from poppler to rasterize PDF pages). Recognized text is cached on disk by
+// content hash, because OCR is by far the slowest part of indexing.
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+use super::{content_hash, PAGE_BREAK};
+use crate::Error;
+
+// Extracted text shorter than this is treated as "no text layer".
+pub const MIN_TEXT_CHARS: usize = 32;
+
+pub fn is_ocr_extension(ext: &str) -> bool {
+    matches!(ext, "pdf" | "png" | "jpg" | "jpeg" | "tif" | "tiff")
+}
+
+fn tesseract(image_path: &Path) -> Result<String, Error> {
+    let output = Command::new("tesseract")
+        .arg(image_path)
+        .arg("stdout")
+        .output()
+        .map_err(|err| Error::io("could not run tesseract", err))?;
+    if !output.status.success() {
+        return Err(Error::invalid(format!(
+            "tesseract failed on {image_path}: {stderr}",
+            image_path = image_path.display(),
+            stderr = String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// The OCR tools only read files, and documents do not have to come from
+// disk, so the input is written into a scratch directory first.
+fn ocr_in_scratch_dir(bytes: &[u8], ext: &str, hash: &str) -> Result<String, Error> {
+    let scratch_dir = env::temp_dir().join(format!("tinysearch-ocr-{}-{hash}", process::id()));
+    fs::create_dir_all(&scratch_dir).map_err(|err| {
+        Error::io(
+            format!(
+                "could not create directory {scratch_dir}",
+                scratch_dir = scratch_dir.display()
+            ),
+            err,
+        )
+    })?;
+    let input_path = scratch_dir.join(format!("input.{ext}"));
+    let result = fs::write(&input_path, bytes)
+        .map_err(|err| Error::io("could not write OCR input", err))
+        .and_then(|()| match ext {
+            "pdf" => ocr_pdf(&input_path, &scratch_dir),
+            _ => tesseract(&input_path),
+        });
+    let _ = fs::remove_dir_all(&scratch_dir);
+    result
+}
+
+fn ocr_pdf(file_path: &Path, scratch_dir: &Path) -> Result<String, Error> {
+    let pages_dir = scratch_dir.join("pages");
+    fs::create_dir_all(&pages_dir).map_err(|err| {
+        Error::io(
+            format!(
+                "could not create directory {pages_dir}",
+                pages_dir = pages_dir.display()
+            ),
+            err,
+        )
+    })?;
+    let status = Command::new("pdftoppm")
+        .args(["-r", "300", "-png"])
+        .arg(file_path)
+        .arg(pages_dir.join("page"))
+        .status()
+        .map_err(|err| Error::io("could not run pdftoppm", err))?;
+    if !status.success() {
+        return Err(Error::invalid(format!(
+            "pdftoppm could not rasterize {file_path}",
+            file_path = file_path.display()
+        )));
+    }
+    let mut pages = fs::read_dir(&pages_dir)
+        .map_err(|err| Error::io("could not list rasterized pages", err))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect::<Vec<PathBuf>>();
+    pages.sort();
+    let mut text = String::new();
+    for page in pages {
+        text.push_str(&tesseract(&page)?);
+        text.push_str(PAGE_BREAK);
+    }
+    Ok(text)
+}
+
+pub fn ocr_document(
+    name: &Path,
+    bytes: &[u8],
+    ext: &str,
+    cache_dir: &Path,
+) -> Result<String, Error> {
+    let hash = content_hash(bytes);
+    // Texts cached without `-v2` predate page breaks.
+    let cache_path = cache_dir.join("ocr").join(format!("{hash}-v2.txt"));
+    if let Ok(text) = fs::read_to_string(&cache_path) {
+        return Ok(text);
+    }
+
+    println!("Running OCR on {name:?}...");
+    let text = ocr_in_scratch_dir(bytes, ext, &hash)?;
+
+    // A failing cache write only costs another OCR run next time.
+    if let Some(parent) = cache_path.parent() {
+        if let Err(err) = fs::create_dir_all(parent).and_then(|()| fs::write(&cache_path, &text)) {
+            eprintln!(
+                "WARNING: could not cache OCR result in {cache_path}: {err}",
+                cache_path = cache_path.display()
+            );
+        }
+    }
+    Ok(text)
+}
@@ -0,0 +1,369 @@
+// Extraction of untrusted documents in a child process, so a parser that a
+// crafted file manages to exploit cannot reach the network or take the
+// indexing host down with it. The child is this binary again, started with
+// the hidden `extract-sandboxed` subcommand: it reads the document from
+// stdin, lowers its resource limits, on Linux forbids network sockets with a
+// seccomp filter, and writes the chunks to stdout as a line of JSON. A
+// document a plugin extracts is handed to the plugin's runtime by the child,
+// so the runtime is held to the limits of the sandbox as well.
+use std::env;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+
+use super::{Chunk, ExtractOptions};
+use crate::plugins::Plugins;
+use crate::Error;
+
+pub const SUBCOMMAND: &str = "extract-sandboxed";
+
+#[derive(Clone, Copy)]
+pub struct SandboxLimits {
+    // Address space of the child, documents and parsers included. Only
+    // Linux enforces it.
+    pub memory_mb: u64,
+    // CPU time before the child is killed with SIGXCPU.
+    pub cpu_secs: u64,
+}
+
+impl Default for SandboxLimits {
+    fn default() -> Self {
+        Self {
+            memory_mb: 1024,
+            cpu_secs: 30,
+        }
+    }
+}
+
+// Variables the OCR tools need. Everything else, credentials included, is
+// kept from the child so a compromised parser cannot copy it into the index.
+const PASSED_ENV: &[&str] = &["PATH", "TESSDATA_PREFIX"];
+
+pub fn extract(
+    name: &Path,
+    bytes: &[u8],
+    options: &ExtractOptions,
+    limits: SandboxLimits,
+) -> Result<Vec<Chunk>, Error> {
+    let exe = env::current_exe().map_err(|err| Error::io("could not find own executable", err))?;
+    let mut command = Command::new(exe);
+    command
+        .arg(SUBCOMMAND)
+        .arg(name)
+        .arg("--cache-dir")
+        .arg(&options.cache_dir)
+        .args(["--memory-mb", &limits.memory_mb.to_string()])
+        .args(["--cpu-secs", &limits.cpu_secs.to_string()]);
+    if options.notebook_outputs {
+        command.arg("--notebook-outputs");
+    }
+    if options.ocr {
+        command.arg("--ocr");
+    }
+    if options.thumbnails {
+        command.arg("--thumbnails");
+    }
+    if let Some((module, _)) = options.plugins.extractor(name) {
+        command.arg("--plugin").arg(module);
+    }
+    command.env_clear();
+    for var in PASSED_ENV {
+        if let Some(value) = env::var_os(var) {
+            command.env(var, value);
+        }
+    }
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| Error::io("could not start sandboxed extractor", err))?;
+    let mut stdin = child.stdin.take().expect("stdin of the child is piped");
+    // Written from another thread, the child may fill its output pipes
+    // before it has read the whole document.
+    let output = thread::scope(|scope| {
+        scope.spawn(move || {
+            // The child failing early closes the pipe, its exit status tells why.
+            let _ = stdin.write_all(bytes);
+        });
+        child.wait_with_output()
+    })
+    .map_err(|err| Error::io("could not wait for sandboxed extractor", err))?;
+
+    if !output.status.success() {
+        // The first line, without the backtrace hint of a panic.
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stderr = stderr.lines().next().unwrap_or_default().trim();
+        return Err(Error::invalid(match killed_by(&output.status) {
+            Some(signal) => format!(
+                "sandboxed extractor was killed by signal {signal}, the document may need more than {memory_mb} MB or {cpu_secs} s of CPU{sep}{stderr}",
+                memory_mb = limits.memory_mb,
+                cpu_secs = limits.cpu_secs,
+                sep = if stderr.is_empty() { "" } else { ": " },
+            ),
+            None if stderr.is_empty() => "sandboxed extractor failed".to_string(),
+            None => stderr.to_string(),
+        }));
+    }
+    // The chunks are the last line, the extractors may print progress before.
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let (progress, chunks) = stdout.rsplit_once('\n').unwrap_or(("", &stdout));
+    if !progress.is_empty() {
+        print!("{progress}");
+    }
+    serde_json::from_str(chunks)
+        .map_err(|err| Error::json("could not read the output of the sandboxed extractor", err))
+}
+
+#[cfg(unix)]
+fn killed_by(status: &std::process::ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn killed_by(_status: &std::process::ExitStatus) -> Option<i32> {
+    None
+}
+
+// The `extract-sandboxed` subcommand. Its arguments are written by `extract`
+// above, so there is no usage to print.
+pub fn child(mut args: impl Iterator<Item = String>) -> Result<(), Error> {
+    let name = args
+        .next()
+        .ok_or_else(|| Error::invalid("no document name"))?;
+    let mut options = ExtractOptions {
+        cache: false,
+        ..Default::default()
+    };
+    let mut limits = SandboxLimits::default();
+    while let Some(flag) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| Error::invalid(format!("no value for {flag}")))
+        };
+        let number = |value: String| {
+            value
+                .parse()
+                .map_err(|_| Error::invalid(format!("{flag} must be a number, not {value}")))
+        };
+        match flag.as_str() {
+            "--notebook-outputs" => options.notebook_outputs = true,
+            "--ocr" => options.ocr = true,
+            "--thumbnails" => options.thumbnails = true,
+            "--cache-dir" => options.cache_dir = PathBuf::from(value()?),
+            "--memory-mb" => limits.memory_mb = number(value()?)?,
+            "--cpu-secs" => limits.cpu_secs = number(value()?)?,
+            "--plugin" => {
+                options.plugins = Plugins::only_extractor(Path::new(&name), value()?.into())
+            }
+            _ => return Err(Error::invalid(format!("unknown flag {flag}"))),
+        }
+    }
+
+    let mut bytes = Vec::new();
+    std::io::stdin()
+        .read_to_end(&mut bytes)
+        .map_err(|err| Error::io("could not read document", err))?;
+    // The runtime of a plugin is another process, which writes its caches.
+    let plugin = options.plugins.extractor(Path::new(&name)).is_some();
+    restrict(limits, options.ocr || options.thumbnails || plugin)
+        .map_err(|err| Error::invalid(format!("could not enter sandbox: {err}")))?;
+    let chunks = super::extract_document(Path::new(&name), &bytes, &options)?;
+    let mut stdout = std::io::stdout().lock();
+    // JSON without pretty printing has no line breaks of its own.
+    writeln!(stdout).map_err(|err| Error::io("could not write chunks", err))?;
+    serde_json::to_writer(&mut stdout, &chunks)
+        .map_err(|err| Error::json("could not write chunks", err))?;
+    stdout
+        .flush()
+        .map_err(|err| Error::io("could not write chunks", err))
+}
+
+#[cfg(unix)]
+mod rlimit {
+    use std::os::raw::c_int;
+
+    #[repr(C)]
+    pub struct Rlimit {
+        pub cur: u64,
+        pub max: u64,
+    }
+
+    extern "C" {
+        pub fn setrlimit(resource: c_int, limit: *const Rlimit) -> c_int;
+    }
+
+    pub const CPU: c_int = 0;
+    pub const FSIZE: c_int = 1;
+    pub const CORE: c_int = 4;
+    #[cfg(target_os = "linux")]
+    pub const NPROC: c_int = 6;
+    #[cfg(target_os = "linux")]
+    pub const AS: c_int = 9;
+
+    pub fn set(resource: c_int, value: u64) -> Result<(), String> {
+        let limit = Rlimit {
+            cur: value,
+            max: value,
+        };
+        if unsafe { setrlimit(resource, &limit) } != 0 {
+            return Err(format!(
+                "setrlimit {resource}: {err}",
+                err = std::io::Error::last_os_error()
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Lowers the limits of this process for good. OCR and thumbnails run
+// tesseract and pdftoppm on scratch files, so they keep the right to start
+// processes and write files.
+#[cfg(unix)]
+fn restrict(limits: SandboxLimits, tools: bool) -> Result<(), String> {
+    rlimit::set(rlimit::CPU, limits.cpu_secs)?;
+    rlimit::set(rlimit::CORE, 0)?;
+    #[cfg(target_os = "linux")]
+    rlimit::set(
+        rlimit::AS,
+        limits
+            .memory_mb
+            .checked_mul(1024 * 1024)
+            .ok_or_else(|| format!("{} MB of memory is too much", limits.memory_mb))?,
+    )?;
+    if !tools {
+        rlimit::set(rlimit::FSIZE, 0)?;
+        #[cfg(target_os = "linux")]
+        rlimit::set(rlimit::NPROC, 0)?;
+    }
+    #[cfg(target_os = "linux")]
+    seccomp::forbid_network()?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn restrict(_limits: SandboxLimits, _tools: bool) -> Result<(), String> {
+    Err("sandboxed extraction is only supported on Unix".to_string())
+}
+
+// A seccomp filter that fails every socket other than Unix domain ones with
+// EACCES, along with ptrace and io_uring (which can open sockets by itself).
+// Kernels without seccomp, or architectures the filter does not know, run
+// the extractor under the resource limits alone.
+#[cfg(target_os = "linux")]
+mod seccomp {
+    use std::os::raw::{c_int, c_ulong};
+
+    #[repr(C)]
+    struct SockFilter {
+        code: u16,
+        jt: u8,
+        jf: u8,
+        k: u32,
+    }
+
+    #[repr(C)]
+    struct SockFprog {
+        len: u16,
+        filter: *const SockFilter,
+    }
+
+    extern "C" {
+        fn prctl(
+            option: c_int,
+            arg2: c_ulong,
+            arg3: c_ulong,
+            arg4: c_ulong,
+            arg5: c_ulong,
+        ) -> c_int;
+    }
+
+    const PR_SET_SECCOMP: c_int = 22;
+    const PR_SET_NO_NEW_PRIVS: c_int = 38;
+    const SECCOMP_MODE_FILTER: c_ulong = 2;
+    const EINVAL: i32 = 22;
+
+    const LD_ABS: u16 = 0x20;
+    const JEQ: u16 = 0x15;
+    const JGE: u16 = 0x35;
+    const RET: u16 = 0x06;
+
+    const RET_KILL_PROCESS: u32 = 0x8000_0000;
+    const RET_ALLOW: u32 = 0x7fff_0000;
+    const RET_EACCES: u32 = 0x0005_0000 | 13;
+
+    // Offsets into `struct seccomp_data`.
+    const NR: u32 = 0;
+    const ARCH: u32 = 4;
+    const ARG0: u32 = 16;
+
+    const AF_UNIX: u32 = 1;
+    // Marks x32 system calls, which would otherwise dodge the numbers below.
+    const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xc000_003e;
+    #[cfg(target_arch = "x86_64")]
+    const SYSCALLS: [u32; 3] = [101, 425, 41];
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xc000_00b7;
+    #[cfg(target_arch = "aarch64")]
+    const SYSCALLS: [u32; 3] = [117, 425, 198];
+
+    const fn op(code: u16, k: u32, jt: u8, jf: u8) -> SockFilter {
+        SockFilter { code, jt, jf, k }
+    }
+
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    pub fn forbid_network() -> Result<(), String> {
+        let [ptrace, io_uring_setup, socket] = SYSCALLS;
+        let filter = [
+            op(LD_ABS, ARCH, 0, 0),
+            op(JEQ, AUDIT_ARCH, 1, 0),
+            op(RET, RET_KILL_PROCESS, 0, 0),
+            op(LD_ABS, NR, 0, 0),
+            op(JGE, X32_SYSCALL_BIT, 6, 0),
+            op(JEQ, ptrace, 5, 0),
+            op(JEQ, io_uring_setup, 4, 0),
+            op(JEQ, socket, 0, 2),
+            op(LD_ABS, ARG0, 0, 0),
+            op(JEQ, AF_UNIX, 0, 1),
+            op(RET, RET_ALLOW, 0, 0),
+            op(RET, RET_EACCES, 0, 0),
+        ];
+        let program = SockFprog {
+            len: filter.len() as u16,
+            filter: filter.as_ptr(),
+        };
+        unsafe {
+            if prctl(PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                return Err(format!(
+                    "no_new_privs: {err}",
+                    err = std::io::Error::last_os_error()
+                ));
+            }
+            if prctl(
+                PR_SET_SECCOMP,
+                SECCOMP_MODE_FILTER,
+                &program as *const SockFprog as c_ulong,
+                0,
+                0,
+            ) != 0
+            {
+                let err = std::io::Error::last_os_error();
+                // Built without CONFIG_SECCOMP_FILTER.
+                if err.raw_os_error() != Some(EINVAL) {
+                    return Err(format!("seccomp: {err}"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    pub fn forbid_network() -> Result<(), String> {
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+// Previews of documents for the results of the web UI, made at index time
+// with `index --thumbnails`: the first page of a PDF rendered small by
+// `pdftoppm`, and the first heading of an HTML page. Both are kept in the
+// metadata of the document, `thumbnail` as a `data:` URL of the PNG and
+// `preview` as text, so they are stored in the index like the rest of it.
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::{self, Command};
+
+use super::content_hash;
+use crate::{Error, Metadata};
+
+// The longer side of a thumbnail in pixels, small enough that the index
+// does not grow by more than a few kilobytes per PDF.
+const THUMBNAIL_SIZE: u32 = 160;
+
+// Previews longer than this are cut at a word.
+#[cfg(feature = "extractor-html")]
+const MAX_PREVIEW_CHARS: usize = 120;
+
+const DATA_URL_PREFIX: &str = "data:image/png;base64,";
+
+// Adds the preview of a document to the metadata of its first chunk. A
+// thumbnail that cannot be made is not worth failing the document for.
+pub fn add_preview(name: &Path, bytes: &[u8], ext: &str, meta: &mut Metadata) {
+    match ext {
+        "pdf" => match pdf_thumbnail(bytes) {
+            Ok(thumbnail) => {
+                meta.insert("thumbnail".to_string(), thumbnail.into());
+            }
+            Err(err) => eprintln!(
+                "WARNING: no thumbnail for {name}: {err}",
+                name = name.display()
+            ),
+        },
+        #[cfg(feature = "extractor-html")]
+        "html" | "htm" => {
+            let headings = super::markup::html_headings(&String::from_utf8_lossy(bytes));
+            if let Some(heading) = headings.into_iter().find(|heading| !heading.is_empty()) {
+                meta.insert("preview".to_string(), shorten(&heading).into());
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(feature = "extractor-html")]
+fn shorten(text: &str) -> String {
+    if text.chars().count() <= MAX_PREVIEW_CHARS {
+        return text.to_string();
+    }
+    let cut = text
+        .char_indices()
+        .nth(MAX_PREVIEW_CHARS)
+        .map_or(text.len(), |(at, _)| at);
+    let cut = text[..cut].rfind(' ').unwrap_or(cut);
+    format!("{}…", text[..cut].trim_end())
+}
+
+// The first page as a PNG `data:` URL. Like OCR, pdftoppm only reads
+// files, so the document is written into a scratch directory first.
+fn pdf_thumbnail(bytes: &[u8]) -> Result<String, Error> {
+    let hash = content_hash(bytes);
+    let scratch_dir = env::temp_dir().join(format!("tinysearch-thumb-{}-{hash}", process::id()));
+    fs::create_dir_all(&scratch_dir).map_err(|err| {
+        Error::io(
+            format!(
+                "could not create directory {scratch_dir}",
+                scratch_dir = scratch_dir.display()
+            ),
+            err,
+        )
+    })?;
+    let input_path = scratch_dir.join("input.pdf");
+    let result = fs::write(&input_path, bytes)
+        .map_err(|err| Error::io("could not write thumbnail input", err))
+        .and_then(|()| {
+            let status = Command::new("pdftoppm")
+                .args(["-png", "-singlefile", "-f", "1", "-l", "1"])
+                .args(["-scale-to", &THUMBNAIL_SIZE.to_string()])
+                .arg(&input_path)
+                .arg(scratch_dir.join("page"))
+                .status()
+                .map_err(|err| Error::io("could not run pdftoppm", err))?;
+            if !status.success() {
+                return Err(Error::invalid("pdftoppm could not render the first page"));
+            }
+            fs::read(scratch_dir.join("page.png"))
+                .map_err(|err| Error::io("could not read the rendered page", err))
+        });
+    let _ = fs::remove_dir_all(&scratch_dir);
+    Ok(format!("{DATA_URL_PREFIX}{}", encode_base64(&result?)))
+}
+
+// The PNG in a `thumbnail` value, unless it is not one `add_preview` wrote.
+pub fn thumbnail_png(data_url: &str) -> Option<Vec<u8>> {
+    decode_base64(data_url.strip_prefix(DATA_URL_PREFIX)?)
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn encode_base64(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for group in bytes.chunks(3) {
+        let word = group
+            .iter()
+            .enumerate()
+            .fold(0u32, |word, (i, &b)| word | u32::from(b) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= group.len() {
+                out.push(BASE64_ALPHABET[(word >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=').as_bytes();
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    for group in text.chunks(4) {
+        if group.len() == 1 {
+            return None;
+        }
+        let mut word = 0u32;
+        for (i, &c) in group.iter().enumerate() {
+            let value = BASE64_ALPHABET.iter().position(|&a| a == c)?;
+            word |= (value as u32) << (18 - 6 * i);
+        }
+        for i in 0..group.len() - 1 {
+            out.push((word >> (16 - 8 * i)) as u8);
+        }
+    }
+    Some(out)
+}
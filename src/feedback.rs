@@ -0,0 +1,102 @@
+// Pseudo-relevance feedback: the best results of a query are taken to be
+// relevant, and the terms that stand out in them by TF-IDF are added to the
+// query at a fraction of the weight of its own. A short query then also
+// finds the documents that say the same in other words.
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use crate::query::Query;
+use crate::scoring::CorpusStats;
+use crate::Model;
+
+// Results taken as relevant, and terms added from them.
+pub const FEEDBACK_DOCS: usize = 10;
+pub const FEEDBACK_TERMS: usize = 5;
+
+// How a query is expanded before it runs, beyond its wildcards and typos.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Expansion {
+    // With the terms of its best results.
+    Prf,
+}
+
+impl Expansion {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "prf" => Some(Self::Prf),
+            _ => None,
+        }
+    }
+}
+
+// The `count` terms with the highest TF-IDF summed over the documents, other
+// than those of the query. Terms no other document has cannot find more and
+// are left out, and so are terms of punctuation alone.
+pub fn feedback_terms(
+    model: &Model,
+    stats: &CorpusStats,
+    docs: &[&Path],
+    query: &Query,
+    count: usize,
+) -> Vec<String> {
+    let in_query = query.all_terms().into_iter().collect::<HashSet<_>>();
+    let mut weights = HashMap::<&str, f32>::new();
+    for path in docs {
+        let Some(doc) = model.docs.get(&*path.to_string_lossy()) else {
+            continue;
+        };
+        let doc_len = doc.tf.values().sum::<usize>().max(1) as f32;
+        for (term, &freq) in &doc.tf {
+            let doc_freq = stats.doc_freq(term);
+            if doc_freq < 2
+                || in_query.contains(term.as_str())
+                || !term.chars().any(char::is_alphanumeric)
+            {
+                continue;
+            }
+            let idf = (stats.docs() as f32 / doc_freq as f32).ln();
+            *weights.entry(term).or_default() += freq as f32 / doc_len * idf;
+        }
+    }
+    let mut ranked = weights
+        .into_iter()
+        .filter(|&(_, weight)| weight > 0.0)
+        .collect::<Vec<_>>();
+    ranked.sort_by(|(term_a, a), (term_b, b)| b.total_cmp(a).then(term_a.cmp(term_b)));
+    ranked
+        .into_iter()
+        .take(count)
+        .map(|(term, _)| term.to_string())
+        .collect()
+}
+
+// The query or any of the terms. Exclusions at the top of the query still
+// apply to the documents the terms find.
+pub fn expand(query: Query, terms: Vec<String>) -> Query {
+    if terms.is_empty() {
+        return query;
+    }
+    let (query, exclusions) = match query {
+        Query::And(operands) => {
+            let (exclusions, mut rest): (Vec<_>, Vec<_>) = operands
+                .into_iter()
+                .partition(|operand| matches!(operand, Query::Not(_)));
+            let query = match rest.len() {
+                0 => return Query::And(exclusions),
+                1 => rest.pop().unwrap(),
+                _ => Query::And(rest),
+            };
+            (query, exclusions)
+        }
+        query => (query, Vec::new()),
+    };
+    let mut alternatives = vec![query];
+    alternatives.extend(terms.into_iter().map(Query::Related));
+    let expanded = Query::Or(alternatives);
+    if exclusions.is_empty() {
+        return expanded;
+    }
+    let mut operands = vec![expanded];
+    operands.extend(exclusions);
+    Query::And(operands)
+}
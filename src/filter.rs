@@ -0,0 +1,106 @@
+use std::cmp::Ordering;
+
+use crate::bitset::Bitset;
+use crate::inverted::InvertedIndex;
+use crate::schema::{self, Schema};
+use crate::{Doc, Error, Model};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    // Any of the values.
+    Eq,
+    // All of the values.
+    All,
+    Ge,
+    Le,
+}
+
+// A condition on document metadata written as `key=value`, `key>=value` or
+// `key<=value`. Ordering compares values as strings, which is what timestamps
+// in RFC 3339 form need.
+//
+// Fields such as tags can hold several values, and a condition holds when
+// one of them meets it. `key=a,b` asks for any of the values given and
+// `key:a,b` for all of them, e.g. `tag:rust,search`. How values compare
+// depends on the type of the field in the schema of the index.
+#[derive(Debug)]
+pub struct Filter {
+    key: String,
+    op: Op,
+    values: Vec<String>,
+}
+
+impl Filter {
+    pub fn parse(source: &str) -> Result<Self, Error> {
+        let (key, op, value) = if let Some((key, value)) = source.split_once(">=") {
+            (key, Op::Ge, value)
+        } else if let Some((key, value)) = source.split_once("<=") {
+            (key, Op::Le, value)
+        } else if let Some((key, value)) = source.split_once('=') {
+            (key, Op::Eq, value)
+        } else if let Some((key, value)) = source.split_once(':') {
+            (key, Op::All, value)
+        } else {
+            return Err(Error::invalid(format!(
+                "filter {source} must look like key=value, key:value, key>=value or key<=value"
+            )));
+        };
+        if key.is_empty() {
+            return Err(Error::invalid(format!(
+                "filter {source} has no metadata key"
+            )));
+        }
+        let values = match op {
+            Op::Eq | Op::All => value.split(',').map(str::to_string).collect(),
+            Op::Ge | Op::Le => vec![value.to_string()],
+        };
+        Ok(Self {
+            key: key.to_string(),
+            op,
+            values,
+        })
+    }
+
+    // The documents of the model that meet the filter, by their ordinals in
+    // `index`, which must be that of the model.
+    pub fn bitset(&self, model: &Model, index: &InvertedIndex) -> Bitset {
+        let schema = &model.manifest.config.fields;
+        let mut matching = Bitset::new(index.docs());
+        for ordinal in 0..index.docs() {
+            if let Some(doc) = model.docs.get(index.key(ordinal)) {
+                if self.matches(doc, schema) {
+                    matching.insert(ordinal);
+                }
+            }
+        }
+        matching
+    }
+
+    pub fn matches(&self, doc: &Doc, schema: &Schema) -> bool {
+        let Some(actual) = doc.meta.get(&self.key) else {
+            return false;
+        };
+        let actual = actual.values();
+        let field = schema.get(&self.key).copied();
+        let has = |wanted: &String| {
+            actual
+                .iter()
+                .any(|value| schema::equals(field, value, wanted))
+        };
+        let bound = |value: &String| schema::compare(field, value, &self.values[0]);
+        match self.op {
+            Op::Eq => self.values.iter().any(has),
+            Op::All => self.values.iter().all(has),
+            Op::Ge => actual
+                .iter()
+                .any(|value| bound(value).is_some_and(Ordering::is_ge)),
+            Op::Le => actual
+                .iter()
+                .any(|value| bound(value).is_some_and(Ordering::is_le)),
+        }
+    }
+}
+
+pub fn matches_all(filters: &[Filter], schema: &Schema, doc: &Doc) -> bool {
+    filters.iter().all(|filter| filter.matches(doc, schema))
+}
@@ -0,0 +1,257 @@
+// The pages the server hands out. They are rendered from templates with the
+// title, branding and wording of the deployment, so an instance can be
+// customized with a config file instead of a fork. The bundled files are
+// compiled into the binary, so the server does not depend on the directory
+// it runs in.
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use minijinja::value::merge_maps;
+use minijinja::{context, Environment, UndefinedBehavior};
+use serde::Deserialize;
+use serde_json::Value;
+
+use tinysearch::Error;
+
+const LAYOUT_HTML: &str = include_str!("frontend/layout.html");
+const INDEX_HTML: &str = include_str!("frontend/index.html");
+const RESULTS_HTML: &str = include_str!("frontend/results.html");
+const SITE_HTML: &str = include_str!("frontend/site.html");
+const DOCUMENT_HTML: &str = include_str!("frontend/document.html");
+const INDEX_JS: &str = include_str!("frontend/index.js");
+const SITE_JS: &str = include_str!("frontend/site.js");
+const STYLE_CSS: &str = include_str!("frontend/style.css");
+const ROBOTS_TXT: &str = include_str!("frontend/robots.txt");
+
+// Wording of the page per language. Keys a language lacks fall back to English.
+const STRINGS: &[(&str, &str)] = &[
+    ("en", include_str!("frontend/strings/en.json")),
+    ("de", include_str!("frontend/strings/de.json")),
+    ("fr", include_str!("frontend/strings/fr.json")),
+];
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct FrontendConfig {
+    pub title: String,
+    // Shown above the heading, e.g. the name of the organization.
+    pub brand: Option<String>,
+    // Selects the bundled strings and becomes the `lang` of the page.
+    pub lang: String,
+    // Name of the searched collection, shown under the heading.
+    pub index_name: Option<String>,
+    // Replacements for single strings, keyed like the bundled ones.
+    pub strings: BTreeMap<String, String>,
+    // Directory whose layout.html, index.html, results.html, index.js and
+    // style.css replace the bundled ones, as do its site.html, document.html
+    // and site.js in the sites of export-site.
+    pub templates: Option<PathBuf>,
+    // Served as /robots.txt instead of the bundled one.
+    pub robots: Option<PathBuf>,
+    // Directory index.js and style.css are read from on every request, so
+    // changes to them show on reload while working on the UI.
+    pub static_dir: Option<PathBuf>,
+    // Has the page ask for results in the languages of the browser first.
+    pub prefer_user_language: bool,
+}
+
+impl Default for FrontendConfig {
+    fn default() -> Self {
+        Self {
+            title: "tinySearch".to_string(),
+            brand: None,
+            lang: "en".to_string(),
+            index_name: None,
+            strings: BTreeMap::new(),
+            templates: None,
+            robots: None,
+            static_dir: None,
+            prefer_user_language: false,
+        }
+    }
+}
+
+// The static files, ready to be served, and the templates of the pages
+// rendered per request.
+pub struct Frontend {
+    templates: Environment<'static>,
+    // What every page is rendered with.
+    globals: minijinja::Value,
+    pub index_html: String,
+    index_js: String,
+    style_css: String,
+    pub site_js: String,
+    static_dir: Option<PathBuf>,
+    pub robots_txt: String,
+}
+
+fn bundled_strings(lang: &str) -> Option<BTreeMap<String, String>> {
+    let (_, json) = STRINGS.iter().find(|(name, _)| *name == lang)?;
+    Some(serde_json::from_str(json).expect("bundled strings are valid JSON"))
+}
+
+impl FrontendConfig {
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let json = fs::read_to_string(path)
+            .map_err(|err| Error::io(format!("could not read frontend config {path}"), err))?;
+        serde_json::from_str(&json)
+            .map_err(|err| Error::json(format!("could not parse frontend config {path}"), err))
+    }
+
+    fn strings(&self) -> BTreeMap<String, String> {
+        let mut strings = bundled_strings("en").unwrap();
+        // Regional variants like de-AT or de_AT use the strings of the language.
+        let language = self.lang.split(['-', '_']).next().unwrap_or_default();
+        match bundled_strings(language) {
+            Some(translated) => strings.extend(translated),
+            None => eprintln!(
+                "WARNING: no bundled strings for language {}, using English",
+                self.lang
+            ),
+        }
+        strings.extend(self.strings.clone());
+        strings
+    }
+
+    fn read_template(&self, name: &str, bundled: &str) -> Result<String, Error> {
+        let Some(dir) = &self.templates else {
+            return Ok(bundled.to_string());
+        };
+        let path = dir.join(name);
+        if !path.exists() {
+            return Ok(bundled.to_string());
+        }
+        fs::read_to_string(&path)
+            .map_err(|err| Error::io(format!("could not read template {}", path.display()), err))
+    }
+
+    fn robots_txt(&self) -> Result<String, Error> {
+        match &self.robots {
+            Some(path) => fs::read_to_string(path)
+                .map_err(|err| Error::io(format!("could not read {}", path.display()), err)),
+            None => Ok(ROBOTS_TXT.to_string()),
+        }
+    }
+
+    // Parses the templates and renders the start page once, so mistakes in
+    // custom templates show up when the server starts rather than on the
+    // first visit.
+    pub fn render(&self) -> Result<Frontend, Error> {
+        let mut templates = Environment::new();
+        // A misspelled string key should fail loudly instead of leaving a gap.
+        templates.set_undefined_behavior(UndefinedBehavior::Strict);
+        for (name, bundled) in [
+            ("layout.html", LAYOUT_HTML),
+            ("index.html", INDEX_HTML),
+            ("results.html", RESULTS_HTML),
+            ("site.html", SITE_HTML),
+            ("document.html", DOCUMENT_HTML),
+        ] {
+            templates
+                .add_template_owned(name, self.read_template(name, bundled)?)
+                .map_err(|err| Error::invalid(format!("could not parse template {name}: {err}")))?;
+        }
+        let globals = minijinja::Value::from_serialize(context! {
+            title => self.title,
+            brand => self.brand,
+            lang => self.lang,
+            index_name => self.index_name,
+            strings => self.strings(),
+            prefer_user_language => self.prefer_user_language,
+        });
+        let index_html = render(
+            &templates,
+            "index.html",
+            context! { query => "", ..globals.clone() },
+        )?;
+        Ok(Frontend {
+            templates,
+            globals,
+            index_html,
+            index_js: self.read_template("index.js", INDEX_JS)?,
+            style_css: self.read_template("style.css", STYLE_CSS)?,
+            site_js: self.read_template("site.js", SITE_JS)?,
+            static_dir: self.static_dir.clone(),
+            robots_txt: self.robots_txt()?,
+        })
+    }
+}
+
+fn render(templates: &Environment, name: &str, context: minijinja::Value) -> Result<String, Error> {
+    templates
+        .get_template(name)
+        .and_then(|template| template.render(context))
+        .map_err(|err| Error::invalid(format!("could not render template {name}: {err}")))
+}
+
+impl Frontend {
+    // index.js or style.css, from the static directory when it has the file
+    // and from memory otherwise.
+    pub fn static_file(&self, name: &str) -> Cow<'_, str> {
+        let loaded = match name {
+            "index.js" => &self.index_js,
+            _ => &self.style_css,
+        };
+        let Some(path) = self.static_dir.as_ref().map(|dir| dir.join(name)) else {
+            return Cow::Borrowed(loaded);
+        };
+        if !path.exists() {
+            return Cow::Borrowed(loaded);
+        }
+        match fs::read_to_string(&path) {
+            Ok(text) => Cow::Owned(text),
+            Err(err) => {
+                eprintln!("WARNING: could not read {}: {err}", path.display());
+                Cow::Borrowed(loaded)
+            }
+        }
+    }
+
+    // The results page for a search payload of `api::search`, with links to
+    // the neighbouring pages and to the page without its `format` for
+    // exporting it.
+    pub fn results_page(
+        &self,
+        payload: &Value,
+        previous: Option<String>,
+        next: Option<String>,
+        export: Option<String>,
+    ) -> Result<String, Error> {
+        let context = context! {
+            previous,
+            next,
+            export,
+            ..merge_maps([minijinja::Value::from_serialize(payload), self.globals.clone()])
+        };
+        render(&self.templates, "results.html", context)
+    }
+
+    // The search page of a site written by export-site.
+    pub fn site_page(&self) -> Result<String, Error> {
+        let context = context! { query => "", ..self.globals.clone() };
+        render(&self.templates, "site.html", context)
+    }
+
+    // The page of one document of such a site, with its text split into
+    // paragraphs.
+    pub fn document_page(
+        &self,
+        doc_title: &str,
+        path: &str,
+        url: Option<&str>,
+        date: Option<&str>,
+        paragraphs: &[&str],
+    ) -> Result<String, Error> {
+        let context = context! {
+            doc_title,
+            path,
+            url,
+            date,
+            paragraphs,
+            ..self.globals.clone()
+        };
+        render(&self.templates, "document.html", context)
+    }
+}
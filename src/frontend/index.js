@@ -0,0 +1,366 @@
+// Results UI: completes words while typing, searches as the form is
+// submitted, loads further pages while scrolling, narrows by facets or to the
+// results of the previous search and moves through results with j/k/enter.
+const PAGE_SIZE = 20;
+const COMPLETIONS = 8;
+// Milliseconds without typing before completions are fetched.
+const COMPLETE_DELAY = 150;
+
+const strings = JSON.parse(document.getElementById("strings").textContent);
+const form = document.getElementById("search");
+const input = document.getElementById("query");
+const status = document.getElementById("status");
+const list = document.getElementById("results");
+const more = document.getElementById("more");
+const facets = document.getElementById("facets");
+const facetList = document.getElementById("facet-list");
+const filterList = document.getElementById("filters");
+const exportLinks = document.getElementById("export");
+const withinOption = document.getElementById("within-option");
+const withinBox = document.getElementById("within");
+const completions = document.getElementById("completions");
+// Results in the languages of the browser first, when the server says so.
+const preferLanguage = document.querySelector("main").hasAttribute("data-prefer-language");
+
+const state = {
+  query: "",
+  filters: [],
+  // The result set the search is narrowed to, and the one it answered with.
+  within: null,
+  resultSet: null,
+  offset: 0,
+  total: null,
+  done: true,
+  loading: false,
+  selected: -1,
+  // Bumped by every new search so late responses of an old one are dropped.
+  generation: 0,
+};
+
+// Theme: the saved choice, else the preference of the system.
+const root = document.documentElement;
+const savedTheme = localStorage.getItem("theme");
+if (savedTheme ? savedTheme === "dark" : matchMedia("(prefers-color-scheme: dark)").matches) {
+  root.classList.add("dark");
+}
+document.getElementById("theme").addEventListener("click", () => {
+  const dark = root.classList.toggle("dark");
+  localStorage.setItem("theme", dark ? "dark" : "light");
+});
+
+// The API answers with the results and their total, and a corrected query
+// when some word of it is not in the index; a result is a `[path, score]`
+// pair, with the relevance when scores are normalized, or an object.
+function normalize(payload) {
+  return {
+    total: payload.total ?? null,
+    didYouMean: payload.did_you_mean ?? null,
+    results: (payload.results || []).map((result) =>
+      Array.isArray(result)
+        ? { path: result[0], score: result[1], relevance: result[2] }
+        : result
+    ),
+  };
+}
+
+function renderSnippet(snippet) {
+  const p = document.createElement("p");
+  // Pieces are `[text, is query term]` pairs that include their spacing.
+  snippet.forEach(([text, hit]) => {
+    if (hit) {
+      const mark = document.createElement("mark");
+      mark.textContent = text;
+      p.append(mark);
+    } else {
+      p.append(text);
+    }
+  });
+  return p;
+}
+
+function renderResult(result) {
+  const item = document.createElement("li");
+  item.className = "result";
+  if (result.thumbnail) {
+    const thumbnail = document.createElement("img");
+    thumbnail.className = "thumbnail";
+    thumbnail.src = result.thumbnail;
+    thumbnail.alt = "";
+    thumbnail.loading = "lazy";
+    item.append(thumbnail);
+  }
+  const link = document.createElement("a");
+  // PDF viewers open the file on the page the fragment names.
+  const page = result.pages ? "#page=" + result.pages[0] : "";
+  link.href = result.url || "file://" + result.path + page;
+  link.textContent = result.title || result.path;
+  // Reported as feedback, which the server counts for sort=popular.
+  link.addEventListener("click", () => {
+    const click = { event: "click", path: result.path, query: state.query };
+    navigator.sendBeacon("/api/v1/feedback", JSON.stringify(click));
+  });
+  item.append(link);
+  const meta = document.createElement("div");
+  meta.className = "meta";
+  const pages = result.pages && strings.pages + " " + result.pages.join(", ");
+  meta.textContent = [result.date, result.score?.toFixed(3), pages].filter(Boolean).join(" · ");
+  if (typeof result.relevance === "number") {
+    const bar = document.createElement("meter");
+    bar.className = "relevance";
+    bar.value = result.relevance;
+    meta.append(" ", bar);
+  }
+  item.append(meta);
+  if (Array.isArray(result.snippet)) item.append(renderSnippet(result.snippet));
+  return item;
+}
+
+// A link searching for the corrected query instead.
+function renderDidYouMean(query) {
+  const link = document.createElement("a");
+  link.href = "#";
+  link.textContent = query;
+  link.addEventListener("click", (event) => {
+    event.preventDefault();
+    input.value = query;
+    form.requestSubmit();
+  });
+  const hint = document.createElement("span");
+  hint.className = "hint";
+  hint.append(strings.did_you_mean + " ", link, "?");
+  return hint;
+}
+
+function select(index) {
+  const items = list.children;
+  if (items.length === 0) return;
+  index = Math.max(0, Math.min(index, items.length - 1));
+  items[state.selected]?.classList.remove("selected");
+  state.selected = index;
+  items[index].classList.add("selected");
+  items[index].scrollIntoView({ block: "nearest" });
+  // Selecting the last result fetches the next page early.
+  if (index === items.length - 1) loadPage();
+}
+
+async function loadPage() {
+  if (state.done || state.loading) return;
+  state.loading = true;
+  const generation = state.generation;
+  status.textContent = strings.loading;
+  try {
+    const response = await fetch("/api/v1/search", {
+      method: "POST",
+      headers: { "Content-Type": "application/json" },
+      body: JSON.stringify({
+        query: state.query,
+        filters: state.filters,
+        offset: state.offset,
+        limit: PAGE_SIZE,
+        normalize: "max",
+        snippets: true,
+        prefer_lang: preferLanguage ? "auto" : undefined,
+        within: state.within ?? undefined,
+        // Further pages come from the matches the first one was cut from.
+        result_set: state.offset > 0 ? state.resultSet ?? undefined : undefined,
+      }),
+    });
+    // Failures answer with `{error: {code, message}}`, and the message,
+    // e.g. about a malformed query, says more than that the search failed.
+    if (!response.ok) {
+      const failure = await response.json().catch(() => null);
+      if (generation !== state.generation) return;
+      state.done = true;
+      status.textContent = failure?.error?.message || strings.search_failed;
+      return;
+    }
+    const page = normalize(await response.json());
+    if (generation !== state.generation) return;
+    state.resultSet = response.headers.get("X-Result-Set");
+    withinOption.hidden = state.resultSet === null;
+    page.results.forEach((result) => list.append(renderResult(result)));
+    state.offset += page.results.length;
+    state.total = page.total;
+    state.done =
+      page.results.length < PAGE_SIZE || (state.total !== null && state.offset >= state.total);
+    exportLinks.hidden = state.offset === 0;
+    if (state.offset === 0) {
+      status.textContent = strings.no_results;
+      if (page.didYouMean) status.append(" ", renderDidYouMean(page.didYouMean));
+    } else if (state.total !== null) {
+      status.textContent = strings.results_count.replace("{count}", state.total);
+    } else {
+      status.textContent = "";
+    }
+  } catch (err) {
+    if (generation !== state.generation) return;
+    state.done = true;
+    status.textContent = strings.search_failed;
+  } finally {
+    if (generation === state.generation) state.loading = false;
+  }
+}
+
+// Offers the query with its last word completed to each of the index terms
+// starting with it.
+async function complete() {
+  const text = input.value;
+  let payload;
+  try {
+    const params = new URLSearchParams({ q: text, limit: COMPLETIONS });
+    const response = await fetch("/api/v1/complete?" + params);
+    if (!response.ok) throw new Error(response.statusText);
+    payload = await response.json();
+  } catch (err) {
+    return;
+  }
+  // Typing went on while the request was under way.
+  if (payload.query !== input.value) return;
+  const head = text.slice(0, text.length - text.match(/\S*$/)[0].length);
+  completions.replaceChildren(
+    ...payload.completions.map(({ term }) => {
+      const option = document.createElement("option");
+      option.value = head + term.toLowerCase();
+      return option;
+    })
+  );
+}
+
+let completeTimer;
+input.setAttribute("list", completions.id);
+input.addEventListener("input", () => {
+  clearTimeout(completeTimer);
+  completeTimer = setTimeout(complete, COMPLETE_DELAY);
+});
+
+async function loadFacets() {
+  const generation = state.generation;
+  const params = new URLSearchParams({ q: state.query });
+  state.filters.forEach((filter) => params.append("filter", filter));
+  let counts;
+  try {
+    const response = await fetch("/api/v1/facets?" + params);
+    if (!response.ok) throw new Error(response.statusText);
+    counts = await response.json();
+  } catch (err) {
+    // The server offers no facets, the results are shown without them.
+    facets.hidden = true;
+    return;
+  }
+  if (generation !== state.generation) return;
+  facetList.replaceChildren();
+  // `{field: {value: count}}`; a value narrows the results when clicked.
+  for (const [field, values] of Object.entries(counts)) {
+    const heading = document.createElement("h3");
+    heading.textContent = field;
+    facetList.append(heading);
+    for (const [value, count] of Object.entries(values)) {
+      const button = document.createElement("button");
+      button.type = "button";
+      button.textContent = value;
+      const badge = document.createElement("span");
+      badge.className = "count";
+      badge.textContent = count;
+      button.append(badge);
+      button.addEventListener("click", () => toggleFilter(`${field}=${value}`));
+      facetList.append(button);
+    }
+  }
+  facets.hidden = false;
+}
+
+// The export links download the results of the search as CSV or Markdown,
+// as many as fit on one page of the API.
+function updateExportLinks() {
+  const params = new URLSearchParams({ q: state.query, limit: 100 });
+  state.filters.forEach((filter) => params.append("filter", filter));
+  if (state.within) params.set("within", state.within);
+  for (const format of ["csv", "md"]) {
+    params.set("format", format);
+    document.getElementById("export-" + format).href = "/search?" + params;
+  }
+}
+
+function renderFilters() {
+  filterList.replaceChildren();
+  state.filters.forEach((filter) => {
+    const button = document.createElement("button");
+    button.type = "button";
+    button.textContent = "✕ " + filter;
+    button.title = strings.remove_filter;
+    button.addEventListener("click", () => toggleFilter(filter));
+    filterList.append(button);
+  });
+}
+
+function toggleFilter(filter) {
+  const at = state.filters.indexOf(filter);
+  if (at === -1) {
+    state.filters.push(filter);
+  } else {
+    state.filters.splice(at, 1);
+  }
+  renderFilters();
+  search();
+}
+
+function search() {
+  state.generation += 1;
+  state.offset = 0;
+  state.total = null;
+  state.done = state.query === "";
+  state.loading = false;
+  state.selected = -1;
+  list.replaceChildren();
+  status.textContent = "";
+  exportLinks.hidden = true;
+  withinOption.hidden = true;
+  if (state.query === "") return;
+  updateExportLinks();
+  loadPage();
+  loadFacets();
+}
+
+form.addEventListener("submit", (event) => {
+  event.preventDefault();
+  state.query = input.value.trim();
+  // Searching within the results keeps them, filters included, as the set
+  // the new query is narrowed to.
+  state.within = withinBox.checked ? state.resultSet : null;
+  withinBox.checked = false;
+  state.filters = [];
+  renderFilters();
+  search();
+  input.blur();
+});
+
+new IntersectionObserver((entries) => {
+  if (entries.some((entry) => entry.isIntersecting)) loadPage();
+}).observe(more);
+
+document.addEventListener("keydown", (event) => {
+  if (event.target === input) {
+    if (event.key === "Escape") input.blur();
+    return;
+  }
+  if (event.ctrlKey || event.metaKey || event.altKey) return;
+  switch (event.key) {
+    case "j":
+      select(state.selected + 1);
+      break;
+    case "k":
+      select(state.selected - 1);
+      break;
+    case "Enter":
+      list.children[state.selected]?.querySelector("a").click();
+      break;
+    case "/":
+      input.focus();
+      break;
+    default:
+      return;
+  }
+  event.preventDefault();
+});
+
+input.focus();
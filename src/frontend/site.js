@@ -0,0 +1,94 @@
+// Search UI of a site written by export-site, which has no server to ask:
+// queries are answered from SEARCH_INDEX, loaded from search-index.js. The
+// words of a query are case folded like the index folds them, mapped to the
+// terms they became, e.g. stemmed, and a word that is no term matches the
+// terms it starts. Documents score the weights of the terms they have.
+const MAX_RESULTS = 100;
+// Terms a word that is no term expands to at most.
+const MAX_EXPANSIONS = 50;
+
+const strings = JSON.parse(document.getElementById("strings").textContent);
+const form = document.getElementById("search");
+const input = document.getElementById("query");
+const status = document.getElementById("status");
+const list = document.getElementById("results");
+const terms = Object.keys(SEARCH_INDEX.postings).sort();
+
+function fold(word) {
+  return word.toUpperCase().normalize("NFC");
+}
+
+function termsOf(word) {
+  const folded = fold(word);
+  const term = SEARCH_INDEX.words[folded] ?? folded;
+  // Stopwords map to no term.
+  if (term === "") return [];
+  if (term in SEARCH_INDEX.postings) return [term];
+  const expansions = [];
+  for (const candidate of terms) {
+    if (candidate.startsWith(folded)) expansions.push(candidate);
+    if (expansions.length === MAX_EXPANSIONS) break;
+  }
+  return expansions;
+}
+
+function search(query) {
+  const scores = new Map();
+  for (const word of query.split(/[^\p{L}\p{N}_]+/u).filter(Boolean)) {
+    for (const term of termsOf(word)) {
+      for (const [doc, weight] of SEARCH_INDEX.postings[term]) {
+        scores.set(doc, (scores.get(doc) ?? 0) + weight);
+      }
+    }
+  }
+  return [...scores].sort(([, a], [, b]) => b - a);
+}
+
+function renderResult([doc, score]) {
+  const { title, path, page, url, date, excerpt } = SEARCH_INDEX.docs[doc];
+  const item = document.createElement("li");
+  item.className = "result";
+  const link = document.createElement("a");
+  // Documents without text have no page, only where they came from.
+  link.href = page ?? url ?? "file://" + path;
+  link.textContent = title || path;
+  item.append(link);
+  const meta = document.createElement("div");
+  meta.className = "meta";
+  meta.textContent = [date, score.toFixed(3)].filter(Boolean).join(" · ");
+  item.append(meta);
+  if (excerpt) {
+    const p = document.createElement("p");
+    p.textContent = excerpt;
+    item.append(p);
+  }
+  return item;
+}
+
+function show(query) {
+  input.value = query;
+  list.replaceChildren();
+  status.textContent = "";
+  if (query === "") return;
+  const results = search(query);
+  status.textContent =
+    results.length === 0
+      ? strings.no_results
+      : strings.results_count.replace("{count}", results.length);
+  list.append(...results.slice(0, MAX_RESULTS).map(renderResult));
+}
+
+// The query is kept in the fragment, so results can be linked to and come
+// back with the back button, from a site opened straight from disk too.
+function queryOfLocation() {
+  return new URLSearchParams(location.hash.slice(1)).get("q") ?? "";
+}
+
+form.addEventListener("submit", (event) => {
+  event.preventDefault();
+  location.hash = new URLSearchParams({ q: input.value.trim() });
+});
+window.addEventListener("hashchange", () => show(queryOfLocation()));
+
+show(queryOfLocation());
+input.focus();
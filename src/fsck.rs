@@ -0,0 +1,196 @@
+// Consistency checks of an index. `fsck` runs all of them; the quick subset
+// only looks at structures that are cheap to check, for programs that want
+// to validate an index every time they open it.
+//
+// `repair` fixes what they find without indexing the corpus again. The
+// documents with their term counts are what an index holds; the postings,
+// document frequencies, IDFs and the term dictionaries are derived from them
+// and written anew whenever the index is saved. So a repair drops what is
+// wrong in the documents themselves, like empty terms or terms the pruning
+// should have removed, numbers documents sharing an ID anew and saves the
+// index again. Documents a damaged binary index no longer has readable are
+// dropped, and reported, rather than failing the whole index.
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::fxhash::FxHashMap;
+use crate::spelling::SpellingDictionary;
+use crate::{indexer, postings, store, DocId, DocKey, Error, Model};
+
+// Every problem found, as a sentence. Errors that prevent loading the index
+// at all are reported as they happen.
+pub fn check_index(index_path: &str, thorough: bool) -> Result<Vec<String>, Error> {
+    let store = store::open_store(index_path);
+    let mut problems = store.check(thorough)?;
+    let model = store.open_readonly()?;
+    if thorough {
+        check_model(&model, &mut problems);
+    }
+    if let Some(bytes) = store::binary_index_bytes(index_path) {
+        let bytes = bytes
+            .map_err(|err| Error::io(format!("could not read index file {index_path}"), err))?;
+        postings::check_block(bytes, &model, thorough, &mut problems);
+    }
+    Ok(problems)
+}
+
+fn check_model(model: &Model, problems: &mut Vec<String>) {
+    let mut df = FxHashMap::<&str, usize>::default();
+    let mut ids = FxHashMap::<DocId, &Path>::default();
+    for (path, doc) in &model.docs {
+        if let Some(other) = ids.insert(doc.id, path) {
+            problems.push(format!(
+                "{path} and {other} have the same ID {id}",
+                path = path.display(),
+                other = other.display(),
+                id = doc.id
+            ));
+        }
+        for (term, count) in &doc.tf {
+            if term.is_empty() {
+                problems.push(format!("{path} has an empty term", path = path.display()));
+            }
+            if *count == 0 {
+                problems.push(format!(
+                    "{term:?} occurs 0 times in {path}",
+                    path = path.display()
+                ));
+            }
+            *df.entry(term).or_insert(0) += 1;
+        }
+    }
+
+    // Terms the recorded pruning should have removed.
+    let Some(pruning) = &model.manifest.pruning else {
+        return;
+    };
+    let max_doc_freq = pruning
+        .max_doc_freq_pct
+        .map(|pct| pct / 100.0 * model.docs.len() as f64);
+    let mut unpruned = df
+        .iter()
+        .filter(|(term, freq)| {
+            **freq < pruning.min_doc_freq
+                || max_doc_freq.is_some_and(|max| **freq as f64 > max)
+                || term.chars().count() < pruning.min_term_len
+        })
+        .map(|(term, _)| *term)
+        .collect::<Vec<_>>();
+    unpruned.sort();
+    for term in unpruned {
+        problems.push(format!(
+            "{term:?} should have been removed by the pruning recorded in the manifest"
+        ));
+    }
+}
+
+// What `repair_index` found and did.
+pub struct Repair {
+    // The problems of the index before, as `check_index` reports them, or
+    // why it could not be read at all.
+    pub problems: Vec<String>,
+    // Every change, as a sentence.
+    pub repairs: Vec<String>,
+    // The problems of the repaired index, which a repair cannot fix.
+    pub remaining: Vec<String>,
+}
+
+impl Repair {
+    pub fn is_needed(&self) -> bool {
+        !self.problems.is_empty() || !self.repairs.is_empty()
+    }
+}
+
+// Repairs the index at `index_path` and saves it to `output`, which may be
+// the same path, unless `dry_run` only asks what would be repaired. An index
+// without problems is left alone.
+pub fn repair_index(index_path: &str, output: &str, dry_run: bool) -> Result<Repair, Error> {
+    let problems = check_index(index_path, true).unwrap_or_else(|err| vec![err.to_string()]);
+    let mut repairs = Vec::new();
+    let mut model = match store::salvage_binary(index_path) {
+        Some(salvaged) => {
+            let (model, broken) = salvaged?;
+            if let Some(broken) = broken {
+                repairs.push(format!(
+                    "dropped the documents of the index file past where it could no longer be read ({broken}), keeping the {docs} before",
+                    docs = model.docs.len()
+                ));
+            }
+            model
+        }
+        None => store::open_store(index_path).load()?,
+    };
+    repair_model(&mut model, &mut repairs);
+    let mut repair = Repair {
+        problems,
+        repairs,
+        remaining: Vec::new(),
+    };
+    if !repair.is_needed() && index_path == output {
+        return Ok(repair);
+    }
+    repair.repairs.push(
+        "rewrote the index from its documents, rebuilding what its format derives from them, like the postings and document frequencies"
+            .to_string(),
+    );
+    if dry_run {
+        return Ok(repair);
+    }
+    model.manifest.spelling = SpellingDictionary::build(&model.docs);
+    model.save(output)?;
+    repair.remaining = check_index(output, true)?;
+    Ok(repair)
+}
+
+// Drops what `check_model` objects to from the documents.
+fn repair_model(model: &mut Model, repairs: &mut Vec<String>) {
+    let mut paths = model.docs.keys().cloned().collect::<Vec<DocKey>>();
+    paths.sort();
+    let mut ids = HashSet::<DocId>::new();
+    let mut renumbered = Vec::new();
+    for path in &paths {
+        let doc = model.docs.get_mut(path).unwrap();
+        if doc.id != 0 && !ids.insert(doc.id) {
+            renumbered.push((path.clone(), doc.id));
+            doc.id = 0;
+        }
+        let terms = doc.tf.len();
+        doc.tf.retain(|term, count| !term.is_empty() && *count > 0);
+        if doc.tf.len() < terms {
+            repairs.push(format!(
+                "dropped {dropped} empty or never occurring terms of {path}",
+                dropped = terms - doc.tf.len(),
+                path = path.display()
+            ));
+        }
+    }
+    model.assign_doc_ids();
+    for (path, id) in renumbered {
+        repairs.push(format!(
+            "gave {path} the new ID {new}, another document had {id}",
+            path = path.display(),
+            new = model.docs[&path].id
+        ));
+    }
+    if let Some(pruning) = model.manifest.pruning.clone() {
+        let pruned = indexer::prune(&mut model.docs, &pruning);
+        if pruned > 0 {
+            repairs.push(format!(
+                "dropped {pruned} terms the pruning recorded in the manifest should have removed"
+            ));
+        }
+    }
+    // Positions are only kept for the terms the documents have.
+    let mut stray = 0;
+    for doc in model.docs.values_mut() {
+        let positions = doc.positions.len();
+        let tf = &doc.tf;
+        doc.positions.retain(|term, _| tf.contains_key(term));
+        stray += positions - doc.positions.len();
+    }
+    if stray > 0 {
+        repairs.push(format!(
+            "dropped the positions of {stray} terms their documents do not have"
+        ));
+    }
+}
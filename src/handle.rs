@@ -0,0 +1,513 @@
+// A handle on a loaded index for programs embedding the search: it is cheap
+// to clone and can be shared between threads, e.g. the request handlers of a
+// web server. Every search sees a consistent snapshot of the index, and
+// replacing the index does not disturb searches still running on the old one.
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::analyzer::Analyzer;
+use crate::bitset::Bitset;
+use crate::bundle::SourceBundle;
+use crate::cache::{CacheStats, Lru};
+use crate::collector::{Collector, TopDocs};
+use crate::config::IndexConfig;
+use crate::exclude;
+use crate::extract::ExtractOptions;
+use crate::feedback::{self, FEEDBACK_DOCS, FEEDBACK_TERMS};
+use crate::filter::Filter;
+use crate::memory::{self, MemoryUsage};
+use crate::postings::{Postings, TermPattern};
+use crate::query::{Query, QueryLimits};
+use crate::rewrite::QueryRewriter;
+use crate::scoring::{CorpusStats, MinScore, Ranking};
+use crate::{load_model, reload_model, search, store, Error, Model};
+
+#[derive(Clone)]
+pub struct SearchHandle {
+    snapshot: Arc<RwLock<Snapshot>>,
+    cache_sizes: CacheSizes,
+    // Used by searches that do not ask for another.
+    ranking: Ranking,
+    min_score: Option<MinScore>,
+    rewriter: Option<Arc<QueryRewriter>>,
+    // How the text of results is extracted again for their snippets.
+    extract: Arc<ExtractOptions>,
+}
+
+// How many entries the caches of a handle hold. They belong to a snapshot,
+// so replacing the index starts them afresh.
+#[derive(Clone, Copy)]
+pub struct CacheSizes {
+    // Posting lists decoded from a binary index file.
+    pub postings: usize,
+    // Results of recent searches.
+    pub results: usize,
+    // The documents recent filters let through.
+    pub filters: usize,
+}
+
+impl Default for CacheSizes {
+    fn default() -> Self {
+        Self {
+            postings: 1024,
+            results: 256,
+            filters: 64,
+        }
+    }
+}
+
+pub struct CacheMetrics {
+    pub postings: CacheStats,
+    pub results: CacheStats,
+    pub filters: CacheStats,
+}
+
+pub type SearchResults = Vec<(PathBuf, f32)>;
+
+#[derive(Clone)]
+struct Snapshot {
+    model: Arc<Model>,
+    // Collection statistics of the model, so queries do not recompute them.
+    stats: Arc<CorpusStats>,
+    // Postings of the binary index file the model was loaded from, if any.
+    postings: Option<Arc<Postings>>,
+    // Whether documents are hidden or moved, which only the model knows.
+    has_hidden: bool,
+    // Whether documents have positions, which only the model has.
+    has_positions: bool,
+    // Whether documents have titles, which boost the terms in them and
+    // which only the model has.
+    has_titles: bool,
+    // Keyed by the parsed query, the filters and the limit.
+    results: Arc<Mutex<Lru<String, SearchResults>>>,
+    // The ordinals of the documents a filter lets through, keyed by the
+    // parsed filter. Faceted searches repeat the same few filters with
+    // other queries, and a lookup saves checking the metadata of every
+    // candidate again.
+    filters: Arc<Mutex<Lru<String, Arc<Bitset>>>>,
+    // Copies of the source files kept next to the index file, if any.
+    sources: Option<Arc<SourceBundle>>,
+    // Estimated bytes of the model and the postings file, which do not
+    // change while the snapshot lives.
+    index_bytes: usize,
+}
+
+impl Snapshot {
+    fn new(
+        model: Model,
+        postings: Option<Postings>,
+        sources: Option<Arc<SourceBundle>>,
+        cache_sizes: CacheSizes,
+    ) -> Self {
+        let index_bytes =
+            memory::model_bytes(&model) + postings.as_ref().map_or(0, Postings::file_bytes);
+        let stats = CorpusStats::of(&model);
+        Self {
+            index_bytes,
+            has_titles: stats.index().has_titles(),
+            stats: Arc::new(stats),
+            has_hidden: !model.manifest.aliases.is_empty()
+                || model.docs.values().any(exclude::is_excluded),
+            has_positions: model.manifest.config.positions,
+            model: Arc::new(model),
+            postings: postings.map(Arc::new),
+            results: Arc::new(Mutex::new(Lru::new(cache_sizes.results))),
+            filters: Arc::new(Mutex::new(Lru::new(cache_sizes.filters))),
+            sources,
+        }
+    }
+
+    fn collect(
+        &self,
+        query: &Query,
+        filters: &[Filter],
+        ranking: Ranking,
+        collector: &mut dyn Collector,
+    ) {
+        let scorer = ranking.scorer(self.stats.avg_doc_len());
+        let scorer = scorer.as_ref();
+        match &self.postings {
+            // Filters and titles need document metadata, and phrases and
+            // proximity positions, which only the model has.
+            Some(postings)
+                if filters.is_empty()
+                    && !self.has_hidden
+                    && !self.has_positions
+                    && !self.has_titles =>
+            {
+                postings.collect(query, scorer, collector)
+            }
+            _ => {
+                let allowed = self.allowed(filters);
+                search::collect_query(
+                    &self.model,
+                    &self.stats,
+                    scorer,
+                    query,
+                    allowed.as_ref(),
+                    collector,
+                )
+            }
+        }
+    }
+
+    // The documents that pass every filter, from the cache where it has them.
+    fn allowed(&self, filters: &[Filter]) -> Option<Bitset> {
+        let mut matching = filters.iter().map(|filter| self.filter_bitset(filter));
+        let mut allowed = Bitset::clone(&*matching.next()?);
+        for other in matching {
+            allowed.intersect_with(&other);
+        }
+        Some(allowed)
+    }
+
+    fn filter_bitset(&self, filter: &Filter) -> Arc<Bitset> {
+        let key = format!("{filter:?}");
+        if let Some(bitset) = self.filters.lock().unwrap().get(&key) {
+            return bitset;
+        }
+        // Computed outside the lock, searches with other filters go on.
+        let bitset = Arc::new(filter.bitset(&self.model, self.stats.index()));
+        self.filters.lock().unwrap().insert(key, bitset.clone());
+        bitset
+    }
+}
+
+pub struct Stats {
+    pub docs: usize,
+    pub terms: usize,
+    // Sum of the number of distinct terms of every document.
+    pub postings: usize,
+}
+
+// Reuses what did not change of `old`, the index as last read, if given.
+fn load(index_path: &str, old: Option<&Model>, cache_sizes: CacheSizes) -> Result<Snapshot, Error> {
+    let model = match old {
+        Some(old) => reload_model(index_path, old)?,
+        None => load_model(index_path)?,
+    };
+    let postings = store::binary_index_bytes(index_path)
+        .and_then(Result::ok)
+        .and_then(|bytes| Postings::from_bytes(bytes, cache_sizes.postings));
+    let sources = Some(SourceBundle::beside(Path::new(index_path)))
+        .filter(SourceBundle::exists)
+        .map(Arc::new);
+    Ok(Snapshot::new(model, postings, sources, cache_sizes))
+}
+
+impl SearchHandle {
+    pub fn new(model: Model) -> Self {
+        let cache_sizes = CacheSizes::default();
+        Self::with_snapshot(Snapshot::new(model, None, None, cache_sizes), cache_sizes)
+    }
+
+    fn with_snapshot(snapshot: Snapshot, cache_sizes: CacheSizes) -> Self {
+        Self {
+            snapshot: Arc::new(RwLock::new(snapshot)),
+            cache_sizes,
+            ranking: Ranking::default(),
+            min_score: None,
+            rewriter: None,
+            extract: Arc::default(),
+        }
+    }
+
+    // The handle ranking searches with `ranking` unless they ask for another.
+    pub fn with_ranking(self, ranking: Ranking) -> Self {
+        Self { ranking, ..self }
+    }
+
+    pub fn ranking(&self) -> Ranking {
+        self.ranking
+    }
+
+    // The cutoff of the results of searches that do not ask for another.
+    // Applying it is up to the caller, as it is for paging.
+    pub fn with_min_score(self, min_score: Option<MinScore>) -> Self {
+        Self { min_score, ..self }
+    }
+
+    pub fn min_score(&self) -> Option<MinScore> {
+        self.min_score
+    }
+
+    // The handle rewriting queries with `rewriter` before they are parsed.
+    pub fn with_rewriter(self, rewriter: Option<Arc<QueryRewriter>>) -> Self {
+        Self { rewriter, ..self }
+    }
+
+    // The handle extracting the text of results with `extract`, e.g. without
+    // a cache for searches that should leave nothing behind.
+    pub fn with_extract_options(self, extract: ExtractOptions) -> Self {
+        Self {
+            extract: Arc::new(extract),
+            ..self
+        }
+    }
+
+    pub fn extract_options(&self) -> &ExtractOptions {
+        &self.extract
+    }
+
+    // The query as the rewrites of the handle make it, for parsing.
+    pub fn rewrite<'a>(&self, query: &'a str) -> Cow<'a, str> {
+        match &self.rewriter {
+            Some(rewriter) => rewriter.rewrite(query),
+            None => Cow::Borrowed(query),
+        }
+    }
+
+    pub fn open(index_path: &str, cache_sizes: CacheSizes) -> Result<Self, Error> {
+        Ok(Self::with_snapshot(
+            load(index_path, None, cache_sizes)?,
+            cache_sizes,
+        ))
+    }
+
+    // Reads the index at `index_path` again and swaps it in for every clone
+    // of this handle. Searches already running finish on the old one.
+    pub fn reload(&self, index_path: &str) -> Result<(), Error> {
+        self.reload_with(index_path, |_| ())
+    }
+
+    // `reload`, running `warm` on a handle of the new index before it is
+    // swapped in, e.g. to search it so its caches are filled by then.
+    // Searches go to the old index until `warm` returns.
+    pub fn reload_with<T>(
+        &self,
+        index_path: &str,
+        warm: impl FnOnce(&SearchHandle) -> T,
+    ) -> Result<T, Error> {
+        let snapshot = load(index_path, Some(&self.snapshot()), self.cache_sizes)?;
+        let staged = Self {
+            snapshot: Arc::new(RwLock::new(snapshot)),
+            ..self.clone()
+        };
+        let warmed = warm(&staged);
+        *self.snapshot.write().unwrap() = staged.snapshot.read().unwrap().clone();
+        Ok(warmed)
+    }
+
+    // The current index. It stays valid while held, even across `replace`.
+    pub fn snapshot(&self) -> Arc<Model> {
+        self.snapshot.read().unwrap().model.clone()
+    }
+
+    // Swaps in a new index for every clone of this handle. It keeps the
+    // bundled source files of the old one.
+    pub fn replace(&self, model: Model) {
+        let mut snapshot = self.snapshot.write().unwrap();
+        *snapshot = Snapshot::new(model, None, snapshot.sources.clone(), self.cache_sizes);
+    }
+
+    // Changes a copy of the current index and swaps it in, like `replace`.
+    // Changes run one at a time, each costing a copy of the whole index.
+    pub fn update(&self, change: impl FnOnce(&mut Model)) {
+        let mut snapshot = self.snapshot.write().unwrap();
+        let mut model = Model::clone(&snapshot.model);
+        change(&mut model);
+        *snapshot = Snapshot::new(model, None, snapshot.sources.clone(), self.cache_sizes);
+    }
+
+    // The copies of the source files `index --bundle-sources` kept next to
+    // the index file, for documents whose files are not there.
+    pub fn sources(&self) -> Option<Arc<SourceBundle>> {
+        self.snapshot.read().unwrap().sources.clone()
+    }
+
+    // The analyzer of the current index, for analyzing queries against it.
+    pub fn analyzer(&self) -> Analyzer {
+        self.snapshot().analyzer()
+    }
+
+    // The analyzer of queries, which with `keep_stopwords` searches for the
+    // stopwords too. That fails for indexes that left them out, where such
+    // queries would find nothing by them.
+    pub fn query_analyzer(&self, keep_stopwords: bool) -> Result<Analyzer, Error> {
+        let model = self.snapshot();
+        let config = &model.manifest.config;
+        if !keep_stopwords {
+            return Ok(model.analyzer());
+        }
+        if !config.stopwords.is_empty() && !config.index_stopwords {
+            return Err(Error::invalid(
+                "the index left out its stopwords, rebuild it with --index-stopwords to search for them",
+            ));
+        }
+        Ok(model.analyzer().keeping_stopwords())
+    }
+
+    // The analysis settings the current index was built with.
+    pub fn config(&self) -> IndexConfig {
+        self.snapshot().manifest.config.clone()
+    }
+
+    // The best `limit` documents for the query, best first.
+    pub fn search(&self, query: &Query, filters: &[Filter], limit: usize) -> SearchResults {
+        self.search_ranked(query, filters, self.ranking, limit)
+    }
+
+    // `search` with another ranking function than that of the handle.
+    pub fn search_ranked(
+        &self,
+        query: &Query,
+        filters: &[Filter],
+        ranking: Ranking,
+        limit: usize,
+    ) -> SearchResults {
+        let snapshot = self.snapshot.read().unwrap().clone();
+        let key = format!("{query:?} {filters:?} {ranking:?} {limit}");
+        if let Some(results) = snapshot.results.lock().unwrap().get(&key) {
+            return results;
+        }
+        let mut top = TopDocs::new(limit);
+        snapshot.collect(query, filters, ranking, &mut top);
+        let results = top.into_sorted();
+        snapshot
+            .results
+            .lock()
+            .unwrap()
+            .insert(key, results.clone());
+        results
+    }
+
+    // Hands every match of the query to the collector, for results other
+    // than a ranked list. Unlike `search` these are not cached.
+    pub fn collect(&self, query: &Query, filters: &[Filter], collector: &mut dyn Collector) {
+        let snapshot = self.snapshot.read().unwrap().clone();
+        snapshot.collect(query, filters, self.ranking, collector);
+    }
+
+    // The query with its wildcards and fuzzy terms replaced by the terms of
+    // the current index they match.
+    pub fn expand(&self, query: Query, limits: &QueryLimits) -> Result<Query, String> {
+        let stats = self.snapshot.read().unwrap().stats.clone();
+        query.expand(&stats, limits)
+    }
+
+    // The query with the terms that stand out in its best results added,
+    // see `feedback`.
+    pub fn expand_feedback(&self, query: Query, filters: &[Filter]) -> Query {
+        let snapshot = self.snapshot.read().unwrap().clone();
+        let hits = self.search(&query, filters, FEEDBACK_DOCS);
+        let docs = hits
+            .iter()
+            .map(|(path, _)| path.as_path())
+            .collect::<Vec<_>>();
+        let terms = feedback::feedback_terms(
+            &snapshot.model,
+            &snapshot.stats,
+            &docs,
+            &query,
+            FEEDBACK_TERMS,
+        );
+        feedback::expand(query, terms)
+    }
+
+    // Hits and misses of the caches of the current snapshot.
+    pub fn cache_metrics(&self) -> CacheMetrics {
+        let snapshot = self.snapshot.read().unwrap();
+        let results = snapshot.results.lock().unwrap().stats();
+        let filters = snapshot.filters.lock().unwrap().stats();
+        CacheMetrics {
+            postings: snapshot
+                .postings
+                .as_ref()
+                .map_or_else(CacheStats::default, |postings| postings.cache_stats()),
+            results,
+            filters,
+        }
+    }
+
+    // Estimated memory of the current snapshot and its caches.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let snapshot = self.snapshot.read().unwrap();
+        let result_cache = snapshot.results.lock().unwrap().bytes(|key, results| {
+            memory::string_bytes(key)
+                + results
+                    .iter()
+                    .map(|(path, _)| size_of::<(PathBuf, f32)>() + path.as_os_str().len())
+                    .sum::<usize>()
+        });
+        let filter_cache = snapshot
+            .filters
+            .lock()
+            .unwrap()
+            .bytes(|key, bitset| memory::string_bytes(key) + bitset.bytes());
+        MemoryUsage {
+            index: snapshot.index_bytes,
+            postings_cache: snapshot
+                .postings
+                .as_ref()
+                .map_or(0, |postings| postings.cache_bytes()),
+            result_cache,
+            filter_cache,
+        }
+    }
+
+    // Empties the caches of the current snapshot, to give their memory back.
+    // They fill again with the searches that follow.
+    pub fn evict_caches(&self) {
+        let snapshot = self.snapshot.read().unwrap();
+        snapshot.results.lock().unwrap().clear();
+        snapshot.filters.lock().unwrap().clear();
+        if let Some(postings) = &snapshot.postings {
+            postings.clear_cache();
+        }
+    }
+
+    // The query with its words that are not in the index's spelling
+    // dictionary replaced by the closest that are, if any word has one.
+    pub fn did_you_mean(&self, query: &str) -> Option<String> {
+        self.snapshot()
+            .manifest
+            .spelling
+            .did_you_mean(query, &self.analyzer())
+    }
+
+    // Completions of the last word of `prefix`: index terms starting with it,
+    // the most common first, with the number of documents they appear in.
+    // They are shown as people write them rather than case folded.
+    pub fn suggest(&self, prefix: &str, limit: usize) -> Vec<(String, usize)> {
+        let analyzer = self.analyzer();
+        let Some(prefix) = analyzer.terms(prefix).pop() else {
+            return Vec::new();
+        };
+        let snapshot = self.snapshot.read().unwrap().clone();
+        let pattern = TermPattern::Prefix(&prefix);
+        let mut terms = match &snapshot.postings {
+            Some(postings) => postings.expand(&pattern, usize::MAX),
+            None => snapshot.stats.expand_counted(&pattern, usize::MAX),
+        };
+        terms.sort_by(|(term_a, a), (term_b, b)| b.cmp(a).then(term_a.cmp(term_b)));
+        terms.truncate(limit);
+        terms
+            .into_iter()
+            .map(|(term, docs)| (analyzer.display_term(&term), docs))
+            .collect()
+    }
+
+    // Distinct terms of the current snapshot, from its inverted index rather
+    // than its documents.
+    pub fn dictionary_size(&self) -> usize {
+        self.snapshot.read().unwrap().stats.index().term_count()
+    }
+
+    pub fn stats(&self) -> Stats {
+        let model = self.snapshot();
+        let mut terms = HashSet::new();
+        let mut postings = 0;
+        for doc in model.docs.values() {
+            postings += doc.tf.len();
+            terms.extend(doc.tf.keys());
+        }
+        Stats {
+            docs: model.docs.len(),
+            terms: terms.len(),
+            postings,
+        }
+    }
+}
@@ -0,0 +1,221 @@
+// The health of the served index over time, so that it degrading shows
+// before searches slow down: serve samples the size of the index, how its
+// file is laid out and how well its caches serve every `--health-secs`, and
+// keeps the last `KEPT_SAMPLES` samples for GET /api/health/history. The
+// latest is also what /metrics reports, in the text format of Prometheus.
+//
+// Documents that are gone but still take up room count as deleted: the
+// records of a binary index replaced by a later segment, until it is
+// rewritten, and hidden documents, until it is rebuilt.
+use std::collections::VecDeque;
+use std::fs;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::Serialize;
+use serde_json::json;
+
+use tinysearch::cache::CacheStats;
+use tinysearch::exclude::is_excluded;
+use tinysearch::handle::SearchHandle;
+use tinysearch::locale;
+use tinysearch::store::{self, SegmentStats};
+
+pub const DEFAULT_HEALTH_INTERVAL: Duration = Duration::from_secs(60);
+// A day of samples at the default interval.
+const KEPT_SAMPLES: usize = 24 * 60;
+
+#[derive(Clone, Serialize)]
+struct CacheSample {
+    hits: u64,
+    misses: u64,
+    // None before the first lookup.
+    hit_rate: Option<f64>,
+}
+
+impl From<CacheStats> for CacheSample {
+    fn from(stats: CacheStats) -> Self {
+        Self {
+            hits: stats.hits,
+            misses: stats.misses,
+            hit_rate: (stats.hits + stats.misses > 0).then(|| stats.hit_rate()),
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct Sample {
+    time: String,
+    docs: usize,
+    // Distinct terms.
+    terms: usize,
+    // None for indexes that are not binary, which are stored whole.
+    segments: Option<usize>,
+    deleted_docs: usize,
+    // Of the documents the index holds, deleted or not.
+    deleted_ratio: f64,
+    postings_cache: CacheSample,
+    result_cache: CacheSample,
+    filter_cache: CacheSample,
+}
+
+// The index file as it was when its segments were counted, by size and
+// modification time, so they are only counted again once it changed.
+type FileKey = (u64, Option<SystemTime>);
+
+#[derive(Default)]
+struct History {
+    samples: VecDeque<Sample>,
+    recorded_at: Option<Instant>,
+    layout: Option<(FileKey, SegmentStats)>,
+}
+
+pub struct HealthHistory {
+    interval: Duration,
+    history: Mutex<History>,
+}
+
+impl HealthHistory {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            history: Mutex::new(History::default()),
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    // Samples the index at `index_path` if the interval passed since the
+    // last sample.
+    pub fn record_if_due(&self, index_path: &str, handle: &SearchHandle) {
+        let mut history = self.history.lock().unwrap();
+        if history
+            .recorded_at
+            .is_some_and(|at| at.elapsed() < self.interval)
+        {
+            return;
+        }
+        history.recorded_at = Some(Instant::now());
+        let layout = segment_stats(index_path, &mut history.layout);
+        let model = handle.snapshot();
+        let hidden = model.docs.values().filter(|doc| is_excluded(doc)).count();
+        let (held, dead) = match layout {
+            Some(layout) => (layout.records, layout.records - layout.live_records),
+            None => (model.docs.len(), 0),
+        };
+        let deleted_docs = dead + hidden;
+        let cache = handle.cache_metrics();
+        let sample = Sample {
+            time: locale::now_rfc3339(),
+            docs: model.docs.len(),
+            terms: handle.dictionary_size(),
+            segments: layout.map(|layout| layout.segments),
+            deleted_docs,
+            deleted_ratio: if held == 0 {
+                0.0
+            } else {
+                deleted_docs as f64 / held as f64
+            },
+            postings_cache: cache.postings.into(),
+            result_cache: cache.results.into(),
+            filter_cache: cache.filters.into(),
+        };
+        if history.samples.len() == KEPT_SAMPLES {
+            history.samples.pop_front();
+        }
+        history.samples.push_back(sample);
+    }
+
+    // GET /api/health/history: the kept samples, oldest first.
+    pub fn to_json(&self) -> serde_json::Value {
+        let history = self.history.lock().unwrap();
+        json!({
+            "interval_secs": self.interval.as_secs(),
+            "samples": history.samples,
+        })
+    }
+
+    // GET /metrics: the latest sample as Prometheus metrics.
+    #[cfg(feature = "metrics")]
+    pub fn to_prometheus(&self) -> String {
+        use std::fmt::Write;
+
+        let history = self.history.lock().unwrap();
+        let mut text = String::new();
+        let Some(sample) = history.samples.back() else {
+            return "# no index is served\n".to_string();
+        };
+        let mut gauge = |name: &str, help: &str, value: f64| {
+            let _ = writeln!(text, "# HELP tinysearch_{name} {help}");
+            let _ = writeln!(text, "# TYPE tinysearch_{name} gauge");
+            let _ = writeln!(text, "tinysearch_{name} {value}");
+        };
+        gauge("index_docs", "Documents in the index.", sample.docs as f64);
+        gauge(
+            "index_terms",
+            "Distinct terms of the index.",
+            sample.terms as f64,
+        );
+        if let Some(segments) = sample.segments {
+            gauge(
+                "index_segments",
+                "Segments of the binary index file.",
+                segments as f64,
+            );
+        }
+        gauge(
+            "index_deleted_docs",
+            "Replaced and hidden documents the index still holds.",
+            sample.deleted_docs as f64,
+        );
+        gauge(
+            "index_deleted_ratio",
+            "Share of the documents the index holds that are deleted.",
+            sample.deleted_ratio,
+        );
+        let caches = [
+            ("postings", &sample.postings_cache),
+            ("results", &sample.result_cache),
+            ("filters", &sample.filter_cache),
+        ];
+        for (kind, help) in [("hits", "Cache hits"), ("misses", "Cache misses")] {
+            let _ = writeln!(
+                text,
+                "# HELP tinysearch_cache_{kind}_total {help} since the index was loaded."
+            );
+            let _ = writeln!(text, "# TYPE tinysearch_cache_{kind}_total counter");
+            for (cache, stats) in caches {
+                let count = if kind == "hits" {
+                    stats.hits
+                } else {
+                    stats.misses
+                };
+                let _ = writeln!(
+                    text,
+                    "tinysearch_cache_{kind}_total{{cache=\"{cache}\"}} {count}"
+                );
+            }
+        }
+        text
+    }
+}
+
+// The segments of the index file, counted again only once the file changed.
+// None for indexes that are not binary or cannot be read.
+fn segment_stats(
+    index_path: &str,
+    counted: &mut Option<(FileKey, SegmentStats)>,
+) -> Option<SegmentStats> {
+    let metadata = fs::metadata(index_path).ok()?;
+    let key = (metadata.len(), metadata.modified().ok());
+    if let Some((counted_key, stats)) = counted {
+        if *counted_key == key {
+            return Some(*stats);
+        }
+    }
+    let stats = store::segment_stats(index_path)?.ok()?;
+    *counted = Some((key, stats));
+    Some(stats)
+}
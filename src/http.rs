@@ -0,0 +1,204 @@
+// The bits of HTTP that tiny_http leaves to the application: query strings,
+// content negotiation, cross-origin requests, and the one request the command
+// line sends a server.
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use tiny_http::{Header, Request};
+
+// Splits a request URL into its path and its decoded query parameters, in
+// the order they appear. A parameter may repeat, like `filter`.
+pub fn split_url(url: &str) -> (&str, Vec<(String, String)>) {
+    let Some((path, query)) = url.split_once('?') else {
+        return (url, Vec::new());
+    };
+    let params = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(name), percent_decode(value))
+        })
+        .collect();
+    (path, params)
+}
+
+fn hex_value(digit: u8) -> Option<u8> {
+    (digit as char).to_digit(16).map(|value| value as u8)
+}
+
+// Decodes `%XX` escapes and the `+` that forms use for spaces. Malformed
+// escapes are kept as they are.
+pub fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                match (hex_value(bytes[i + 1]), hex_value(bytes[i + 2])) {
+                    (Some(high), Some(low)) => {
+                        out.push(high << 4 | low);
+                        i += 2;
+                    }
+                    _ => out.push(b'%'),
+                }
+            }
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+// Escapes everything but the unreserved characters of RFC 3986, for
+// building links with query parameters.
+pub fn percent_encode(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                out.push(byte as char)
+            }
+            _ => out.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    out
+}
+
+// Quality the Accept header gives to a media type, counting `type/*` and
+// `*/*` ranges. Without the header anything is acceptable.
+fn quality(accept: &str, media_type: &str) -> f32 {
+    let (kind, _) = media_type.split_once('/').unwrap_or((media_type, ""));
+    let mut best: Option<(u8, f32)> = None;
+    for range in accept.split(',') {
+        let mut parts = range.split(';').map(str::trim);
+        let name = parts.next().unwrap_or_default().to_ascii_lowercase();
+        let q = parts
+            .find_map(|param| param.strip_prefix("q="))
+            .and_then(|q| q.parse().ok())
+            .unwrap_or(1.0);
+        // The most specific matching range decides.
+        let specificity = if name == media_type {
+            2
+        } else if name == format!("{kind}/*") {
+            1
+        } else if name == "*/*" {
+            0
+        } else {
+            continue;
+        };
+        if best.is_none_or(|(seen, _)| specificity > seen) {
+            best = Some((specificity, q));
+        }
+    }
+    best.map_or(0.0, |(_, q)| q)
+}
+
+pub fn header<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str())
+}
+
+// Whether the client would rather have JSON than HTML. Browsers ask for
+// HTML and clients that ask for nothing in particular get it too.
+pub fn prefers_json(request: &Request) -> bool {
+    let Some(accept) = header(request, "Accept") else {
+        return false;
+    };
+    quality(accept, "application/json") > quality(accept, "text/html")
+}
+
+// The origins whose pages may call the server, e.g. another web app using
+// tinySearch as its search backend. `*` allows every origin.
+pub struct Cors {
+    origins: Vec<String>,
+}
+
+impl Cors {
+    // Comma separated origins like `https://app.example.com`.
+    pub fn parse(value: &str) -> Self {
+        let origins = value
+            .split(',')
+            .map(|origin| origin.trim().trim_end_matches('/').to_string())
+            .filter(|origin| !origin.is_empty())
+            .collect();
+        Self { origins }
+    }
+
+    // The headers that let the page at the origin of the request read the
+    // response, with the headers holding the total and the result set.
+    pub fn headers(&self, request: &Request) -> Vec<Header> {
+        let Some(origin) = header(request, "Origin") else {
+            return Vec::new();
+        };
+        let allowed = if self.origins.iter().any(|allowed| allowed == "*") {
+            "*"
+        } else if self.origins.iter().any(|allowed| allowed == origin) {
+            origin
+        } else {
+            return Vec::new();
+        };
+        let mut headers = vec![
+            Header::from_bytes("Access-Control-Allow-Origin", allowed).unwrap(),
+            Header::from_bytes(
+                "Access-Control-Expose-Headers",
+                "X-Total-Count, X-Result-Set, X-Request-Id, API-Version, Deprecation, Sunset, Link",
+            )
+            .unwrap(),
+        ];
+        if allowed != "*" {
+            headers.push(Header::from_bytes("Vary", "Origin").unwrap());
+        }
+        headers
+    }
+
+    // Those of the answer to a preflight OPTIONS request, which browsers send
+    // before a POST with a JSON body.
+    pub fn preflight_headers(&self, request: &Request) -> Vec<Header> {
+        let mut headers = self.headers(request);
+        if !headers.is_empty() {
+            headers.extend([
+                Header::from_bytes("Access-Control-Allow-Methods", "GET, POST").unwrap(),
+                Header::from_bytes(
+                    "Access-Control-Allow-Headers",
+                    "Content-Type, X-Query-Logging, API-Version",
+                )
+                .unwrap(),
+                Header::from_bytes("Access-Control-Max-Age", "600").unwrap(),
+            ]);
+        }
+        headers
+    }
+}
+
+// Asks the server at `address`, e.g. 127.0.0.1:8888, to read its index
+// again. Returns the body of the answer, or why there was none or it was not
+// a success.
+pub fn request_reload(address: &str) -> Result<String, String> {
+    let mut stream =
+        TcpStream::connect(address).map_err(|err| format!("could not connect: {err}"))?;
+    stream
+        .set_read_timeout(Some(Duration::from_secs(60)))
+        .map_err(|err| err.to_string())?;
+    write!(
+        stream,
+        "POST /api/v1/reload HTTP/1.0\r\nHost: {address}\r\nContent-Length: 0\r\n\r\n"
+    )
+    .map_err(|err| format!("could not send request: {err}"))?;
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .map_err(|err| format!("could not read response: {err}"))?;
+    let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+    let status = head.split_whitespace().nth(1).unwrap_or_default();
+    if status != "200" {
+        return Err(format!("server answered {status}: {body}"));
+    }
+    Ok(body.to_string())
+}
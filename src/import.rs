@@ -0,0 +1,187 @@
+// Indexes built from the document exports of other search engines instead of
+// from files, e.g. `elasticdump` output or a Meilisearch dump. Every document
+// becomes one indexed document named by its id, its text taken from one
+// field and its other scalar fields, and arrays of them, kept as metadata.
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use serde_json::{Map, Value};
+
+use crate::writer::IndexWriter;
+use crate::{Error, MetaValue, Metadata};
+
+#[derive(Clone, Copy)]
+pub enum ImportFormat {
+    // One JSON object per line.
+    Ndjson,
+}
+
+impl ImportFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "ndjson" | "jsonl" => Some(Self::Ndjson),
+            _ => None,
+        }
+    }
+}
+
+// Fields are named by dotted paths, so `_source.body` reaches into the
+// envelope Elasticsearch wraps documents in.
+pub struct ImportOptions {
+    pub format: ImportFormat,
+    pub text_field: String,
+    // Without one, documents are named `<file>#<line>`.
+    pub id_field: Option<String>,
+}
+
+// Counts of an import, for the summary printed after it.
+#[derive(Default)]
+pub struct ImportStats {
+    pub imported: usize,
+    // Lines that are not documents or lack the text field.
+    pub skipped: usize,
+    // Documents whose id an earlier one already had; the last one wins.
+    pub duplicates: usize,
+}
+
+pub fn import_file(
+    export_path: &Path,
+    writer: &mut IndexWriter,
+    options: &ImportOptions,
+) -> Result<ImportStats, Error> {
+    let file = File::open(export_path).map_err(|err| {
+        Error::io(
+            format!("could not open export {path}", path = export_path.display()),
+            err,
+        )
+    })?;
+    match options.format {
+        ImportFormat::Ndjson => import_ndjson(export_path, BufReader::new(file), writer, options),
+    }
+}
+
+fn import_ndjson(
+    export_path: &Path,
+    reader: impl BufRead,
+    writer: &mut IndexWriter,
+    options: &ImportOptions,
+) -> Result<ImportStats, Error> {
+    let mut stats = ImportStats::default();
+    let mut seen = HashSet::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line_number = i + 1;
+        let line = line.map_err(|err| {
+            Error::io(
+                format!(
+                    "could not read line {line_number} of {path}",
+                    path = export_path.display()
+                ),
+                err,
+            )
+        })?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match import_document(&line, options) {
+            Ok((id, text, meta)) => {
+                let doc_path = id.map_or_else(
+                    || PathBuf::from(format!("{}#{line_number}", export_path.display())),
+                    PathBuf::from,
+                );
+                if !seen.insert(doc_path.clone()) {
+                    eprintln!(
+                        "WARNING: line {line_number} of {path} repeats the id {doc_path:?}, replacing the earlier document",
+                        path = export_path.display()
+                    );
+                    stats.duplicates += 1;
+                }
+                writer.add(doc_path, &text, meta);
+                stats.imported += 1;
+            }
+            Err(reason) => {
+                eprintln!(
+                    "ERROR: skipping line {line_number} of {path}: {reason}",
+                    path = export_path.display()
+                );
+                stats.skipped += 1;
+            }
+        }
+    }
+    Ok(stats)
+}
+
+// The id, text and metadata of the document on one line of an export.
+fn import_document(
+    line: &str,
+    options: &ImportOptions,
+) -> Result<(Option<String>, String, Metadata), String> {
+    let value = serde_json::from_str::<Value>(line).map_err(|err| err.to_string())?;
+    let Value::Object(object) = &value else {
+        return Err("not a JSON object".to_string());
+    };
+    let text = match field(&value, &options.text_field) {
+        Some(Value::String(text)) => text.clone(),
+        // Multi-valued fields, e.g. paragraphs.
+        Some(Value::Array(items)) => items
+            .iter()
+            .filter_map(scalar_text)
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Some(_) => return Err(format!("field {} is not text", options.text_field)),
+        None => return Err(format!("no field {}", options.text_field)),
+    };
+    let id = match &options.id_field {
+        Some(id_field) => {
+            let id = field(&value, id_field).and_then(scalar_text);
+            Some(id.ok_or_else(|| format!("no field {id_field}"))?)
+        }
+        None => None,
+    };
+    // The metadata comes from the object the text is in, so it is the
+    // document's own fields rather than those of an envelope.
+    let document = match options.text_field.rsplit_once('.') {
+        Some((parent, _)) => match field(&value, parent) {
+            Some(Value::Object(document)) => document,
+            _ => object,
+        },
+        None => object,
+    };
+    Ok((id, text, metadata(document, options)))
+}
+
+fn field<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(value, |value, key| value.get(key))
+}
+
+fn scalar_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+// Scalars, and arrays of scalars such as tags, which stay multi-valued.
+fn meta_value(value: &Value) -> Option<MetaValue> {
+    match value {
+        Value::Array(items) => {
+            let values = items.iter().filter_map(scalar_text).collect::<Vec<_>>();
+            (!values.is_empty()).then_some(MetaValue::Many(values))
+        }
+        _ => scalar_text(value).map(MetaValue::One),
+    }
+}
+
+fn metadata(document: &Map<String, Value>, options: &ImportOptions) -> Metadata {
+    let last = |path: &str| path.rsplit('.').next().unwrap_or(path).to_string();
+    let text_key = last(&options.text_field);
+    let id_key = options.id_field.as_deref().map(last);
+    document
+        .iter()
+        .filter(|(key, _)| **key != text_key && Some(*key) != id_key.as_ref())
+        .filter_map(|(key, value)| Some((key.clone(), meta_value(value)?)))
+        .collect()
+}
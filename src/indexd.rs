@@ -0,0 +1,442 @@
+// `indexd`: index building as a service, apart from the servers answering
+// searches. A job is a run of the index subcommand submitted over HTTP with
+// its arguments; jobs run one at a time in the order they came in, and a job
+// can have a search server reload the index it wrote.
+//
+//   POST /jobs {"args": [...], "reload": <address>}   queue a job
+//   GET /jobs                                          every job, newest first
+//   GET /jobs/<id>                                     status and progress of one
+//   DELETE /jobs/<id>, POST /jobs/<id>/cancel           cancel it
+//
+// Paths in the arguments are relative to the working directory of indexd,
+// and what a job writes, its index, report and extraction cache, has to be
+// inside it. Jobs cannot load --plugins, which run programs. Any page a
+// browser shows could send requests to indexd, so it only takes jobs posted
+// as application/json and refuses changes from pages of another origin.
+use std::any::Any;
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Component, Path};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+use tinysearch::indexer::{Progress, Verbosity};
+use tinysearch::{locale, Error, ErrorCode};
+use tracing::{info, warn};
+
+use crate::{api, http, normalize_args, parse_index_args, read_body, run_index, IndexCommand};
+
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:8890";
+
+// How many finished jobs are listed, the oldest dropped first.
+const KEPT_FINISHED_JOBS: usize = 100;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Status {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl Status {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Queued => "queued",
+            Self::Running => "running",
+            Self::Done => "done",
+            Self::Failed => "failed",
+            Self::Cancelled => "cancelled",
+        }
+    }
+
+    fn is_finished(self) -> bool {
+        matches!(self, Self::Done | Self::Failed | Self::Cancelled)
+    }
+}
+
+struct Job {
+    id: u64,
+    args: Vec<String>,
+    // Server to have reload the index once the job wrote it.
+    reload: Option<String>,
+    // Taken by the runner when the job starts.
+    command: Option<IndexCommand>,
+    status: Status,
+    progress: Arc<Progress>,
+    submitted: String,
+    started: Option<String>,
+    finished: Option<String>,
+    // Why the job failed.
+    error: Option<(ErrorCode, String)>,
+}
+
+impl Job {
+    fn to_json(&self) -> Value {
+        json!({
+            "id": self.id,
+            "args": self.args,
+            "reload": self.reload,
+            "status": self.status.name(),
+            "progress": {
+                "total": self.progress.total(),
+                "done": self.progress.done(),
+                "failed": self.progress.failed(),
+            },
+            "submitted": self.submitted,
+            "started": self.started,
+            "finished": self.finished,
+            "error": self.error.as_ref().map(|(code, message)| {
+                json!({"code": code.as_str(), "message": message})
+            }),
+        })
+    }
+}
+
+#[derive(Default)]
+struct Queue {
+    next_id: u64,
+    // Oldest first.
+    jobs: VecDeque<Job>,
+}
+
+impl Queue {
+    fn job(&mut self, id: u64) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|job| job.id == id)
+    }
+
+    fn drop_old_finished(&mut self) {
+        let finished = self
+            .jobs
+            .iter()
+            .filter(|job| job.status.is_finished())
+            .count();
+        let mut excess = finished.saturating_sub(KEPT_FINISHED_JOBS);
+        self.jobs.retain(|job| {
+            let dropped = excess > 0 && job.status.is_finished();
+            if dropped {
+                excess -= 1;
+            }
+            !dropped
+        });
+    }
+}
+
+// The jobs, shared by the runner and the thread answering requests.
+#[derive(Default)]
+struct Jobs {
+    queue: Mutex<Queue>,
+    // Signalled when a job is queued.
+    queued: Condvar,
+}
+
+// Runs the queued jobs one after the other, forever.
+fn run_jobs(jobs: &Jobs) {
+    loop {
+        let (id, command, reload) = {
+            let mut queue = jobs.queue.lock().unwrap();
+            let job = loop {
+                let next = queue
+                    .jobs
+                    .iter_mut()
+                    .find(|job| job.status == Status::Queued);
+                match next {
+                    Some(job) => break job,
+                    None => queue = jobs.queued.wait(queue).unwrap(),
+                }
+            };
+            job.status = Status::Running;
+            job.started = Some(locale::now_rfc3339());
+            let command = job.command.take().expect("a queued job has its command");
+            (job.id, command, job.reload.clone())
+        };
+        info!(job = id, "started indexing job");
+        // A job that panics fails by itself, the jobs after it still run.
+        let result = match panic::catch_unwind(AssertUnwindSafe(|| run_index(command))) {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(Error::Cancelled)) => Err((Status::Cancelled, None)),
+            Ok(Err(err)) => Err((Status::Failed, Some((err.code(), err.to_string())))),
+            Err(panic) => {
+                let message = format!("indexing panicked: {}", panic_message(&*panic));
+                Err((Status::Failed, Some((ErrorCode::Internal, message))))
+            }
+        };
+        let result = result.and_then(|()| match &reload {
+            Some(address) => http::request_reload(address).map(|_| ()).map_err(|err| {
+                let message = format!(
+                    "the index was written, but the server at {address} could not reload it: {err}"
+                );
+                (Status::Failed, Some((ErrorCode::Http, message)))
+            }),
+            None => Ok(()),
+        });
+        let mut queue = jobs.queue.lock().unwrap();
+        let Some(job) = queue.job(id) else {
+            continue;
+        };
+        job.finished = Some(locale::now_rfc3339());
+        match result {
+            Ok(()) => {
+                job.status = Status::Done;
+                info!(job = id, "finished indexing job");
+            }
+            Err((status, error)) => {
+                job.status = status;
+                if let Some((code, message)) = &error {
+                    warn!(job = id, code = %code, error = %message, "indexing job failed");
+                }
+                job.error = error;
+            }
+        }
+        queue.drop_old_finished();
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("no message")
+}
+
+fn answer(request: Request, status: u16, payload: &Value) {
+    let header = Header::from_bytes("Content-Type", "application/json; charset=utf-8").unwrap();
+    let response = Response::from_string(payload.to_string())
+        .with_status_code(status)
+        .with_header(header);
+    if let Err(err) = request.respond(response) {
+        eprintln!("ERROR: could not answer request: {err}");
+    }
+}
+
+// The job of the body of POST /jobs, its arguments checked as those of the
+// index subcommand.
+fn job_of(body: &str, id: u64) -> Result<Job, Value> {
+    let body: Value = serde_json::from_str(body).map_err(|err| {
+        api::error(
+            ErrorCode::InvalidBody,
+            format!("the body is not JSON: {err}"),
+        )
+    })?;
+    let args = body["args"]
+        .as_array()
+        .and_then(|args| {
+            args.iter()
+                .map(|arg| arg.as_str().map(str::to_string))
+                .collect::<Option<Vec<_>>>()
+        })
+        .ok_or_else(|| {
+            api::error(
+                ErrorCode::InvalidBody,
+                "args must be the arguments of the index subcommand, as strings",
+            )
+        })?;
+    let reload = match &body["reload"] {
+        Value::Null => None,
+        Value::String(address) => Some(address.clone()),
+        _ => {
+            return Err(api::error(
+                ErrorCode::InvalidBody,
+                "reload must be the address of a server",
+            ))
+        }
+    };
+    let normalized = normalize_args(args.iter().cloned());
+    if normalized.iter().any(|arg| arg == "--plugins") {
+        return Err(api::error(
+            ErrorCode::InvalidArguments,
+            "jobs cannot load --plugins, which run programs",
+        ));
+    }
+    let mut command = parse_index_args("tinySearch", normalized.into_iter()).map_err(|()| {
+        api::error(
+            ErrorCode::InvalidArguments,
+            "the arguments are not those of the index subcommand, the log of indexd says why",
+        )
+    })?;
+    let outputs = [
+        ("--output", Path::new(&command.index_path)),
+        ("--report", Path::new(&command.report_path)),
+        ("--cache-dir", command.options.extract.cache_dir.as_path()),
+    ];
+    for (flag, path) in outputs {
+        if !is_inside_working_dir(path) {
+            return Err(api::error(
+                ErrorCode::InvalidArguments,
+                format!(
+                    "{flag} {path} is outside of the directory of indexd, jobs only write inside it",
+                    path = path.display()
+                ),
+            ));
+        }
+    }
+    let progress = Arc::new(Progress::default());
+    command.options.verbosity = Verbosity::Quiet;
+    command.options.progress = Some(progress.clone());
+    Ok(Job {
+        id,
+        args,
+        reload,
+        command: Some(command),
+        status: Status::Queued,
+        progress,
+        submitted: locale::now_rfc3339(),
+        started: None,
+        finished: None,
+        error: None,
+    })
+}
+
+// Whether a relative path without `..` stays inside the working directory.
+fn is_inside_working_dir(path: &Path) -> bool {
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+// Browsers tell the origin of the page that sent a request; indexd has no
+// pages, so any origin but its own address is another site's.
+fn is_cross_origin(request: &Request) -> bool {
+    let Some(origin) = http::header(request, "Origin") else {
+        return false;
+    };
+    let host = http::header(request, "Host").unwrap_or_default();
+    origin
+        .split_once("://")
+        .is_none_or(|(_, origin_host)| !origin_host.eq_ignore_ascii_case(host))
+}
+
+// Cancels a queued job at once and a running one once its workers finished
+// the documents they are reading.
+fn cancel(queue: &mut Queue, id: u64) -> Result<Value, (u16, Value)> {
+    let job = queue.job(id).ok_or_else(|| {
+        (
+            404,
+            api::error(ErrorCode::NotFound, format!("there is no job {id}")),
+        )
+    })?;
+    match job.status {
+        Status::Queued => {
+            job.status = Status::Cancelled;
+            job.command = None;
+            job.finished = Some(locale::now_rfc3339());
+        }
+        Status::Running => job.progress.cancel(),
+        _ => {
+            return Err((
+                409,
+                api::error(
+                    ErrorCode::JobFinished,
+                    format!("job {id} has already finished"),
+                ),
+            ))
+        }
+    }
+    Ok(job.to_json())
+}
+
+fn serve_request(mut request: Request, jobs: &Jobs) {
+    let url = request.url().to_string();
+    let path = http::split_url(&url).0;
+    let segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+    let id = segments.get(1).map(|id| id.parse::<u64>());
+    if *request.method() != Method::Get && is_cross_origin(&request) {
+        return answer(
+            request,
+            403,
+            &api::error(
+                ErrorCode::Forbidden,
+                "indexd does not take requests from the pages of other sites",
+            ),
+        );
+    }
+    match (request.method(), segments.as_slice(), id) {
+        (Method::Post, ["jobs"], _) => {
+            let json = http::header(&request, "Content-Type").is_some_and(|content_type| {
+                let media_type = content_type.split(';').next().unwrap_or_default();
+                media_type.trim().eq_ignore_ascii_case("application/json")
+            });
+            if !json {
+                return answer(
+                    request,
+                    415,
+                    &api::error(
+                        ErrorCode::InvalidBody,
+                        "jobs have to be posted with Content-Type: application/json",
+                    ),
+                );
+            }
+            let body = match read_body(&mut request) {
+                Ok(body) => body,
+                Err(err) => return answer(request, 400, &api::error(ErrorCode::InvalidBody, err)),
+            };
+            let mut queue = jobs.queue.lock().unwrap();
+            queue.next_id += 1;
+            match job_of(&body, queue.next_id) {
+                Ok(job) => {
+                    let payload = job.to_json();
+                    queue.jobs.push_back(job);
+                    jobs.queued.notify_one();
+                    drop(queue);
+                    answer(request, 202, &payload);
+                }
+                Err(payload) => {
+                    drop(queue);
+                    answer(request, 400, &payload);
+                }
+            }
+        }
+        (Method::Get, ["jobs"], _) => {
+            let queue = jobs.queue.lock().unwrap();
+            let listed = queue
+                .jobs
+                .iter()
+                .rev()
+                .map(Job::to_json)
+                .collect::<Vec<_>>();
+            drop(queue);
+            answer(request, 200, &json!({ "jobs": listed }));
+        }
+        (Method::Get, ["jobs", _], Some(Ok(id))) => {
+            let payload = jobs.queue.lock().unwrap().job(id).map(|job| job.to_json());
+            match payload {
+                Some(payload) => answer(request, 200, &payload),
+                None => answer(
+                    request,
+                    404,
+                    &api::error(ErrorCode::NotFound, format!("there is no job {id}")),
+                ),
+            }
+        }
+        (Method::Delete, ["jobs", _], Some(Ok(id)))
+        | (Method::Post, ["jobs", _, "cancel"], Some(Ok(id))) => {
+            let cancelled = cancel(&mut jobs.queue.lock().unwrap(), id);
+            match cancelled {
+                Ok(payload) => answer(request, 200, &payload),
+                Err((status, payload)) => answer(request, status, &payload),
+            }
+        }
+        _ => answer(
+            request,
+            404,
+            &api::error(ErrorCode::NotFound, "no such route"),
+        ),
+    }
+}
+
+// Answers the job API at `address` until the process is stopped.
+pub fn run(address: &str) -> Result<(), Error> {
+    let server = Server::http(address)
+        .map_err(|err| Error::Http(format!("could not start HTTP server at {address}: {err}")))?;
+    info!("indexd listening at http://{address}/");
+    let jobs = Arc::new(Jobs::default());
+    let runner = jobs.clone();
+    thread::spawn(move || run_jobs(&runner));
+    for request in server.incoming_requests() {
+        serve_request(request, &jobs);
+    }
+    Ok(())
+}
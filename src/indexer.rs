@@ -0,0 +1,585 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::analyzer::Analyzer;
+use crate::bundle::SourceBundle;
+use crate::extract::{self, ExtractOptions};
+use crate::fxhash::FxHashMap;
+use crate::language;
+use crate::provenance::InputHashes;
+use crate::report::IndexReport;
+use crate::schema::{self, Schema};
+use crate::source::{DocumentSource, FolderSource, SourceDocument};
+use crate::walk::WalkOptions;
+use crate::writer::IndexWriter;
+use crate::{
+    index_document, index_document_truncated, locale, term_positions, Aliases, Doc, DocKey, Error,
+    FileStamp, FileStamps, TermFreqIndex,
+};
+
+// Index-time removal of terms that bloat the dictionary without helping
+// ranking. The applied settings are recorded in the manifest.
+#[derive(Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pruning {
+    pub min_doc_freq: usize,
+    pub max_doc_freq_pct: Option<f64>,
+    pub min_term_len: usize,
+}
+
+// What happens to documents with more tokens than `max_tokens_per_doc`.
+#[derive(Clone, Copy, PartialEq)]
+pub enum OverTokenLimit {
+    // Index the first tokens and mark the document as truncated.
+    Truncate,
+    // Leave the document out and list it in the report.
+    Skip,
+}
+
+pub struct IndexOptions {
+    pub walk: WalkOptions,
+    pub extract: ExtractOptions,
+    pub pruning: Pruning,
+    // Cap on the tokens of a single indexed document, so one huge file
+    // cannot dominate the size of the index.
+    pub max_tokens_per_doc: Option<usize>,
+    pub over_token_limit: OverTokenLimit,
+    // Number of worker threads extracting and tokenizing files.
+    pub threads: usize,
+    // Cap on how many megabytes per second the workers read, across all of them.
+    pub throttle_mb_per_sec: Option<f64>,
+    // Run the workers with the lowest CPU and IO scheduling priority.
+    pub low_priority: bool,
+    // What is printed while indexing.
+    pub verbosity: Verbosity,
+    // Only extract files that changed since the writer's index was built,
+    // keep the documents of the others and drop those of vanished files.
+    pub incremental: bool,
+    // Keep a copy of every source file read here.
+    pub bundle_sources: Option<SourceBundle>,
+    // Hash every source file read here for the manifest of the build.
+    pub input_hashes: Option<InputHashes>,
+    // Counts of the run for another thread to watch, and to cancel it with.
+    pub progress: Option<Arc<Progress>>,
+    // Flush the writer whenever this many documents are pending, so that a
+    // new binary index is written in segments as it is built.
+    pub flush_docs: Option<usize>,
+}
+
+// How much indexing prints besides warnings and errors: nothing, the counts
+// at the end, or also every file as it is indexed.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+// How far an indexing run has got. The counts are of source documents,
+// `total` known once the sources are listed. Cancelling lets the workers
+// finish the documents they are reading and makes the run fail with
+// `Error::Cancelled` before anything is written.
+#[derive(Default)]
+pub struct Progress {
+    total: AtomicUsize,
+    done: AtomicUsize,
+    failed: AtomicUsize,
+    cancelled: AtomicBool,
+    // The document a worker last started on.
+    current: Mutex<Option<DocKey>>,
+}
+
+impl Progress {
+    pub fn total(&self) -> usize {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    // Documents read so far, the failed ones included.
+    pub fn done(&self) -> usize {
+        self.done.load(Ordering::Relaxed)
+    }
+
+    pub fn failed(&self) -> usize {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    pub fn current(&self) -> Option<DocKey> {
+        self.current.lock().unwrap().clone()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for IndexOptions {
+    fn default() -> Self {
+        Self {
+            walk: WalkOptions::default(),
+            extract: ExtractOptions::default(),
+            pruning: Pruning::default(),
+            max_tokens_per_doc: None,
+            over_token_limit: OverTokenLimit::Truncate,
+            threads: thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            throttle_mb_per_sec: None,
+            low_priority: false,
+            verbosity: Verbosity::Normal,
+            incremental: false,
+            bundle_sources: None,
+            input_hashes: None,
+            progress: None,
+            flush_docs: None,
+        }
+    }
+}
+
+// Shared read budget: every worker accounts the size of the document it has
+// just read and sleeps until the average rate drops back under the limit.
+struct Throttle {
+    bytes_per_sec: f64,
+    started: Instant,
+    consumed: Mutex<u64>,
+}
+
+impl Throttle {
+    fn new(mb_per_sec: f64) -> Self {
+        Self {
+            bytes_per_sec: mb_per_sec * 1024.0 * 1024.0,
+            started: Instant::now(),
+            consumed: Mutex::new(0),
+        }
+    }
+
+    fn consume(&self, bytes: u64) {
+        let consumed = {
+            let mut consumed = self.consumed.lock().unwrap();
+            *consumed += bytes;
+            *consumed
+        };
+        let due = Duration::from_secs_f64(consumed as f64 / self.bytes_per_sec);
+        if let Some(wait) = due.checked_sub(self.started.elapsed()) {
+            thread::sleep(wait);
+        }
+    }
+}
+
+// Lowers the priority of the calling thread only: on Linux both nice values
+// and IO priorities are per thread, so the rest of the process is unaffected.
+#[cfg(target_os = "linux")]
+fn lower_thread_priority() {
+    use std::os::raw::{c_int, c_long, c_uint};
+    extern "C" {
+        fn setpriority(which: c_int, who: c_uint, prio: c_int) -> c_int;
+        fn syscall(number: c_long, ...) -> c_long;
+    }
+    const PRIO_PROCESS: c_int = 0;
+    const IOPRIO_WHO_PROCESS: c_int = 1;
+    const IOPRIO_CLASS_IDLE: c_int = 3;
+    #[cfg(target_arch = "x86_64")]
+    const SYS_IOPRIO_SET: c_long = 251;
+    #[cfg(target_arch = "aarch64")]
+    const SYS_IOPRIO_SET: c_long = 30;
+    unsafe {
+        setpriority(PRIO_PROCESS, 0, 19);
+        #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+        syscall(
+            SYS_IOPRIO_SET,
+            IOPRIO_WHO_PROCESS,
+            0,
+            IOPRIO_CLASS_IDLE << 13,
+        );
+    }
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn lower_thread_priority() {
+    use std::os::raw::{c_int, c_uint};
+    extern "C" {
+        fn setpriority(which: c_int, who: c_uint, prio: c_int) -> c_int;
+    }
+    unsafe {
+        setpriority(0, 0, 19);
+    }
+}
+
+#[cfg(not(unix))]
+fn lower_thread_priority() {}
+
+// What became of a source document.
+enum Outcome {
+    Indexed(Indexed),
+    // Its documents in the index are up to date.
+    Unchanged,
+}
+
+// The indexed documents of a source document, one per chunk.
+struct Indexed {
+    docs: Vec<(DocKey, Doc)>,
+    // Chunks left out for having too many tokens, with their token count.
+    skipped: Vec<(DocKey, usize)>,
+}
+
+fn index_source_document(
+    document: &SourceDocument,
+    bytes: &[u8],
+    options: &IndexOptions,
+    analyzer: &Analyzer,
+    schema: &Schema,
+) -> Result<Indexed, Error> {
+    let chunks = extract::extract_document(&document.id, bytes, &options.extract)?;
+    let mut indexed = Indexed {
+        docs: Vec::new(),
+        skipped: Vec::new(),
+    };
+    for chunk in chunks {
+        let doc_path = match chunk.anchor {
+            Some(anchor) => DocKey::from(format!("{}#{anchor}", document.id)),
+            None => document.id.clone(),
+        };
+        let mut meta = document.meta.clone();
+        // Those of the whole file for the documents of its sections.
+        if let Some(stat) = document.stat {
+            meta.insert("size".to_string(), stat.size.to_string().into());
+            let mtime = (stat.mtime_ns / 1_000_000_000) as i64;
+            meta.insert("mtime".to_string(), locale::rfc3339_from_unix(mtime).into());
+        }
+        meta.extend(chunk.meta);
+        // Documents without a title of their own go by their file name;
+        // web pages have their URL to show.
+        if !meta.contains_key("title") && !meta.contains_key("url") {
+            if let Some(name) = document.id.file_name() {
+                meta.insert(
+                    "title".to_string(),
+                    name.to_string_lossy().into_owned().into(),
+                );
+            }
+        }
+        language::tag(&mut meta, &chunk.text);
+        let tf = match options.max_tokens_per_doc {
+            Some(max_tokens) => {
+                let (tf, tokens) = index_document_truncated(analyzer, &chunk.text, max_tokens);
+                if tokens > max_tokens {
+                    if options.over_token_limit == OverTokenLimit::Skip {
+                        indexed.skipped.push((doc_path, tokens));
+                        continue;
+                    }
+                    // Results from the document may be partial; the value is
+                    // the token count of the whole document.
+                    meta.insert("truncated".to_string(), tokens.to_string().into());
+                }
+                tf
+            }
+            None => index_document(analyzer, &chunk.text),
+        };
+        let max_tokens = options.max_tokens_per_doc.unwrap_or(usize::MAX);
+        let positions = term_positions(analyzer, &chunk.text, max_tokens);
+        let mut doc = Doc {
+            tf,
+            meta,
+            positions,
+            ..Doc::default()
+        };
+        schema::index_fields(schema, analyzer, &mut doc);
+        indexed.docs.push((doc_path, doc));
+    }
+    Ok(indexed)
+}
+
+pub fn tf_index_of_folder(
+    dir_path: &Path,
+    writer: &mut IndexWriter,
+    options: &IndexOptions,
+    report: &mut IndexReport,
+) -> Result<(), Error> {
+    let folder = FolderSource {
+        root: dir_path.to_path_buf(),
+        walk: options.walk.clone(),
+    };
+    index_sources(&[Box::new(folder)], writer, options, report)
+}
+
+pub fn index_sources(
+    sources: &[Box<dyn DocumentSource>],
+    writer: &mut IndexWriter,
+    options: &IndexOptions,
+    report: &mut IndexReport,
+) -> Result<(), Error> {
+    let documents = sources
+        .iter()
+        .map(|source| source.documents())
+        .collect::<Result<Vec<_>, Error>>()?;
+    let progress = options.progress.as_deref();
+    if let Some(progress) = progress {
+        let listed = documents.iter().map(|docs| docs.size_hint().0).sum();
+        progress.total.store(listed, Ordering::Relaxed);
+    }
+    let cancelled = || progress.is_some_and(Progress::is_cancelled);
+
+    let analyzer = writer.analyzer().clone();
+    let schema = writer.config().fields.clone();
+    let previous = if options.incremental {
+        writer.file_stamps().clone()
+    } else {
+        FileStamps::new()
+    };
+    let queue = Mutex::new(documents.into_iter().flatten());
+    let throttle = options.throttle_mb_per_sec.map(Throttle::new);
+    let (sender, receiver) = mpsc::channel();
+    let mut stamps = FileStamps::new();
+    let mut unchanged = HashSet::new();
+    let mut added = HashSet::new();
+    let mut flushed = Ok(());
+    thread::scope(|scope| {
+        for _ in 0..options.threads.max(1) {
+            let sender = sender.clone();
+            let (queue, throttle, analyzer, schema, previous) =
+                (&queue, &throttle, &analyzer, &schema, &previous);
+            scope.spawn(move || {
+                if options.low_priority {
+                    lower_thread_priority();
+                }
+                loop {
+                    if cancelled() {
+                        break;
+                    }
+                    let Some(mut document) = queue.lock().unwrap().next() else {
+                        break;
+                    };
+                    let old = previous.get(&document.id);
+                    if let Some(old) =
+                        old.filter(|old| old.stat.is_some() && old.stat == document.stat)
+                    {
+                        let message = (
+                            document.id,
+                            0,
+                            Duration::ZERO,
+                            Ok(Outcome::Unchanged),
+                            Some(old.clone()),
+                        );
+                        if sender.send(message).is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                    if options.verbosity == Verbosity::Verbose {
+                        println!("Indexing {:?}...", document.id);
+                    }
+                    if let Some(progress) = progress {
+                        *progress.current.lock().unwrap() = Some(document.id.clone());
+                    }
+                    let started = Instant::now();
+                    let mut bytes = Vec::new();
+                    let mut stamp = None;
+                    let result = match document.reader.read_to_end(&mut bytes) {
+                        Ok(_) => {
+                            if let Some(throttle) = throttle {
+                                throttle.consume(bytes.len() as u64);
+                            }
+                            let new_stamp = FileStamp {
+                                stat: document.stat,
+                                hash: extract::content_hash(&bytes),
+                            };
+                            if let Some(bundle) = &options.bundle_sources {
+                                if let Err(err) = bundle.add(&new_stamp.hash, &bytes) {
+                                    eprintln!(
+                                        "WARNING: could not bundle {id}: {err}",
+                                        id = document.id.display()
+                                    );
+                                }
+                            }
+                            if let Some(hashes) = &options.input_hashes {
+                                hashes.record(&document.id, &bytes);
+                            }
+                            // Touched but not modified.
+                            let unchanged = old.is_some_and(|old| old.hash == new_stamp.hash);
+                            stamp = Some(new_stamp);
+                            if unchanged {
+                                Ok(Outcome::Unchanged)
+                            } else {
+                                index_source_document(&document, &bytes, options, analyzer, schema)
+                                    .map(Outcome::Indexed)
+                            }
+                        }
+                        Err(err) => Err(Error::io(
+                            format!("could not read {id}", id = document.id.display()),
+                            err,
+                        )),
+                    };
+                    let elapsed = started.elapsed();
+                    let message = (document.id, bytes.len() as u64, elapsed, result, stamp);
+                    if sender.send(message).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        for (doc_id, bytes, elapsed, result, stamp) in receiver {
+            if let Some(progress) = progress {
+                progress.done.fetch_add(1, Ordering::Relaxed);
+                if result.is_err() {
+                    progress.failed.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            if let (Ok(_), Some(stamp)) = (&result, stamp) {
+                stamps.insert(doc_id.clone(), stamp);
+            }
+            match result {
+                Ok(Outcome::Unchanged) => {
+                    unchanged.insert(doc_id);
+                }
+                Ok(Outcome::Indexed(indexed)) => {
+                    report.record(&doc_id, bytes, elapsed, Ok(indexed.docs.len()));
+                    for (doc_path, tokens) in indexed.skipped {
+                        eprintln!(
+                            "WARNING: skipping {doc_path:?}, its {tokens} tokens are more than --max-tokens-per-doc allows"
+                        );
+                        report.record_skipped(doc_path, tokens);
+                    }
+                    for (doc_path, doc) in indexed.docs {
+                        if options.incremental {
+                            added.insert(doc_path.clone());
+                        }
+                        writer.add_doc(doc_path, doc);
+                    }
+                    if options
+                        .flush_docs
+                        .is_some_and(|docs| writer.pending() >= docs)
+                    {
+                        flushed = writer.flush();
+                        // Ending the run makes the workers stop.
+                        if flushed.is_err() {
+                            break;
+                        }
+                    }
+                }
+                Err(err) => {
+                    eprintln!("ERROR: {err}");
+                    report.record(&doc_id, bytes, elapsed, Err(err.to_string()));
+                }
+            }
+        }
+    });
+    flushed?;
+    if cancelled() {
+        return Err(Error::Cancelled);
+    }
+
+    if options.incremental {
+        let moved = moved_files(&previous, &stamps);
+        writer.carry_doc_ids(&moved);
+        writer.retain(|doc_path| {
+            added.contains(doc_path)
+                || unchanged.contains(source_file(doc_path.as_str(), &unchanged))
+        });
+        if options.verbosity > Verbosity::Quiet {
+            println!(
+                "{unchanged} files unchanged, {moved} moved, {removed} gone",
+                unchanged = unchanged.len(),
+                moved = moved.len(),
+                removed = previous
+                    .keys()
+                    .filter(|file| !stamps.contains_key(*file) && !moved.contains_key(*file))
+                    .count()
+            );
+        }
+        let aliases = update_aliases(writer.aliases(), &moved, &stamps);
+        writer.set_aliases(aliases);
+    }
+    let collapsed = writer.collapse_urls();
+    if collapsed > 0 && options.verbosity > Verbosity::Quiet {
+        println!("{collapsed} documents dropped for having the URL of another");
+    }
+    writer.set_file_stamps(stamps);
+    Ok(())
+}
+
+// Files of the last run that are gone, mapped to a new file with the same
+// content: they were moved or renamed.
+fn moved_files(previous: &FileStamps, stamps: &FileStamps) -> Aliases {
+    let mut new_files = HashMap::new();
+    for (file, stamp) in stamps {
+        if !previous.contains_key(file) {
+            new_files.entry(stamp.hash.as_str()).or_insert(file);
+        }
+    }
+    previous
+        .iter()
+        .filter(|(file, _)| !stamps.contains_key(*file))
+        .filter_map(|(file, stamp)| {
+            let target = new_files.get(stamp.hash.as_str())?;
+            Some((file.clone(), (*target).clone()))
+        })
+        .collect()
+}
+
+// The aliases after this run: the files that moved now, and the earlier
+// aliases following their file if it moved again. Aliases of a file that is
+// gone, or from a path that is indexed again, are dropped.
+fn update_aliases(aliases: &Aliases, moved: &Aliases, stamps: &FileStamps) -> Aliases {
+    let mut updated = aliases
+        .iter()
+        .filter(|(old, _)| !stamps.contains_key(*old))
+        .filter_map(|(old, target)| {
+            let target = moved.get(target).unwrap_or(target);
+            stamps
+                .contains_key(target)
+                .then(|| (old.clone(), target.clone()))
+        })
+        .collect::<Aliases>();
+    updated.extend(moved.iter().map(|(old, new)| (old.clone(), new.clone())));
+    updated
+}
+
+// The source file of an indexed document: the document itself or, for a
+// section `file#anchor`, the file, as far as `files` knows it.
+pub(crate) fn source_file<'a>(doc_path: &'a str, files: &HashSet<DocKey>) -> &'a str {
+    let mut file = doc_path;
+    loop {
+        if files.contains(file) {
+            return file;
+        }
+        match file.rsplit_once('#') {
+            Some((prefix, _)) => file = prefix,
+            None => return doc_path,
+        }
+    }
+}
+
+// Returns the number of distinct terms removed from the index.
+pub fn prune(tf_index: &mut TermFreqIndex, pruning: &Pruning) -> usize {
+    let mut df = FxHashMap::<String, usize>::default();
+    for doc in tf_index.values() {
+        for term in doc.tf.keys() {
+            if let Some(freq) = df.get_mut(term) {
+                *freq += 1;
+            } else {
+                df.insert(term.clone(), 1);
+            }
+        }
+    }
+    let max_doc_freq = pruning
+        .max_doc_freq_pct
+        .map(|pct| pct / 100.0 * tf_index.len() as f64);
+    let keep = |term: &str, freq: usize| {
+        freq >= pruning.min_doc_freq
+            && max_doc_freq.is_none_or(|max| freq as f64 <= max)
+            && term.chars().count() >= pruning.min_term_len
+    };
+    let pruned = df.iter().filter(|(term, freq)| !keep(term, **freq)).count();
+    for doc in tf_index.values_mut() {
+        doc.tf.retain(|term, _| keep(term, df[term]));
+    }
+    pruned
+}
@@ -0,0 +1,127 @@
+// The inverted index of a model: the posting list of every term, the
+// documents it appears in with its count in each, over a table numbering the
+// documents. Indexes are stored per document, which is what writing, diffing
+// and filtering need; this is built from them once per index so a query only
+// visits the documents that have its terms instead of every document.
+use std::path::Path;
+
+use crate::fxhash::FxHashMap;
+use crate::postings::{self, PostingList};
+use crate::query::Query;
+use crate::{DocKey, Model};
+
+pub struct InvertedIndex {
+    // Documents by ordinal, in path order.
+    docs: Vec<DocKey>,
+    doc_lens: Vec<usize>,
+    terms: FxHashMap<String, PostingList>,
+    // The documents whose title has the term, by ordinal in order.
+    title_terms: FxHashMap<String, Vec<usize>>,
+}
+
+// The list of terms no document has.
+static EMPTY: PostingList = PostingList {
+    ordinals: Vec::new(),
+    counts: Vec::new(),
+};
+
+impl InvertedIndex {
+    pub fn of(model: &Model) -> Self {
+        let mut docs = model.docs.iter().collect::<Vec<_>>();
+        docs.sort_unstable_by_key(|(path, _)| *path);
+        let mut terms = FxHashMap::<String, PostingList>::default();
+        let mut doc_lens = Vec::with_capacity(docs.len());
+        let mut title_terms = FxHashMap::<String, Vec<usize>>::default();
+        let analyzer = model.document_analyzer();
+        // Documents are visited in ordinal order, so every list stays sorted.
+        for (ordinal, (_, doc)) in docs.iter().enumerate() {
+            doc_lens.push(doc.tf.values().sum());
+            if let Some(title) = doc.meta.get("title").and_then(|title| title.first()) {
+                for term in analyzer.terms(title) {
+                    let list = title_terms.entry(term).or_default();
+                    if list.last() != Some(&ordinal) {
+                        list.push(ordinal);
+                    }
+                }
+            }
+            for (term, &count) in &doc.tf {
+                let list = terms.entry(term.clone()).or_insert_with(|| PostingList {
+                    ordinals: Vec::new(),
+                    counts: Vec::new(),
+                });
+                list.ordinals.push(ordinal);
+                list.counts.push(count);
+            }
+        }
+        Self {
+            docs: docs.into_iter().map(|(path, _)| path.clone()).collect(),
+            doc_lens,
+            terms,
+            title_terms,
+        }
+    }
+
+    pub fn docs(&self) -> usize {
+        self.docs.len()
+    }
+
+    pub fn path(&self, ordinal: usize) -> &Path {
+        &self.docs[ordinal]
+    }
+
+    pub fn key(&self, ordinal: usize) -> &str {
+        self.docs[ordinal].as_str()
+    }
+
+    // Number of tokens of the document.
+    pub fn doc_len(&self, ordinal: usize) -> usize {
+        self.doc_lens[ordinal]
+    }
+
+    pub fn terms(&self) -> impl Iterator<Item = &str> {
+        self.terms.keys().map(String::as_str)
+    }
+
+    pub fn term_count(&self) -> usize {
+        self.terms.len()
+    }
+
+    pub fn avg_doc_len(&self) -> f32 {
+        if self.docs.is_empty() {
+            0.0
+        } else {
+            self.doc_lens.iter().sum::<usize>() as f32 / self.docs.len() as f32
+        }
+    }
+
+    // Number of documents the term appears in.
+    pub fn doc_freq(&self, term: &str) -> usize {
+        self.list(term).ordinals.len()
+    }
+
+    // How often the term occurs in the document, if at all.
+    pub fn count(&self, term: &str, ordinal: usize) -> Option<usize> {
+        self.list(term).count(ordinal)
+    }
+
+    // Whether any document has a title with a term.
+    pub fn has_titles(&self) -> bool {
+        !self.title_terms.is_empty()
+    }
+
+    pub fn in_title(&self, term: &str, ordinal: usize) -> bool {
+        self.title_terms
+            .get(term)
+            .is_some_and(|ordinals| ordinals.binary_search(&ordinal).is_ok())
+    }
+
+    // Ordinals of the documents that may match the query, in order. They
+    // still have to be checked against the whole query.
+    pub fn candidates(&self, query: &Query) -> Vec<usize> {
+        postings::candidates(query, self.docs.len(), |term| self.list(term))
+    }
+
+    fn list(&self, term: &str) -> &PostingList {
+        self.terms.get(term).unwrap_or(&EMPTY)
+    }
+}
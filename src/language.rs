@@ -0,0 +1,238 @@
+// The language of a document told from its text, recorded as its `lang`
+// metadata so results can be faceted and filtered by it, and boosted for
+// searchers who prefer it. Scripts used by one language give it away;
+// texts in Latin script are told apart by how many of the most common
+// words of each language they have. Texts too short or too mixed to tell
+// get no language rather than a guess.
+use crate::Metadata;
+
+pub const LANG_KEY: &str = "lang";
+
+// Words looked at, enough to tell and few enough to be cheap on long texts.
+const SAMPLE_WORDS: usize = 2000;
+// Common words a text needs before its language is told at all.
+const MIN_HITS: usize = 3;
+// How far the best language has to be ahead of the next.
+const MIN_LEAD: f64 = 1.5;
+// Letters of a script a text needs for it to give the language away.
+const MIN_SCRIPT_SHARE: f64 = 0.3;
+
+const COMMON_WORDS: &[(&str, &[&str])] = &[
+    (
+        "en",
+        &[
+            "the", "and", "of", "to", "is", "in", "that", "it", "with", "for", "was", "on", "are",
+            "this", "be", "as", "have", "not", "by", "from",
+        ],
+    ),
+    (
+        "de",
+        &[
+            "der", "die", "und", "das", "ist", "nicht", "ein", "eine", "zu", "den", "mit", "sich",
+            "auch", "auf", "für", "von", "dem", "des", "im", "wird",
+        ],
+    ),
+    (
+        "fr",
+        &[
+            "le", "la", "les", "et", "des", "est", "une", "un", "du", "que", "dans", "pour", "qui",
+            "pas", "sur", "au", "avec", "ce", "sont", "il",
+        ],
+    ),
+    (
+        "es",
+        &[
+            "el", "la", "los", "las", "y", "que", "es", "en", "un", "una", "por", "con", "para",
+            "del", "se", "no", "lo", "como", "más", "pero",
+        ],
+    ),
+    (
+        "it",
+        &[
+            "il", "la", "che", "e", "di", "un", "una", "per", "non", "sono", "del", "della", "con",
+            "è", "gli", "le", "si", "nel", "anche", "come",
+        ],
+    ),
+    (
+        "pt",
+        &[
+            "o", "a", "os", "as", "que", "é", "de", "do", "da", "em", "um", "uma", "para", "com",
+            "não", "no", "na", "se", "por", "mais",
+        ],
+    ),
+    (
+        "nl",
+        &[
+            "de", "het", "een", "en", "van", "is", "dat", "niet", "op", "te", "zijn", "met",
+            "voor", "ook", "die", "er", "maar", "aan", "bij", "wordt",
+        ],
+    ),
+    (
+        "sv",
+        &[
+            "och", "att", "det", "som", "en", "är", "på", "för", "med", "den", "till", "av",
+            "inte", "har", "jag", "om", "ett", "var", "men", "så",
+        ],
+    ),
+    (
+        "pl",
+        &[
+            "i", "w", "nie", "na", "się", "z", "że", "do", "jest", "to", "jak", "o", "ale", "po",
+            "co", "tak", "od", "przez", "być", "są",
+        ],
+    ),
+];
+
+// Scripts that give the language of a text away. Cyrillic and the Chinese
+// characters are shared: Ukrainian has letters of its own, and Japanese
+// mixes the characters with kana.
+#[derive(Clone, Copy, PartialEq)]
+enum Script {
+    Greek,
+    Ukrainian,
+    Cyrillic,
+    Hebrew,
+    Arabic,
+    Thai,
+    Kana,
+    Hangul,
+    Han,
+}
+
+fn script(c: char) -> Option<Script> {
+    let script = match c {
+        '\u{0370}'..='\u{03ff}' => Script::Greek,
+        'і' | 'ї' | 'є' | 'ґ' | 'І' | 'Ї' | 'Є' | 'Ґ' => Script::Ukrainian,
+        '\u{0400}'..='\u{04ff}' => Script::Cyrillic,
+        '\u{0590}'..='\u{05ff}' => Script::Hebrew,
+        '\u{0600}'..='\u{06ff}' => Script::Arabic,
+        '\u{0e00}'..='\u{0e7f}' => Script::Thai,
+        '\u{3040}'..='\u{30ff}' => Script::Kana,
+        '\u{ac00}'..='\u{d7af}' | '\u{1100}'..='\u{11ff}' => Script::Hangul,
+        '\u{4e00}'..='\u{9fff}' => Script::Han,
+        _ => return None,
+    };
+    Some(script)
+}
+
+fn by_script(text: &str) -> Option<&'static str> {
+    let mut letters = 0;
+    let mut counts = Vec::<(Script, usize)>::new();
+    for c in text
+        .chars()
+        .filter(|c| c.is_alphabetic())
+        .take(SAMPLE_WORDS * 5)
+    {
+        letters += 1;
+        if let Some(script) = script(c) {
+            match counts.iter_mut().find(|(known, _)| *known == script) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((script, 1)),
+            }
+        }
+    }
+    let count = |script| {
+        counts
+            .iter()
+            .find(|(known, _)| *known == script)
+            .map_or(0, |(_, count)| *count)
+    };
+    let languages = [
+        ("el", count(Script::Greek)),
+        (
+            if count(Script::Ukrainian) > 0 {
+                "uk"
+            } else {
+                "ru"
+            },
+            count(Script::Ukrainian) + count(Script::Cyrillic),
+        ),
+        ("he", count(Script::Hebrew)),
+        ("ar", count(Script::Arabic)),
+        ("th", count(Script::Thai)),
+        ("ko", count(Script::Hangul)),
+        (
+            if count(Script::Kana) > 0 { "ja" } else { "zh" },
+            count(Script::Kana) + count(Script::Han),
+        ),
+    ];
+    let (language, share) = languages.into_iter().max_by_key(|(_, count)| *count)?;
+    (share > 0 && share as f64 / letters as f64 >= MIN_SCRIPT_SHARE).then_some(language)
+}
+
+fn by_common_words(text: &str) -> Option<&'static str> {
+    let mut hits = vec![0; COMMON_WORDS.len()];
+    let words = text
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|word| !word.is_empty())
+        .take(SAMPLE_WORDS);
+    for word in words {
+        let word = word.to_lowercase();
+        for (hits, (_, common)) in hits.iter_mut().zip(COMMON_WORDS) {
+            if common.contains(&word.as_str()) {
+                *hits += 1;
+            }
+        }
+    }
+    let mut ranked = hits.iter().copied().enumerate().collect::<Vec<_>>();
+    ranked.sort_by(|(_, a), (_, b)| b.cmp(a));
+    let (best, best_hits) = ranked[0];
+    let next_hits = ranked.get(1).map_or(0, |(_, hits)| *hits);
+    (best_hits >= MIN_HITS && best_hits as f64 >= next_hits as f64 * MIN_LEAD)
+        .then_some(COMMON_WORDS[best].0)
+}
+
+// The ISO 639-1 code of the language of the text, if it can be told.
+pub fn detect(text: &str) -> Option<&'static str> {
+    by_script(text).or_else(|| by_common_words(text))
+}
+
+// Records the language of the text as `lang`, unless the metadata has one.
+pub fn tag(meta: &mut Metadata, text: &str) {
+    if meta.contains_key(LANG_KEY) {
+        return;
+    }
+    if let Some(language) = detect(text) {
+        meta.insert(LANG_KEY.to_string(), language.into());
+    }
+}
+
+// The primary language subtags of an Accept-Language header, or a list of
+// codes in its syntax, with their weights: `de-CH, de;q=0.9, en;q=0.5` gives
+// de with 1 and en with 0.5. The same language given twice keeps its first
+// weight, `*` and languages weighted 0 are left out.
+pub fn parse_preferences(header: &str) -> Vec<(String, f32)> {
+    let mut preferences: Vec<(String, f32)> = Vec::new();
+    for range in header.split(',') {
+        let mut parts = range.split(';');
+        let tag = parts.next().unwrap_or_default().trim();
+        let weight = parts
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0)
+            .clamp(0.0, 1.0);
+        let language = tag.split(['-', '_']).next().unwrap_or_default();
+        if language.is_empty() || language == "*" || weight == 0.0 {
+            continue;
+        }
+        let language = language.to_ascii_lowercase();
+        if !preferences.iter().any(|(known, _)| *known == language) {
+            preferences.push((language, weight));
+        }
+    }
+    preferences
+}
+
+// Share of its score a document in the language a searcher prefers most
+// gains; those in other languages they accept gain in proportion to their
+// weight.
+const PREFERRED_BOOST: f32 = 0.5;
+
+// The factor the score of a document in `language` is multiplied by for a
+// searcher with these preferences, 1 for languages they did not name.
+pub fn preference_boost(preferences: &[(String, f32)], language: &str) -> f32 {
+    preferences
+        .iter()
+        .find(|(preferred, _)| preferred == language)
+        .map_or(1.0, |(_, weight)| 1.0 + PREFERRED_BOOST * weight)
+}
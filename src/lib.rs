@@ -0,0 +1,751 @@
+// The search engine without its command line: analysis, indexing, the index
+// stores and ranking. `Model` is the index itself; `IndexWriter` and
+// `SearchHandle` are the way to build and query large ones.
+//
+// Failures are returned as an `Error` saying what went wrong, for the caller
+// to report; only warnings about single documents are printed.
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use unicode_normalization::char::is_combining_mark;
+
+pub mod aggregate;
+pub mod analyzer;
+pub mod ascii_lexer;
+pub mod bitset;
+pub mod bundle;
+pub mod cache;
+pub mod collector;
+pub mod config;
+pub mod crawl;
+pub mod diff;
+pub mod error;
+pub mod eval;
+pub mod exclude;
+pub mod extract;
+pub mod feedback;
+pub mod filter;
+pub mod fsck;
+pub mod fxhash;
+pub mod handle;
+pub mod import;
+pub mod indexer;
+pub mod inverted;
+pub mod language;
+pub mod locale;
+pub mod lock;
+pub mod memory;
+pub mod merge;
+pub mod overrides;
+pub mod percolate;
+pub mod plugins;
+pub mod postings;
+pub mod provenance;
+pub mod query;
+pub mod report;
+pub mod rewrite;
+pub mod schema;
+pub mod scoring;
+pub mod search;
+pub mod snippet;
+pub mod source;
+pub mod spelling;
+pub mod stats;
+pub mod store;
+pub mod vocab;
+pub mod walk;
+pub mod writer;
+
+pub use error::{Error, ErrorCode};
+
+use analyzer::Analyzer;
+use config::IndexConfig;
+use fxhash::FxHashMap;
+use indexer::Pruning;
+use percolate::StoredQueries;
+use query::QueryLimits;
+use scoring::{CorpusStats, TfIdf};
+use source::FileStat;
+use spelling::SpellingDictionary;
+
+pub struct Lexer<'a> {
+    content: &'a [char],
+    // The joiners of the code tokenizer, which keeps identifiers whole.
+    code: Option<&'a str>,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(content: &'a [char]) -> Self {
+        Self {
+            content,
+            code: None,
+        }
+    }
+
+    // Tokens of the code tokenizer, which joins letters and digits through
+    // `_` and the joiners.
+    pub fn code(content: &'a [char], joiners: &'a str) -> Self {
+        Self {
+            content,
+            code: Some(joiners),
+        }
+    }
+
+    fn trim_left(&mut self) {
+        while !self.content.is_empty() && self.content[0].is_whitespace() {
+            self.content = &self.content[1..];
+        }
+    }
+
+    fn chop(&mut self, n: usize) -> &'a [char] {
+        let token = &self.content[0..n];
+        self.content = &self.content[n..];
+        token
+    }
+
+    fn chop_while<P>(&mut self, mut predicate: P) -> &'a [char]
+    where
+        P: FnMut(&char) -> bool,
+    {
+        let mut idx = 0;
+        while idx < self.content.len() && predicate(&self.content[idx]) {
+            idx += 1;
+        }
+        self.chop(idx)
+    }
+
+    fn next_token(&mut self) -> Option<&'a [char]> {
+        // trim whitespaces from left.
+        self.trim_left();
+        if self.content.is_empty() {
+            return None;
+        }
+
+        if let Some(joiners) = self.code {
+            let len = code_token_len(self.content, joiners);
+            if len > 0 {
+                return Some(self.chop(len));
+            }
+        }
+
+        if self.content[0].is_numeric() {
+            return Some(self.chop_while(|idx| idx.is_numeric()));
+        }
+
+        // Chinese and Japanese are written without spaces, every ideograph
+        // and kana is a token of its own and phrases find the words.
+        if is_cjk(self.content[0]) {
+            return Some(self.chop(1));
+        }
+
+        // Combining marks belong to the letter they follow, as in the
+        // decomposed form of "naïve".
+        if self.content[0].is_alphabetic() {
+            return Some(
+                self.chop_while(|&c| (c.is_alphabetic() || is_combining_mark(c)) && !is_cjk(c)),
+            );
+        }
+        Some(self.chop(1))
+    }
+}
+
+// Length of the identifier the content starts with, 0 if it does not start
+// with one: letters, digits and `_`, also joined by a joiner when a letter or
+// digit follows, and the `+`s or `#` ending names like C++ and C#. Works on
+// chars and on the bytes of ASCII text alike.
+pub(crate) fn code_token_len<C: Copy + Into<char>>(content: &[C], joiners: &str) -> usize {
+    let is_part = |c: char| (c.is_alphanumeric() || c == '_' || is_combining_mark(c)) && !is_cjk(c);
+    let at = |i: usize| content.get(i).map(|&c| c.into());
+    let mut len = 0;
+    while let Some(c) = at(len) {
+        if is_part(c) && (len > 0 || !is_combining_mark(c)) {
+            len += 1;
+        } else if len > 0 && joiners.contains(c) && at(len + 1).is_some_and(is_part) {
+            len += 2;
+        } else {
+            break;
+        }
+    }
+    if len == 0 {
+        return 0;
+    }
+    let suffix = match at(len) {
+        Some('+') => (len..).take_while(|&i| at(i) == Some('+')).count(),
+        Some('#') => 1,
+        _ => 0,
+    };
+    if at(len + suffix).is_some_and(is_part) {
+        len
+    } else {
+        len + suffix
+    }
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30FF}' // Hiragana, Katakana
+        | '\u{31F0}'..='\u{31FF}' // Katakana extensions
+        | '\u{3400}'..='\u{4DBF}' // CJK extension A
+        | '\u{4E00}'..='\u{9FFF}' // CJK unified ideographs
+        | '\u{F900}'..='\u{FAFF}' // CJK compatibility ideographs
+        | '\u{FF66}'..='\u{FF9F}' // Halfwidth Katakana
+        | '\u{20000}'..='\u{2FA1F}' // CJK extensions B to F, compatibility supplement
+    )
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = &'a [char];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_token()
+    }
+}
+
+pub type TermFreq = FxHashMap<String, usize>;
+pub type Metadata = BTreeMap<String, MetaValue>;
+// Where every term occurs in a document, counted in terms, in order.
+pub type Positions = FxHashMap<String, Vec<u32>>;
+// Numbers documents for good: a document keeps its ID when it is indexed
+// again or its file moves, so postings refer to documents by ID rather than
+// by path. IDs start at 1, 0 marks a document that has none yet.
+pub type DocId = u32;
+
+fn is_unassigned(id: &DocId) -> bool {
+    *id == 0
+}
+
+// A metadata field holds one value or, for tags, authors and the like, a
+// list of them. Either is stored as it was given: a string or an array.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MetaValue {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl MetaValue {
+    pub fn values(&self) -> &[String] {
+        match self {
+            Self::One(value) => std::slice::from_ref(value),
+            Self::Many(values) => values,
+        }
+    }
+
+    // The value of a single-valued field, or the first of a list, e.g. for
+    // a title or a date.
+    pub fn first(&self) -> Option<&str> {
+        self.values().first().map(String::as_str)
+    }
+
+    pub fn is_many(&self) -> bool {
+        matches!(self, Self::Many(_))
+    }
+}
+
+impl From<String> for MetaValue {
+    fn from(value: String) -> Self {
+        Self::One(value)
+    }
+}
+
+impl From<&str> for MetaValue {
+    fn from(value: &str) -> Self {
+        Self::One(value.to_string())
+    }
+}
+
+impl From<Vec<String>> for MetaValue {
+    fn from(values: Vec<String>) -> Self {
+        Self::Many(values)
+    }
+}
+
+impl std::fmt::Display for MetaValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.values().join(", "))
+    }
+}
+
+#[derive(Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "StoredDoc")]
+pub struct Doc {
+    #[serde(default, skip_serializing_if = "is_unassigned")]
+    pub id: DocId,
+    pub tf: TermFreq,
+    #[serde(skip_serializing_if = "Metadata::is_empty")]
+    pub meta: Metadata,
+    // Only recorded in indexes built with positions, for phrase queries.
+    #[serde(skip_serializing_if = "Positions::is_empty")]
+    pub positions: Positions,
+}
+
+impl Doc {
+    // Whether both have the same terms, metadata and positions, whatever
+    // their IDs.
+    pub fn same_content(&self, other: &Doc) -> bool {
+        self.tf == other.tf && self.meta == other.meta && self.positions == other.positions
+    }
+}
+
+// Indexes written before documents carried metadata map every path straight
+// to its term frequencies.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredDoc {
+    Doc {
+        #[serde(default)]
+        id: DocId,
+        tf: TermFreq,
+        #[serde(default)]
+        meta: Metadata,
+        #[serde(default)]
+        positions: Positions,
+    },
+    TermFreq(TermFreq),
+}
+
+impl From<StoredDoc> for Doc {
+    fn from(stored: StoredDoc) -> Self {
+        match stored {
+            StoredDoc::Doc {
+                id,
+                tf,
+                meta,
+                positions,
+            } => Self {
+                id,
+                tf,
+                meta,
+                positions,
+            },
+            StoredDoc::TermFreq(tf) => Self {
+                tf,
+                ..Self::default()
+            },
+        }
+    }
+}
+
+// The key a document is stored under: the path of its file as it was given,
+// or the URL of a page, exactly as written. Unlike a PathBuf it compares as
+// text, so `https://x/docs/` and `https://x/docs`, or `a/./b` and `a/b`, are
+// different documents. It derefs to a Path for the extension and file name.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct DocKey(String);
+
+impl DocKey {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn as_path(&self) -> &Path {
+        Path::new(&self.0)
+    }
+}
+
+impl std::ops::Deref for DocKey {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        self.as_path()
+    }
+}
+
+impl AsRef<Path> for DocKey {
+    fn as_ref(&self) -> &Path {
+        self.as_path()
+    }
+}
+
+impl std::borrow::Borrow<str> for DocKey {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for DocKey {
+    fn from(key: String) -> Self {
+        Self(key)
+    }
+}
+
+impl From<&str> for DocKey {
+    fn from(key: &str) -> Self {
+        Self(key.to_string())
+    }
+}
+
+impl From<&Path> for DocKey {
+    fn from(path: &Path) -> Self {
+        Self(path.to_string_lossy().into_owned())
+    }
+}
+
+impl From<PathBuf> for DocKey {
+    fn from(path: PathBuf) -> Self {
+        Self::from(path.as_path())
+    }
+}
+
+impl std::fmt::Display for DocKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+pub type TermFreqIndex = HashMap<DocKey, Doc>;
+
+// The version of the index format, stored in the manifest of every index:
+//
+//   1  indexes written before the format had a version
+//   2  the version is stored, and the spelling dictionary is always built
+//
+// Indexes of older versions are migrated as they are loaded and written in
+// the current version when saved; those of newer versions are refused, as
+// this build cannot know what they hold.
+pub const INDEX_VERSION: u32 = 2;
+
+// Describes how an index was built, so tools reading it later know which
+// settings shaped its contents.
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    // The format version the index was read in; whatever it was, the index
+    // is saved in `INDEX_VERSION`.
+    #[serde(default = "unversioned", serialize_with = "current_version")]
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "IndexConfig::is_default")]
+    pub config: IndexConfig,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pruning: Option<Pruning>,
+    // The source files of the documents, as they were when indexed.
+    #[serde(default, skip_serializing_if = "FileStamps::is_empty")]
+    pub files: FileStamps,
+    // Where files that moved since they were first indexed are now, so the
+    // old paths still lead to them.
+    #[serde(default, skip_serializing_if = "Aliases::is_empty")]
+    pub aliases: Aliases,
+    // The ID the next new document gets, so the IDs of removed documents are
+    // not handed out again.
+    #[serde(default, skip_serializing_if = "is_unassigned")]
+    pub next_doc_id: DocId,
+    // The terms "did you mean" suggestions are drawn from.
+    #[serde(default, skip_serializing_if = "SpellingDictionary::is_empty")]
+    pub spelling: SpellingDictionary,
+    // The queries documents are percolated against.
+    #[serde(default, skip_serializing_if = "StoredQueries::is_empty")]
+    pub queries: StoredQueries,
+}
+
+fn unversioned() -> u32 {
+    1
+}
+
+fn current_version<S: serde::Serializer>(_: &u32, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u32(INDEX_VERSION)
+}
+
+// In path order, so that indexing the same files again writes the same
+// bytes.
+fn docs_by_path<S: serde::Serializer>(
+    docs: &TermFreqIndex,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(docs.iter().collect::<BTreeMap<_, _>>())
+}
+
+// What a source file looked like when it was indexed, so an incremental run
+// can tell whether it changed: by its modification time and size without
+// reading it, or else by the hash of its content.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileStamp {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stat: Option<FileStat>,
+    pub hash: String,
+}
+
+// By the key of the documents of the file, or of the page of a URL.
+pub type FileStamps = BTreeMap<DocKey, FileStamp>;
+
+// Old path of a moved file → its current path.
+pub type Aliases = BTreeMap<DocKey, DocKey>;
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(from = "StoredModel")]
+pub struct Model {
+    pub manifest: Manifest,
+    #[serde(serialize_with = "docs_by_path")]
+    pub docs: TermFreqIndex,
+}
+
+impl Model {
+    // An empty index analyzing its documents as `config` says.
+    pub fn new(config: IndexConfig) -> Self {
+        Self {
+            manifest: Manifest {
+                config,
+                ..Manifest::default()
+            },
+            docs: TermFreqIndex::new(),
+        }
+    }
+
+    // The analyzer the index was built with, for analyzing queries against it.
+    pub fn analyzer(&self) -> Analyzer {
+        Analyzer::new(&self.manifest.config)
+    }
+
+    // The analyzer of the documents, which unlike that of queries keeps the
+    // stopwords of indexes built with `index_stopwords`.
+    pub fn document_analyzer(&self) -> Analyzer {
+        Analyzer::for_documents(&self.manifest.config)
+    }
+
+    // The path the document once at `path` is at now, if it moved: `path`
+    // itself unless an alias names it, and a section `file#anchor` follows
+    // its file.
+    pub fn resolve_alias(&self, path: &Path) -> Option<PathBuf> {
+        let aliases = &self.manifest.aliases;
+        if aliases.is_empty() || self.doc(path).is_some() {
+            return None;
+        }
+        let path = path.to_str()?;
+        if let Some(target) = aliases.get(path) {
+            return Some(target.to_path_buf());
+        }
+        let (file, anchor) = path.split_once('#')?;
+        let target = aliases.get(file)?;
+        Some(PathBuf::from(format!("{target}#{anchor}")))
+    }
+
+    // Whether the document is a leftover at the old path of a file that
+    // moved, which results leave out in favour of the file's new path.
+    pub fn is_moved(&self, path: &Path) -> bool {
+        let aliases = &self.manifest.aliases;
+        if aliases.is_empty() {
+            return false;
+        }
+        let path = path.to_string_lossy();
+        let file = path.split_once('#').map_or(&*path, |(file, _)| file);
+        aliases.contains_key(file)
+    }
+
+    // The document keyed by the text of `path`, as the paths of results and
+    // requests keep it.
+    pub fn doc(&self, path: &Path) -> Option<&Doc> {
+        self.docs.get(&*path.to_string_lossy())
+    }
+
+    pub fn doc_mut(&mut self, path: &Path) -> Option<&mut Doc> {
+        self.docs.get_mut(&*path.to_string_lossy())
+    }
+
+    // The ID of the document at `path`, or a new one if there is none.
+    pub fn doc_id_for(&mut self, path: &Path) -> DocId {
+        match self.doc(path) {
+            Some(doc) if doc.id != 0 => doc.id,
+            _ => {
+                let id = self.manifest.next_doc_id.max(1);
+                self.manifest.next_doc_id = id + 1;
+                id
+            }
+        }
+    }
+
+    // Adds the document, replacing any document of that path, under the ID
+    // it has or else that of the document it replaces.
+    pub fn insert_doc(&mut self, key: impl Into<DocKey>, mut doc: Doc) {
+        let key = key.into();
+        if doc.id == 0 {
+            doc.id = self.doc_id_for(&key);
+        }
+        self.docs.insert(key, doc);
+    }
+
+    // Numbers the documents that have no ID, those of indexes written before
+    // documents had IDs, in path order. New IDs also come after every ID in
+    // use, as appending a segment does not update the stored manifest.
+    pub fn assign_doc_ids(&mut self) {
+        let highest = self.docs.values().map(|doc| doc.id).max().unwrap_or(0);
+        self.manifest.next_doc_id = self.manifest.next_doc_id.max(highest + 1);
+        let mut unnumbered = self
+            .docs
+            .iter()
+            .filter(|(_, doc)| doc.id == 0)
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+        unnumbered.sort();
+        for path in unnumbered {
+            let id = self.doc_id_for(&path);
+            if let Some(doc) = self.docs.get_mut(&path) {
+                doc.id = id;
+            }
+        }
+    }
+
+    // Analyzes `content` and adds it as the document `path`, replacing any
+    // document of that path. Adding many documents is cheaper through an
+    // `IndexWriter`, which builds the analyzer once.
+    pub fn add_document(&mut self, path: impl Into<PathBuf>, content: &str, mut meta: Metadata) {
+        let analyzer = self.document_analyzer();
+        language::tag(&mut meta, content);
+        let mut doc = Doc {
+            tf: index_document(&analyzer, content),
+            meta,
+            positions: term_positions(&analyzer, content, usize::MAX),
+            ..Doc::default()
+        };
+        schema::index_fields(&self.manifest.config.fields, &analyzer, &mut doc);
+        self.insert_doc(path.into(), doc);
+    }
+
+    // The documents matching `query`, in the syntax of the search subcommand,
+    // ranked by TF-IDF, best first. Fails if the query is malformed or
+    // expands to too many terms.
+    pub fn search_query(&self, query: &str) -> Result<Vec<(&Path, f32)>, Error> {
+        let parsed = query::parse(query, &self.analyzer())?;
+        let stats = CorpusStats::of(self);
+        let parsed = parsed
+            .expand(&stats, &QueryLimits::default())
+            .map_err(Error::Invalid)?;
+        Ok(search::search_query(self, &stats, &TfIdf, &parsed, &[]))
+    }
+
+    // Reads the index at `index_path`, in the format its extension names:
+    // `.tsidx`, `.sqlite` or otherwise JSON.
+    pub fn load(index_path: &str) -> Result<Self, Error> {
+        store::open_store(index_path).open_readonly()
+    }
+
+    // Replaces the index at `index_path` with this one.
+    pub fn save(&self, index_path: &str) -> Result<(), Error> {
+        store::open_store(index_path).save(self)
+    }
+}
+
+// Indexes written before the manifest existed are a bare map of documents.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredModel {
+    Model {
+        #[serde(default)]
+        manifest: Box<Manifest>,
+        docs: TermFreqIndex,
+    },
+    Docs(TermFreqIndex),
+}
+
+impl From<StoredModel> for Model {
+    fn from(stored: StoredModel) -> Self {
+        let mut model = match stored {
+            StoredModel::Model { manifest, docs } => Self {
+                manifest: *manifest,
+                docs,
+            },
+            StoredModel::Docs(docs) => Self {
+                manifest: Manifest::default(),
+                docs,
+            },
+        };
+        model.assign_doc_ids();
+        model
+    }
+}
+
+pub fn index_document(analyzer: &Analyzer, content: &str) -> TermFreq {
+    let mut tf = TermFreq::default();
+    // Terms are only allocated the first time they are seen in a document.
+    analyzer.for_each_term(content, |term| {
+        if let Some(freq) = tf.get_mut(term) {
+            *freq += 1;
+        } else {
+            tf.insert(term.to_string(), 1);
+        }
+    });
+    tf
+}
+
+// Like `index_document`, but only the first `max_tokens` terms are counted.
+// Also returns how many terms the whole text has.
+pub fn index_document_truncated(
+    analyzer: &Analyzer,
+    content: &str,
+    max_tokens: usize,
+) -> (TermFreq, usize) {
+    let mut tf = TermFreq::default();
+    let mut tokens = 0;
+    analyzer.for_each_term(content, |term| {
+        tokens += 1;
+        if tokens > max_tokens {
+            return;
+        }
+        if let Some(freq) = tf.get_mut(term) {
+            *freq += 1;
+        } else {
+            tf.insert(term.to_string(), 1);
+        }
+    });
+    (tf, tokens)
+}
+
+// Where the first `max_tokens` terms of the text occur, if the analyzer
+// records positions, else nothing. Terms dropped by the analysis take up no
+// position, so a phrase matches across the stopwords it contains.
+pub fn term_positions(analyzer: &Analyzer, content: &str, max_tokens: usize) -> Positions {
+    let mut positions = Positions::default();
+    if !analyzer.records_positions() {
+        return positions;
+    }
+    let mut position = 0;
+    analyzer.for_each_term(content, |term| {
+        if position < max_tokens {
+            let at = position as u32;
+            if let Some(list) = positions.get_mut(term) {
+                list.push(at);
+            } else {
+                positions.insert(term.to_string(), vec![at]);
+            }
+        }
+        position += 1;
+    });
+    positions
+}
+
+pub fn save_model(model: &Model, index_path: &str) -> Result<(), Error> {
+    println!("Saving {index_path}...");
+    model.save(index_path)
+}
+
+pub fn load_model(index_path: &str) -> Result<Model, Error> {
+    // Progress goes to stderr so machine-readable output on stdout stays clean.
+    eprintln!("Reading {index_path} index file...");
+    Model::load(index_path)
+}
+
+// Like `load_model`, given the index as it was last read, of which a sharded
+// index only reads the shards that changed.
+pub fn reload_model(index_path: &str, old: &Model) -> Result<Model, Error> {
+    eprintln!("Reading {index_path} index file...");
+    store::open_store(index_path).reload(old)
+}
+
+// The RFC 3339 date of a document from its metadata, if it has one.
+pub fn document_date<'a>(model: &'a Model, path: &Path) -> Option<&'a str> {
+    let meta = &model.doc(path)?.meta;
+    locale::DATE_KEYS
+        .iter()
+        .find_map(|key| meta.get(*key)?.first())
+}
+
+// The URL a web document is known under, its canonical URL if the page
+// names one, else the URL it was fetched from.
+pub fn document_url<'a>(model: &'a Model, path: &Path) -> Option<&'a str> {
+    model.doc(path)?.meta.get("url")?.first()
+}
+
+// Whether only the first tokens of a document were indexed.
+pub fn is_truncated(model: &Model, path: &Path) -> bool {
+    model
+        .doc(path)
+        .is_some_and(|doc| doc.meta.contains_key("truncated"))
+}
@@ -0,0 +1,159 @@
+// How dates and sizes are shown to people. Stored and machine-readable values
+// are always RFC 3339 timestamps and plain byte counts; this only decides how
+// they are displayed: as ISO 8601 and IEC units, or the way the user's locale
+// writes them. TINYSEARCH_FORMAT=iso or =locale picks one, by default the
+// locale of LC_ALL, LC_TIME or LANG is followed when there is one.
+use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Metadata keys whose values are timestamps, the most telling first: the
+// modification time of the file only says when it was last saved.
+pub const DATE_KEYS: &[&str] = &["timestamp", "taken_at", "mtime"];
+
+// Formats seconds since the Unix epoch as an RFC 3339 UTC timestamp, so that
+// timestamps from different exports compare correctly as strings.
+pub fn rfc3339_from_unix(secs: i64) -> String {
+    let days = secs.div_euclid(86400);
+    let rem = secs.rem_euclid(86400);
+    // Civil date from days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+pub fn rfc3339_from_system_time(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() as i64);
+    rfc3339_from_unix(secs)
+}
+
+pub fn now_rfc3339() -> String {
+    rfc3339_from_system_time(SystemTime::now())
+}
+
+#[derive(Clone, Copy)]
+enum DateOrder {
+    YearMonthDay,
+    DayMonthYear,
+    MonthDayYear,
+}
+
+#[derive(Clone, Copy)]
+struct LocaleFormat {
+    order: DateOrder,
+    separator: char,
+    decimal_comma: bool,
+}
+
+impl LocaleFormat {
+    // From a POSIX locale name like `de_DE.UTF-8`. None for C and POSIX, and
+    // for languages without a known convention, which fall back to ISO.
+    fn from_name(name: &str) -> Option<Self> {
+        let name = name.split(['.', '@']).next().unwrap_or_default();
+        let (language, region) = name.split_once('_').unwrap_or((name, ""));
+        use DateOrder::*;
+        let (order, separator) = match (language, region) {
+            ("en", "US") => (MonthDayYear, '/'),
+            ("en", "CA") | ("sv" | "lt", _) => (YearMonthDay, '-'),
+            ("en", _) | ("fr" | "es" | "it" | "pt" | "el" | "ca", _) => (DayMonthYear, '/'),
+            ("nl", _) => (DayMonthYear, '-'),
+            (
+                "de" | "ru" | "uk" | "pl" | "cs" | "sk" | "fi" | "nb" | "nn" | "no" | "da" | "tr"
+                | "ro",
+                _,
+            ) => (DayMonthYear, '.'),
+            ("ja" | "zh", _) => (YearMonthDay, '/'),
+            ("ko" | "hu", _) => (YearMonthDay, '.'),
+            _ => return None,
+        };
+        let decimal_comma = !matches!(language, "en" | "ja" | "zh" | "ko");
+        Some(Self {
+            order,
+            separator,
+            decimal_comma,
+        })
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Format {
+    locale: Option<LocaleFormat>,
+}
+
+impl Format {
+    pub fn iso() -> Self {
+        Self { locale: None }
+    }
+
+    pub fn detect() -> Self {
+        if env::var("TINYSEARCH_FORMAT").is_ok_and(|format| format == "iso") {
+            return Self::iso();
+        }
+        let locale = ["LC_ALL", "LC_TIME", "LANG"]
+            .iter()
+            .find_map(|var| env::var(var).ok().filter(|value| !value.is_empty()));
+        Self {
+            locale: locale.as_deref().and_then(LocaleFormat::from_name),
+        }
+    }
+
+    // An RFC 3339 timestamp for display. Anything else is shown unchanged.
+    pub fn date(&self, timestamp: &str) -> String {
+        let Some(locale) = self.locale else {
+            return timestamp.to_string();
+        };
+        let field = |range: std::ops::Range<usize>| {
+            timestamp
+                .get(range)
+                .filter(|digits| digits.bytes().all(|b| b.is_ascii_digit()))
+        };
+        let (Some(year), Some(month), Some(day)) = (field(0..4), field(5..7), field(8..10)) else {
+            return timestamp.to_string();
+        };
+        let sep = locale.separator;
+        let mut date = match locale.order {
+            DateOrder::YearMonthDay => format!("{year}{sep}{month}{sep}{day}"),
+            DateOrder::DayMonthYear => format!("{day}{sep}{month}{sep}{year}"),
+            DateOrder::MonthDayYear => format!("{month}{sep}{day}{sep}{year}"),
+        };
+        if let (Some(hour), Some(minute)) = (field(11..13), field(14..16)) {
+            date.push_str(&format!(" {hour}:{minute}"));
+            if timestamp.ends_with('Z') {
+                date.push_str(" UTC");
+            }
+        }
+        date
+    }
+
+    // A byte count in IEC units, like `1.5 MiB`.
+    pub fn size(&self, bytes: u64) -> String {
+        const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+        let mut value = bytes as f64;
+        let mut unit = 0;
+        while value >= 1024.0 && unit + 1 < UNITS.len() {
+            value /= 1024.0;
+            unit += 1;
+        }
+        if unit == 0 {
+            return format!("{bytes} B");
+        }
+        let mut size = format!("{value:.1} {}", UNITS[unit]);
+        if self.locale.is_some_and(|locale| locale.decimal_comma) {
+            size = size.replace('.', ",");
+        }
+        size
+    }
+}
@@ -0,0 +1,71 @@
+// Advisory locks keeping two writers of an index, like two index runs or an
+// index run and serve --watch, from writing it at once, each saving over the
+// documents of the other. The lock is taken on `<index>.lock`, which holds
+// the PID of the process holding it for the error of those refused. The
+// system releases the lock when its holder exits, however it exits, so a
+// lock file left behind locks nothing.
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::Error;
+
+pub struct IndexLock {
+    // Holding the file holds the lock.
+    _file: File,
+}
+
+pub fn lock_path(index_path: &str) -> PathBuf {
+    PathBuf::from(format!("{index_path}.lock"))
+}
+
+impl IndexLock {
+    // Fails, with the PID of the holder, while another process holds it.
+    pub fn acquire(index_path: &str) -> Result<Self, Error> {
+        let (mut file, path) = open(index_path)?;
+        match file.try_lock() {
+            Ok(()) => Self::held(file, &path),
+            Err(TryLockError::WouldBlock) => {
+                let mut pid = String::new();
+                // Empty if the holder has yet to write it.
+                let _ = file.read_to_string(&mut pid);
+                Err(Error::IndexLocked(
+                    index_path.to_string(),
+                    pid.trim().parse().ok(),
+                ))
+            }
+            Err(TryLockError::Error(err)) => Err(lock_error(&path, err)),
+        }
+    }
+
+    // Waits for the process holding it to be done.
+    pub fn wait(index_path: &str) -> Result<Self, Error> {
+        let (file, path) = open(index_path)?;
+        file.lock().map_err(|err| lock_error(&path, err))?;
+        Self::held(file, &path)
+    }
+
+    fn held(mut file: File, path: &Path) -> Result<Self, Error> {
+        file.set_len(0)
+            .and_then(|()| write!(file, "{}", process::id()))
+            .map_err(|err| lock_error(path, err))?;
+        Ok(Self { _file: file })
+    }
+}
+
+fn open(index_path: &str) -> Result<(File, PathBuf), Error> {
+    let path = lock_path(index_path);
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .map_err(|err| lock_error(&path, err))?;
+    Ok((file, path))
+}
+
+fn lock_error(path: &Path, err: io::Error) -> Error {
+    Error::io(format!("could not lock {path}", path = path.display()), err)
+}
@@ -0,0 +1,147 @@
+// Append-only logs of a long-running server, one JSON record per line.
+//
+// A record is written with a single write to a file opened for appending, so
+// a crash can at worst cut the last record short; the next run starts on a
+// fresh line. Data reaches the disk at least every `sync_interval`. The log is
+// rotated when it grows past `max_bytes` or a new day (UTC) starts: `log`
+// becomes `log.1`, `log.1` becomes `log.2` and so on, and only `keep` rotated
+// files are retained.
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde_json::Value;
+
+#[derive(Clone)]
+pub struct LogOptions {
+    pub max_bytes: u64,
+    pub keep: usize,
+    pub sync_interval: Duration,
+}
+
+impl Default for LogOptions {
+    fn default() -> Self {
+        Self {
+            max_bytes: 64 * 1024 * 1024,
+            keep: 7,
+            sync_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+pub struct RotatingLog {
+    path: PathBuf,
+    options: LogOptions,
+    file: File,
+    size: u64,
+    // UTC day the current file was started on.
+    day: u64,
+    // When records were first written after the last sync, if they were.
+    unsynced_since: Option<Instant>,
+}
+
+fn day_of(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() / 86400)
+}
+
+fn rotated_path(path: &Path, generation: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{generation}"));
+    PathBuf::from(name)
+}
+
+impl RotatingLog {
+    pub fn open(path: &Path, options: LogOptions) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .read(true)
+            .open(path)?;
+        let metadata = file.metadata()?;
+        let size = metadata.len();
+        let day = day_of(metadata.modified().unwrap_or_else(|_| SystemTime::now()));
+        // A record cut short by a crash is left alone, but must not swallow
+        // the next one.
+        if size > 0 {
+            let mut last = [0];
+            file.seek(SeekFrom::End(-1))?;
+            file.read_exact(&mut last)?;
+            if last != *b"\n" {
+                file.write_all(b"\n")?;
+            }
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            options,
+            file,
+            size,
+            day,
+            unsynced_since: None,
+        })
+    }
+
+    pub fn append(&mut self, record: &Value) -> io::Result<()> {
+        let mut line = record.to_string();
+        line.push('\n');
+        let today = day_of(SystemTime::now());
+        if self.size > 0
+            && (self.size + line.len() as u64 > self.options.max_bytes || today != self.day)
+        {
+            self.rotate()?;
+            self.day = today;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        self.unsynced_since.get_or_insert_with(Instant::now);
+        self.sync_if_due()
+    }
+
+    // Flushes records older than the sync interval to disk. Servers call it
+    // when idle too, so quiet periods do not leave records unsynced.
+    pub fn sync_if_due(&mut self) -> io::Result<()> {
+        match self.unsynced_since {
+            Some(since) if since.elapsed() >= self.options.sync_interval => self.sync(),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn sync(&mut self) -> io::Result<()> {
+        if self.unsynced_since.take().is_some() {
+            self.file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.sync()?;
+        if self.options.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let oldest = rotated_path(&self.path, self.options.keep);
+            if oldest.exists() {
+                fs::remove_file(&oldest)?;
+            }
+            for generation in (1..self.options.keep).rev() {
+                let from = rotated_path(&self.path, generation);
+                if from.exists() {
+                    fs::rename(&from, rotated_path(&self.path, generation + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Drop for RotatingLog {
+    fn drop(&mut self) {
+        self.sync().ok();
+    }
+}
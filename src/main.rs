@@ -1,282 +1,4153 @@
-use std::collections::HashMap;
+use serde_json::json;
+use std::collections::VecDeque;
 use std::env;
-use std::fs::{self, File};
+use std::fmt::Display;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Cursor, IsTerminal, Read, Write};
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
-use std::process::ExitCode;
+use std::process::{self, ExitCode};
 use std::result::Result;
-use std::str;
+use std::str::{self, FromStr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tiny_http::{Header, Method, Request, Response, Server};
-use xml::common::{Position, TextPosition};
-use xml::reader::{EventReader, XmlEvent};
+use tracing::level_filters::LevelFilter;
+use tracing::{info, info_span};
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
 
-struct Lexer<'a> {
-    content: &'a [char],
+mod api;
+mod doctor;
+mod export;
+mod frontend;
+mod health;
+mod http;
+mod indexd;
+mod logfile;
+mod memlimit;
+mod open;
+mod output;
+mod popularity;
+mod privacy;
+mod progress;
+#[cfg(feature = "repl")]
+mod repl;
+mod resultsets;
+mod router;
+mod site;
+mod slowlog;
+mod snapshot;
+mod warmup;
+#[cfg(feature = "watch")]
+mod watch;
+
+use export::ExportFormat;
+use frontend::{Frontend, FrontendConfig};
+use health::{HealthHistory, DEFAULT_HEALTH_INTERVAL};
+use logfile::{LogOptions, RotatingLog};
+use memlimit::MemoryLimit;
+use popularity::Popularity;
+use privacy::{QueryLogging, QueryPrivacy, Redaction};
+use resultsets::{ResultSets, DEFAULT_KEPT_RESULT_SETS, DEFAULT_RESULT_SET_TTL};
+use router::Route;
+use snapshot::{SnapshotOptions, Snapshots};
+use tinysearch::analyzer::Analyzer;
+use tinysearch::bundle::SourceBundle;
+use tinysearch::config::{IndexConfig, IndexConfigBuilder, Profiles, Stemmer, Tokenizer};
+use tinysearch::crawl::{CrawlOptions, CrawlSource};
+use tinysearch::exclude::PathPattern;
+use tinysearch::feedback::Expansion;
+use tinysearch::filter::{self, Filter};
+use tinysearch::handle::{CacheSizes, SearchHandle, SearchResults};
+use tinysearch::import::{self, ImportFormat, ImportOptions};
+use tinysearch::indexer::{self, IndexOptions, OverTokenLimit, Progress, Pruning, Verbosity};
+use tinysearch::lock::IndexLock;
+use tinysearch::merge::{self, OnDuplicate};
+use tinysearch::plugins::Plugins;
+use tinysearch::provenance::{self, BuildManifest, InputHashes, InputState};
+use tinysearch::query::{self, Query, QueryLimits, Typos};
+use tinysearch::report::IndexReport;
+use tinysearch::rewrite::QueryRewriter;
+use tinysearch::scoring::{MinScore, Ranking};
+use tinysearch::source::{
+    ArchiveSource, DocumentSource, FolderSource, GitSource, HttpSource, SampledSource,
+};
+use tinysearch::stats::{self, AnalysisStats, IndexStats};
+use tinysearch::store::StoreFormat;
+use tinysearch::vocab::{self, Vocabulary};
+use tinysearch::writer::IndexWriter;
+use tinysearch::{
+    config, diff, eval, exclude, extract, fsck, locale, memory, percolate, schema, snippet, source,
+};
+use tinysearch::{
+    document_date, document_url, index_document, is_truncated, load_model, Error, ErrorCode, Model,
+    INDEX_VERSION,
+};
+use warmup::WarmUp;
+#[cfg(feature = "watch")]
+use watch::{FolderWatch, WatchStrategy};
+
+fn check_index(index_path: &str, filters: &[Filter]) -> Result<(), ()> {
+    let handle = SearchHandle::open(index_path, CacheSizes::default()).map_err(print_error)?;
+    let stats = handle.stats();
+    let format = locale::Format::detect();
+    let file = fs::metadata(index_path)
+        .ok()
+        .map(|metadata| {
+            let modified = metadata
+                .modified()
+                .map(|time| format.date(&locale::rfc3339_from_system_time(time)))
+                .unwrap_or_default();
+            format!(
+                " ({size}, modified {modified})",
+                size = format.size(metadata.len())
+            )
+        })
+        .unwrap_or_default();
+    println!(
+        "{index_path}{file} contains {docs} files with {terms} distinct terms in {postings} postings",
+        docs = stats.docs,
+        terms = stats.terms,
+        postings = stats.postings
+    );
+
+    if !filters.is_empty() {
+        let model = handle.snapshot();
+        let schema = &model.manifest.config.fields;
+        let matching = model
+            .docs
+            .values()
+            .filter(|doc| filter::matches_all(filters, schema, doc))
+            .count();
+        println!("{matching} of them match the filters");
+    }
+
+    Ok(())
+}
+
+fn print_stats(index_path: &str, size: u64, stats: &IndexStats, as_json: bool) {
+    if as_json {
+        let mut payload = json!(stats);
+        payload["index"] = json!(index_path);
+        payload["size"] = json!(size);
+        println!("{}", serde_json::to_string_pretty(&payload).unwrap());
+        return;
+    }
+    let format = locale::Format::detect();
+    println!(
+        "Index:            {index_path} ({size})",
+        size = format.size(size)
+    );
+    println!("Documents:        {}", stats.docs);
+    println!("Terms:            {}", stats.terms);
+    println!("Unique terms:     {}", stats.unique_terms);
+    println!("Postings:         {}", stats.postings);
+    println!(
+        "Document length:  {avg:.1} terms on average, {max} at most",
+        avg = stats.avg_doc_len,
+        max = stats.max_doc_len
+    );
+    if stats.top_terms.is_empty() {
+        return;
+    }
+    println!("Most frequent terms:");
+    let width = stats
+        .top_terms
+        .iter()
+        .map(|term| term.term.chars().count())
+        .max()
+        .unwrap_or(0);
+    for term in &stats.top_terms {
+        println!(
+            "  {name:<width$}  {count:>10} times in {docs} documents",
+            name = term.term,
+            count = term.count,
+            docs = term.docs
+        );
+    }
 }
 
-impl<'a> Lexer<'a> {
-    fn new(content: &'a [char]) -> Self {
-        Self { content }
+// Documents compare-analyzers indexes unless --sample says otherwise.
+const DEFAULT_COMPARISON_SAMPLE: usize = 1000;
+
+// The analyses compare-analyzers indexed the sample with, side by side, with
+// how much the second one changes every number.
+fn print_analysis_comparison(compared: &[(String, AnalysisStats); 2], as_json: bool) {
+    let [(a_name, a), (b_name, b)] = compared;
+    if as_json {
+        let payload = json!({
+            "docs": a.docs,
+            "a": {"analyzer": a_name, "stats": a},
+            "b": {"analyzer": b_name, "stats": b},
+        });
+        println!("{}", serde_json::to_string_pretty(&payload).unwrap());
+        return;
     }
-    fn trim_left(&mut self) {
-        while self.content.len() > 0 && self.content[0].is_whitespace() {
-            self.content = &self.content[1..];
+    let format = locale::Format::detect();
+    let change = |a: f64, b: f64| {
+        if a == 0.0 {
+            "-".to_string()
+        } else {
+            format!("{:+.1}%", (b - a) / a * 100.0)
         }
+    };
+    let rows = [
+        (
+            "Unique terms",
+            a.unique_terms.to_string(),
+            b.unique_terms.to_string(),
+            change(a.unique_terms as f64, b.unique_terms as f64),
+        ),
+        (
+            "Postings",
+            a.postings.to_string(),
+            b.postings.to_string(),
+            change(a.postings as f64, b.postings as f64),
+        ),
+        (
+            "Postings per term",
+            format!("{:.2}", a.avg_postings_len),
+            format!("{:.2}", b.avg_postings_len),
+            change(a.avg_postings_len, b.avg_postings_len),
+        ),
+        (
+            "Index size",
+            format.size(a.index_size),
+            format.size(b.index_size),
+            change(a.index_size as f64, b.index_size as f64),
+        ),
+    ];
+    let width = |column: fn(&(&str, String, String, String)) -> usize, header: &str| {
+        rows.iter().map(column).max().unwrap_or(0).max(header.len())
+    };
+    let a_width = width(|row| row.1.len(), a_name);
+    let b_width = width(|row| row.2.len(), b_name);
+    println!("{docs} documents sampled", docs = a.docs);
+    println!("{:<18}  {a_name:>a_width$}  {b_name:>b_width$}  change", "");
+    for (name, a, b, change) in &rows {
+        println!("{name:<18}  {a:>a_width$}  {b:>b_width$}  {change:>6}");
     }
+}
 
-    fn chop(&mut self, n: usize) -> &'a [char] {
-        let token = &self.content[0..n];
-        self.content = &self.content[n..];
-        return &token;
+fn print_vocabulary(vocabulary: &Vocabulary, as_json: bool) -> Result<(), ()> {
+    if as_json {
+        println!("{}", serde_json::to_string_pretty(vocabulary).unwrap());
+        return Ok(());
+    }
+    let mut stdout = io::stdout().lock();
+    for term in &vocabulary.terms {
+        writeln!(stdout, "{}\t{}\t{}", term.term, term.count, term.docs)
+            .map_err(|err| eprintln!("ERROR: could not print the vocabulary: {err}"))?;
+    }
+    if vocabulary.stopwords.is_empty() {
+        eprintln!("No term is common enough to suggest as a stopword");
+    } else {
+        eprintln!(
+            "Suggested stopwords, in the most of the {docs} documents first: {words}",
+            docs = vocabulary.docs,
+            words = vocabulary.stopwords.join(", ")
+        );
     }
+    Ok(())
+}
 
-    fn chop_while<P>(&mut self, mut predicate: P) -> &'a [char]
-    where
-        P: FnMut(&char) -> bool,
-    {
-        let mut idx = 0;
-        while idx < self.content.len() && predicate(&self.content[idx]) {
-            idx += 1;
-        }
-        self.chop(idx)
+// The suggested stopwords as a list `--stopwords` reads.
+fn write_stopwords(path: &str, index_path: &str, vocabulary: &Vocabulary) -> Result<(), ()> {
+    let mut text = format!("# Terms in most documents of {index_path}\n");
+    for word in &vocabulary.stopwords {
+        text.push_str(word);
+        text.push('\n');
     }
+    fs::write(path, text)
+        .map_err(|err| print_error(Error::io(format!("could not write stopwords {path}"), err)))?;
+    eprintln!(
+        "Wrote {count} stopwords to {path}",
+        count = vocabulary.stopwords.len()
+    );
+    Ok(())
+}
 
-    fn next_token(&mut self) -> Option<&'a [char]> {
-        // trim whitespaces from left.
-        self.trim_left();
-        if self.content.len() == 0 {
-            return None;
-        }
+// How many matching line numbers are reported per document.
+const MAX_REPORTED_LINES: usize = 20;
+
+// How many problems fsck prints before summing up the rest.
+const MAX_REPORTED_PROBLEMS: usize = 50;
+
+struct SearchOptions {
+    filters: Vec<Filter>,
+    limit: usize,
+    // Results skipped before the first one printed.
+    offset: usize,
+    // 1-based page of `limit` results, replacing `offset`.
+    page: Option<usize>,
+    // Report the numbers of the lines containing query terms.
+    lines: bool,
+    // Open the result with this 1-based rank after printing.
+    open_rank: Option<usize>,
+    // Print only `path<TAB>score` lines, for scripts.
+    plain: bool,
+    // Show the first match with this many words around it instead of the
+    // best passage.
+    context: Option<usize>,
+    // Also write the results to this file, as CSV or Markdown.
+    export: Option<(String, ExportFormat)>,
+    ranking: Ranking,
+    min_score: Option<MinScore>,
+    expand: Option<Expansion>,
+    keep_stopwords: bool,
+    rewriter: Option<Arc<QueryRewriter>>,
+    cache_sizes: CacheSizes,
+    limits: QueryLimits,
+    // The analysis the index is expected to use, from --tokenizer, --stopwords
+    // and --stemmer.
+    analyzer: Option<IndexConfigBuilder>,
+    // Search with the analysis of the index even if it differs from `analyzer`.
+    adopt_index_analyzer: bool,
+}
 
-        if self.content[0].is_numeric() {
-            return Some(self.chop_while(|idx| idx.is_numeric()));
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            filters: Vec::new(),
+            limit: 10,
+            offset: 0,
+            page: None,
+            lines: false,
+            open_rank: None,
+            plain: false,
+            context: None,
+            export: None,
+            ranking: Ranking::default(),
+            min_score: None,
+            expand: None,
+            keep_stopwords: false,
+            rewriter: None,
+            cache_sizes: CacheSizes::default(),
+            limits: QueryLimits::default(),
+            analyzer: None,
+            adopt_index_analyzer: false,
         }
+    }
+}
 
-        if self.content[0].is_alphabetic() {
-            return Some(self.chop_while(|idx| idx.is_alphabetic()));
+impl SearchOptions {
+    fn offset(&self) -> usize {
+        match self.page {
+            Some(page) => page.saturating_sub(1).saturating_mul(self.limit),
+            None => self.offset,
         }
-        return Some(self.chop(1));
     }
 }
 
-impl<'a> Iterator for Lexer<'a> {
-    type Item = &'a [char];
+// Flags shared by the subcommands that rank and print results.
+fn parse_search_flag(
+    args: &mut impl Iterator<Item = String>,
+    program: &str,
+    flag: &str,
+    options: &mut SearchOptions,
+) -> Result<(), ()> {
+    match flag {
+        "--filter" => {
+            let source = flag_value(args, program, flag)?;
+            options
+                .filters
+                .push(Filter::parse(&source).map_err(print_error)?);
+        }
+        "--limit" => options.limit = parse_flag(args, program, flag)?,
+        "--offset" => options.offset = parse_flag(args, program, flag)?,
+        "--page" => options.page = Some(parse_flag(args, program, flag)?),
+        "--lines" => options.lines = true,
+        "--plain" => options.plain = true,
+        "--context" => options.context = Some(parse_flag(args, program, flag)?),
+        "--open" => options.open_rank = Some(parse_flag(args, program, flag)?),
+        "--export" => {
+            let path = flag_value(args, program, flag)?;
+            let format = ExportFormat::from_path(&path).ok_or_else(|| {
+                eprintln!("ERROR: cannot tell the format of {path}, name it .csv or .md")
+            })?;
+            options.export = Some((path, format));
+        }
+        "--postings-cache" => options.cache_sizes.postings = parse_flag(args, program, flag)?,
+        "--result-cache" => options.cache_sizes.results = parse_flag(args, program, flag)?,
+        "--filter-cache" => options.cache_sizes.filters = parse_flag(args, program, flag)?,
+        "--max-expansions" => options.limits.max_expansions = parse_flag(args, program, flag)?,
+        "--max-clauses" => options.limits.max_clauses = parse_flag(args, program, flag)?,
+        "--typos" => options.limits.typos = parse_typos(args, program, flag)?,
+        "--fuzzy" => options.limits.fuzzy = true,
+        "--ranking" => options.ranking = parse_ranking(args, program, flag)?,
+        "--min-score" => options.min_score = Some(parse_min_score(args, program, flag)?),
+        "--rewrites" => {
+            let path = flag_value(args, program, flag)?;
+            options.rewriter = Some(Arc::new(QueryRewriter::load(&path).map_err(print_error)?));
+        }
+        "--expand" => {
+            let value = flag_value(args, program, flag)?;
+            options.expand = Some(Expansion::parse(&value).ok_or_else(|| {
+                eprintln!("ERROR: invalid value {value} for {flag}, expected prf")
+            })?);
+        }
+        "--tokenizer" | "--joiners" | "--stopwords" | "--stemmer" | "--profile" => {
+            let config = options.analyzer.take().unwrap_or_default();
+            options.analyzer = Some(parse_config_flag(args, program, flag, config)?);
+        }
+        "--adopt-index-analyzer" => options.adopt_index_analyzer = true,
+        "--keep-stopwords" => options.keep_stopwords = true,
+        _ => {
+            usage(program);
+            eprintln!("ERROR: unknown flag {flag}");
+            return Err(());
+        }
+    }
+    Ok(())
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        self.next_token()
+// Queries are always analyzed like the index they run against. When the
+// caller asked for other settings the index would silently answer a
+// different question, so that fails unless the index settings are adopted.
+fn check_analyzer(
+    index_path: &str,
+    handle: &SearchHandle,
+    requested: Option<IndexConfigBuilder>,
+    adopt: bool,
+) -> Result<(), ()> {
+    let Some(requested) = requested else {
+        return Ok(());
+    };
+    let differences = handle.config().differences(&requested.build());
+    if differences.is_empty() {
+        return Ok(());
+    }
+    let level = if adopt { "WARNING" } else { "ERROR" };
+    eprintln!("{level}: {index_path} was built with other analyzer settings than requested:");
+    for difference in &differences {
+        eprintln!("{level}:   {difference}");
+    }
+    if adopt {
+        eprintln!("WARNING: searching with the settings of the index");
+        return Ok(());
     }
+    eprintln!("ERROR: rebuild the index with the requested settings, or pass --adopt-index-analyzer to use those of the index");
+    Err(())
 }
 
-fn index_document(_doc_content: &str) -> HashMap<String, usize> {
-    todo!("not implemented");
+// The page of matches the options ask for, best first, and the number of all
+// matches.
+fn search_page(
+    handle: &SearchHandle,
+    query: &Query,
+    options: &SearchOptions,
+) -> (SearchResults, usize) {
+    let mut hits = handle.search(query, &options.filters, usize::MAX);
+    if let Some(min_score) = handle.min_score() {
+        min_score.apply(&mut hits);
+    }
+    let total = hits.len();
+    let page = hits
+        .into_iter()
+        .skip(options.offset())
+        .take(options.limit)
+        .collect();
+    (page, total)
 }
 
-fn parse_entire_xml_file(file_path: &Path) -> Result<String, ()> {
-    let file = File::open(file_path).map_err(|err| {
-        eprintln!(
-            "ERROR: could not open file {file_path} due to {err}",
-            file_path = file_path.display()
-        );
+fn search_and_print(handle: &SearchHandle, query: &str, options: &SearchOptions) -> Result<(), ()> {
+    let style = output::Style::detect();
+    let analyzer = handle
+        .query_analyzer(options.keep_stopwords)
+        .map_err(print_error)?;
+    let rewritten = handle.rewrite(query);
+    let parsed = query::parse(&rewritten, &analyzer).map_err(|err| {
+        eprintln!("{}", style.error(&err.render(&rewritten)));
+    })?;
+    analyzer.check_filters().map_err(print_error)?;
+    let parsed = handle.expand(parsed, &options.limits).map_err(|err| {
+        eprintln!("{}", style.error(&format!("error: {err}")));
     })?;
-    let er = EventReader::new(file);
-    let mut content = String::new();
-    for event in er.into_iter() {
-        let event = event.map_err(|err| {
-            let TextPosition { row, column } = err.position();
-            let msg = err.msg();
+    let parsed = match options.expand {
+        Some(Expansion::Prf) => handle.expand_feedback(parsed, &options.filters),
+        None => parsed,
+    };
+    let terms = parsed.positive_terms();
+    let (hits, total) = search_page(handle, &parsed, options);
+    let offset = options.offset();
+    if total == 0 {
+        eprintln!("No documents match {query}");
+        if let Some(corrected) = handle.did_you_mean(query) {
+            eprintln!("Did you mean: {corrected}");
+        }
+        return Ok(());
+    }
+    if hits.is_empty() {
+        eprintln!("No results past {offset}, {total} documents match {query}");
+        return Ok(());
+    }
+    if options.plain {
+        let mut stdout = io::stdout().lock();
+        for (path, score) in &hits {
+            writeln!(stdout, "{}\t{score}", path.display())
+                .map_err(|err| eprintln!("ERROR: could not print search results: {err}"))?;
+        }
+    } else {
+        print_hits(&hits, &terms, &analyzer, handle, options)?;
+        if hits.len() < total {
             eprintln!(
-                "{file_path}: {row}: {column}: ERROR: {msg}",
-                file_path = file_path.display()
+                "Results {first} to {last} of {total}",
+                first = offset + 1,
+                last = offset + hits.len()
             );
-        })?;
-        if let XmlEvent::Characters(text) = event {
-            content.push_str(&text);
-            content.push_str(" ");
         }
     }
-    Ok(content)
+    if let Some((export_path, format)) = &options.export {
+        let model = handle.snapshot();
+        let bundle = handle.sources();
+        let sources = bundle.as_deref().map(|bundle| bundle.sources(&model));
+        let extract = handle.extract_options();
+        let results = hits
+            .iter()
+            .map(|(path, score)| {
+                api::result(&model, path, *score, &terms, &analyzer, sources, extract)
+            })
+            .collect::<Vec<_>>();
+        fs::write(export_path, format.render(&results, offset + 1)).map_err(|err| {
+            eprintln!("ERROR: could not export the results to {export_path}: {err}")
+        })?;
+        eprintln!(
+            "Exported {count} results to {export_path}",
+            count = hits.len()
+        );
+    }
+
+    if let Some(rank) = options.open_rank {
+        // Ranks count from the first match, as printed.
+        let (path, _) = rank
+            .checked_sub(offset + 1)
+            .and_then(|i| hits.get(i))
+            .ok_or_else(|| {
+                eprintln!(
+                    "ERROR: cannot open result {rank}, the results shown are {first} to {last}",
+                    first = offset + 1,
+                    last = offset + hits.len()
+                )
+            })?;
+        open::open_result(path, &terms, &analyzer).map_err(print_error)?;
+    }
+    Ok(())
 }
 
-type TermFreq = HashMap<String, usize>;
-type TermFreqIndex = HashMap<PathBuf, TermFreq>;
+fn print_hits(
+    hits: &[(PathBuf, f32)],
+    terms: &[&str],
+    analyzer: &Analyzer,
+    handle: &SearchHandle,
+    options: &SearchOptions,
+) -> Result<(), ()> {
+    let style = output::Style::detect();
+    let model = handle.snapshot();
+    let bundle = handle.sources();
+    let sources = bundle.as_deref().map(|bundle| bundle.sources(&model));
+    let format = locale::Format::detect();
+    let extract = handle.extract_options();
+    let results = hits
+        .iter()
+        .map(|(path, score)| {
+            let title = model
+                .doc(path)
+                .and_then(|doc| doc.meta.get("title")?.first());
+            output::ResultLine {
+                path,
+                url: document_url(&model, path),
+                title,
+                score: *score,
+                date: document_date(&model, path).map(|date| format.date(date)),
+                truncated: is_truncated(&model, path),
+                lines: if options.lines {
+                    snippet::matching_lines(path, terms, MAX_REPORTED_LINES, analyzer)
+                } else {
+                    Vec::new()
+                },
+                pages: snippet::matching_pages(path, terms, analyzer, sources, extract),
+                snippet: match options.context {
+                    Some(context) => snippet::bundled_document_text(path, sources, extract)
+                        .and_then(|text| snippet::context_snippet(&text, terms, analyzer, context)),
+                    // The title has a line of its own, and a page's heading
+                    // is often its title again.
+                    None => snippet::field_snippet(path, None, terms, analyzer, sources, extract)
+                        .map(|(_, snippet)| snippet)
+                        .filter(|snippet| {
+                            let text = snippet.iter().map(|(piece, _)| piece.as_str());
+                            title.is_none_or(|title| text.collect::<String>().trim() != title)
+                        }),
+                },
+            }
+        })
+        .collect::<Vec<_>>();
+    output::print_results(&style, &results, options.offset() + 1)
+        .map_err(|err| eprintln!("ERROR: could not print search results: {err}"))
+}
 
-fn save_tf_index(tf_index: &TermFreqIndex, index_path: &str) -> Result<(), ()> {
-    println!("Saving {index_path}...");
-    let index_file = File::create(index_path).map_err(|err| {
-        eprintln!("ERROR: could not create index file {index_path}: {err}");
-    })?;
-    serde_json::to_writer(index_file, &tf_index).map_err(|err| {
-        eprintln!("ERROR: could not write to index file {index_path}: {err}");
-    })?;
+// Runs every non-empty line of the queries file (or stdin for `-`) against
+// the index loaded once, printing one JSON object per query.
+fn search_batch(index_path: &str, queries_path: &str, options: &SearchOptions) -> Result<(), ()> {
+    let handle = SearchHandle::open(index_path, options.cache_sizes)
+        .map_err(print_error)?
+        .with_ranking(options.ranking)
+        .with_min_score(options.min_score)
+        .with_rewriter(options.rewriter.clone());
+    check_analyzer(
+        index_path,
+        &handle,
+        options.analyzer.clone(),
+        options.adopt_index_analyzer,
+    )?;
+    let analyzer = handle
+        .query_analyzer(options.keep_stopwords)
+        .map_err(print_error)?;
+    let model = handle.snapshot();
+    let reader: Box<dyn BufRead> = if queries_path == "-" {
+        Box::new(io::stdin().lock())
+    } else {
+        let file = File::open(queries_path)
+            .map_err(|err| eprintln!("ERROR: could not open queries file {queries_path}: {err}"))?;
+        Box::new(BufReader::new(file))
+    };
+    let mut stdout = io::stdout().lock();
+    for line in reader.lines() {
+        let query = line
+            .map_err(|err| eprintln!("ERROR: could not read queries from {queries_path}: {err}"))?;
+        let query = query.trim();
+        if query.is_empty() {
+            continue;
+        }
+        let rewritten = handle.rewrite(query);
+        let parsed = query::parse(&rewritten, &analyzer)
+            .map_err(|err| {
+                eprintln!("{}", err.render(&rewritten));
+                api::query_error(&rewritten, &err)
+            })
+            .and_then(|parsed| {
+                handle.expand(parsed, &options.limits).map_err(|err| {
+                    eprintln!("error: {err}");
+                    api::limit_error(query, &err)
+                })
+            })
+            .map(|parsed| match options.expand {
+                Some(Expansion::Prf) => handle.expand_feedback(parsed, &options.filters),
+                None => parsed,
+            });
+        // Unlike a malformed query, a failed token filter fails every query after it.
+        analyzer.check_filters().map_err(print_error)?;
+        let line = match parsed {
+            Ok(parsed) => {
+                let terms = parsed.positive_terms();
+                let (hits, total) = search_page(&handle, &parsed, options);
+                let results = hits
+                    .into_iter()
+                    .map(|(path, score)| {
+                        let mut result = json!({"path": path, "score": score});
+                        if let Some(date) = document_date(&model, &path) {
+                            result["date"] = json!(date);
+                        }
+                        if options.lines {
+                            result["lines"] = json!(snippet::matching_lines(
+                                &path,
+                                &terms,
+                                MAX_REPORTED_LINES,
+                                &analyzer,
+                            ));
+                            result["pages"] = json!(snippet::matching_pages(
+                                &path,
+                                &terms,
+                                &analyzer,
+                                None,
+                                handle.extract_options(),
+                            ));
+                        }
+                        result
+                    })
+                    .collect::<Vec<_>>();
+                json!({
+                    "query": query,
+                    "total": total,
+                    "offset": options.offset(),
+                    "results": results,
+                })
+            }
+            Err(error) => error,
+        };
+        writeln!(stdout, "{line}")
+            .map_err(|err| eprintln!("ERROR: could not write search results: {err}"))?;
+    }
+    #[cfg(feature = "metrics")]
+    report_cache_metrics(&handle);
     Ok(())
 }
 
-fn tf_index_of_folder(dir_path: &Path, tf_index: &mut TermFreqIndex) -> Result<(), ()> {
-    let dir = fs::read_dir(dir_path).map_err(|err| {
+// How well the caches served a batch of queries.
+#[cfg(feature = "metrics")]
+fn report_cache_metrics(handle: &SearchHandle) {
+    let metrics = handle.cache_metrics();
+    eprintln!(
+        "INFO: cache hit rates: postings {postings:.1}% of {postings_lookups}, results {results:.1}% of {results_lookups}, filters {filters:.1}% of {filters_lookups}",
+        postings = metrics.postings.hit_rate() * 100.0,
+        postings_lookups = metrics.postings.hits + metrics.postings.misses,
+        results = metrics.results.hit_rate() * 100.0,
+        results_lookups = metrics.results.hits + metrics.results.misses,
+        filters = metrics.filters.hit_rate() * 100.0,
+        filters_lookups = metrics.filters.hits + metrics.filters.misses,
+    );
+}
+
+// Where users define profiles of their own, unless TINYSEARCH_PROFILES names
+// another file.
+const PROFILES_FILE: &str = "tinysearch-profiles.json";
+
+// The profile named `name`, the user's or a bundled one.
+fn find_profile(name: &str) -> Result<IndexConfig, ()> {
+    let profiles = match env::var("TINYSEARCH_PROFILES") {
+        Ok(path) => config::load_profiles(&path).map_err(print_error)?,
+        Err(_) if Path::new(PROFILES_FILE).exists() => {
+            config::load_profiles(PROFILES_FILE).map_err(print_error)?
+        }
+        Err(_) => Profiles::new(),
+    };
+    IndexConfig::profile(name, &profiles).ok_or_else(|| {
+        let names = config::PRESET_NAMES
+            .iter()
+            .copied()
+            .chain(profiles.keys().map(String::as_str))
+            .collect::<Vec<_>>();
         eprintln!(
-            "ERROR: could not open directory {dir_path} fox indexing. Read full error: {err}",
-            dir_path = dir_path.display()
+            "ERROR: unknown profile {name}, expected one of: {names}",
+            names = names.join(", ")
         );
-    })?;
-    'next_file: for file in dir {
-        let file = file.map_err(|err| {
-            eprintln!(
-                "ERROR: could not open directory {dir_path} for indexing. Read full error: {err}",
-                dir_path = dir_path.display()
-            )
-        })?;
-        let file_path = file.path();
-
-        let file_type = file.file_type().map_err(|err| {
-            eprintln!(
-                "ERROR: could not determine file type of file {file_path}. Read full error: {err}",
-                file_path = file_path.display()
-            )
-        })?;
+    })
+}
 
-        if file_type.is_dir() {
-            tf_index_of_folder(&file_path, tf_index)?;
-            continue 'next_file;
+// Flags of the subcommands that analyze text, applied to `config`.
+fn parse_config_flag(
+    args: &mut impl Iterator<Item = String>,
+    program: &str,
+    flag: &str,
+    config: IndexConfigBuilder,
+) -> Result<IndexConfigBuilder, ()> {
+    match flag {
+        "--tokenizer" => {
+            let name = flag_value(args, program, flag)?;
+            let tokenizer = Tokenizer::from_name(&name).ok_or_else(|| {
+                eprintln!("ERROR: unknown tokenizer {name}, expected default, words or code");
+            })?;
+            Ok(config.tokenizer(tokenizer))
+        }
+        "--joiners" => Ok(config.joiners(flag_value(args, program, flag)?)),
+        "--stopwords" => {
+            let value = flag_value(args, program, flag)?;
+            Ok(config.stopwords(config::parse_stopwords(&value).map_err(print_error)?))
+        }
+        "--stemmer" => {
+            let name = flag_value(args, program, flag)?;
+            let stemmer = Stemmer::from_name(&name).ok_or_else(|| {
+                eprintln!("ERROR: unknown stemmer {name}, expected none or plural");
+            })?;
+            Ok(config.stemmer(stemmer))
+        }
+        "--profile" => {
+            let name = flag_value(args, program, flag)?;
+            Ok(config.profile(find_profile(&name)?))
         }
+        _ => {
+            usage(program);
+            eprintln!("ERROR: unknown flag {flag}");
+            Err(())
+        }
+    }
+}
 
-        // TODO: Work with symlinks.
+fn parse_typos(
+    args: &mut impl Iterator<Item = String>,
+    program: &str,
+    flag: &str,
+) -> Result<Typos, ()> {
+    let value = flag_value(args, program, flag)?;
+    Typos::parse(&value).ok_or_else(|| {
+        eprintln!("ERROR: invalid value {value} for {flag}, expected auto, off, 0, 1 or 2")
+    })
+}
 
-        println!("Indexing {file_path:?}...");
+// Flags of serve that can also be set with environment variables, for
+// containers without a mounted config file: `--index-name` is read from
+// TINYSEARCH_INDEX_NAME. Switches are on for 1, true or yes.
+const SERVE_ENV_FLAGS: &[&str] = &[
+    "--index",
+    "--frontend",
+    "--title",
+    "--lang",
+    "--index-name",
+    "--robots",
+    "--templates",
+    "--static-dir",
+    "--cors-origin",
+    "--query-log",
+    "--warm-searches",
+    "--feedback-log",
+    "--read-only",
+    "--popularity",
+    "--popularity-prior",
+    "--query-logging",
+    "--query-hash-key",
+    "--max-expansions",
+    "--max-clauses",
+    "--typos",
+    "--ranking",
+    "--min-score",
+    "--rewrites",
+    "--profile",
+    "--tokenizer",
+    "--joiners",
+    "--stopwords",
+    "--stemmer",
+    "--slow-log",
+    "--slow-ms",
+    "--log-max-mb",
+    "--log-keep",
+    "--log-sync-secs",
+    "--snapshot-dir",
+    "--snapshot-hours",
+    "--snapshot-keep",
+    "--save-secs",
+    "--result-set-minutes",
+    "--result-sets",
+    "--watch",
+    "--watch-strategy",
+    "--threads",
+    "--max-memory",
+    "--health-secs",
+];
+const SERVE_ENV_SWITCHES: &[&str] = &["--adopt-index-analyzer", "--fuzzy"];
 
-        let content = match parse_entire_xml_file(&file_path) {
-            Ok(content) => content.chars().collect::<Vec<_>>(),
-            Err(()) => continue 'next_file,
-        };
+fn env_var_of(flag: &str) -> String {
+    format!(
+        "TINYSEARCH_{}",
+        flag.trim_start_matches('-')
+            .replace('-', "_")
+            .to_uppercase()
+    )
+}
 
-        let mut tf = TermFreq::new();
+// Whether an argument of serve is the address, `host:port`, rather than the
+// index file.
+fn is_address(arg: &str) -> bool {
+    arg.rsplit_once(':')
+        .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok())
+}
 
-        for token in Lexer::new(&content) {
-            let term = token
-                .iter()
-                .map(|x| x.to_ascii_uppercase())
-                .collect::<String>();
-            if let Some(freq) = tf.get_mut(&term) {
-                *freq += 1;
-            } else {
-                tf.insert(term, 1);
-            }
+// The serve settings of the environment as arguments, with the address from
+// TINYSEARCH_ADDRESS. They go before those of the command line, which win.
+fn serve_env_args() -> Vec<String> {
+    let mut args = Vec::new();
+    if let Ok(address) = env::var("TINYSEARCH_ADDRESS") {
+        args.extend(["--address".to_string(), address]);
+    }
+    for flag in SERVE_ENV_FLAGS {
+        if let Ok(value) = env::var(env_var_of(flag)) {
+            args.extend([flag.to_string(), value]);
         }
-        tf_index.insert(file_path, tf);
     }
-    Ok(())
+    for flag in SERVE_ENV_SWITCHES {
+        let on = env::var(env_var_of(flag))
+            .is_ok_and(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"));
+        if on {
+            args.push(flag.to_string());
+        }
+    }
+    args
+}
+
+fn parse_min_score(
+    args: &mut impl Iterator<Item = String>,
+    program: &str,
+    flag: &str,
+) -> Result<MinScore, ()> {
+    let value = flag_value(args, program, flag)?;
+    MinScore::parse(&value).ok_or_else(|| {
+        eprintln!("ERROR: invalid value {value} for {flag}, expected a score or a percentage of the best score like 25%")
+    })
 }
 
-fn check_index(index_path: &str) -> Result<(), ()> {
-    let index_file = File::open(index_path)
-        .map_err(|err| eprintln!("ERROR: could not open index file {index_path}: {err}"))?;
+fn parse_ranking(
+    args: &mut impl Iterator<Item = String>,
+    program: &str,
+    flag: &str,
+) -> Result<Ranking, ()> {
+    let value = flag_value(args, program, flag)?;
+    Ranking::parse(&value).ok_or_else(|| {
+        eprintln!("ERROR: invalid value {value} for {flag}, expected tfidf, bm25 or bm25:k1=<k1>,b=<b> with b between 0 and 1")
+    })
+}
 
-    println!("Reading {index_path} index file...");
+// A whole number above 0 of a unit that is `scale` of the unit it is used
+// in, like megabytes counted in bytes, and small enough to be converted
+// to it. Returns the number as given.
+fn parse_scaled_count(
+    args: &mut impl Iterator<Item = String>,
+    program: &str,
+    flag: &str,
+    scale: u64,
+) -> Result<u64, ()> {
+    let count: u64 = parse_flag(args, program, flag)?;
+    if count == 0 || count.checked_mul(scale).is_none() {
+        usage(program);
+        eprintln!(
+            "ERROR: {flag} must be above 0 and at most {max}",
+            max = u64::MAX / scale
+        );
+        return Err(());
+    }
+    Ok(count)
+}
 
-    let tf_index: TermFreqIndex = serde_json::from_reader(&index_file)
-        .map_err(|err| eprintln!("ERROR: could not parse index file {index_path}: {err}"))?;
+fn flag_value(
+    args: &mut impl Iterator<Item = String>,
+    program: &str,
+    flag: &str,
+) -> Result<String, ()> {
+    args.next().ok_or_else(|| {
+        usage(program);
+        eprintln!("ERROR: no value is provided for {flag}")
+    })
+}
 
-    println!(
-        "{index_path} contains {count} files",
-        count = tf_index.len()
-    );
+fn parse_flag<T>(
+    args: &mut impl Iterator<Item = String>,
+    program: &str,
+    flag: &str,
+) -> Result<T, ()>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let value = flag_value(args, program, flag)?;
+    value
+        .parse()
+        .map_err(|err| eprintln!("ERROR: invalid value {value} for {flag}: {err}"))
+}
 
-    Ok(())
+fn usage_text(program: &str) -> String {
+    let mut text = String::new();
+    macro_rules! usage_line {
+        ($($arg:tt)*) => {
+            text.push_str(&format!($($arg)*));
+            text.push('\n');
+        };
+    }
+    usage_line!("Usage: {program} <SUBCOMMAND> [OPTIONS]");
+    usage_line!("Options take their value as --flag <value> or --flag=<value>; {program} <subcommand> --help shows the options of one subcommand, {program} --version the version");
+    usage_line!("Subcommands: ");
+    usage_line!("  index <source>...   index folders, .tar/.tar.gz/.zip archives and http(s) URLs and save the index");
+    usage_line!("      documents get the language told from their text as lang metadata, e.g. lang=de, unless they have a lang; texts too short or mixed to tell get none");
+    usage_line!("    -o, --output <file>   where to save the index (default: index.json), stored as binary for .tsidx and in SQLite for .sqlite or .db");
+    usage_line!("    --format <name>   store the index as json, bin, sqlite or sharded whatever its name, sharded splitting it into a JSON file per top-level directory so a change rewrites and reloads only the files of its directory; readers recognize the format by the contents of the file");
+    usage_line!(
+        "    --compress   compress a binary index with zstd, smaller but rewritten on every change"
+    );
+    usage_line!("    --tokenizer <name>   how text is split into tokens: default, words to drop punctuation, or code to also keep identifiers like utf8, tf_index and C++ whole");
+    usage_line!("    --joiners <chars>   characters the code tokenizer keeps between letters and digits besides `_`, e.g. \"-.\" for utf-8 and v1.2");
+    usage_line!("    --stopwords <words>   words that are not indexed: comma separated words, bundled lists (english, web) and files with one word per line; the index records the words");
+    usage_line!("    --index-stopwords   index the stopwords anyway and only leave them out of queries, so search --keep-stopwords and stopwords=keep can find \"the who\"");
+    usage_line!("    --stemmer <name>   reduce words to a common stem: none (default) or plural");
+    usage_line!("    --profile <name>   start from the analysis settings of a kind of corpus: code, docs, notes or web, or a profile of tinysearch-profiles.json (or the file in TINYSEARCH_PROFILES); --tokenizer, --stopwords and --stemmer adjust it");
+    usage_line!("    --git-rev <rev>   index the files of <rev> in the git repositories given as folders instead of the working tree");
+    usage_line!("    --hidden   also index dotfiles and OS/editor junk like .DS_Store, Thumbs.db and swap files");
+    usage_line!(
+        "    --one-file-system   do not descend into directories on other mounted filesystems"
+    );
+    usage_line!("    --exclude <pattern>   leave out the files and folders matching <pattern>: its name like '*.log' or node_modules, or its path below the folder with a / like 'build/**'; may be repeated");
+    usage_line!("    --include <pattern>   only index the files matching one of the --include patterns, given like those of --exclude, e.g. '*.md'");
+    usage_line!("    --gitignore   leave out what the .gitignore and .ignore files of the folders ignore, and .git, before any file is read");
+    usage_line!("    --follow-symlinks   also descend into symlinked folders, every folder once however many links lead to it; symlinked files are always indexed");
+    usage_line!("    --notebook-outputs   also index the outputs of Jupyter notebook code cells");
+    usage_line!("    --ocr   run tesseract on PDFs and images that have no text layer");
+    usage_line!("    --bundle-sources   keep a compressed copy of every source file in <file>.sources, so search and serve show the text of documents whose files are not there; files --incremental skips as unchanged are only copied by an earlier run with it");
+    usage_line!("    --manifest   write <file>.manifest.json with the size and SHA-256 of every input, the analysis settings and the SHA-256 of the index, for verify-manifest; --incremental runs read unchanged files again to hash them");
+    usage_line!("      rebuilding from the same files, with the same modification times, with --threads 1 writes the same index byte for byte, as documents are numbered in the order they are indexed");
+    usage_line!("    --thumbnails   keep a thumbnail of the first page of PDFs (made with pdftoppm) and the first heading of HTML pages, shown next to the results of the web UI");
+    usage_line!("    --plugins <dir>   run the WebAssembly extractors and token filters in <dir> with the wasmtime CLI, which has to be on the PATH (https://wasmtime.dev), under limits of fuel and of 512 MiB of memory: every <name>.wasm next to a <name>.json of {{\"kind\": \"extractor\", \"extensions\": [...]}} or {{\"kind\": \"token-filter\"}}; searches of the index go through its token filters too, and a filter that fails fails the run or the search");
+    usage_line!("    --cache-dir <dir>   where extracted text is cached by file content (default: .tinysearch-cache)");
+    usage_line!("    --no-cache   always extract files again instead of reusing cached text");
+    usage_line!("    --sandbox   extract every file, those of extractor plugins included, in a child process without network access, for untrusted documents");
+    usage_line!("    --sandbox-memory-mb <n>, --sandbox-cpu-secs <n>   limits of the sandboxed extractor (default: 1024 MB, 30 s), imply --sandbox; the memory is only limited on Linux");
+    usage_line!("    --threads <n>   number of indexing worker threads (default: number of CPUs)");
+    usage_line!("    --throttle <MB/s>   limit how fast the workers read files from disk");
+    usage_line!("    --positions   record where every term occurs, so \"quoted phrases\" only match their words in order and documents with the query terms close together rank higher");
+    usage_line!("    --field <name:type>   store metadata field <name> as text (analyzed and searchable), keyword (matched exactly), numeric, date or bool, e.g. tags:keyword; profiles set them as \"fields\"");
+    usage_line!("    --incremental   only extract the files that changed since <file> was last built and drop those that are gone, keeping its analyzer settings");
+    usage_line!("      files moved with their content unchanged are recorded as aliases from their old path");
+    usage_line!("    -q, --quiet   print neither the progress nor the counts of the run, only the files written, warnings and errors; by default a progress line shows on the terminal and the counts of the run at the end");
+    usage_line!("    -v, --verbose   also print every file as it is indexed");
+    usage_line!("    --flush-every <n>   write a new binary index in segments of <n> documents as they are indexed, keeping only their metadata in memory, for collections too large to hold; a cancelled or failed run leaves the segments written so far");
+    usage_line!("    --low-priority   run the workers with idle CPU and IO scheduling priority");
+    usage_line!("    --min-doc-freq <n>   drop terms that appear in fewer than <n> documents");
+    usage_line!("    --max-doc-freq-pct <pct>   drop terms that appear in more than <pct>% of the documents");
+    usage_line!("    --min-term-len <n>   drop terms shorter than <n> characters");
+    usage_line!("    --max-tokens-per-doc <n>   index at most <n> tokens of a document");
+    usage_line!("    --over-token-limit <policy>   truncate (default) longer documents and add a truncated field with their token count to their metadata, or skip them");
+    usage_line!("    --force   write the index even while another process holds its lock, <file>.lock, which keeps two runs, or a run and serve --watch, from writing it at once");
+    usage_line!("    --report <file>   where to write per-extension statistics and failures (default: index.report.json)");
+    usage_line!("  crawl <url>...   index the pages at the URLs and the pages of the same site they link to, and theirs, stored under their URLs");
+    usage_line!("    --depth <n>   links followed from a start page to the farthest page indexed (default: 2)");
+    usage_line!("    --max-pages <n>   pages indexed per start page at most (default: 100)");
+    usage_line!("    --delay <ms>   pause between two requests to the site (default: 0)");
+    usage_line!("    --ignore-robots   also index the paths robots.txt disallows");
+    usage_line!("    takes the flags of the index subcommand, like --output, --incremental and the analysis flags");
+    usage_line!("  indexd [address]   run index jobs submitted over HTTP one at a time, apart from the servers answering searches (default address: {address})", address = indexd::DEFAULT_ADDRESS);
+    usage_line!("      POST /jobs {{\"args\": [<arguments of the index subcommand>...], \"reload\": <address>}} queues a job, which has the server at the optional address reload the index once it is written; the body has to be sent as application/json, the index, report and --cache-dir of a job have to be inside the directory of indexd and jobs cannot load --plugins");
+    usage_line!("      GET /jobs lists the jobs, newest first; GET /jobs/<id> tells the status (queued, running, done, failed or cancelled) and progress of one, DELETE /jobs/<id> cancels it");
+    usage_line!("  import <export-file>   index the documents of another search engine's export, one per line");
+    usage_line!(
+        "    --format <name>   format of the export: ndjson (default), one JSON document per line"
+    );
+    usage_line!("    --text-field <field>   field holding the text to index, dotted for nested fields like _source.body");
+    usage_line!("    --id-field <field>   field naming the document in results (default: <export-file>#<line>)");
+    usage_line!(
+        "    takes --output, --field, --tokenizer, --joiners, --stopwords, --stemmer and --profile like the index subcommand"
+    );
+    usage_line!("  merge <index-file>... --output <file>   merge indexes built with the same analysis settings into one, e.g. of folders on different machines");
+    usage_line!("    --on-duplicate <name>   for files that more than one index has with other contents: newest (default) keeps the one modified last, error refuses to merge");
+    usage_line!(
+        "    --format <name>   store the merged index as json, bin, sqlite or sharded whatever its name"
+    );
+    usage_line!("  search <index-file> [query]   rank the documents matching the query, or count the indexed documents without one");
+    usage_line!("    queries combine words, \"quoted phrases\", AND, OR, NOT (or -), parentheses, wildcards (a*b?) and word~; a backslash takes the character after it literally, e.g. \\AND, \\(C\\) or what\\?, and only \\\" and \\\\ need one inside phrases");
+    usage_line!("    --filter <key=value>   only consider documents whose metadata matches, e.g. author=alice or timestamp>=2023-01-01; tag=a,b matches any of the values, tag:a,b all of them");
+    usage_line!("    --queries <file>   run every line of <file> (or stdin for -) as a query and print the results as JSON lines");
+    usage_line!("    --limit <n>   number of results per query (default: 10)");
+    usage_line!("    --offset <n>   skip the first <n> results; --page <n> shows the <n>th page of --limit results instead");
+    usage_line!("    --lines   report the numbers of the lines that contain query terms; the pages of PDFs that do are always reported");
+    usage_line!("    --plain   print only the path and score of every result, separated by a tab");
+    usage_line!("    --context <n>   show the first match of every result with <n> words before and after it, instead of the passage with the most matches");
+    usage_line!("    --open <n>   open the <n>th result in $EDITOR at the first matching line, or in the browser for URLs");
+    usage_line!("    --export <file>   also write the results with their titles, paths, scores and snippets to <file>, as CSV for .csv or a Markdown table for .md");
+    usage_line!("    --postings-cache <n>   decoded posting lists of binary indexes kept in memory (default: 1024)");
+    usage_line!("    --result-cache <n>   results of recent queries kept in memory (default: 256)");
+    usage_line!("    --filter-cache <n>   documents passing recent filters kept in memory as bitsets, one bit per document (default: 64)");
+    usage_line!("    --max-expansions <n>   index terms the wildcards (a*b?) and fuzzy words (word~, word~2) of a query may expand to (default: 256)");
+    usage_line!("    --max-clauses <n>   terms a query may have once expanded (default: 1024)");
+    usage_line!("    --typos <n>   typos a word~ may have: auto (default) allows none up to 4 characters, one up to 8 and two beyond, or off, 0, 1 or 2 for every word");
+    usage_line!("    --fuzzy   take every word outside phrases and exclusions as word~, ranking the words matched with typos lower");
+    usage_line!("    --ranking <name>   rank by tfidf (default) or bm25, which does not favor long documents; tune it with bm25:k1=<k1>,b=<b> (default: k1=1.2, b=0.75)");
+    usage_line!("    --min-score <score>   leave out matches scoring below <score>, or below a share of the best match's score like 25%");
+    usage_line!("    --expand prf   add the terms that stand out in the best results to the query at a lower weight and search again, finding documents that use other words");
+    usage_line!("    --keep-stopwords   search for the stopwords of the query too, in indexes built with --index-stopwords");
+    usage_line!("    --rewrites <file>   rewrite the words of queries before they are parsed, with the rules of a JSON file like [{{\"pattern\": \"PROJ-{{id}}\", \"rewrite\": \"ticket:PROJ-{{id}}\"}}]; {{name}} matches part of a word");
+    usage_line!("    --tokenizer, --joiners, --stopwords, --stemmer, --profile   the analysis the index is expected to use, searching fails if it was built otherwise");
+    usage_line!("    --adopt-index-analyzer   search with the analysis of the index, with a warning, when it differs from the requested one");
+    usage_line!("  repl <index-file>   search the index interactively, a query per line; Ctrl-R searches the queries of earlier sessions, :help lists the commands for bookmarking queries");
+    usage_line!("    takes the search flags but --queries");
+    usage_line!(
+        "    --history <file>   keep the queries run in <file> (default: ~/.tinysearch_history)"
+    );
+    usage_line!("    --bookmarks <file>   keep the bookmarked queries in <file>, a saved-search file like queries --import reads (default: ~/.tinysearch_bookmarks.json)");
+    usage_line!("  grep <folder> <query>   rank the documents of <folder> against the query without writing an index");
+    usage_line!("    takes --hidden, --exclude, --include, --gitignore, --follow-symlinks, --threads, --tokenizer, --joiners, --stopwords, --stemmer, --profile and the search flags --filter, --limit, --offset, --page, --lines, --plain, --context, --open, --export, --ranking, --min-score and --expand");
+    usage_line!("  analyze <text>   print the tokens after every stage of the analysis pipeline, with their positions");
+    usage_line!("    --analyzer <name>   the analyzer to start from, default or a profile like --profile (default: default)");
+    usage_line!(
+        "    takes --tokenizer, --joiners, --stopwords, --stemmer and --profile like the index subcommand"
+    );
+    usage_line!("  compare-analyzers <folder> --a <name> --b <name>   index a sample of <folder> with two analyzers, default or profiles like --profile, and compare their dictionary size, postings per term and index size");
+    usage_line!(
+        "    --sample <n>   documents of <folder> to index, spread evenly over it (default: 1000)"
+    );
+    usage_line!("    --json   print the numbers of both analyzers as JSON");
+    usage_line!("    takes --hidden, --exclude, --include, --gitignore, --follow-symlinks and --threads like the index subcommand");
+    usage_line!("  extract <file>   print the text the indexer extracts from <file>");
+    usage_line!("    --json   print every chunk with its anchor, metadata and term count");
+    usage_line!(
+        "    takes --notebook-outputs, --ocr, --thumbnails and --sandbox like the index subcommand"
+    );
+    usage_line!("  eval <index-file> --queries <queries.tsv> --qrels <judgments.tsv>   compute MAP, nDCG@10 and MRR of the ranking");
+    usage_line!("    --ranking <name>   the ranking function to evaluate, as for search");
+    usage_line!("  diff <old-index> <new-index>   show added, removed and changed documents and term statistics shifts");
+    usage_line!("  stats <index-file>   report the number of documents, terms and unique terms, the size on disk, the document lengths and the most frequent terms");
+    usage_line!(
+        "    --top <n>   number of most frequent terms listed (default: {top})",
+        top = stats::DEFAULT_TOP_TERMS
+    );
+    usage_line!("    --json   print the numbers as JSON");
+    usage_line!("  vocab <index-file>   print the most frequent terms as term, occurrences and documents separated by tabs, and suggest the terms in most documents as stopwords");
+    usage_line!(
+        "    --top <n>   number of terms printed (default: {top})",
+        top = vocab::DEFAULT_VOCAB_TERMS
+    );
+    usage_line!(
+        "    --stopword-share <share>   suggest the terms in at least this share of the documents, between 0 and 1 (default: {share})",
+        share = vocab::DEFAULT_STOPWORD_SHARE
+    );
+    usage_line!("    --stopwords <file>   write the suggested stopwords to <file>, one per line, to index with --stopwords <file>");
+    usage_line!("    --json   print the terms and suggestions as JSON");
+    usage_line!("  export-site <index-file> --out <dir>   write a static site searching the index without a server: a page with the text of every document, the search page and search-index.js with the index it searches; open <dir>/index.html, straight from disk too");
+    usage_line!("    --frontend <file>, --title <title>, --lang <lang>, --templates <dir>   the wording and templates of the pages, as for serve; site.html, document.html and site.js in <dir> replace the bundled ones");
+    usage_line!("    --ranking <name>   the ranking function the weights of the index are computed with, as for search");
+    usage_line!("  upgrade <index-file>   rewrite an index written by an older tinySearch in the current format version ({INDEX_VERSION}), keeping its storage format; older indexes are also read as they are, newer ones refused with E_INDEX_VERSION");
+    usage_line!("    -o, --output <file>   write the upgraded index to <file> instead, leaving the old one as it is");
+    usage_line!("  fsck <index-file>   check the index for inconsistencies, like postings of missing documents");
+    usage_line!("    --quick   only run the cheap checks");
+    usage_line!("  repair <index-file>   fix the inconsistencies fsck finds without indexing the corpus again: rewrite the postings, document frequencies and dictionaries from the documents, number documents sharing an ID anew and drop bad terms and the documents of a damaged binary index past where it can be read, reporting every change");
+    usage_line!("    -o, --output <file>   write the repaired index to <file>, leaving the damaged one as it is");
+    usage_line!("    --dry-run   only report what would be repaired");
+    usage_line!("  verify-manifest <index-file>   compare the inputs index --manifest recorded with the files as they are now, and the index file with the one it wrote; lists the modified and missing inputs and fails if there are any or the index differs");
+    usage_line!("    --manifest <file>   read the manifest from <file> (default: <index-file>.manifest.json)");
+    usage_line!("    --root <dir>   take the paths of the inputs relative to <dir>, as they were to the directory the index was built in (default: the current directory)");
+    usage_line!("    --json   print the inputs that changed and the counts as JSON");
+    usage_line!("  doctor [address]   check what tinySearch needs to index and serve: the index, config files, web UI, the address serve listens at and external tools, with what to do about problems");
+    usage_line!("    --index <file>, --frontend <file>   the index and frontend config to check, as for serve; like the address they are also read from the environment");
+    usage_line!("  rollback <index-file> --snapshot-dir <dir>   list the snapshots of the index, newest first");
+    usage_line!("    --to <n>   replace the index with snapshot <n> of the list, or the one with that timestamp");
+    usage_line!("    --reload <address>   then have the server at <address> reload the index");
+    usage_line!("  exclude <index-file> <pattern>...   hide the documents whose path matches a pattern from results, until the index is rebuilt");
+    usage_line!("      `**` matches any part of a path, `*` any part of one of its components and `?` one character, e.g. \"notes/**\" or \"**/*.log\"");
+    usage_line!("    --undo   show the hidden documents matching the patterns again");
+    usage_line!("    --reload <address>   then have the server at <address> reload the index");
+    usage_line!("  queries <index-file> [<name> <query>]   store a query in the index under a name, replacing any of that name, for percolate to match documents against; lists the stored queries without one");
+    usage_line!(
+        "      stored queries are kept as long as the index is updated rather than rebuilt"
+    );
+    usage_line!("    --remove <name>   drop the stored query; may be repeated");
+    usage_line!("    --import <file>   store the queries of a saved-search file, a JSON object of name to query like the bookmarks of repl; may be repeated");
+    usage_line!("    --reload <address>   then have the server at <address> reload the index");
+    usage_line!("  percolate <index-file> <file>...   print the names of the stored queries each document matches, documents analyzed like those of the index but not added to it; - reads one from stdin");
+    usage_line!(
+        "    --json   print a JSON object with the path and the matching names per document"
+    );
+    usage_line!("  serve [index-file] [address]   start the server at the address (default: 127.0.0.1:8888), searching the index file like --index");
+    usage_line!("    --address <host:port>   the address to listen at, like the address argument");
+    usage_line!("    --index <file>   index searched by GET /search, which answers with HTML or, when asked for, JSON");
+    usage_line!("      POST /api/v1/search takes the query as JSON and answers with {{\"results\": [[path, score], ...], \"total\": <n>, ...}}, or with the result objects of GET /search, snippets with the query terms marked included, for \"snippets\": true");
+    usage_line!("      GET /api/v1/search answers the same way to the parameters of GET /search, e.g. /api/v1/search?q=rust&limit=5&snippets=true");
+    usage_line!("      fields=<name>,... (\"fields\": [<name>, ...] in the body) trims the results of JSON answers to those fields, e.g. fields=path,score,title; [path, score] pairs become objects");
+    usage_line!("      stemming=off and case=sensitive (\"stemming\": \"off\", \"case\": \"sensitive\" in the body) override the analysis of the index for one search: its matches are narrowed to the documents whose text, kept with index --bundle-sources, matches analyzed that way, which takes longer the more matches there are");
+    usage_line!("      overrides the index cannot honor, like fold_accents=on or stemming=on for an index built without a stemmer, get a 400 with E_UNSUPPORTED_OVERRIDE and every one of them and why under \"unsupported\"");
+    usage_line!("      prefer_lang=<languages> (\"prefer_lang\" in the body) ranks documents whose lang is among them higher, in the syntax of Accept-Language like de,en;q=0.5: those in the first scored up to 1.5 times as high; prefer_lang=auto takes the Accept-Language of the request");
+    usage_line!("      searches take limit=<n> (default: 20, at most 100) and offset=<n> or the 1-based page=<n>, and answer with the total number of matches (the X-Total-Count header of /api/v1/search)");
+    usage_line!("      failed requests answer with a 4xx or 5xx status and {{\"error\": {{\"code\": <code, E_...>, \"message\": <text>, \"request_id\": <id>}}}}: 400 for bad queries, filters and bodies, 404, 503 without an index and 500 when the index cannot be read");
+    usage_line!("      the API is versioned by its path, /api/v1/...; the paths without a version, like /api/search, still answer as version 1, or the version of an API-Version header, with Deprecation and Sunset headers and a Link to the path with one; versions the server does not have get a 400 with E_UNSUPPORTED_API_VERSION");
+    usage_line!("      POST /api/v1/reload from this host reads it again, after the index or rollback subcommand replaced it");
+    usage_line!("      POST /api/v1/documents {{\"path\": <path or URL>, \"text\": <text>, \"meta\": {{...}}}} from this host analyzes a document into the served index, replacing the one at its path, and DELETE /api/v1/documents?path=<path> removes one; searches see the change right away");
+    usage_line!("      GET /api/v1/doc?path=<path> returns a document's metadata and text, redirecting the old path of a moved file to its new one");
+    usage_line!("      POST /api/v1/docs with {{\"docs\": [<path or ID>, ...], \"text\": true}} returns up to {max} documents at once, their text only when asked for, trimmed to \"fields\" like searches", max = api::MAX_PAGE_SIZE);
+    usage_line!("      POST /api/v1/percolate with {{\"text\": <text>, \"meta\": {{...}}}} answers with {{\"matches\": [<name>, ...]}}, the stored queries of the index the document matches");
+    usage_line!("      GET /api/v1/thumb?path=<path> returns the thumbnail of a document indexed with --thumbnails, as PNG, or its first heading as SVG");
+    usage_line!("      GET /api/v1/changes[?since=<time>] lists the documents every reload added, removed and modified");
+    usage_line!("      GET /api/v1/health/history lists samples of the health of the index, oldest first: its documents and terms, the segments of a binary index, the share of deleted documents it still holds and the hits and misses of the caches");
+    usage_line!("      GET /metrics reports the latest sample in the text format of Prometheus, in builds with the metrics feature");
+    usage_line!("      GET /api/v1/facets?q=<query>[&filter=<filter>][&facet=<field>]... counts the values of the fields over the matches as {{\"<field>\": {{\"<value>\": <count>}}}}, of lang without a facet; filter=lang:de narrows searches to a language");
+    usage_line!("      GET /api/v1/complete?q=<text>[&limit=<n>] lists the index terms starting with the last word of <text>, those in the most documents first");
+    usage_line!("      searches answer with the token of their result set (result_set, or the X-Result-Set header of /api/v1/search); result_set=<token> pages through those matches as they were, within=<token> searches only them");
+    usage_line!("    --max-expansions <n>, --max-clauses <n>   limits of the expanded queries, as for search; queries past them get a 400");
+    usage_line!("    --typos <n>   typos a word~ may have, as for search; requests override it with typos=<n>");
+    usage_line!("    --fuzzy   take every word of a query as word~, as for search; requests override it with fuzzy=true or fuzzy=false");
+    usage_line!("    --ranking <name>   ranking function, as for search; requests override it with ranking=<name>");
+    usage_line!("    --min-score <score>   cutoff of the results, as for search; requests override it with min_score=<score>");
+    usage_line!("    --rewrites <file>   rewrite the words of queries before they are parsed, as for search");
+    usage_line!("      normalize=max or normalize=logistic[:k=<k>,mid=<score>] adds every result's score on a 0 to 1 scale as relevance");
+    usage_line!("      expand=prf expands the query with the terms that stand out in its best results, like search --expand prf");
+    usage_line!("      stopwords=keep searches for the stopwords of the query too, like search --keep-stopwords");
+    usage_line!(
+        "      sort=popular orders the results by the clicks of --popularity, most clicked first"
+    );
+    usage_line!("    --tokenizer, --joiners, --stopwords, --stemmer, --profile, --adopt-index-analyzer   check the analysis of the index, as for search");
+    usage_line!("    --frontend <file>   JSON file with the title, brand, lang, index_name, strings, templates and static_dir of the page, and prefer_user_language: true to have it rank results in the languages of the browser higher");
+    usage_line!("    --title <title>   title of the page (default: tinySearch)");
+    usage_line!("    --lang <lang>   language of the page, bundled: en, de, fr (default: en)");
+    usage_line!("    --index-name <name>   name of the searched collection shown on the page");
+    usage_line!("    --cors-origin <origins>   comma separated origins whose pages may call the server, like https://app.example.com, or * for any");
+    usage_line!("    --robots <file>   served as /robots.txt (default: disallow crawling the search results and the API)");
+    usage_line!("    --templates <dir>   directory with an index.html template, index.js and style.css replacing the bundled ones");
+    usage_line!("    --static-dir <dir>   read index.js and style.css from <dir> on every request, for working on the web UI without restarting; the bundled files are compiled in");
+    usage_line!("    --query-log <file>   append every search to <file> as JSON lines");
+    usage_line!("    --warm-searches <n>   on POST /api/v1/reload, replay the <n> searches the --query-log recorded most often lately against the new index before swapping it in, so their results are cached by the time traffic reaches it (default: 0)");
+    usage_line!("    --feedback-log <file>   append the feedback posted to /api/v1/feedback to <file> as JSON lines");
+    usage_line!("    --popularity <file>   count the clicks on results posted to /api/v1/feedback as {{\"path\": ...}}, kept in <file> across restarts, for sort=popular; a click weighs half as much after 30 days");
+    usage_line!("    --popularity-prior <weight>   multiply the scores of results by 1 + <weight> × ln(1 + clicks), so often clicked documents rank higher; between 0 and 1 (default: 0, scores left alone)");
+    usage_line!("    --query-logging <mode>   how queries appear in the logs and request traces: full, hashed (a hash telling which records share a query) or off (default: full); a request can ask for less with an X-Query-Logging header or DNT: 1");
+    usage_line!("    --query-hash-key <key>   secret mixed into the hashes of --query-logging hashed, so that nobody without it can tell which hash a guessed query has");
+    usage_line!("    --slow-log <file>   append searches slower than --slow-ms to <file> with their parsed query, match count and phase timings");
+    usage_line!(
+        "    --slow-ms <n>   milliseconds after which a search counts as slow (default: 500)"
+    );
+    usage_line!("    --log-max-mb <n>   rotate a log once it grows past <n> megabytes (default: 64), logs also rotate daily");
+    usage_line!("    --log-keep <n>   number of rotated files kept per log (default: 7)");
+    usage_line!("    --log-sync-secs <n>   longest time logged records may wait to be synced to disk (default: 5)");
+    usage_line!("    --snapshot-dir <dir>   copy the index into <dir> at startup and then periodically, when it has changed");
+    usage_line!("    --snapshot-hours <n>   hours between snapshots (default: 24)");
+    usage_line!("    --read-only   write nothing, for indexes on read-only or network mounts: --save-secs, --snapshot-dir, --watch and --feedback-log are ignored, /api/v1/documents is refused, clicks for --popularity are not saved and logs go to the temporary folder if theirs is read-only; on by itself when the folder of the index is read-only");
+    usage_line!("    --save-secs <n>   save the documents posted to and deleted from /api/v1/documents into the index file every <n> seconds; without it, and on a reload, they are gone with the server");
+    usage_line!("    --snapshot-keep <n>   number of snapshots kept (default: 7)");
+    usage_line!("    --result-set-minutes <n>   minutes the matches of a search stay available to result_set and within after they were last used (default: 10)");
+    usage_line!("    --result-sets <n>   number of result sets kept, the least recently used dropped first (default: 64)");
+    usage_line!("    --health-secs <n>   seconds between samples of the health of the index, of which a day's worth at the default are kept (default: 60)");
+    usage_line!("    --threads <n>   number of worker threads answering requests, so that slow requests do not hold up the others (default: number of CPUs)");
+    usage_line!("    --max-memory <size>   soft memory limit like 512M or 2G: near it the server evicts its caches and result sets, stops keeping result sets and refuses exports (503) until memory is well below it again");
+    usage_line!("    --watch <folder>   index files created, modified or deleted in <folder> into the index as they change and serve the result, <folder> being the one the index was built from");
+    usage_line!("    --watch-strategy <name>   how --watch notices changes: notify with file system notifications, poll by comparing the modification times and sizes of the files every 2 seconds, or auto (default) to poll on network filesystems like NFS and where notifications are not available");
+    usage_line!("    every flag can also be set in the environment as TINYSEARCH_ and its name, e.g. TINYSEARCH_INDEX_NAME=docs for --index-name docs or TINYSEARCH_ADOPT_INDEX_ANALYZER=1, and the address as TINYSEARCH_ADDRESS; the command line wins over the environment");
+    usage_line!("Set TINYSEARCH_LOG=debug for the time each stage of a search takes in serve, or warn to only log problems");
+    usage_line!("Dates and sizes follow the locale in LC_ALL, LC_TIME or LANG, set TINYSEARCH_FORMAT=iso for ISO 8601");
+    text
 }
 
 fn usage(program: &str) {
-    eprintln!("Usage: {program} [SUBCOMMAND] [OPTIONS]");
-    eprintln!("Subcommands: ");
-    eprintln!("  index <folder>   index the <folder> and save the index to index.json file");
-    eprintln!("  search <index-file>   check how many documents are indexed in the file (searching is not implemented yet)");
-    eprintln!("  serve [address]   start the server at the address");
+    eprint!("{}", usage_text(program));
 }
 
-fn serve_static_file(request: Request, file_path: &str, content_type: &str) -> Result<(), ()> {
-    let header = Header::from_bytes("Content-Type", content_type).unwrap();
-    let file = File::open(file_path).map_err(|err| {
-        eprintln!("ERROR: could not open file {file_path}: {err}");
-    })?;
-    let response = Response::from_file(file).with_header(header);
-    request
-        .respond(response)
-        .unwrap_or_else(|err| eprintln!("ERROR: could not serve a request: {err}"));
+// The lines of the usage about one subcommand: its own and the indented ones
+// of its options below it.
+fn subcommand_usage(program: &str, sub_command: &str) -> Option<String> {
+    let text = usage_text(program);
+    let mut lines = text.lines().skip_while(|line| {
+        let Some(rest) = line.strip_prefix("  ") else {
+            return true;
+        };
+        rest.split(' ').next() != Some(sub_command)
+    });
+    let first = lines.next()?;
+    let mut usage = format!("Usage: {program} {}\n", first.trim_start());
+    for line in lines.take_while(|line| line.starts_with("    ")) {
+        usage.push_str(line);
+        usage.push('\n');
+    }
+    Some(usage)
+}
+
+// Short forms of flags.
+const SHORT_FLAGS: &[(&str, &str)] = &[
+    ("-o", "--output"),
+    ("-q", "--quiet"),
+    ("-v", "--verbose"),
+    ("-h", "--help"),
+    ("-V", "--version"),
+];
+
+// The arguments with the short flags spelled out and `--flag=value` split
+// into `--flag value`, so the subcommands only look for one form.
+fn normalize_args(args: impl Iterator<Item = String>) -> Vec<String> {
+    let mut normalized = Vec::new();
+    for arg in args {
+        if let Some((_, long)) = SHORT_FLAGS.iter().find(|(short, _)| *short == arg) {
+            normalized.push(long.to_string());
+        } else if let Some((flag, value)) = arg
+            .split_once('=')
+            .filter(|(flag, _)| flag.starts_with("--") && flag.len() > 2)
+        {
+            normalized.extend([flag.to_string(), value.to_string()]);
+        } else {
+            normalized.push(arg);
+        }
+    }
+    normalized
+}
+
+// Errors of the library are printed here, at the command line, and once.
+fn print_error(err: Error) {
+    eprintln!("ERROR[{code}]: {err}", code = err.code());
+}
+
+// Prints what `fsck` found and fails if it found anything.
+fn report_problems(index_path: &str, problems: &[String]) -> Result<(), ()> {
+    for problem in problems.iter().take(MAX_REPORTED_PROBLEMS) {
+        eprintln!("ERROR: {problem}");
+    }
+    if problems.len() > MAX_REPORTED_PROBLEMS {
+        eprintln!(
+            "ERROR: ... and {more} more problems",
+            more = problems.len() - MAX_REPORTED_PROBLEMS
+        );
+    }
+    if !problems.is_empty() {
+        eprintln!("ERROR: {index_path} is inconsistent, fix it with the repair subcommand or rebuild it with the index subcommand");
+        return Err(());
+    }
     Ok(())
 }
 
-fn serve_404(request: Request) -> Result<(), ()> {
+// Origins allowed to read the answers of the server, from --cors-origin. Set
+// once when serve starts, every answer consults it.
+static CORS: OnceLock<http::Cors> = OnceLock::new();
+
+// Sends a response tagged with the ID of the request it answers.
+fn respond<R: Read>(request: Request, id: &str, response: Response<R>) -> Result<(), Error> {
+    let header = Header::from_bytes("X-Request-Id", id).unwrap();
+    let mut response = response.with_header(header);
+    for header in CORS
+        .get()
+        .map(|cors| cors.headers(&request))
+        .unwrap_or_default()
+    {
+        response.add_header(header);
+    }
+    for header in router::version_headers(&request) {
+        response.add_header(header);
+    }
     request
-        .respond(Response::from_string("404").with_status_code(404))
-        .map_err(|err| eprintln!("ERROR: could not serve request: {err}"))?;
-    Ok(())
+        .respond(response)
+        .map_err(|err| Error::Http(format!("could not answer request {id}: {err}")))
 }
 
-fn serve_request(mut request: Request) -> Result<(), ()> {
-    println!(
-        "INFO: received request! method: {:?}, url : {:?}",
-        request.method(),
-        request.url(),
-    );
-    match (request.method(), request.url()) {
-        (Method::Post, "/api/search") => {
-            let mut buf = Vec::new();
-            request.as_reader().read_to_end(&mut buf);
-            let body = str::from_utf8(&buf).map_err(|err| {
-                eprintln!("ERROR: could not interpret body as UTF-8 string : {err}")
-            })?;
-            println!("Search: {body}");
-            request
-                .respond(Response::from_string("ok"))
-                .map_err(|err| eprintln!("ERROR: {err}"));
-        }
-        (Method::Get, "/") | (Method::Get, "/index.html") => {
-            let index_html_path = "src/index.html";
-            serve_static_file(request, index_html_path, "text/html, charset=utf-8")?;
+fn serve_page(request: Request, id: &str, body: &str, content_type: &str) -> Result<(), Error> {
+    let header = Header::from_bytes("Content-Type", content_type).unwrap();
+    respond(request, id, Response::from_string(body).with_header(header))
+}
+
+// Results are kept out of other search engines even when a crawler ignores
+// robots.txt.
+fn serve_results(
+    request: Request,
+    id: &str,
+    status: u16,
+    body: &str,
+    content_type: &str,
+) -> Result<(), Error> {
+    respond(request, id, results_response(status, body, content_type))
+}
+
+fn results_response(status: u16, body: &str, content_type: &str) -> Response<Cursor<Vec<u8>>> {
+    let header = Header::from_bytes("Content-Type", content_type).unwrap();
+    let noindex = Header::from_bytes("X-Robots-Tag", "noindex").unwrap();
+    Response::from_string(body)
+        .with_status_code(status)
+        .with_header(header)
+        .with_header(noindex)
+}
+
+fn serve_404(request: Request, id: &str) -> Result<(), Error> {
+    if request.url().starts_with("/api/") {
+        return serve_error(request, id, 404, ErrorCode::NotFound, "no such API route");
+    }
+    respond(
+        request,
+        id,
+        Response::from_string("404").with_status_code(404),
+    )
+}
+
+// Answers with the JSON error payload of `api::error`.
+fn serve_error(
+    request: Request,
+    id: &str,
+    status: u16,
+    code: ErrorCode,
+    message: &str,
+) -> Result<(), Error> {
+    let mut payload = api::error(code, message);
+    payload["error"]["request_id"] = json!(id);
+    serve_results(
+        request,
+        id,
+        status,
+        &payload.to_string(),
+        "application/json; charset=utf-8",
+    )
+}
+
+// Refuses what the server can do without while it is short of memory.
+fn serve_memory_pressure(request: Request, id: &str) -> Result<(), Error> {
+    serve_error(
+        request,
+        id,
+        503,
+        ErrorCode::MemoryPressure,
+        "the server is near its memory limit and does not export results for now, try again later",
+    )
+}
+
+// Names requests so that a response, the log lines about it and its log
+// records can be matched up. An ID the client sends along, e.g. one a proxy
+// assigned, is kept.
+struct RequestIds {
+    // Tells the IDs of different server runs apart.
+    prefix: String,
+    next: u64,
+}
+
+impl RequestIds {
+    const MAX_LEN: usize = 64;
+
+    fn new() -> Self {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Self {
+            prefix: format!("{:x}", started.as_secs()),
+            next: 0,
         }
-        (Method::Get, "/index.js") => {
-            let index_js_path = "src/index.js";
-            serve_static_file(request, index_js_path, "text/javascript, charset=utf-8")?;
+    }
+
+    fn assign(&mut self, request: &Request) -> String {
+        if let Some(id) = http::header(request, "X-Request-Id") {
+            let valid = id.len() <= Self::MAX_LEN
+                && !id.is_empty()
+                && id
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b));
+            if valid {
+                return id.to_string();
+            }
         }
-        _ => serve_404(request)?,
+        self.next += 1;
+        format!("{}-{}", self.prefix, self.next)
     }
-    Ok(())
 }
 
-fn entry() -> Result<(), ()> {
-    let mut args = env::args();
-    let program = args.next().expect("path to program is provided.");
+// What the server was asked, kept only when a log file is configured.
+#[derive(Default)]
+struct ServerLogs {
+    queries: Option<RotatingLog>,
+    // Clicks and ratings the frontend reports on results.
+    feedback: Option<RotatingLog>,
+    // Queries that took longer than `slow_after`, with the time each phase
+    // of their evaluation took.
+    slow: Option<RotatingLog>,
+    slow_after: Duration,
+}
 
-    let sub_command = args.next().ok_or_else(|| {
-        usage(&program);
-        eprintln!("ERROR: no subcommand is provided")
-    })?;
+impl ServerLogs {
+    fn open(path: &str, options: &LogOptions) -> Result<RotatingLog, ()> {
+        RotatingLog::open(Path::new(path), options.clone())
+            .map_err(|err| eprintln!("ERROR: could not open log file {path}: {err}"))
+    }
 
-    match sub_command.as_str() {
-        "index" => {
-            let dir_path = args.next().ok_or_else(|| {
-                usage(&program);
-                eprintln!("ERROR: no directory path is provided")
-            })?;
+    // Every record goes through the redaction of the request it is about.
+    fn append(log: &mut Option<RotatingLog>, record: serde_json::Value, redaction: Redaction) {
+        if let Some(log) = log {
+            log.append(&redaction.record(record))
+                .unwrap_or_else(|err| eprintln!("ERROR: could not write log record: {err}"));
+        }
+    }
 
-            let mut tf_index = TermFreqIndex::new();
-            tf_index_of_folder(Path::new(&dir_path), &mut tf_index);
-            save_tf_index(&tf_index, "index.json");
+    // The query log holds nothing but queries, so searches whose query may
+    // not be logged leave no record in it.
+    fn append_query(&mut self, record: serde_json::Value, redaction: Redaction) {
+        if redaction.logging != QueryLogging::Off {
+            Self::append(&mut self.queries, record, redaction);
         }
-        "search" => {
-            let index_path = args.next().ok_or_else(|| {
-                usage(&program);
-                eprintln!("ERROR: no path to index is provided for {sub_command} subcommand")
-            })?;
-            check_index(&index_path);
+    }
+
+    fn sync_if_due(&mut self) {
+        for log in [&mut self.queries, &mut self.feedback, &mut self.slow]
+            .into_iter()
+            .flatten()
+        {
+            log.sync_if_due()
+                .unwrap_or_else(|err| eprintln!("ERROR: could not sync log: {err}"));
         }
-        "serve" => {
-            let address = args.next().unwrap_or("127.0.0.1:8888".to_string());
-            let server = Server::http(&address).map_err(|err| {
-                eprintln!("ERROR: could not start HTTP server at {address} : {err}");
-            })?;
+    }
+}
+
+// The body of the request, or why it could not be read.
+fn read_body(request: &mut Request) -> Result<String, String> {
+    let mut buf = Vec::new();
+    request
+        .as_reader()
+        .read_to_end(&mut buf)
+        .map_err(|err| format!("could not read the body of the request: {err}"))?;
+    String::from_utf8(buf).map_err(|err| format!("the body of the request is not UTF-8: {err}"))
+}
+
+// Link to another page of the results of `search`.
+fn search_page_link(
+    search: &api::SearchRequest,
+    offset: usize,
+    result_set: Option<&str>,
+) -> String {
+    let mut link = format!("search?q={}", http::percent_encode(&search.query));
+    for filter in &search.filters {
+        link.push_str(&format!("&filter={}", http::percent_encode(filter)));
+    }
+    if let Some(within) = &search.within {
+        link.push_str(&format!("&within={}", http::percent_encode(within)));
+    }
+    if let Some(result_set) = result_set {
+        link.push_str(&format!("&result_set={}", http::percent_encode(result_set)));
+    }
+    link.push_str(&format!("&offset={offset}&limit={}", search.limit));
+    link
+}
+
+// A page of search results as CSV or Markdown. Failed searches still answer
+// with their JSON error payload.
+fn serve_export(
+    request: Request,
+    id: &str,
+    search: &api::SearchRequest,
+    status: u16,
+    payload: &serde_json::Value,
+    format: ExportFormat,
+) -> Result<(), Error> {
+    if status != 200 {
+        let body = payload.to_string();
+        return serve_results(
+            request,
+            id,
+            status,
+            &body,
+            "application/json; charset=utf-8",
+        );
+    }
+    let results = payload["results"].as_array().map_or(&[][..], Vec::as_slice);
+    let body = format.render(results, search.offset + 1);
+    serve_results(request, id, status, &body, format.content_type())
+}
+
+// Status and payload of an API route that needs the index: 400 with the
+// error payload when `run` fails, 503 without an index. Errors carry the
+// request id.
+fn api_response(
+    id: &str,
+    index: Option<&SearchHandle>,
+    query: &str,
+    run: impl FnOnce(&SearchHandle) -> Result<serde_json::Value, serde_json::Value>,
+) -> (u16, serde_json::Value) {
+    let (status, mut payload) = match index {
+        Some(handle) => match run(handle) {
+            Ok(payload) => (200, payload),
+            Err(payload) => (400, payload),
+        },
+        None => return no_index(id, query),
+    };
+    if let Some(error) = payload.get_mut("error") {
+        error["request_id"] = json!(id);
+    }
+    (status, payload)
+}
+
+// A document moved since it was first indexed redirects to its new path.
+fn serve_document(
+    request: Request,
+    id: &str,
+    index: Option<&SearchHandle>,
+    path: &Path,
+) -> Result<(), Error> {
+    let Some(handle) = index else {
+        let (status, payload) = no_index(id, "");
+        return serve_results(
+            request,
+            id,
+            status,
+            &payload.to_string(),
+            "application/json; charset=utf-8",
+        );
+    };
+    let model = handle.snapshot();
+    if let Some(target) = model.resolve_alias(path) {
+        let location = format!(
+            "/api/v1/doc?path={}",
+            http::percent_encode(&target.to_string_lossy())
+        );
+        let payload = json!({"path": path, "moved_to": target});
+        let response = Response::from_string(payload.to_string())
+            .with_status_code(301)
+            .with_header(Header::from_bytes("Location", location).unwrap())
+            .with_header(
+                Header::from_bytes("Content-Type", "application/json; charset=utf-8").unwrap(),
+            );
+        return respond(request, id, response);
+    }
+    let bundle = handle.sources();
+    let sources = bundle.as_deref().map(|bundle| bundle.sources(&model));
+    let (status, payload) = match api::document(&model, path, sources, handle.extract_options()) {
+        Some(document) => (200, document),
+        None => (
+            404,
+            json!({
+                "path": path,
+                "error": {"code": ErrorCode::DocNotFound.as_str(), "message": "no such document in the index", "request_id": id},
+            }),
+        ),
+    };
+    serve_results(
+        request,
+        id,
+        status,
+        &payload.to_string(),
+        "application/json; charset=utf-8",
+    )
+}
+
+// The preview of a document indexed with `--thumbnails`, or a 404 for
+// documents without one.
+fn serve_thumbnail(
+    request: Request,
+    id: &str,
+    index: Option<&SearchHandle>,
+    path: &Path,
+) -> Result<(), Error> {
+    let (status, payload) = match index {
+        Some(handle) => match api::thumbnail(&handle.snapshot(), path) {
+            Some((image, content_type)) => {
+                let response = Response::from_data(image)
+                    .with_header(Header::from_bytes("Content-Type", content_type).unwrap());
+                return respond(request, id, response);
+            }
+            None => (
+                404,
+                json!({
+                    "path": path,
+                    "error": {"code": ErrorCode::DocNotFound.as_str(), "message": "no thumbnail of this document in the index", "request_id": id},
+                }),
+            ),
+        },
+        None => no_index(id, ""),
+    };
+    serve_results(
+        request,
+        id,
+        status,
+        &payload.to_string(),
+        "application/json; charset=utf-8",
+    )
+}
+
+fn no_index(id: &str, query: &str) -> (u16, serde_json::Value) {
+    (
+        503,
+        json!({
+            "query": query,
+            "error": {
+                "code": ErrorCode::NoIndex.as_str(),
+                "message": "no index is loaded, start the server with --index <file>",
+                "request_id": id,
+            },
+        }),
+    )
+}
 
-            println!("INFO: server listening at http://{address}/");
+// The index a server answers from, with the changes of its last reloads.
+struct ServedIndex {
+    path: String,
+    handle: SearchHandle,
+    // Newest last. Reloads hold the lock throughout, so they run one at a
+    // time while searches go on with the index they started with.
+    changes: Mutex<VecDeque<serde_json::Value>>,
+    // Whether documents were posted to or deleted from the served index
+    // since it was last read or saved.
+    unsaved: AtomicBool,
+}
+
+// How many reloads /api/changes reports.
+const KEPT_CHANGES: usize = 32;
+
+impl ServedIndex {
+    // Changes newest first, only those after `since` (RFC 3339) if given.
+    fn changes(&self, since: Option<&str>) -> Vec<serde_json::Value> {
+        self.changes
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|change| since.is_none_or(|since| change["time"].as_str() > Some(since)))
+            .cloned()
+            .collect()
+    }
+}
+
+// The folder of a file, `.` for a bare file name.
+fn parent_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+// Whether a file can be created in `dir`, which is not the case on a
+// read-only mount.
+fn is_writable(dir: &Path) -> bool {
+    let probe = dir.join(format!(".tinysearch-probe-{}", process::id()));
+    let created = OpenOptions::new().write(true).create_new(true).open(&probe);
+    let _ = fs::remove_file(&probe);
+    created.is_ok()
+}
+
+// Where a log goes while serving read-only: where it was asked to, unless its
+// folder is read-only too, then in the temporary folder.
+fn local_log_path(path: &str) -> String {
+    if is_writable(&parent_dir(Path::new(path))) {
+        return path.to_string();
+    }
+    let file_name = Path::new(path)
+        .file_name()
+        .unwrap_or("tinysearch.log".as_ref());
+    let local = env::temp_dir().join(file_name);
+    eprintln!(
+        "WARNING: {path} is on a read-only filesystem, logging to {local} instead",
+        local = local.display()
+    );
+    local.to_string_lossy().into_owned()
+}
+
+// What the workers answering requests share.
+struct ServerState {
+    frontend: Frontend,
+    served: Option<ServedIndex>,
+    limits: QueryLimits,
+    result_sets: ResultSets,
+    logs: Mutex<ServerLogs>,
+    privacy: QueryPrivacy,
+    memory: Option<MemoryLimit>,
+    // Clicks on results, when kept.
+    popularity: Option<Popularity>,
+    health: HealthHistory,
+    // Replays frequent searches against reloaded indexes, if asked to.
+    warm_up: Option<WarmUp>,
+    // Nothing is written besides the logs, which are kept in the temporary
+    // folder when theirs is read-only too.
+    read_only: bool,
+}
+
+impl ServerState {
+    fn index(&self) -> Option<&SearchHandle> {
+        self.served.as_ref().map(|served| &served.handle)
+    }
+
+    fn under_memory_pressure(&self) -> bool {
+        self.memory
+            .as_ref()
+            .is_some_and(MemoryLimit::under_pressure)
+    }
+}
+
+fn unreadable_index(err: Error) -> serde_json::Value {
+    // An index of a newer format is readable, just not by this build.
+    let code = match err.code() {
+        ErrorCode::IndexVersion => ErrorCode::IndexVersion,
+        _ => ErrorCode::IndexUnreadable,
+    };
+    api::error(code, err.to_string())
+}
+
+// POST /api/documents analyzes the posted document into the served index,
+// replacing the document at its path, and DELETE /api/documents?path=<path>
+// removes one. Searches see the change right away, the index file once it
+// is saved.
+fn serve_documents(
+    mut request: Request,
+    id: &str,
+    state: &ServerState,
+    params: &[(String, String)],
+) -> Result<(), Error> {
+    let local = request
+        .remote_addr()
+        .is_some_and(|addr| addr.ip().is_loopback());
+    if !local {
+        return serve_error(
+            request,
+            id,
+            403,
+            ErrorCode::Forbidden,
+            "documents can only be posted and deleted from this host",
+        );
+    }
+    if state.read_only {
+        return serve_error(
+            request,
+            id,
+            403,
+            ErrorCode::ReadOnly,
+            "the server is read-only, documents cannot be posted or deleted",
+        );
+    }
+    let Some(served) = &state.served else {
+        let (status, payload) = no_index(id, "");
+        return serve_results(
+            request,
+            id,
+            status,
+            &payload.to_string(),
+            "application/json; charset=utf-8",
+        );
+    };
+    let payload = if *request.method() == Method::Post {
+        let document = match read_body(&mut request)
+            .and_then(|body| api::DocumentRequest::from_body(&body))
+        {
+            Ok(document) => document,
+            Err(message) => return serve_error(request, id, 400, ErrorCode::InvalidBody, &message),
+        };
+        let mut replaced = false;
+        let mut docs = 0;
+        served.handle.update(|model| {
+            replaced = model.docs.contains_key(document.path.as_str());
+            let mut writer = IndexWriter::with_model(std::mem::take(model));
+            writer.add(document.path.as_str(), &document.text, document.meta);
+            *model = writer.into_model();
+            docs = model.docs.len();
+        });
+        info!(path = document.path, replaced, "posted document");
+        json!({"path": document.path, "replaced": replaced, "docs": docs})
+    } else {
+        let path = params
+            .iter()
+            .find(|(name, _)| name == "path")
+            .map_or("", |(_, path)| path.as_str());
+        let mut deleted = false;
+        let mut docs = 0;
+        served.handle.update(|model| {
+            deleted = model.docs.remove(path).is_some();
+            // So that an incremental run indexes the file again.
+            model.manifest.files.remove(path);
+            docs = model.docs.len();
+        });
+        if !deleted {
+            return serve_error(
+                request,
+                id,
+                404,
+                ErrorCode::DocNotFound,
+                &format!("the index has no document {path}"),
+            );
+        }
+        info!(path, "deleted document");
+        json!({"path": path, "deleted": true, "docs": docs})
+    };
+    served.unsaved.store(true, Ordering::Relaxed);
+    serve_results(
+        request,
+        id,
+        200,
+        &payload.to_string(),
+        "application/json; charset=utf-8",
+    )
+}
+
+// Saves the documents posted to the server into its index file, if any were
+// posted or deleted since it was read or saved.
+fn save_posted(served: &ServedIndex) {
+    if !served.unsaved.swap(false, Ordering::Relaxed) {
+        return;
+    }
+    let index_path = served.path.as_str();
+    let saved = IndexLock::acquire(index_path).and_then(|_lock| {
+        let model = served.handle.snapshot();
+        model.save(index_path).map(|()| model.docs.len())
+    });
+    match saved {
+        Ok(docs) => info!(index = index_path, docs, "saved the posted documents"),
+        Err(err) => {
+            served.unsaved.store(true, Ordering::Relaxed);
+            tracing::warn!(
+                index = index_path,
+                "could not save the posted documents: {err}"
+            );
+        }
+    }
+}
+
+// Swaps the index file in for the served one, unless fsck finds it
+// inconsistent, and records which documents the new one added, removed and
+// modified. With --warm-searches the new index is warmed up first.
+fn reload_index(
+    state: &ServerState,
+    served: &ServedIndex,
+) -> Result<serde_json::Value, serde_json::Value> {
+    let mut changes_kept = served.changes.lock().unwrap();
+    let index_path = served.path.as_str();
+    let problems = fsck::check_index(index_path, false).map_err(unreadable_index)?;
+    if !problems.is_empty() {
+        return Err(api::error(
+            ErrorCode::IndexInconsistent,
+            format!(
+            "{index_path} has {count} problems, the served index was kept; the first is: {first}",
+            count = problems.len(),
+            first = problems[0]
+        ),
+        ));
+    }
+    let old = served.handle.snapshot();
+    let warmed = served
+        .handle
+        .reload_with(index_path, |staged| {
+            let popularity = state.popularity.as_ref();
+            (state.warm_up.as_ref()).map(|warm_up| warm_up.run(staged, &state.limits, popularity))
+        })
+        .map_err(unreadable_index)?;
+    if served.unsaved.swap(false, Ordering::Relaxed) {
+        tracing::warn!(
+            index = index_path,
+            "the documents posted since the index was last saved are gone with the reload"
+        );
+    }
+    let mut change = record_changes(served, &mut changes_kept, &old);
+    if let Some(warmed) = warmed {
+        change["warmed_searches"] = json!(warmed);
+    }
+    Ok(change)
+}
+
+// Records which documents the served index added, removed and modified since
+// it was `old`, for /api/changes.
+fn record_changes(
+    served: &ServedIndex,
+    changes_kept: &mut VecDeque<serde_json::Value>,
+    old: &Model,
+) -> serde_json::Value {
+    let index_path = served.path.as_str();
+    let new = served.handle.snapshot();
+    let changes = diff::doc_changes(&old.docs, &new.docs);
+    info!(
+        index = index_path,
+        added = changes.added.len(),
+        removed = changes.removed.len(),
+        modified = changes.changed.len(),
+        "updated index"
+    );
+    let change = json!({
+        "time": locale::now_rfc3339(),
+        "index": index_path,
+        "docs": new.docs.len(),
+        "counts": {
+            "added": changes.added.len(),
+            "removed": changes.removed.len(),
+            "modified": changes.changed.len(),
+        },
+        "added": changes.added,
+        "removed": changes.removed,
+        "modified": changes.changed,
+    });
+    if changes_kept.len() == KEPT_CHANGES {
+        changes_kept.pop_front();
+    }
+    changes_kept.push_back(change.clone());
+    change
+}
+
+// GET /search: the results page for browsers, the API payload for clients
+// that accept JSON, or the results as CSV or Markdown with `format`.
+fn serve_search(request: Request, id: &str, state: &ServerState) -> Result<(), Error> {
+    let redaction = state.privacy.for_request(&request);
+    let url = request.url().to_string();
+    let mut search = api::SearchRequest::from_params(&http::split_url(&url).1);
+    accepted_languages(&request, &mut search);
+    if search.export.is_some() && state.under_memory_pressure() {
+        return serve_memory_pressure(request, id);
+    }
+    let (status, payload) = api_response(id, state.index(), &search.query, |handle| {
+        slowlog::reset();
+        let started = Instant::now();
+        let result = api::search(
+            handle,
+            &search,
+            &state.limits,
+            &state.result_sets,
+            state.popularity.as_ref(),
+        );
+        let elapsed = started.elapsed();
+        let mut logs = state.logs.lock().unwrap();
+        if logs.slow.is_some() && elapsed > logs.slow_after {
+            let parsed = query::parse(&handle.rewrite(&search.query), &handle.analyzer())
+                .map_or_else(|err| err.to_string(), |parsed| parsed.to_string());
+            let matches = result.as_ref().ok().map(|payload| &payload["total"]);
+            ServerLogs::append(
+                &mut logs.slow,
+                json!({
+                    "time": locale::now_rfc3339(),
+                    "request_id": id,
+                    "query": search.query,
+                    "filters": search.filters,
+                    "parsed": parsed,
+                    "matches": matches,
+                    "ms": slowlog::millis(elapsed),
+                    "phases": slowlog::phase_timings(),
+                }),
+                redaction,
+            );
+        }
+        result
+    });
+    if let Some(format) = search.export {
+        return serve_export(request, id, &search, status, &payload, format);
+    }
+    if http::prefers_json(&request) {
+        let mut payload = payload;
+        api::select_fields(&mut payload, &search.fields);
+        return serve_results(
+            request,
+            id,
+            status,
+            &payload.to_string(),
+            "application/json; charset=utf-8",
+        );
+    }
+    let total = payload["total"].as_u64().unwrap_or(0) as usize;
+    // Other pages come from the kept matches, so they do not shift when the
+    // index reloads in between.
+    let result_set = payload["result_set"].as_str();
+    let page = |offset| search_page_link(&search, offset, result_set);
+    let previous = (search.offset > 0).then(|| page(search.offset.saturating_sub(search.limit)));
+    let next = (search.offset + search.limit < total).then(|| page(search.offset + search.limit));
+    let export = (total > 0).then(|| page(search.offset));
+    match state
+        .frontend
+        .results_page(&payload, previous, next, export)
+    {
+        Ok(html) => serve_results(request, id, status, &html, "text/html; charset=utf-8"),
+        Err(err) => {
+            eprintln!("ERROR: {err}");
+            serve_results(request, id, 500, "500", "text/plain; charset=utf-8")
+        }
+    }
+}
+
+// `prefer_lang=auto` stands for the languages the client accepts.
+fn accepted_languages(request: &Request, search: &mut api::SearchRequest) {
+    if search.prefer_lang.as_deref() == Some("auto") {
+        search.prefer_lang = http::header(request, "Accept-Language").map(str::to_string);
+    }
+}
+
+// Answers /api/search with [path, score] pairs or, for `snippets`, the result
+// objects of GET /search, as the results of the payload.
+fn serve_api_search(
+    request: Request,
+    id: &str,
+    state: &ServerState,
+    mut search: api::SearchRequest,
+) -> Result<(), Error> {
+    accepted_languages(&request, &mut search);
+    let (limits, sets) = (&state.limits, &state.result_sets);
+    let popularity = state.popularity.as_ref();
+    let index = state.index();
+    if let Some(format) = search.export {
+        if state.under_memory_pressure() {
+            return serve_memory_pressure(request, id);
+        }
+        let (status, payload) = api_response(id, index, &search.query, |handle| {
+            api::search(handle, &search, limits, sets, popularity)
+        });
+        return serve_export(request, id, &search, status, &payload, format);
+    }
+    let (status, mut payload) = api_response(id, index, &search.query, |handle| {
+        if search.snippets {
+            api::search(handle, &search, limits, sets, popularity)
+        } else {
+            api::ranked(handle, &search, limits, sets, popularity)
+        }
+    });
+    api::select_fields(&mut payload, &search.fields);
+    let mut response = results_response(
+        status,
+        &payload.to_string(),
+        "application/json; charset=utf-8",
+    );
+    // The total and the token of the result set also go along as headers,
+    // for clients that only look at the results.
+    if status == 200 {
+        if let Some(total) = payload["total"].as_u64() {
+            response.add_header(Header::from_bytes("X-Total-Count", total.to_string()).unwrap());
+        }
+        if let Some(token) = payload["result_set"].as_str() {
+            response.add_header(Header::from_bytes("X-Result-Set", token).unwrap());
+        }
+    }
+    respond(request, id, response)
+}
+
+fn serve_request(mut request: Request, id: &str, state: &ServerState) -> Result<(), Error> {
+    let (frontend, limits) = (&state.frontend, &state.limits);
+    let index = state.index();
+    let redaction = state.privacy.for_request(&request);
+    let url = request.url().to_string();
+    let (path, params) = http::split_url(&url);
+    let route = match router::route(
+        request.method(),
+        path,
+        http::header(&request, "API-Version"),
+    ) {
+        Ok(route) => route,
+        Err(message) => {
+            return serve_error(request, id, 400, ErrorCode::UnsupportedApiVersion, &message)
+        }
+    };
+    match route {
+        Route::Search => {
+            let body = match read_body(&mut request) {
+                Ok(body) => body,
+                Err(message) => {
+                    return serve_error(request, id, 400, ErrorCode::InvalidBody, &message)
+                }
+            };
+            info!(query = %redaction.query(&body).unwrap_or_default(), "search");
+            state.logs.lock().unwrap().append_query(
+                json!({"time": locale::now_rfc3339(), "request_id": id, "query": body}),
+                redaction,
+            );
+            serve_api_search(request, id, state, api::SearchRequest::from_body(&body))?;
+        }
+        Route::SearchParams => {
+            info!(query = %redaction.url(&url), "search");
+            state.logs.lock().unwrap().append_query(
+                json!({"time": locale::now_rfc3339(), "request_id": id, "query": url}),
+                redaction,
+            );
+            serve_api_search(request, id, state, api::SearchRequest::from_params(&params))?;
+        }
+        Route::Preflight => {
+            let mut response = Response::empty(204);
+            for header in CORS
+                .get()
+                .map(|cors| cors.preflight_headers(&request))
+                .unwrap_or_default()
+            {
+                response.add_header(header);
+            }
+            respond(request, id, response)?;
+        }
+        Route::Reload => {
+            let local = request
+                .remote_addr()
+                .is_some_and(|addr| addr.ip().is_loopback());
+            if !local {
+                return serve_error(
+                    request,
+                    id,
+                    403,
+                    ErrorCode::Forbidden,
+                    "the index can only be reloaded from this host",
+                );
+            }
+            let (status, payload) = match &state.served {
+                Some(served) => match reload_index(state, served) {
+                    Ok(change) => (200, change),
+                    // The file the index was reloaded from is broken.
+                    Err(mut payload) => {
+                        payload["error"]["request_id"] = json!(id);
+                        (500, payload)
+                    }
+                },
+                None => no_index(id, ""),
+            };
+            serve_results(
+                request,
+                id,
+                status,
+                &payload.to_string(),
+                "application/json; charset=utf-8",
+            )?;
+        }
+        Route::Documents => serve_documents(request, id, state, &params)?,
+        Route::Feedback => {
+            let body = match read_body(&mut request) {
+                Ok(body) => body,
+                Err(message) => {
+                    return serve_error(request, id, 400, ErrorCode::InvalidBody, &message)
+                }
+            };
+            let Ok(feedback) = serde_json::from_str::<serde_json::Value>(&body) else {
+                return serve_error(
+                    request,
+                    id,
+                    400,
+                    ErrorCode::InvalidBody,
+                    "feedback must be JSON",
+                );
+            };
+            if let Some(popularity) = &state.popularity {
+                popularity.record(&feedback);
+            }
+            ServerLogs::append(
+                &mut state.logs.lock().unwrap().feedback,
+                json!({"time": locale::now_rfc3339(), "request_id": id, "feedback": feedback}),
+                redaction,
+            );
+            respond(request, id, Response::empty(204))?;
+        }
+        Route::IndexPage => {
+            serve_page(
+                request,
+                id,
+                &frontend.index_html,
+                "text/html; charset=utf-8",
+            )?;
+        }
+        Route::Script => {
+            serve_page(
+                request,
+                id,
+                &frontend.static_file("index.js"),
+                "text/javascript; charset=utf-8",
+            )?;
+        }
+        Route::Stylesheet => {
+            serve_page(
+                request,
+                id,
+                &frontend.static_file("style.css"),
+                "text/css; charset=utf-8",
+            )?;
+        }
+        Route::Robots => {
+            serve_page(
+                request,
+                id,
+                &frontend.robots_txt,
+                "text/plain; charset=utf-8",
+            )?;
+        }
+        Route::SearchPage => serve_search(request, id, state)?,
+        Route::Changes => {
+            let since = params
+                .iter()
+                .find(|(name, _)| name == "since")
+                .map(|(_, since)| since.as_str());
+            let (status, payload) = match &state.served {
+                Some(served) => (200, json!({"changes": served.changes(since)})),
+                None => no_index(id, ""),
+            };
+            serve_results(
+                request,
+                id,
+                status,
+                &payload.to_string(),
+                "application/json; charset=utf-8",
+            )?;
+        }
+        Route::HealthHistory => {
+            let (status, payload) = match &state.served {
+                Some(_) => (200, state.health.to_json()),
+                None => no_index(id, ""),
+            };
+            serve_results(
+                request,
+                id,
+                status,
+                &payload.to_string(),
+                "application/json; charset=utf-8",
+            )?;
+        }
+        #[cfg(feature = "metrics")]
+        Route::Metrics => serve_results(
+            request,
+            id,
+            200,
+            &state.health.to_prometheus(),
+            "text/plain; version=0.0.4; charset=utf-8",
+        )?,
+        Route::Doc => {
+            let path = params
+                .iter()
+                .find(|(name, _)| name == "path")
+                .map_or("", |(_, path)| path.as_str());
+            serve_document(request, id, index, Path::new(path))?
+        }
+        Route::Docs => {
+            let body = match read_body(&mut request) {
+                Ok(body) => body,
+                Err(message) => {
+                    return serve_error(request, id, 400, ErrorCode::InvalidBody, &message)
+                }
+            };
+            let (status, payload) =
+                api_response(id, index, "", |handle| api::documents(handle, &body));
+            serve_results(
+                request,
+                id,
+                status,
+                &payload.to_string(),
+                "application/json; charset=utf-8",
+            )?;
+        }
+        Route::Percolate => {
+            let body = match read_body(&mut request) {
+                Ok(body) => body,
+                Err(message) => {
+                    return serve_error(request, id, 400, ErrorCode::InvalidBody, &message)
+                }
+            };
+            let (status, payload) =
+                api_response(id, index, "", |handle| api::percolate(handle, &body));
+            serve_results(
+                request,
+                id,
+                status,
+                &payload.to_string(),
+                "application/json; charset=utf-8",
+            )?;
+        }
+        Route::Thumb => {
+            let path = params
+                .iter()
+                .find(|(name, _)| name == "path")
+                .map_or("", |(_, path)| path.as_str());
+            serve_thumbnail(request, id, index, Path::new(path))?
+        }
+        Route::Complete => {
+            let (status, payload) =
+                api_response(id, index, "", |handle| Ok(api::complete(handle, &params)));
+            serve_results(
+                request,
+                id,
+                status,
+                &payload.to_string(),
+                "application/json; charset=utf-8",
+            )?;
+        }
+        Route::Facets => {
+            let query = params
+                .iter()
+                .find(|(name, _)| name == "q")
+                .map_or("", |(_, query)| query.as_str());
+            let (status, payload) = api_response(id, index, query, |handle| {
+                api::facets(handle, &params, limits, &state.result_sets)
+            });
+            serve_results(
+                request,
+                id,
+                status,
+                &payload.to_string(),
+                "application/json; charset=utf-8",
+            )?;
+        }
+        Route::Aggregate => {
+            let query = params
+                .iter()
+                .find(|(name, _)| name == "q")
+                .map_or("", |(_, query)| query.as_str());
+            let (status, payload) = api_response(id, index, query, |handle| {
+                api::aggregate(handle, &params, limits)
+            });
+            serve_results(
+                request,
+                id,
+                status,
+                &payload.to_string(),
+                "application/json; charset=utf-8",
+            )?;
+        }
+        Route::NotFound => serve_404(request, id)?,
+    }
+    Ok(())
+}
+
+// The index subcommand as given by its arguments, run at the command line or
+// as a job of indexd.
+struct IndexCommand {
+    options: IndexOptions,
+    config: IndexConfigBuilder,
+    report_path: String,
+    git_rev: Option<String>,
+    index_path: String,
+    source_args: Vec<String>,
+    // Whether analysis flags were given, which --incremental checks.
+    analysis_flags: bool,
+    format: StoreFormat,
+    bundle_sources: bool,
+    // Crawl the sites of the URLs instead of fetching only their pages.
+    crawl: Option<CrawlOptions>,
+    // Write the index even while another process holds its lock.
+    force: bool,
+}
+
+fn parse_index_args(
+    program: &str,
+    mut args: impl Iterator<Item = String>,
+) -> Result<IndexCommand, ()> {
+    let mut options = IndexOptions::default();
+    let mut config = IndexConfig::builder();
+    let mut report_path = "index.report.json".to_string();
+    let mut git_rev = None;
+    let mut index_path = "index.json".to_string();
+    let mut source_args = Vec::new();
+    let mut analysis_flags = false;
+    let mut format = None;
+    let mut compress = false;
+    let mut bundle_sources = false;
+    let mut force = false;
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "--git-rev" => git_rev = Some(flag_value(&mut args, program, &flag)?),
+            "--output" => index_path = flag_value(&mut args, program, &flag)?,
+            "--format" => {
+                let name = flag_value(&mut args, program, &flag)?;
+                format = Some(StoreFormat::parse(&name).ok_or_else(|| {
+                    usage(program);
+                    eprintln!(
+                        "ERROR: unknown index format {name}, expected json, bin, sqlite or sharded"
+                    )
+                })?);
+            }
+            "--compress" => compress = true,
+            "--notebook-outputs" => options.extract.notebook_outputs = true,
+            "--ocr" => options.extract.ocr = true,
+            "--thumbnails" => options.extract.thumbnails = true,
+            "--plugins" => {
+                let dir = flag_value(&mut args, program, &flag)?;
+                let plugins = Plugins::load(Path::new(&dir)).map_err(print_error)?;
+                if !plugins.token_filters().is_empty() {
+                    config = config.token_filters(plugins.token_filters().to_vec());
+                    analysis_flags = true;
+                }
+                options.extract.plugins = plugins;
+            }
+            "--bundle-sources" => bundle_sources = true,
+            "--manifest" => options.input_hashes = Some(InputHashes::default()),
+            "--force" => force = true,
+            "--no-cache" => options.extract.cache = false,
+            "--sandbox" => {
+                options.extract.sandbox.get_or_insert_with(Default::default);
+            }
+            "--sandbox-memory-mb" => {
+                // Only Linux limits the address space of the sandbox, a
+                // limit that would be ignored elsewhere is refused.
+                if !cfg!(target_os = "linux") {
+                    usage(program);
+                    eprintln!("ERROR: {flag} is only supported on Linux, elsewhere the memory of the sandbox is not limited");
+                    return Err(());
+                }
+                let limits = options.extract.sandbox.get_or_insert_with(Default::default);
+                limits.memory_mb = parse_scaled_count(&mut args, program, &flag, 1024 * 1024)?;
+            }
+            "--sandbox-cpu-secs" => {
+                let limits = options.extract.sandbox.get_or_insert_with(Default::default);
+                limits.cpu_secs = parse_scaled_count(&mut args, program, &flag, 1)?;
+            }
+            "--quiet" => options.verbosity = Verbosity::Quiet,
+            "--verbose" => options.verbosity = Verbosity::Verbose,
+            "--low-priority" => options.low_priority = true,
+            "--incremental" => options.incremental = true,
+            "--positions" => config = config.positions(true),
+            "--index-stopwords" => config = config.index_stopwords(true),
+            "--field" => {
+                let (name, field) = schema::parse_field(&flag_value(&mut args, program, &flag)?)
+                    .map_err(print_error)?;
+                config = config.field(name, field);
+            }
+            "--hidden" => options.walk.hidden = true,
+            "--one-file-system" => options.walk.one_file_system = true,
+            "--exclude" => options
+                .walk
+                .excludes
+                .push(PathPattern::new(&flag_value(&mut args, program, &flag)?)),
+            "--include" => options
+                .walk
+                .includes
+                .push(PathPattern::new(&flag_value(&mut args, program, &flag)?)),
+            "--gitignore" => options.walk.ignore_files = true,
+            "--follow-symlinks" => options.walk.follow_symlinks = true,
+            "--threads" => options.threads = parse_flag(&mut args, program, &flag)?,
+            "--throttle" => {
+                let mb_per_sec: f64 = parse_flag(&mut args, program, &flag)?;
+                if !mb_per_sec.is_finite() || mb_per_sec <= 0.0 {
+                    usage(program);
+                    eprintln!("ERROR: {flag} must be a positive number of MB/s");
+                    return Err(());
+                }
+                options.throttle_mb_per_sec = Some(mb_per_sec);
+            }
+            "--flush-every" => options.flush_docs = Some(parse_flag(&mut args, program, &flag)?),
+            "--min-doc-freq" => {
+                options.pruning.min_doc_freq = parse_flag(&mut args, program, &flag)?
+            }
+            "--max-doc-freq-pct" => {
+                options.pruning.max_doc_freq_pct = Some(parse_flag(&mut args, program, &flag)?)
+            }
+            "--max-tokens-per-doc" => {
+                options.max_tokens_per_doc = Some(parse_flag(&mut args, program, &flag)?)
+            }
+            "--over-token-limit" => {
+                options.over_token_limit = match flag_value(&mut args, program, &flag)?.as_str() {
+                    "truncate" => OverTokenLimit::Truncate,
+                    "skip" => OverTokenLimit::Skip,
+                    policy => {
+                        usage(program);
+                        eprintln!(
+                            "ERROR: unknown policy {policy} for {flag}, expected truncate or skip"
+                        );
+                        return Err(());
+                    }
+                }
+            }
+            "--min-term-len" => {
+                options.pruning.min_term_len = parse_flag(&mut args, program, &flag)?
+            }
+            "--cache-dir" => {
+                let dir = flag_value(&mut args, program, &flag)?;
+                options.extract.cache_dir = PathBuf::from(dir);
+            }
+            "--report" => {
+                report_path = flag_value(&mut args, program, &flag)?;
+            }
+            _ if !flag.starts_with("--") => source_args.push(flag),
+            _ => {
+                config = parse_config_flag(&mut args, program, &flag, config)?;
+                analysis_flags = true;
+            }
+        }
+    }
+
+    if source_args.is_empty() {
+        usage(program);
+        eprintln!("ERROR: no folder, archive or URL to index is provided");
+        return Err(());
+    }
+    let format = match format.unwrap_or_else(|| StoreFormat::from_extension(&index_path)) {
+        StoreFormat::Binary { .. } => StoreFormat::Binary {
+            compressed: compress,
+        },
+        _ if compress => {
+            eprintln!("ERROR: only binary indexes can be compressed, pass --format bin or name the index .tsidx");
+            return Err(());
+        }
+        format => format,
+    };
+    if options.flush_docs.is_some() {
+        if format != (StoreFormat::Binary { compressed: false }) {
+            eprintln!("ERROR: only uncompressed binary indexes can be flushed, pass --format bin or name the index .tsidx");
+            return Err(());
+        }
+        if options.pruning != Pruning::default() {
+            eprintln!("ERROR: pruning needs the terms of all documents, it cannot be combined with --flush-every");
+            return Err(());
+        }
+    }
+    Ok(IndexCommand {
+        options,
+        config,
+        report_path,
+        git_rev,
+        index_path,
+        source_args,
+        analysis_flags,
+        format,
+        bundle_sources,
+        crawl: None,
+        force,
+    })
+}
+
+// Indexes the sources of the command into its index file.
+fn run_index(command: IndexCommand) -> Result<(), Error> {
+    let IndexCommand {
+        mut options,
+        config,
+        report_path,
+        git_rev,
+        index_path,
+        source_args,
+        analysis_flags,
+        format,
+        bundle_sources,
+        crawl,
+        force,
+    } = command;
+    let mut sources = Vec::<Box<dyn DocumentSource>>::new();
+    let (urls, paths): (Vec<_>, Vec<_>) =
+        source_args.into_iter().partition(|arg| source::is_url(arg));
+    match crawl {
+        Some(crawl) => {
+            for start in urls {
+                sources.push(Box::new(CrawlSource {
+                    start,
+                    options: crawl.clone(),
+                }));
+            }
+        }
+        None if !urls.is_empty() => sources.push(Box::new(HttpSource { urls })),
+        None => {}
+    }
+    for path in paths.into_iter().map(PathBuf::from) {
+        if source::is_archive(&path) {
+            sources.push(Box::new(ArchiveSource { path }));
+        } else if let Some(rev) = &git_rev {
+            sources.push(Box::new(GitSource {
+                repo: path,
+                rev: rev.clone(),
+            }));
+        } else {
+            sources.push(Box::new(FolderSource {
+                root: path,
+                walk: options.walk.clone(),
+            }));
+        }
+    }
+
+    // Held until the index is written.
+    let _lock = (!force)
+        .then(|| IndexLock::acquire(&index_path))
+        .transpose()?;
+    let mut writer = if options.incremental && Path::new(&index_path).exists() {
+        let writer = IndexWriter::open(&index_path)?;
+        let requested = config.build();
+        let fields = &requested.fields;
+        if !fields.is_empty() && *fields != writer.config().fields {
+            return Err(Error::invalid(format!("{index_path} was built with other field types than requested, rebuild it without --incremental to change them")));
+        }
+        let differences = writer.config().differences(&requested);
+        if analysis_flags && !differences.is_empty() {
+            return Err(Error::invalid(format!(
+                "{index_path} was built with other analyzer settings than requested: {}; rebuild the index without --incremental to change them",
+                differences.join("; ")
+            )));
+        }
+        writer
+    } else {
+        IndexWriter::create_as(&index_path, config.build(), format)
+    };
+    if bundle_sources {
+        options.bundle_sources = Some(SourceBundle::beside(Path::new(&index_path)));
+    }
+    let mut report = IndexReport::default();
+    let progress = options
+        .progress
+        .get_or_insert_with(|| Arc::new(Progress::default()))
+        .clone();
+    let started = Instant::now();
+    let indexed = if options.verbosity == Verbosity::Normal {
+        let done = AtomicBool::new(false);
+        thread::scope(|scope| {
+            scope.spawn(|| progress::show(&progress, started, &done));
+            let indexed = indexer::index_sources(&sources, &mut writer, &options, &mut report);
+            done.store(true, Ordering::Relaxed);
+            indexed
+        })
+    } else {
+        indexer::index_sources(&sources, &mut writer, &options, &mut report)
+    };
+    indexed?;
+    if options.pruning != Pruning::default() {
+        let pruned = writer.prune(&options.pruning);
+        println!("Pruned {pruned} terms");
+    }
+    writer.commit()?;
+    report.save(&report_path)?;
+    // Only after the commit, so a failed run leaves the copies the
+    // old index reads.
+    if let Some(bundle) = &options.bundle_sources {
+        let removed = bundle.retain(writer.file_stamps()).map_err(|err| {
+            Error::io(
+                format!("could not clean up {dir}", dir = bundle.dir().display()),
+                err,
+            )
+        })?;
+        if removed > 0 {
+            println!("Removed {removed} files no longer indexed from the bundle");
+        }
+    }
+    if let Some(hashes) = &options.input_hashes {
+        let index_path = Path::new(&index_path);
+        let manifest = hashes.manifest(index_path, writer.config(), writer.file_stamps())?;
+        let manifest_path = provenance::manifest_path(index_path);
+        manifest.save(&manifest_path)?;
+        if options.verbosity > Verbosity::Quiet {
+            println!(
+                "Wrote the hashes of {inputs} inputs to {path}",
+                inputs = manifest.inputs.len(),
+                path = manifest_path.display()
+            );
+        }
+    }
+    if options.verbosity > Verbosity::Quiet {
+        let model = writer.model();
+        let terms = writer.unique_terms();
+        println!(
+            "Indexed {docs} documents with {terms} unique terms from {files} files in {elapsed}, {failed} failed",
+            docs = model.docs.len(),
+            files = progress.done(),
+            elapsed = progress::elapsed(started.elapsed()),
+            failed = progress.failed(),
+        );
+    }
+    Ok(())
+}
+
+// Log lines of the server go to stderr. TINYSEARCH_LOG=debug also reports
+// how long each stage of a search took; `time_phases` records those times
+// for the slow query log whatever the level.
+fn init_tracing(time_phases: bool) {
+    let level = env::var("TINYSEARCH_LOG")
+        .ok()
+        .and_then(|level| level.parse::<LevelFilter>().ok())
+        .unwrap_or(LevelFilter::INFO);
+    let no_color = env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    let log_lines = tracing_subscriber::fmt::layer()
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal() && !no_color)
+        .with_filter(level);
+    tracing_subscriber::registry()
+        .with(log_lines)
+        .with(time_phases.then_some(slowlog::PhaseTimer))
+        .init();
+}
+
+fn entry() -> Result<(), ()> {
+    let args = env::args().collect::<Vec<_>>();
+    // The sandboxed child gets its arguments from the parent as they are.
+    let args = if args.get(1).map(String::as_str) == Some(extract::sandbox::SUBCOMMAND) {
+        args
+    } else {
+        normalize_args(args.into_iter())
+    };
+    let mut args = args.into_iter();
+    let program = args.next().expect("path to program is provided.");
+
+    let sub_command = args.next().ok_or_else(|| {
+        usage(&program);
+        eprintln!("ERROR: no subcommand is provided")
+    })?;
+
+    match sub_command.as_str() {
+        "--help" | "help" => {
+            print!("{}", usage_text(&program));
+            return Ok(());
+        }
+        "--version" => {
+            println!("tinySearch {}", env!("CARGO_PKG_VERSION"));
+            return Ok(());
+        }
+        _ => {}
+    }
+    if args.as_slice().iter().any(|arg| arg == "--help") {
+        if let Some(usage) = subcommand_usage(&program, &sub_command) {
+            print!("{usage}");
+            return Ok(());
+        }
+    }
+
+    match sub_command.as_str() {
+        "index" => {
+            let command = parse_index_args(&program, args.by_ref())?;
+            run_index(command).map_err(print_error)?;
+        }
+        "crawl" => {
+            let mut crawl = CrawlOptions::default();
+            let mut index_args = vec![program.clone()];
+            while let Some(flag) = args.next() {
+                match flag.as_str() {
+                    "--depth" => crawl.max_depth = parse_flag(&mut args, &program, &flag)?,
+                    "--max-pages" => crawl.max_pages = parse_flag(&mut args, &program, &flag)?,
+                    "--delay" => {
+                        crawl.delay = Duration::from_millis(parse_flag(&mut args, &program, &flag)?)
+                    }
+                    "--ignore-robots" => crawl.robots = false,
+                    _ => index_args.push(flag),
+                }
+            }
+            let mut command = parse_index_args(&program, index_args.into_iter().skip(1))?;
+            if let Some(arg) = command.source_args.iter().find(|arg| !source::is_url(arg)) {
+                usage(&program);
+                eprintln!("ERROR: {sub_command} starts from the pages at URLs, {arg} is not one");
+                return Err(());
+            }
+            command.crawl = Some(crawl);
+            run_index(command).map_err(print_error)?;
+        }
+        "indexd" => {
+            let mut address = indexd::DEFAULT_ADDRESS.to_string();
+            for arg in args.by_ref() {
+                match arg.as_str() {
+                    _ if !arg.starts_with("--") => address = arg,
+                    _ => {
+                        usage(&program);
+                        eprintln!("ERROR: unknown flag {arg}");
+                        return Err(());
+                    }
+                }
+            }
+            init_tracing(false);
+            indexd::run(&address).map_err(print_error)?;
+        }
+        "import" => {
+            let mut config = IndexConfig::builder();
+            let mut index_path = "index.json".to_string();
+            let mut format = ImportFormat::Ndjson;
+            let mut text_field = None;
+            let mut id_field = None;
+            let mut export_path = None;
+            while let Some(flag) = args.next() {
+                match flag.as_str() {
+                    "--output" => index_path = flag_value(&mut args, &program, &flag)?,
+                    "--format" => {
+                        let name = flag_value(&mut args, &program, &flag)?;
+                        format = ImportFormat::parse(&name).ok_or_else(|| {
+                            usage(&program);
+                            eprintln!("ERROR: unknown export format {name}, expected ndjson")
+                        })?;
+                    }
+                    "--text-field" => text_field = Some(flag_value(&mut args, &program, &flag)?),
+                    "--id-field" => id_field = Some(flag_value(&mut args, &program, &flag)?),
+                    "--field" => {
+                        let (name, field) =
+                            schema::parse_field(&flag_value(&mut args, &program, &flag)?)
+                                .map_err(print_error)?;
+                        config = config.field(name, field);
+                    }
+                    _ if !flag.starts_with("--") && export_path.is_none() => {
+                        export_path = Some(PathBuf::from(flag))
+                    }
+                    _ => config = parse_config_flag(&mut args, &program, &flag, config)?,
+                }
+            }
+            let export_path = export_path.ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no export file is provided for {sub_command} subcommand")
+            })?;
+            let text_field = text_field.ok_or_else(|| {
+                usage(&program);
+                eprintln!(
+                    "ERROR: {sub_command} needs the --text-field holding the text of the documents"
+                )
+            })?;
+            let options = ImportOptions {
+                format,
+                text_field,
+                id_field,
+            };
+
+            let _lock = IndexLock::acquire(&index_path).map_err(print_error)?;
+            let mut writer = IndexWriter::create(&index_path, config.build());
+            let stats =
+                import::import_file(&export_path, &mut writer, &options).map_err(print_error)?;
+            println!(
+                "Imported {imported} documents ({skipped} skipped, {duplicates} duplicate ids)",
+                imported = stats.imported,
+                skipped = stats.skipped,
+                duplicates = stats.duplicates
+            );
+            if stats.imported == 0 {
+                eprintln!(
+                    "ERROR: no documents could be imported from {path}",
+                    path = export_path.display(),
+                );
+                return Err(());
+            }
+            writer.commit().map_err(print_error)?;
+        }
+        "merge" => {
+            let mut index_paths = Vec::new();
+            let mut output = None;
+            let mut format = None;
+            let mut on_duplicate = OnDuplicate::Newest;
+            while let Some(flag) = args.next() {
+                match flag.as_str() {
+                    "--output" => output = Some(flag_value(&mut args, &program, &flag)?),
+                    "--format" => {
+                        let name = flag_value(&mut args, &program, &flag)?;
+                        format = Some(StoreFormat::parse(&name).ok_or_else(|| {
+                            usage(&program);
+                            eprintln!(
+                                "ERROR: unknown index format {name}, expected json, bin, sqlite or sharded"
+                            )
+                        })?);
+                    }
+                    "--on-duplicate" => {
+                        let name = flag_value(&mut args, &program, &flag)?;
+                        on_duplicate = OnDuplicate::parse(&name).ok_or_else(|| {
+                            usage(&program);
+                            eprintln!(
+                                "ERROR: unknown --on-duplicate {name}, expected newest or error"
+                            )
+                        })?;
+                    }
+                    _ if !flag.starts_with("--") => index_paths.push(flag),
+                    _ => {
+                        usage(&program);
+                        eprintln!("ERROR: unknown flag {flag} for {sub_command} subcommand");
+                        return Err(());
+                    }
+                }
+            }
+            if index_paths.len() < 2 {
+                usage(&program);
+                eprintln!("ERROR: {sub_command} needs at least two indexes to merge");
+                return Err(());
+            }
+            let output = output.ok_or_else(|| {
+                usage(&program);
+                eprintln!(
+                    "ERROR: {sub_command} needs the --output file to write the merged index to"
+                )
+            })?;
+            if index_paths.contains(&output) {
+                eprintln!("ERROR: {output} is merged, it cannot be the --output too");
+                return Err(());
+            }
+            let indexes = index_paths
+                .into_iter()
+                .map(|path| load_model(&path).map(|model| (path, model)))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(print_error)?;
+            let config = indexes[0].1.manifest.config.clone();
+            let format = format.unwrap_or_else(|| StoreFormat::from_extension(&output));
+            let _lock = IndexLock::acquire(&output).map_err(print_error)?;
+            let mut writer = IndexWriter::create_as(&output, config, format);
+            let count = indexes.len();
+            let stats = merge::merge(indexes, &mut writer, on_duplicate).map_err(print_error)?;
+            writer.commit().map_err(print_error)?;
+            println!(
+                "Merged {docs} documents of {count} indexes into {output} ({duplicates} files in more than one)",
+                docs = stats.docs,
+                duplicates = stats.duplicates
+            );
+        }
+        "search" => {
+            let index_path = args.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no path to index is provided for {sub_command} subcommand")
+            })?;
+            let mut options = SearchOptions::default();
+            let mut queries_path = None;
+            let mut words = Vec::new();
+            while let Some(flag) = args.next() {
+                match flag.as_str() {
+                    "--queries" => queries_path = Some(flag_value(&mut args, &program, &flag)?),
+                    _ if !flag.starts_with("--") => words.push(flag),
+                    _ => parse_search_flag(&mut args, &program, &flag, &mut options)?,
+                }
+            }
+            match queries_path {
+                Some(queries_path) => search_batch(&index_path, &queries_path, &options)?,
+                None if !words.is_empty() => {
+                    let handle = SearchHandle::open(&index_path, options.cache_sizes)
+                        .map_err(print_error)?
+                        .with_ranking(options.ranking)
+                        .with_min_score(options.min_score)
+                        .with_rewriter(options.rewriter.clone());
+                    check_analyzer(
+                        &index_path,
+                        &handle,
+                        options.analyzer.clone(),
+                        options.adopt_index_analyzer,
+                    )?;
+                    search_and_print(&handle, &words.join(" "), &options)?
+                }
+                None => check_index(&index_path, &options.filters)?,
+            }
+        }
+        "repl" => {
+            let index_path = args.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no path to index is provided for {sub_command} subcommand")
+            })?;
+            let mut options = SearchOptions::default();
+            let mut history = None;
+            let mut bookmarks = None;
+            while let Some(flag) = args.next() {
+                match flag.as_str() {
+                    "--history" => history = Some(flag_value(&mut args, &program, &flag)?),
+                    "--bookmarks" => bookmarks = Some(flag_value(&mut args, &program, &flag)?),
+                    _ => parse_search_flag(&mut args, &program, &flag, &mut options)?,
+                }
+            }
+            #[cfg(feature = "repl")]
+            {
+                let handle = SearchHandle::open(&index_path, options.cache_sizes)
+                    .map_err(print_error)?
+                    .with_ranking(options.ranking)
+                    .with_min_score(options.min_score)
+                    .with_rewriter(options.rewriter.clone());
+                check_analyzer(
+                    &index_path,
+                    &handle,
+                    options.analyzer.clone(),
+                    options.adopt_index_analyzer,
+                )?;
+                let files = repl::ReplFiles {
+                    history: history.map(PathBuf::from),
+                    bookmarks: bookmarks.map(PathBuf::from),
+                }
+                .or_home();
+                repl::run(&handle, &options, &files).map_err(print_error)?;
+            }
+            #[cfg(not(feature = "repl"))]
+            {
+                let _ = (index_path, options, history, bookmarks);
+                eprintln!("ERROR: {sub_command} is not supported by this build of tinySearch, rebuild it with the repl feature");
+                return Err(());
+            }
+        }
+        "grep" => {
+            let dir_path = args.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no directory path is provided for {sub_command} subcommand")
+            })?;
+            let mut index_options = IndexOptions::default();
+            // An ad-hoc search should leave nothing behind in the working directory.
+            index_options.extract.cache = false;
+            index_options.verbosity = Verbosity::Quiet;
+            let mut options = SearchOptions::default();
+            let mut words = Vec::new();
+            while let Some(flag) = args.next() {
+                match flag.as_str() {
+                    "--hidden" => index_options.walk.hidden = true,
+                    "--exclude" => index_options
+                        .walk
+                        .excludes
+                        .push(PathPattern::new(&flag_value(&mut args, &program, &flag)?)),
+                    "--include" => index_options
+                        .walk
+                        .includes
+                        .push(PathPattern::new(&flag_value(&mut args, &program, &flag)?)),
+                    "--gitignore" => index_options.walk.ignore_files = true,
+                    "--follow-symlinks" => index_options.walk.follow_symlinks = true,
+                    "--threads" => index_options.threads = parse_flag(&mut args, &program, &flag)?,
+                    _ if !flag.starts_with("--") => words.push(flag),
+                    _ => parse_search_flag(&mut args, &program, &flag, &mut options)?,
+                }
+            }
+            if words.is_empty() {
+                usage(&program);
+                eprintln!("ERROR: no query is provided for {sub_command} subcommand");
+                return Err(());
+            }
+            // There is no stored index to disagree with, the flags say how
+            // the folder is analyzed.
+            let config = options.analyzer.take().unwrap_or_default().build();
+            let mut writer = IndexWriter::new(config);
+            indexer::tf_index_of_folder(
+                Path::new(&dir_path),
+                &mut writer,
+                &index_options,
+                &mut IndexReport::default(),
+            )
+            .map_err(print_error)?;
+            let handle = SearchHandle::new(writer.into_model())
+                .with_extract_options(index_options.extract)
+                .with_ranking(options.ranking)
+                .with_min_score(options.min_score)
+                .with_rewriter(options.rewriter.clone());
+            search_and_print(&handle, &words.join(" "), &options)?;
+        }
+        "eval" => {
+            let index_path = args.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no path to index is provided for {sub_command} subcommand")
+            })?;
+            let mut queries_path = None;
+            let mut qrels_path = None;
+            let mut ranking = Ranking::default();
+            while let Some(flag) = args.next() {
+                match flag.as_str() {
+                    "--queries" => queries_path = Some(flag_value(&mut args, &program, &flag)?),
+                    "--qrels" => qrels_path = Some(flag_value(&mut args, &program, &flag)?),
+                    "--ranking" => ranking = parse_ranking(&mut args, &program, &flag)?,
+                    _ => {
+                        usage(&program);
+                        eprintln!("ERROR: unknown flag {flag} for {sub_command} subcommand");
+                        return Err(());
+                    }
+                }
+            }
+            let (Some(queries_path), Some(qrels_path)) = (queries_path, qrels_path) else {
+                usage(&program);
+                eprintln!("ERROR: {sub_command} needs both --queries and --qrels");
+                return Err(());
+            };
+            let model = load_model(&index_path).map_err(print_error)?;
+            eval::run_eval(&model, &queries_path, &qrels_path, ranking).map_err(print_error)?;
+        }
+        "analyze" => {
+            let text = args.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no text is provided for {sub_command} subcommand")
+            })?;
+            let mut config = IndexConfig::builder();
+            while let Some(flag) = args.next() {
+                match flag.as_str() {
+                    "--analyzer" => {
+                        let name = flag_value(&mut args, &program, &flag)?;
+                        config = config.profile(find_profile(&name)?);
+                    }
+                    _ => config = parse_config_flag(&mut args, &program, &flag, config)?,
+                }
+            }
+            let analyzer = Analyzer::new(&config.build());
+            let stages = analyzer.stages(&text);
+            let width = stages.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+            for (name, tokens) in &stages {
+                let tokens = tokens
+                    .iter()
+                    .map(|token| format!("{}@{}", token.text, token.position))
+                    .collect::<Vec<_>>();
+                println!("{name:>width$}: {}", tokens.join(" "));
+            }
+        }
+        "compare-analyzers" => {
+            let dir_path = args.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no directory path is provided for {sub_command} subcommand")
+            })?;
+            let mut index_options = IndexOptions::default();
+            index_options.extract.cache = false;
+            index_options.verbosity = Verbosity::Quiet;
+            let mut analyzers = [None, None];
+            let mut sample = DEFAULT_COMPARISON_SAMPLE;
+            let mut as_json = false;
+            while let Some(flag) = args.next() {
+                match flag.as_str() {
+                    "--a" => analyzers[0] = Some(flag_value(&mut args, &program, &flag)?),
+                    "--b" => analyzers[1] = Some(flag_value(&mut args, &program, &flag)?),
+                    "--sample" => sample = parse_flag(&mut args, &program, &flag)?,
+                    "--json" => as_json = true,
+                    "--hidden" => index_options.walk.hidden = true,
+                    "--exclude" => index_options
+                        .walk
+                        .excludes
+                        .push(PathPattern::new(&flag_value(&mut args, &program, &flag)?)),
+                    "--include" => index_options
+                        .walk
+                        .includes
+                        .push(PathPattern::new(&flag_value(&mut args, &program, &flag)?)),
+                    "--gitignore" => index_options.walk.ignore_files = true,
+                    "--follow-symlinks" => index_options.walk.follow_symlinks = true,
+                    "--threads" => index_options.threads = parse_flag(&mut args, &program, &flag)?,
+                    _ => {
+                        usage(&program);
+                        eprintln!("ERROR: unknown flag {flag} for {sub_command} subcommand");
+                        return Err(());
+                    }
+                }
+            }
+            let [Some(a), Some(b)] = analyzers else {
+                usage(&program);
+                eprintln!("ERROR: {sub_command} needs both --a and --b");
+                return Err(());
+            };
+            if sample == 0 {
+                eprintln!("ERROR: --sample has to be at least 1");
+                return Err(());
+            }
+            let index_sample = |name: &str| -> Result<AnalysisStats, ()> {
+                let config = find_profile(name)?;
+                let mut writer = IndexWriter::new(config);
+                let source = SampledSource {
+                    inner: Box::new(FolderSource {
+                        root: PathBuf::from(&dir_path),
+                        walk: index_options.walk.clone(),
+                    }),
+                    size: sample,
+                };
+                indexer::index_sources(
+                    &[Box::new(source)],
+                    &mut writer,
+                    &index_options,
+                    &mut IndexReport::default(),
+                )
+                .map_err(print_error)?;
+                AnalysisStats::of(&writer.into_model()).map_err(print_error)
+            };
+            let a_stats = index_sample(&a)?;
+            let b_stats = index_sample(&b)?;
+            print_analysis_comparison(&[(a, a_stats), (b, b_stats)], as_json);
+        }
+        // The parent takes the first line the child prints as the error of
+        // the document, so it goes without the ERROR: prefix.
+        extract::sandbox::SUBCOMMAND => {
+            extract::sandbox::child(args).map_err(|err| eprintln!("{err}"))?
+        }
+        "extract" => {
+            let file_path = args.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no file path is provided for {sub_command} subcommand")
+            })?;
+            // Always run the extractors, the point is to see what they produce now.
+            let mut options = extract::ExtractOptions {
+                cache: false,
+                ..Default::default()
+            };
+            let mut as_json = false;
+            for flag in args.by_ref() {
+                match flag.as_str() {
+                    "--json" => as_json = true,
+                    "--notebook-outputs" => options.notebook_outputs = true,
+                    "--ocr" => options.ocr = true,
+                    "--thumbnails" => options.thumbnails = true,
+                    "--sandbox" => options.sandbox = Some(Default::default()),
+                    _ => {
+                        usage(&program);
+                        eprintln!("ERROR: unknown flag {flag} for {sub_command} subcommand");
+                        return Err(());
+                    }
+                }
+            }
+            let chunks =
+                extract::extract_chunks(Path::new(&file_path), &options).map_err(|err| {
+                    eprintln!("ERROR: could not extract {file_path}: {err}");
+                })?;
+            if as_json {
+                let chunks = chunks
+                    .iter()
+                    .map(|chunk| {
+                        json!({
+                            "anchor": chunk.anchor,
+                            "meta": chunk.meta,
+                            "text": chunk.text,
+                            "terms": index_document(&Analyzer::default(), &chunk.text).len(),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                let doc = json!({"path": file_path, "chunks": chunks});
+                println!("{}", serde_json::to_string_pretty(&doc).unwrap());
+            } else {
+                for (i, chunk) in chunks.iter().enumerate() {
+                    if i > 0 {
+                        println!();
+                    }
+                    println!("{}", chunk.text.trim_end());
+                }
+            }
+        }
+        "doctor" => {
+            let env_flag = |flag: &str| env::var(env_var_of(flag)).ok();
+            let mut options = doctor::DoctorOptions {
+                index: env_flag("--index"),
+                address: env::var("TINYSEARCH_ADDRESS")
+                    .unwrap_or_else(|_| "127.0.0.1:8888".to_string()),
+                frontend: env_flag("--frontend"),
+                profiles: match env::var("TINYSEARCH_PROFILES") {
+                    Ok(path) => Some(path),
+                    Err(_) if Path::new(PROFILES_FILE).exists() => Some(PROFILES_FILE.to_string()),
+                    Err(_) => None,
+                },
+            };
+            while let Some(flag) = args.next() {
+                match flag.as_str() {
+                    "--index" => options.index = Some(flag_value(&mut args, &program, &flag)?),
+                    "--frontend" => {
+                        options.frontend = Some(flag_value(&mut args, &program, &flag)?)
+                    }
+                    _ if !flag.starts_with("--") => options.address = flag,
+                    _ => {
+                        usage(&program);
+                        eprintln!("ERROR: unknown flag {flag}");
+                        return Err(());
+                    }
+                }
+            }
+            doctor::run(&doctor::checks(), &options)?;
+        }
+        "fsck" => {
+            let index_path = args.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no path to index is provided for {sub_command} subcommand")
+            })?;
+            let mut thorough = true;
+            for flag in args.by_ref() {
+                match flag.as_str() {
+                    "--quick" => thorough = false,
+                    _ => {
+                        usage(&program);
+                        eprintln!("ERROR: unknown flag {flag}");
+                        return Err(());
+                    }
+                }
+            }
+            let problems = fsck::check_index(&index_path, thorough).map_err(print_error)?;
+            report_problems(&index_path, &problems)?;
+            println!("{index_path}: no problems found");
+        }
+        "repair" => {
+            let index_path = args.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no path to index is provided for {sub_command} subcommand")
+            })?;
+            let mut output = None;
+            let mut dry_run = false;
+            while let Some(flag) = args.next() {
+                match flag.as_str() {
+                    "-o" | "--output" => output = Some(flag_value(&mut args, &program, &flag)?),
+                    "--dry-run" => dry_run = true,
+                    _ => {
+                        usage(&program);
+                        eprintln!("ERROR: unknown flag {flag} for {sub_command} subcommand");
+                        return Err(());
+                    }
+                }
+            }
+            let output = output.unwrap_or_else(|| index_path.clone());
+            let _lock = IndexLock::acquire(&output).map_err(print_error)?;
+            let repair = fsck::repair_index(&index_path, &output, dry_run).map_err(print_error)?;
+            if !repair.is_needed() {
+                println!("{index_path}: no problems found, nothing to repair");
+                return Ok(());
+            }
+            for problem in repair.problems.iter().take(MAX_REPORTED_PROBLEMS) {
+                println!("found: {problem}");
+            }
+            if repair.problems.len() > MAX_REPORTED_PROBLEMS {
+                println!(
+                    "found: ... and {more} more problems",
+                    more = repair.problems.len() - MAX_REPORTED_PROBLEMS
+                );
+            }
+            let verb = if dry_run { "would repair" } else { "repaired" };
+            for change in &repair.repairs {
+                println!("{verb}: {change}");
+            }
+            if dry_run {
+                return Ok(());
+            }
+            report_problems(&output, &repair.remaining)?;
+            println!(
+                "{output}: repaired {count} problems",
+                count = repair.problems.len()
+            );
+        }
+        "verify-manifest" => {
+            let index_path = args.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no path to index is provided for {sub_command} subcommand")
+            })?;
+            let mut manifest_path = provenance::manifest_path(Path::new(&index_path));
+            let mut root = PathBuf::from(".");
+            let mut as_json = false;
+            while let Some(flag) = args.next() {
+                match flag.as_str() {
+                    "--manifest" => {
+                        manifest_path = PathBuf::from(flag_value(&mut args, &program, &flag)?)
+                    }
+                    "--root" => root = PathBuf::from(flag_value(&mut args, &program, &flag)?),
+                    "--json" => as_json = true,
+                    _ => {
+                        usage(&program);
+                        eprintln!("ERROR: unknown flag {flag} for {sub_command} subcommand");
+                        return Err(());
+                    }
+                }
+            }
+            let manifest = BuildManifest::load(&manifest_path).map_err(print_error)?;
+            let verification = provenance::verify(&manifest, &root, Some(Path::new(&index_path)));
+            if as_json {
+                println!("{}", json!(verification));
+            } else {
+                for (path, state) in &verification.changed {
+                    let state = match state {
+                        InputState::Unchanged => "unchanged",
+                        InputState::Modified => "modified",
+                        InputState::Missing => "missing",
+                        InputState::Unchecked => "unchecked",
+                    };
+                    println!("{state}\t{path}", path = path.display());
+                }
+                let count = |wanted| {
+                    verification
+                        .changed
+                        .iter()
+                        .filter(|(_, state)| *state == wanted)
+                        .count()
+                };
+                let index = match verification.index_matches {
+                    Some(true) => "the index file is the one built",
+                    Some(false) => "the index file is not the one built",
+                    None => "the manifest has no hash of the index file",
+                };
+                println!(
+                    "{index_path}: {unchanged} of {inputs} inputs unchanged, {modified} modified, {missing} missing, {unchecked} URLs and archive members not checked; {index}",
+                    unchanged = verification.unchanged,
+                    inputs = manifest.inputs.len(),
+                    modified = count(InputState::Modified),
+                    missing = count(InputState::Missing),
+                    unchecked = count(InputState::Unchecked),
+                );
+            }
+            if !verification.is_verified() {
+                eprintln!(
+                    "ERROR: {index_path} does not match its manifest {path}",
+                    path = manifest_path.display()
+                );
+                return Err(());
+            }
+        }
+        "diff" => {
+            let old_path = args.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no old index is provided for {sub_command} subcommand")
+            })?;
+            let new_path = args.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no new index is provided for {sub_command} subcommand")
+            })?;
+            let old = load_model(&old_path).map_err(print_error)?;
+            let new = load_model(&new_path).map_err(print_error)?;
+            diff::print_index_diff(&old.docs, &new.docs);
+        }
+        "stats" => {
+            let index_path = args.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no path to index is provided for {sub_command} subcommand")
+            })?;
+            let mut top = stats::DEFAULT_TOP_TERMS;
+            let mut as_json = false;
+            while let Some(flag) = args.next() {
+                match flag.as_str() {
+                    "--top" => top = parse_flag(&mut args, &program, &flag)?,
+                    "--json" => as_json = true,
+                    _ => {
+                        usage(&program);
+                        eprintln!("ERROR: unknown flag {flag} for {sub_command} subcommand");
+                        return Err(());
+                    }
+                }
+            }
+            let size = fs::metadata(&index_path)
+                .map(|metadata| metadata.len())
+                .map_err(|err| {
+                    print_error(Error::io(format!("could not read {index_path}"), err))
+                })?;
+            let model = load_model(&index_path).map_err(print_error)?;
+            let stats = IndexStats::of(&model, top);
+            print_stats(&index_path, size, &stats, as_json);
+        }
+        "vocab" => {
+            let index_path = args.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no path to index is provided for {sub_command} subcommand")
+            })?;
+            let mut top = vocab::DEFAULT_VOCAB_TERMS;
+            let mut stopword_share = vocab::DEFAULT_STOPWORD_SHARE;
+            let mut stopwords_path = None;
+            let mut as_json = false;
+            while let Some(flag) = args.next() {
+                match flag.as_str() {
+                    "--top" => top = parse_flag(&mut args, &program, &flag)?,
+                    "--stopword-share" => {
+                        stopword_share = parse_flag(&mut args, &program, &flag)?;
+                        if !(0.0..=1.0).contains(&stopword_share) {
+                            eprintln!("ERROR: {flag} has to be between 0 and 1");
+                            return Err(());
+                        }
+                    }
+                    "--stopwords" => stopwords_path = Some(flag_value(&mut args, &program, &flag)?),
+                    "--json" => as_json = true,
+                    _ => {
+                        usage(&program);
+                        eprintln!("ERROR: unknown flag {flag} for {sub_command} subcommand");
+                        return Err(());
+                    }
+                }
+            }
+            let model = load_model(&index_path).map_err(print_error)?;
+            let vocabulary = Vocabulary::of(&model, top, stopword_share);
+            print_vocabulary(&vocabulary, as_json)?;
+            if let Some(path) = stopwords_path {
+                write_stopwords(&path, &index_path, &vocabulary)?;
+            }
+        }
+        "export-site" => {
+            let index_path = args.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no path to index is provided for {sub_command} subcommand")
+            })?;
+            let mut out = None;
+            let mut frontend_path = None;
+            let mut title = None;
+            let mut lang = None;
+            let mut templates = None;
+            let mut ranking = Ranking::default();
+            while let Some(flag) = args.next() {
+                match flag.as_str() {
+                    "--out" => out = Some(PathBuf::from(flag_value(&mut args, &program, &flag)?)),
+                    "--frontend" => frontend_path = Some(flag_value(&mut args, &program, &flag)?),
+                    "--title" => title = Some(flag_value(&mut args, &program, &flag)?),
+                    "--lang" => lang = Some(flag_value(&mut args, &program, &flag)?),
+                    "--templates" => {
+                        templates = Some(PathBuf::from(flag_value(&mut args, &program, &flag)?))
+                    }
+                    "--ranking" => ranking = parse_ranking(&mut args, &program, &flag)?,
+                    _ => {
+                        usage(&program);
+                        eprintln!("ERROR: unknown flag {flag} for {sub_command} subcommand");
+                        return Err(());
+                    }
+                }
+            }
+            let Some(out) = out else {
+                usage(&program);
+                eprintln!("ERROR: no output folder is provided for {sub_command} subcommand, use --out <dir>");
+                return Err(());
+            };
+            // Flags win over the config file, as for serve.
+            let mut frontend = match &frontend_path {
+                Some(path) => FrontendConfig::load(path).map_err(print_error)?,
+                None => FrontendConfig::default(),
+            };
+            frontend.title = title.unwrap_or(frontend.title);
+            frontend.lang = lang.unwrap_or(frontend.lang);
+            frontend.templates = templates.or(frontend.templates);
+            let frontend = frontend.render().map_err(print_error)?;
+            let model = load_model(&index_path).map_err(print_error)?;
+            let bundle = SourceBundle::beside(Path::new(&index_path));
+            let sources = bundle.exists().then(|| bundle.sources(&model));
+            let report = site::export_site(
+                &model,
+                &frontend,
+                sources,
+                &extract::ExtractOptions::default(),
+                ranking,
+                &out,
+            )
+            .map_err(print_error)?;
+            if report.without_text > 0 {
+                eprintln!(
+                    "WARNING: could not read the text of {count} documents, they are searchable but have no page",
+                    count = report.without_text
+                );
+            }
+            println!(
+                "Wrote a site of {docs} documents to {out}, open {index} to search it",
+                docs = report.docs,
+                out = out.display(),
+                index = out.join("index.html").display()
+            );
+        }
+        "upgrade" => {
+            let index_path = args.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no path to index is provided for {sub_command} subcommand")
+            })?;
+            let mut output = None;
+            while let Some(flag) = args.next() {
+                match flag.as_str() {
+                    "-o" | "--output" => output = Some(flag_value(&mut args, &program, &flag)?),
+                    _ => {
+                        usage(&program);
+                        eprintln!("ERROR: unknown flag {flag} for {sub_command} subcommand");
+                        return Err(());
+                    }
+                }
+            }
+            let output = output.unwrap_or_else(|| index_path.clone());
+            let _lock = IndexLock::acquire(&output).map_err(print_error)?;
+            let model = load_model(&index_path).map_err(print_error)?;
+            let version = model.manifest.version;
+            if version == INDEX_VERSION && output == index_path {
+                println!("{index_path} is already in format version {INDEX_VERSION}");
+                return Ok(());
+            }
+            model.save(&output).map_err(print_error)?;
+            println!("Upgraded {output} from format version {version} to {INDEX_VERSION}");
+        }
+        "rollback" => {
+            let index_path = args.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no path to index is provided for {sub_command} subcommand")
+            })?;
+            let mut snapshot_dir = None;
+            let mut generation = None;
+            let mut reload = None;
+            while let Some(flag) = args.next() {
+                match flag.as_str() {
+                    "--snapshot-dir" => {
+                        snapshot_dir = Some(PathBuf::from(flag_value(&mut args, &program, &flag)?))
+                    }
+                    "--to" => generation = Some(flag_value(&mut args, &program, &flag)?),
+                    "--reload" => reload = Some(flag_value(&mut args, &program, &flag)?),
+                    _ => {
+                        usage(&program);
+                        eprintln!("ERROR: unknown flag {flag}");
+                        return Err(());
+                    }
+                }
+            }
+            let snapshot_dir = snapshot_dir.ok_or_else(|| {
+                usage(&program);
+                eprintln!(
+                    "ERROR: {sub_command} needs the --snapshot-dir the server keeps snapshots in"
+                )
+            })?;
+            let index = Path::new(&index_path);
+            let Some(generation) = generation else {
+                return snapshot::print_generations(index, &snapshot_dir).map_err(print_error);
+            };
+            let _lock = IndexLock::acquire(&index_path).map_err(print_error)?;
+            let chosen =
+                snapshot::roll_back(index, &snapshot_dir, &generation).map_err(print_error)?;
+            println!(
+                "Rolled {index_path} back to {chosen}",
+                chosen = chosen.display()
+            );
+            if let Some(address) = reload {
+                let answer = http::request_reload(&address).map_err(|err| {
+                    eprintln!("ERROR: could not reload the index of the server at {address}: {err}")
+                })?;
+                println!("Server at {address} reloaded the index: {answer}");
+            }
+        }
+        "exclude" => {
+            let index_path = args.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no path to index is provided for {sub_command} subcommand")
+            })?;
+            let mut patterns = Vec::new();
+            let mut undo = false;
+            let mut reload = None;
+            while let Some(flag) = args.next() {
+                match flag.as_str() {
+                    "--undo" => undo = true,
+                    "--reload" => reload = Some(flag_value(&mut args, &program, &flag)?),
+                    _ if !flag.starts_with("--") => patterns.push(flag),
+                    _ => {
+                        usage(&program);
+                        eprintln!("ERROR: unknown flag {flag}");
+                        return Err(());
+                    }
+                }
+            }
+            if patterns.is_empty() {
+                usage(&program);
+                eprintln!("ERROR: no path pattern is provided for {sub_command} subcommand");
+                return Err(());
+            }
+            let _lock = IndexLock::acquire(&index_path).map_err(print_error)?;
+            let mut writer = IndexWriter::open(&index_path).map_err(print_error)?;
+            for pattern in &patterns {
+                if undo {
+                    let count = exclude::include(&mut writer, pattern);
+                    println!("{count} documents matching {pattern} are searched again");
+                } else {
+                    let count = exclude::exclude(&mut writer, pattern);
+                    println!("{count} documents matching {pattern} are hidden from results");
+                }
+            }
+            writer.commit().map_err(print_error)?;
+            if let Some(address) = reload {
+                let answer = http::request_reload(&address).map_err(|err| {
+                    eprintln!("ERROR: could not reload the index of the server at {address}: {err}")
+                })?;
+                println!("Server at {address} reloaded the index: {answer}");
+            }
+        }
+        "queries" => {
+            let index_path = args.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no path to index is provided for {sub_command} subcommand")
+            })?;
+            let mut positional = Vec::new();
+            let mut remove = Vec::new();
+            let mut imports = Vec::new();
+            let mut reload = None;
+            while let Some(flag) = args.next() {
+                match flag.as_str() {
+                    "--remove" => remove.push(flag_value(&mut args, &program, &flag)?),
+                    "--import" => imports.push(flag_value(&mut args, &program, &flag)?),
+                    "--reload" => reload = Some(flag_value(&mut args, &program, &flag)?),
+                    _ if !flag.starts_with("--") => positional.push(flag),
+                    _ => {
+                        usage(&program);
+                        eprintln!("ERROR: unknown flag {flag}");
+                        return Err(());
+                    }
+                }
+            }
+            let added = match positional.as_slice() {
+                [] => None,
+                [name, query] => Some((name.clone(), query.clone())),
+                _ => {
+                    usage(&program);
+                    eprintln!("ERROR: a query is stored as <name> <query>, quote the query if it has spaces");
+                    return Err(());
+                }
+            };
+            if added.is_none() && remove.is_empty() && imports.is_empty() {
+                let model = Model::load(&index_path).map_err(print_error)?;
+                if model.manifest.queries.is_empty() {
+                    println!("{index_path} has no stored queries");
+                }
+                for (name, query) in &model.manifest.queries {
+                    println!("{name}: {query}");
+                }
+                return Ok(());
+            }
+            let _lock = IndexLock::acquire(&index_path).map_err(print_error)?;
+            let mut writer = IndexWriter::open(&index_path).map_err(print_error)?;
+            let mut queries = writer.queries().clone();
+            if let Some(name) = remove.iter().find(|name| !queries.contains_key(*name)) {
+                eprintln!("ERROR: {index_path} has no stored query {name}");
+                return Err(());
+            }
+            for name in &remove {
+                queries.remove(name);
+                println!("Removed stored query {name}");
+            }
+            let mut saved = Vec::new();
+            for path in &imports {
+                let imported = percolate::read_saved(Path::new(path)).map_err(print_error)?;
+                saved.extend(imported);
+            }
+            for (name, query) in saved.into_iter().chain(added) {
+                percolate::check(writer.model(), &name, &query).map_err(print_error)?;
+                println!("Stored query {name}");
+                queries.insert(name, query);
+            }
+            writer.set_queries(queries);
+            writer.commit().map_err(print_error)?;
+            if let Some(address) = reload {
+                let answer = http::request_reload(&address).map_err(|err| {
+                    eprintln!("ERROR: could not reload the index of the server at {address}: {err}")
+                })?;
+                println!("Server at {address} reloaded the index: {answer}");
+            }
+        }
+        "percolate" => {
+            let index_path = args.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no path to index is provided for {sub_command} subcommand")
+            })?;
+            let mut files = Vec::new();
+            let mut as_json = false;
+            for flag in args.by_ref() {
+                match flag.as_str() {
+                    "--json" => as_json = true,
+                    _ if flag == "-" || !flag.starts_with("--") => files.push(flag),
+                    _ => {
+                        usage(&program);
+                        eprintln!("ERROR: unknown flag {flag} for {sub_command} subcommand");
+                        return Err(());
+                    }
+                }
+            }
+            if files.is_empty() {
+                usage(&program);
+                eprintln!("ERROR: no document is provided for {sub_command} subcommand");
+                return Err(());
+            }
+            let model = Model::load(&index_path).map_err(print_error)?;
+            let percolator = percolate::Percolator::new(&model).map_err(print_error)?;
+            if percolator.is_empty() {
+                eprintln!("WARNING: {index_path} has no stored queries, store some with the queries subcommand");
+            }
+            let options = extract::ExtractOptions::default();
+            for file in &files {
+                let (text, meta) = if file == "-" {
+                    let mut text = String::new();
+                    io::stdin().read_to_string(&mut text).map_err(|err| {
+                        eprintln!("ERROR: could not read the document from stdin: {err}")
+                    })?;
+                    (text, Default::default())
+                } else {
+                    // The sections of a document are percolated as one.
+                    let chunks = extract::extract_chunks(Path::new(file), &options)
+                        .map_err(|err| eprintln!("ERROR: could not extract {file}: {err}"))?;
+                    let meta = chunks
+                        .first()
+                        .map(|chunk| chunk.meta.clone())
+                        .unwrap_or_default();
+                    let text = chunks
+                        .iter()
+                        .map(|chunk| chunk.text.as_str())
+                        .collect::<Vec<_>>()
+                        .join("\n\n");
+                    (text, meta)
+                };
+                let matches = percolator.matches(&text, meta).map_err(print_error)?;
+                if as_json {
+                    println!("{}", json!({"path": file, "matches": matches}));
+                } else if matches.is_empty() {
+                    println!("{file}: no stored query matches");
+                } else {
+                    println!("{file}: {}", matches.join(", "));
+                }
+            }
+        }
+        "serve" => {
+            let mut address = "127.0.0.1:8888".to_string();
+            let mut query_log = None;
+            let mut warm_searches = 0;
+            let mut feedback_log = None;
+            let mut popularity_path = None;
+            let mut popularity_prior = 0.0;
+            let mut slow_log = None;
+            let mut privacy = QueryPrivacy::default();
+            let mut slow_after = Duration::from_millis(500);
+            let mut limits = QueryLimits::default();
+            let mut ranking = Ranking::default();
+            let mut min_score = None;
+            let mut rewriter = None;
+            let mut result_set_ttl = DEFAULT_RESULT_SET_TTL;
+            let mut kept_result_sets = DEFAULT_KEPT_RESULT_SETS;
+            let mut health_interval = DEFAULT_HEALTH_INTERVAL;
+            let mut log_options = LogOptions::default();
+            let mut index_path = None;
+            let mut frontend_path = None;
+            let mut title = None;
+            let mut lang = None;
+            let mut index_name = None;
+            let mut templates = None;
+            let mut static_dir = None;
+            let mut cors = None;
+            let mut robots = None;
+            let mut analyzer = None;
+            let mut adopt_index_analyzer = false;
+            let mut snapshot_dir = None;
+            let mut snapshot_hours: f64 = 24.0;
+            let mut snapshot_keep = 7;
+            let mut save_interval = None;
+            let mut watch_dir = None;
+            let mut read_only = false;
+            #[cfg(feature = "watch")]
+            let mut watch_strategy = WatchStrategy::Auto;
+            let mut threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+            let mut max_memory = None;
+            let mut args = serve_env_args().into_iter().chain(args);
+            while let Some(flag) = args.next() {
+                match flag.as_str() {
+                    "--index" => index_path = Some(flag_value(&mut args, &program, &flag)?),
+                    "--frontend" => frontend_path = Some(flag_value(&mut args, &program, &flag)?),
+                    "--title" => title = Some(flag_value(&mut args, &program, &flag)?),
+                    "--lang" => lang = Some(flag_value(&mut args, &program, &flag)?),
+                    "--index-name" => index_name = Some(flag_value(&mut args, &program, &flag)?),
+                    "--robots" => {
+                        robots = Some(PathBuf::from(flag_value(&mut args, &program, &flag)?))
+                    }
+                    "--templates" => {
+                        templates = Some(PathBuf::from(flag_value(&mut args, &program, &flag)?))
+                    }
+                    "--cors-origin" => {
+                        cors = Some(http::Cors::parse(&flag_value(&mut args, &program, &flag)?))
+                    }
+                    "--static-dir" => {
+                        static_dir = Some(PathBuf::from(flag_value(&mut args, &program, &flag)?))
+                    }
+                    "--query-log" => query_log = Some(flag_value(&mut args, &program, &flag)?),
+                    "--warm-searches" => warm_searches = parse_flag(&mut args, &program, &flag)?,
+                    "--feedback-log" => {
+                        feedback_log = Some(flag_value(&mut args, &program, &flag)?)
+                    }
+                    "--popularity" => {
+                        popularity_path =
+                            Some(PathBuf::from(flag_value(&mut args, &program, &flag)?))
+                    }
+                    "--popularity-prior" => {
+                        popularity_prior = parse_flag(&mut args, &program, &flag)?;
+                        if !(0.0..=1.0).contains(&popularity_prior) {
+                            eprintln!("ERROR: {flag} must be between 0 and 1");
+                            return Err(());
+                        }
+                    }
+                    "--query-logging" => {
+                        let value = flag_value(&mut args, &program, &flag)?;
+                        privacy.logging = QueryLogging::from_name(&value).ok_or_else(|| {
+                            eprintln!("ERROR: invalid value {value} for {flag}, expected full, hashed or off")
+                        })?;
+                    }
+                    "--query-hash-key" => {
+                        privacy.hash_key = flag_value(&mut args, &program, &flag)?
+                    }
+                    "--max-expansions" => {
+                        limits.max_expansions = parse_flag(&mut args, &program, &flag)?
+                    }
+                    "--max-clauses" => limits.max_clauses = parse_flag(&mut args, &program, &flag)?,
+                    "--typos" => limits.typos = parse_typos(&mut args, &program, &flag)?,
+                    "--fuzzy" => limits.fuzzy = true,
+                    "--ranking" => ranking = parse_ranking(&mut args, &program, &flag)?,
+                    "--min-score" => min_score = Some(parse_min_score(&mut args, &program, &flag)?),
+                    "--rewrites" => {
+                        let path = flag_value(&mut args, &program, &flag)?;
+                        rewriter = Some(Arc::new(QueryRewriter::load(&path).map_err(print_error)?));
+                    }
+                    "--tokenizer" | "--joiners" | "--stopwords" | "--stemmer" | "--profile" => {
+                        let config = analyzer.take().unwrap_or_default();
+                        analyzer = Some(parse_config_flag(&mut args, &program, &flag, config)?);
+                    }
+                    "--adopt-index-analyzer" => adopt_index_analyzer = true,
+                    "--slow-log" => slow_log = Some(flag_value(&mut args, &program, &flag)?),
+                    "--slow-ms" => {
+                        slow_after = Duration::from_millis(parse_flag(&mut args, &program, &flag)?)
+                    }
+                    "--log-max-mb" => {
+                        let mb = parse_scaled_count(&mut args, &program, &flag, 1024 * 1024)?;
+                        log_options.max_bytes = mb * 1024 * 1024;
+                    }
+                    "--log-keep" => log_options.keep = parse_flag(&mut args, &program, &flag)?,
+                    "--snapshot-dir" => {
+                        snapshot_dir = Some(PathBuf::from(flag_value(&mut args, &program, &flag)?))
+                    }
+                    "--snapshot-hours" => snapshot_hours = parse_flag(&mut args, &program, &flag)?,
+                    "--snapshot-keep" => snapshot_keep = parse_flag(&mut args, &program, &flag)?,
+                    "--save-secs" => {
+                        let secs: u64 = parse_flag(&mut args, &program, &flag)?;
+                        save_interval = Some(Duration::from_secs(secs.max(1)));
+                    }
+                    "--result-set-minutes" => {
+                        let minutes = parse_scaled_count(&mut args, &program, &flag, 60)?;
+                        result_set_ttl = Duration::from_secs(minutes * 60);
+                    }
+                    "--result-sets" => kept_result_sets = parse_flag(&mut args, &program, &flag)?,
+                    "--health-secs" => {
+                        let secs: u64 = parse_flag(&mut args, &program, &flag)?;
+                        health_interval = Duration::from_secs(secs.max(1));
+                    }
+                    "--watch" => {
+                        watch_dir = Some(PathBuf::from(flag_value(&mut args, &program, &flag)?))
+                    }
+                    "--read-only" => read_only = true,
+                    #[cfg(feature = "watch")]
+                    "--watch-strategy" => {
+                        let name = flag_value(&mut args, &program, &flag)?;
+                        watch_strategy = WatchStrategy::parse(&name).ok_or_else(|| {
+                            eprintln!("ERROR: unknown watch strategy {name}, expected auto, notify or poll")
+                        })?;
+                    }
+                    "--log-sync-secs" => {
+                        let secs = parse_flag(&mut args, &program, &flag)?;
+                        log_options.sync_interval = Duration::from_secs(secs);
+                    }
+                    "--threads" => threads = parse_flag(&mut args, &program, &flag)?,
+                    "--max-memory" => {
+                        let value = flag_value(&mut args, &program, &flag)?;
+                        let limit = memory::parse_size(&value).filter(|&limit| limit > 0);
+                        max_memory = Some(limit.ok_or_else(|| {
+                            eprintln!("ERROR: invalid value {value} for {flag}, expected a size like 512M or 2G")
+                        })?);
+                    }
+                    "--address" => {
+                        let value = flag_value(&mut args, &program, &flag)?;
+                        if !is_address(&value) {
+                            usage(&program);
+                            eprintln!(
+                                "ERROR: invalid value {value} for {flag}, expected host:port"
+                            );
+                            return Err(());
+                        }
+                        address = value;
+                    }
+                    _ if is_address(&flag) => address = flag,
+                    _ if !flag.starts_with("--") => index_path = Some(flag),
+                    _ => {
+                        usage(&program);
+                        eprintln!("ERROR: unknown flag {flag}");
+                        return Err(());
+                    }
+                }
+            }
+            init_tracing(slow_log.is_some());
+            // On a read-only mount, every write would fail mid-request, so
+            // whatever writes is turned off up front.
+            let detected = !read_only
+                && index_path
+                    .as_deref()
+                    .is_some_and(|path| !is_writable(&parent_dir(Path::new(path))));
+            if detected {
+                eprintln!("WARNING: the index is on a read-only filesystem, serving read-only");
+            }
+            let read_only = read_only || detected;
+            if read_only {
+                let writers = [
+                    ("--save-secs", save_interval.take().is_some()),
+                    ("--snapshot-dir", snapshot_dir.take().is_some()),
+                    ("--watch", watch_dir.take().is_some()),
+                    ("--feedback-log", feedback_log.take().is_some()),
+                ];
+                for (flag, _) in writers.iter().filter(|(_, given)| *given) {
+                    eprintln!("WARNING: {flag} is ignored while serving read-only");
+                }
+                query_log = query_log.map(|path| local_log_path(&path));
+                slow_log = slow_log.map(|path| local_log_path(&path));
+            }
+            // Flags win over the config file.
+            let mut frontend = match &frontend_path {
+                Some(path) => FrontendConfig::load(path).map_err(print_error)?,
+                None => FrontendConfig::default(),
+            };
+            frontend.title = title.unwrap_or(frontend.title);
+            frontend.lang = lang.unwrap_or(frontend.lang);
+            frontend.index_name = index_name.or(frontend.index_name);
+            frontend.templates = templates.or(frontend.templates);
+            frontend.static_dir = static_dir.or(frontend.static_dir);
+            frontend.robots = robots.or(frontend.robots);
+            let frontend = frontend.render().map_err(print_error)?;
+            // Refuse to serve an index that is known to give wrong results.
+            let served = match &index_path {
+                Some(path) => {
+                    report_problems(path, &fsck::check_index(path, false).map_err(print_error)?)?;
+                    let handle = SearchHandle::open(path, CacheSizes::default())
+                        .map_err(print_error)?
+                        .with_ranking(ranking)
+                        .with_min_score(min_score)
+                        .with_rewriter(rewriter.clone());
+                    check_analyzer(path, &handle, analyzer, adopt_index_analyzer)?;
+                    Some(ServedIndex {
+                        path: path.clone(),
+                        handle,
+                        changes: Mutex::new(VecDeque::new()),
+                        unsaved: AtomicBool::new(false),
+                    })
+                }
+                None => None,
+            };
+
+            let mut snapshots = match (snapshot_dir, &index_path) {
+                (Some(dir), Some(index_path)) => {
+                    if !snapshot_hours.is_finite() || snapshot_hours <= 0.0 {
+                        eprintln!("ERROR: --snapshot-hours must be positive");
+                        return Err(());
+                    }
+                    let options = SnapshotOptions {
+                        dir,
+                        interval: Duration::from_secs_f64(snapshot_hours * 3600.0),
+                        keep: snapshot_keep,
+                    };
+                    Some(Snapshots::new(Path::new(index_path), options).map_err(print_error)?)
+                }
+                (Some(_), None) => {
+                    eprintln!(
+                        "ERROR: --snapshot-dir needs an index to snapshot, given with --index"
+                    );
+                    return Err(());
+                }
+                (None, _) => None,
+            };
+
+            #[cfg(feature = "watch")]
+            let mut watch = match (watch_dir, &index_path) {
+                (Some(dir), Some(index_path)) => {
+                    Some(FolderWatch::new(&dir, index_path, watch_strategy).map_err(print_error)?)
+                }
+                (Some(_), None) => {
+                    eprintln!("ERROR: --watch needs the index the folder was indexed into, given with --index");
+                    return Err(());
+                }
+                (None, _) => None,
+            };
+            #[cfg(not(feature = "watch"))]
+            if watch_dir.is_some() {
+                eprintln!("ERROR: --watch is not supported by this build of tinySearch, rebuild it with the watch feature");
+                return Err(());
+            }
+
+            let warm_up = match (warm_searches, &query_log) {
+                (0, _) => None,
+                (searches, Some(path)) => Some(WarmUp {
+                    query_log: PathBuf::from(path),
+                    searches,
+                }),
+                (_, None) => {
+                    eprintln!("ERROR: --warm-searches replays the searches of the --query-log, which is not given");
+                    return Err(());
+                }
+            };
+            let mut logs = ServerLogs::default();
+            if let Some(path) = &query_log {
+                logs.queries = Some(ServerLogs::open(path, &log_options)?);
+            }
+            if let Some(path) = &feedback_log {
+                logs.feedback = Some(ServerLogs::open(path, &log_options)?);
+            }
+            if let Some(path) = &slow_log {
+                logs.slow = Some(ServerLogs::open(path, &log_options)?);
+            }
+            logs.slow_after = slow_after;
+            let popularity = popularity_path
+                .map(|path| Popularity::open(&path, popularity_prior))
+                .transpose()
+                .map_err(print_error)?;
+            if let Some(cors) = cors {
+                let _ = CORS.set(cors);
+            }
+            let server = Server::http(&address).map_err(|err| {
+                eprintln!("ERROR: could not start HTTP server at {address} : {err}");
+            })?;
+
+            info!("server listening at http://{address}/");
+
+            let state = Arc::new(ServerState {
+                frontend,
+                served,
+                limits,
+                result_sets: ResultSets::new(result_set_ttl, kept_result_sets),
+                logs: Mutex::new(logs),
+                privacy,
+                memory: max_memory.map(MemoryLimit::new),
+                popularity,
+                health: HealthHistory::new(health_interval),
+                warm_up,
+                read_only,
+            });
+            if let (Some(memory), Some(index)) = (&state.memory, state.index()) {
+                memory.check_index(index);
+            }
+            if let Some(served) = &state.served {
+                state.health.record_if_due(&served.path, &served.handle);
+            }
+            // Requests are received here and answered by the workers, so a
+            // slow one only holds up the worker answering it.
+            let (sender, receiver) = mpsc::channel::<(Request, String)>();
+            let receiver = Arc::new(Mutex::new(receiver));
+            for _ in 0..threads.max(1) {
+                let (state, receiver) = (state.clone(), receiver.clone());
+                thread::spawn(move || loop {
+                    let Ok((request, id)) = receiver.lock().unwrap().recv() else {
+                        break;
+                    };
+                    let span = info_span!(
+                        "request",
+                        id = %id,
+                        method = ?request.method(),
+                        url = %state.privacy.for_request(&request).url(request.url()),
+                    );
+                    let _entered = span.enter();
+                    info!("received request");
+                    if let Err(err) = serve_request(request, &id, &state) {
+                        eprintln!("ERROR: {err}");
+                    }
+                });
+            }
 
-            for request in server.incoming_requests() {
-                serve_request(request);
+            let mut request_ids = RequestIds::new();
+            let mut saved_at = Instant::now();
+            // Waking up at least once per sync interval keeps the logs synced,
+            // and the snapshots and health samples taken, while no requests
+            // come in. Watched
+            // changes should show up in results soon after they are made.
+            let wake_interval = log_options.sync_interval.min(state.health.interval());
+            #[cfg(feature = "watch")]
+            let wake_interval = match &watch {
+                Some(watch) => wake_interval.min(watch.wake_interval()),
+                None => wake_interval,
+            };
+            loop {
+                match server.recv_timeout(wake_interval) {
+                    Ok(Some(request)) => {
+                        let id = request_ids.assign(&request);
+                        if sender.send((request, id)).is_err() {
+                            eprintln!("ERROR: the workers answering requests have stopped");
+                            return Err(());
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(err) => {
+                        eprintln!("ERROR: could not receive request: {err}");
+                        return Err(());
+                    }
+                }
+                state.logs.lock().unwrap().sync_if_due();
+                if let Some(popularity) = state.popularity.as_ref().filter(|_| !read_only) {
+                    popularity.save_if_due();
+                }
+                if let Some(memory) = &state.memory {
+                    memory.check(state.index(), &state.result_sets);
+                }
+                if let Some(snapshots) = &mut snapshots {
+                    snapshots.take_if_due();
+                }
+                if let Some(served) = &state.served {
+                    state.health.record_if_due(&served.path, &served.handle);
+                }
+                if let (Some(interval), Some(served)) = (save_interval, &state.served) {
+                    if saved_at.elapsed() >= interval {
+                        save_posted(served);
+                        saved_at = Instant::now();
+                    }
+                }
+                #[cfg(feature = "watch")]
+                if let (Some(watch), Some(served)) = (&mut watch, &state.served) {
+                    let mut changes_kept = served.changes.lock().unwrap();
+                    let old = served.handle.snapshot();
+                    if watch.update_if_due(&served.handle) {
+                        record_changes(served, &mut changes_kept, &old);
+                    }
+                }
             }
         }
         _ => {
@@ -0,0 +1,94 @@
+// The soft memory limit of serve, `--max-memory`. The server checks its
+// memory between requests; as it nears the limit it gives back what it can
+// do without, the caches and the kept result sets, stops keeping new result
+// sets and refuses exports, and logs a warning instead of growing until the
+// operating system kills it. It goes back to normal once its memory is well
+// below the limit again.
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tinysearch::handle::SearchHandle;
+use tinysearch::memory::{self, MemoryUsage};
+use tracing::{info, warn};
+
+use crate::resultsets::ResultSets;
+
+// Share of the limit at which the server starts to save memory.
+const PRESSURE_AT: f64 = 0.9;
+// Share of the limit below which it stops again.
+const RELIEVED_AT: f64 = 0.75;
+
+pub struct MemoryLimit {
+    limit: u64,
+    pressure: AtomicBool,
+}
+
+fn megabytes(bytes: u64) -> u64 {
+    bytes / (1024 * 1024)
+}
+
+impl MemoryLimit {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            pressure: AtomicBool::new(false),
+        }
+    }
+
+    // Whether the server is saving memory and refuses what it can do without.
+    pub fn under_pressure(&self) -> bool {
+        self.pressure.load(Ordering::Relaxed)
+    }
+
+    // The memory of the process as the operating system counts it, or the
+    // estimate of the index and its caches where it does not tell.
+    fn used(usage: &MemoryUsage) -> u64 {
+        memory::resident_bytes().unwrap_or(usage.total() as u64)
+    }
+
+    // Warns when the index alone takes most of the limit, as nothing the
+    // server can give back will bring it below.
+    pub fn check_index(&self, index: &SearchHandle) {
+        let usage = index.memory_usage();
+        if usage.index as f64 >= self.limit as f64 * PRESSURE_AT {
+            warn!(
+                index_mb = megabytes(usage.index as u64),
+                limit_mb = megabytes(self.limit),
+                "the index takes most of the memory limit, the server will run without caches"
+            );
+        }
+    }
+
+    // Compares the memory in use with the limit and saves memory or goes
+    // back to normal.
+    pub fn check(&self, index: Option<&SearchHandle>, sets: &ResultSets) {
+        let usage = index.map(SearchHandle::memory_usage).unwrap_or_default();
+        let used = Self::used(&usage);
+        let share = used as f64 / self.limit as f64;
+        if share >= PRESSURE_AT {
+            let was_under_pressure = self.pressure.swap(true, Ordering::Relaxed);
+            if !was_under_pressure {
+                warn!(
+                    used_mb = megabytes(used),
+                    limit_mb = megabytes(self.limit),
+                    index_mb = megabytes(usage.index as u64),
+                    postings_cache_mb = megabytes(usage.postings_cache as u64),
+                    result_cache_mb = megabytes(usage.result_cache as u64),
+                    filter_cache_mb = megabytes(usage.filter_cache as u64),
+                    "memory is near the limit, evicting caches and result sets and refusing exports"
+                );
+            }
+            if let Some(index) = index {
+                index.evict_caches();
+            }
+            sets.clear();
+            sets.pause(true);
+        } else if share < RELIEVED_AT && self.pressure.swap(false, Ordering::Relaxed) {
+            sets.pause(false);
+            info!(
+                used_mb = megabytes(used),
+                limit_mb = megabytes(self.limit),
+                "memory is well below the limit again"
+            );
+        }
+    }
+}
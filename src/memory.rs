@@ -0,0 +1,107 @@
+// How much memory a loaded index and its caches take, for serving within a
+// memory limit. The sizes are estimates from the lengths of what is held,
+// close enough to tell which part grows; the operating system's figure for
+// the whole process is `resident_bytes`.
+use std::mem::size_of;
+
+use crate::{Doc, Model};
+
+// Bytes of every part of a loaded index.
+#[derive(Clone, Copy, Default)]
+pub struct MemoryUsage {
+    // The documents, their terms, metadata and positions, and the bytes of a
+    // binary index file.
+    pub index: usize,
+    // Posting lists decoded from a binary index file.
+    pub postings_cache: usize,
+    // Results of recent searches.
+    pub result_cache: usize,
+    // Documents recent filters let through.
+    pub filter_cache: usize,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.index + self.postings_cache + self.result_cache + self.filter_cache
+    }
+}
+
+// Bookkeeping of a hash map entry, beyond its key and value.
+const ENTRY_OVERHEAD: usize = 2 * size_of::<usize>();
+
+pub fn string_bytes(text: &str) -> usize {
+    size_of::<String>() + text.len()
+}
+
+fn doc_bytes(doc: &Doc) -> usize {
+    let terms = doc
+        .tf
+        .keys()
+        .map(|term| string_bytes(term) + size_of::<usize>() + ENTRY_OVERHEAD)
+        .sum::<usize>();
+    let meta = doc
+        .meta
+        .iter()
+        .map(|(key, value)| {
+            string_bytes(key)
+                + value
+                    .values()
+                    .iter()
+                    .map(|v| string_bytes(v))
+                    .sum::<usize>()
+                + ENTRY_OVERHEAD
+        })
+        .sum::<usize>();
+    let positions = doc
+        .positions
+        .iter()
+        .map(|(term, at)| string_bytes(term) + at.len() * size_of::<u32>() + ENTRY_OVERHEAD)
+        .sum::<usize>();
+    size_of::<Doc>() + terms + meta + positions
+}
+
+pub fn model_bytes(model: &Model) -> usize {
+    model
+        .docs
+        .iter()
+        .map(|(path, doc)| path.as_os_str().len() + doc_bytes(doc) + ENTRY_OVERHEAD)
+        .sum()
+}
+
+// A size like 512M, 2G, 640K or a plain number of bytes. The suffixes are
+// powers of 1024, with or without a trailing B.
+pub fn parse_size(text: &str) -> Option<u64> {
+    let text = text.trim();
+    let text = text
+        .strip_suffix(['B', 'b'])
+        .filter(|rest| rest.ends_with(|c: char| c.is_ascii_alphabetic()))
+        .unwrap_or(text);
+    let (number, unit) = match text.char_indices().last()? {
+        (at, c) if c.is_ascii_alphabetic() => (&text[..at], c.to_ascii_uppercase()),
+        _ => (text, 'B'),
+    };
+    let scale: u64 = match unit {
+        'B' => 1,
+        'K' => 1 << 10,
+        'M' => 1 << 20,
+        'G' => 1 << 30,
+        'T' => 1 << 40,
+        _ => return None,
+    };
+    number.trim().parse::<u64>().ok()?.checked_mul(scale)
+}
+
+// The memory of this process that is resident in RAM, where the operating
+// system tells.
+#[cfg(target_os = "linux")]
+pub fn resident_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kb = line.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn resident_bytes() -> Option<u64> {
+    None
+}
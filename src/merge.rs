@@ -0,0 +1,161 @@
+// Indexes built apart, e.g. of folders on different machines, merged into
+// one. Documents keep their paths. When more than one index has a source
+// file, all of its documents come from one of them, so the sections of a
+// file are never mixed. Collection statistics are not stored but computed
+// when an index is loaded, so the merged index ranks by those of the whole
+// corpus; documents are numbered afresh, as the IDs of separate indexes
+// overlap.
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::indexer::source_file;
+use crate::writer::IndexWriter;
+use crate::{document_date, DocKey, Error, Model};
+
+// What to do with a source file that more than one index has, with other
+// contents in each.
+#[derive(Clone, Copy, PartialEq)]
+pub enum OnDuplicate {
+    // Keep the one modified last, by its file or else its date metadata,
+    // and the one of the index given last where neither tells.
+    Newest,
+    // Refuse to merge.
+    Error,
+}
+
+impl OnDuplicate {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "newest" => Some(Self::Newest),
+            "error" => Some(Self::Error),
+            _ => None,
+        }
+    }
+}
+
+// Counts of a merge, for the summary printed after it.
+#[derive(Default)]
+pub struct MergeStats {
+    pub docs: usize,
+    // Source files more than one index has.
+    pub duplicates: usize,
+}
+
+// The documents of an index by their source file.
+fn by_source(model: &Model) -> BTreeMap<&str, Vec<&DocKey>> {
+    let files = model.manifest.files.keys().cloned().collect::<HashSet<_>>();
+    let mut sources = BTreeMap::<&str, Vec<&DocKey>>::new();
+    for path in model.docs.keys() {
+        sources
+            .entry(source_file(path.as_str(), &files))
+            .or_default()
+            .push(path);
+    }
+    sources
+}
+
+// How recent the documents of a source file in an index are.
+fn modified(model: &Model, source: &str, docs: &[&DocKey]) -> (Option<u64>, Option<String>) {
+    let mtime = model
+        .manifest
+        .files
+        .get(source)
+        .and_then(|stamp| stamp.stat)
+        .map(|stat| stat.mtime_ns);
+    let date = docs
+        .iter()
+        .filter_map(|path| document_date(model, path))
+        .max()
+        .map(str::to_string);
+    (mtime, date)
+}
+
+// Whether two indexes have the same documents for a source file, by the
+// documents of the file in the first.
+fn same_docs(a: &Model, b: &Model, docs: &[&DocKey]) -> bool {
+    docs.iter().all(|path| {
+        let (Some(a_doc), Some(b_doc)) = (a.docs.get(*path), b.docs.get(*path)) else {
+            return false;
+        };
+        a_doc.same_content(b_doc)
+    })
+}
+
+// Adds the documents of every index to the writer, whose analysis settings
+// every index must have been built with. `indexes` are the paths of the
+// indexes, for messages, with their models.
+pub fn merge(
+    indexes: Vec<(String, Model)>,
+    writer: &mut IndexWriter,
+    on_duplicate: OnDuplicate,
+) -> Result<MergeStats, Error> {
+    for (path, model) in &indexes {
+        if &model.manifest.config != writer.config() {
+            return Err(Error::invalid(format!(
+                "{path} was built with other analysis settings than {first}, its terms cannot be merged; index it again like {first}",
+                first = indexes[0].0
+            )));
+        }
+    }
+
+    // The index every source file is taken from.
+    let mut chosen = HashMap::<DocKey, usize>::new();
+    let mut stats = MergeStats::default();
+    let sources = indexes
+        .iter()
+        .map(|(_, model)| by_source(model))
+        .collect::<Vec<_>>();
+    for (i, (path, model)) in indexes.iter().enumerate() {
+        for (source, docs) in &sources[i] {
+            let Some(&other) = chosen.get(*source) else {
+                chosen.insert(DocKey::from(*source), i);
+                continue;
+            };
+            stats.duplicates += 1;
+            let (other_path, other_model) = &indexes[other];
+            let other_docs = &sources[other][source];
+            if docs.len() == other_docs.len() && same_docs(model, other_model, docs) {
+                continue;
+            }
+            match on_duplicate {
+                OnDuplicate::Error => {
+                    return Err(Error::invalid(format!(
+                        "{source} differs in {other_path} and {path}; merge with --on-duplicate newest to keep the newest",
+                    )))
+                }
+                OnDuplicate::Newest => {
+                    // Later indexes win ties.
+                    if modified(model, source, docs) >= modified(other_model, source, other_docs) {
+                        chosen.insert(DocKey::from(*source), i);
+                    }
+                }
+            }
+        }
+    }
+    drop(sources);
+
+    let mut files = writer.file_stamps().clone();
+    let mut aliases = writer.aliases().clone();
+    let mut queries = writer.queries().clone();
+    for (i, (_, model)) in indexes.into_iter().enumerate() {
+        let source_files = model.manifest.files.keys().cloned().collect::<HashSet<_>>();
+        let is_chosen = |path: &str| chosen.get(source_file(path, &source_files)) == Some(&i);
+        for (file, stamp) in &model.manifest.files {
+            if is_chosen(file.as_str()) {
+                files.insert(file.clone(), stamp.clone());
+            }
+        }
+        aliases.extend(model.manifest.aliases.clone());
+        queries.extend(model.manifest.queries.clone());
+        for (path, mut doc) in model.docs {
+            if is_chosen(path.as_str()) {
+                doc.id = 0;
+                writer.add_doc(path, doc);
+                stats.docs += 1;
+            }
+        }
+    }
+    writer.set_file_stamps(files);
+    writer.set_aliases(aliases);
+    writer.set_queries(queries);
+    Ok(stats)
+}
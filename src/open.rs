@@ -0,0 +1,90 @@
+// Opening a search result: text documents in $EDITOR at the first line with
+// a query term, URLs and binary formats with the desktop's default handler.
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+use tinysearch::analyzer::Analyzer;
+use tinysearch::{snippet, Error};
+
+const BINARY_EXTENSIONS: &[&str] = &["pdf", "png", "jpg", "jpeg", "tif", "tiff", "mp3"];
+
+fn system_opener() -> Command {
+    if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else {
+        Command::new("xdg-open")
+    }
+}
+
+// Most terminal editors take `+LINE file`; some GUI editors want `file:LINE`.
+fn editor_command(editor: &str, file_path: &Path, line: Option<usize>) -> Command {
+    let mut parts = editor.split_whitespace();
+    let mut command = Command::new(parts.next().unwrap_or(editor));
+    command.args(parts);
+    let name = Path::new(editor.split_whitespace().next().unwrap_or(editor))
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    match (name, line) {
+        ("code" | "codium", Some(line)) => {
+            command
+                .arg("-g")
+                .arg(format!("{}:{line}", file_path.display()));
+        }
+        ("subl" | "hx" | "zed", Some(line)) => {
+            command.arg(format!("{}:{line}", file_path.display()));
+        }
+        (_, Some(line)) => {
+            command.arg(format!("+{line}")).arg(file_path);
+        }
+        (_, None) => {
+            command.arg(file_path);
+        }
+    }
+    command
+}
+
+pub fn open_result(doc_path: &Path, terms: &[&str], analyzer: &Analyzer) -> Result<(), Error> {
+    let target = doc_path.to_str().unwrap_or_default();
+    let mut command = if target.starts_with("http://") || target.starts_with("https://") {
+        let mut command = system_opener();
+        command.arg(target);
+        command
+    } else {
+        // Chunks are keyed `<file>#<anchor>`, the editor gets the file.
+        let (file_path, _) = snippet::split_doc_path(doc_path);
+        let ext = file_path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase())
+            .unwrap_or_default();
+        let editor = env::var("VISUAL").or_else(|_| env::var("EDITOR"));
+        match editor {
+            Ok(editor) if !BINARY_EXTENSIONS.contains(&ext.as_str()) => {
+                let line = snippet::matching_lines(file_path, terms, 1, analyzer)
+                    .first()
+                    .copied();
+                editor_command(&editor, file_path, line)
+            }
+            _ => {
+                let mut command = system_opener();
+                command.arg(file_path);
+                command
+            }
+        }
+    };
+    let status = command
+        .status()
+        .map_err(|err| Error::io(format!("could not open {target}"), err))?;
+    if !status.success() {
+        return Err(Error::invalid(format!(
+            "opening {target} failed with {status}"
+        )));
+    }
+    Ok(())
+}
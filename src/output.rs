@@ -0,0 +1,173 @@
+// Human readable search results for the terminal.
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+
+use tinysearch::snippet::Snippet;
+
+pub struct Style {
+    color: bool,
+}
+
+impl Style {
+    // Colors only when stdout is a terminal and NO_COLOR (https://no-color.org) is unset.
+    pub fn detect() -> Self {
+        let no_color = env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+        Self {
+            color: io::stdout().is_terminal() && !no_color,
+        }
+    }
+
+    fn paint(&self, code: &str, text: &str) -> String {
+        if self.color {
+            format!("\x1b[{code}m{text}\x1b[0m")
+        } else {
+            text.to_string()
+        }
+    }
+
+    pub fn bold(&self, text: &str) -> String {
+        self.paint("1", text)
+    }
+
+    pub fn dim(&self, text: &str) -> String {
+        self.paint("2", text)
+    }
+
+    pub fn path(&self, text: &str) -> String {
+        self.paint("1;36", text)
+    }
+
+    // Green for scores close to the best hit, yellow and red further down.
+    pub fn score(&self, text: &str, relative: f32) -> String {
+        let code = if relative >= 0.66 {
+            "32"
+        } else if relative >= 0.33 {
+            "33"
+        } else {
+            "31"
+        };
+        self.paint(code, text)
+    }
+
+    pub fn error(&self, text: &str) -> String {
+        self.paint("1;31", text)
+    }
+}
+
+// Paths under the current directory are shown relative to it.
+pub fn display_path(path: &Path) -> String {
+    env::current_dir()
+        .ok()
+        .and_then(|cwd| path.strip_prefix(cwd).ok())
+        .unwrap_or(path)
+        .display()
+        .to_string()
+}
+
+pub struct ResultLine<'a> {
+    pub path: &'a Path,
+    // Shown instead of the path for web documents.
+    pub url: Option<&'a str>,
+    pub title: Option<&'a str>,
+    pub score: f32,
+    // Date of the document, formatted for display.
+    pub date: Option<String>,
+    // The document was indexed only in part.
+    pub truncated: bool,
+    pub lines: Vec<usize>,
+    // Pages of a PDF with query terms.
+    pub pages: Vec<usize>,
+    pub snippet: Option<Snippet>,
+}
+
+// Ranks count from `first_rank`, the rank of the first result of the page.
+pub fn print_results(style: &Style, results: &[ResultLine], first_rank: usize) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    let top = results.first().map_or(0.0, |r| r.score);
+    let rank_width = (first_rank + results.len().saturating_sub(1))
+        .to_string()
+        .len();
+    for (i, result) in results.iter().enumerate() {
+        let relative = if top > 0.0 { result.score / top } else { 0.0 };
+        let date = result
+            .date
+            .as_ref()
+            .map(|date| format!("  {}", style.dim(date)))
+            .unwrap_or_default();
+        let truncated = if result.truncated {
+            format!("  {}", style.dim("(truncated)"))
+        } else {
+            String::new()
+        };
+        writeln!(
+            stdout,
+            "{rank} {score} {path}{date}{truncated}",
+            rank = style.dim(&format!("{:>rank_width$}.", first_rank + i)),
+            score = style.score(&format!("{:>8.4}", result.score), relative),
+            path = style.path(
+                &result
+                    .url
+                    .map_or_else(|| display_path(result.path), str::to_string)
+            ),
+        )?;
+        if let Some(title) = result.title {
+            writeln!(
+                stdout,
+                "{:indent$}{}",
+                "",
+                style.bold(title),
+                indent = rank_width + 11
+            )?;
+        }
+        if !result.lines.is_empty() {
+            let lines = result
+                .lines
+                .iter()
+                .map(|line| line.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            writeln!(
+                stdout,
+                "{:indent$}{}",
+                "",
+                style.dim(&format!("lines {lines}")),
+                indent = rank_width + 11
+            )?;
+        }
+        if !result.pages.is_empty() {
+            let pages = result
+                .pages
+                .iter()
+                .map(|page| page.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            let label = if result.pages.len() == 1 {
+                "page"
+            } else {
+                "pages"
+            };
+            writeln!(
+                stdout,
+                "{:indent$}{}",
+                "",
+                style.dim(&format!("{label} {pages}")),
+                indent = rank_width + 11
+            )?;
+        }
+        if let Some(snippet) = &result.snippet {
+            let text = snippet
+                .iter()
+                .map(|(piece, hit)| {
+                    if *hit {
+                        style.bold(piece)
+                    } else {
+                        piece.clone()
+                    }
+                })
+                .collect::<String>();
+            writeln!(stdout, "{:indent$}{text}", "", indent = rank_width + 11)?;
+        }
+    }
+    Ok(())
+}
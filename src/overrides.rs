@@ -0,0 +1,135 @@
+// Analysis options a search overrides for itself, for expert queries that
+// need to match more exactly than the index was built to: `stemming=off`
+// only finds words as they are written, `case=sensitive` only in their case.
+// The index only has the terms its own analysis made, so such a search runs
+// as usual and its matches are narrowed to the documents whose text,
+// analyzed again the overridden way, matches the query too. That takes the
+// copies of the documents an index keeps with `--bundle-sources`. Overrides
+// asking for looser matching than the index's, like stemming the words of an
+// index built without a stemmer, cannot be honored from its terms at all.
+use crate::analyzer::Analyzer;
+use crate::config::{IndexConfig, Stemmer};
+use crate::query::{self, ParseError, Query, QueryLimits};
+use crate::scoring::CorpusStats;
+use crate::{index_document, term_positions, Doc, Model};
+
+// The names requests override the options by.
+pub const OVERRIDE_NAMES: &[&str] = &["stemming", "case", "fold_accents"];
+
+// An override the index cannot honor, with why.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unsupported {
+    pub name: String,
+    pub value: String,
+    pub reason: &'static str,
+}
+
+// The analyzer the matches of a search with the `overrides` (name, value)
+// are narrowed by, none when the index already analyzes that way, or the
+// overrides it cannot honor. `has_text` tells whether the index keeps the
+// copies of its documents.
+pub fn resolve(
+    config: &IndexConfig,
+    overrides: &[(String, String)],
+    has_text: bool,
+) -> Result<Option<Analyzer>, Vec<Unsupported>> {
+    let mut stem = true;
+    let mut keep_case = false;
+    let mut unsupported = Vec::new();
+    let mut needing_text = Vec::new();
+    for (name, value) in overrides {
+        let stems = config.stemmer != Stemmer::None;
+        let problem = match (name.as_str(), value.as_str()) {
+            ("stemming", "off") => {
+                stem = false;
+                if stems {
+                    needing_text.push((name, value));
+                }
+                None
+            }
+            ("stemming", "on") if !stems => {
+                Some("the index was built without a stemmer, its terms are the words as written")
+            }
+            ("case", "sensitive") if !config.token_filters.is_empty() => {
+                Some("the token filters of the index take case folded terms")
+            }
+            ("case", "sensitive") => {
+                keep_case = true;
+                needing_text.push((name, value));
+                None
+            }
+            ("fold_accents", "on") => Some(
+                "the index keeps accents as they are written, words with and without them are different terms",
+            ),
+            ("stemming", "on") | ("case", "insensitive") | ("fold_accents", "off") => None,
+            ("stemming" | "fold_accents", _) => Some("expected on or off"),
+            ("case", _) => Some("expected sensitive or insensitive"),
+            _ => Some("not an analysis option"),
+        };
+        if let Some(reason) = problem {
+            unsupported.push(Unsupported {
+                name: name.clone(),
+                value: value.clone(),
+                reason,
+            });
+        }
+    }
+    if !has_text {
+        for (name, value) in needing_text.drain(..) {
+            unsupported.push(Unsupported {
+                name: name.clone(),
+                value: value.clone(),
+                reason: "it needs the text of the documents, which the index keeps when built with --bundle-sources",
+            });
+        }
+    }
+    if !unsupported.is_empty() {
+        return Err(unsupported);
+    }
+    Ok((!needing_text.is_empty()).then(|| Analyzer::overriding(config, stem, keep_case)))
+}
+
+pub struct Narrowing {
+    analyzer: Analyzer,
+    query: Query,
+    limits: QueryLimits,
+    // An empty index of the same configuration, to search documents in.
+    empty: Model,
+}
+
+impl Narrowing {
+    // The query parsed with the analyzer `resolve` gave for the index of
+    // `model`; wildcards and fuzzy words are expanded within the limits.
+    pub fn new(
+        model: &Model,
+        analyzer: Analyzer,
+        query: &str,
+        limits: QueryLimits,
+    ) -> Result<Self, ParseError> {
+        let query = query::parse(query, &analyzer)?;
+        Ok(Self {
+            analyzer,
+            query,
+            limits,
+            empty: Model::new(model.manifest.config.clone()),
+        })
+    }
+
+    // Whether the text of a document, analyzed the overridden way, matches.
+    pub fn matches(&self, text: &str) -> bool {
+        let mut model = self.empty.clone();
+        let doc = Doc {
+            tf: index_document(&self.analyzer, text),
+            positions: term_positions(&self.analyzer, text, usize::MAX),
+            ..Doc::default()
+        };
+        model.insert_doc("", doc);
+        let stats = CorpusStats::of(&model);
+        let doc = &model.docs[""];
+        // A query growing past the limits on one document matches none.
+        self.query
+            .clone()
+            .expand(&stats, &self.limits)
+            .is_ok_and(|query| query.matches_doc(doc))
+    }
+}
@@ -0,0 +1,112 @@
+// Searches turned around: queries stored in an index under a name, and
+// documents matched against them rather than them against the documents.
+// A percolated document is analyzed like the documents of the index, but
+// only searched on its own, so wildcards and fuzzy words expand to its terms
+// and a stored query matches it exactly when a search would find it once it
+// is indexed. The building block for alerting on new documents and routing
+// them by content.
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::query::{self, Query, QueryLimits};
+use crate::scoring::CorpusStats;
+use crate::{Error, Metadata, Model};
+
+// Name → query, in the syntax of the search subcommand.
+pub type StoredQueries = BTreeMap<String, String>;
+
+// A saved-search file, as the bookmarks of the repl are exported to and
+// `queries --import` reads: a JSON object of name → query.
+pub fn read_saved(path: &Path) -> Result<StoredQueries, Error> {
+    let json = fs::read_to_string(path).map_err(|err| {
+        Error::io(
+            format!("could not read saved searches {}", path.display()),
+            err,
+        )
+    })?;
+    serde_json::from_str(&json).map_err(|err| {
+        Error::json(
+            format!("could not parse saved searches {}", path.display()),
+            err,
+        )
+    })
+}
+
+pub fn write_saved(path: &Path, queries: &StoredQueries) -> Result<(), Error> {
+    let json = serde_json::to_string_pretty(queries).map_err(|err| {
+        Error::json(
+            format!("could not serialize saved searches {}", path.display()),
+            err,
+        )
+    })?;
+    fs::write(path, json + "\n").map_err(|err| {
+        Error::io(
+            format!("could not write saved searches {}", path.display()),
+            err,
+        )
+    })
+}
+
+// Checks the query parses against the index, for storing it.
+pub fn check(model: &Model, name: &str, query: &str) -> Result<(), Error> {
+    if name.trim().is_empty() {
+        return Err(Error::invalid("stored queries need a name"));
+    }
+    query::parse(query, &model.analyzer())?;
+    Ok(())
+}
+
+pub struct Percolator {
+    // An empty index of the same configuration, to analyze documents with.
+    empty: Model,
+    queries: Vec<(String, Query)>,
+}
+
+impl Percolator {
+    // The stored queries of the index, parsed once for every document.
+    pub fn new(model: &Model) -> Result<Self, Error> {
+        let analyzer = model.analyzer();
+        let queries = model
+            .manifest
+            .queries
+            .iter()
+            .map(|(name, text)| {
+                let query = query::parse(text, &analyzer).map_err(|err| {
+                    Error::invalid(format!("stored query {name} does not parse: {err}"))
+                })?;
+                Ok((name.clone(), query))
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(Self {
+            empty: Model::new(model.manifest.config.clone()),
+            queries,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queries.is_empty()
+    }
+
+    // The names of the stored queries the document matches, in name order.
+    pub fn matches(&self, text: &str, meta: Metadata) -> Result<Vec<&str>, Error> {
+        let mut model = self.empty.clone();
+        model.add_document("", text, meta);
+        model.analyzer().check_filters()?;
+        let stats = CorpusStats::of(&model);
+        let doc = &model.docs[""];
+        let matches = self
+            .queries
+            .iter()
+            .filter(|(_, query)| {
+                // A query growing past the limits on one document matches none.
+                query
+                    .clone()
+                    .expand(&stats, &QueryLimits::default())
+                    .is_ok_and(|query| query.matches_doc(doc))
+            })
+            .map(|(name, _)| name.as_str())
+            .collect();
+        Ok(matches)
+    }
+}
@@ -0,0 +1,447 @@
+// Extractors and token filters compiled to WebAssembly and dropped into a
+// plugins directory, for formats and analysis the built-in ones do not
+// cover, without recompiling tinySearch. Every `<name>.wasm` module comes
+// with a `<name>.json` saying what it is:
+//
+//   {"kind": "extractor", "extensions": ["rtf"]}
+//   {"kind": "token-filter"}
+//
+// Modules are WASI commands run by the `wasmtime` CLI, which has to be
+// installed on its own. They run without access to the file system or the
+// network, with a budget of fuel, spent as they execute instructions, and a
+// size of memory, a module running out of either failing as if it exited
+// with an error. They talk over stdin and stdout:
+//
+// - An extractor reads the bytes of a document from stdin and writes its
+//   text, UTF-8, to stdout. A document it exits with an error on, or takes
+//   longer than a minute over, fails like any other unreadable document,
+//   with the first line of stderr as reason.
+// - A token filter reads terms, one per line and case folded, as long as
+//   stdin is open, and answers every line with one: the term it becomes, or
+//   an empty line to drop it. It has to flush after each line, and answer
+//   within ten seconds. It runs after the built-in stages, filters in the
+//   order of their names. A filter that fails stays failed: the index run
+//   or search that used it fails, rather than going on with terms it did
+//   not filter.
+//
+// The modules of token filters are recorded with the configuration of an
+// index, so queries are filtered like its documents.
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::extract::content_hash;
+use crate::Error;
+
+pub const RUNTIME: &str = "wasmtime";
+
+// Filtered terms remembered per filter, most terms of a corpus repeat.
+const MAX_CACHED_TERMS: usize = 100_000;
+
+// How long an extractor may take over a document, and a token filter over a
+// term, before it is killed. A filter holds up every analysis sharing it
+// while it thinks.
+const EXTRACT_TIMEOUT: Duration = Duration::from_secs(60);
+const FILTER_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The fuel of an extractor is per document, that of a token filter for all
+// the terms of the process, which runs as long as tinySearch does.
+const EXTRACT_FUEL: u64 = 100_000_000_000;
+const FILTER_FUEL: u64 = 10_000_000_000_000;
+// Per linear memory of a module.
+const MAX_MEMORY_BYTES: u64 = 512 * 1024 * 1024;
+
+// How often an extractor is checked for having exited.
+const WAIT_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+enum PluginManifest {
+    Extractor { extensions: Vec<String> },
+    TokenFilter,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Plugins {
+    // Lowercase extension → module and the hash of its bytes, which takes
+    // part in the key of cached extractions.
+    extractors: HashMap<String, (PathBuf, String)>,
+    token_filters: Vec<PathBuf>,
+}
+
+impl Plugins {
+    // The plugins in `dir`, after checking the runtime is there to run them
+    // and starting the token filters.
+    pub fn load(dir: &Path) -> Result<Self, Error> {
+        let read_error = |err| {
+            Error::io(
+                format!(
+                    "could not read plugins directory {dir}",
+                    dir = dir.display()
+                ),
+                err,
+            )
+        };
+        let mut modules = fs::read_dir(dir)
+            .map_err(read_error)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(read_error)?;
+        modules.retain(|path| path.extension().is_some_and(|ext| ext == "wasm"));
+        modules.sort();
+        let mut plugins = Self::default();
+        if modules.is_empty() {
+            return Ok(plugins);
+        }
+        check_runtime()?;
+        for module in modules {
+            let module = fs::canonicalize(&module).map_err(|err| {
+                Error::io(
+                    format!("could not read plugin {module}", module = module.display()),
+                    err,
+                )
+            })?;
+            let manifest_path = module.with_extension("json");
+            let json = fs::read_to_string(&manifest_path).map_err(|err| {
+                Error::io(
+                    format!(
+                        "could not read {manifest}, which says what plugin {module} is",
+                        manifest = manifest_path.display(),
+                        module = module.display()
+                    ),
+                    err,
+                )
+            })?;
+            let manifest = serde_json::from_str(&json).map_err(|err| {
+                Error::json(
+                    format!(
+                        "could not parse plugin manifest {manifest}",
+                        manifest = manifest_path.display()
+                    ),
+                    err,
+                )
+            })?;
+            match manifest {
+                PluginManifest::Extractor { extensions } => {
+                    let bytes = fs::read(&module).map_err(|err| {
+                        Error::io(
+                            format!("could not read plugin {module}", module = module.display()),
+                            err,
+                        )
+                    })?;
+                    let hash = content_hash(&bytes);
+                    for ext in extensions {
+                        let ext = ext.trim_start_matches('.').to_ascii_lowercase();
+                        plugins
+                            .extractors
+                            .insert(ext, (module.clone(), hash.clone()));
+                    }
+                }
+                PluginManifest::TokenFilter => {
+                    token_filter(&module).start()?;
+                    plugins.token_filters.push(module);
+                }
+            }
+        }
+        Ok(plugins)
+    }
+
+    // Only the module extracting documents named like `name`, for the
+    // sandboxed extractor, which keeps no cache for the hash to key.
+    pub fn only_extractor(name: &Path, module: PathBuf) -> Self {
+        let mut plugins = Self::default();
+        if let Some(ext) = name.extension().and_then(|ext| ext.to_str()) {
+            plugins
+                .extractors
+                .insert(ext.to_ascii_lowercase(), (module, String::new()));
+        }
+        plugins
+    }
+
+    // The module extracting documents named like `name`, with its hash.
+    pub fn extractor(&self, name: &Path) -> Option<(&Path, &str)> {
+        let ext = name.extension()?.to_str()?.to_ascii_lowercase();
+        let (module, hash) = self.extractors.get(&ext)?;
+        Some((module, hash))
+    }
+
+    pub fn token_filters(&self) -> &[PathBuf] {
+        &self.token_filters
+    }
+}
+
+fn check_runtime() -> Result<(), Error> {
+    let runs = Command::new(RUNTIME)
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success());
+    if runs {
+        Ok(())
+    } else {
+        Err(Error::invalid(format!(
+            "could not run {RUNTIME}, which runs the plugins; install it from https://wasmtime.dev"
+        )))
+    }
+}
+
+fn runtime_command(module: &Path, fuel: u64) -> Command {
+    let mut command = Command::new(RUNTIME);
+    command
+        .arg("run")
+        .arg("-W")
+        .arg(format!("fuel={fuel},max-memory-size={MAX_MEMORY_BYTES}"))
+        .arg(module);
+    command
+}
+
+// The text the extractor module makes of a document.
+pub fn extract(module: &Path, name: &Path, bytes: &[u8]) -> Result<String, Error> {
+    let mut child = runtime_command(module, EXTRACT_FUEL)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| Error::io(format!("could not run {RUNTIME}"), err))?;
+    let mut stdin = child.stdin.take().expect("stdin of the plugin is piped");
+    let mut stdout = child.stdout.take().expect("stdout of the plugin is piped");
+    let mut stderr = child.stderr.take().expect("stderr of the plugin is piped");
+    // Written and read from other threads, the module may write text before
+    // it has read the whole document, and killing it ends them all.
+    let (status, stdout, stderr) = thread::scope(|scope| {
+        scope.spawn(move || {
+            // A module failing early closes the pipe, its exit status tells why.
+            let _ = stdin.write_all(bytes);
+        });
+        let text = scope.spawn(move || {
+            let mut text = Vec::new();
+            stdout.read_to_end(&mut text).map(|_| text)
+        });
+        let reason = scope.spawn(move || {
+            let mut reason = Vec::new();
+            let _ = stderr.read_to_end(&mut reason);
+            reason
+        });
+        let status = wait_or_kill(&mut child, EXTRACT_TIMEOUT);
+        (status, text.join().unwrap(), reason.join().unwrap())
+    });
+    let status = status.map_err(|err| Error::io(format!("could not wait for {RUNTIME}"), err))?;
+    let Some(status) = status else {
+        return Err(Error::invalid(format!(
+            "plugin {module} could not extract {name}: it took longer than {secs} seconds",
+            module = module.display(),
+            name = name.display(),
+            secs = EXTRACT_TIMEOUT.as_secs()
+        )));
+    };
+    if !status.success() {
+        let stderr = String::from_utf8_lossy(&stderr);
+        return Err(Error::invalid(format!(
+            "plugin {module} could not extract {name}: {reason}",
+            module = module.display(),
+            name = name.display(),
+            reason = stderr.lines().next().unwrap_or("it failed").trim()
+        )));
+    }
+    let stdout = stdout.map_err(|err| Error::io(format!("could not read from {RUNTIME}"), err))?;
+    String::from_utf8(stdout).map_err(|_| {
+        Error::invalid(format!(
+            "plugin {module} extracted text of {name} that is not UTF-8",
+            module = module.display(),
+            name = name.display()
+        ))
+    })
+}
+
+// The exit status of the child, none if it was killed for running longer
+// than `timeout`.
+fn wait_or_kill(child: &mut Child, timeout: Duration) -> io::Result<Option<ExitStatus>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            child.wait()?;
+            return Ok(None);
+        }
+        thread::sleep(WAIT_INTERVAL);
+    }
+}
+
+// A running token filter module. Analyzers of the same configuration share
+// it, and it runs as long as the process does.
+pub struct TokenFilter {
+    module: PathBuf,
+    state: Mutex<FilterState>,
+}
+
+#[derive(Default)]
+struct FilterState {
+    process: Option<FilterProcess>,
+    // Term → the term it became, none if dropped.
+    cache: HashMap<String, Option<String>>,
+    // Why the filter stopped working, if it did.
+    failure: Option<String>,
+}
+
+struct FilterProcess {
+    child: Child,
+    stdin: ChildStdin,
+    // The lines of stdout, read on their own thread so waiting for one can
+    // time out. It ends once the process is killed.
+    lines: Receiver<io::Result<String>>,
+}
+
+impl Drop for FilterProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// The filter of the module, started on its first term.
+pub fn token_filter(module: &Path) -> Arc<TokenFilter> {
+    static FILTERS: OnceLock<Mutex<HashMap<PathBuf, Arc<TokenFilter>>>> = OnceLock::new();
+    let mut filters = FILTERS.get_or_init(Default::default).lock().unwrap();
+    filters
+        .entry(module.to_path_buf())
+        .or_insert_with(|| {
+            Arc::new(TokenFilter {
+                module: module.to_path_buf(),
+                state: Mutex::new(FilterState::default()),
+            })
+        })
+        .clone()
+}
+
+// Filters are told apart by their module, for comparing analyzers.
+impl PartialEq for TokenFilter {
+    fn eq(&self, other: &Self) -> bool {
+        self.module == other.module
+    }
+}
+
+impl fmt::Debug for TokenFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TokenFilter({})", self.module.display())
+    }
+}
+
+impl TokenFilter {
+    pub fn module(&self) -> &Path {
+        &self.module
+    }
+
+    fn start(&self) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        self.process(&mut state).map(|_| ())
+    }
+
+    fn process<'a>(&self, state: &'a mut FilterState) -> Result<&'a mut FilterProcess, Error> {
+        if state.process.is_none() {
+            let mut child = runtime_command(&self.module, FILTER_FUEL)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()
+                .map_err(|err| Error::io(format!("could not run {RUNTIME}"), err))?;
+            let stdin = child.stdin.take().expect("stdin of the plugin is piped");
+            let stdout = child.stdout.take().expect("stdout of the plugin is piped");
+            let (sender, lines) = mpsc::channel();
+            thread::spawn(move || {
+                for line in BufReader::new(stdout).lines() {
+                    let failed = line.is_err();
+                    if sender.send(line).is_err() || failed {
+                        break;
+                    }
+                }
+            });
+            state.process = Some(FilterProcess {
+                child,
+                stdin,
+                lines,
+            });
+        }
+        Ok(state.process.as_mut().unwrap())
+    }
+
+    fn filter(&self, state: &mut FilterState, term: &str) -> Result<Option<String>, Error> {
+        let module = self.module.display();
+        let process = self.process(state)?;
+        writeln!(process.stdin, "{term}")
+            .and_then(|()| process.stdin.flush())
+            .map_err(|err| Error::io(format!("could not write to token filter {module}"), err))?;
+        // A filter that does not answer in time is killed by the caller
+        // dropping the process along with the failure.
+        match process.lines.recv_timeout(FILTER_TIMEOUT) {
+            Ok(Ok(filtered)) => Ok((!filtered.is_empty()).then_some(filtered)),
+            Ok(Err(err)) => Err(Error::io(
+                format!("could not read from token filter {module}"),
+                err,
+            )),
+            Err(RecvTimeoutError::Timeout) => Err(Error::invalid(format!(
+                "token filter {module} did not answer {term:?} within {secs} seconds",
+                secs = FILTER_TIMEOUT.as_secs()
+            ))),
+            Err(RecvTimeoutError::Disconnected) => {
+                Err(Error::invalid(format!("token filter {module} exited")))
+            }
+        }
+    }
+
+    // Why the filter stopped working, for the callers analyzing with it to
+    // fail. Analysis itself cannot fail, so they have to ask.
+    pub fn failure(&self) -> Option<Error> {
+        let state = self.state.lock().unwrap();
+        state.failure.clone().map(Error::Plugin)
+    }
+
+    // Rewrites the term in place, false when the filter drops it. A filter
+    // that failed leaves the terms as they are, see `failure`.
+    pub fn apply(&self, term: &mut String) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if let Some(filtered) = state.cache.get(term.as_str()) {
+            return match filtered {
+                Some(filtered) => {
+                    term.clone_from(filtered);
+                    true
+                }
+                None => false,
+            };
+        }
+        if state.failure.is_some() {
+            return true;
+        }
+        match self.filter(&mut state, term) {
+            Ok(filtered) => {
+                if state.cache.len() >= MAX_CACHED_TERMS {
+                    state.cache.clear();
+                }
+                state.cache.insert(term.clone(), filtered.clone());
+                match filtered {
+                    Some(filtered) => {
+                        *term = filtered;
+                        true
+                    }
+                    None => false,
+                }
+            }
+            Err(err) => {
+                state.failure = Some(err.to_string());
+                state.process = None;
+                true
+            }
+        }
+    }
+}
@@ -0,0 +1,181 @@
+// How often documents were opened from search results, counted from the
+// clicks the frontend reports to /api/feedback, so results can be sorted by
+// popularity or nudged towards it. A click loses half its weight every
+// `HALF_LIFE`, so documents popular long ago give way to those popular now.
+// Every document keeps its clicks as they weighed when it was last clicked,
+// with the time of that click. The counts are kept in a JSON file and saved
+// at most every `SAVE_INTERVAL`, so they outlive restarts.
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use tinysearch::handle::SearchResults;
+use tinysearch::{DocKey, Error};
+
+pub const HALF_LIFE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+const SAVE_INTERVAL: Duration = Duration::from_secs(10);
+
+// The order of the results of a search.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Sort {
+    #[default]
+    Relevance,
+    // Most clicked first, the more relevant first among equally popular.
+    Popular,
+}
+
+impl Sort {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "relevance" => Some(Self::Relevance),
+            "popular" => Some(Self::Popular),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct Clicks {
+    weight: f64,
+    // Seconds since the Unix epoch.
+    last_click: u64,
+}
+
+impl Clicks {
+    fn weight_at(&self, now: u64) -> f64 {
+        let age = now.saturating_sub(self.last_click) as f64;
+        self.weight * 0.5f64.powf(age / HALF_LIFE.as_secs_f64())
+    }
+}
+
+struct Counts {
+    docs: BTreeMap<DocKey, Clicks>,
+    changed: bool,
+    saved_at: Instant,
+}
+
+pub struct Popularity {
+    path: PathBuf,
+    // Weight of popularity in the score of a result, 0 to leave scores alone.
+    prior: f32,
+    counts: Mutex<Counts>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+impl Popularity {
+    // Starts from the counts in `path`, if it exists yet.
+    pub fn open(path: &Path, prior: f32) -> Result<Self, Error> {
+        let docs = match fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|err| {
+                Error::json(
+                    format!(
+                        "could not parse popularity file {path}",
+                        path = path.display()
+                    ),
+                    err,
+                )
+            })?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => {
+                return Err(Error::io(
+                    format!(
+                        "could not read popularity file {path}",
+                        path = path.display()
+                    ),
+                    err,
+                ))
+            }
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            prior,
+            counts: Mutex::new(Counts {
+                docs,
+                changed: false,
+                saved_at: Instant::now(),
+            }),
+        })
+    }
+
+    // Counts the click of a feedback record, which is one when it names the
+    // `path` of a result and its `event`, if any, is "click".
+    pub fn record(&self, feedback: &serde_json::Value) {
+        let Some(path) = feedback["path"].as_str() else {
+            return;
+        };
+        if feedback["event"]
+            .as_str()
+            .is_some_and(|event| event != "click")
+        {
+            return;
+        }
+        let now = now();
+        let mut counts = self.counts.lock().unwrap();
+        let clicks = counts.docs.entry(DocKey::from(path)).or_insert(Clicks {
+            weight: 0.0,
+            last_click: now,
+        });
+        *clicks = Clicks {
+            weight: clicks.weight_at(now) + 1.0,
+            last_click: now,
+        };
+        counts.changed = true;
+    }
+
+    // Nudges the scores of the matches by the clicks of their documents and
+    // orders them as `sort` says.
+    pub fn rank(&self, matches: &mut SearchResults, sort: Sort) {
+        if self.prior == 0.0 && sort == Sort::Relevance {
+            return;
+        }
+        let now = now();
+        let counts = self.counts.lock().unwrap();
+        let weight = |path: &Path| {
+            counts
+                .docs
+                .get(&*path.to_string_lossy())
+                .map_or(0.0, |c| c.weight_at(now))
+        };
+        if self.prior != 0.0 {
+            for (path, score) in matches.iter_mut() {
+                *score *= 1.0 + self.prior * weight(path).ln_1p() as f32;
+            }
+            matches.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        }
+        if sort == Sort::Popular {
+            // The sort is stable, keeping the order of relevance of ties.
+            matches.sort_by(|(a, _), (b, _)| weight(b).total_cmp(&weight(a)));
+        }
+    }
+
+    // Writes the counts when they changed and were not saved for a while.
+    pub fn save_if_due(&self) {
+        let mut counts = self.counts.lock().unwrap();
+        if !counts.changed || counts.saved_at.elapsed() < SAVE_INTERVAL {
+            return;
+        }
+        let tmp_path = PathBuf::from(format!("{}.tmp", self.path.display()));
+        let saved = serde_json::to_vec(&counts.docs)
+            .map_err(io::Error::from)
+            .and_then(|json| fs::write(&tmp_path, json))
+            .and_then(|()| fs::rename(&tmp_path, &self.path));
+        match saved {
+            Ok(()) => counts.changed = false,
+            Err(err) => eprintln!(
+                "ERROR: could not save popularity to {path}: {err}",
+                path = self.path.display()
+            ),
+        }
+        counts.saved_at = Instant::now();
+    }
+}
@@ -0,0 +1,688 @@
+// Postings of the binary index format, laid out so a query can be scored
+// straight from the bytes of the file: nothing per term is decoded up front,
+// the (document, count) pairs are varint decoded while they are scored.
+//
+// The block is written last, as
+//
+//   'P' <len: u64> <payload> <offset of the 'P': u64> "PST4"
+//
+// where the payload is
+//
+//   <docs: u32> (<doc id: u32> <path> <tokens in doc: u32>)*
+//   <dictionary len: u32> <dictionary>
+//   <terms: u32> (<df: u32> <idf: f32> <postings start: u64> <postings len: u32>)*
+//   <postings>*
+//
+// The dictionary is a finite-state transducer mapping every term to its id,
+// the index of its entry in the fixed-size term table, so looking terms up or
+// expanding prefixes, ranges, typos and wildcards never loads all the terms.
+// Documents are listed in the order of their IDs and postings are
+// (<doc id delta: varint> <count: varint>)* in that order, their start
+// relative to the first of them. As documents keep their IDs, renaming one
+// only changes its entry in the document table. Segments appended later are
+// not covered by the block, so it only counts while the trailer is at the
+// very end of the file. Blocks of older versions, with a "PSTG", "PST2" or
+// "PST3" trailer, are ignored; those of "PST3" hold IDFs computed before they
+// were smoothed.
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::mem::size_of;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+use fst::automaton::{Levenshtein, Str};
+use fst::{Automaton, IntoStreamer, Map, MapBuilder, Streamer};
+use tracing::debug_span;
+
+use crate::cache::{CacheStats, Lru};
+use crate::collector::Collector;
+use crate::memory;
+use crate::query::Query;
+use crate::scoring::{self, Scorer};
+use crate::{DocKey, Model, TermFreqIndex};
+
+const TRAILER: &[u8; 4] = b"PST4";
+
+// Bytes of an entry of the term table.
+const TERM_ENTRY_LEN: usize = 20;
+
+fn write_varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push((n as u8 & 0x7f) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn read_varint(bytes: &[u8], at: &mut usize) -> Option<u64> {
+    let mut n = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes.get(*at)?;
+        *at += 1;
+        n |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(n);
+        }
+    }
+    None
+}
+
+fn push_u32(out: &mut Vec<u8>, n: usize) -> io::Result<()> {
+    let n = u32::try_from(n).map_err(|_| io::Error::other("length does not fit in 32 bits"))?;
+    out.extend_from_slice(&n.to_le_bytes());
+    Ok(())
+}
+
+fn push_str(out: &mut Vec<u8>, s: &str) -> io::Result<()> {
+    push_u32(out, s.len())?;
+    out.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+// `offset` is where the block starts in the file.
+pub fn write_postings_block(
+    out: &mut impl Write,
+    offset: u64,
+    docs: &TermFreqIndex,
+) -> io::Result<()> {
+    let mut by_id = docs.iter().collect::<Vec<_>>();
+    by_id.sort_by_key(|(_, doc)| doc.id);
+    let mut payload = Vec::new();
+    push_u32(&mut payload, by_id.len())?;
+    let mut postings = HashMap::<&str, Vec<(usize, usize)>>::new();
+    for (path, doc) in &by_id {
+        payload.extend_from_slice(&doc.id.to_le_bytes());
+        push_str(&mut payload, &path.to_string_lossy())?;
+        push_u32(&mut payload, doc.tf.values().sum())?;
+        for (term, count) in &doc.tf {
+            postings
+                .entry(term)
+                .or_default()
+                .push((doc.id as usize, *count));
+        }
+    }
+    let mut terms = postings.into_iter().collect::<Vec<_>>();
+    terms.sort();
+    let mut dictionary = MapBuilder::memory();
+    let mut table = Vec::with_capacity(terms.len() * TERM_ENTRY_LEN);
+    let mut encoded = Vec::new();
+    for (id, (term, list)) in terms.iter().enumerate() {
+        dictionary
+            .insert(term, id as u64)
+            .map_err(io::Error::other)?;
+        let start = encoded.len();
+        let mut previous = 0;
+        for (ordinal, count) in list {
+            write_varint(&mut encoded, (ordinal - previous) as u64);
+            write_varint(&mut encoded, *count as u64);
+            previous = *ordinal;
+        }
+        push_u32(&mut table, list.len())?;
+        table.extend_from_slice(&scoring::idf(by_id.len(), list.len()).to_le_bytes());
+        table.extend_from_slice(&(start as u64).to_le_bytes());
+        push_u32(&mut table, encoded.len() - start)?;
+    }
+    let dictionary = dictionary.into_inner().map_err(io::Error::other)?;
+    push_u32(&mut payload, dictionary.len())?;
+    payload.extend_from_slice(&dictionary);
+    push_u32(&mut payload, terms.len())?;
+    payload.extend_from_slice(&table);
+    payload.extend_from_slice(&encoded);
+
+    out.write_all(b"P")?;
+    out.write_all(&(payload.len() as u64).to_le_bytes())?;
+    out.write_all(&payload)?;
+    out.write_all(&offset.to_le_bytes())?;
+    out.write_all(TRAILER)
+}
+
+// Bytes the block adds around its payload, for readers skipping it.
+pub const BLOCK_TRAILER_LEN: usize = 8 + TRAILER.len();
+
+struct TermEntry {
+    df: usize,
+    idf: f32,
+    postings: Range<usize>,
+}
+
+// The postings of a binary index file. The file is kept as it is, only the
+// document table is read up front: terms are looked up in the dictionary and
+// the term table when a query needs them.
+pub struct Postings {
+    bytes: Vec<u8>,
+    // Posting lists recently decoded for queries.
+    decoded: Mutex<Lru<String, Arc<PostingList>>>,
+    // Path and number of tokens of every document, at the index of its ID.
+    // IDs of removed documents leave gaps.
+    docs: Vec<Option<(DocKey, usize)>>,
+    doc_count: usize,
+    dictionary: Map<Vec<u8>>,
+    // Where the term table and the postings start in `bytes`.
+    term_table: usize,
+    postings: Range<usize>,
+}
+
+// Patterns terms can be expanded from, answered by walking the dictionary.
+pub enum TermPattern<'a> {
+    Prefix(&'a str),
+    // Terms from the first, included, up to the second, excluded.
+    Range(&'a str, &'a str),
+    // Terms within the given number of edits of the term, swapping two
+    // neighbouring characters being one edit like in most typos.
+    Fuzzy(&'a str, u32),
+    // `*` stands for any run of characters and `?` for a single one.
+    Wildcard(&'a str),
+}
+
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        let slice = self.bytes.get(self.at..self.at.checked_add(n)?)?;
+        self.at += n;
+        Some(slice)
+    }
+
+    fn u32(&mut self) -> Option<usize> {
+        let bytes = self.take(4)?;
+        Some(u32::from_le_bytes(bytes.try_into().ok()?) as usize)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        let bytes = self.take(8)?;
+        Some(u64::from_le_bytes(bytes.try_into().ok()?))
+    }
+
+    fn f32(&mut self) -> Option<f32> {
+        let bytes = self.take(4)?;
+        Some(f32::from_le_bytes(bytes.try_into().ok()?))
+    }
+
+    fn str(&mut self) -> Option<&'a str> {
+        let len = self.u32()?;
+        std::str::from_utf8(self.take(len)?).ok()
+    }
+}
+
+impl Postings {
+    // None when the file has no up to date postings block. Up to
+    // `cache_capacity` decoded posting lists are kept for later queries.
+    pub fn from_bytes(bytes: Vec<u8>, cache_capacity: usize) -> Option<Self> {
+        let trailer_at = bytes.len().checked_sub(BLOCK_TRAILER_LEN)?;
+        if &bytes[trailer_at + 8..] != TRAILER {
+            return None;
+        }
+        let offset = Cursor {
+            bytes: &bytes,
+            at: trailer_at,
+        }
+        .u64()? as usize;
+        let mut cursor = Cursor {
+            bytes: &bytes,
+            at: offset,
+        };
+        if cursor.take(1)? != b"P" || offset + 9 + cursor.u64()? as usize != trailer_at {
+            return None;
+        }
+
+        let doc_count = cursor.u32()?;
+        let mut docs = Vec::new();
+        for _ in 0..doc_count {
+            let id = cursor.u32()?;
+            let path = DocKey::from(cursor.str()?);
+            // IDs are in order, so the table grows to the last of them.
+            if id < docs.len() {
+                return None;
+            }
+            docs.resize(id, None);
+            docs.push(Some((path, cursor.u32()?)));
+        }
+        let len = cursor.u32()?;
+        let dictionary = Map::new(cursor.take(len)?.to_vec()).ok()?;
+        let terms = cursor.u32()?;
+        if dictionary.len() != terms {
+            return None;
+        }
+        let term_table = cursor.at;
+        cursor.take(terms.checked_mul(TERM_ENTRY_LEN)?)?;
+        let postings = cursor.at..trailer_at;
+        Some(Self {
+            bytes,
+            decoded: Mutex::new(Lru::new(cache_capacity)),
+            docs,
+            doc_count,
+            dictionary,
+            term_table,
+            postings,
+        })
+    }
+
+    fn entry_by_id(&self, id: u64) -> Option<TermEntry> {
+        let mut cursor = Cursor {
+            bytes: &self.bytes,
+            at: self.term_table + usize::try_from(id).ok()? * TERM_ENTRY_LEN,
+        };
+        let df = cursor.u32()?;
+        let idf = cursor.f32()?;
+        let start = self.postings.start.checked_add(cursor.u64()? as usize)?;
+        let end = start.checked_add(cursor.u32()?)?;
+        if end > self.postings.end {
+            return None;
+        }
+        Some(TermEntry {
+            df,
+            idf,
+            postings: start..end,
+        })
+    }
+
+    fn entry(&self, term: &str) -> Option<TermEntry> {
+        self.entry_by_id(self.dictionary.get(term)?)
+    }
+
+    // Index terms matching the pattern, in order, with the number of
+    // documents they appear in. At most `limit` of them.
+    pub fn expand(&self, pattern: &TermPattern, limit: usize) -> Vec<(String, usize)> {
+        expand_dictionary(&self.dictionary, pattern, limit)
+            .into_iter()
+            .filter_map(|(term, id)| Some((term, self.entry_by_id(id)?.df)))
+            .collect()
+    }
+
+    // (document ordinal, count) pairs of a term, decoded as they are consumed.
+    fn postings<'a>(&'a self, term: &str) -> impl Iterator<Item = (usize, usize)> + 'a {
+        let bytes = match self.entry(term) {
+            Some(entry) => &self.bytes[entry.postings],
+            None => &[][..],
+        };
+        let (mut at, mut ordinal) = (0, 0);
+        std::iter::from_fn(move || {
+            let delta = read_varint(bytes, &mut at)?;
+            let count = read_varint(bytes, &mut at)?;
+            ordinal += delta as usize;
+            Some((ordinal, count as usize))
+        })
+    }
+
+    fn decode(&self, term: &str) -> Arc<PostingList> {
+        if let Some(list) = self.decoded.lock().unwrap().get(term) {
+            return list;
+        }
+        let (ordinals, counts) = self.postings(term).unzip();
+        let list = Arc::new(PostingList { ordinals, counts });
+        self.decoded
+            .lock()
+            .unwrap()
+            .insert(term.to_string(), list.clone());
+        list
+    }
+
+    // Compares the block with the model loaded from the segments of the same
+    // file. Quick checks only look at the tables, thorough ones decode every
+    // posting list.
+    fn check(&self, model: &Model, thorough: bool, problems: &mut Vec<String>) {
+        let n = self.doc_count;
+        if n != model.docs.len() {
+            problems.push(format!(
+                "the postings block has {n} documents but the index has {docs}",
+                docs = model.docs.len()
+            ));
+        }
+        let mut postings_total = 0;
+        let mut stream = self.dictionary.stream();
+        while let Some((term, id)) = stream.next() {
+            let term = String::from_utf8_lossy(term);
+            let Some(entry) = self.entry_by_id(id) else {
+                problems.push(format!("the postings of {term:?} lie outside of the block"));
+                continue;
+            };
+            if !thorough {
+                continue;
+            }
+            if entry.idf.to_bits() != scoring::idf(n, entry.df).to_bits() {
+                problems.push(format!(
+                    "the IDF of {term:?} does not match its document frequency"
+                ));
+            }
+            let bytes = &self.bytes[entry.postings];
+            let (mut at, mut ordinal, mut len) = (0, None, 0);
+            while at < bytes.len() {
+                let (Some(delta), Some(count)) =
+                    (read_varint(bytes, &mut at), read_varint(bytes, &mut at))
+                else {
+                    problems.push(format!("the postings of {term:?} are truncated"));
+                    break;
+                };
+                if ordinal.is_some() && delta == 0 {
+                    problems.push(format!(
+                        "the postings of {term:?} are not in document order"
+                    ));
+                }
+                let current = ordinal.map_or(delta as usize, |ordinal| ordinal + delta as usize);
+                ordinal = Some(current);
+                len += 1;
+                let Some(Some((path, _))) = self.docs.get(current) else {
+                    problems.push(format!(
+                        "a posting of {term:?} references document {current}, which does not exist"
+                    ));
+                    continue;
+                };
+                let indexed = model.docs.get(path).and_then(|doc| doc.tf.get(&*term));
+                if indexed != Some(&(count as usize)) {
+                    problems.push(format!(
+                        "the postings say {term:?} occurs {count} times in {path}, the index says {indexed:?}",
+                        path = path.display()
+                    ));
+                }
+            }
+            if len != entry.df {
+                problems.push(format!(
+                    "{term:?} has a document frequency of {df} but {len} postings",
+                    df = entry.df
+                ));
+            }
+            postings_total += len;
+        }
+        if !thorough {
+            return;
+        }
+        for (id, (path, tokens)) in self
+            .docs
+            .iter()
+            .enumerate()
+            .filter_map(|(id, doc)| Some((id, doc.as_ref()?)))
+        {
+            match model.docs.get(path) {
+                None => problems.push(format!(
+                    "{path} is in the postings block but not in the index",
+                    path = path.display()
+                )),
+                Some(doc) if doc.id as usize != id => problems.push(format!(
+                    "{path} has ID {id} in the postings block but {indexed} in the index",
+                    path = path.display(),
+                    indexed = doc.id
+                )),
+                Some(doc) if doc.tf.values().sum::<usize>() != *tokens => problems.push(format!(
+                    "{path} has {tokens} tokens in the postings block but {indexed} in the index",
+                    path = path.display(),
+                    indexed = doc.tf.values().sum::<usize>()
+                )),
+                Some(_) => {}
+            }
+        }
+        let indexed = model.docs.values().map(|doc| doc.tf.len()).sum::<usize>();
+        if postings_total != indexed {
+            problems.push(format!(
+                "the postings block has {postings_total} postings but the index has {indexed}"
+            ));
+        }
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.decoded.lock().unwrap().stats()
+    }
+
+    // Bytes of the index file held in memory.
+    pub fn file_bytes(&self) -> usize {
+        self.bytes.len()
+    }
+
+    // Estimated bytes of the decoded posting lists kept.
+    pub fn cache_bytes(&self) -> usize {
+        self.decoded.lock().unwrap().bytes(|term, list| {
+            memory::string_bytes(term)
+                + (list.ordinals.len() + list.counts.len()) * size_of::<usize>()
+        })
+    }
+
+    pub fn clear_cache(&self) {
+        self.decoded.lock().unwrap().clear();
+    }
+
+    // `search::collect_query` without filters.
+    pub fn collect(&self, query: &Query, scorer: &dyn Scorer, collector: &mut dyn Collector) {
+        let n = self.doc_count;
+        let retrieve = debug_span!("retrieve").entered();
+        let lists = query
+            .all_terms()
+            .into_iter()
+            .map(|term| (term, self.decode(term)))
+            .collect::<HashMap<_, _>>();
+        let candidates = candidates(query, self.docs.len(), |term| &lists[term]);
+        drop(retrieve);
+
+        // Documents still have to match the whole query, which is checked
+        // with their counts while scoring.
+        let _score = debug_span!("score").entered();
+
+        let positive_terms = if collector.needs_scores() {
+            query.weighted_terms()
+        } else {
+            Vec::new()
+        };
+        let idfs = positive_terms
+            .iter()
+            .map(|(term, _)| {
+                let doc_freq = self.entry(term).map_or(0, |entry| entry.df);
+                scorer.idf(n, doc_freq)
+            })
+            .collect::<Vec<_>>();
+        for ordinal in candidates {
+            let Some(Some((path, total))) = self.docs.get(ordinal) else {
+                continue;
+            };
+            let count = |term: &str| lists.get(term).and_then(|list| list.count(ordinal));
+            if !query.matches(&|term| count(term).is_some()) {
+                continue;
+            }
+            let mut score = 0.0;
+            for ((term, weight), idf) in positive_terms.iter().zip(&idfs) {
+                if let Some(count) = count(term) {
+                    score += weight * scorer.score(count, *total, *idf);
+                }
+            }
+            collector.collect(path, score);
+        }
+    }
+}
+
+// Checks the postings block at the end of a binary index file, if it has an up
+// to date one, against the model loaded from the same file.
+pub fn check_block(bytes: Vec<u8>, model: &Model, thorough: bool, problems: &mut Vec<String>) {
+    if !bytes.ends_with(TRAILER) {
+        return;
+    }
+    match Postings::from_bytes(bytes, 0) {
+        Some(postings) => postings.check(model, thorough, problems),
+        None => problems.push("the postings block is corrupt".to_string()),
+    }
+}
+
+// Whether the whole text matches a pattern where `*` stands for any run of
+// characters and `?` for a single one.
+// Keys of an FST term dictionary matching the pattern, in order, with their
+// values. At most `limit` of them.
+pub fn expand_dictionary<D: AsRef<[u8]>>(
+    dictionary: &Map<D>,
+    pattern: &TermPattern,
+    limit: usize,
+) -> Vec<(String, u64)> {
+    match pattern {
+        TermPattern::Prefix(prefix) => {
+            let matcher = Str::new(prefix).starts_with();
+            collect(dictionary.search(matcher).into_stream(), limit, |_| true)
+        }
+        TermPattern::Range(from, to) => {
+            let stream = dictionary.range().ge(from).lt(to).into_stream();
+            collect(stream, limit, |_| true)
+        }
+        TermPattern::Fuzzy(term, distance) => {
+            // A swap is two edits to the automaton, so it looks one edit
+            // further and the terms are checked counting swaps as one.
+            let chars = term.chars().collect::<Vec<_>>();
+            let keep = |candidate: &str| {
+                edit_distance(&chars, &candidate.chars().collect::<Vec<_>>()) <= *distance
+            };
+            match Levenshtein::new(term, distance + 1) {
+                Ok(automaton) => collect(dictionary.search(automaton).into_stream(), limit, keep),
+                // The automaton is too large to build: no expansion at all is
+                // better than a runaway one.
+                Err(_) => Vec::new(),
+            }
+        }
+        TermPattern::Wildcard(pattern) => {
+            // Only terms starting with the text before the first wildcard can
+            // match, which the dictionary finds without a full scan.
+            let literal = pattern.split(['*', '?']).next().unwrap_or_default();
+            let pattern = pattern.chars().collect::<Vec<_>>();
+            let matcher = Str::new(literal).starts_with();
+            collect(dictionary.search(matcher).into_stream(), limit, |term| {
+                wildcard_matches(&pattern, &term.chars().collect::<Vec<_>>())
+            })
+        }
+    }
+}
+
+fn collect<S>(mut stream: S, limit: usize, keep: impl Fn(&str) -> bool) -> Vec<(String, u64)>
+where
+    S: for<'a> Streamer<'a, Item = (&'a [u8], u64)>,
+{
+    let mut terms = Vec::new();
+    while let Some((term, value)) = stream.next() {
+        if terms.len() == limit {
+            break;
+        }
+        let Ok(term) = std::str::from_utf8(term) else {
+            continue;
+        };
+        if keep(term) {
+            terms.push((term.to_string(), value));
+        }
+    }
+    terms
+}
+
+// Edits turning `a` into `b`: inserting, deleting or replacing a character,
+// or swapping two neighbouring ones (the optimal string alignment distance).
+pub(crate) fn edit_distance(a: &[char], b: &[char]) -> u32 {
+    // Three rows of the table: the one two rows up is needed for swaps.
+    let mut before = vec![0; b.len() + 1];
+    let mut previous = (0..=b.len() as u32).collect::<Vec<_>>();
+    let mut current = vec![0; b.len() + 1];
+    for i in 1..=a.len() {
+        current[0] = i as u32;
+        for j in 1..=b.len() {
+            let cost = u32::from(a[i - 1] != b[j - 1]);
+            current[j] = (previous[j] + 1)
+                .min(current[j - 1] + 1)
+                .min(previous[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                current[j] = current[j].min(before[j - 2] + 1);
+            }
+        }
+        before.clone_from(&previous);
+        previous.clone_from(&current);
+    }
+    previous[b.len()]
+}
+
+fn wildcard_matches(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Where to resume after the last `*`: the pattern after it, and the text
+    // position it has swallowed up to.
+    let mut star = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((after, swallowed)) => {
+                    p = after;
+                    t = swallowed + 1;
+                    star = Some((after, swallowed + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// A decoded posting list, in document order.
+pub(crate) struct PostingList {
+    pub(crate) ordinals: Vec<usize>,
+    pub(crate) counts: Vec<usize>,
+}
+
+impl PostingList {
+    pub(crate) fn count(&self, ordinal: usize) -> Option<usize> {
+        let at = self.ordinals.binary_search(&ordinal).ok()?;
+        Some(self.counts[at])
+    }
+}
+
+// Ordinals of the documents out of `docs` that may match the query, in order,
+// given the posting list of every term of the query. They still have to be
+// checked against the whole query.
+//
+// Candidates are the intersection of the postings of the terms every match
+// must contain, rarest first: each list only gallops through the next one, so
+// a rare term keeps the work small however common the others are. Without
+// required terms, a query that no empty document matches only matches
+// documents with one of its terms, the union of their postings. Only queries
+// like `NOT term` consider every document.
+pub(crate) fn candidates<'a>(
+    query: &Query,
+    docs: usize,
+    list: impl Fn(&str) -> &'a PostingList,
+) -> Vec<usize> {
+    let mut required = query.required_terms();
+    required.sort_by_key(|term| (list(term).ordinals.len(), *term));
+    required.dedup();
+    match required.split_first() {
+        Some((rarest, rest)) => {
+            let mut candidates = list(rarest).ordinals.clone();
+            for term in rest {
+                let ordinals = &list(term).ordinals;
+                let mut from = 0;
+                candidates.retain(|&ordinal| {
+                    from = gallop(ordinals, from, ordinal);
+                    ordinals.get(from) == Some(&ordinal)
+                });
+            }
+            candidates
+        }
+        None if !query.matches(&|_| false) => {
+            let mut candidates = query
+                .all_terms()
+                .into_iter()
+                .flat_map(|term| list(term).ordinals.iter().copied())
+                .collect::<Vec<_>>();
+            candidates.sort_unstable();
+            candidates.dedup();
+            candidates
+        }
+        None => (0..docs).collect(),
+    }
+}
+
+// Index of the first ordinal at or after `from` that is not below `target`:
+// steps doubling in size find a range holding it, then a binary search.
+fn gallop(ordinals: &[usize], from: usize, target: usize) -> usize {
+    let mut bound = 1;
+    while from + bound < ordinals.len() && ordinals[from + bound] < target {
+        bound *= 2;
+    }
+    let start = from + bound / 2;
+    let end = (from + bound + 1).min(ordinals.len());
+    start + ordinals[start..end].partition_point(|&ordinal| ordinal < target)
+}
@@ -0,0 +1,125 @@
+// How much of the queries the server writes to its logs, for deployments
+// where what people search for is sensitive. `--query-logging` sets it for
+// every request: `full` logs queries as they are, `hashed` logs a hash that
+// still shows which records have the same query, and `off` logs no queries
+// at all. A client can ask for less for its own request, with an
+// `X-Query-Logging` header or `DNT: 1`, never for more. The logs and the
+// request traces only ever see queries through `Redaction`, so no record
+// can leak one the settings keep out.
+use serde_json::Value;
+use tiny_http::Request;
+
+use crate::http;
+use tinysearch::extract::content_hash;
+
+// Fields of log records that give away what was searched for: the query,
+// the parsed query of the slow log and the filters.
+const QUERY_FIELDS: &[&str] = &["query", "parsed", "filters"];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum QueryLogging {
+    #[default]
+    Full,
+    Hashed,
+    Off,
+}
+
+impl QueryLogging {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "full" => Some(Self::Full),
+            "hashed" => Some(Self::Hashed),
+            "off" => Some(Self::Off),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct QueryPrivacy {
+    pub logging: QueryLogging,
+    // Mixed into the hashes, so that nobody without it can find out which
+    // hash a common query has by hashing it.
+    pub hash_key: String,
+}
+
+impl QueryPrivacy {
+    // What may be logged of the queries of a request.
+    pub fn for_request(&self, request: &Request) -> Redaction<'_> {
+        let asked = match http::header(request, "X-Query-Logging") {
+            Some(value) => QueryLogging::from_name(value.trim()).unwrap_or(QueryLogging::Off),
+            None if http::header(request, "DNT") == Some("1") => QueryLogging::Off,
+            None => QueryLogging::Full,
+        };
+        Redaction {
+            logging: self.logging.max(asked),
+            hash_key: &self.hash_key,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Redaction<'a> {
+    pub logging: QueryLogging,
+    hash_key: &'a str,
+}
+
+impl Redaction<'_> {
+    // A query as it may be logged, None when it may not be.
+    pub fn query(&self, query: &str) -> Option<String> {
+        match self.logging {
+            QueryLogging::Full => Some(query.to_string()),
+            QueryLogging::Hashed => Some(format!(
+                "hash:{}",
+                content_hash(format!("{}\0{query}", self.hash_key).as_bytes())
+            )),
+            QueryLogging::Off => None,
+        }
+    }
+
+    // A request URL with its query string, which holds the query of a GET
+    // search, redacted like a query.
+    pub fn url(&self, url: &str) -> String {
+        match url.split_once('?') {
+            Some((path, params)) if self.logging != QueryLogging::Full => {
+                match self.query(params) {
+                    Some(hash) => format!("{path}?{hash}"),
+                    None => path.to_string(),
+                }
+            }
+            _ => url.to_string(),
+        }
+    }
+
+    // A log record with the fields that hold a query, and those of posted
+    // feedback, redacted; the fields are dropped when queries may not be
+    // logged.
+    pub fn record(&self, mut record: Value) -> Value {
+        self.redact_fields(&mut record);
+        if let Some(feedback) = record.get_mut("feedback") {
+            self.redact_fields(feedback);
+        }
+        record
+    }
+
+    fn redact_fields(&self, record: &mut Value) {
+        if self.logging == QueryLogging::Full {
+            return;
+        }
+        let Some(object) = record.as_object_mut() else {
+            return;
+        };
+        for field in QUERY_FIELDS {
+            let Some(value) = object.remove(*field) else {
+                continue;
+            };
+            let text = match value {
+                Value::String(text) => text,
+                other => other.to_string(),
+            };
+            if let Some(hash) = self.query(&text) {
+                object.insert(field.to_string(), hash.into());
+            }
+        }
+    }
+}
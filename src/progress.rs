@@ -0,0 +1,65 @@
+// The progress line of the index subcommand: files done out of the files
+// listed, failures, the time spent and the file being read, redrawn in place
+// on the terminal while the workers index.
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tinysearch::indexer::Progress;
+
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+// Longest file path shown, its end kept.
+const MAX_PATH_CHARS: usize = 60;
+
+// Seconds, or minutes and seconds past a minute.
+pub fn elapsed(duration: Duration) -> String {
+    let secs = duration.as_secs_f64();
+    if secs < 60.0 {
+        format!("{secs:.1}s")
+    } else {
+        format!("{}m{:02}s", secs as u64 / 60, secs as u64 % 60)
+    }
+}
+
+fn path_tail(path: &str) -> String {
+    let chars = path.chars().count();
+    if chars <= MAX_PATH_CHARS {
+        return path.to_string();
+    }
+    let tail = path
+        .chars()
+        .skip(chars - MAX_PATH_CHARS + 1)
+        .collect::<String>();
+    format!("…{tail}")
+}
+
+fn line(progress: &Progress, started: Instant) -> String {
+    let mut line = format!(
+        "{done}/{total} files, {failed} failed, {elapsed}",
+        done = progress.done(),
+        total = progress.total(),
+        failed = progress.failed(),
+        elapsed = elapsed(started.elapsed()),
+    );
+    if let Some(current) = progress.current() {
+        line.push_str(": ");
+        line.push_str(&path_tail(&current.to_string_lossy()));
+    }
+    line
+}
+
+// Redraws the line on stderr until `done` is set, then clears it. Draws
+// nothing unless stderr is a terminal.
+pub fn show(progress: &Progress, started: Instant, done: &AtomicBool) {
+    let mut stderr = io::stderr();
+    if !stderr.is_terminal() {
+        return;
+    }
+    while !done.load(Ordering::Relaxed) {
+        let _ = write!(stderr, "\r\x1b[K{}", line(progress, started));
+        let _ = stderr.flush();
+        thread::sleep(REDRAW_INTERVAL);
+    }
+    let _ = write!(stderr, "\r\x1b[K");
+}
@@ -0,0 +1,238 @@
+// What an index was built from, written by `index --manifest` to
+// `<index>.manifest.json` next to it: every source document read, by the
+// path it is indexed under, with its size and SHA-256, the analysis settings
+// and the SHA-256 of the index file written. `verify-manifest` compares it
+// with the corpus as it is now, so a published index can be checked to be
+// built from exactly these inputs, and a rebuild to be the same byte for
+// byte. The manifest has no timestamps, so rebuilding from the same inputs
+// writes the same manifest too.
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::config::IndexConfig;
+use crate::source;
+use crate::{DocKey, Error, FileStamps};
+
+pub const MANIFEST_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Input {
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl Input {
+    pub fn of(bytes: &[u8]) -> Self {
+        Self {
+            size: bytes.len() as u64,
+            sha256: hex(&Sha256::digest(bytes)),
+        }
+    }
+
+    // The file at `path`, read in blocks rather than whole.
+    pub fn of_file(path: &Path) -> io::Result<Self> {
+        let mut file = fs::File::open(path)?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0; 64 * 1024];
+        let mut size = 0;
+        loop {
+            let read = file.read(&mut buf)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buf[..read]);
+            size += read as u64;
+        }
+        Ok(Self {
+            size,
+            sha256: hex(&hasher.finalize()),
+        })
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BuildManifest {
+    pub version: u32,
+    // The version of tinySearch that built the index.
+    pub tinysearch: String,
+    pub config: IndexConfig,
+    // None for indexes that are not one file, like sharded ones.
+    pub index: Option<Input>,
+    pub inputs: BTreeMap<DocKey, Input>,
+}
+
+// `<index>.manifest.json`.
+pub fn manifest_path(index_path: &Path) -> PathBuf {
+    let mut path = index_path.as_os_str().to_owned();
+    path.push(".manifest.json");
+    PathBuf::from(path)
+}
+
+impl BuildManifest {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let json = fs::read_to_string(path).map_err(|err| {
+            Error::io(
+                format!("could not read manifest {path}", path = path.display()),
+                err,
+            )
+        })?;
+        let manifest: Self = serde_json::from_str(&json).map_err(|err| {
+            Error::json(
+                format!("could not parse manifest {path}", path = path.display()),
+                err,
+            )
+        })?;
+        if manifest.version > MANIFEST_VERSION {
+            return Err(Error::invalid(format!(
+                "manifest {path} has version {version}, this tinySearch reads up to {MANIFEST_VERSION}",
+                path = path.display(),
+                version = manifest.version
+            )));
+        }
+        Ok(manifest)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let json = serde_json::to_string_pretty(self).expect("manifests serialize");
+        fs::write(path, json + "\n").map_err(|err| {
+            Error::io(
+                format!("could not write manifest {path}", path = path.display()),
+                err,
+            )
+        })
+    }
+}
+
+// Hashes the source documents as the indexer reads them.
+#[derive(Default)]
+pub struct InputHashes {
+    inputs: Mutex<BTreeMap<DocKey, Input>>,
+}
+
+impl InputHashes {
+    pub fn record(&self, id: &DocKey, bytes: &[u8]) {
+        let input = Input::of(bytes);
+        self.inputs.lock().unwrap().insert(id.clone(), input);
+    }
+
+    // The manifest of the index at `index_path` as last written, whose
+    // source files are `files`. Files an incremental run did not read as
+    // they were unchanged are read now.
+    pub fn manifest(
+        &self,
+        index_path: &Path,
+        config: &IndexConfig,
+        files: &FileStamps,
+    ) -> Result<BuildManifest, Error> {
+        let mut read = self.inputs.lock().unwrap();
+        let mut inputs = BTreeMap::new();
+        for path in files.keys() {
+            let input = match read.remove(path) {
+                Some(input) => input,
+                None => Input::of_file(path).map_err(|err| {
+                    Error::io(format!("could not hash {path}", path = path.display()), err)
+                })?,
+            };
+            inputs.insert(path.clone(), input);
+        }
+        let index = if index_path.is_file() {
+            Some(Input::of_file(index_path).map_err(|err| {
+                Error::io(
+                    format!("could not hash {path}", path = index_path.display()),
+                    err,
+                )
+            })?)
+        } else {
+            None
+        };
+        Ok(BuildManifest {
+            version: MANIFEST_VERSION,
+            tinysearch: env!("CARGO_PKG_VERSION").to_string(),
+            config: config.clone(),
+            index,
+            inputs,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InputState {
+    Unchanged,
+    Modified,
+    Missing,
+    // URLs and the members of archives, which are not files to read again.
+    Unchecked,
+}
+
+// How the corpus as it is now compares to the manifest.
+#[derive(Debug, Serialize)]
+pub struct Verification {
+    // The inputs that are not unchanged, in path order.
+    pub changed: Vec<(DocKey, InputState)>,
+    pub unchanged: usize,
+    // None when the manifest has no hash of the index or it was not checked.
+    pub index_matches: Option<bool>,
+}
+
+impl Verification {
+    pub fn is_verified(&self) -> bool {
+        self.index_matches != Some(false)
+            && self
+                .changed
+                .iter()
+                .all(|(_, state)| *state == InputState::Unchecked)
+    }
+}
+
+// Compares the inputs of the manifest with the files as they are now, their
+// paths taken relative to `root` as they were to the directory the index was
+// built in, and the index at `index_path` with the one it recorded.
+pub fn verify(manifest: &BuildManifest, root: &Path, index_path: Option<&Path>) -> Verification {
+    let mut changed = Vec::new();
+    let mut unchanged = 0;
+    for (path, input) in &manifest.inputs {
+        let file = root.join(path);
+        let state = if source::is_url(&path.to_string_lossy()) {
+            InputState::Unchecked
+        } else if !file.is_file() {
+            if file.ancestors().skip(1).any(Path::is_file) {
+                InputState::Unchecked
+            } else {
+                InputState::Missing
+            }
+        } else {
+            match Input::of_file(&file) {
+                Ok(current) if current == *input => InputState::Unchanged,
+                Ok(_) => InputState::Modified,
+                Err(_) => InputState::Missing,
+            }
+        };
+        if state == InputState::Unchanged {
+            unchanged += 1;
+        } else {
+            changed.push((path.clone(), state));
+        }
+    }
+    let index_matches = match (index_path, &manifest.index) {
+        (Some(index_path), Some(recorded)) => {
+            Some(Input::of_file(index_path).is_ok_and(|current| current == *recorded))
+        }
+        _ => None,
+    };
+    Verification {
+        changed,
+        unchanged,
+        index_matches,
+    }
+}
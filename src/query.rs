@@ -0,0 +1,926 @@
+// The query language: bare words, "quoted phrases", AND, OR, NOT (or a
+// leading `-`) and parentheses. Words next to each other without an operator
+// are alternatives, like the original bag-of-words search. Words with `*` or
+// `?` are wildcards and a trailing `~` matches words with typos, as many as
+// the typo tolerance allows for the length of the word, or `~2` with up to
+// two; both stand for the index terms they expand to. The terms matched with
+// typos score less than the word itself.
+//
+// A backslash takes the character after it literally, so `\AND` searches for
+// the word rather than the operator, `\(C\)` for a word in parentheses,
+// `\-1` for a negative number and `what\?` for a question rather than a
+// wildcard. A word with escapes is never an operator, but can still end in
+// `~`. In quoted phrases only `\"` and `\\` need escaping; everything else
+// in them, `"AND"` and `"C++"` too, is taken as written.
+use std::fmt;
+
+use crate::analyzer::Analyzer;
+use crate::postings::TermPattern;
+use crate::scoring::{CorpusStats, FEEDBACK_WEIGHT, TYPO_PENALTY};
+use crate::{Doc, Positions};
+
+// Queries can also be built in code rather than parsed, and every query
+// prints in the syntax above:
+//
+//   Query::term("index").and(Query::phrase(["term", "frequency"]).or(Query::typo_tolerant("ranking")))
+//
+// is `index AND ("term frequency" OR ranking~)`.
+
+// Longest edit distance of a fuzzy term. Beyond it nearly every short term
+// matches and the automaton gets expensive to build.
+const MAX_EDIT_DISTANCE: u32 = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    Term(String),
+    Phrase(Vec<String>),
+    And(Vec<Query>),
+    Or(Vec<Query>),
+    Not(Box<Query>),
+    // Replaced by the terms they match in `expand`.
+    Wildcard(String),
+    // Without an edit distance, the typo tolerance decides on it.
+    Fuzzy(String, Option<u32>),
+    // An index term a fuzzy word expanded to other than the word itself. It
+    // matches like a term but scores less.
+    Typo(String),
+    // An index term pseudo-relevance feedback added, from the best results
+    // of the query as typed. It matches like a term but scores less.
+    Related(String),
+}
+
+// Building queries for programs embedding the search. The words are taken
+// as typed; `analyzed` turns them into index terms like `parse` does.
+impl Query {
+    pub fn term(word: impl Into<String>) -> Self {
+        Query::Term(word.into())
+    }
+
+    pub fn phrase<I, S>(words: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Query::Phrase(words.into_iter().map(Into::into).collect())
+    }
+
+    // `*` stands for any characters, `?` for a single one.
+    pub fn wildcard(pattern: impl Into<String>) -> Self {
+        Query::Wildcard(pattern.into())
+    }
+
+    pub fn fuzzy(word: impl Into<String>, distance: u32) -> Self {
+        Query::Fuzzy(word.into(), Some(distance.min(MAX_EDIT_DISTANCE)))
+    }
+
+    // `word~`: the word with as many typos as `QueryLimits::typos` allows.
+    pub fn typo_tolerant(word: impl Into<String>) -> Self {
+        Query::Fuzzy(word.into(), None)
+    }
+
+    // Documents matching every one of the queries.
+    pub fn all(queries: impl IntoIterator<Item = Query>) -> Self {
+        queries
+            .into_iter()
+            .reduce(Query::and)
+            .unwrap_or(Query::And(Vec::new()))
+    }
+
+    // Documents matching any of the queries.
+    pub fn any(queries: impl IntoIterator<Item = Query>) -> Self {
+        queries
+            .into_iter()
+            .reduce(Query::or)
+            .unwrap_or(Query::Or(Vec::new()))
+    }
+
+    pub fn and(self, other: Query) -> Self {
+        match (self, other) {
+            (Query::And(mut left), Query::And(right)) => {
+                left.extend(right);
+                Query::And(left)
+            }
+            (Query::And(mut left), right) => {
+                left.push(right);
+                Query::And(left)
+            }
+            (left, right) => Query::And(vec![left, right]),
+        }
+    }
+
+    pub fn or(self, other: Query) -> Self {
+        match (self, other) {
+            (Query::Or(mut left), Query::Or(right)) => {
+                left.extend(right);
+                Query::Or(left)
+            }
+            (Query::Or(mut left), right) => {
+                left.push(right);
+                Query::Or(left)
+            }
+            (left, right) => Query::Or(vec![left, right]),
+        }
+    }
+
+    // Documents matching this query but not `excluded`.
+    pub fn and_not(self, excluded: Query) -> Self {
+        self.and(Query::Not(Box::new(excluded)))
+    }
+
+    // The query with its words analyzed into index terms, which is what
+    // `parse` makes of its printed form.
+    pub fn analyzed(self, analyzer: &Analyzer) -> Self {
+        // Operands that analyze to nothing are dropped, as in the parser.
+        let analyze_all = |operands: Vec<Query>, combine: fn(Vec<Query>) -> Query| {
+            let mut operands = operands
+                .into_iter()
+                .map(|operand| operand.analyzed(analyzer))
+                .filter(|operand| !operand.is_empty())
+                .collect::<Vec<_>>();
+            match operands.len() {
+                0 => Query::Phrase(Vec::new()),
+                1 => operands.pop().unwrap(),
+                _ => combine(operands),
+            }
+        };
+        match self {
+            Query::Term(word) | Query::Typo(word) | Query::Related(word) => {
+                words_query(analyzer.terms(&word))
+            }
+            Query::Phrase(words) => Query::Phrase(analyzer.terms(&words.join(" "))),
+            Query::And(operands) => analyze_all(operands, Query::And),
+            Query::Or(operands) => analyze_all(operands, Query::Or),
+            Query::Not(inner) => match inner.analyzed(analyzer) {
+                inner if inner.is_empty() => inner,
+                inner => Query::Not(Box::new(inner)),
+            },
+            Query::Wildcard(pattern) => Query::Wildcard(analyzer.fold_pattern(&pattern)),
+            Query::Fuzzy(word, distance) => {
+                let mut terms = analyzer.terms(&word);
+                match terms.len() {
+                    1 => Query::Fuzzy(terms.pop().unwrap(), distance),
+                    _ => words_query(terms),
+                }
+            }
+        }
+    }
+}
+
+// Writes a word so it parses back as the same term: operator names in
+// lowercase, and words with characters the syntax gives a meaning to quoted.
+fn write_word(f: &mut fmt::Formatter, word: &str) -> fmt::Result {
+    match word {
+        "AND" | "OR" | "NOT" => write!(f, "{}", word.to_lowercase()),
+        _ if word.starts_with('-')
+            || word.contains(|c: char| c.is_whitespace() || "()\"*?~\\".contains(c)) =>
+        {
+            write!(f, "\"{}\"", escape_quoted(word))
+        }
+        _ => write!(f, "{word}"),
+    }
+}
+
+// The text of a phrase with the quotes and backslashes in it escaped.
+fn escape_quoted(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// Operands that would not parse back as one operand, like an OR under an
+// AND, or an AND under an AND that the parser would flatten, are wrapped in
+// parentheses.
+fn write_operand(f: &mut fmt::Formatter, operand: &Query, parenthesize: bool) -> fmt::Result {
+    if parenthesize {
+        write!(f, "({operand})")
+    } else {
+        write!(f, "{operand}")
+    }
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Query::Term(word) | Query::Typo(word) | Query::Related(word) => write_word(f, word),
+            Query::Phrase(words) => write!(f, "\"{}\"", escape_quoted(&words.join(" "))),
+            Query::And(operands) | Query::Or(operands) if operands.is_empty() => write!(f, "()"),
+            Query::And(operands) => {
+                for (i, operand) in operands.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " AND ")?;
+                    }
+                    write_operand(f, operand, matches!(operand, Query::And(_) | Query::Or(_)))?;
+                }
+                Ok(())
+            }
+            Query::Or(operands) => {
+                for (i, operand) in operands.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " OR ")?;
+                    }
+                    write_operand(f, operand, matches!(operand, Query::Or(_)))?;
+                }
+                Ok(())
+            }
+            Query::Not(inner) => {
+                write!(f, "NOT ")?;
+                write_operand(f, inner, matches!(**inner, Query::And(_) | Query::Or(_)))
+            }
+            Query::Wildcard(pattern) => write!(f, "{pattern}"),
+            Query::Fuzzy(word, None) => write!(f, "{word}~"),
+            Query::Fuzzy(word, Some(distance)) => write!(f, "{word}~{distance}"),
+        }
+    }
+}
+
+// How far a single query may grow once its wildcards and fuzzy terms are
+// expanded, so a pattern like `a*` cannot make a search scan most of the
+// index.
+#[derive(Debug, Clone, Copy)]
+pub struct QueryLimits {
+    // Index terms all wildcards and fuzzy terms of a query expand to.
+    pub max_expansions: usize,
+    // Terms of the expanded query, the ones typed in included.
+    pub max_clauses: usize,
+    // Typos allowed in `word~`.
+    pub typos: Typos,
+    // Every word outside phrases and exclusions is taken as `word~`.
+    pub fuzzy: bool,
+}
+
+impl Default for QueryLimits {
+    fn default() -> Self {
+        Self {
+            max_expansions: 256,
+            max_clauses: 1024,
+            typos: Typos::Tiered,
+            fuzzy: false,
+        }
+    }
+}
+
+// How many typos a `word~` may have. Like in Meilisearch or Typesense the
+// allowance grows with the word: a typo in a short word makes it another
+// word more often than not.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Typos {
+    // None up to 4 characters, one up to 8, two beyond.
+    Tiered,
+    // The same for every word, none turning `word~` into `word`.
+    Fixed(u32),
+}
+
+impl Typos {
+    // `auto`, `off` or a number of typos.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "auto" => Some(Self::Tiered),
+            "off" => Some(Self::Fixed(0)),
+            _ => value
+                .parse()
+                .ok()
+                .filter(|&typos| typos <= MAX_EDIT_DISTANCE)
+                .map(Self::Fixed),
+        }
+    }
+
+    pub fn distance(self, term: &str) -> u32 {
+        match self {
+            Self::Tiered => match term.chars().count() {
+                0..=4 => 0,
+                5..=8 => 1,
+                _ => 2,
+            },
+            Self::Fixed(typos) => typos,
+        }
+    }
+}
+
+// A syntax error with the character range it refers to, so it can be shown
+// with a caret under the offending part of the query.
+#[derive(Debug)]
+pub struct ParseError {
+    pub start: usize,
+    pub end: usize,
+    pub message: String,
+    pub suggestion: Option<String>,
+}
+
+impl ParseError {
+    fn new(start: usize, end: usize, message: impl Into<String>) -> Self {
+        Self {
+            start,
+            end: end.max(start + 1),
+            message: message.into(),
+            suggestion: None,
+        }
+    }
+
+    fn suggest(mut self, suggestion: impl Into<String>) -> Self {
+        self.suggestion = Some(suggestion.into());
+        self
+    }
+
+    // Renders the error like a compiler diagnostic:
+    //
+    //   error: unbalanced quote
+    //     "term frequency
+    //     ^
+    //   help: add a closing `"`
+    pub fn render(&self, query: &str) -> String {
+        let underline = format!(
+            "{}{}",
+            " ".repeat(self.start),
+            "^".repeat(self.end - self.start)
+        );
+        let mut out = format!("error: {}\n  {query}\n  {underline}", self.message);
+        if let Some(suggestion) = &self.suggestion {
+            out.push_str(&format!("\nhelp: {suggestion}"));
+        }
+        out
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.start)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Word(String),
+    Quoted(String),
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+}
+
+struct Token {
+    kind: TokenKind,
+    start: usize,
+    end: usize,
+}
+
+fn scan(query: &str) -> Result<Vec<Token>, ParseError> {
+    let chars = query.chars().collect::<Vec<_>>();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        let kind = match c {
+            _ if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => {
+                i += 1;
+                TokenKind::Open
+            }
+            ')' => {
+                i += 1;
+                TokenKind::Close
+            }
+            '-' if chars.get(i + 1).is_some_and(|n| !n.is_whitespace()) => {
+                i += 1;
+                TokenKind::Not
+            }
+            '"' => {
+                let mut text = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => {
+                            return Err(ParseError::new(start, start + 1, "unbalanced quote")
+                                .suggest(
+                                    "add a closing `\"` after the phrase, or write `\\\"` to search for a quote",
+                                ))
+                        }
+                        Some('"') => break,
+                        Some('\\') if i + 1 < chars.len() => {
+                            text.push(chars[i + 1]);
+                            i += 2;
+                        }
+                        Some(&c) => {
+                            text.push(c);
+                            i += 1;
+                        }
+                    }
+                }
+                i += 1;
+                TokenKind::Quoted(text)
+            }
+            _ => {
+                while i < chars.len() && !chars[i].is_whitespace() && !"()\"".contains(chars[i]) {
+                    if chars[i] == '\\' {
+                        if i + 1 == chars.len() {
+                            return Err(ParseError::new(
+                                i,
+                                i + 1,
+                                "backslash at the end of the query",
+                            )
+                            .suggest("remove it, or write `\\\\` to search for a backslash"));
+                        }
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                // Kept as written: the escapes still tell which characters
+                // of the word are literal.
+                let word = chars[start..i].iter().collect::<String>();
+                match word.as_str() {
+                    "AND" => TokenKind::And,
+                    "OR" => TokenKind::Or,
+                    "NOT" => TokenKind::Not,
+                    _ => TokenKind::Word(word),
+                }
+            }
+        };
+        tokens.push(Token {
+            kind,
+            start,
+            end: i,
+        });
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    analyzer: &'a Analyzer,
+    tokens: Vec<Token>,
+    pos: usize,
+    len: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        token
+    }
+
+    fn starts_operand(&self) -> bool {
+        matches!(
+            self.peek().map(|t| &t.kind),
+            Some(TokenKind::Word(_) | TokenKind::Quoted(_) | TokenKind::Open | TokenKind::Not)
+        )
+    }
+
+    // or := and (("OR")? and)*
+    fn parse_or(&mut self) -> Result<Query, ParseError> {
+        let mut operands = vec![self.parse_and()?];
+        loop {
+            match self.peek().map(|t| t.kind.clone()) {
+                Some(TokenKind::Or) => {
+                    let or = self.next().map(|t| (t.start, t.end)).unwrap();
+                    if !self.starts_operand() {
+                        return Err(
+                            ParseError::new(or.0, or.1, "OR is missing its right operand")
+                                .suggest("add a term after OR, remove it or write `\\OR` to search for the word"),
+                        );
+                    }
+                }
+                _ if self.starts_operand() => {}
+                _ => break,
+            }
+            operands.push(self.parse_and()?);
+        }
+        operands.retain(|operand| !operand.is_empty());
+        Ok(match operands.len() {
+            0 => Query::Phrase(Vec::new()),
+            1 => operands.pop().unwrap(),
+            _ => Query::Or(operands),
+        })
+    }
+
+    // and := unary ("AND" unary)*
+    fn parse_and(&mut self) -> Result<Query, ParseError> {
+        let mut operands = vec![self.parse_unary()?];
+        while let Some(TokenKind::And) = self.peek().map(|t| &t.kind) {
+            let and = self.next().map(|t| (t.start, t.end)).unwrap();
+            if !self.starts_operand() {
+                return Err(
+                    ParseError::new(and.0, and.1, "AND is missing its right operand").suggest(
+                        "add a term after AND, remove it or write `\\AND` to search for the word",
+                    ),
+                );
+            }
+            operands.push(self.parse_unary()?);
+        }
+        operands.retain(|operand| !operand.is_empty());
+        Ok(match operands.len() {
+            0 => Query::Phrase(Vec::new()),
+            1 => operands.pop().unwrap(),
+            _ => Query::And(operands),
+        })
+    }
+
+    fn parse_unary(&mut self) -> Result<Query, ParseError> {
+        if let Some(TokenKind::Not) = self.peek().map(|t| &t.kind) {
+            let not = self.next().map(|t| (t.start, t.end)).unwrap();
+            if !self.starts_operand() {
+                return Err(ParseError::new(not.0, not.1, "NOT is missing its operand")
+                    .suggest("add the term to exclude after NOT"));
+            }
+            let inner = self.parse_unary()?;
+            if inner.is_empty() {
+                return Ok(inner);
+            }
+            return Ok(Query::Not(Box::new(inner)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Query, ParseError> {
+        let len = self.len;
+        let Some(token) = self.next() else {
+            return Err(ParseError::new(len, len + 1, "unexpected end of query")
+                .suggest("finish the query with a term"));
+        };
+        let (start, end) = (token.start, token.end);
+        match token.kind.clone() {
+            TokenKind::Word(word) => {
+                let chars = unescape(&word);
+                if chars
+                    .iter()
+                    .any(|&(c, escaped)| !escaped && "*?".contains(c))
+                {
+                    if chars.iter().any(|&(_, escaped)| escaped) {
+                        return Err(ParseError::new(
+                            start,
+                            end,
+                            "wildcards cannot have escaped characters",
+                        )
+                        .suggest("escape the `*` and `?` too to search for the word as written"));
+                    }
+                    return Ok(Query::Wildcard(self.analyzer.fold_pattern(&word)));
+                }
+                match fuzzy_word(&chars) {
+                    Some((word, distance)) => self.fuzzy(&word, distance, start, end),
+                    None => {
+                        let word = chars.iter().map(|&(c, _)| c).collect::<String>();
+                        Ok(words_query(self.analyzer.terms(&word)))
+                    }
+                }
+            }
+            TokenKind::Quoted(text) => {
+                if text.trim().is_empty() {
+                    return Err(ParseError::new(start, end, "empty phrase")
+                        .suggest("put some words between the quotes or remove them"));
+                }
+                Ok(Query::Phrase(self.analyzer.terms(&text)))
+            }
+            TokenKind::Open => {
+                if let Some(TokenKind::Close) = self.peek().map(|t| &t.kind) {
+                    let close = self.peek().map(|t| t.end).unwrap();
+                    return Err(ParseError::new(start, close, "empty parentheses")
+                        .suggest("put a query inside the parentheses or remove them"));
+                }
+                let inner = self.parse_or()?;
+                match self.next().map(|t| t.kind.clone()) {
+                    Some(TokenKind::Close) => Ok(inner),
+                    _ => Err(ParseError::new(start, end, "unbalanced parenthesis")
+                        .suggest("add a closing `)`")),
+                }
+            }
+            TokenKind::Close => Err(ParseError::new(start, end, "unexpected `)`").suggest(
+                "remove it, add a matching `(` before it or write `\\)` to search for it",
+            )),
+            TokenKind::And | TokenKind::Or => {
+                let operator = if token.kind == TokenKind::And {
+                    "AND"
+                } else {
+                    "OR"
+                };
+                Err(
+                    ParseError::new(start, end, "operator is missing its left operand").suggest(
+                        format!("add a term before the operator, or write `\\{operator}` to search for the word"),
+                    ),
+                )
+            }
+            TokenKind::Not => unreachable!("NOT is handled by parse_unary"),
+        }
+    }
+}
+
+impl Parser<'_> {
+    fn fuzzy(
+        &self,
+        word: &str,
+        distance: Option<u32>,
+        start: usize,
+        end: usize,
+    ) -> Result<Query, ParseError> {
+        if distance.is_some_and(|distance| distance > MAX_EDIT_DISTANCE) {
+            return Err(ParseError::new(start, end, "edit distance is too large")
+                .suggest(format!("allow at most {MAX_EDIT_DISTANCE} edits")));
+        }
+        let mut terms = self.analyzer.terms(word);
+        match terms.len() {
+            0 => Ok(Query::Phrase(Vec::new())),
+            1 => Ok(Query::Fuzzy(terms.pop().unwrap(), distance)),
+            _ => Err(
+                ParseError::new(start, end, "only single words can be fuzzy")
+                    .suggest("put `~` after each word that may have typos"),
+            ),
+        }
+    }
+}
+
+// The characters of a word as written, each with whether it was escaped.
+fn unescape(word: &str) -> Vec<(char, bool)> {
+    let mut chars = Vec::with_capacity(word.len());
+    let mut escaped = false;
+    for c in word.chars() {
+        if c == '\\' && !escaped {
+            escaped = true;
+        } else {
+            chars.push((c, escaped));
+            escaped = false;
+        }
+    }
+    chars
+}
+
+// `word~` and `word~2`: the word and how many edits it may be off by, if
+// that is given. An escaped `~` is part of the word.
+fn fuzzy_word(chars: &[(char, bool)]) -> Option<(String, Option<u32>)> {
+    let tilde = chars
+        .iter()
+        .rposition(|&(c, escaped)| c == '~' && !escaped)?;
+    if tilde == 0 {
+        return None;
+    }
+    let word = chars[..tilde].iter().map(|&(c, _)| c).collect();
+    let distance = chars[tilde + 1..]
+        .iter()
+        .map(|&(c, _)| c)
+        .collect::<String>();
+    if distance.is_empty() {
+        return Some((word, None));
+    }
+    Some((word, Some(distance.parse().ok()?)))
+}
+
+// A single query word can still be several index terms (`tf_index`), which
+// have to appear together, or none at all when it is a stopword.
+// Whether the words occur one right after the other somewhere in the
+// document.
+fn phrase_occurs(words: &[String], positions: &Positions) -> bool {
+    let Some((first, rest)) = words.split_first() else {
+        return true;
+    };
+    let (Some(starts), Some(lists)) = (
+        positions.get(first),
+        rest.iter()
+            .map(|word| positions.get(word))
+            .collect::<Option<Vec<_>>>(),
+    ) else {
+        return false;
+    };
+    starts.iter().any(|&start| {
+        lists
+            .iter()
+            .zip(start + 1..)
+            .all(|(list, at)| list.binary_search(&at).is_ok())
+    })
+}
+
+fn words_query(mut terms: Vec<String>) -> Query {
+    if terms.len() == 1 {
+        Query::Term(terms.pop().unwrap())
+    } else {
+        Query::Phrase(terms)
+    }
+}
+
+// Query words go through the same analyzer as the indexed text.
+pub fn parse(query: &str, analyzer: &Analyzer) -> Result<Query, ParseError> {
+    let tokens = scan(query)?;
+    let len = query.chars().count();
+    if tokens.is_empty() {
+        return Err(ParseError::new(0, 1, "empty query"));
+    }
+    let mut parser = Parser {
+        analyzer,
+        tokens,
+        pos: 0,
+        len,
+    };
+    let query = parser.parse_or()?;
+    if let Some(token) = parser.peek() {
+        let message = match token.kind {
+            TokenKind::Close => "unexpected `)`",
+            _ => "unexpected input",
+        };
+        return Err(ParseError::new(token.start, token.end, message)
+            .suggest("remove it or add a matching `(` before it"));
+    }
+    if query.is_empty() {
+        return Err(ParseError::new(0, len, "the query has no searchable terms")
+            .suggest("stopwords are not indexed, add a more specific word"));
+    }
+    Ok(query)
+}
+
+impl Query {
+    // Words that analyze to no terms at all, like stopwords, leave an empty
+    // phrase behind, which the operators drop.
+    fn is_empty(&self) -> bool {
+        matches!(self, Query::Phrase(words) if words.is_empty())
+    }
+
+    // Terms that contribute to the score: everything not under a NOT.
+    pub fn positive_terms(&self) -> Vec<&str> {
+        self.weighted_terms()
+            .into_iter()
+            .map(|(term, _)| term)
+            .collect()
+    }
+
+    // `positive_terms` with the share of its score each term counts with:
+    // all of it, or less for a term matched with typos.
+    pub fn weighted_terms(&self) -> Vec<(&str, f32)> {
+        let mut terms = Vec::new();
+        self.collect_terms_with(&mut terms, false);
+        terms
+    }
+
+    // Every term the query looks at, including the excluded ones.
+    pub fn all_terms(&self) -> Vec<&str> {
+        let mut terms = Vec::new();
+        self.collect_terms_with(&mut terms, true);
+        terms.into_iter().map(|(term, _)| term).collect()
+    }
+
+    fn collect_terms_with<'a>(&'a self, terms: &mut Vec<(&'a str, f32)>, negated: bool) {
+        match self {
+            Query::Wildcard(_) | Query::Fuzzy(..) => {}
+            Query::Term(term) => terms.push((term, 1.0)),
+            Query::Typo(term) => terms.push((term, 1.0 - TYPO_PENALTY)),
+            Query::Related(term) => terms.push((term, FEEDBACK_WEIGHT)),
+            Query::Phrase(words) => terms.extend(words.iter().map(|w| (w.as_str(), 1.0))),
+            Query::And(operands) | Query::Or(operands) => {
+                for operand in operands {
+                    operand.collect_terms_with(terms, negated);
+                }
+            }
+            Query::Not(inner) if negated => inner.collect_terms_with(terms, negated),
+            Query::Not(_) => {}
+        }
+    }
+
+    // Terms every matching document contains, so candidates can be found by
+    // intersecting their postings. Alternatives and exclusions require none.
+    pub fn required_terms(&self) -> Vec<&str> {
+        match self {
+            Query::Term(term) | Query::Typo(term) | Query::Related(term) => vec![term],
+            Query::Phrase(words) => words.iter().map(|w| w.as_str()).collect(),
+            Query::And(operands) => operands.iter().flat_map(Query::required_terms).collect(),
+            Query::Or(_) | Query::Not(_) | Query::Wildcard(_) | Query::Fuzzy(..) => Vec::new(),
+        }
+    }
+
+    // Whether a document with the given terms satisfies the query. Without
+    // positions a phrase matches when all of its words occur.
+    pub fn matches(&self, has_term: &impl Fn(&str) -> bool) -> bool {
+        self.matches_phrases(has_term, &|words| words.iter().all(|word| has_term(word)))
+    }
+
+    // Whether the document satisfies the query, its phrases only where their
+    // words follow each other if the index recorded positions.
+    pub fn matches_doc(&self, doc: &Doc) -> bool {
+        let has_term = |term: &str| doc.tf.contains_key(term);
+        if doc.positions.is_empty() {
+            return self.matches(&has_term);
+        }
+        self.matches_phrases(&has_term, &|words| phrase_occurs(words, &doc.positions))
+    }
+
+    fn matches_phrases(
+        &self,
+        has_term: &impl Fn(&str) -> bool,
+        has_phrase: &impl Fn(&[String]) -> bool,
+    ) -> bool {
+        match self {
+            Query::Term(term) | Query::Typo(term) | Query::Related(term) => has_term(term),
+            Query::Phrase(words) => has_phrase(words),
+            Query::And(operands) => operands
+                .iter()
+                .all(|q| q.matches_phrases(has_term, has_phrase)),
+            Query::Or(operands) => operands
+                .iter()
+                .any(|q| q.matches_phrases(has_term, has_phrase)),
+            Query::Not(inner) => !inner.matches_phrases(has_term, has_phrase),
+            // Patterns only match through the terms `expand` replaces them with.
+            Query::Wildcard(_) | Query::Fuzzy(..) => false,
+        }
+    }
+
+    // Replaces wildcards and fuzzy terms with the alternatives of the index
+    // terms they match, or fails when the query grows past the limits.
+    pub fn expand(self, stats: &CorpusStats, limits: &QueryLimits) -> Result<Query, String> {
+        let mut expansions = 0;
+        let query = self.expand_patterns(stats, limits, &mut expansions)?;
+        let clauses = query.all_terms().len();
+        if clauses > limits.max_clauses {
+            return Err(format!(
+                "the query has {clauses} terms once expanded, more than the limit of {}",
+                limits.max_clauses
+            ));
+        }
+        Ok(query)
+    }
+
+    fn expand_patterns(
+        self,
+        stats: &CorpusStats,
+        limits: &QueryLimits,
+        expansions: &mut usize,
+    ) -> Result<Query, String> {
+        let mut expand_all = |operands: Vec<Query>| {
+            operands
+                .into_iter()
+                .map(|operand| operand.expand_patterns(stats, limits, expansions))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        match self {
+            Query::Term(term) if limits.fuzzy => {
+                Query::Fuzzy(term, None).expand_patterns(stats, limits, expansions)
+            }
+            Query::Term(_) | Query::Typo(_) | Query::Related(_) | Query::Phrase(_) => Ok(self),
+            Query::And(operands) => Ok(Query::And(expand_all(operands)?)),
+            Query::Or(operands) => Ok(Query::Or(expand_all(operands)?)),
+            // Excluding the words with typos too would drop what was meant.
+            Query::Not(inner) => {
+                let limits = QueryLimits {
+                    fuzzy: false,
+                    ..*limits
+                };
+                Ok(Query::Not(Box::new(
+                    inner.expand_patterns(stats, &limits, expansions)?,
+                )))
+            }
+            Query::Wildcard(pattern) => expand_pattern(
+                &TermPattern::Wildcard(&pattern),
+                &pattern,
+                stats,
+                limits,
+                expansions,
+                Query::Term,
+            ),
+            Query::Fuzzy(term, distance) => {
+                let distance = distance.unwrap_or_else(|| limits.typos.distance(&term));
+                if distance == 0 {
+                    return Ok(Query::Term(term));
+                }
+                expand_pattern(
+                    &TermPattern::Fuzzy(&term, distance),
+                    &format!("{term}~{distance}"),
+                    stats,
+                    limits,
+                    expansions,
+                    |matched| {
+                        if matched == term {
+                            Query::Term(matched)
+                        } else {
+                            Query::Typo(matched)
+                        }
+                    },
+                )
+            }
+        }
+    }
+}
+
+fn expand_pattern(
+    pattern: &TermPattern,
+    text: &str,
+    stats: &CorpusStats,
+    limits: &QueryLimits,
+    expansions: &mut usize,
+    query: impl Fn(String) -> Query,
+) -> Result<Query, String> {
+    // One more than what is left tells whether the pattern overflows.
+    let left = limits.max_expansions - *expansions;
+    let mut terms = stats.expand(pattern, left + 1);
+    if terms.len() > left {
+        return Err(format!(
+            "{text} matches too many index terms, at most {} are allowed for the whole query",
+            limits.max_expansions
+        ));
+    }
+    *expansions += terms.len();
+    Ok(match terms.len() {
+        1 => query(terms.pop().unwrap()),
+        // No terms leave an empty alternative, which matches nothing.
+        _ => Query::Or(terms.into_iter().map(query).collect()),
+    })
+}
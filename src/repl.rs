@@ -0,0 +1,186 @@
+// The interactive search of `repl`: one query per line against an index
+// loaded once. Lines are edited like in a shell, and the queries of earlier
+// sessions are kept in a history file, searched backwards with Ctrl-R. A
+// query can be bookmarked under a name to run it again later; the bookmarks
+// are kept in a saved-search file, which `queries --import` stores in an
+// index for percolate to alert on the documents matching them.
+use std::env;
+use std::io;
+use std::path::PathBuf;
+
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::{Config, Editor};
+
+use tinysearch::handle::SearchHandle;
+use tinysearch::percolate::{self, StoredQueries};
+use tinysearch::Error;
+
+use crate::{search_and_print, SearchOptions};
+
+const MAX_HISTORY: usize = 1000;
+
+const HELP: &str = "\
+Every line is a query, in the syntax of the search subcommand. Ctrl-R searches the history.
+  :bookmark <name> [query]   bookmark the query, or the last one run
+  :run <name>                run a bookmarked query
+  :bookmarks                 list the bookmarks
+  :forget <name>             drop a bookmark
+  :export <file>             write the bookmarks to <file>, for queries --import
+  :help                      show this
+  :quit                      leave, as does Ctrl-D";
+
+// Where the history and the bookmarks are kept. Without a home directory
+// and without the flags naming them, they are only kept for the session.
+#[derive(Default)]
+pub struct ReplFiles {
+    pub history: Option<PathBuf>,
+    pub bookmarks: Option<PathBuf>,
+}
+
+impl ReplFiles {
+    // The files not named by flags go to the home directory.
+    pub fn or_home(self) -> Self {
+        let home = env::var_os("HOME")
+            .filter(|home| !home.is_empty())
+            .map(PathBuf::from);
+        let in_home = |name: &str| home.as_ref().map(|home| home.join(name));
+        Self {
+            history: self.history.or_else(|| in_home(".tinysearch_history")),
+            bookmarks: self
+                .bookmarks
+                .or_else(|| in_home(".tinysearch_bookmarks.json")),
+        }
+    }
+}
+
+pub fn run(handle: &SearchHandle, options: &SearchOptions, files: &ReplFiles) -> Result<(), Error> {
+    let config = Config::builder()
+        .max_history_size(MAX_HISTORY)
+        .and_then(|config| config.history_ignore_dups(true))
+        .map_err(editor_error)?
+        .auto_add_history(false)
+        .build();
+    let mut editor = Editor::<(), DefaultHistory>::with_config(config).map_err(editor_error)?;
+    if let Some(path) = files.history.as_ref().filter(|path| path.exists()) {
+        editor.load_history(path).map_err(|err| {
+            Error::io(
+                format!("could not read the history {}", path.display()),
+                io::Error::other(err),
+            )
+        })?;
+    }
+    let mut bookmarks = match files.bookmarks.as_ref().filter(|path| path.exists()) {
+        Some(path) => percolate::read_saved(path)?,
+        None => StoredQueries::new(),
+    };
+    if files.bookmarks.is_none() {
+        eprintln!("WARNING: there is no home directory, bookmarks are kept for this session only unless --bookmarks names a file");
+    }
+    let mut last_query = None::<String>;
+    loop {
+        let line = match editor.readline("> ") {
+            Ok(line) => line,
+            // Ctrl-C drops the line being typed, like in a shell.
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(editor_error(err)),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(line);
+        if let Some(path) = &files.history {
+            if let Err(err) = editor.save_history(path) {
+                eprintln!(
+                    "WARNING: could not write the history {}: {err}",
+                    path.display()
+                );
+            }
+        }
+        let Some(command) = line.strip_prefix(':') else {
+            // Errors in the query are printed, the session goes on.
+            let _ = search_and_print(handle, line, options);
+            last_query = Some(line.to_string());
+            continue;
+        };
+        let (command, rest) = command
+            .split_once(char::is_whitespace)
+            .map_or((command, ""), |(command, rest)| (command, rest.trim()));
+        match command {
+            "bookmark" => {
+                let (name, query) = rest
+                    .split_once(char::is_whitespace)
+                    .map_or((rest, None), |(name, query)| (name, Some(query.trim())));
+                let Some(query) = query.or(last_query.as_deref()) else {
+                    eprintln!("ERROR: there is no query to bookmark, give one after the name");
+                    continue;
+                };
+                if name.is_empty() {
+                    eprintln!("ERROR: bookmarks need a name, like :bookmark <name> [query]");
+                    continue;
+                }
+                let query = query.to_string();
+                bookmarks.insert(name.to_string(), query.clone());
+                if save_bookmarks(files, &bookmarks) {
+                    println!("Bookmarked {name}: {query}");
+                }
+            }
+            "run" => match bookmarks.get(rest) {
+                Some(query) => {
+                    println!("{query}");
+                    let _ = search_and_print(handle, query, options);
+                    last_query = Some(query.clone());
+                }
+                None => eprintln!("ERROR: there is no bookmark {rest:?}"),
+            },
+            "bookmarks" => {
+                if bookmarks.is_empty() {
+                    println!("No bookmarks yet, make one with :bookmark <name>");
+                }
+                for (name, query) in &bookmarks {
+                    println!("{name}: {query}");
+                }
+            }
+            "forget" => {
+                if bookmarks.remove(rest).is_none() {
+                    eprintln!("ERROR: there is no bookmark {rest:?}");
+                } else if save_bookmarks(files, &bookmarks) {
+                    println!("Forgot {rest}");
+                }
+            }
+            "export" => {
+                if rest.is_empty() {
+                    eprintln!("ERROR: the bookmarks are exported to a file, like :export <file>");
+                    continue;
+                }
+                match percolate::write_saved(rest.as_ref(), &bookmarks) {
+                    Ok(()) => println!(
+                        "Exported {count} bookmarks to {rest}, store them in an index with queries <index-file> --import {rest}",
+                        count = bookmarks.len()
+                    ),
+                    Err(err) => eprintln!("ERROR: {err}"),
+                }
+            }
+            "help" => println!("{HELP}"),
+            "quit" | "q" => break,
+            _ => eprintln!("ERROR: unknown command :{command}, :help lists them"),
+        }
+    }
+    Ok(())
+}
+
+// Whether the bookmarks were written to their file, if they have one.
+fn save_bookmarks(files: &ReplFiles, bookmarks: &StoredQueries) -> bool {
+    let Some(path) = &files.bookmarks else {
+        return true;
+    };
+    percolate::write_saved(path, bookmarks)
+        .map_err(|err| eprintln!("ERROR: {err}"))
+        .is_ok()
+}
+
+fn editor_error(err: ReadlineError) -> Error {
+    Error::io("could not read from the terminal", io::Error::other(err))
+}
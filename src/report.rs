@@ -0,0 +1,86 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::{DocKey, Error};
+
+#[derive(Default, Serialize)]
+pub struct ExtensionStats {
+    pub files: usize,
+    pub failed: usize,
+    pub chunks: usize,
+    pub bytes: u64,
+    pub extract_ms: u64,
+}
+
+#[derive(Serialize)]
+pub struct Failure {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+// Summary of an indexing run, written next to the index so problems with
+// large corpora can be diagnosed without digging through the log.
+#[derive(Default, Serialize)]
+pub struct IndexReport {
+    pub extensions: BTreeMap<String, ExtensionStats>,
+    pub failures: Vec<Failure>,
+    // Documents left out for going over `--max-tokens-per-doc`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<Skipped>,
+}
+
+#[derive(Serialize)]
+pub struct Skipped {
+    pub path: DocKey,
+    pub tokens: usize,
+}
+
+impl IndexReport {
+    pub fn record(
+        &mut self,
+        file_path: &Path,
+        bytes: u64,
+        elapsed: Duration,
+        result: Result<usize, String>,
+    ) {
+        let ext = file_path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase())
+            .unwrap_or_else(|| "(none)".to_string());
+        let stats = self.extensions.entry(ext).or_default();
+        stats.files += 1;
+        stats.bytes += bytes;
+        stats.extract_ms += elapsed.as_millis() as u64;
+        match result {
+            Ok(chunks) => stats.chunks += chunks,
+            Err(reason) => {
+                stats.failed += 1;
+                self.failures.push(Failure {
+                    path: file_path.to_path_buf(),
+                    reason,
+                });
+            }
+        }
+    }
+
+    pub fn record_skipped(&mut self, doc_path: DocKey, tokens: usize) {
+        self.skipped.push(Skipped {
+            path: doc_path,
+            tokens,
+        });
+    }
+
+    pub fn save(&self, report_path: &str) -> Result<(), Error> {
+        println!("Saving {report_path}...");
+        let report_file = File::create(report_path)
+            .map_err(|err| Error::io(format!("could not create report file {report_path}"), err))?;
+        serde_json::to_writer_pretty(report_file, self).map_err(|err| {
+            Error::json(format!("could not write to report file {report_path}"), err)
+        })?;
+        Ok(())
+    }
+}
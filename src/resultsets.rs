@@ -0,0 +1,132 @@
+// Result sets the server keeps for a while: the ranked matches of a search,
+// under an opaque token the search answers with. Sending the token as
+// `result_set` pages through the kept matches, so pages stay the same while
+// the index reloads; sending it as `within` narrows a new search to them
+// ("search within these results") rather than repeating the earlier query
+// and a growing list of filters. A set lives as long as it keeps being used
+// and only the most recently used are kept. The workers of the server share
+// them.
+use std::collections::hash_map::RandomState;
+use std::collections::{HashSet, VecDeque};
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tinysearch::handle::SearchResults;
+
+// How long a result set is kept after it was last used, by default.
+pub const DEFAULT_RESULT_SET_TTL: Duration = Duration::from_secs(10 * 60);
+// How many result sets are kept at most, by default.
+pub const DEFAULT_KEPT_RESULT_SETS: usize = 64;
+
+pub type ResultSet = Arc<SearchResults>;
+
+// The paths of a result set, for narrowing a search to them, as the text
+// documents are keyed by.
+pub fn paths(set: &ResultSet) -> HashSet<&str> {
+    set.iter().filter_map(|(path, _)| path.to_str()).collect()
+}
+
+struct Kept {
+    token: String,
+    results: ResultSet,
+    used: Instant,
+}
+
+// The sets themselves, behind the lock the workers of the server share.
+struct KeptSets {
+    next: u64,
+    // Least recently used first.
+    sets: VecDeque<Kept>,
+}
+
+impl KeptSets {
+    // Moves the set to the back as the most recently used.
+    fn used(&mut self, at: usize) -> &Kept {
+        let mut kept = self.sets.remove(at).unwrap();
+        kept.used = Instant::now();
+        self.sets.push_back(kept);
+        self.sets.back().unwrap()
+    }
+
+    fn expire(&mut self, ttl: Duration) {
+        let now = Instant::now();
+        self.sets.retain(|kept| now.duration_since(kept.used) < ttl);
+    }
+}
+
+pub struct ResultSets {
+    ttl: Duration,
+    capacity: usize,
+    // Seeded differently by every server run, so tokens cannot be guessed
+    // from one another.
+    keys: RandomState,
+    kept: Mutex<KeptSets>,
+    // No new sets are kept while the server is short of memory.
+    paused: AtomicBool,
+}
+
+impl ResultSets {
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ttl,
+            capacity: capacity.max(1),
+            keys: RandomState::new(),
+            kept: Mutex::new(KeptSets {
+                next: 0,
+                sets: VecDeque::new(),
+            }),
+            paused: AtomicBool::new(false),
+        }
+    }
+
+    // Drops every kept set.
+    pub fn clear(&self) {
+        self.kept.lock().unwrap().sets.clear();
+    }
+
+    pub fn pause(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    // Keeps the ranked matches of a search and returns the token to refer to
+    // them. Searching again for the same matches, e.g. for the next page,
+    // gives the token they already have. Nothing is kept while paused.
+    pub fn keep(&self, results: ResultSet) -> Option<String> {
+        if self.paused.load(Ordering::Relaxed) {
+            return None;
+        }
+        let mut kept = self.kept.lock().unwrap();
+        kept.expire(self.ttl);
+        let at = kept
+            .sets
+            .iter()
+            .position(|kept| Arc::ptr_eq(&kept.results, &results) || kept.results == results);
+        if let Some(at) = at {
+            return Some(kept.used(at).token.clone());
+        }
+        if kept.sets.len() == self.capacity {
+            kept.sets.pop_front();
+        }
+        kept.next += 1;
+        let mut hasher = self.keys.build_hasher();
+        hasher.write_u64(kept.next);
+        let token = format!("{:016x}{:x}", hasher.finish(), kept.next);
+        kept.sets.push_back(Kept {
+            token: token.clone(),
+            results,
+            used: Instant::now(),
+        });
+        Some(token)
+    }
+
+    // The ranked matches kept under the token, unless they expired or were
+    // never kept.
+    pub fn get(&self, token: &str) -> Option<ResultSet> {
+        let mut kept = self.kept.lock().unwrap();
+        kept.expire(self.ttl);
+        let at = kept.sets.iter().position(|kept| kept.token == token)?;
+        Some(kept.used(at).results.clone())
+    }
+}
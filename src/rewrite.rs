@@ -0,0 +1,210 @@
+// Rewrites of queries before they are parsed, declared in a JSON file, so a
+// deployment can map the shorthand of its users onto the query language,
+// e.g. ticket IDs onto a field:
+//
+//   [{"pattern": "PROJ-{id}", "rewrite": "ticket:PROJ-{id}"}]
+//
+// A rule applies to every word of the query it matches whole, a word being
+// what lies between spaces, parentheses and quotes, but not to the words of
+// quoted phrases. `{name}` in a pattern matches one or more characters of the
+// word, as few as let the rest of the pattern match, and stands for them in
+// the rewrite; the rest of the pattern has to be in the word as written. The
+// first rule matching a word rewrites it, and what rules wrote is not
+// rewritten again. Words with backslash escapes are meant literally and are
+// left alone. The search handle applies the rewrites, so searches at the
+// command line and of the server agree.
+use std::borrow::Cow;
+use std::fs;
+
+use serde::Deserialize;
+
+use crate::Error;
+
+#[derive(Deserialize)]
+struct RuleSpec {
+    pattern: String,
+    rewrite: String,
+}
+
+#[derive(Debug, PartialEq)]
+enum Part {
+    Literal(String),
+    Capture(String),
+}
+
+#[derive(Debug)]
+struct Rule {
+    pattern: Vec<Part>,
+    rewrite: Vec<Part>,
+}
+
+#[derive(Debug, Default)]
+pub struct QueryRewriter {
+    rules: Vec<Rule>,
+}
+
+fn is_word_char(c: char) -> bool {
+    !c.is_whitespace() && !matches!(c, '(' | ')' | '"')
+}
+
+// The length in bytes of the word `text` starts with. A character after a
+// backslash belongs to it, whatever it is.
+fn word_len(text: &str) -> usize {
+    let mut chars = text.char_indices();
+    while let Some((at, c)) = chars.next() {
+        if c == '\\' {
+            chars.next();
+        } else if !is_word_char(c) {
+            return at;
+        }
+    }
+    text.len()
+}
+
+// The literals and `{name}` captures of a pattern or rewrite.
+fn parts(text: &str) -> Result<Vec<Part>, String> {
+    let mut parts = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            parts.push(Part::Literal(rest[..start].to_string()));
+        }
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("unclosed {{ in {text}"))?;
+        let name = &rest[start + 1..start + end];
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Err(format!("invalid capture name {{{name}}} in {text}"));
+        }
+        parts.push(Part::Capture(name.to_string()));
+        rest = &rest[start + end + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Literal(rest.to_string()));
+    }
+    Ok(parts)
+}
+
+impl Rule {
+    fn parse(spec: RuleSpec) -> Result<Self, String> {
+        let pattern = parts(&spec.pattern)?;
+        if pattern.is_empty() {
+            return Err("empty pattern".to_string());
+        }
+        if pattern
+            .windows(2)
+            .any(|pair| matches!(pair, [Part::Capture(_), Part::Capture(_)]))
+        {
+            return Err(format!(
+                "captures in {pattern} need text between them",
+                pattern = spec.pattern
+            ));
+        }
+        let rewrite = parts(&spec.rewrite)?;
+        for part in &rewrite {
+            if let Part::Capture(name) = part {
+                if !pattern.contains(part) {
+                    return Err(format!(
+                        "{{{name}}} of rewrite {rewrite} is not in pattern {pattern}",
+                        rewrite = spec.rewrite,
+                        pattern = spec.pattern
+                    ));
+                }
+            }
+        }
+        Ok(Self { pattern, rewrite })
+    }
+
+    fn apply(&self, word: &str) -> Option<String> {
+        let mut captures = Vec::new();
+        if !match_parts(&self.pattern, word, &mut captures) {
+            return None;
+        }
+        let mut rewritten = String::new();
+        for part in &self.rewrite {
+            match part {
+                Part::Literal(text) => rewritten.push_str(text),
+                Part::Capture(name) => {
+                    let (_, value) = captures.iter().find(|(capture, _)| capture == name)?;
+                    rewritten.push_str(value);
+                }
+            }
+        }
+        Some(rewritten)
+    }
+}
+
+// Whether the parts match all of `text`, with what the captures matched.
+fn match_parts<'a>(
+    parts: &'a [Part],
+    text: &'a str,
+    captures: &mut Vec<(&'a str, &'a str)>,
+) -> bool {
+    match parts.split_first() {
+        None => text.is_empty(),
+        Some((Part::Literal(literal), rest)) => text
+            .strip_prefix(literal.as_str())
+            .is_some_and(|text| match_parts(rest, text, captures)),
+        Some((Part::Capture(name), rest)) => {
+            // As few characters as let the rest match, at least one.
+            for (end, c) in text.char_indices() {
+                let end = end + c.len_utf8();
+                captures.push((name, &text[..end]));
+                if match_parts(rest, &text[end..], captures) {
+                    return true;
+                }
+                captures.pop();
+            }
+            false
+        }
+    }
+}
+
+impl QueryRewriter {
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let json = fs::read_to_string(path)
+            .map_err(|err| Error::io(format!("could not read query rewrites {path}"), err))?;
+        let specs: Vec<RuleSpec> = serde_json::from_str(&json)
+            .map_err(|err| Error::json(format!("could not parse query rewrites {path}"), err))?;
+        let rules = specs
+            .into_iter()
+            .map(Rule::parse)
+            .collect::<Result<_, _>>()
+            .map_err(|err| Error::invalid(format!("invalid query rewrite in {path}: {err}")))?;
+        Ok(Self { rules })
+    }
+
+    pub fn rewrite<'a>(&self, query: &'a str) -> Cow<'a, str> {
+        if self.rules.is_empty() {
+            return Cow::Borrowed(query);
+        }
+        let mut rewritten = String::with_capacity(query.len());
+        let mut changed = false;
+        let mut rest = query;
+        let mut in_phrase = false;
+        while let Some(start) = rest.find(is_word_char) {
+            let gap = &rest[..start];
+            in_phrase ^= gap.matches('"').count() % 2 == 1;
+            rewritten.push_str(gap);
+            rest = &rest[start..];
+            let (word, after) = rest.split_at(word_len(rest));
+            rest = after;
+            let replacement = (!in_phrase && !word.contains('\\'))
+                .then(|| self.rules.iter().find_map(|rule| rule.apply(word)))
+                .flatten();
+            match replacement {
+                Some(replacement) => {
+                    rewritten.push_str(&replacement);
+                    changed = true;
+                }
+                None => rewritten.push_str(word),
+            }
+        }
+        rewritten.push_str(rest);
+        if changed {
+            Cow::Owned(rewritten)
+        } else {
+            Cow::Borrowed(query)
+        }
+    }
+}
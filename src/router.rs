@@ -0,0 +1,175 @@
+// Where the requests of serve go. The JSON API is under /api/v<n>/, so that
+// its contract can change in a later version while frontends built against
+// an earlier one keep working. Paths without a version, like /api/search,
+// are what the API was before it had versions: they stay as aliases of the
+// oldest version, or of the one a client asks for with an API-Version
+// header, and their answers carry a Deprecation header, a Sunset header
+// telling when they go away and a Link to the path to use instead. A version
+// in the path wins over the header. Every answer of the API tells the
+// version it was given in by its API-Version header.
+use tiny_http::{Header, Method, Request};
+
+use crate::http;
+
+// The versions of the API served, oldest first.
+pub const API_VERSIONS: &[u32] = &[1];
+
+// When the paths without a version were deprecated, as the Unix time the
+// Deprecation header takes, and the HTTP date they go away.
+const UNVERSIONED_DEPRECATED: &str = "@1792108800";
+const UNVERSIONED_SUNSET: &str = "Fri, 16 Apr 2027 00:00:00 GMT";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Route {
+    // The API.
+    Search,
+    SearchParams,
+    Reload,
+    Documents,
+    Feedback,
+    Changes,
+    HealthHistory,
+    Doc,
+    Docs,
+    Percolate,
+    Thumb,
+    Complete,
+    Facets,
+    Aggregate,
+    // The frontend and the rest.
+    IndexPage,
+    Script,
+    Stylesheet,
+    Robots,
+    SearchPage,
+    #[cfg(feature = "metrics")]
+    Metrics,
+    Preflight,
+    NotFound,
+}
+
+// The routes of the API, by their path under /api/v<n>/. Once a version
+// changes a route, the table of the older one keeps the old route.
+const API_ROUTES: &[(Method, &str, Route)] = &[
+    (Method::Post, "search", Route::Search),
+    // The same search with the parameters of GET /search, for clients that
+    // cannot send a body, like a curl one-liner.
+    (Method::Get, "search", Route::SearchParams),
+    (Method::Post, "reload", Route::Reload),
+    (Method::Post, "documents", Route::Documents),
+    (Method::Delete, "documents", Route::Documents),
+    (Method::Post, "feedback", Route::Feedback),
+    (Method::Get, "changes", Route::Changes),
+    (Method::Get, "health/history", Route::HealthHistory),
+    (Method::Get, "doc", Route::Doc),
+    (Method::Post, "docs", Route::Docs),
+    (Method::Post, "percolate", Route::Percolate),
+    (Method::Get, "thumb", Route::Thumb),
+    (Method::Get, "complete", Route::Complete),
+    (Method::Get, "facets", Route::Facets),
+    (Method::Get, "aggregate", Route::Aggregate),
+];
+
+const PAGE_ROUTES: &[(Method, &str, Route)] = &[
+    (Method::Get, "/", Route::IndexPage),
+    (Method::Get, "/index.html", Route::IndexPage),
+    (Method::Get, "/index.js", Route::Script),
+    (Method::Get, "/style.css", Route::Stylesheet),
+    (Method::Get, "/robots.txt", Route::Robots),
+    (Method::Get, "/search", Route::SearchPage),
+    #[cfg(feature = "metrics")]
+    (Method::Get, "/metrics", Route::Metrics),
+];
+
+// A request to the API: the version it is given in and its path under the
+// version.
+struct ApiRequest<'a> {
+    version: u32,
+    path: &'a str,
+    // Whether it came by a path without a version.
+    unversioned: bool,
+}
+
+// None for requests outside of the API, an error for a version that is not
+// served. `asked` is the API-Version header.
+fn api_request<'a>(path: &'a str, asked: Option<&str>) -> Option<Result<ApiRequest<'a>, String>> {
+    let rest = path.strip_prefix("/api/")?;
+    let versioned = rest.split_once('/').and_then(|(segment, path)| {
+        let version = segment.strip_prefix('v')?;
+        version
+            .bytes()
+            .all(|b| b.is_ascii_digit())
+            .then(|| (version.parse::<u32>().unwrap_or(u32::MAX), path))
+    });
+    let (version, path, unversioned) = match (versioned, asked) {
+        (Some((version, path)), _) => (version, path, false),
+        (None, Some(asked)) => {
+            let asked = asked.trim();
+            match asked.strip_prefix('v').unwrap_or(asked).parse() {
+                Ok(version) => (version, rest, true),
+                Err(_) => {
+                    return Some(Err(format!(
+                        "API-Version {asked:?} is not a version, expected a number like 1"
+                    )))
+                }
+            }
+        }
+        (None, None) => (API_VERSIONS[0], rest, true),
+    };
+    if !API_VERSIONS.contains(&version) {
+        let served = API_VERSIONS
+            .iter()
+            .map(|version| version.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        return Some(Err(format!(
+            "there is no version {version} of the API, this server has {served}"
+        )));
+    }
+    Some(Ok(ApiRequest {
+        version,
+        path,
+        unversioned,
+    }))
+}
+
+// The route of a request to `path`, or why the version of the API it asks
+// for is not served. `asked` is the API-Version header.
+pub fn route(method: &Method, path: &str, asked: Option<&str>) -> Result<Route, String> {
+    // Browsers send a preflight before the request itself, whatever its
+    // version.
+    if *method == Method::Options {
+        return Ok(Route::Preflight);
+    }
+    let (routes, path) = match api_request(path, asked) {
+        Some(api) => (API_ROUTES, api?.path),
+        None => (PAGE_ROUTES, path),
+    };
+    Ok(routes
+        .iter()
+        .find(|(known_method, known_path, _)| known_method == method && *known_path == path)
+        .map_or(Route::NotFound, |(_, _, route)| *route))
+}
+
+// The headers telling the client of an API request the version it was
+// answered in and, for paths without a version, that they are deprecated.
+pub fn version_headers(request: &Request) -> Vec<Header> {
+    let (path, _) = http::split_url(request.url());
+    let Some(Ok(api)) = api_request(path, http::header(request, "API-Version")) else {
+        return Vec::new();
+    };
+    let mut headers = vec![Header::from_bytes("API-Version", api.version.to_string()).unwrap()];
+    if api.unversioned {
+        let successor = format!(
+            "</api/v{version}/{path}>; rel=\"successor-version\"",
+            version = api.version,
+            path = api.path
+        );
+        headers.extend([
+            Header::from_bytes("Deprecation", UNVERSIONED_DEPRECATED).unwrap(),
+            Header::from_bytes("Sunset", UNVERSIONED_SUNSET).unwrap(),
+            Header::from_bytes("Link", successor).unwrap(),
+        ]);
+    }
+    headers
+}
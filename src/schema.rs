@@ -0,0 +1,190 @@
+// Types of metadata fields, declared in the index configuration:
+//
+//   {"fields": {"title": "text", "tags": "keyword", "pages": "numeric",
+//               "published": "date", "draft": "bool"}}
+//
+// The type decides how the values of a field are stored and filtered. Text
+// fields are analyzed like the body, so searches also find their words, and
+// filters match the words they contain. Keyword fields are kept as written
+// and match exactly. Numeric, date and bool fields are stored in one form,
+// `1.5`, RFC 3339 and `true`, so that filters compare them as numbers, dates
+// and truth values; values that are not of the type are dropped. Fields
+// without a type are compared as strings, ignoring case.
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::analyzer::{fold_case, Analyzer};
+use crate::{index_document, locale, Doc, Error, MetaValue};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    Text,
+    Keyword,
+    Numeric,
+    Date,
+    Bool,
+}
+
+pub type Schema = BTreeMap<String, FieldType>;
+
+impl FieldType {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(Self::Text),
+            "keyword" => Some(Self::Keyword),
+            "numeric" => Some(Self::Numeric),
+            "date" => Some(Self::Date),
+            "bool" => Some(Self::Bool),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Text => "text",
+            Self::Keyword => "keyword",
+            Self::Numeric => "numeric",
+            Self::Date => "date",
+            Self::Bool => "bool",
+        }
+    }
+
+    // The stored form of a value, if it is one of the type.
+    pub fn normalize(self, value: &str) -> Option<String> {
+        let value = value.trim();
+        match self {
+            Self::Text | Self::Keyword => Some(value.to_string()),
+            Self::Numeric => number(value).map(|number| number.to_string()),
+            Self::Date => date(value),
+            Self::Bool => truth(value).map(|truth| truth.to_string()),
+        }
+    }
+}
+
+// A `--field` value: the name of a field and its type, `tags:keyword`.
+pub fn parse_field(value: &str) -> Result<(String, FieldType), Error> {
+    let Some((name, type_name)) = value.rsplit_once(':').filter(|(name, _)| !name.is_empty())
+    else {
+        return Err(Error::invalid(format!(
+            "field {value} must look like name:type, e.g. tags:keyword"
+        )));
+    };
+    let field = FieldType::from_name(type_name).ok_or_else(|| {
+        Error::invalid(format!(
+            "unknown field type {type_name}, expected text, keyword, numeric, date or bool"
+        ))
+    })?;
+    Ok((name.to_string(), field))
+}
+
+fn number(value: &str) -> Option<f64> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|number| number.is_finite())
+}
+
+fn truth(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+// RFC 3339 from the forms dates come in: RFC 3339 itself, `2024-05-17`,
+// `2024/05/17 10:00:00` and seconds since the Unix epoch.
+fn date(value: &str) -> Option<String> {
+    if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) && value.len() > 8 {
+        return Some(locale::rfc3339_from_unix(value.parse().ok()?));
+    }
+    let bytes = value.as_bytes();
+    let digits = |range: std::ops::Range<usize>| bytes[range].iter().all(u8::is_ascii_digit);
+    let separated = bytes.len() >= 10
+        && matches!(bytes[4], b'-' | b'/')
+        && bytes[7] == bytes[4]
+        && digits(0..4)
+        && digits(5..7)
+        && digits(8..10);
+    if !separated {
+        return None;
+    }
+    let day = value[..10].replace('/', "-");
+    match value[10..].trim_start_matches(['T', ' ']) {
+        "" => Some(day),
+        time => Some(format!("{day}T{time}")),
+    }
+}
+
+// The words of a text field, compared case-insensitively.
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut word = word.to_string();
+            fold_case(&mut word);
+            word
+        })
+        .collect()
+}
+
+// Whether a stored value is the one a filter asks for: dates match by
+// prefix, so `2024-05` is any day of that month, and text fields when they
+// contain its words.
+pub fn equals(field: Option<FieldType>, value: &str, wanted: &str) -> bool {
+    match field {
+        None => value.eq_ignore_ascii_case(wanted),
+        Some(FieldType::Keyword) => value == wanted,
+        Some(FieldType::Text) => {
+            let contained = words(value);
+            words(wanted).iter().all(|word| contained.contains(word))
+        }
+        Some(FieldType::Numeric) => number(value).is_some_and(|n| Some(n) == number(wanted)),
+        Some(FieldType::Date) => value.starts_with(wanted.trim()),
+        Some(FieldType::Bool) => truth(value).is_some_and(|t| Some(t) == truth(wanted)),
+    }
+}
+
+// How a stored value orders against the bound of a `>=` or `<=` filter.
+pub fn compare(field: Option<FieldType>, value: &str, bound: &str) -> Option<Ordering> {
+    match field {
+        Some(FieldType::Numeric) => number(value)?.partial_cmp(&number(bound)?),
+        Some(FieldType::Date) => Some(value.cmp(&date(bound).unwrap_or_else(|| bound.to_string()))),
+        _ => Some(value.cmp(bound)),
+    }
+}
+
+// Stores the values of the typed fields of a document in the form of their
+// type and adds the terms of its text fields to those of the document.
+pub fn index_fields(schema: &Schema, analyzer: &Analyzer, doc: &mut Doc) {
+    for (key, field) in schema {
+        let Some(value) = doc.meta.get(key) else {
+            continue;
+        };
+        let mut values = value
+            .values()
+            .iter()
+            .filter_map(|value| field.normalize(value))
+            .collect::<Vec<_>>();
+        if values.is_empty() {
+            doc.meta.remove(key);
+            continue;
+        }
+        if *field == FieldType::Text {
+            for value in &values {
+                for (term, count) in index_document(analyzer, value) {
+                    *doc.tf.entry(term).or_insert(0) += count;
+                }
+            }
+        }
+        let value = if value.is_many() {
+            MetaValue::Many(values)
+        } else {
+            MetaValue::One(values.remove(0))
+        };
+        doc.meta.insert(key.clone(), value);
+    }
+}
@@ -0,0 +1,326 @@
+// How matching documents are ranked. The collection statistics ranking needs,
+// the length of every document and the number of documents of every term,
+// are computed once per index rather than on every query, from its inverted
+// index.
+use std::sync::OnceLock;
+
+use fst::Map;
+
+use crate::inverted::InvertedIndex;
+use crate::postings::{self, TermPattern};
+use crate::{Model, Positions};
+
+pub trait Scorer {
+    // Weight of a query term that occurs `count` times in a document of
+    // `doc_len` tokens.
+    fn score(&self, count: usize, doc_len: usize, idf: f32) -> f32;
+
+    // How much a term that appears in `doc_freq` of the `docs` documents
+    // tells them apart, computed once per query term.
+    fn idf(&self, docs: usize, doc_freq: usize) -> f32 {
+        idf(docs, doc_freq)
+    }
+}
+
+pub struct TfIdf;
+
+impl Scorer for TfIdf {
+    fn score(&self, count: usize, doc_len: usize, idf: f32) -> f32 {
+        if doc_len == 0 {
+            0.0
+        } else {
+            count as f32 / doc_len as f32 * idf
+        }
+    }
+}
+
+// Smoothed, so a term in every document still weighs a little rather than
+// nothing, and the documents matching only such terms keep a score. Never
+// negative, unlike the classic form of BM25 for terms in most documents.
+pub fn idf(docs: usize, doc_freq: usize) -> f32 {
+    let (docs, doc_freq) = (docs as f32, doc_freq as f32);
+    (1.0 + (docs - doc_freq + 0.5) / (doc_freq + 0.5)).ln()
+}
+
+// Okapi BM25: the weight of a term saturates as it repeats, at a rate set by
+// `k1`, and documents longer than the average are penalized less than by
+// their full length, as far as `b` says. TF-IDF divides by the length, so
+// long documents win on terms they mention in passing.
+pub struct Bm25 {
+    pub k1: f32,
+    pub b: f32,
+    // Tokens of the average document of the index.
+    pub avg_doc_len: f32,
+}
+
+impl Scorer for Bm25 {
+    fn score(&self, count: usize, doc_len: usize, idf: f32) -> f32 {
+        let count = count as f32;
+        let length = if self.avg_doc_len > 0.0 {
+            doc_len as f32 / self.avg_doc_len
+        } else {
+            1.0
+        };
+        idf * count * (self.k1 + 1.0) / (count + self.k1 * (1.0 - self.b + self.b * length))
+    }
+}
+
+const BM25_K1: f32 = 1.2;
+const BM25_B: f32 = 0.75;
+
+// The ranking function a search uses.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Ranking {
+    #[default]
+    TfIdf,
+    Bm25 {
+        k1: f32,
+        b: f32,
+    },
+}
+
+impl Ranking {
+    // BM25 with the usual parameters.
+    pub const BM25: Self = Self::Bm25 {
+        k1: BM25_K1,
+        b: BM25_B,
+    };
+
+    // "tfidf" or "bm25", the latter optionally with its parameters, e.g.
+    // "bm25:k1=1.5,b=0.5".
+    pub fn parse(value: &str) -> Option<Self> {
+        let (name, params) = value.split_once(':').unwrap_or((value, ""));
+        match name {
+            "tfidf" | "tf-idf" if params.is_empty() => Some(Self::TfIdf),
+            "bm25" => {
+                let (mut k1, mut b) = (BM25_K1, BM25_B);
+                for param in params.split(',').filter(|param| !param.is_empty()) {
+                    let (key, value) = param.split_once('=')?;
+                    let value = value.parse::<f32>().ok().filter(|v| *v >= 0.0)?;
+                    match key {
+                        "k1" => k1 = value,
+                        "b" if value <= 1.0 => b = value,
+                        _ => return None,
+                    }
+                }
+                Some(Self::Bm25 { k1, b })
+            }
+            _ => None,
+        }
+    }
+
+    // The scorer for an index whose documents have `avg_doc_len` tokens on
+    // average.
+    pub fn scorer(self, avg_doc_len: f32) -> Box<dyn Scorer> {
+        match self {
+            Self::TfIdf => Box::new(TfIdf),
+            Self::Bm25 { k1, b } => Box::new(Bm25 { k1, b, avg_doc_len }),
+        }
+    }
+}
+
+// The share of its score a term loses when a fuzzy word matched it with
+// typos, so documents with the word as typed rank above them.
+pub const TYPO_PENALTY: f32 = 0.5;
+
+// The share of a term's score that counts for a term pseudo-relevance
+// feedback added to the query, so documents with the words as typed rank
+// above those found through the added ones.
+pub const FEEDBACK_WEIGHT: f32 = 0.3;
+
+// How much more a query term weighs in a document whose title has it, the
+// title saying what the document is about better than any other part of it.
+pub const TITLE_BOOST: f32 = 2.0;
+
+// How much query terms close together raise the score of a document whose
+// positions the index recorded: next to each other they multiply it by
+// 1 + PROXIMITY_BOOST, `d` positions apart by 1 + PROXIMITY_BOOST / d.
+pub const PROXIMITY_BOOST: f32 = 0.5;
+
+// The factor of a document whose closest occurrences of two different query
+// terms are `distance` positions apart.
+pub fn proximity_boost(distance: u32) -> f32 {
+    1.0 + PROXIMITY_BOOST / distance.max(1) as f32
+}
+
+// The fewest positions between occurrences of two different terms in the
+// document, None if fewer than two of the terms occur in it.
+pub fn min_distance(positions: &Positions, terms: &[&str]) -> Option<u32> {
+    let mut terms = terms.to_vec();
+    terms.sort_unstable();
+    terms.dedup();
+    let mut occurrences = terms
+        .iter()
+        .enumerate()
+        .flat_map(|(i, term)| {
+            positions
+                .get(*term)
+                .into_iter()
+                .flatten()
+                .map(move |&at| (at, i))
+        })
+        .collect::<Vec<_>>();
+    occurrences.sort_unstable();
+    occurrences
+        .windows(2)
+        .filter(|pair| pair[0].1 != pair[1].1)
+        .map(|pair| pair[1].0 - pair[0].0)
+        .min()
+}
+
+// Matches scoring below this are left out instead of filling the page with
+// documents that barely match: an absolute score or, written as a
+// percentage, a share of the score of the best match.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MinScore {
+    Absolute(f32),
+    Relative(f32),
+}
+
+impl MinScore {
+    // "0.05" or "25%".
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.strip_suffix('%') {
+            Some(percent) => {
+                let percent = percent.parse::<f32>().ok()?;
+                (0.0..=100.0)
+                    .contains(&percent)
+                    .then_some(Self::Relative(percent / 100.0))
+            }
+            None => Some(Self::Absolute(
+                value.parse::<f32>().ok().filter(|v| v.is_finite())?,
+            )),
+        }
+    }
+
+    // Drops the matches below the cutoff from results ranked best first.
+    pub fn apply<P>(self, results: &mut Vec<(P, f32)>) {
+        let Some(&(_, best)) = results.first() else {
+            return;
+        };
+        let cutoff = match self {
+            Self::Absolute(min) => min,
+            Self::Relative(share) => best * share,
+        };
+        let keep = results
+            .iter()
+            .take_while(|(_, score)| *score >= cutoff)
+            .count();
+        results.truncate(keep);
+    }
+}
+
+// Maps the raw scores of a query's matches onto 0 to 1, which unlike the
+// scores themselves means something to a reader, e.g. as a relevance bar.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Normalization {
+    // Relative to the best match, which gets 1.
+    Max,
+    // The logistic curve 1 / (1 + e^(-k (score - mid))). Unless given, `mid`
+    // is the mean score of the matches and `k` one over their standard
+    // deviation.
+    Logistic { k: Option<f32>, mid: Option<f32> },
+}
+
+impl Normalization {
+    // "max" or "logistic", the latter optionally with its parameters, e.g.
+    // "logistic:k=8,mid=0.2".
+    pub fn parse(value: &str) -> Option<Self> {
+        let (name, params) = value.split_once(':').unwrap_or((value, ""));
+        match name {
+            "max" if params.is_empty() => Some(Self::Max),
+            "logistic" => {
+                let (mut k, mut mid) = (None, None);
+                for param in params.split(',').filter(|param| !param.is_empty()) {
+                    let (key, value) = param.split_once('=')?;
+                    let value = value.parse::<f32>().ok().filter(|v| v.is_finite())?;
+                    match key {
+                        "k" if value > 0.0 => k = Some(value),
+                        "mid" => mid = Some(value),
+                        _ => return None,
+                    }
+                }
+                Some(Self::Logistic { k, mid })
+            }
+            _ => None,
+        }
+    }
+
+    // The normalized score of each raw score of a query whose matches
+    // scored `scores`.
+    pub fn normalizer(self, scores: &[f32]) -> impl Fn(f32) -> f32 {
+        let max = scores.iter().copied().fold(0.0_f32, f32::max);
+        let count = scores.len().max(1) as f32;
+        let mean = scores.iter().sum::<f32>() / count;
+        let deviation = (scores.iter().map(|s| (s - mean).powi(2)).sum::<f32>() / count).sqrt();
+        move |score| match self {
+            Self::Max if max > 0.0 => score / max,
+            Self::Max => 0.0,
+            Self::Logistic { k, mid } => {
+                let k = k.unwrap_or(if deviation > 0.0 {
+                    1.0 / deviation
+                } else {
+                    1.0
+                });
+                1.0 / (1.0 + (-k * (score - mid.unwrap_or(mean))).exp())
+            }
+        }
+    }
+}
+
+pub struct CorpusStats {
+    index: InvertedIndex,
+    // The terms in order with the number of documents they appear in, for
+    // expanding prefixes, wildcards and fuzzy terms. Only built once a query
+    // needs it.
+    dictionary: OnceLock<Map<Vec<u8>>>,
+}
+
+impl CorpusStats {
+    pub fn of(model: &Model) -> Self {
+        Self {
+            index: InvertedIndex::of(model),
+            dictionary: OnceLock::new(),
+        }
+    }
+
+    pub fn index(&self) -> &InvertedIndex {
+        &self.index
+    }
+
+    pub fn docs(&self) -> usize {
+        self.index.docs()
+    }
+
+    pub fn doc_freq(&self, term: &str) -> usize {
+        self.index.doc_freq(term)
+    }
+
+    pub fn avg_doc_len(&self) -> f32 {
+        self.index.avg_doc_len()
+    }
+
+    // Index terms matching the pattern, in order. At most `limit` of them.
+    pub fn expand(&self, pattern: &TermPattern, limit: usize) -> Vec<String> {
+        self.expand_counted(pattern, limit)
+            .into_iter()
+            .map(|(term, _)| term)
+            .collect()
+    }
+
+    // `expand` with the number of documents each term appears in.
+    pub fn expand_counted(&self, pattern: &TermPattern, limit: usize) -> Vec<(String, usize)> {
+        let dictionary = self.dictionary.get_or_init(|| {
+            let mut terms = self.index.terms().collect::<Vec<_>>();
+            terms.sort_unstable();
+            let terms = terms
+                .into_iter()
+                .map(|term| (term, self.index.doc_freq(term) as u64));
+            Map::from_iter(terms).expect("terms are sorted and unique")
+        });
+        postings::expand_dictionary(dictionary, pattern, limit)
+            .into_iter()
+            .map(|(term, doc_freq)| (term, doc_freq as usize))
+            .collect()
+    }
+}
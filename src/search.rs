@@ -0,0 +1,124 @@
+use std::path::Path;
+
+use tracing::debug_span;
+
+use crate::bitset::Bitset;
+use crate::collector::Collector;
+use crate::exclude;
+use crate::filter::Filter;
+use crate::query::Query;
+use crate::scoring::{self, CorpusStats, Scorer};
+use crate::Model;
+
+// Ranks the documents that pass the filters and match the query by the
+// scores of the query terms, best first. `stats` must be those of `model`.
+pub fn search_query<'a>(
+    model: &'a Model,
+    stats: &CorpusStats,
+    scorer: &dyn Scorer,
+    query: &Query,
+    filters: &[Filter],
+) -> Vec<(&'a Path, f32)> {
+    let mut matching = filters
+        .iter()
+        .map(|filter| filter.bitset(model, stats.index()));
+    let allowed = matching.next().map(|mut allowed| {
+        for other in matching {
+            allowed.intersect_with(&other);
+        }
+        allowed
+    });
+    let mut results = Vec::new();
+    for_each_match(
+        model,
+        stats,
+        scorer,
+        query,
+        allowed.as_ref(),
+        true,
+        |path, score| results.push((path, score)),
+    );
+    results.sort_by(|(path_a, a), (path_b, b)| b.total_cmp(a).then(path_a.cmp(path_b)));
+    results
+}
+
+// Hands the documents that match the query to the collector, scored like
+// `search_query` if it needs scores. Only those in `allowed`, the ordinals of
+// the documents passing the filters of the search, if it has filters.
+pub fn collect_query(
+    model: &Model,
+    stats: &CorpusStats,
+    scorer: &dyn Scorer,
+    query: &Query,
+    allowed: Option<&Bitset>,
+    collector: &mut dyn Collector,
+) {
+    let scores = collector.needs_scores();
+    for_each_match(
+        model,
+        stats,
+        scorer,
+        query,
+        allowed,
+        scores,
+        |path, score| collector.collect(path, score),
+    );
+}
+
+fn for_each_match<'a>(
+    model: &'a Model,
+    stats: &CorpusStats,
+    scorer: &dyn Scorer,
+    query: &Query,
+    allowed: Option<&Bitset>,
+    scores: bool,
+    mut f: impl FnMut(&'a Path, f32),
+) {
+    // Only the documents with the terms the query needs are looked at.
+    let index = stats.index();
+    let matching = debug_span!("retrieve").in_scope(|| {
+        index
+            .candidates(query)
+            .into_iter()
+            .filter_map(|ordinal| {
+                let (path, doc) = model.docs.get_key_value(index.key(ordinal))?;
+                Some((ordinal, path, doc))
+            })
+            .filter(|(_, path, doc)| !exclude::is_excluded(doc) && !model.is_moved(path))
+            .filter(|(ordinal, _, _)| allowed.is_none_or(|allowed| allowed.contains(*ordinal)))
+            .filter(|(_, _, doc)| query.matches_doc(doc))
+            .collect::<Vec<_>>()
+    });
+
+    let _score = debug_span!("score").entered();
+    let terms = if scores {
+        query.weighted_terms()
+    } else {
+        Vec::new()
+    };
+    let idfs = terms
+        .iter()
+        .map(|(term, _)| scorer.idf(stats.docs(), stats.doc_freq(term)))
+        .collect::<Vec<_>>();
+    for (ordinal, path, doc) in matching {
+        let doc_len = index.doc_len(ordinal);
+        // Starting from 0.0 rather than `sum`'s -0.0, which queries without
+        // positive terms would end up scoring.
+        let mut score = 0.0;
+        for ((term, weight), idf) in terms.iter().zip(&idfs) {
+            if let Some(&count) = doc.tf.get(*term) {
+                let boost = if index.in_title(term, ordinal) {
+                    scoring::TITLE_BOOST
+                } else {
+                    1.0
+                };
+                score += weight * boost * scorer.score(count, doc_len, *idf);
+            }
+        }
+        let words = terms.iter().map(|(term, _)| *term).collect::<Vec<_>>();
+        if let Some(distance) = scoring::min_distance(&doc.positions, &words) {
+            score *= scoring::proximity_boost(distance);
+        }
+        f(path.as_path(), score);
+    }
+}
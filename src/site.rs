@@ -0,0 +1,212 @@
+// A static copy of an index that searches without a server, for export-site:
+// a page per document with its text, the search page with the client-side
+// search of site.js, and search-index.js with what that searches, the
+// postings of every term weighted as the ranking scores them and the words
+// of the documents mapped to the terms the query analyzer makes of them. The
+// site opens straight from disk, so the index is a script rather than JSON
+// the page would have to fetch.
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use serde::Serialize;
+use serde_json::json;
+
+use crate::frontend::Frontend;
+use tinysearch::bundle::Sources;
+use tinysearch::exclude::is_excluded;
+use tinysearch::extract::{ExtractOptions, PAGE_BREAK};
+use tinysearch::scoring::Ranking;
+use tinysearch::snippet::bundled_document_text;
+use tinysearch::{document_date, document_url, DocKey, Error, Model};
+
+// Characters of the excerpt of a document shown with its result.
+const EXCERPT_CHARS: usize = 200;
+
+#[derive(Default)]
+pub struct SiteReport {
+    pub docs: usize,
+    // Documents whose text could not be read, searchable but without a page.
+    pub without_text: usize,
+}
+
+#[derive(Serialize)]
+struct SiteDoc<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<&'a str>,
+    path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    date: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    excerpt: Option<String>,
+}
+
+// Folded word → term, for the words that are not their own term, and to
+// nothing for stopwords, which would otherwise match the terms they start.
+type Words = BTreeMap<String, String>;
+
+fn write_file(path: &Path, contents: &str) -> Result<(), Error> {
+    fs::write(path, contents).map_err(|err| {
+        Error::io(
+            format!("could not write {path}", path = path.display()),
+            err,
+        )
+    })
+}
+
+fn excerpt(text: &str) -> Option<String> {
+    let mut excerpt = String::new();
+    for word in text.split_whitespace() {
+        if excerpt.chars().count() + word.chars().count() > EXCERPT_CHARS {
+            excerpt.push('…');
+            break;
+        }
+        if !excerpt.is_empty() {
+            excerpt.push(' ');
+        }
+        excerpt.push_str(word);
+    }
+    (!excerpt.is_empty()).then_some(excerpt)
+}
+
+// Runs of lines without blank lines between them, pages of PDFs apart.
+fn paragraphs(text: &str) -> Vec<String> {
+    let mut paragraphs = Vec::new();
+    let mut lines = Vec::new();
+    for line in text.replace(PAGE_BREAK, "\n\n").lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            if !lines.is_empty() {
+                paragraphs.push(lines.join("\n"));
+                lines.clear();
+            }
+        } else {
+            lines.push(line.to_string());
+        }
+    }
+    if !lines.is_empty() {
+        paragraphs.push(lines.join("\n"));
+    }
+    paragraphs
+}
+
+pub fn export_site(
+    model: &Model,
+    frontend: &Frontend,
+    sources: Option<Sources>,
+    extract: &ExtractOptions,
+    ranking: Ranking,
+    out: &Path,
+) -> Result<SiteReport, Error> {
+    let docs_dir = out.join("docs");
+    fs::create_dir_all(&docs_dir).map_err(|err| {
+        Error::io(
+            format!("could not create {dir}", dir = docs_dir.display()),
+            err,
+        )
+    })?;
+    let analyzer = model.analyzer();
+    let mut paths = model
+        .docs
+        .iter()
+        .filter(|(_, doc)| !is_excluded(doc))
+        .map(|(path, _)| path)
+        .collect::<Vec<&DocKey>>();
+    paths.sort();
+
+    let mut report = SiteReport::default();
+    let mut site_docs = Vec::with_capacity(paths.len());
+    let mut words = Words::new();
+    for (ordinal, path) in paths.iter().enumerate() {
+        let doc = &model.docs[*path];
+        let title = doc.meta.get("title").and_then(|title| title.first());
+        let url = document_url(model, path);
+        let date = document_date(model, path);
+        let display = path.to_string_lossy();
+        let text = bundled_document_text(path, sources, extract);
+        let page = match &text {
+            Some(text) => {
+                let paragraphs = paragraphs(text);
+                let paragraphs = paragraphs.iter().map(String::as_str).collect::<Vec<_>>();
+                let doc_title = title.unwrap_or(&display);
+                let html = frontend.document_page(doc_title, &display, url, date, &paragraphs)?;
+                let page = format!("docs/{ordinal}.html");
+                write_file(&out.join(&page), &html)?;
+                let chars = text.chars().collect::<Vec<_>>();
+                for token in analyzer.lexer(&chars) {
+                    let word = token.iter().collect::<String>();
+                    let term = analyzer.normalize(&word);
+                    let word = analyzer.fold_pattern(&word);
+                    match term {
+                        Some(term) if term != word && doc.tf.contains_key(&term) => {
+                            words.entry(word).or_insert(term);
+                        }
+                        Some(_) => {}
+                        None => {
+                            words.entry(word).or_default();
+                        }
+                    }
+                }
+                Some(page)
+            }
+            None => {
+                report.without_text += 1;
+                None
+            }
+        };
+        site_docs.push(SiteDoc {
+            title,
+            path: display.into_owned(),
+            page,
+            url,
+            date,
+            excerpt: text.as_deref().and_then(excerpt),
+        });
+    }
+    report.docs = site_docs.len();
+
+    let doc_lens = paths
+        .iter()
+        .map(|path| model.docs[*path].tf.values().sum::<usize>())
+        .collect::<Vec<_>>();
+    let avg_doc_len = if doc_lens.is_empty() {
+        0.0
+    } else {
+        doc_lens.iter().sum::<usize>() as f32 / doc_lens.len() as f32
+    };
+    let scorer = ranking.scorer(avg_doc_len);
+    let mut postings = HashMap::<&str, Vec<(usize, usize)>>::new();
+    for (ordinal, path) in paths.iter().enumerate() {
+        for (term, &count) in &model.docs[*path].tf {
+            postings.entry(term).or_default().push((ordinal, count));
+        }
+    }
+    let postings = postings
+        .into_iter()
+        .map(|(term, docs)| {
+            let idf = scorer.idf(paths.len(), docs.len());
+            let weighted = docs
+                .into_iter()
+                .map(|(ordinal, count)| {
+                    let weight = scorer.score(count, doc_lens[ordinal], idf);
+                    // Four decimals tell results apart and keep the file small.
+                    (ordinal, (f64::from(weight) * 10000.0).round() / 10000.0)
+                })
+                .collect::<Vec<_>>();
+            (term, weighted)
+        })
+        .collect::<BTreeMap<_, _>>();
+    let index = json!({"docs": site_docs, "words": words, "postings": postings});
+    write_file(
+        &out.join("search-index.js"),
+        &format!("const SEARCH_INDEX = {index};\n"),
+    )?;
+    write_file(&out.join("index.html"), &frontend.site_page()?)?;
+    write_file(&out.join("site.js"), &frontend.site_js)?;
+    write_file(&out.join("style.css"), &frontend.static_file("style.css"))?;
+    Ok(report)
+}
@@ -0,0 +1,74 @@
+// Times the stages of query evaluation for the slow query log. The stages
+// are the tracing spans searches already open (parse, expand, retrieve,
+// score, snippet); a layer notes how long each ran on the current thread, so the
+// request handler can pick the timings up once the query is done.
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Map, Value};
+use tracing::span::{Attributes, Id};
+use tracing::Subscriber;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+pub const PHASES: &[&str] = &["parse", "expand", "retrieve", "score", "snippet"];
+
+thread_local! {
+    static TIMINGS: RefCell<Vec<(&'static str, Duration)>> = const { RefCell::new(Vec::new()) };
+}
+
+pub struct PhaseTimer;
+
+struct Started(Instant);
+
+impl<S> Layer<S> for PhaseTimer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if !PHASES.contains(&attrs.metadata().name()) {
+            return;
+        }
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(Started(Instant::now()));
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let started = span.extensions().get::<Started>().map(|started| started.0);
+        if let Some(start) = started {
+            let elapsed = start.elapsed();
+            TIMINGS.with(|timings| timings.borrow_mut().push((span.name(), elapsed)));
+        }
+    }
+}
+
+// Forgets the timings of earlier queries on this thread.
+pub fn reset() {
+    TIMINGS.with(|timings| timings.borrow_mut().clear());
+}
+
+// Milliseconds, to the microsecond.
+pub fn millis(duration: Duration) -> f64 {
+    duration.as_micros() as f64 / 1000.0
+}
+
+// Milliseconds spent in each phase since the last `reset`. The times of a
+// phase that ran more than once are summed up.
+pub fn phase_timings() -> Value {
+    let mut phases = BTreeMap::<&str, Duration>::new();
+    TIMINGS.with(|timings| {
+        for (name, elapsed) in timings.borrow().iter() {
+            *phases.entry(name).or_default() += *elapsed;
+        }
+    });
+    phases
+        .into_iter()
+        .map(|(name, elapsed)| (name.to_string(), json!(millis(elapsed))))
+        .collect::<Map<_, _>>()
+        .into()
+}
@@ -0,0 +1,251 @@
+// Copies of the served index taken at a fixed interval, so an index broken by
+// a bad update can be rolled back by copying a snapshot over it. A snapshot
+// of `index.tsidx` is `index-20240517T120000Z.tsidx` in the snapshot folder;
+// only the newest `keep` are retained. The index is replaced by renaming a
+// new file over it, so a copy never sees a half-written one.
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use tracing::{info, warn};
+
+use tinysearch::{locale, Error, Model};
+
+pub struct SnapshotOptions {
+    pub dir: PathBuf,
+    pub interval: Duration,
+    pub keep: usize,
+}
+
+pub struct Snapshots {
+    index: PathBuf,
+    options: SnapshotOptions,
+    next: Instant,
+}
+
+impl Snapshots {
+    // The first snapshot is taken right away.
+    pub fn new(index: &Path, options: SnapshotOptions) -> Result<Self, Error> {
+        fs::create_dir_all(&options.dir).map_err(|err| {
+            Error::io(
+                format!(
+                    "could not create snapshot folder {dir}",
+                    dir = options.dir.display()
+                ),
+                err,
+            )
+        })?;
+        Ok(Self {
+            index: index.to_path_buf(),
+            options,
+            next: Instant::now(),
+        })
+    }
+
+    // Failures are logged and retried at the next interval, they must not
+    // stop the server.
+    pub fn take_if_due(&mut self) {
+        if Instant::now() < self.next {
+            return;
+        }
+        self.next = Instant::now() + self.options.interval;
+        match self.take() {
+            Ok(Some(path)) => info!(snapshot = %path.display(), "took index snapshot"),
+            Ok(None) => {}
+            Err(err) => warn!(index = %self.index.display(), "could not snapshot index: {err}"),
+        }
+        if let Err(err) = self.prune() {
+            warn!(dir = %self.options.dir.display(), "could not remove old snapshots: {err}");
+        }
+    }
+
+    // Skipped while the index is no newer than the last snapshot.
+    pub fn take(&self) -> io::Result<Option<PathBuf>> {
+        let modified = fs::metadata(&self.index)?.modified()?;
+        if let Some(last) = list(&self.index, &self.options.dir)?.last() {
+            let taken = fs::metadata(last)?.modified()?;
+            if modified <= taken {
+                return Ok(None);
+            }
+        }
+        let (prefix, ext) = name_parts(&self.index);
+        let stamp = stamp(SystemTime::now());
+        let path = self.options.dir.join(format!("{prefix}{stamp}{ext}"));
+        // Copied under another name first, a crash must not leave a partial
+        // snapshot that looks like a complete one.
+        let tmp_path = self.options.dir.join(format!("{prefix}{stamp}{ext}.tmp"));
+        fs::copy(&self.index, &tmp_path)?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(Some(path))
+    }
+
+    fn prune(&self) -> io::Result<()> {
+        let snapshots = list(&self.index, &self.options.dir)?;
+        let excess = snapshots.len().saturating_sub(self.options.keep);
+        for path in &snapshots[..excess] {
+            fs::remove_file(path)?;
+            info!(snapshot = %path.display(), "removed old index snapshot");
+        }
+        Ok(())
+    }
+}
+
+// The index file name split around the timestamp of its snapshots.
+fn name_parts(index: &Path) -> (String, String) {
+    let stem = index
+        .file_stem()
+        .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+    let ext = index
+        .extension()
+        .map_or_else(String::new, |ext| format!(".{}", ext.to_string_lossy()));
+    (format!("{stem}-"), ext)
+}
+
+// Snapshots of `index` in `dir`, oldest first. The timestamps sort by name.
+pub fn list(index: &Path, dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let (prefix, ext) = name_parts(index);
+    let mut snapshots = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let name = name.to_string_lossy();
+        let stamp = name
+            .strip_prefix(&prefix)
+            .and_then(|rest| rest.strip_suffix(&ext));
+        if stamp.is_some_and(is_stamp) {
+            snapshots.push(dir.join(&*name));
+        }
+    }
+    snapshots.sort();
+    Ok(snapshots)
+}
+
+// When a snapshot of `index` was taken, as an RFC 3339 timestamp.
+pub fn taken_at(index: &Path, snapshot: &Path) -> Option<String> {
+    let (prefix, ext) = name_parts(index);
+    let name = snapshot.file_name()?.to_str()?;
+    let stamp = name.strip_prefix(&prefix)?.strip_suffix(&ext)?;
+    is_stamp(stamp).then(|| {
+        format!(
+            "{}-{}-{}T{}:{}:{}Z",
+            &stamp[0..4],
+            &stamp[4..6],
+            &stamp[6..8],
+            &stamp[9..11],
+            &stamp[11..13],
+            &stamp[13..15]
+        )
+    })
+}
+
+// Prints the index and its snapshots, newest first and numbered for
+// `roll_back`, with when they were taken and how many documents they have.
+pub fn print_generations(index: &Path, dir: &Path) -> Result<(), Error> {
+    let snapshots = list(index, dir).map_err(|err| {
+        Error::io(
+            format!("could not list snapshots in {dir}", dir = dir.display()),
+            err,
+        )
+    })?;
+    let docs = |path: &Path| {
+        Model::load(&path.to_string_lossy()).map_or_else(
+            |_| "unreadable".to_string(),
+            |model| format!("{} documents", model.docs.len()),
+        )
+    };
+    let modified = fs::metadata(index)
+        .and_then(|metadata| metadata.modified())
+        .map_or_else(|_| "?".to_string(), locale::rfc3339_from_system_time);
+    println!("current  {modified}  {docs}", docs = docs(index));
+    if snapshots.is_empty() {
+        println!("no snapshots in {dir}", dir = dir.display());
+    }
+    for (i, snapshot) in snapshots.iter().rev().enumerate() {
+        println!(
+            "{generation:>7}  {taken}  {docs}",
+            generation = i + 1,
+            taken = taken_at(index, snapshot).unwrap_or_default(),
+            docs = docs(snapshot)
+        );
+    }
+    Ok(())
+}
+
+// Makes a snapshot the index again: `generation` is its number in
+// `print_generations`, 1 for the newest, or its timestamp. The replaced index
+// is snapshotted first, so the rollback can be undone the same way.
+pub fn roll_back(index: &Path, dir: &Path, generation: &str) -> Result<PathBuf, Error> {
+    let snapshots = list(index, dir).map_err(|err| {
+        Error::io(
+            format!("could not list snapshots in {dir}", dir = dir.display()),
+            err,
+        )
+    })?;
+    let chosen = match generation.parse::<usize>() {
+        Ok(n) => n.checked_sub(1).and_then(|i| snapshots.iter().rev().nth(i)),
+        Err(_) => snapshots.iter().find(|snapshot| {
+            taken_at(index, snapshot).is_some_and(|taken| taken == generation)
+                || snapshot.to_string_lossy().contains(generation)
+        }),
+    };
+    let chosen = chosen.cloned().ok_or_else(|| {
+        Error::invalid(format!(
+            "there is no snapshot {generation} of {index} in {dir}, there are {count}",
+            index = index.display(),
+            dir = dir.display(),
+            count = snapshots.len()
+        ))
+    })?;
+    let current = Snapshots {
+        index: index.to_path_buf(),
+        options: SnapshotOptions {
+            dir: dir.to_path_buf(),
+            interval: Duration::ZERO,
+            keep: usize::MAX,
+        },
+        next: Instant::now(),
+    };
+    if let Some(path) = current.take().map_err(|err| {
+        Error::io(
+            format!(
+                "could not snapshot {index} before rolling back",
+                index = index.display()
+            ),
+            err,
+        )
+    })? {
+        println!("Saved the current index as {path}", path = path.display());
+    }
+    // Renamed over the index like a newly written one, so a server reading
+    // it meanwhile never sees half of it.
+    let mut tmp_path = index.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    fs::copy(&chosen, &tmp_path)
+        .and_then(|_| fs::rename(&tmp_path, index))
+        .map_err(|err| {
+            Error::io(
+                format!(
+                    "could not replace {index} with {chosen}",
+                    index = index.display(),
+                    chosen = chosen.display()
+                ),
+                err,
+            )
+        })?;
+    Ok(chosen)
+}
+
+// `20240517T120000Z` from `2024-05-17T12:00:00Z`, with no characters file
+// systems object to.
+fn stamp(time: SystemTime) -> String {
+    locale::rfc3339_from_system_time(time).replace(['-', ':'], "")
+}
+
+fn is_stamp(stamp: &str) -> bool {
+    stamp.len() == 16
+        && stamp.bytes().enumerate().all(|(i, byte)| match i {
+            8 => byte == b'T',
+            15 => byte == b'Z',
+            _ => byte.is_ascii_digit(),
+        })
+}
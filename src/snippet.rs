@@ -0,0 +1,599 @@
+// Short excerpts of a document around the query terms. The index does not
+// keep document text, so the document is extracted again at query time.
+use std::collections::{HashSet, VecDeque};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use crate::analyzer::Analyzer;
+use crate::bundle::Sources;
+use crate::extract::{self, ExtractOptions};
+
+// Formats whose raw bytes have no meaningful lines.
+const BINARY_EXTENSIONS: &[&str] = &["pdf", "png", "jpg", "jpeg", "tif", "tiff", "mp3"];
+
+// Pages reported of a result at most.
+pub const MAX_REPORTED_PAGES: usize = 20;
+
+// Length of a snippet in tokens.
+const SNIPPET_TOKENS: usize = 30;
+
+// Tokens a snippet may grow by on either side to start and end with whole
+// sentences, so it is never longer than `SNIPPET_TOKENS + 2 * SENTENCE_SLACK`.
+const SENTENCE_SLACK: usize = 15;
+
+// Pieces of a snippet; the flag tells whether the piece is a query term.
+pub type Snippet = Vec<(String, bool)>;
+
+// Index keys of chunks look like `<file>#<anchor>`.
+pub fn split_doc_path(doc_path: &Path) -> (&Path, Option<&str>) {
+    if doc_path.exists() {
+        return (doc_path, None);
+    }
+    let display = doc_path.to_str().unwrap_or_default();
+    match display.rsplit_once('#') {
+        Some((file, anchor)) => (Path::new(file), Some(anchor)),
+        None => (doc_path, None),
+    }
+}
+
+// The text of a document from its file or, when the file is not there,
+// from the copy of it in `sources`.
+pub fn bundled_document_text(
+    doc_path: &Path,
+    sources: Option<Sources>,
+    extract: &ExtractOptions,
+) -> Option<String> {
+    document_text(doc_path, extract).or_else(|| sources?.document_text(doc_path))
+}
+
+// The text of a document extracted again from its file, as `extract` says,
+// so a caller that keeps no extraction cache writes none here either.
+pub fn document_text(doc_path: &Path, extract: &ExtractOptions) -> Option<String> {
+    let (file_path, anchor) = split_doc_path(doc_path);
+    let chunks = extract::extract_chunks(file_path, extract).ok()?;
+    chunks
+        .into_iter()
+        .find(|chunk| chunk.anchor.as_deref() == anchor)
+        .map(|chunk| chunk.text)
+}
+
+// 1-based numbers of the lines of the original file that contain a query
+// term, grep style. Binary formats have no lines to report.
+pub fn matching_lines(
+    doc_path: &Path,
+    terms: &[&str],
+    max: usize,
+    analyzer: &Analyzer,
+) -> Vec<usize> {
+    let (file_path, _) = split_doc_path(doc_path);
+    let ext = file_path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+    if BINARY_EXTENSIONS.contains(&ext.as_str()) {
+        return Vec::new();
+    }
+    let Ok(file) = File::open(file_path) else {
+        return Vec::new();
+    };
+    // Read a line at a time so huge files are never held in memory at once.
+    let mut reader = BufReader::new(file);
+    let mut line = Vec::new();
+    let mut numbers = Vec::new();
+    let mut number = 0;
+    while numbers.len() < max {
+        line.clear();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => number += 1,
+        }
+        let mut found = false;
+        analyzer.for_each_term(&String::from_utf8_lossy(&line), |term| {
+            found |= terms.contains(&term);
+        });
+        if found {
+            numbers.push(number);
+        }
+    }
+    numbers
+}
+
+// 1-based numbers of the pages of a PDF that contain a query term, told by
+// the page breaks of its text, so a viewer can be opened on them. Other
+// documents have no pages to report.
+pub fn matching_pages(
+    doc_path: &Path,
+    terms: &[&str],
+    analyzer: &Analyzer,
+    sources: Option<Sources>,
+    extract: &ExtractOptions,
+) -> Vec<usize> {
+    let (file_path, _) = split_doc_path(doc_path);
+    let is_pdf = file_path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("pdf"));
+    if !is_pdf {
+        return Vec::new();
+    }
+    let Some(text) = bundled_document_text(doc_path, sources, extract) else {
+        return Vec::new();
+    };
+    let mut numbers = Vec::new();
+    for (i, page) in text.split(extract::PAGE_BREAK).enumerate() {
+        if numbers.len() == MAX_REPORTED_PAGES {
+            break;
+        }
+        let mut found = false;
+        analyzer.for_each_term(page, |term| {
+            found |= terms.contains(&term);
+        });
+        if found {
+            numbers.push(i + 1);
+        }
+    }
+    numbers
+}
+
+// Picks the window of `SNIPPET_TOKENS` tokens with the most query term
+// occurrences, widened to the sentences it cuts when they end close by, and
+// returns it with whitespace collapsed. The text is fed to a
+// `SnippetWindow` a piece at a time, so however large the document is, only
+// a piece and two windows of tokens are held besides it.
+pub fn make_snippet(text: &str, terms: &[&str], analyzer: &Analyzer) -> Option<Snippet> {
+    fill_window(SnippetWindow::new(terms, analyzer), text)
+}
+
+// Where the snippet of a result comes from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SnippetField {
+    Title,
+    Heading,
+    Body,
+}
+
+impl SnippetField {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Title => "title",
+            Self::Heading => "heading",
+            Self::Body => "body",
+        }
+    }
+}
+
+// The snippet of a result: its title if that has a query term, else the
+// heading with the most query terms, else the best window of its text,
+// read from `sources` when its file is not there. Headings say what a
+// document is about better than a passage does.
+pub fn field_snippet(
+    doc_path: &Path,
+    title: Option<&str>,
+    terms: &[&str],
+    analyzer: &Analyzer,
+    sources: Option<Sources>,
+    extract: &ExtractOptions,
+) -> Option<(SnippetField, Snippet)> {
+    let hits = |snippet: &Snippet| snippet.iter().filter(|(_, hit)| *hit).count();
+    let title = title.and_then(|title| make_snippet(title, terms, analyzer));
+    if let Some(snippet) = title.filter(|snippet| hits(snippet) > 0) {
+        return Some((SnippetField::Title, snippet));
+    }
+    // The headings of a file belong to the file, not to one of its chunks.
+    if let (file_path, None) = split_doc_path(doc_path) {
+        let heading = extract::extract_headings(file_path)
+            .iter()
+            .filter_map(|heading| make_snippet(heading, terms, analyzer))
+            .rev()
+            .max_by_key(hits);
+        if let Some(snippet) = heading.filter(|snippet| hits(snippet) > 0) {
+            return Some((SnippetField::Heading, snippet));
+        }
+    }
+    let text = bundled_document_text(doc_path, sources, extract)?;
+    Some((SnippetField::Body, make_snippet(&text, terms, analyzer)?))
+}
+
+// The first query term occurrence with `context` tokens on either side, like
+// the context lines of grep. None if the text has no query term.
+pub fn context_snippet(
+    text: &str,
+    terms: &[&str],
+    analyzer: &Analyzer,
+    context: usize,
+) -> Option<Snippet> {
+    fill_window(
+        SnippetWindow::around_first_hit(terms, analyzer, context),
+        text,
+    )
+}
+
+fn fill_window(mut window: SnippetWindow, text: &str) -> Option<Snippet> {
+    let mut at = 0;
+    while at < text.len() && !window.is_complete() {
+        let mut end = (at + SNIPPET_PIECE_BYTES).min(text.len());
+        while !text.is_char_boundary(end) {
+            end += 1;
+        }
+        window.push(&text[at..end]);
+        at = end;
+    }
+    window.finish()
+}
+
+// Bytes of text tokenized at once while looking for the best snippet.
+const SNIPPET_PIECE_BYTES: usize = 64 * 1024;
+
+#[derive(Clone)]
+struct WindowToken {
+    text: String,
+    hit: bool,
+    // Whether whitespace separates the token from the previous one.
+    spaced: bool,
+    // Whether a sentence starts with the token.
+    starts_sentence: bool,
+}
+
+// Sentence ends: full stops, question and exclamation marks of Latin, Greek,
+// Cyrillic, Armenian, Arabic and Devanagari script, followed by whitespace.
+fn is_terminator(c: char) -> bool {
+    matches!(c, '.' | '!' | '?' | '…' | '؟' | '।' | '։' | '\u{37e}')
+}
+
+// Chinese and Japanese end sentences without a space after them.
+fn is_wide_terminator(c: char) -> bool {
+    matches!(c, '。' | '！' | '？' | '｡')
+}
+
+// What may come between a sentence end and the whitespace after it.
+fn is_closing(c: char) -> bool {
+    matches!(
+        c,
+        '"' | '\'' | ')' | ']' | '”' | '’' | '»' | '«' | '」' | '』' | '）'
+    )
+}
+
+// Words whose period does not end a sentence, in lowercase. Initials, the
+// letters of "e.g." and "z.B." and the like, are told apart by their length.
+const ABBREVIATIONS: &[&str] = &[
+    "mr", "mrs", "ms", "dr", "prof", "st", "vs", "etc", "inc", "ltd", "jr", "sr", "no", "nr",
+    "fig", "approx", "cf", "ca", "bzw", "usw", "vgl", "mme", "mlle", "sra", "sr",
+];
+
+fn is_abbreviation(word: &str) -> bool {
+    let mut chars = word.chars();
+    let single = chars.next().is_some_and(char::is_alphabetic) && chars.next().is_none();
+    single || ABBREVIATIONS.contains(&word.to_lowercase().as_str())
+}
+
+// Finds where sentences start as the tokens of a text and the text between
+// them go by. Punctuation is a token of its own for some tokenizers and part
+// of the text between tokens for others, so both are looked at.
+struct Sentences {
+    // A sentence ended since the last token, so the next one starts one.
+    ended: bool,
+    // A terminator came, ending the sentence once whitespace follows.
+    terminator: bool,
+    // The last word was an abbreviation, so its period ends nothing.
+    abbreviation: bool,
+    // Line breaks since the last token; a blank line ends a sentence too,
+    // like a heading without a period.
+    newlines: usize,
+}
+
+impl Sentences {
+    fn new() -> Self {
+        Self {
+            ended: true,
+            terminator: false,
+            abbreviation: false,
+            newlines: 0,
+        }
+    }
+
+    fn gap(&mut self, gap: &[char]) {
+        for &c in gap {
+            self.character(c);
+        }
+    }
+
+    fn character(&mut self, c: char) {
+        if c.is_whitespace() {
+            self.newlines += usize::from(c == '\n');
+            self.ended |= self.terminator || self.newlines > 1;
+        } else if is_terminator(c) {
+            self.terminator |= !self.abbreviation;
+        } else if is_wide_terminator(c) {
+            self.ended = true;
+        } else if !is_closing(c) {
+            self.terminator = false;
+        }
+    }
+
+    // Whether the token starts a sentence, given the text before it.
+    fn token(&mut self, gap: &[char], token: &str) -> bool {
+        self.gap(gap);
+        let starts = self.ended;
+        self.ended = false;
+        self.newlines = 0;
+        let mut chars = token.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if !c.is_alphanumeric() => self.character(c),
+            _ => {
+                self.terminator = false;
+                self.abbreviation = is_abbreviation(token);
+            }
+        }
+        starts
+    }
+}
+
+// Which window of the text becomes the snippet.
+#[derive(Clone, Copy)]
+enum WindowChoice {
+    // `SNIPPET_TOKENS` tokens with the most query terms.
+    Best,
+    // The first query term with this many tokens before and after it.
+    FirstHit(usize),
+}
+
+fn is_word(token: &WindowToken) -> bool {
+    token.text.chars().any(char::is_alphanumeric)
+}
+
+// Finds the best snippet window incrementally: it slides over the tokens of
+// the text as it is pushed and keeps a copy of the best window seen so far.
+pub struct SnippetWindow<'a> {
+    choice: WindowChoice,
+    // Tokens pushed since the first query term, once there was one.
+    after_hit: Option<usize>,
+    terms: HashSet<&'a str>,
+    analyzer: &'a Analyzer,
+    // Text of a token that may continue in the next piece.
+    pending: String,
+    pending_spaced: bool,
+    sentences: Sentences,
+    current: VecDeque<WindowToken>,
+    current_hits: usize,
+    // The last tokens before the current window, to reach back to the
+    // start of its first sentence.
+    before: VecDeque<WindowToken>,
+    best: Option<(usize, Vec<WindowToken>)>,
+    // The tokens after the best window up to the end of its last sentence,
+    // while that has not come yet.
+    best_tail: Option<Vec<WindowToken>>,
+}
+
+impl<'a> SnippetWindow<'a> {
+    pub fn new(terms: &[&'a str], analyzer: &'a Analyzer) -> Self {
+        Self {
+            choice: WindowChoice::Best,
+            after_hit: None,
+            terms: terms.iter().copied().collect(),
+            analyzer,
+            pending: String::new(),
+            pending_spaced: false,
+            sentences: Sentences::new(),
+            current: VecDeque::with_capacity(SNIPPET_TOKENS),
+            current_hits: 0,
+            before: VecDeque::with_capacity(SENTENCE_SLACK),
+            best: None,
+            best_tail: None,
+        }
+    }
+
+    pub fn around_first_hit(terms: &[&'a str], analyzer: &'a Analyzer, context: usize) -> Self {
+        Self {
+            choice: WindowChoice::FirstHit(context),
+            ..Self::new(terms, analyzer)
+        }
+    }
+
+    // Whether more text cannot change the snippet.
+    pub fn is_complete(&self) -> bool {
+        matches!(self.choice, WindowChoice::FirstHit(_)) && self.best.is_some()
+    }
+
+    pub fn push(&mut self, text: &str) {
+        self.pending.push_str(text);
+        self.tokenize(false);
+    }
+
+    fn tokenize(&mut self, finished: bool) {
+        let content = self.pending.chars().collect::<Vec<_>>();
+        let base = content.as_ptr() as usize;
+        let mut tokens = self
+            .analyzer
+            .lexer(&content)
+            .map(|token| {
+                let start = (token.as_ptr() as usize - base) / std::mem::size_of::<char>();
+                (start, start + token.len())
+            })
+            .collect::<Vec<_>>();
+        // Unless the text is over, the last token may be cut short.
+        let held = match tokens.last() {
+            Some(&(start, end)) if !finished && end == content.len() => {
+                tokens.pop();
+                Some(start)
+            }
+            _ => None,
+        };
+        let mut previous_end = 0;
+        for (start, end) in tokens {
+            let spaced = start > previous_end || (previous_end == 0 && self.pending_spaced);
+            let text = content[start..end].iter().collect::<String>();
+            let starts_sentence = self.sentences.token(&content[previous_end..start], &text);
+            self.slide(WindowToken {
+                text,
+                hit: false,
+                spaced,
+                starts_sentence,
+            });
+            previous_end = end;
+        }
+        self.sentences
+            .gap(&content[previous_end..held.unwrap_or(content.len())]);
+        self.pending_spaced = match held {
+            Some(start) => start > previous_end || (previous_end == 0 && self.pending_spaced),
+            None => content.len() > previous_end || (previous_end == 0 && self.pending_spaced),
+        };
+        self.pending = content[held.unwrap_or(content.len())..].iter().collect();
+    }
+
+    fn slide(&mut self, mut token: WindowToken) {
+        if self.is_complete() {
+            return;
+        }
+        token.hit = self
+            .analyzer
+            .normalize(&token.text)
+            .is_some_and(|term| self.terms.contains(term.as_str()));
+        if let WindowChoice::FirstHit(context) = self.choice {
+            // The context is counted in words, punctuation comes along.
+            let (word, hit) = (is_word(&token), token.hit);
+            self.current.push_back(token);
+            self.after_hit = match self.after_hit {
+                Some(after) => Some(after + usize::from(word)),
+                None if hit => Some(0),
+                None => {
+                    // Up to the first hit only the last `context` words are
+                    // kept, from then on everything until `context` more.
+                    while self.current.front().is_some_and(|token| {
+                        !is_word(token)
+                            || self.current.iter().filter(|t| is_word(t)).count() > context
+                    }) {
+                        self.current.pop_front();
+                    }
+                    None
+                }
+            };
+            if self.after_hit == Some(context) {
+                self.save_best();
+            }
+            return;
+        }
+        self.extend_tail(&token);
+        if self.current.len() == SNIPPET_TOKENS {
+            if let Some(dropped) = self.current.pop_front() {
+                self.current_hits -= usize::from(dropped.hit);
+                if self.before.len() == SENTENCE_SLACK {
+                    self.before.pop_front();
+                }
+                self.before.push_back(dropped);
+            }
+        }
+        self.current_hits += usize::from(token.hit);
+        self.current.push_back(token);
+        // Later windows only win with strictly more hits, so ties go to the
+        // earliest one.
+        if self.current.len() == SNIPPET_TOKENS
+            && self
+                .best
+                .as_ref()
+                .is_none_or(|(hits, _)| self.current_hits > *hits)
+        {
+            self.save_best();
+            self.start_at_sentence();
+        }
+    }
+
+    fn save_best(&mut self) {
+        let tokens = self.current.iter().cloned().collect();
+        self.best = Some((self.current_hits, tokens));
+    }
+
+    // Widens the best window back to the start of the sentence it begins
+    // in, or else, when that is too far back, drops the part of the
+    // sentence before its first query term.
+    fn start_at_sentence(&mut self) {
+        let Some((_, tokens)) = &mut self.best else {
+            return;
+        };
+        self.best_tail = Some(Vec::new());
+        if tokens.first().is_none_or(|token| token.starts_sentence) {
+            return;
+        }
+        if let Some(start) = self.before.iter().rposition(|token| token.starts_sentence) {
+            tokens.splice(0..0, self.before.range(start..).cloned());
+            return;
+        }
+        let first_hit = tokens.iter().position(|token| token.hit).unwrap_or(0);
+        if let Some(start) = tokens[..=first_hit]
+            .iter()
+            .rposition(|token| token.starts_sentence)
+        {
+            tokens.drain(..start);
+        }
+    }
+
+    // Adds the token to the best window while its last sentence goes on.
+    fn extend_tail(&mut self, token: &WindowToken) {
+        let Some(tail) = &mut self.best_tail else {
+            return;
+        };
+        if token.starts_sentence {
+            self.end_at_sentence(true);
+        } else if tail.len() == SENTENCE_SLACK {
+            self.end_at_sentence(false);
+        } else {
+            tail.push(token.clone());
+        }
+    }
+
+    // Adds the rest of the last sentence to the best window if it ended
+    // close enough, or else drops the part of it after the last query term.
+    fn end_at_sentence(&mut self, ended: bool) {
+        let (Some((_, tokens)), Some(tail)) = (&mut self.best, self.best_tail.take()) else {
+            return;
+        };
+        if ended {
+            tokens.extend(tail);
+            return;
+        }
+        let last_hit = tokens.iter().rposition(|token| token.hit).unwrap_or(0);
+        if let Some(end) = tokens[last_hit + 1..]
+            .iter()
+            .position(|token| token.starts_sentence)
+        {
+            tokens.truncate(last_hit + 1 + end);
+        }
+    }
+
+    pub fn finish(mut self) -> Option<Snippet> {
+        self.tokenize(true);
+        // Texts shorter than a window have a single, partial window. Without
+        // a query term there is nothing to show context of.
+        let partial = match self.choice {
+            WindowChoice::Best => !self.current.is_empty(),
+            WindowChoice::FirstHit(_) => self.after_hit.is_some(),
+        };
+        if self.best.is_none() && partial {
+            self.save_best();
+        }
+        // The text ends the last sentence.
+        self.end_at_sentence(true);
+        let (_, tokens) = self.best?;
+
+        let mut snippet = Snippet::new();
+        let mut plain = String::new();
+        for (i, token) in tokens.into_iter().enumerate() {
+            if i > 0 && token.spaced {
+                plain.push(' ');
+            }
+            if token.hit {
+                if !plain.is_empty() {
+                    snippet.push((std::mem::take(&mut plain), false));
+                }
+                snippet.push((token.text, true));
+            } else {
+                plain.push_str(&token.text);
+            }
+        }
+        if !plain.is_empty() {
+            snippet.push((plain, false));
+        }
+        Some(snippet)
+    }
+}
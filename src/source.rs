@@ -0,0 +1,356 @@
+// Where documents come from. The indexer pulls documents out of any number of
+// sources and does not care whether they live in a folder, an archive, a git
+// revision or on a web server.
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+
+use crate::walk::{self, WalkOptions};
+use crate::{DocKey, Error, Metadata};
+
+pub struct SourceDocument {
+    // Path the document is stored under in the index, or the URL of a page.
+    // Its extension picks the extractor.
+    pub id: DocKey,
+    pub reader: Box<dyn Read + Send>,
+    pub meta: Metadata,
+    // Only for documents that are files on disk.
+    pub stat: Option<FileStat>,
+}
+
+// Modification time, in nanoseconds since the epoch, and size of a file.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FileStat {
+    pub mtime_ns: u64,
+    pub size: u64,
+}
+
+impl FileStat {
+    pub fn of(metadata: &fs::Metadata) -> Option<Self> {
+        let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Self {
+            mtime_ns: u64::try_from(mtime.as_nanos()).ok()?,
+            size: metadata.len(),
+        })
+    }
+}
+
+pub type Documents = Box<dyn Iterator<Item = SourceDocument> + Send>;
+
+// Listing documents should be cheap: the actual reading happens when the
+// reader is first used, on an indexing worker.
+pub trait DocumentSource {
+    fn documents(&self) -> Result<Documents, Error>;
+}
+
+// Opens the file on the first read.
+struct LazyFile {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl Read for LazyFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => self.file.insert(File::open(&self.path)?),
+        };
+        file.read(buf)
+    }
+}
+
+// Runs the command on the first read and yields what it printed.
+struct CommandOutput {
+    command: Option<Command>,
+    output: Cursor<Vec<u8>>,
+}
+
+impl CommandOutput {
+    fn new(command: Command) -> Self {
+        Self {
+            command: Some(command),
+            output: Cursor::new(Vec::new()),
+        }
+    }
+}
+
+impl Read for CommandOutput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(mut command) = self.command.take() {
+            self.output = Cursor::new(run(&mut command)?);
+        }
+        self.output.read(buf)
+    }
+}
+
+fn run(command: &mut Command) -> io::Result<Vec<u8>> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command
+        .output()
+        .map_err(|err| io::Error::other(format!("could not run {program}: {err}")))?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{program} failed: {stderr}",
+            stderr = String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output.stdout)
+}
+
+pub struct FolderSource {
+    pub root: PathBuf,
+    pub walk: WalkOptions,
+}
+
+impl DocumentSource for FolderSource {
+    fn documents(&self) -> Result<Documents, Error> {
+        let mut files = Vec::new();
+        walk::collect_files(&self.root, &self.walk, &mut files)?;
+        Ok(Box::new(files.into_iter().map(|(path, metadata)| {
+            SourceDocument {
+                id: path.clone().into(),
+                reader: Box::new(LazyFile { path, file: None }),
+                meta: Metadata::new(),
+                stat: FileStat::of(&metadata),
+            }
+        })))
+    }
+}
+
+// At most `size` of the documents of another source, spread evenly over
+// them so that a sample of a folder is not only its first subfolder.
+pub struct SampledSource {
+    pub inner: Box<dyn DocumentSource>,
+    pub size: usize,
+}
+
+impl DocumentSource for SampledSource {
+    fn documents(&self) -> Result<Documents, Error> {
+        let documents = self.inner.documents()?.collect::<Vec<_>>();
+        let step = documents.len().div_ceil(self.size.max(1)).max(1);
+        Ok(Box::new(
+            documents.into_iter().step_by(step).take(self.size),
+        ))
+    }
+}
+
+// Members of .tar, .tar.gz/.tgz and .zip archives, stored as
+// `<archive>!/<member>` so they cannot collide with files on disk.
+pub struct ArchiveSource {
+    pub path: PathBuf,
+}
+
+pub fn is_archive(path: &Path) -> bool {
+    let name = path.to_string_lossy().to_ascii_lowercase();
+    [".tar", ".tar.gz", ".tgz", ".zip"]
+        .iter()
+        .any(|ext| name.ends_with(ext))
+}
+
+fn tar_field(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+// The regular files of a ustar/GNU tar archive, with GNU long names.
+fn tar_members(archive: &[u8]) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let mut members = Vec::new();
+    let mut long_name = None;
+    let mut offset = 0;
+    while offset + 512 <= archive.len() {
+        let header = &archive[offset..offset + 512];
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        let size = tar_field(&header[124..136]);
+        let size = usize::from_str_radix(size.trim(), 8)
+            .map_err(|_| format!("corrupt tar header at offset {offset}"))?;
+        let data_start = offset + 512;
+        let data = archive
+            .get(data_start..data_start + size)
+            .ok_or_else(|| format!("truncated tar member at offset {offset}"))?;
+        let mut name = tar_field(&header[0..100]);
+        // Only POSIX ustar headers have a prefix field, GNU ones reuse the space.
+        if &header[257..263] == b"ustar\0" {
+            let prefix = tar_field(&header[345..500]);
+            if !prefix.is_empty() {
+                name = format!("{prefix}/{name}");
+            }
+        }
+        match header[156] {
+            b'L' => long_name = Some(tar_field(data)),
+            b'0' | 0 => {
+                let name = long_name.take().unwrap_or(name);
+                members.push((name, data.to_vec()));
+            }
+            _ => long_name = None,
+        }
+        offset = data_start + size.div_ceil(512) * 512;
+    }
+    Ok(members)
+}
+
+fn archive_member_id(archive: &Path, member: &str) -> PathBuf {
+    PathBuf::from(format!("{}!/{member}", archive.display()))
+}
+
+impl DocumentSource for ArchiveSource {
+    fn documents(&self) -> Result<Documents, Error> {
+        let path = &self.path;
+        let name = path.to_string_lossy().to_ascii_lowercase();
+        if name.ends_with(".zip") {
+            let listing = run(Command::new("unzip").arg("-Z1").arg(path)).map_err(|err| {
+                Error::io(format!("could not list {path}", path = path.display()), err)
+            })?;
+            let members = String::from_utf8_lossy(&listing)
+                .lines()
+                .filter(|member| !member.ends_with('/'))
+                .map(|member| {
+                    let mut command = Command::new("unzip");
+                    command.arg("-p").arg(path).arg(member);
+                    SourceDocument {
+                        id: archive_member_id(path, member).into(),
+                        reader: Box::new(CommandOutput::new(command)),
+                        meta: Metadata::new(),
+                        stat: None,
+                    }
+                })
+                .collect::<Vec<_>>();
+            return Ok(Box::new(members.into_iter()));
+        }
+
+        let archive = if name.ends_with(".tar") {
+            fs::read(path)
+        } else {
+            run(Command::new("gzip").arg("-dc").arg(path))
+        }
+        .map_err(|err| Error::io(format!("could not read {path}", path = path.display()), err))?;
+        let members = tar_members(&archive).map_err(|err| {
+            Error::invalid(format!(
+                "could not read {path}: {err}",
+                path = path.display()
+            ))
+        })?;
+        let path = path.clone();
+        Ok(Box::new(members.into_iter().map(move |(member, data)| {
+            SourceDocument {
+                id: archive_member_id(&path, &member).into(),
+                reader: Box::new(Cursor::new(data)),
+                meta: Metadata::new(),
+                stat: None,
+            }
+        })))
+    }
+}
+
+// The files of a revision of a git repository, as if it was checked out.
+pub struct GitSource {
+    pub repo: PathBuf,
+    pub rev: String,
+}
+
+impl DocumentSource for GitSource {
+    fn documents(&self) -> Result<Documents, Error> {
+        let (repo, rev) = (&self.repo, &self.rev);
+        let mut ls_tree = Command::new("git");
+        ls_tree
+            .arg("-C")
+            .arg(repo)
+            .args(["ls-tree", "-r", "-z", "--name-only", rev]);
+        let listing = run(&mut ls_tree).map_err(|err| {
+            Error::io(
+                format!("could not list {rev} of {repo}", repo = repo.display()),
+                err,
+            )
+        })?;
+        let documents = String::from_utf8_lossy(&listing)
+            .split('\0')
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                let mut command = Command::new("git");
+                command
+                    .arg("-C")
+                    .arg(repo)
+                    .args(["cat-file", "blob", &format!("{rev}:{name}")]);
+                let mut meta = Metadata::new();
+                meta.insert("git_rev".to_string(), rev.clone().into());
+                SourceDocument {
+                    id: repo.join(name).into(),
+                    reader: Box::new(CommandOutput::new(command)),
+                    meta,
+                    stat: None,
+                }
+            })
+            .collect::<Vec<_>>();
+        Ok(Box::new(documents.into_iter()))
+    }
+}
+
+// Pages downloaded with curl, stored under their URL, which is also their
+// `url` until the page names its canonical URL.
+pub struct HttpSource {
+    pub urls: Vec<String>,
+}
+
+pub fn is_url(arg: &str) -> bool {
+    arg.starts_with("http://") || arg.starts_with("https://")
+}
+
+// `href` as an absolute URL, relative ones taken from `base`, the URL of the
+// page they are on. None for relative links without a URL to resolve them
+// against.
+pub fn resolve_url(base: Option<&str>, href: &str) -> Option<String> {
+    if is_url(href) {
+        return Some(href.to_string());
+    }
+    // Links of other schemes, like `mailto:` and `javascript:`.
+    let scheme_end = href.find(':').unwrap_or(href.len());
+    if href[..scheme_end]
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+        && scheme_end < href.len()
+    {
+        return None;
+    }
+    let base = base.filter(|base| is_url(base))?;
+    let (scheme, rest) = base.split_once("://")?;
+    let origin_end = rest.find('/').unwrap_or(rest.len());
+    let origin = &base[..scheme.len() + 3 + origin_end];
+    Some(if let Some(host_relative) = href.strip_prefix("//") {
+        format!("{scheme}://{host_relative}")
+    } else if href.starts_with('/') {
+        format!("{origin}{href}")
+    } else {
+        let path = &rest[origin_end..];
+        let path = &path[..path.find(['?', '#']).unwrap_or(path.len())];
+        let dir = &path[..path.rfind('/').map_or(0, |slash| slash + 1)];
+        let dir = if dir.is_empty() { "/" } else { dir };
+        format!("{origin}{dir}{href}")
+    })
+}
+
+impl DocumentSource for HttpSource {
+    fn documents(&self) -> Result<Documents, Error> {
+        let documents = self
+            .urls
+            .iter()
+            .map(|url| {
+                let mut command = Command::new("curl");
+                command.args(["--silent", "--show-error", "--fail", "--location", url]);
+                let mut meta = Metadata::new();
+                meta.insert("url".to_string(), url.clone().into());
+                SourceDocument {
+                    id: url.as_str().into(),
+                    reader: Box::new(CommandOutput::new(command)),
+                    meta,
+                    stat: None,
+                }
+            })
+            .collect::<Vec<_>>();
+        Ok(Box::new(documents.into_iter()))
+    }
+}
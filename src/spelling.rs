@@ -0,0 +1,152 @@
+// The "did you mean" dictionary of an index: its own terms weighted by the
+// number of documents they occur in, so corrections lean towards the words of
+// the corpus, its function names and jargon, rather than those of an English
+// wordlist. Terms of fewer than `MIN_DOCS` documents are left out, as that is
+// where the typos of the corpus itself are.
+//
+// The dictionary is kept in the manifest and built whenever the index is
+// written whole; documents appended since join it at the next rewrite.
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::OnceLock;
+
+use fst::Map;
+use serde::{Deserialize, Serialize};
+
+use crate::analyzer::{fold_case, Analyzer};
+use crate::postings::{self, TermPattern};
+use crate::TermFreqIndex;
+
+pub const MIN_DOCS: usize = 2;
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SpellingDictionary {
+    // Term → documents it occurs in.
+    words: BTreeMap<String, u64>,
+    // The words as an FST for looking up those within some edits of a term,
+    // built the first time a correction is asked for.
+    #[serde(skip)]
+    automaton: OnceLock<Map<Vec<u8>>>,
+}
+
+// Edits a term may be away from its correction: none for the shortest terms,
+// where about every word is a couple of edits from another.
+fn max_edits(term: &str) -> u32 {
+    match term.chars().count() {
+        0..=2 => 0,
+        3..=5 => 1,
+        _ => 2,
+    }
+}
+
+impl SpellingDictionary {
+    pub fn build(docs: &TermFreqIndex) -> Self {
+        let mut doc_freqs = HashMap::<&str, usize>::new();
+        for doc in docs.values() {
+            for term in doc.tf.keys() {
+                *doc_freqs.entry(term.as_str()).or_default() += 1;
+            }
+        }
+        Self::from_doc_freqs(doc_freqs)
+    }
+
+    // From every term and the number of documents it occurs in.
+    pub fn from_doc_freqs<'a>(doc_freqs: impl IntoIterator<Item = (&'a str, usize)>) -> Self {
+        let words = doc_freqs
+            .into_iter()
+            .filter(|&(_, docs)| docs >= MIN_DOCS)
+            .map(|(term, docs)| (term.to_string(), docs as u64))
+            .collect();
+        Self {
+            words,
+            automaton: OnceLock::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    // The term of the dictionary closest to `term`, the one in the most
+    // documents among those as close, unless `term` is in the dictionary.
+    pub fn correct(&self, term: &str) -> Option<String> {
+        let edits = max_edits(term);
+        if edits == 0 || self.words.contains_key(term) {
+            return None;
+        }
+        let automaton = self.automaton.get_or_init(|| {
+            Map::from_iter(self.words.iter().map(|(word, &docs)| (word, docs)))
+                .expect("words are sorted and unique")
+        });
+        let chars = term.chars().collect::<Vec<_>>();
+        postings::expand_dictionary(automaton, &TermPattern::Fuzzy(term, edits), usize::MAX)
+            .into_iter()
+            .min_by_key(|(word, docs)| {
+                let distance = postings::edit_distance(&chars, &word.chars().collect::<Vec<_>>());
+                (distance, Reverse(*docs), word.clone())
+            })
+            .map(|(word, _)| word)
+    }
+
+    // The query with every word whose term has a correction replaced by it,
+    // or none if no word has one. The part of a word the analysis dropped,
+    // like a plural ending, is put back on its correction, which is in
+    // lowercase unless the word was typed in uppercase.
+    pub fn did_you_mean(&self, query: &str, analyzer: &Analyzer) -> Option<String> {
+        if self.is_empty() {
+            return None;
+        }
+        let mut corrected = String::with_capacity(query.len());
+        let mut changed = false;
+        let mut rest = query;
+        while let Some(start) = rest.find(char::is_alphanumeric) {
+            corrected.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let end = rest
+                .find(|c: char| !c.is_alphanumeric())
+                .unwrap_or(rest.len());
+            let (word, after) = rest.split_at(end);
+            rest = after;
+            // Operators and the field names of `field:value` are left alone.
+            let correction = (!matches!(word, "AND" | "OR" | "NOT") && !after.starts_with(':'))
+                .then(|| correct_word(self, word, analyzer))
+                .flatten();
+            match correction {
+                Some(correction) => {
+                    corrected.push_str(&correction);
+                    changed = true;
+                }
+                None => corrected.push_str(word),
+            }
+        }
+        corrected.push_str(rest);
+        changed.then_some(corrected)
+    }
+}
+
+fn correct_word(
+    dictionary: &SpellingDictionary,
+    word: &str,
+    analyzer: &Analyzer,
+) -> Option<String> {
+    let term = analyzer.normalize(word)?;
+    let mut correction = dictionary.correct(&term)?;
+    let mut folded = word.to_string();
+    fold_case(&mut folded);
+    // What stemming made of the end of the word, e.g. "IES" → "Y".
+    let common = folded
+        .chars()
+        .zip(term.chars())
+        .take_while(|(a, b)| a == b)
+        .map(|(c, _)| c.len_utf8())
+        .sum::<usize>();
+    let (dropped, added) = (&folded[common..], &term[common..]);
+    if let Some(stem) = correction.strip_suffix(added) {
+        correction = format!("{stem}{dropped}");
+    }
+    if word.chars().any(char::is_lowercase) {
+        correction = correction.to_lowercase();
+    }
+    Some(correction)
+}
@@ -0,0 +1,122 @@
+// Corpus-level numbers of an index, for the stats subcommand: how big the
+// corpus is, how long its documents are and which terms make up most of it.
+// Nothing is kept of the loaded index.
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::{store, Error, Model};
+
+pub const DEFAULT_TOP_TERMS: usize = 20;
+
+#[derive(Serialize)]
+pub struct TermCount {
+    pub term: String,
+    // Occurrences in the whole corpus.
+    pub count: usize,
+    // Documents it occurs in.
+    pub docs: usize,
+}
+
+#[derive(Serialize)]
+pub struct IndexStats {
+    pub docs: usize,
+    // Terms of every document, counted as often as they occur.
+    pub terms: usize,
+    pub unique_terms: usize,
+    // Sum of the number of distinct terms of every document.
+    pub postings: usize,
+    // Terms per document.
+    pub avg_doc_len: f64,
+    pub max_doc_len: usize,
+    // The most frequent terms, most frequent first, ties broken by term.
+    pub top_terms: Vec<TermCount>,
+}
+
+// Every term of the index with its counts, most frequent first, ties broken
+// by term.
+pub fn term_counts(model: &Model) -> Vec<TermCount> {
+    let mut counts = HashMap::<&str, (usize, usize)>::new();
+    for doc in model.docs.values() {
+        for (term, &freq) in &doc.tf {
+            let (count, docs) = counts.entry(term.as_str()).or_default();
+            *count += freq;
+            *docs += 1;
+        }
+    }
+    let mut ranked = counts.into_iter().collect::<Vec<_>>();
+    ranked.sort_unstable_by_key(|&(term, (count, _))| (Reverse(count), term));
+    ranked
+        .into_iter()
+        .map(|(term, (count, docs))| TermCount {
+            term: term.to_string(),
+            count,
+            docs,
+        })
+        .collect()
+}
+
+impl IndexStats {
+    pub fn of(model: &Model, top: usize) -> Self {
+        let mut terms = 0;
+        let mut postings = 0;
+        let mut max_doc_len = 0;
+        for doc in model.docs.values() {
+            let doc_len = doc.tf.values().sum::<usize>();
+            terms += doc_len;
+            max_doc_len = max_doc_len.max(doc_len);
+            postings += doc.tf.len();
+        }
+        let mut top_terms = term_counts(model);
+        let unique_terms = top_terms.len();
+        top_terms.truncate(top);
+        let docs = model.docs.len();
+        Self {
+            docs,
+            terms,
+            unique_terms,
+            postings,
+            avg_doc_len: if docs == 0 {
+                0.0
+            } else {
+                terms as f64 / docs as f64
+            },
+            max_doc_len,
+            top_terms,
+        }
+    }
+}
+
+// What an analysis makes of a corpus, for compare-analyzers to tell the
+// settings apart by before a whole corpus is indexed with one of them.
+#[derive(Serialize)]
+pub struct AnalysisStats {
+    pub docs: usize,
+    // The terms of the dictionary.
+    pub unique_terms: usize,
+    pub postings: usize,
+    // Documents per term.
+    pub avg_postings_len: f64,
+    // Bytes of the index as an uncompressed binary index.
+    pub index_size: u64,
+}
+
+impl AnalysisStats {
+    pub fn of(model: &Model) -> Result<Self, Error> {
+        let stats = IndexStats::of(model, 0);
+        let index_size =
+            store::binary_size(model).map_err(|err| Error::io("could not size the index", err))?;
+        Ok(Self {
+            docs: stats.docs,
+            unique_terms: stats.unique_terms,
+            postings: stats.postings,
+            avg_postings_len: if stats.unique_terms == 0 {
+                0.0
+            } else {
+                stats.postings as f64 / stats.unique_terms as f64
+            },
+            index_size,
+        })
+    }
+}
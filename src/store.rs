@@ -0,0 +1,800 @@
+// Where an index lives. Search, serve and the other readers only see a Model,
+// the backend of a new index is picked from the extension of the index path
+// unless it is given:
+//
+//   .json            one JSON document, the original format
+//   .tsidx           a binary log of segments that new documents are appended to,
+//                    with postings to search straight from the file bytes,
+//                    optionally compressed with zstd as a whole
+//   .sqlite / .db    an SQLite database
+//   --format sharded a list of JSON shards, one per top-level directory
+//
+// An existing index is opened in the format its first bytes show, whatever
+// its name. SQLite and compressed indexes need the `store-sqlite` and
+// `compression` features; builds without them recognize such indexes but
+// refuse to read or write them.
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::postings;
+use crate::spelling::SpellingDictionary;
+use crate::{
+    Doc, DocId, DocKey, Error, Manifest, MetaValue, Metadata, Model, Positions, TermFreq,
+    TermFreqIndex, INDEX_VERSION,
+};
+
+mod sharded;
+#[cfg(feature = "store-sqlite")]
+mod sqlite;
+use sharded::ShardedStore;
+#[cfg(feature = "store-sqlite")]
+use sqlite::SqliteStore;
+
+pub trait IndexStore {
+    // Loads the index to modify it.
+    fn load(&self) -> Result<Model, Error>;
+
+    // Loads the index only to search it, so backends can avoid taking write
+    // access to it.
+    fn open_readonly(&self) -> Result<Model, Error> {
+        self.load()
+    }
+
+    // Reads the index again to search it, given the index as it was last
+    // read, so backends can reuse what did not change.
+    fn reload(&self, _old: &Model) -> Result<Model, Error> {
+        self.open_readonly()
+    }
+
+    // Replaces the whole index.
+    fn save(&self, model: &Model) -> Result<(), Error>;
+
+    // Inconsistencies of the storage itself that loading does not notice.
+    // Quick checks are cheap enough to run whenever the index is opened.
+    fn check(&self, _thorough: bool) -> Result<Vec<String>, Error> {
+        Ok(Vec::new())
+    }
+
+    // Adds the documents of a segment, replacing documents with the same path.
+    // Backends that cannot append rewrite the index.
+    fn append_segment(&self, segment: &TermFreqIndex) -> Result<(), Error> {
+        append_by_rewrite(self, segment)
+    }
+}
+
+// Refuses an index of a newer format version than this build reads, and
+// brings one of an older version up to the current one. The manifest keeps
+// the version it was read in until the index is saved.
+pub(crate) fn migrate(path: &Path, mut model: Model) -> Result<Model, Error> {
+    // Indexes without a manifest predate versions too.
+    if model.manifest.version == 0 {
+        model.manifest.version = 1;
+    }
+    let version = model.manifest.version;
+    if version > INDEX_VERSION {
+        return Err(Error::IndexVersion(path.display().to_string(), version));
+    }
+    if version < 2 && model.manifest.spelling.is_empty() {
+        model.manifest.spelling = SpellingDictionary::build(&model.docs);
+    }
+    Ok(model)
+}
+
+fn append_by_rewrite(
+    store: &(impl IndexStore + ?Sized),
+    segment: &TermFreqIndex,
+) -> Result<(), Error> {
+    let mut model = store.load()?;
+    model.docs.extend(
+        segment
+            .iter()
+            .map(|(path, doc)| (path.clone(), doc.clone())),
+    );
+    store.save(&model)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StoreFormat {
+    Json,
+    Binary { compressed: bool },
+    Sqlite,
+    Sharded,
+}
+
+const SQLITE_MAGIC: &[u8; 16] = b"SQLite format 3\0";
+const ZSTD_MAGIC: &[u8; 4] = b"\x28\xb5\x2f\xfd";
+
+impl StoreFormat {
+    // `json`, `bin`, `sqlite` or `sharded`, as `index --format` takes them.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(Self::Json),
+            "bin" | "binary" | "tsidx" => Some(Self::Binary { compressed: false }),
+            "sqlite" => Some(Self::Sqlite),
+            "sharded" => Some(Self::Sharded),
+            _ => None,
+        }
+    }
+
+    pub fn from_extension(index_path: &str) -> Self {
+        let ext = Path::new(index_path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+        match ext.as_deref() {
+            Some("tsidx") => Self::Binary { compressed: false },
+            Some("sqlite" | "db") => Self::Sqlite,
+            _ => Self::Json,
+        }
+    }
+
+    // The format of the index at `index_path`, or the one its extension
+    // names if there is none yet or its start is not recognized.
+    pub fn detect(index_path: &str) -> Self {
+        let mut start = [0; 16];
+        let read = File::open(index_path).and_then(|mut file| {
+            let mut read = 0;
+            while read < start.len() {
+                match file.read(&mut start[read..])? {
+                    0 => break,
+                    n => read += n,
+                }
+            }
+            Ok(read)
+        });
+        let start = match read {
+            Ok(read) => &start[..read],
+            Err(_) => return Self::from_extension(index_path),
+        };
+        if start.starts_with(BINARY_MAGIC) {
+            Self::Binary { compressed: false }
+        } else if start.starts_with(ZSTD_MAGIC) {
+            Self::Binary { compressed: true }
+        } else if start.starts_with(SQLITE_MAGIC) {
+            Self::Sqlite
+        } else if start.starts_with(sharded::SHARDS_START) {
+            Self::Sharded
+        } else if start.trim_ascii_start().starts_with(b"{") {
+            Self::Json
+        } else {
+            Self::from_extension(index_path)
+        }
+    }
+}
+
+// The store of an existing index, or of a new one named by its extension.
+pub fn open_store(index_path: &str) -> Box<dyn IndexStore> {
+    open_store_as(index_path, StoreFormat::detect(index_path))
+}
+
+pub fn open_store_as(index_path: &str, format: StoreFormat) -> Box<dyn IndexStore> {
+    let path = PathBuf::from(index_path);
+    match format {
+        StoreFormat::Json => Box::new(JsonStore { path }),
+        #[cfg(not(feature = "compression"))]
+        StoreFormat::Binary { compressed: true } => Box::new(UnsupportedStore {
+            path,
+            format: "a compressed index",
+            feature: "compression",
+        }),
+        StoreFormat::Binary { compressed } => Box::new(BinaryStore { path, compressed }),
+        #[cfg(feature = "store-sqlite")]
+        StoreFormat::Sqlite => Box::new(SqliteStore { path }),
+        #[cfg(not(feature = "store-sqlite"))]
+        StoreFormat::Sqlite => Box::new(UnsupportedStore {
+            path,
+            format: "an SQLite index",
+            feature: "store-sqlite",
+        }),
+        StoreFormat::Sharded => Box::new(ShardedStore { path }),
+    }
+}
+
+// The bytes of a binary index, decompressed, for reading its postings. None
+// for indexes in other formats.
+pub fn binary_index_bytes(index_path: &str) -> Option<io::Result<Vec<u8>>> {
+    match StoreFormat::detect(index_path) {
+        StoreFormat::Binary { compressed: false } => Some(fs::read(index_path)),
+        #[cfg(feature = "compression")]
+        StoreFormat::Binary { compressed: true } => {
+            Some(File::open(index_path).and_then(zstd::decode_all))
+        }
+        #[cfg(not(feature = "compression"))]
+        StoreFormat::Binary { compressed: true } => Some(Err(io::Error::other(
+            "compressed indexes are not supported by this build",
+        ))),
+        _ => None,
+    }
+}
+
+// How the documents of a binary index lie in its file: the segments appended
+// to it and the document records in them. Records replaced by one of the
+// same path in a later segment are dead weight until the index is rewritten.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SegmentStats {
+    pub segments: usize,
+    pub records: usize,
+    pub live_records: usize,
+}
+
+// The segments of a binary index, read without loading its documents. None
+// for indexes in other formats, which are stored whole.
+pub fn segment_stats(index_path: &str) -> Option<io::Result<SegmentStats>> {
+    Some(binary_index_bytes(index_path)?.and_then(|bytes| count_segments(&bytes)))
+}
+
+fn count_segments(bytes: &[u8]) -> io::Result<SegmentStats> {
+    fn skip<'a>(input: &mut &'a [u8], len: usize) -> io::Result<&'a [u8]> {
+        if input.len() < len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let (skipped, rest) = input.split_at(len);
+        *input = rest;
+        Ok(skipped)
+    }
+    fn skip_str<'a>(input: &mut &'a [u8]) -> io::Result<&'a [u8]> {
+        let len = read_u32(input)?;
+        skip(input, len)
+    }
+    let mut input = bytes;
+    if skip(&mut input, BINARY_MAGIC.len())? != BINARY_MAGIC {
+        return Err(io::Error::other("not a tinySearch binary index"));
+    }
+    let mut stats = SegmentStats::default();
+    let mut paths = HashSet::new();
+    while let Some((&tag, rest)) = input.split_first() {
+        input = rest;
+        match tag {
+            b'M' => {
+                skip_str(&mut input)?;
+            }
+            b'S' => {
+                stats.segments += 1;
+                for _ in 0..read_u32(&mut input)? {
+                    stats.records += 1;
+                    paths.insert(skip_str(&mut input)?);
+                    for _ in 0..read_u32(&mut input)? {
+                        skip_str(&mut input)?;
+                        skip(&mut input, 8)?;
+                    }
+                    for _ in 0..read_u32(&mut input)? {
+                        skip_str(&mut input)?;
+                        skip_str(&mut input)?;
+                    }
+                }
+            }
+            b'L' => {
+                for _ in 0..read_u32(&mut input)? {
+                    skip_str(&mut input)?;
+                    for _ in 0..read_u32(&mut input)? {
+                        skip_str(&mut input)?;
+                        let positions = read_u32(&mut input)?;
+                        skip(&mut input, positions * 4)?;
+                    }
+                }
+            }
+            b'I' => {
+                for _ in 0..read_u32(&mut input)? {
+                    skip_str(&mut input)?;
+                    skip(&mut input, 4)?;
+                }
+            }
+            b'V' => {
+                for _ in 0..read_u32(&mut input)? {
+                    skip_str(&mut input)?;
+                    for _ in 0..read_u32(&mut input)? {
+                        skip_str(&mut input)?;
+                        for _ in 0..read_u32(&mut input)? {
+                            skip_str(&mut input)?;
+                        }
+                    }
+                }
+            }
+            b'P' => {
+                let len = read_u64(&mut input)? as usize + postings::BLOCK_TRAILER_LEN;
+                skip(&mut input, len)?;
+            }
+            _ => return Err(io::Error::other(format!("unknown block {:?}", tag as char))),
+        }
+    }
+    stats.live_records = paths.len();
+    Ok(stats)
+}
+
+// The new index is written next to the old one and renamed over it, so
+// readers never see a half-written file.
+fn replace_file(
+    path: &Path,
+    write: impl FnOnce(BufWriter<File>) -> io::Result<()>,
+) -> Result<(), Error> {
+    let tmp_path = PathBuf::from(format!("{}.tmp", path.display()));
+    let file = File::create(&tmp_path).map_err(|err| {
+        Error::io(
+            format!(
+                "could not create index file {tmp_path}",
+                tmp_path = tmp_path.display()
+            ),
+            err,
+        )
+    })?;
+    write(BufWriter::new(file)).map_err(|err| {
+        Error::io(
+            format!(
+                "could not write to index file {tmp_path}",
+                tmp_path = tmp_path.display()
+            ),
+            err,
+        )
+    })?;
+    fs::rename(&tmp_path, path).map_err(|err| {
+        Error::io(
+            format!("could not replace index file {path}", path = path.display()),
+            err,
+        )
+    })
+}
+
+fn open_file(path: &Path) -> Result<BufReader<File>, Error> {
+    let file = File::open(path).map_err(|err| {
+        Error::io(
+            format!("could not open index file {path}", path = path.display()),
+            err,
+        )
+    })?;
+    Ok(BufReader::new(file))
+}
+
+pub struct JsonStore {
+    path: PathBuf,
+}
+
+impl IndexStore for JsonStore {
+    fn load(&self) -> Result<Model, Error> {
+        let path = &self.path;
+        match serde_json::from_reader(open_file(path)?) {
+            Ok(model) => migrate(path, model),
+            // A newer version may hold what this build cannot parse, which is
+            // better told than the parse error.
+            Err(err) => match json_version(path) {
+                Some(version) if version > INDEX_VERSION => {
+                    Err(Error::IndexVersion(path.display().to_string(), version))
+                }
+                _ => Err(Error::json(
+                    format!("could not parse index file {path}", path = path.display()),
+                    err,
+                )),
+            },
+        }
+    }
+
+    fn save(&self, model: &Model) -> Result<(), Error> {
+        replace_file(&self.path, |mut file| {
+            serde_json::to_writer(&mut file, model)?;
+            file.flush()
+        })
+    }
+}
+
+// The format version in the manifest of a JSON index, if it has one.
+fn json_version(path: &Path) -> Option<u32> {
+    #[derive(Deserialize)]
+    struct Versioned {
+        manifest: VersionOnly,
+    }
+    #[derive(Deserialize)]
+    struct VersionOnly {
+        version: u32,
+    }
+    let file = BufReader::new(File::open(path).ok()?);
+    let versioned: Versioned = serde_json::from_reader(file).ok()?;
+    Some(versioned.manifest.version)
+}
+
+// A magic header followed by blocks:
+//
+//   'M' <len: u32> <manifest as JSON>
+//   'S' <docs: u32> (<path> <terms: u32> (<term> <count: u64>)* <meta: u32> (<key> <value>)*)*
+//   'L' <docs: u32> (<path> <terms: u32> (<term> <positions: u32> <position: u32>*)*)*
+//   'I' <docs: u32> (<path> <id: u32>)*
+//   'V' <docs: u32> (<path> <fields: u32> (<key> <values: u32> <value>*)*)*
+//
+// where an 'L' block follows the 'S' block of documents that have positions,
+// an 'I' block gives the IDs of the documents of the 'S' block before it and
+// a 'V' block the multi-valued metadata fields of its documents, whose 'S'
+// value joins them with commas. Strings are stored as <len: u32> <utf-8
+// bytes> and all integers in little endian. Appending a segment appends an 'S' block, and documents in later
+// blocks replace earlier ones with the same path, as a later 'M' block
+// replaces the manifest.
+const BINARY_MAGIC: &[u8; 8] = b"TSIDX\x00\x00\x01";
+
+pub struct BinaryStore {
+    path: PathBuf,
+    // The whole file is one zstd frame. Compressed indexes are smaller but
+    // rewritten on every append.
+    compressed: bool,
+}
+
+// Level 3 is zstd's default, compressing well at nearly the speed of writing.
+#[cfg(feature = "compression")]
+const ZSTD_LEVEL: i32 = 3;
+
+fn write_u32(out: &mut impl Write, n: usize) -> io::Result<()> {
+    let n = u32::try_from(n).map_err(|_| io::Error::other("length does not fit in 32 bits"))?;
+    out.write_all(&n.to_le_bytes())
+}
+
+fn write_str(out: &mut impl Write, s: &str) -> io::Result<()> {
+    write_u32(out, s.len())?;
+    out.write_all(s.as_bytes())
+}
+
+fn write_segment(out: &mut impl Write, segment: &TermFreqIndex) -> io::Result<()> {
+    // In path order, so that indexing the same files again writes the same
+    // bytes.
+    let mut segment = segment.iter().collect::<Vec<_>>();
+    segment.sort_by_key(|(path, _)| *path);
+    out.write_all(b"S")?;
+    write_u32(out, segment.len())?;
+    for &(path, doc) in &segment {
+        write_str(out, &path.to_string_lossy())?;
+        write_u32(out, doc.tf.len())?;
+        for (term, count) in &doc.tf {
+            write_str(out, term)?;
+            out.write_all(&(*count as u64).to_le_bytes())?;
+        }
+        write_u32(out, doc.meta.len())?;
+        for (key, value) in &doc.meta {
+            write_str(out, key)?;
+            write_str(out, &value.to_string())?;
+        }
+    }
+    let numbered = segment
+        .iter()
+        .filter(|(_, doc)| doc.id != 0)
+        .collect::<Vec<_>>();
+    if !numbered.is_empty() {
+        out.write_all(b"I")?;
+        write_u32(out, numbered.len())?;
+        for (path, doc) in numbered {
+            write_str(out, &path.to_string_lossy())?;
+            out.write_all(&doc.id.to_le_bytes())?;
+        }
+    }
+    let listed = segment
+        .iter()
+        .filter(|(_, doc)| doc.meta.values().any(MetaValue::is_many))
+        .collect::<Vec<_>>();
+    if !listed.is_empty() {
+        out.write_all(b"V")?;
+        write_u32(out, listed.len())?;
+        for (path, doc) in listed {
+            write_str(out, &path.to_string_lossy())?;
+            let fields = doc.meta.iter().filter(|(_, value)| value.is_many());
+            write_u32(out, fields.clone().count())?;
+            for (key, value) in fields {
+                write_str(out, key)?;
+                write_u32(out, value.values().len())?;
+                for value in value.values() {
+                    write_str(out, value)?;
+                }
+            }
+        }
+    }
+    let positioned = segment
+        .iter()
+        .filter(|(_, doc)| !doc.positions.is_empty())
+        .collect::<Vec<_>>();
+    if positioned.is_empty() {
+        return Ok(());
+    }
+    out.write_all(b"L")?;
+    write_u32(out, positioned.len())?;
+    for (path, doc) in positioned {
+        write_str(out, &path.to_string_lossy())?;
+        write_u32(out, doc.positions.len())?;
+        for (term, positions) in &doc.positions {
+            write_str(out, term)?;
+            write_u32(out, positions.len())?;
+            for &position in positions {
+                out.write_all(&position.to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<usize> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes) as usize)
+}
+
+fn read_u64(input: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_str(input: &mut impl Read) -> io::Result<String> {
+    let mut bytes = vec![0; read_u32(input)?];
+    input.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(io::Error::other)
+}
+
+// Reads the blocks of a binary index. With `salvage`, reading stops at the
+// first block that cannot be read rather than failing, like the end of a
+// segment cut short by a crash, keeping the documents read before it, and
+// why it stopped is returned along. The manifest has to be read though.
+fn read_binary(input: &mut impl Read, salvage: bool) -> io::Result<(Model, Option<io::Error>)> {
+    let mut magic = [0; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != BINARY_MAGIC {
+        return Err(io::Error::other("not a tinySearch binary index"));
+    }
+    let mut model = Model::default();
+    let mut has_manifest = false;
+    let mut broken = None;
+    loop {
+        match read_block(input, &mut model) {
+            Ok(None) => break,
+            Ok(Some(tag)) => {
+                has_manifest |= tag == b'M';
+                // The blocks of a newer version may not be readable, and
+                // loading refuses it anyway.
+                if model.manifest.version > INDEX_VERSION {
+                    return Ok((model, None));
+                }
+            }
+            Err(err) if salvage && has_manifest => {
+                broken = Some(err);
+                break;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    model.assign_doc_ids();
+    Ok((model, broken))
+}
+
+// Reads the next block into the model, returning its tag, or None at the end
+// of the index.
+fn read_block(input: &mut impl Read, model: &mut Model) -> io::Result<Option<u8>> {
+    let mut tag = [0; 1];
+    match input.read(&mut tag)? {
+        0 => return Ok(None),
+        _ if tag[0] == b'M' => {
+            model.manifest = serde_json::from_str(&read_str(input)?)?;
+        }
+        _ if tag[0] == b'S' => {
+            for _ in 0..read_u32(input)? {
+                let path = DocKey::from(read_str(input)?);
+                let mut tf = TermFreq::default();
+                for _ in 0..read_u32(input)? {
+                    let term = read_str(input)?;
+                    tf.insert(term, read_u64(input)? as usize);
+                }
+                let mut meta = Metadata::new();
+                for _ in 0..read_u32(input)? {
+                    let key = read_str(input)?;
+                    meta.insert(key, read_str(input)?.into());
+                }
+                let doc = Doc {
+                    tf,
+                    meta,
+                    ..Doc::default()
+                };
+                model.docs.insert(path, doc);
+            }
+        }
+        _ if tag[0] == b'L' => {
+            for _ in 0..read_u32(input)? {
+                let path = DocKey::from(read_str(input)?);
+                let mut positions = Positions::default();
+                for _ in 0..read_u32(input)? {
+                    let term = read_str(input)?;
+                    let list = (0..read_u32(input)?)
+                        .map(|_| read_u32(input).map(|position| position as u32))
+                        .collect::<io::Result<Vec<_>>>()?;
+                    positions.insert(term, list);
+                }
+                if let Some(doc) = model.docs.get_mut(&path) {
+                    doc.positions = positions;
+                }
+            }
+        }
+        _ if tag[0] == b'I' => {
+            for _ in 0..read_u32(input)? {
+                let path = DocKey::from(read_str(input)?);
+                let id = read_u32(input)? as DocId;
+                if let Some(doc) = model.docs.get_mut(&path) {
+                    doc.id = id;
+                }
+            }
+        }
+        _ if tag[0] == b'V' => {
+            for _ in 0..read_u32(input)? {
+                let path = DocKey::from(read_str(input)?);
+                let mut fields = Vec::new();
+                for _ in 0..read_u32(input)? {
+                    let key = read_str(input)?;
+                    let values = (0..read_u32(input)?)
+                        .map(|_| read_str(input))
+                        .collect::<io::Result<Vec<_>>>()?;
+                    fields.push((key, MetaValue::Many(values)));
+                }
+                if let Some(doc) = model.docs.get_mut(&path) {
+                    doc.meta.extend(fields);
+                }
+            }
+        }
+        // Postings only speed up searching, the segments have all the data.
+        _ if tag[0] == b'P' => {
+            let len = read_u64(input)? as usize + postings::BLOCK_TRAILER_LEN;
+            io::copy(&mut input.by_ref().take(len as u64), &mut io::sink())?;
+        }
+        _ => {
+            return Err(io::Error::other(format!(
+                "unknown block {:?}",
+                tag[0] as char
+            )))
+        }
+    }
+    Ok(Some(tag[0]))
+}
+
+fn binary_bytes(model: &Model) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    bytes.write_all(BINARY_MAGIC)?;
+    bytes.write_all(b"M")?;
+    write_str(&mut bytes, &serde_json::to_string(&model.manifest)?)?;
+    write_segment(&mut bytes, &model.docs)?;
+    let offset = bytes.len() as u64;
+    postings::write_postings_block(&mut bytes, offset, &model.docs)?;
+    Ok(bytes)
+}
+
+// The size of the index saved as an uncompressed binary index, without
+// writing it.
+pub fn binary_size(model: &Model) -> io::Result<u64> {
+    Ok(binary_bytes(model)?.len() as u64)
+}
+
+impl BinaryStore {
+    fn read(&self, salvage: bool) -> Result<(Model, Option<io::Error>), Error> {
+        let path = &self.path;
+        let file = open_file(path)?;
+        #[cfg(feature = "compression")]
+        let read = if self.compressed {
+            zstd::Decoder::with_buffer(file).and_then(|mut input| read_binary(&mut input, salvage))
+        } else {
+            read_binary(&mut { file }, salvage)
+        };
+        #[cfg(not(feature = "compression"))]
+        let read = read_binary(&mut { file }, salvage);
+        read.map_err(|err| {
+            Error::io(
+                format!("could not parse index file {path}", path = path.display()),
+                err,
+            )
+        })
+    }
+}
+
+// Reads as much of a binary index as can be read, for repairing one whose
+// file is damaged past some point: the documents before it are kept, and why
+// reading stopped is returned along. None for indexes in other formats.
+pub fn salvage_binary(index_path: &str) -> Option<Result<(Model, Option<String>), Error>> {
+    let StoreFormat::Binary { compressed } = StoreFormat::detect(index_path) else {
+        return None;
+    };
+    let store = BinaryStore {
+        path: PathBuf::from(index_path),
+        compressed,
+    };
+    Some(store.read(true).and_then(|(model, broken)| {
+        let model = migrate(&store.path, model)?;
+        Ok((model, broken.map(|err| err.to_string())))
+    }))
+}
+
+impl IndexStore for BinaryStore {
+    fn load(&self) -> Result<Model, Error> {
+        let path = &self.path;
+        let (model, _) = self.read(false)?;
+        migrate(path, model)
+    }
+
+    fn save(&self, model: &Model) -> Result<(), Error> {
+        replace_file(&self.path, |file| {
+            let bytes = binary_bytes(model)?;
+            let mut file = file;
+            #[cfg(feature = "compression")]
+            if self.compressed {
+                zstd::stream::copy_encode(&bytes[..], &mut file, ZSTD_LEVEL)?;
+                return file.flush();
+            }
+            file.write_all(&bytes)?;
+            file.flush()
+        })
+    }
+
+    fn append_segment(&self, segment: &TermFreqIndex) -> Result<(), Error> {
+        if self.compressed {
+            return append_by_rewrite(self, segment);
+        }
+        let path = &self.path;
+        let file = OpenOptions::new().append(true).open(path).map_err(|err| {
+            Error::io(
+                format!("could not open index file {path}", path = path.display()),
+                err,
+            )
+        })?;
+        let mut file = BufWriter::new(file);
+        write_segment(&mut file, segment)
+            .and_then(|()| file.flush())
+            .map_err(|err| {
+                Error::io(
+                    format!(
+                        "could not append to index file {path}",
+                        path = path.display()
+                    ),
+                    err,
+                )
+            })
+    }
+}
+
+// Appends a manifest block to an uncompressed binary index, which replaces
+// the manifest of the blocks before it.
+pub fn append_manifest(index_path: &str, manifest: &Manifest) -> Result<(), Error> {
+    let path = Path::new(index_path);
+    let append = || -> io::Result<()> {
+        let mut file = BufWriter::new(OpenOptions::new().append(true).open(path)?);
+        file.write_all(b"M")?;
+        write_str(&mut file, &serde_json::to_string(manifest)?)?;
+        file.flush()
+    };
+    append().map_err(|err| {
+        Error::io(
+            format!(
+                "could not append to index file {path}",
+                path = path.display()
+            ),
+            err,
+        )
+    })
+}
+
+// An index in a format this build leaves out. It is recognized, so it is not
+// mistaken for another format and overwritten, but cannot be read or written.
+#[cfg(not(all(feature = "store-sqlite", feature = "compression")))]
+struct UnsupportedStore {
+    path: PathBuf,
+    format: &'static str,
+    feature: &'static str,
+}
+
+#[cfg(not(all(feature = "store-sqlite", feature = "compression")))]
+impl IndexStore for UnsupportedStore {
+    fn load(&self) -> Result<Model, Error> {
+        Err(Error::invalid(format!(
+            "{path} is {format}, which this build of tinySearch cannot read; rebuild it with the {feature} feature",
+            path = self.path.display(),
+            format = self.format,
+            feature = self.feature,
+        )))
+    }
+
+    fn save(&self, _model: &Model) -> Result<(), Error> {
+        Err(Error::invalid(format!(
+            "cannot write {path} as {format}, this build of tinySearch has no {feature} feature",
+            path = self.path.display(),
+            format = self.format,
+            feature = self.feature,
+        )))
+    }
+}
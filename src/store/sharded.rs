@@ -0,0 +1,261 @@
+// Indexes split by the top-level directories of their documents: the index
+// file lists the shards with the manifest, and every shard is a JSON file of
+// the documents under one directory in `<index>.shards/`. Shard files are
+// named by a fingerprint of their documents, so saving writes only the shards
+// whose documents changed and a reload reads only those, while readers see
+// the documents of all shards as one index.
+//
+// The top-level directories are those right below the directory all the
+// documents share, e.g. `blog/` and `docs/` for an index of `site/blog` and
+// `site/docs`; documents in that shared directory itself make a shard too.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::hash::Hasher;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::{migrate, open_file, replace_file, IndexStore};
+use crate::fxhash::FxHasher;
+use crate::{Doc, DocKey, Error, Manifest, Model, TermFreqIndex};
+
+pub struct ShardedStore {
+    pub(super) path: PathBuf,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ShardList {
+    // First, so the format shows at the start of the file.
+    shards: Vec<Shard>,
+    // Path components of the directories of the shards.
+    depth: usize,
+    manifest: Manifest,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Shard {
+    dir: PathBuf,
+    // In the shards folder.
+    file: String,
+    fingerprint: String,
+    docs: usize,
+}
+
+type ShardDocs<'a> = BTreeMap<&'a DocKey, &'a Doc>;
+
+pub(super) const SHARDS_START: &[u8] = b"{\"shards\":";
+
+fn shards_folder(index_path: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.shards", index_path.display()))
+}
+
+// One more component than the directory all documents are in.
+fn shard_depth(docs: &TermFreqIndex) -> usize {
+    let mut dirs = docs
+        .keys()
+        .map(|path| path.parent().unwrap_or(Path::new("")));
+    let Some(first) = dirs.next() else {
+        return 0;
+    };
+    let mut common = first.components().collect::<Vec<_>>();
+    for dir in dirs {
+        let shared = common
+            .iter()
+            .zip(dir.components())
+            .take_while(|(a, b)| *a == b)
+            .count();
+        common.truncate(shared);
+    }
+    common.len() + 1
+}
+
+fn split(docs: &TermFreqIndex, depth: usize) -> BTreeMap<PathBuf, ShardDocs<'_>> {
+    let mut shards = BTreeMap::<PathBuf, ShardDocs>::new();
+    for (path, doc) in docs {
+        let dir = path
+            .parent()
+            .map(|dir| dir.components().take(depth).collect())
+            .unwrap_or_default();
+        shards.entry(dir).or_default().insert(path, doc);
+    }
+    shards
+}
+
+// Hashes the documents with their terms and metadata in sorted order, as
+// JSON values keep their keys, so equal documents hash alike whatever order
+// their maps are in.
+fn fingerprint(docs: &ShardDocs) -> String {
+    let mut hasher = FxHasher::default();
+    for (path, doc) in docs {
+        hasher.write(path.as_os_str().as_encoded_bytes());
+        hasher.write(&[0]);
+        let value = serde_json::to_value(doc).expect("documents serialize to JSON");
+        hasher.write(value.to_string().as_bytes());
+    }
+    format!("{:016x}", hasher.finish())
+}
+
+// The directory as a file name, e.g. `site_blog`.
+fn file_stem(dir: &Path) -> String {
+    let stem = dir
+        .to_string_lossy()
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '.' => c,
+            _ => '_',
+        })
+        .collect::<String>();
+    let stem = stem.trim_matches(|c| c == '_' || c == '.');
+    match stem {
+        "" => "root".to_string(),
+        stem => stem.chars().take(64).collect(),
+    }
+}
+
+impl ShardedStore {
+    fn read_list(&self) -> Result<ShardList, Error> {
+        let path = &self.path;
+        serde_json::from_reader(open_file(path)?).map_err(|err| {
+            Error::json(
+                format!("could not parse index file {path}", path = path.display()),
+                err,
+            )
+        })
+    }
+
+    fn read_shard(&self, shard: &Shard) -> Result<TermFreqIndex, Error> {
+        let path = shards_folder(&self.path).join(&shard.file);
+        serde_json::from_reader(open_file(&path)?).map_err(|err| {
+            Error::json(
+                format!("could not parse index shard {path}", path = path.display()),
+                err,
+            )
+        })
+    }
+}
+
+impl IndexStore for ShardedStore {
+    fn load(&self) -> Result<Model, Error> {
+        let list = self.read_list()?;
+        let mut docs = TermFreqIndex::default();
+        for shard in &list.shards {
+            docs.extend(self.read_shard(shard)?);
+        }
+        let model = Model {
+            manifest: list.manifest,
+            docs,
+        };
+        migrate(&self.path, model)
+    }
+
+    // Takes the documents of the shards that did not change from `old`.
+    fn reload(&self, old: &Model) -> Result<Model, Error> {
+        let list = self.read_list()?;
+        let mut old_shards = split(&old.docs, list.depth);
+        let mut docs = TermFreqIndex::default();
+        for shard in &list.shards {
+            match old_shards.remove(&shard.dir) {
+                Some(old_docs) if fingerprint(&old_docs) == shard.fingerprint => docs.extend(
+                    old_docs
+                        .into_iter()
+                        .map(|(path, doc)| (path.clone(), doc.clone())),
+                ),
+                _ => docs.extend(self.read_shard(shard)?),
+            }
+        }
+        let model = Model {
+            manifest: list.manifest,
+            docs,
+        };
+        migrate(&self.path, model)
+    }
+
+    // Writes the shards not already there, then the list, and then removes
+    // the shards of the old list it no longer names.
+    fn save(&self, model: &Model) -> Result<(), Error> {
+        let old = self.read_list().ok();
+        let folder = shards_folder(&self.path);
+        fs::create_dir_all(&folder).map_err(|err| {
+            Error::io(
+                format!(
+                    "could not create shards folder {folder}",
+                    folder = folder.display()
+                ),
+                err,
+            )
+        })?;
+        let depth = shard_depth(&model.docs);
+        let mut shards = Vec::new();
+        for (dir, docs) in split(&model.docs, depth) {
+            let fingerprint = fingerprint(&docs);
+            let file = format!("{}-{fingerprint}.json", file_stem(&dir));
+            let path = folder.join(&file);
+            if !path.exists() {
+                replace_file(&path, |mut out| {
+                    serde_json::to_writer(&mut out, &docs)?;
+                    out.flush()
+                })?;
+            }
+            shards.push(Shard {
+                dir,
+                file,
+                fingerprint,
+                docs: docs.len(),
+            });
+        }
+        let list = ShardList {
+            shards,
+            depth,
+            manifest: model.manifest.clone(),
+        };
+        replace_file(&self.path, |mut out| {
+            serde_json::to_writer(&mut out, &list)?;
+            out.flush()
+        })?;
+        let listed = list
+            .shards
+            .iter()
+            .map(|shard| shard.file.as_str())
+            .collect::<BTreeSet<_>>();
+        for shard in old.into_iter().flat_map(|old| old.shards) {
+            if !listed.contains(shard.file.as_str()) {
+                // One left behind takes space but is never read.
+                let _ = fs::remove_file(folder.join(&shard.file));
+            }
+        }
+        Ok(())
+    }
+
+    // Shards missing, and when thorough, shards whose documents are not the
+    // ones they were listed with.
+    fn check(&self, thorough: bool) -> Result<Vec<String>, Error> {
+        let list = self.read_list()?;
+        let folder = shards_folder(&self.path);
+        let mut problems = Vec::new();
+        for shard in &list.shards {
+            let path = folder.join(&shard.file);
+            if !path.exists() {
+                problems.push(format!(
+                    "shard of {dir} is missing: {path}",
+                    dir = shard.dir.display(),
+                    path = path.display()
+                ));
+                continue;
+            }
+            if !thorough {
+                continue;
+            }
+            let docs = self.read_shard(shard)?;
+            let docs = docs.iter().collect::<ShardDocs>();
+            if docs.len() != shard.docs || fingerprint(&docs) != shard.fingerprint {
+                problems.push(format!(
+                    "shard of {dir} does not hold the documents it was listed with: {path}",
+                    dir = shard.dir.display(),
+                    path = path.display()
+                ));
+            }
+        }
+        Ok(problems)
+    }
+}
@@ -0,0 +1,243 @@
+// Indexes in an SQLite database: one row per document, term and position
+// list, so appending a segment replaces the rows of its documents in place.
+use std::path::PathBuf;
+
+use rusqlite::{params, Connection, OpenFlags};
+
+use super::IndexStore;
+use crate::{Doc, DocId, DocKey, Error, Model, TermFreqIndex};
+
+pub struct SqliteStore {
+    pub(super) path: PathBuf,
+}
+
+const SQLITE_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS manifest (json TEXT NOT NULL);
+    CREATE TABLE IF NOT EXISTS docs (path TEXT PRIMARY KEY, meta TEXT NOT NULL);
+    CREATE TABLE IF NOT EXISTS terms (
+        path TEXT NOT NULL REFERENCES docs(path) ON DELETE CASCADE,
+        term TEXT NOT NULL,
+        count INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS terms_by_path ON terms(path);
+    CREATE TABLE IF NOT EXISTS positions (
+        path TEXT NOT NULL REFERENCES docs(path) ON DELETE CASCADE,
+        term TEXT NOT NULL,
+        positions TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS positions_by_path ON positions(path);
+    CREATE TABLE IF NOT EXISTS doc_ids (
+        path TEXT PRIMARY KEY REFERENCES docs(path) ON DELETE CASCADE,
+        id INTEGER NOT NULL
+    );
+";
+
+impl SqliteStore {
+    fn connect(&self, flags: OpenFlags) -> Result<Connection, Error> {
+        let path = &self.path;
+        Connection::open_with_flags(path, flags).map_err(|err| {
+            Error::Sqlite(
+                format!(
+                    "could not open index database {path}",
+                    path = path.display()
+                ),
+                err,
+            )
+        })
+    }
+
+    fn error(&self, err: rusqlite::Error) -> Error {
+        Error::Sqlite(
+            format!("index database {path}", path = self.path.display()),
+            err,
+        )
+    }
+
+    fn read(&self, conn: &Connection) -> rusqlite::Result<Model> {
+        let mut model = Model::default();
+        let manifest: Option<String> = conn
+            .query_row("SELECT json FROM manifest", [], |row| row.get(0))
+            .ok();
+        if let Some(manifest) = manifest {
+            model.manifest = serde_json::from_str(&manifest).unwrap_or_default();
+        }
+        let mut docs = conn.prepare("SELECT path, meta FROM docs")?;
+        let rows = docs.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        for row in rows {
+            let (path, meta) = row?;
+            let doc = Doc {
+                meta: serde_json::from_str(&meta).unwrap_or_default(),
+                ..Doc::default()
+            };
+            model.docs.insert(DocKey::from(path), doc);
+        }
+        let mut terms = conn.prepare("SELECT path, term, count FROM terms")?;
+        let rows = terms.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, i64>(2)?,
+            ))
+        })?;
+        for row in rows {
+            let (path, term, count) = row?;
+            if let Some(doc) = model.docs.get_mut(path.as_str()) {
+                doc.tf.insert(term, count as usize);
+            }
+        }
+        // Space separated numbers.
+        let mut positions = conn.prepare("SELECT path, term, positions FROM positions")?;
+        let rows = positions.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?;
+        for row in rows {
+            let (path, term, list) = row?;
+            if let Some(doc) = model.docs.get_mut(path.as_str()) {
+                let list = list.split(' ').filter_map(|n| n.parse().ok()).collect();
+                doc.positions.insert(term, list);
+            }
+        }
+        // Databases written before documents had IDs have no table of them.
+        let has_ids = conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'doc_ids'",
+            [],
+            |row| row.get::<_, i64>(0),
+        )? > 0;
+        if has_ids {
+            let mut ids = conn.prepare("SELECT path, id FROM doc_ids")?;
+            let rows = ids.query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, DocId>(1)?))
+            })?;
+            for row in rows {
+                let (path, id) = row?;
+                if let Some(doc) = model.docs.get_mut(path.as_str()) {
+                    doc.id = id;
+                }
+            }
+        }
+        model.assign_doc_ids();
+        Ok(model)
+    }
+
+    fn write_docs(conn: &Connection, docs: &TermFreqIndex) -> rusqlite::Result<()> {
+        let mut insert_doc =
+            conn.prepare("INSERT OR REPLACE INTO docs (path, meta) VALUES (?1, ?2)")?;
+        let mut delete_terms = conn.prepare("DELETE FROM terms WHERE path = ?1")?;
+        let mut insert_term =
+            conn.prepare("INSERT INTO terms (path, term, count) VALUES (?1, ?2, ?3)")?;
+        let mut delete_positions = conn.prepare("DELETE FROM positions WHERE path = ?1")?;
+        let mut insert_id =
+            conn.prepare("INSERT OR REPLACE INTO doc_ids (path, id) VALUES (?1, ?2)")?;
+        let mut insert_positions =
+            conn.prepare("INSERT INTO positions (path, term, positions) VALUES (?1, ?2, ?3)")?;
+        for (path, doc) in docs {
+            let path = path.to_string_lossy();
+            let meta = serde_json::to_string(&doc.meta).unwrap_or_default();
+            delete_terms.execute(params![path])?;
+            insert_doc.execute(params![path, meta])?;
+            if doc.id != 0 {
+                insert_id.execute(params![path, doc.id])?;
+            }
+            for (term, count) in &doc.tf {
+                insert_term.execute(params![path, term, *count as i64])?;
+            }
+            delete_positions.execute(params![path])?;
+            for (term, positions) in &doc.positions {
+                let positions = positions
+                    .iter()
+                    .map(u32::to_string)
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                insert_positions.execute(params![path, term, positions])?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl IndexStore for SqliteStore {
+    fn load(&self) -> Result<Model, Error> {
+        let conn = self.connect(OpenFlags::SQLITE_OPEN_READ_WRITE)?;
+        let model = self.read(&conn).map_err(|err| self.error(err))?;
+        super::migrate(&self.path, model)
+    }
+
+    fn open_readonly(&self) -> Result<Model, Error> {
+        let conn = self.connect(OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        let model = self.read(&conn).map_err(|err| self.error(err))?;
+        super::migrate(&self.path, model)
+    }
+
+    fn check(&self, thorough: bool) -> Result<Vec<String>, Error> {
+        let conn = self.connect(OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        let result = (|| {
+            let pragma = if thorough {
+                "PRAGMA integrity_check"
+            } else {
+                "PRAGMA quick_check"
+            };
+            let mut problems = conn
+                .prepare(pragma)?
+                .query_map([], |row| row.get::<_, String>(0))?
+                .filter(|row| !matches!(row.as_deref(), Ok("ok")))
+                .map(|row| row.map(|problem| format!("database: {problem}")))
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            if !thorough {
+                return Ok(problems);
+            }
+            let count = |sql: &str| conn.query_row(sql, [], |row| row.get::<_, i64>(0));
+            let orphans =
+                count("SELECT COUNT(*) FROM terms WHERE path NOT IN (SELECT path FROM docs)")?;
+            if orphans > 0 {
+                problems.push(format!("{orphans} term rows belong to no document"));
+            }
+            let duplicates = count(
+                "SELECT COUNT(*) FROM (SELECT 1 FROM terms GROUP BY path, term HAVING COUNT(*) > 1)",
+            )?;
+            if duplicates > 0 {
+                problems.push(format!(
+                    "{duplicates} terms are stored more than once for the same document"
+                ));
+            }
+            let empty = count("SELECT COUNT(*) FROM terms WHERE count <= 0")?;
+            if empty > 0 {
+                problems.push(format!("{empty} term rows have a count of zero or less"));
+            }
+            Ok(problems)
+        })();
+        result.map_err(|err| self.error(err))
+    }
+
+    fn save(&self, model: &Model) -> Result<(), Error> {
+        let mut conn = self.connect(OpenFlags::default())?;
+        let result = (|| {
+            let tx = conn.transaction()?;
+            tx.execute_batch(SQLITE_SCHEMA)?;
+            tx.execute_batch(
+                "DELETE FROM doc_ids; DELETE FROM positions; DELETE FROM terms; DELETE FROM docs; DELETE FROM manifest;",
+            )?;
+            let manifest = serde_json::to_string(&model.manifest).unwrap_or_default();
+            tx.execute("INSERT INTO manifest (json) VALUES (?1)", params![manifest])?;
+            Self::write_docs(&tx, &model.docs)?;
+            tx.commit()
+        })();
+        result.map_err(|err| self.error(err))
+    }
+
+    fn append_segment(&self, segment: &TermFreqIndex) -> Result<(), Error> {
+        let mut conn = self.connect(OpenFlags::SQLITE_OPEN_READ_WRITE)?;
+        let result = (|| {
+            let tx = conn.transaction()?;
+            tx.execute_batch(SQLITE_SCHEMA)?;
+            Self::write_docs(&tx, segment)?;
+            tx.commit()
+        })();
+        result.map_err(|err| self.error(err))
+    }
+}
@@ -0,0 +1,49 @@
+// The vocabulary of an index, for the vocab subcommand: its most frequent
+// terms with the documents they occur in, and the terms so common they tell
+// documents apart no better than stopwords do. Those are suggested as
+// stopwords, in the one-word-per-line format `--stopwords` reads, so the
+// next build of the index can leave them out.
+use std::cmp::Reverse;
+
+use serde::Serialize;
+
+use crate::stats::{self, TermCount};
+use crate::Model;
+
+pub const DEFAULT_VOCAB_TERMS: usize = 1000;
+// Share of the documents a term has to occur in to be suggested.
+pub const DEFAULT_STOPWORD_SHARE: f64 = 0.5;
+
+#[derive(Serialize)]
+pub struct Vocabulary {
+    pub docs: usize,
+    // The most frequent terms, most frequent first.
+    pub terms: Vec<TermCount>,
+    // Terms in at least the share of the documents, in the most documents
+    // first, lowercased like words typed into a stopword list.
+    pub stopwords: Vec<String>,
+}
+
+impl Vocabulary {
+    pub fn of(model: &Model, top: usize, stopword_share: f64) -> Self {
+        let docs = model.docs.len();
+        let mut terms = stats::term_counts(model);
+        // A single document makes every term ubiquitous.
+        let min_docs = ((docs as f64 * stopword_share).ceil() as usize).max(2);
+        let mut common = terms
+            .iter()
+            .filter(|term| term.docs >= min_docs)
+            .collect::<Vec<_>>();
+        common.sort_by_key(|term| (Reverse(term.docs), term.term.as_str()));
+        let stopwords = common
+            .into_iter()
+            .map(|term| term.term.to_lowercase())
+            .collect();
+        terms.truncate(top);
+        Self {
+            docs,
+            terms,
+            stopwords,
+        }
+    }
+}
@@ -0,0 +1,359 @@
+// Directory traversal for indexing. Everything that decides whether a file
+// or directory is visited at all lives here, before any file is opened.
+use std::collections::HashSet;
+use std::fs::{self, Metadata};
+use std::path::{Path, PathBuf};
+
+use crate::exclude::PathPattern;
+use crate::Error;
+
+// Files that operating systems and editors leave behind next to real content.
+const JUNK_FILE_NAMES: &[&str] = &["Thumbs.db", "ehthumbs.db", "desktop.ini", "Icon\r"];
+const JUNK_FILE_SUFFIXES: &[&str] = &["~", ".swp", ".swo", ".swx", ".tmp", ".bak"];
+
+#[derive(Default, Clone)]
+pub struct WalkOptions {
+    // Also visit dotfiles, dot directories and known OS/editor junk.
+    pub hidden: bool,
+    // Do not descend into directories on a different filesystem than the root.
+    pub one_file_system: bool,
+    // Files and directories left out, from `--exclude`: patterns without a
+    // `/` are matched against their names, like `*.log`, the others against
+    // their paths below the root, like `build/**`.
+    pub excludes: Vec<PathPattern>,
+    // When not empty, only the files matching one of these, from `--include`,
+    // matched like the excludes. Directories are always descended into.
+    pub includes: Vec<PathPattern>,
+    // Leave out what the `.gitignore` and `.ignore` files of the folder and
+    // its subfolders ignore, and `.git` itself.
+    pub ignore_files: bool,
+    // Also descend into symlinked directories. Symlinked files are always
+    // indexed, under the path of the link.
+    pub follow_symlinks: bool,
+}
+
+// Names of the files read with `ignore_files`, later ones winning.
+const IGNORE_FILE_NAMES: &[&str] = &[".gitignore", ".ignore"];
+
+// A line of an ignore file.
+struct IgnoreRule {
+    pattern: PathPattern,
+    // `!pattern` brings back what earlier rules ignored.
+    negated: bool,
+    // `pattern/` only matches directories.
+    dir_only: bool,
+    // Patterns with a `/` before their end match paths below the folder of
+    // the ignore file, the others names at any depth.
+    anchored: bool,
+}
+
+// The rules of the ignore files of one folder.
+struct IgnoreFile {
+    dir: PathBuf,
+    rules: Vec<IgnoreRule>,
+}
+
+impl IgnoreFile {
+    fn read(dir: &Path) -> Option<Self> {
+        let mut rules = Vec::new();
+        for name in IGNORE_FILE_NAMES {
+            let Ok(content) = fs::read_to_string(dir.join(name)) else {
+                continue;
+            };
+            rules.extend(content.lines().filter_map(parse_ignore_rule));
+        }
+        if rules.is_empty() {
+            return None;
+        }
+        Some(Self {
+            dir: dir.to_path_buf(),
+            rules,
+        })
+    }
+}
+
+fn parse_ignore_rule(line: &str) -> Option<IgnoreRule> {
+    let line = line.trim_end();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (negated, line) = match line.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, line.strip_prefix('\\').unwrap_or(line)),
+    };
+    let (dir_only, line) = match line.strip_suffix('/') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let anchored = line.contains('/');
+    Some(IgnoreRule {
+        pattern: PathPattern::new(line.strip_prefix('/').unwrap_or(line)),
+        negated,
+        dir_only,
+        anchored,
+    })
+}
+
+// State shared by the whole traversal.
+struct Walk<'a> {
+    options: &'a WalkOptions,
+    root: &'a Path,
+    root_dev: Option<u64>,
+    // (device, inode) of every multiply linked file seen so far, so a file
+    // with several hard links is indexed once.
+    linked: HashSet<(u64, u64)>,
+    // (device, inode) of every directory walked, so one reached again
+    // through a symlink, e.g. a link to a parent, is not walked twice.
+    dirs: HashSet<(u64, u64)>,
+    // The ignore files of the folders from the root down to the current one.
+    ignores: Vec<IgnoreFile>,
+}
+
+impl Walk<'_> {
+    // Whether the last rule of the ignore files matching the path ignores it.
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        for ignore in self.ignores.iter().rev() {
+            let Ok(relative) = path.strip_prefix(&ignore.dir) else {
+                continue;
+            };
+            let rule = ignore.rules.iter().rev().find(|rule| {
+                (is_dir || !rule.dir_only)
+                    && if rule.anchored {
+                        rule.pattern.matches(relative)
+                    } else {
+                        path.file_name()
+                            .is_some_and(|name| rule.pattern.matches(Path::new(name)))
+                    }
+            });
+            if let Some(rule) = rule {
+                return !rule.negated;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(unix)]
+fn device_of(metadata: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.dev())
+}
+
+#[cfg(not(unix))]
+fn device_of(_metadata: &Metadata) -> Option<u64> {
+    None
+}
+
+#[cfg(unix)]
+fn hard_link_id(metadata: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    if metadata.nlink() > 1 {
+        Some((metadata.dev(), metadata.ino()))
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+fn hard_link_id(_metadata: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+#[cfg(unix)]
+fn file_id(metadata: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+fn is_hidden_or_junk(name: &str) -> bool {
+    name.starts_with('.')
+        || (name.starts_with('#') && name.ends_with('#'))
+        || JUNK_FILE_NAMES.contains(&name)
+        || JUNK_FILE_SUFFIXES
+            .iter()
+            .any(|suffix| name.ends_with(suffix))
+}
+
+fn is_skipped(path: &Path, walk: &Walk) -> bool {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+    if !walk.options.hidden && is_hidden_or_junk(name) {
+        return true;
+    }
+    if walk.options.ignore_files && name == ".git" {
+        return true;
+    }
+    walk.options
+        .excludes
+        .iter()
+        .any(|pattern| matches_pattern(pattern, path, walk.root))
+}
+
+// Patterns without a `/` match the name of the file, the others its path
+// below the root.
+fn matches_pattern(pattern: &PathPattern, path: &Path, root: &Path) -> bool {
+    if pattern.has_slash() {
+        pattern.matches(path.strip_prefix(root).unwrap_or(path))
+    } else {
+        path.file_name()
+            .is_some_and(|name| pattern.matches(Path::new(name)))
+    }
+}
+
+fn is_included(path: &Path, walk: &Walk) -> bool {
+    let includes = &walk.options.includes;
+    includes.is_empty()
+        || includes
+            .iter()
+            .any(|pattern| matches_pattern(pattern, path, walk.root))
+}
+
+pub fn collect_files(
+    dir_path: &Path,
+    options: &WalkOptions,
+    files: &mut Vec<(PathBuf, fs::Metadata)>,
+) -> Result<(), Error> {
+    let root = fs::metadata(dir_path).map_err(|err| {
+        Error::io(
+            format!(
+                "could not read metadata of {dir_path}",
+                dir_path = dir_path.display()
+            ),
+            err,
+        )
+    })?;
+    let mut walk = Walk {
+        options,
+        root: dir_path,
+        root_dev: device_of(&root),
+        linked: HashSet::new(),
+        dirs: file_id(&root).into_iter().collect(),
+        ignores: Vec::new(),
+    };
+    walk_dir(&mut walk, dir_path, files)
+}
+
+fn walk_dir(
+    walk: &mut Walk,
+    dir_path: &Path,
+    files: &mut Vec<(PathBuf, fs::Metadata)>,
+) -> Result<(), Error> {
+    let dir = fs::read_dir(dir_path).map_err(|err| {
+        Error::io(
+            format!(
+                "could not open directory {dir_path} for indexing. Read full error",
+                dir_path = dir_path.display()
+            ),
+            err,
+        )
+    })?;
+    let ignore_file = if walk.options.ignore_files {
+        IgnoreFile::read(dir_path)
+    } else {
+        None
+    };
+    let has_ignore_file = ignore_file.is_some();
+    walk.ignores.extend(ignore_file);
+    let walked = walk_entries(walk, dir_path, dir, files);
+    if has_ignore_file {
+        walk.ignores.pop();
+    }
+    walked
+}
+
+fn walk_entries(
+    walk: &mut Walk,
+    dir_path: &Path,
+    dir: fs::ReadDir,
+    files: &mut Vec<(PathBuf, fs::Metadata)>,
+) -> Result<(), Error> {
+    'next_file: for file in dir {
+        let file = file.map_err(|err| {
+            Error::io(
+                format!(
+                    "could not open directory {dir_path} for indexing. Read full error",
+                    dir_path = dir_path.display()
+                ),
+                err,
+            )
+        })?;
+        let file_path = file.path();
+
+        if is_skipped(&file_path, walk) {
+            continue 'next_file;
+        }
+
+        let mut file_type = file.file_type().map_err(|err| {
+            Error::io(
+                format!(
+                    "could not determine file type of file {file_path}. Read full error",
+                    file_path = file_path.display()
+                ),
+                err,
+            )
+        })?;
+
+        let mut metadata = file.metadata().map_err(|err| {
+            Error::io(
+                format!(
+                    "could not read metadata of file {file_path}. Read full error",
+                    file_path = file_path.display()
+                ),
+                err,
+            )
+        })?;
+
+        // A symlink stands for what it points to.
+        if file_type.is_symlink() {
+            metadata = match fs::metadata(&file_path) {
+                Ok(target) => target,
+                Err(err) => {
+                    eprintln!("WARNING: skipping {file_path:?}: the symlink is broken: {err}");
+                    continue 'next_file;
+                }
+            };
+            file_type = metadata.file_type();
+            if file_type.is_dir() && !walk.options.follow_symlinks {
+                continue 'next_file;
+            }
+        }
+
+        if walk.is_ignored(&file_path, file_type.is_dir()) {
+            continue 'next_file;
+        }
+
+        if file_type.is_dir() {
+            if walk.options.one_file_system && device_of(&metadata) != walk.root_dev {
+                println!("Skipping {file_path:?}: on a different filesystem");
+                continue 'next_file;
+            }
+            if let Some(id) = file_id(&metadata) {
+                if !walk.dirs.insert(id) {
+                    println!("Skipping {file_path:?}: the folder was already walked, it is linked to more than once or a symlink loop leads back to it");
+                    continue 'next_file;
+                }
+            }
+            walk_dir(walk, &file_path, files)?;
+            continue 'next_file;
+        }
+
+        if !is_included(&file_path, walk) {
+            continue 'next_file;
+        }
+
+        if let Some(id) = hard_link_id(&metadata) {
+            if !walk.linked.insert(id) {
+                continue 'next_file;
+            }
+        }
+
+        files.push((file_path, metadata));
+    }
+    Ok(())
+}
@@ -0,0 +1,115 @@
+// Warming up a reloaded index before it is swapped in. Results are cached
+// per loaded index, so after every reindex all searches would miss the cache
+// at once. With `--warm-searches <n>` serve replays the <n> searches the
+// query log recorded most often lately against the new index first, so
+// theirs are cached by the time traffic reaches it, while searches still go
+// to the old index. Searches logged hashed, or paging through result sets
+// the server kept, cannot be replayed and are left out.
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use tinysearch::handle::SearchHandle;
+use tinysearch::query::QueryLimits;
+use tracing::info;
+
+use crate::api::{self, SearchRequest};
+use crate::http;
+use crate::popularity::Popularity;
+use crate::resultsets::ResultSets;
+
+// How much of the end of the query log counts as recent.
+const RECENT_LOG_BYTES: u64 = 4 * 1024 * 1024;
+
+pub struct WarmUp {
+    pub query_log: PathBuf,
+    pub searches: usize,
+}
+
+impl WarmUp {
+    // Replays the most frequent recent searches against `handle`, returning
+    // how many it replayed.
+    pub fn run(
+        &self,
+        handle: &SearchHandle,
+        limits: &QueryLimits,
+        popularity: Option<&Popularity>,
+    ) -> usize {
+        let started = Instant::now();
+        let searches = match self.frequent_searches() {
+            Ok(searches) => searches,
+            Err(err) => {
+                tracing::warn!(
+                    log = %self.query_log.display(),
+                    "could not read the query log to warm up the index: {err}"
+                );
+                return 0;
+            }
+        };
+        // The result sets of replayed searches are nobody's to page through.
+        let sets = ResultSets::new(Duration::ZERO, 1);
+        for search in &searches {
+            let _ = if search.snippets {
+                api::search(handle, search, limits, &sets, popularity)
+            } else {
+                api::ranked(handle, search, limits, &sets, popularity)
+            };
+        }
+        info!(
+            searches = searches.len(),
+            ms = started.elapsed().as_millis() as u64,
+            "warmed up the reloaded index"
+        );
+        searches.len()
+    }
+
+    // The searches logged most often in the recent part of the query log,
+    // most frequent first, ties going to the one logged last.
+    fn frequent_searches(&self) -> io::Result<Vec<SearchRequest>> {
+        let mut file = File::open(&self.query_log)?;
+        let size = file.metadata()?.len();
+        let start = size.saturating_sub(RECENT_LOG_BYTES);
+        file.seek(SeekFrom::Start(start))?;
+        let mut lines = BufReader::new(file).lines();
+        if start > 0 {
+            // Most likely the end of a record.
+            lines.next();
+        }
+        let mut counts = HashMap::<String, (usize, usize)>::new();
+        for (at, line) in lines.enumerate() {
+            let Ok(record) = serde_json::from_str::<serde_json::Value>(&line?) else {
+                continue;
+            };
+            let Some(query) = record["query"].as_str() else {
+                continue;
+            };
+            if query.starts_with("hash:") {
+                continue;
+            }
+            let (count, last) = counts.entry(query.to_string()).or_default();
+            *count += 1;
+            *last = at;
+        }
+        let mut ranked = counts.into_iter().collect::<Vec<_>>();
+        ranked.sort_by(|(_, a), (_, b)| b.cmp(a));
+        Ok(ranked
+            .into_iter()
+            .map(|(query, _)| logged_search(&query))
+            .filter(|search| search.within.is_none() && search.result_set.is_none())
+            .take(self.searches)
+            .collect())
+    }
+}
+
+// The search of a query log record: the URL of a GET /api/v1/search, or
+// /api/search before the API had versions, or the body of a POST.
+fn logged_search(query: &str) -> SearchRequest {
+    if query.starts_with("/api/") {
+        let (_, params) = http::split_url(query);
+        SearchRequest::from_params(&params)
+    } else {
+        SearchRequest::from_body(query)
+    }
+}
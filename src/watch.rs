@@ -0,0 +1,293 @@
+// Keeps a served index up to date with the folder it was built from. File
+// system notifications mark the index stale; once the folder has been quiet
+// for `QUIET_PERIOD`, so a burst of saves is indexed once, the changed files
+// are indexed incrementally into the served index, which is written to the
+// index file and swapped in without reading the file again. Only when another
+// process wrote the file since is it read before.
+//
+// Network filesystems like NFS and SMB, and folders shared into some
+// containers, send no notifications for changes made elsewhere. There the
+// folder is polled instead: every `POLL_INTERVAL` the modification times and
+// sizes of its files are compared with those of the scan before.
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::time::{Duration, Instant};
+
+use notify::event::ModifyKind;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tracing::{info, warn};
+
+use tinysearch::handle::SearchHandle;
+use tinysearch::indexer::{self, IndexOptions, Verbosity};
+use tinysearch::lock::IndexLock;
+use tinysearch::report::IndexReport;
+use tinysearch::source::{DocumentSource, FileStat, FolderSource};
+use tinysearch::writer::IndexWriter;
+use tinysearch::{Error, Model};
+
+pub const QUIET_PERIOD: Duration = Duration::from_millis(500);
+pub const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+// How changes to the folder are noticed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchStrategy {
+    // Polling on network filesystems and where notifications are not
+    // available, notifications elsewhere.
+    Auto,
+    Notify,
+    Poll,
+}
+
+impl WatchStrategy {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "auto" => Some(Self::Auto),
+            "notify" => Some(Self::Notify),
+            "poll" => Some(Self::Poll),
+            _ => None,
+        }
+    }
+}
+
+enum Changes {
+    Notify {
+        // Dropping the watcher ends the notifications.
+        _watcher: RecommendedWatcher,
+        events: Receiver<notify::Result<Event>>,
+    },
+    Poll {
+        // The files as of the last scan.
+        files: BTreeMap<PathBuf, Option<FileStat>>,
+        scanned_at: Instant,
+    },
+}
+
+pub struct FolderWatch {
+    root: PathBuf,
+    index_path: String,
+    changes: Changes,
+    // When the last change came in, while some are not indexed yet.
+    changed_at: Option<Instant>,
+    // The index file as the served index was read from or written to it.
+    index_stat: Option<FileStat>,
+}
+
+impl FolderWatch {
+    // Files changed while nothing watched are picked up by the first update,
+    // due once the server has started.
+    pub fn new(root: &Path, index_path: &str, strategy: WatchStrategy) -> Result<Self, Error> {
+        let changes = match strategy {
+            WatchStrategy::Auto if is_network_filesystem(root) => {
+                info!(root = %root.display(), "polling the folder for changes, as it is on a network filesystem");
+                poll(root, index_path)
+            }
+            WatchStrategy::Auto => notify(root).unwrap_or_else(|err| {
+                warn!("{err}, polling the folder instead");
+                poll(root, index_path)
+            }),
+            WatchStrategy::Notify => notify(root)?,
+            WatchStrategy::Poll => poll(root, index_path),
+        };
+        Ok(Self {
+            root: root.to_path_buf(),
+            index_path: index_path.to_string(),
+            changes,
+            changed_at: Some(Instant::now()),
+            index_stat: file_stat(Path::new(index_path)),
+        })
+    }
+
+    // How long the caller may wait between calls of `update_if_due`.
+    pub fn wake_interval(&self) -> Duration {
+        match self.changes {
+            Changes::Notify { .. } => QUIET_PERIOD,
+            Changes::Poll { .. } => QUIET_PERIOD.min(POLL_INTERVAL),
+        }
+    }
+
+    // Indexes the changes into the index of `handle` once the folder is
+    // quiet. True if the served index changed.
+    pub fn update_if_due(&mut self, handle: &SearchHandle) -> bool {
+        match &mut self.changes {
+            Changes::Notify { events, .. } => loop {
+                match events.try_recv() {
+                    Ok(Ok(event)) if is_change(&self.index_path, &event) => {
+                        self.changed_at = Some(Instant::now())
+                    }
+                    Ok(Ok(_)) => {}
+                    Ok(Err(err)) => {
+                        warn!(root = %self.root.display(), "file system notification failed: {err}")
+                    }
+                    Err(TryRecvError::Empty | TryRecvError::Disconnected) => break,
+                }
+            },
+            Changes::Poll { files, scanned_at } if scanned_at.elapsed() >= POLL_INTERVAL => {
+                let scanned = scan(&self.root, &self.index_path);
+                if scanned != *files {
+                    *files = scanned;
+                    self.changed_at = Some(Instant::now());
+                }
+                *scanned_at = Instant::now();
+            }
+            Changes::Poll { .. } => {}
+        }
+        if self.changed_at.is_none_or(|at| at.elapsed() < QUIET_PERIOD) {
+            return false;
+        }
+        self.changed_at = None;
+        self.update(handle).unwrap_or_else(|err| {
+            warn!(root = %self.root.display(), "could not index the changes: {err}");
+            false
+        })
+    }
+
+    // Ok(false) if no document had changed after all.
+    fn update(&mut self, handle: &SearchHandle) -> Result<bool, Error> {
+        let started = Instant::now();
+        // An index run writing the index is waited for, and the changes
+        // indexed on top of what it wrote.
+        let _lock = IndexLock::wait(&self.index_path)?;
+        let served =
+            self.index_stat.is_some() && self.index_stat == file_stat(Path::new(&self.index_path));
+        let mut writer = if served {
+            IndexWriter::open_with(&self.index_path, Model::clone(&handle.snapshot()))
+        } else {
+            IndexWriter::open(&self.index_path)?
+        };
+        let options = IndexOptions {
+            incremental: true,
+            verbosity: Verbosity::Quiet,
+            ..IndexOptions::default()
+        };
+        let sources: [Box<dyn DocumentSource>; 1] = [Box::new(FolderSource {
+            root: self.root.clone(),
+            walk: options.walk.clone(),
+        })];
+        indexer::index_sources(&sources, &mut writer, &options, &mut IndexReport::default())?;
+        // An index read from the file is served even if nothing changed.
+        if !writer.has_pending() && served {
+            return Ok(false);
+        }
+        writer.commit()?;
+        self.index_stat = file_stat(Path::new(&self.index_path));
+        handle.replace(writer.into_model());
+        info!(
+            root = %self.root.display(),
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "indexed changed files"
+        );
+        Ok(true)
+    }
+}
+
+fn notify(root: &Path) -> Result<Changes, Error> {
+    let (sender, events) = mpsc::channel();
+    let watch_failed = |err: notify::Error| {
+        Error::invalid(format!(
+            "could not watch {root} for changes: {err}",
+            root = root.display()
+        ))
+    };
+    let mut watcher = notify::recommended_watcher(sender).map_err(watch_failed)?;
+    watcher
+        .watch(root, RecursiveMode::Recursive)
+        .map_err(watch_failed)?;
+    Ok(Changes::Notify {
+        _watcher: watcher,
+        events,
+    })
+}
+
+fn poll(root: &Path, index_path: &str) -> Changes {
+    Changes::Poll {
+        files: scan(root, index_path),
+        scanned_at: Instant::now(),
+    }
+}
+
+// Reads and metadata changes, and the writes of the index itself and its
+// temporary files when it lives in the folder, leave the documents as they
+// are.
+fn is_change(index_path: &str, event: &Event) -> bool {
+    let document_changed = match event.kind {
+        EventKind::Modify(ModifyKind::Metadata(_)) => false,
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) => true,
+        _ => false,
+    };
+    document_changed
+        && event
+            .paths
+            .iter()
+            .any(|path| !is_index_file(index_path, path))
+}
+
+fn is_index_file(index_path: &str, path: &Path) -> bool {
+    let index_name = Path::new(index_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with(&index_name))
+}
+
+// The files below `root` with their modification times and sizes, other than
+// the index files. Symlinks are not followed.
+fn scan(root: &Path, index_path: &str) -> BTreeMap<PathBuf, Option<FileStat>> {
+    let mut files = BTreeMap::new();
+    let mut folders = vec![root.to_path_buf()];
+    while let Some(folder) = folders.pop() {
+        let Ok(entries) = fs::read_dir(&folder) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            match entry.file_type() {
+                Ok(kind) if kind.is_dir() => folders.push(path),
+                Ok(_) if !is_index_file(index_path, &path) => {
+                    files.insert(path.clone(), file_stat(&path));
+                }
+                _ => {}
+            }
+        }
+    }
+    files
+}
+
+// Whether `root` is on NFS, SMB and the like, going by the mount table of
+// Linux. Elsewhere nothing is taken to be.
+fn is_network_filesystem(root: &Path) -> bool {
+    const NETWORK: &[&str] = &[
+        "nfs",
+        "nfs4",
+        "cifs",
+        "smb3",
+        "smbfs",
+        "9p",
+        "afs",
+        "fuse.sshfs",
+    ];
+    let (Ok(root), Ok(mounts)) = (root.canonicalize(), fs::read_to_string("/proc/mounts")) else {
+        return false;
+    };
+    // The mount point of the folder is the longest one it is below.
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let (_, mount_point, kind) = (fields.next()?, fields.next()?, fields.next()?);
+            // Spaces in mount points are escaped as \040.
+            let mount_point = PathBuf::from(mount_point.replace("\\040", " "));
+            root.starts_with(&mount_point)
+                .then(|| (mount_point.as_os_str().len(), kind.to_string()))
+        })
+        .max_by_key(|(len, _)| *len)
+        .is_some_and(|(_, kind)| NETWORK.contains(&kind.as_str()))
+}
+
+fn file_stat(path: &Path) -> Option<FileStat> {
+    fs::metadata(path)
+        .ok()
+        .and_then(|metadata| FileStat::of(&metadata))
+}
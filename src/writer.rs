@@ -0,0 +1,461 @@
+// Builds an index from documents pushed by any producer, so building does not
+// depend on the folder walker. Added documents collect in a pending segment
+// that becomes part of the index, and is written to its store, on commit.
+//
+// A new binary index can also be flushed while it is built: the pending
+// segment is appended to the file and the terms and positions of its
+// documents are let go, so that memory holds one segment at a time rather
+// than the whole index. Its manifest is appended on commit.
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::path::Path;
+
+use crate::analyzer::Analyzer;
+use crate::config::IndexConfig;
+use crate::indexer::{self, Pruning};
+use crate::language;
+use crate::percolate::StoredQueries;
+use crate::schema;
+use crate::spelling::SpellingDictionary;
+use crate::store::{self, StoreFormat};
+use crate::{
+    index_document, load_model, term_positions, Aliases, Doc, DocKey, Error, FileStamps, Metadata,
+    Model, TermFreqIndex,
+};
+
+pub struct IndexWriter {
+    model: Model,
+    segment: TermFreqIndex,
+    analyzer: Analyzer,
+    index_path: Option<String>,
+    format: StoreFormat,
+    // Whether the stored index no longer matches the committed documents, so
+    // the next commit has to rewrite it instead of appending the segment.
+    rewrite: bool,
+    // Set once the index has been flushed.
+    flushed: Option<Flushed>,
+}
+
+// What is left in memory of the flushed documents besides their metadata.
+#[derive(Default)]
+struct Flushed {
+    paths: HashSet<DocKey>,
+    // Term → flushed documents it occurs in, for the spelling dictionary.
+    doc_freqs: HashMap<String, usize>,
+}
+
+impl Flushed {
+    fn record(&mut self, segment: &TermFreqIndex) {
+        for (path, doc) in segment {
+            self.paths.insert(path.clone());
+            for term in doc.tf.keys() {
+                match self.doc_freqs.get_mut(term) {
+                    Some(docs) => *docs += 1,
+                    None => {
+                        self.doc_freqs.insert(term.clone(), 1);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl IndexWriter {
+    // A writer whose index only lives in memory.
+    pub fn new(config: IndexConfig) -> Self {
+        let mut model = Model::default();
+        let analyzer = Analyzer::for_documents(&config);
+        model.manifest.config = config;
+        Self {
+            model,
+            segment: TermFreqIndex::new(),
+            analyzer,
+            index_path: None,
+            format: StoreFormat::Json,
+            rewrite: false,
+            flushed: None,
+        }
+    }
+
+    // A writer that replaces whatever index is at `index_path`, in the
+    // format its extension names.
+    pub fn create(index_path: &str, config: IndexConfig) -> Self {
+        Self::create_as(index_path, config, StoreFormat::from_extension(index_path))
+    }
+
+    pub fn create_as(index_path: &str, config: IndexConfig, format: StoreFormat) -> Self {
+        Self {
+            index_path: Some(index_path.to_string()),
+            format,
+            rewrite: true,
+            ..Self::new(config)
+        }
+    }
+
+    // A writer that adds to the existing index at `index_path`.
+    pub fn open(index_path: &str) -> Result<Self, Error> {
+        Ok(Self::open_with(index_path, load_model(index_path)?))
+    }
+
+    // Like `open`, with the index at `index_path` already in memory as `model`.
+    pub fn open_with(index_path: &str, model: Model) -> Self {
+        Self {
+            analyzer: model.document_analyzer(),
+            model,
+            segment: TermFreqIndex::new(),
+            index_path: Some(index_path.to_string()),
+            format: StoreFormat::detect(index_path),
+            rewrite: false,
+            flushed: None,
+        }
+    }
+
+    // A writer adding to `model` in memory, like the server does with the
+    // documents posted to it.
+    pub fn with_model(model: Model) -> Self {
+        Self {
+            analyzer: model.document_analyzer(),
+            model,
+            segment: TermFreqIndex::new(),
+            index_path: None,
+            format: StoreFormat::Json,
+            rewrite: false,
+            flushed: None,
+        }
+    }
+
+    // For producers that have plain text rather than extracted documents.
+    pub fn add(&mut self, doc_path: impl Into<DocKey>, text: &str, mut meta: Metadata) {
+        language::tag(&mut meta, text);
+        let mut doc = Doc {
+            tf: index_document(&self.analyzer, text),
+            meta,
+            positions: term_positions(&self.analyzer, text, usize::MAX),
+            ..Doc::default()
+        };
+        schema::index_fields(&self.config().fields, &self.analyzer, &mut doc);
+        self.add_doc(doc_path, doc);
+    }
+
+    pub fn add_doc(&mut self, doc_path: impl Into<DocKey>, doc: Doc) {
+        self.segment.insert(doc_path.into(), doc);
+    }
+
+    // Documents added through `add_doc` have to be analyzed with this, and
+    // their fields stored with `schema::index_fields`.
+    pub fn analyzer(&self) -> &Analyzer {
+        &self.analyzer
+    }
+
+    pub fn config(&self) -> &IndexConfig {
+        &self.model.manifest.config
+    }
+
+    // The source files of the documents, as recorded by the last run.
+    pub fn file_stamps(&self) -> &FileStamps {
+        &self.model.manifest.files
+    }
+
+    pub fn set_file_stamps(&mut self, files: FileStamps) {
+        if files != self.model.manifest.files {
+            self.model.manifest.files = files;
+            self.rewrite = true;
+        }
+    }
+
+    pub fn aliases(&self) -> &Aliases {
+        &self.model.manifest.aliases
+    }
+
+    pub fn set_aliases(&mut self, aliases: Aliases) {
+        if aliases != self.model.manifest.aliases {
+            self.model.manifest.aliases = aliases;
+            self.rewrite = true;
+        }
+    }
+
+    pub fn queries(&self) -> &StoredQueries {
+        &self.model.manifest.queries
+    }
+
+    pub fn set_queries(&mut self, queries: StoredQueries) {
+        if queries != self.model.manifest.queries {
+            self.model.manifest.queries = queries;
+            self.rewrite = true;
+        }
+    }
+
+    // Gives the documents added for moved files the IDs their documents had
+    // at the old paths, sections matched by their anchors, before those are
+    // dropped.
+    pub fn carry_doc_ids(&mut self, moved: &Aliases) {
+        for (old, new) in moved {
+            let new = new.to_string_lossy();
+            for (path, doc) in &mut self.segment {
+                let Some(anchor) = path.to_str().and_then(|path| path.strip_prefix(&*new)) else {
+                    continue;
+                };
+                if !anchor.is_empty() && !anchor.starts_with('#') {
+                    continue;
+                }
+                let old_path = format!("{}{anchor}", old.display());
+                if let Some(old_doc) = self.model.docs.get(old_path.as_str()) {
+                    doc.id = old_doc.id;
+                }
+            }
+        }
+    }
+
+    // Drops every document, added or committed, whose path `keep` rejects.
+    pub fn retain(&mut self, mut keep: impl FnMut(&DocKey) -> bool) {
+        self.merge_segment();
+        let count = self.model.docs.len();
+        self.model.docs.retain(|path, _| keep(path));
+        if self.model.docs.len() != count {
+            self.rewrite = true;
+        }
+    }
+
+    // Changes the metadata of the documents, added or committed, that
+    // `update` returns true for. Returns how many it changed.
+    pub fn update_meta(&mut self, mut update: impl FnMut(&Path, &mut Metadata) -> bool) -> usize {
+        self.merge_segment();
+        let mut changed = 0;
+        for (path, doc) in &mut self.model.docs {
+            if update(path, &mut doc.meta) {
+                changed += 1;
+            }
+        }
+        if changed > 0 {
+            self.rewrite = true;
+        }
+        changed
+    }
+
+    // Keeps one document of those with the same `url`, pages saved or fetched
+    // more than once: the one stored under that very URL, else the first by
+    // path. Sections of a page are told apart by their anchors. Returns how
+    // many documents it dropped.
+    pub fn collapse_urls(&mut self) -> usize {
+        self.merge_segment();
+        let mut kept = HashMap::<String, &DocKey>::new();
+        for (path, doc) in &self.model.docs {
+            let Some(url) = doc.meta.get("url").and_then(|url| url.first()) else {
+                continue;
+            };
+            let key = match path.as_str().rsplit_once('#') {
+                Some((_, anchor)) => format!("{url}#{anchor}"),
+                None => url.to_string(),
+            };
+            let preferred = |other: &DocKey| {
+                let at_url = |path: &DocKey| path.as_str() == key;
+                (!at_url(other), other) < (!at_url(path), path)
+            };
+            match kept.get(&key) {
+                Some(other) if preferred(other) => {}
+                _ => {
+                    kept.insert(key, path);
+                }
+            }
+        }
+        let kept = kept.into_values().cloned().collect::<HashSet<_>>();
+        let before = self.model.docs.len();
+        self.model
+            .docs
+            .retain(|path, doc| !doc.meta.contains_key("url") || kept.contains(path));
+        let dropped = before - self.model.docs.len();
+        if dropped > 0 {
+            self.rewrite = true;
+        }
+        dropped
+    }
+
+    fn merge_segment(&mut self) {
+        self.number_segment();
+        self.model.docs.extend(self.segment.drain());
+    }
+
+    // Gives the added documents that have no ID theirs, in path order so that
+    // building an index twice numbers its documents the same way.
+    fn number_segment(&mut self) {
+        let mut unnumbered = self
+            .segment
+            .iter()
+            .filter(|(_, doc)| doc.id == 0)
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+        unnumbered.sort();
+        for path in unnumbered {
+            let id = self.model.doc_id_for(&path);
+            if let Some(doc) = self.segment.get_mut(&path) {
+                doc.id = id;
+            }
+        }
+    }
+
+    // Drops rare, ubiquitous and short terms from everything added so far and
+    // records the settings in the manifest. Returns the number of terms dropped.
+    pub fn prune(&mut self, pruning: &Pruning) -> usize {
+        self.merge_segment();
+        self.rewrite = true;
+        self.model.manifest.pruning = Some(pruning.clone());
+        indexer::prune(&mut self.model.docs, pruning)
+    }
+
+    // Whether `commit` has anything to store.
+    pub fn has_pending(&self) -> bool {
+        self.rewrite || !self.segment.is_empty()
+    }
+
+    // The number of documents added since the last commit or flush.
+    pub fn pending(&self) -> usize {
+        self.segment.len()
+    }
+
+    // Whether `flush` writes anything: only a new, uncompressed binary index
+    // can be written in segments.
+    pub fn can_flush(&self) -> bool {
+        self.index_path.is_some()
+            && self.format == (StoreFormat::Binary { compressed: false })
+            && (self.flushed.is_some() || (self.rewrite && self.model.docs.is_empty()))
+    }
+
+    // Writes the pending documents to the index file and keeps only their
+    // metadata, if the index can be flushed.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if !self.can_flush() {
+            return Ok(());
+        }
+        self.analyzer.check_filters()?;
+        self.number_segment();
+        let index_path = self
+            .index_path
+            .as_deref()
+            .expect("flushed indexes have a path");
+        let store = store::open_store_as(index_path, self.format);
+        match &self.flushed {
+            None => {
+                println!("Saving {index_path}...");
+                let model = Model {
+                    manifest: self.model.manifest.clone(),
+                    docs: mem::take(&mut self.segment),
+                };
+                store.save(&model)?;
+                self.segment = model.docs;
+                self.rewrite = false;
+            }
+            Some(_) => {
+                println!(
+                    "Appending {count} documents to {index_path}...",
+                    count = self.segment.len()
+                );
+                store.append_segment(&self.segment)?;
+            }
+        }
+        let flushed = self.flushed.get_or_insert_with(Flushed::default);
+        flushed.record(&self.segment);
+        for (path, mut doc) in self.segment.drain() {
+            doc.tf = Default::default();
+            doc.positions = Default::default();
+            self.model.docs.insert(path, doc);
+        }
+        Ok(())
+    }
+
+    // The number of distinct terms of the index, flushed documents included.
+    pub fn unique_terms(&self) -> usize {
+        let mut terms = self
+            .model
+            .docs
+            .values()
+            .chain(self.segment.values())
+            .flat_map(|doc| doc.tf.keys())
+            .collect::<HashSet<_>>();
+        if let Some(flushed) = &self.flushed {
+            terms.extend(flushed.doc_freqs.keys());
+        }
+        terms.len()
+    }
+
+    // Makes the pending documents part of the index and stores them.
+    pub fn commit(&mut self) -> Result<(), Error> {
+        // Documents analyzed by a token filter that failed are not written.
+        self.analyzer.check_filters()?;
+        if self.flushed.is_some() {
+            return self.commit_flushed();
+        }
+        self.number_segment();
+        if let Some(index_path) = &self.index_path {
+            if self.rewrite {
+                self.model.docs.extend(self.segment.drain());
+                self.model.manifest.spelling = SpellingDictionary::build(&self.model.docs);
+                println!("Saving {index_path}...");
+                store::open_store_as(index_path, self.format).save(&self.model)?;
+                self.rewrite = false;
+            } else if !self.segment.is_empty() {
+                println!(
+                    "Appending {count} documents to {index_path}...",
+                    count = self.segment.len()
+                );
+                store::open_store_as(index_path, self.format).append_segment(&self.segment)?;
+            }
+        }
+        self.merge_segment();
+        Ok(())
+    }
+
+    // Appends what was added since the last flush, and then the manifest,
+    // which replaces the one written with the first segment.
+    fn commit_flushed(&mut self) -> Result<(), Error> {
+        // The manifest is appended whatever changed in it, but documents
+        // dropped since they were written stay in the file.
+        let flushed = self.flushed.as_ref().expect("the index was flushed");
+        if flushed
+            .paths
+            .iter()
+            .any(|path| !self.model.docs.contains_key(path))
+        {
+            return Err(Error::invalid(
+                "documents were removed from the index after they were flushed, which only rewriting it could store; build it without --flush-every",
+            ));
+        }
+        // Documents merged into the model by `retain` and the like.
+        let unwritten = self
+            .model
+            .docs
+            .keys()
+            .filter(|path| !flushed.paths.contains(*path))
+            .cloned()
+            .collect::<Vec<_>>();
+        for path in unwritten {
+            if let Some(doc) = self.model.docs.remove(&path) {
+                self.segment.insert(path, doc);
+            }
+        }
+        if !self.segment.is_empty() {
+            self.flush()?;
+        }
+        let flushed = self.flushed.as_ref().expect("the index was flushed");
+        self.model.manifest.spelling = SpellingDictionary::from_doc_freqs(
+            flushed
+                .doc_freqs
+                .iter()
+                .map(|(term, &docs)| (term.as_str(), docs)),
+        );
+        let index_path = self
+            .index_path
+            .as_deref()
+            .expect("flushed indexes have a path");
+        store::append_manifest(index_path, &self.model.manifest)
+    }
+
+    // The committed index. The documents of a flushed index have no terms.
+    pub fn model(&self) -> &Model {
+        &self.model
+    }
+
+    pub fn into_model(mut self) -> Model {
+        self.merge_segment();
+        self.model
+    }
+}